@@ -0,0 +1,53 @@
+//! Throughput of transfers and their serialization, see `bank::benchmarks`.
+//!
+//! `cargo bench` runs everything; arguments other than flags pick the
+//! measurements whose names contain them, e.g. `cargo bench -- kept`.
+
+use bank::benchmarks::{self, Measurement};
+
+const ACCOUNT_COUNTS: [usize; 3] = [10, 1_000, 100_000];
+
+const TRANSFERS: u64 = 20_000;
+
+const SERIALIZATIONS: u64 = 200_000;
+
+const ACTOR_ACCOUNT_COUNTS: [usize; 2] = [10, 1_000];
+
+const ACTOR_THREADS: usize = 8;
+
+fn main() {
+    let filters: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    let wanted = |name: &str| filters.is_empty() || filters.iter().any(|f| name.contains(f));
+    let report = |measurement: Measurement| println!("{measurement}");
+
+    for accounts in ACCOUNT_COUNTS {
+        if wanted(&format!("transfer, {accounts} accounts, in memory")) {
+            report(benchmarks::transfers(accounts, TRANSFERS, None));
+        }
+        if wanted(&format!("transfer, {accounts} accounts, kept")) {
+            let data_dir =
+                std::env::temp_dir().join(format!("bank-bench-{}-{accounts}", std::process::id()));
+            report(benchmarks::transfers(accounts, TRANSFERS, Some(&data_dir)));
+            std::fs::remove_dir_all(&data_dir).unwrap();
+        }
+    }
+    for accounts in ACTOR_ACCOUNT_COUNTS {
+        if wanted(&format!("actor transfer, {accounts} accounts")) {
+            let (measurement, contention) =
+                benchmarks::actor_transfers(accounts, TRANSFERS, ACTOR_THREADS);
+            report(measurement);
+            for hot in contention.iter().take(3) {
+                println!("    {hot}");
+            }
+        }
+    }
+    if wanted("parse transfer") || wanted("write receipt") {
+        benchmarks::serialization(SERIALIZATIONS)
+            .into_iter()
+            .filter(|measurement| wanted(&measurement.name))
+            .for_each(report);
+    }
+}
@@ -0,0 +1,7 @@
+//! A request datagram, then after the first newline its payload.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| bank::fuzzing::request(data));
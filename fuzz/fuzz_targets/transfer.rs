@@ -0,0 +1,7 @@
+//! The payload of the `t` instruction.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| bank::fuzzing::transfer(data));
@@ -0,0 +1,329 @@
+//! Another way of applying transfers than one at a time on the bank: every
+//! account is an actor, a thread owning the account, and a transfer is an
+//! exchange of messages, a withdrawal with the payer's actor and then a
+//! deposit with the payee's. Transfers on different accounts proceed in
+//! parallel, with no lock over the accounts, while those on one account
+//! queue in its mailbox, which its `Contention` measures.
+//!
+//! Transfers are planned by the rules of the bank, PINs and hooks included,
+//! but only balances, overdrafts, holds and statuses are enforced; they are
+//! not screened or limited, and transfers with fees or conversions are
+//! refused. `finish` commits the transfers made to the bank in the order
+//! their deposits were made. That order replays: a withdrawal only ever
+//! relies on deposits made before it.
+//!
+//! An actor is a thread, so this is for banks of some thousands of accounts
+//! at most.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::ledger::Movement;
+use crate::money::Money;
+use crate::{Account, AccountId, Bank, Checks, CustomError, TxInfo};
+
+#[derive(Error, Debug)]
+pub enum ActorError {
+    #[error(transparent)]
+    Transfer(#[from] CustomError),
+    #[error("Transfers with fees or conversions cannot be made by actors")]
+    Unsupported,
+    #[error("The actor of account {0} stopped")]
+    Stopped(AccountId),
+}
+
+/// A transfer made by the actors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActorReceipt {
+    pub from: AccountId,
+    pub to: AccountId,
+    pub amount: Money,
+}
+
+/// How busy an account's actor was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contention {
+    pub account: AccountId,
+    pub messages: u64,
+    /// Most messages waiting in the mailbox at once.
+    pub max_queued: usize,
+    /// Time messages spent in the mailbox, in total.
+    pub waited: Duration,
+}
+
+impl Display for Contention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "account {}: {} messages, at most {} queued, waited {:?}",
+            self.account, self.messages, self.max_queued, self.waited
+        )
+    }
+}
+
+enum Request {
+    Withdraw(Money),
+    Deposit(Movement),
+    /// Undoes a withdrawal whose deposit failed.
+    Refund(Money),
+}
+
+struct Message {
+    request: Request,
+    sent: Instant,
+    reply: Option<Sender<Result<(), CustomError>>>,
+}
+
+struct Mailbox {
+    sender: Sender<Message>,
+    queued: Arc<AtomicUsize>,
+}
+
+/// What an actor leaves when its mailbox closes.
+struct Finished {
+    /// Deposits made, by the order they were made in across the actors.
+    deposits: Vec<(u64, Movement)>,
+    contention: Contention,
+}
+
+pub struct Actors {
+    bank: Bank,
+    mailboxes: HashMap<AccountId, Mailbox>,
+    actors: Vec<JoinHandle<Finished>>,
+}
+
+impl Actors {
+    /// Start an actor for every account of `bank`, which is left alone
+    /// until `finish`.
+    pub fn start(bank: Bank) -> Actors {
+        let deposits = Arc::new(AtomicU64::new(0));
+        let mut mailboxes = HashMap::new();
+        let mut actors = Vec::new();
+        for account in bank.accounts.values() {
+            let (sender, receiver) = mpsc::channel();
+            let queued = Arc::new(AtomicUsize::new(0));
+            let actor = {
+                let (account, queued, deposits) =
+                    (account.clone(), queued.clone(), deposits.clone());
+                thread::spawn(move || run(account, receiver, &queued, &deposits))
+            };
+            mailboxes.insert(account.id, Mailbox { sender, queued });
+            actors.push(actor);
+        }
+        Actors {
+            bank,
+            mailboxes,
+            actors,
+        }
+    }
+
+    /// Make a transfer, from any number of threads at once.
+    pub fn transfer(&self, tx_info: TxInfo) -> Result<ActorReceipt, ActorError> {
+        let transfer = self.bank.plan_transfer(tx_info, Checks::All)?;
+        let ([movement], []) = (&transfer.movements[..], &transfer.fee_movements[..]) else {
+            return Err(ActorError::Unsupported);
+        };
+        let (Some(from), Some(to)) = (movement.from, movement.to) else {
+            return Err(ActorError::Unsupported);
+        };
+        self.ask(from, Request::Withdraw(movement.amount))?;
+        if let Err(e) = self.ask(to, Request::Deposit(movement.clone())) {
+            self.send(from, Request::Refund(movement.amount), None)?;
+            return Err(e);
+        }
+        Ok(ActorReceipt {
+            from,
+            to,
+            amount: movement.amount,
+        })
+    }
+
+    /// Stop the actors and commit the transfers they made to the bank.
+    /// Returns the bank and the contention of each account, the most
+    /// waited on first.
+    pub fn finish(self) -> (Bank, Vec<Contention>) {
+        let Actors {
+            mut bank,
+            mailboxes,
+            actors,
+        } = self;
+        // Closing the mailboxes stops the actors.
+        drop(mailboxes);
+        let mut deposits = Vec::new();
+        let mut contention = Vec::new();
+        for actor in actors {
+            let finished = actor.join().expect("an account actor panicked");
+            deposits.extend(finished.deposits);
+            contention.push(finished.contention);
+        }
+        deposits.sort_by_key(|(sequence, _)| *sequence);
+        for (_, movement) in deposits {
+            bank.record_transfer(vec![movement], Vec::new(), None);
+        }
+        contention.sort_by_key(|contention| Reverse(contention.waited));
+        (bank, contention)
+    }
+
+    fn send(
+        &self,
+        account: AccountId,
+        request: Request,
+        reply: Option<Sender<Result<(), CustomError>>>,
+    ) -> Result<(), ActorError> {
+        let mailbox = &self.mailboxes[&account];
+        mailbox.queued.fetch_add(1, Ordering::Relaxed);
+        let message = Message {
+            request,
+            sent: Instant::now(),
+            reply,
+        };
+        mailbox
+            .sender
+            .send(message)
+            .map_err(|_| ActorError::Stopped(account))
+    }
+
+    /// Send `request` to the actor of `account` and wait for its answer.
+    fn ask(&self, account: AccountId, request: Request) -> Result<(), ActorError> {
+        let (reply, answer) = mpsc::channel();
+        self.send(account, request, Some(reply))?;
+        let answer = answer.recv().map_err(|_| ActorError::Stopped(account))?;
+        Ok(answer?)
+    }
+}
+
+fn run(
+    mut account: Account,
+    mailbox: Receiver<Message>,
+    queued: &AtomicUsize,
+    deposits: &AtomicU64,
+) -> Finished {
+    let mut finished = Finished {
+        deposits: Vec::new(),
+        contention: Contention {
+            account: account.id,
+            messages: 0,
+            max_queued: 0,
+            waited: Duration::ZERO,
+        },
+    };
+    for message in mailbox {
+        let contention = &mut finished.contention;
+        // Counting this one, which was still queued.
+        contention.max_queued = contention
+            .max_queued
+            .max(queued.fetch_sub(1, Ordering::Relaxed));
+        contention.messages += 1;
+        contention.waited += message.sent.elapsed();
+        let answer = match message.request {
+            Request::Withdraw(amount) => account
+                .ensure_can_send()
+                .and_then(|()| account.balance_after_withdrawal(amount))
+                .map(|balance| account.balance = balance),
+            Request::Deposit(movement) => account
+                .ensure_can_receive()
+                .and_then(|()| account.balance_after_deposit(movement.amount))
+                .map(|balance| {
+                    account.balance = balance;
+                    let sequence = deposits.fetch_add(1, Ordering::SeqCst);
+                    finished.deposits.push((sequence, movement));
+                }),
+            Request::Refund(amount) => account
+                .balance_after_deposit(amount)
+                .map(|balance| account.balance = balance),
+        };
+        if let Some(reply) = message.reply {
+            // The sender only goes away if its thread panicked.
+            let _ = reply.send(answer);
+        }
+    }
+    finished
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::invariants;
+    use crate::money::{Balance, Currency};
+    use crate::AccountRef;
+
+    fn tx_info(from: usize, to: usize, amount: u64) -> TxInfo {
+        TxInfo {
+            from: AccountRef::Name(format!("account{from}")),
+            to: AccountRef::Name(format!("account{to}")),
+            amount: Money::from_minor(amount),
+            currency: None,
+            convert: false,
+            pin: None,
+            memo: None,
+            external_ref: None,
+            idempotency_key: None,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn transfers_made_by_actors_are_committed_in_an_order_that_replays() {
+        let mut bank = Bank::new();
+        for number in 0..5 {
+            bank.open_account(
+                format!("account{number}"),
+                Balance::from_minor(50),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        let actors = Actors::start(bank);
+        let made = AtomicU64::new(0);
+        thread::scope(|scope| {
+            for worker in 0..4 {
+                let (actors, made) = (&actors, &made);
+                scope.spawn(move || {
+                    for step in 0..200 {
+                        let from = (worker + step) % 5;
+                        let to = (worker * 3 + step * 7 + 1) % 5;
+                        match actors.transfer(tx_info(from, to, 7)) {
+                            Ok(_) => {
+                                made.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(ActorError::Transfer(_)) => {}
+                            Err(e) => panic!("{e}"),
+                        }
+                    }
+                });
+            }
+        });
+
+        let (bank, contention) = actors.finish();
+        assert!(made.load(Ordering::Relaxed) > 0);
+        assert_eq!(contention.len(), 5);
+        assert!(invariants::check(&bank).consistent);
+        let total: i64 = bank
+            .accounts
+            .values()
+            .map(|account| account.balance.minor_units())
+            .sum();
+        assert_eq!(total, 250);
+        assert!(bank
+            .accounts
+            .values()
+            .all(|account| account.balance.minor_units() >= 0));
+        // Replaying the events, which checks every transfer again, gives
+        // the same balances.
+        let mut events = Vec::new();
+        bank.write_events(&mut events).unwrap();
+        let replayed = Bank::replay(Arc::new(SystemClock), &events[..]).unwrap();
+        for (id, account) in &bank.accounts {
+            assert_eq!(replayed.accounts[id].balance, account.balance);
+        }
+    }
+}
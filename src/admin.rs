@@ -0,0 +1,229 @@
+//! Administrative commands, sent as the JSON payload of the `a` instruction,
+//! e.g. `{"op": "create_account", "name": "jozko", "balance": 100}`.
+//! Accounts are given by name or by ID, like in transfers.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use log::info;
+use serde::Deserialize;
+
+use crate::limits::TransferLimits;
+use crate::loans::LoanId;
+use crate::review::ReviewId;
+use crate::{AccountRef, AccountStatus, Amount, Bank, Currency, CustomError, ExportFormat, Rate};
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AdminCommand {
+    CreateAccount {
+        name: String,
+        #[serde(default)]
+        balance: Amount,
+        #[serde(default)]
+        currency: Currency,
+    },
+    /// Open an envelope of the parent account's money, in its currency.
+    CreateSubAccount { parent: AccountRef, name: String },
+    /// Credit an account with newly created money, in the account's currency.
+    Mint { account: AccountRef, amount: Amount },
+    /// Let the account's balance go down to minus `limit`.
+    SetOverdraftLimit { account: AccountRef, limit: Amount },
+    /// Give an account limits of its own, or with `null` the configured
+    /// ones.
+    SetTransferLimits {
+        account: AccountRef,
+        limits: Option<TransferLimits>,
+    },
+    /// Give an account a new name. Settings naming the account, e.g. the
+    /// `accounts` of an identity, have to be changed to the new one.
+    RenameAccount { account: AccountRef, name: String },
+    /// Move an account's balance into another of the same currency and
+    /// close it. Replies with the ID of the transaction that moved the
+    /// balance, `null` if there was none.
+    MergeAccount {
+        account: AccountRef,
+        into: AccountRef,
+    },
+    /// Freeze, close or reactivate an account.
+    SetStatus {
+        account: AccountRef,
+        status: AccountStatus,
+    },
+    /// Update the descriptive details of an account; omitted fields are kept.
+    UpdateMetadata {
+        account: AccountRef,
+        #[serde(default)]
+        display_name: Option<String>,
+        #[serde(default)]
+        email: Option<String>,
+        #[serde(default)]
+        tags: Option<BTreeSet<String>>,
+    },
+    /// Set the annual interest rate of an account, e.g. `"0.035"`, or stop
+    /// interest with `null`.
+    SetInterestRate {
+        account: AccountRef,
+        rate: Option<Rate>,
+    },
+    /// Set the rate between two currencies, in units of `to` per unit of `from`.
+    SetFxRate {
+        from: Currency,
+        to: Currency,
+        rate: Rate,
+    },
+    /// Write the whole ledger to a file on the server, replacing it.
+    ExportLedger {
+        path: PathBuf,
+        #[serde(default)]
+        format: ExportFormat,
+    },
+    /// Write a backup of the bank to a new directory on the server, see
+    /// `backup`. Replies with where it was written and the event it goes up
+    /// to.
+    Backup { path: PathBuf },
+    /// Open the accounts listed in a file on the server, see `import`.
+    /// Replies with the import report.
+    ImportAccounts { path: PathBuf },
+    /// Apply the transfers of an ISO 20022 pain.001 file on the server as
+    /// one batch, see `pain001`. Replies with the batch receipt.
+    ImportPayments { path: PathBuf },
+    /// Lend `principal` to `borrower`, repaid in `installments` every
+    /// `period_secs`, 30 days by default, see `loans`. Replies with the loan
+    /// and its schedule.
+    IssueLoan {
+        borrower: AccountRef,
+        principal: Amount,
+        /// Annual interest rate, e.g. `0.05` for 5 %.
+        rate: Rate,
+        installments: u32,
+        #[serde(default)]
+        period_secs: Option<u64>,
+    },
+    /// Reply with a loan, what is outstanding and its schedule.
+    Loan { loan_id: LoanId },
+    /// Reply with the transfers waiting for review, see `review`.
+    ParkedTransfers,
+    /// Make a parked transfer. Replies with its receipt. A transfer that
+    /// cannot be made stays parked.
+    ApproveTransfer { review_id: ReviewId },
+    /// Drop a parked transfer.
+    RejectTransfer { review_id: ReviewId },
+    /// Net and settle what is owed to and by the peer banks now, see
+    /// `settlement`. Replies with the batches closed.
+    Settle,
+    /// Reply with the settlement batches of a peer bank's settlement
+    /// account, the open one last.
+    SettlementBatches { account: AccountRef },
+}
+
+impl AdminCommand {
+    /// Returns the reply for the client, if the command has one.
+    pub fn execute(self, bank: &mut Bank) -> Result<Option<String>, CustomError> {
+        let outcome = match self {
+            AdminCommand::CreateAccount {
+                name,
+                balance,
+                currency,
+            } => bank
+                .create_account(name, balance, currency)
+                .map(|id| info!("Created account {id}")),
+            AdminCommand::CreateSubAccount { parent, name } => bank
+                .open_sub_account(&parent, name)
+                .map(|id| info!("Created sub-account {id} of {parent}")),
+            AdminCommand::Mint { account, amount } => bank.mint(&account, amount),
+            AdminCommand::SetOverdraftLimit { account, limit } => {
+                bank.set_overdraft_limit(&account, limit)
+            }
+            AdminCommand::SetTransferLimits { account, limits } => {
+                bank.set_transfer_limits(&account, limits)
+            }
+            AdminCommand::SetStatus { account, status } => bank.set_status(&account, status),
+            AdminCommand::RenameAccount { account, name } => bank.rename_account(&account, name),
+            AdminCommand::MergeAccount { account, into } => {
+                let transaction_id = bank.merge_accounts(&account, &into)?;
+                info!("Merged account {account} into {into}");
+                let reply = serde_json::to_string(&transaction_id).expect("IDs serialize");
+                return Ok(Some(reply));
+            }
+            AdminCommand::UpdateMetadata {
+                account,
+                display_name,
+                email,
+                tags,
+            } => bank.update_metadata(&account, display_name, email, tags),
+            AdminCommand::SetInterestRate { account, rate } => {
+                bank.set_interest_rate(&account, rate)
+            }
+            AdminCommand::SetFxRate { from, to, rate } => bank.set_fx_rate(from, to, rate),
+            AdminCommand::ExportLedger { path, format } => {
+                let entries = bank.export_ledger(&path, format)?;
+                info!("Exported {entries} ledger entries to {}", path.display());
+                Ok(())
+            }
+            AdminCommand::Backup { path } => {
+                let receipt = bank.backup(&path)?;
+                info!(
+                    "Backed up the bank up to event {} to {}",
+                    receipt.sequence,
+                    path.display()
+                );
+                let reply = serde_json::to_string(&receipt).expect("backup receipts serialize");
+                return Ok(Some(reply));
+            }
+            AdminCommand::ImportAccounts { path } => {
+                let report = bank.import_accounts(&path)?;
+                let reply = serde_json::to_string(&report).expect("import reports serialize");
+                return Ok(Some(reply));
+            }
+            AdminCommand::ImportPayments { path } => {
+                let receipt = bank.import_payments(&path)?;
+                let reply = serde_json::to_string(&receipt).expect("receipts serialize");
+                return Ok(Some(reply));
+            }
+            AdminCommand::IssueLoan {
+                borrower,
+                principal,
+                rate,
+                installments,
+                period_secs,
+            } => {
+                let loan =
+                    bank.issue_loan(&borrower, principal, rate, installments, period_secs)?;
+                info!("Issued loan {} to {borrower}", loan.loan.id);
+                let reply = serde_json::to_string(&loan).expect("loans serialize");
+                return Ok(Some(reply));
+            }
+            AdminCommand::Loan { loan_id } => {
+                let loan = bank.loan(loan_id)?;
+                let reply = serde_json::to_string(&loan).expect("loans serialize");
+                return Ok(Some(reply));
+            }
+            AdminCommand::ParkedTransfers => {
+                let parked = bank.parked_transfers();
+                let reply = serde_json::to_string(&parked).expect("transfers serialize");
+                return Ok(Some(reply));
+            }
+            AdminCommand::ApproveTransfer { review_id } => {
+                let receipt = bank.approve_parked(review_id)?;
+                info!("Approved parked transfer {review_id}");
+                let reply = serde_json::to_string(&receipt).expect("receipts serialize");
+                return Ok(Some(reply));
+            }
+            AdminCommand::RejectTransfer { review_id } => bank
+                .reject_parked(review_id)
+                .map(|()| info!("Rejected parked transfer {review_id}")),
+            AdminCommand::Settle => {
+                let batches = bank.settle();
+                let reply = serde_json::to_string(&batches).expect("batches serialize");
+                return Ok(Some(reply));
+            }
+            AdminCommand::SettlementBatches { account } => {
+                let batches = bank.settlement_batches(&account)?;
+                let reply = serde_json::to_string(&batches).expect("batches serialize");
+                return Ok(Some(reply));
+            }
+        };
+        outcome.map(|()| None)
+    }
+}
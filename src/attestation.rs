@@ -0,0 +1,188 @@
+//! Signed statements of an account's balance, which anyone holding the
+//! bank's public key can check without seeing the other accounts.
+//!
+//! The bank commits to the balances of all accounts as of a ledger entry
+//! with a Merkle tree over SHA-256 and signs the root with its Ed25519
+//! attestation key. A statement carries one account's balance, the path from
+//! its leaf to the root and the signature. To check one:
+//!
+//! 1. Hash the leaf: the byte 0x00, the account ID as 8 big-endian bytes,
+//!    the currency code as 3 ASCII bytes and the balance in minor units as 8
+//!    big-endian bytes, two's complement.
+//! 2. Fold in the proof in order. A step with its hash on the left gives
+//!    SHA-256 of 0x01, that hash and the node so far; on the right, of 0x01,
+//!    the node so far and that hash.
+//! 3. Compare the result with `merkle_root`.
+//! 4. Verify `signature` under `public_key` over the bytes
+//!    `bank-attestation:v1:`, the ledger sequence as 8 big-endian bytes and
+//!    the root.
+//!
+//! Leaves are ordered by account ID. A node without a sibling on its level
+//! moves up unchanged and adds no step to the proof.
+
+use serde::Serialize;
+
+use crate::crypto::ed25519::SigningKey;
+use crate::crypto::{self, Digest, Sha256};
+use crate::money::{Balance, Currency};
+use crate::AccountId;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+const SIGNATURE_CONTEXT: &[u8] = b"bank-attestation:v1:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Sibling of a node on the path from a leaf to the root.
+#[derive(Debug, Serialize)]
+pub struct ProofStep {
+    pub side: Side,
+    /// Hex-encoded.
+    pub hash: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Attestation {
+    pub account: AccountId,
+    pub currency: Currency,
+    pub balance: Balance,
+    /// The balance is as of this ledger entry, 0 for before the first.
+    pub ledger_sequence: u64,
+    /// Number of leaves in the tree.
+    pub accounts: usize,
+    /// Position of the account's leaf, from 0.
+    pub leaf_index: usize,
+    pub proof: Vec<ProofStep>,
+    /// Hex-encoded.
+    pub merkle_root: String,
+    /// Hex-encoded Ed25519 public key.
+    pub public_key: String,
+    /// Hex-encoded Ed25519 signature.
+    pub signature: String,
+}
+
+/// Attest to the balance of `balances[index]`. `balances` holds every
+/// account, ordered by ID, as of ledger entry `ledger_sequence`.
+pub fn attest(
+    key: &SigningKey,
+    ledger_sequence: u64,
+    balances: &[(AccountId, Currency, Balance)],
+    index: usize,
+) -> Attestation {
+    let leaves: Vec<Digest> = balances
+        .iter()
+        .map(|&(account, currency, balance)| leaf(account, currency, balance))
+        .collect();
+    let (root, proof) = prove(&leaves, index);
+    let (account, currency, balance) = balances[index];
+    Attestation {
+        account,
+        currency,
+        balance,
+        ledger_sequence,
+        accounts: balances.len(),
+        leaf_index: index,
+        proof,
+        merkle_root: crypto::to_hex(&root),
+        public_key: crypto::to_hex(key.public_key()),
+        signature: crypto::to_hex(&key.sign(&message(ledger_sequence, &root))),
+    }
+}
+
+pub fn leaf(account: AccountId, currency: Currency, balance: Balance) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(&account.0.to_be_bytes());
+    hasher.update(currency.as_str().as_bytes());
+    hasher.update(&balance.minor_units().to_be_bytes());
+    hasher.finalize()
+}
+
+fn node(left: &Digest, right: &Digest) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize()
+}
+
+/// Root of the tree over `leaves`, which must not be empty, and the proof
+/// for the leaf at `index`.
+fn prove(leaves: &[Digest], mut index: usize) -> (Digest, Vec<ProofStep>) {
+    let mut level = leaves.to_vec();
+    let mut proof = Vec::new();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            proof.push(ProofStep {
+                side: if sibling < index {
+                    Side::Left
+                } else {
+                    Side::Right
+                },
+                hash: crypto::to_hex(&level[sibling]),
+            });
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        index /= 2;
+    }
+    (level[0], proof)
+}
+
+/// What the signature covers.
+pub fn message(ledger_sequence: u64, root: &Digest) -> Vec<u8> {
+    let mut message = SIGNATURE_CONTEXT.to_vec();
+    message.extend_from_slice(&ledger_sequence.to_be_bytes());
+    message.extend_from_slice(root);
+    message
+}
+
+/// The root `proof` leads to from `leaf`, `None` if a step is not valid hex.
+#[cfg(test)]
+pub fn fold(leaf: Digest, proof: &[ProofStep]) -> Option<Digest> {
+    proof.iter().try_fold(leaf, |hash, step| {
+        let sibling = Digest::try_from(crypto::from_hex(&step.hash)?).ok()?;
+        Some(match step.side {
+            Side::Left => node(&sibling, &hash),
+            Side::Right => node(&hash, &sibling),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_leaf_proves_the_same_root() {
+        for count in 1..=7u64 {
+            let leaves: Vec<Digest> = (1..=count)
+                .map(|id| {
+                    leaf(
+                        AccountId(id),
+                        Currency::default(),
+                        Balance::from_minor(-(id as i64)),
+                    )
+                })
+                .collect();
+            let (root, _) = prove(&leaves, 0);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let (proven, proof) = prove(&leaves, index);
+                assert_eq!(proven, root);
+                assert_eq!(fold(*leaf, &proof), Some(root));
+            }
+        }
+    }
+}
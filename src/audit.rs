@@ -0,0 +1,169 @@
+//! Tamper-evident record of every event, kept in `audit.jsonl` in the data
+//! directory and never compacted. Each entry carries the hash of the entry
+//! before it, and its own hash covers that and the event, so editing,
+//! removing or reordering entries breaks the chain.
+//!
+//! `bank verify-audit <data_dir>` checks the chain and that the snapshot and
+//! the event log agree with it, which also catches an audit log cut short.
+//! Cutting all three back consistently leaves a valid but shorter chain, so
+//! keep the head hash it prints somewhere else to compare against.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::crypto::{self, Digest, Sha256, DIGEST_LENGTH};
+use crate::encryption::{self, EncryptionError};
+use crate::events::EventRecord;
+use crate::store::{self, StoreError};
+
+pub const FILE: &str = "audit.jsonl";
+
+/// Hash the first entry follows.
+pub const GENESIS: Digest = [0; DIGEST_LENGTH];
+
+#[derive(Error, Debug)]
+pub enum AuditError {
+    #[error("Unable to read {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Invalid entry on line {line} of the audit log")]
+    Parse {
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Unable to read line {line} of the audit log")]
+    Sealed {
+        line: usize,
+        #[source]
+        source: EncryptionError,
+    },
+    #[error("Entry on line {line} of the audit log is out of sequence")]
+    OutOfSequence { line: usize },
+    #[error("Audit entry for event {sequence} was modified or does not follow the one before")]
+    Broken { sequence: u64 },
+    #[error("The audit log ends at event {audited} but the bank has events up to {expected}")]
+    Truncated { audited: u64, expected: u64 },
+    #[error("Event {sequence} of the bank differs from the audit log")]
+    Diverged { sequence: u64 },
+    #[error(transparent)]
+    Store(#[from] StoreError),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuditEntry {
+    /// Hex-encoded hash of the previous entry.
+    pub prev_hash: String,
+    /// Hex-encoded `chain(prev_hash, record)`.
+    pub hash: String,
+    pub record: EventRecord,
+}
+
+impl AuditEntry {
+    pub fn new(prev: &Digest, record: &EventRecord) -> AuditEntry {
+        AuditEntry {
+            prev_hash: crypto::to_hex(prev),
+            hash: crypto::to_hex(&chain(prev, record)),
+            record: record.clone(),
+        }
+    }
+}
+
+/// Hash of the entry for `record` after the entry hashed `prev`.
+pub fn chain(prev: &Digest, record: &EventRecord) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(prev);
+    hasher.update(&serde_json::to_vec(record).expect("events serialize"));
+    hasher.finalize()
+}
+
+#[derive(Debug)]
+pub struct AuditSummary {
+    /// Number of events in the audit log.
+    pub events: u64,
+    /// Hash of the last entry.
+    pub head: Digest,
+}
+
+/// Check the audit log in `data_dir` against itself and against the bank
+/// kept there.
+pub fn verify_audit_log(data_dir: &Path) -> Result<AuditSummary, AuditError> {
+    let path = data_dir.join(FILE);
+    let io_error = |source| AuditError::Io {
+        path: path.clone(),
+        source,
+    };
+    let file = File::open(&path).map_err(io_error)?;
+    // Hash of the entry for each event, by sequence number from 1.
+    let mut hashes: Vec<Digest> = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(io_error)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line = encryption::decode(&line).map_err(|source| AuditError::Sealed {
+            line: index + 1,
+            source,
+        })?;
+        let entry: AuditEntry =
+            serde_json::from_str(&line).map_err(|source| AuditError::Parse {
+                line: index + 1,
+                source,
+            })?;
+        let sequence = entry.record.sequence;
+        if sequence != hashes.len() as u64 + 1 {
+            return Err(AuditError::OutOfSequence { line: index + 1 });
+        }
+        let prev = hashes.last().unwrap_or(&GENESIS);
+        let hash = chain(prev, &entry.record);
+        if entry.prev_hash != crypto::to_hex(prev) || entry.hash != crypto::to_hex(&hash) {
+            return Err(AuditError::Broken { sequence });
+        }
+        hashes.push(hash);
+    }
+    let audited = hashes.len() as u64;
+    let hash_of = |sequence: u64| match sequence {
+        0 => &GENESIS,
+        sequence => &hashes[sequence as usize - 1],
+    };
+
+    let (snapshot, records) = store::read(data_dir)?;
+    if let Some(snapshot) = &snapshot {
+        if snapshot.sequence > audited {
+            return Err(AuditError::Truncated {
+                audited,
+                expected: snapshot.sequence,
+            });
+        }
+        if snapshot.audit_hash != crypto::to_hex(hash_of(snapshot.sequence)) {
+            return Err(AuditError::Diverged {
+                sequence: snapshot.sequence,
+            });
+        }
+    }
+    if let Some(last) = records.last() {
+        if last.sequence > audited {
+            return Err(AuditError::Truncated {
+                audited,
+                expected: last.sequence,
+            });
+        }
+    }
+    for record in &records {
+        let sequence = record.sequence;
+        if sequence == 0 || chain(hash_of(sequence - 1), record) != *hash_of(sequence) {
+            return Err(AuditError::Diverged { sequence });
+        }
+    }
+    Ok(AuditSummary {
+        events: audited,
+        head: *hash_of(audited),
+    })
+}
@@ -0,0 +1,450 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::crypto::constant_time_eq;
+use crate::protocol::RequestHeader;
+use crate::signing::RequestVerifier;
+use crate::socket::PeerCredentials;
+use crate::{users, Config};
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    #[error("missing or invalid credentials")]
+    Unauthenticated,
+    #[error("client is not allowed to perform this request")]
+    Forbidden,
+    #[error("request nonce was already used")]
+    Replayed,
+    #[error("request nonce is too far from the server's clock")]
+    Expired,
+}
+
+impl AuthError {
+    /// Status sent back to the client.
+    pub fn status(&self) -> &'static str {
+        match self {
+            AuthError::Unauthenticated | AuthError::Replayed | AuthError::Expired => "401",
+            AuthError::Forbidden => "403",
+        }
+    }
+}
+
+/// What an identity is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Everything, including shutdown and administrative instructions.
+    Admin,
+    /// Transfers between any accounts and account listings.
+    Teller,
+    /// Transfers out of the identity's own accounts and account listings.
+    Customer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdentityConfig {
+    pub role: Role,
+    /// Accounts a customer may transfer from.
+    #[serde(default)]
+    pub accounts: Vec<String>,
+}
+
+/// Who sent a request, as established by a token or by the peer's uid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    /// `None` when the identity has no entry in the `identities` setting.
+    pub role: Option<Role>,
+    pub accounts: Vec<String>,
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Checks applied to every request before it is dispatched.
+#[derive(Debug, Default)]
+pub struct Auth {
+    peers: PeerAuth,
+    tokens: TokenAuth,
+    signatures: RequestVerifier,
+    roles: Roles,
+}
+
+impl Auth {
+    pub fn from_config(config: &Config) -> io::Result<Auth> {
+        Ok(Auth {
+            peers: PeerAuth::from_config(config.peer_auth.as_ref())?,
+            tokens: TokenAuth::from_config(config.token_auth.as_ref())?,
+            signatures: RequestVerifier::from_config(config.request_signing.as_ref())?,
+            roles: Roles {
+                identities: config.identities.clone(),
+            },
+        })
+    }
+
+    /// Carry runtime state, such as seen nonces, over from the instance this
+    /// one replaces after a configuration reload.
+    pub fn carry_over(&mut self, previous: Auth) {
+        self.signatures.carry_over(previous.signatures);
+    }
+
+    pub fn authorize(
+        &self,
+        instruction: &str,
+        header: &RequestHeader,
+        peer: Option<&PeerCredentials>,
+    ) -> Result<Option<Identity>, AuthError> {
+        if !self.peers.permits(instruction, peer) {
+            return Err(AuthError::Forbidden);
+        }
+        // Without admins configured, the server's own user and root are.
+        if matches!(instruction, "q" | "d")
+            && !self.peers.enabled
+            && self.roles.identities.is_empty()
+            && !peer.is_some_and(is_local_admin)
+        {
+            return Err(AuthError::Forbidden);
+        }
+        let name = match self.tokens.authenticate(header.token.as_deref())? {
+            Some(name) => Some(name),
+            None => peer.and_then(|peer| self.peers.identity(peer.uid)),
+        };
+        let identity = name.map(|name| self.roles.identity(name));
+        self.roles
+            .permits_instruction(identity.as_ref(), instruction)?;
+        Ok(identity)
+    }
+
+    /// Check that `identity` may move money out of `from_account`.
+    pub fn authorize_transfer(
+        &self,
+        identity: Option<&Identity>,
+        from_account: &str,
+    ) -> Result<(), AuthError> {
+        self.roles.permits_account(identity, from_account)
+    }
+
+    /// Check that the identity may look at the account's history.
+    pub fn authorize_account(
+        &self,
+        identity: Option<&Identity>,
+        account: &str,
+    ) -> Result<(), AuthError> {
+        self.roles.permits_account(identity, account)
+    }
+
+    /// Check that the identity may decide on an escrow with `arbiter`.
+    pub fn authorize_arbiter(
+        &self,
+        identity: Option<&Identity>,
+        arbiter: &str,
+    ) -> Result<(), AuthError> {
+        self.roles.permits_arbiter(identity, arbiter)
+    }
+
+    /// Check that the identity may look at every account, not just its own.
+    pub fn authorize_all_accounts(&self, identity: Option<&Identity>) -> Result<(), AuthError> {
+        self.roles.permits_all_accounts(identity)
+    }
+
+    /// Check that the identity is an admin, for changes no one customer may
+    /// make alone.
+    pub fn authorize_admin(&self, identity: Option<&Identity>) -> Result<(), AuthError> {
+        self.roles.permits_admin(identity)
+    }
+
+    /// Refuse signed requests from before now, on taking over writes from
+    /// another server.
+    pub fn refuse_earlier_requests(&mut self) {
+        self.signatures.refuse_earlier();
+    }
+
+    /// Check the request signature over `payload`, which is empty for
+    /// instructions that carry none.
+    pub fn verify_signature(
+        &mut self,
+        instruction: &str,
+        header: &RequestHeader,
+        payload: &[u8],
+    ) -> Result<(), AuthError> {
+        self.signatures.verify(instruction, header, payload)
+    }
+}
+
+/// Which local users may talk to the bank, based on the credentials the
+/// kernel attaches to each datagram.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerAuthConfig {
+    /// Users (names or uids) allowed to send instructions.
+    pub allowed_users: Vec<String>,
+    /// Groups (names or gids) whose members may send instructions.
+    pub allowed_groups: Vec<String>,
+    /// Users allowed to send any instruction, including the `q` and `d`
+    /// shutdowns.
+    pub admin_users: Vec<String>,
+    /// Users (names or uids) mapped to the identity their requests carry
+    /// when they present no token. Listed users are allowed to connect.
+    pub identities: HashMap<String, String>,
+}
+
+/// Resolved form of `PeerAuthConfig`. Without a configuration every peer is
+/// allowed, which matches the behaviour before peer checks existed.
+#[derive(Debug, Default)]
+struct PeerAuth {
+    enabled: bool,
+    allowed_uids: HashSet<libc::uid_t>,
+    allowed_gids: HashSet<libc::gid_t>,
+    admin_uids: HashSet<libc::uid_t>,
+    identities: HashMap<libc::uid_t, String>,
+}
+
+impl PeerAuth {
+    fn from_config(config: Option<&PeerAuthConfig>) -> io::Result<PeerAuth> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(PeerAuth::default()),
+        };
+        Ok(PeerAuth {
+            enabled: true,
+            allowed_uids: config
+                .allowed_users
+                .iter()
+                .map(|user| users::resolve_user(user))
+                .collect::<io::Result<_>>()?,
+            allowed_gids: config
+                .allowed_groups
+                .iter()
+                .map(|group| users::resolve_group(group))
+                .collect::<io::Result<_>>()?,
+            admin_uids: config
+                .admin_users
+                .iter()
+                .map(|user| users::resolve_user(user))
+                .collect::<io::Result<_>>()?,
+            identities: config
+                .identities
+                .iter()
+                .map(|(user, identity)| Ok((users::resolve_user(user)?, identity.clone())))
+                .collect::<io::Result<_>>()?,
+        })
+    }
+
+    fn permits(&self, instruction: &str, peer: Option<&PeerCredentials>) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        let peer = match peer {
+            Some(peer) => peer,
+            None => return false,
+        };
+        if self.admin_uids.contains(&peer.uid) {
+            return true;
+        }
+        match instruction {
+            "q" | "d" | "z" => false,
+            _ => {
+                self.allowed_uids.contains(&peer.uid)
+                    || self.allowed_gids.contains(&peer.gid)
+                    || self.identities.contains_key(&peer.uid)
+            }
+        }
+    }
+
+    fn identity(&self, uid: libc::uid_t) -> Option<String> {
+        self.identities.get(&uid).cloned()
+    }
+}
+
+fn is_local_admin(peer: &PeerCredentials) -> bool {
+    peer.uid == 0 || peer.uid == unsafe { libc::geteuid() }
+}
+
+/// Bearer tokens clients present in the request header.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenAuthConfig {
+    /// Accepted tokens, mapped to the identity each one authenticates.
+    pub tokens: HashMap<String, String>,
+    /// File with further `<token> <identity>` lines. Blank lines and lines
+    /// starting with `#` are ignored. Re-read on every configuration reload.
+    pub token_file: Option<PathBuf>,
+}
+
+/// Resolved form of `TokenAuthConfig`. Without a configuration requests need
+/// no token and carry no identity.
+#[derive(Debug, Default)]
+struct TokenAuth {
+    enabled: bool,
+    tokens: Vec<(String, String)>,
+}
+
+impl TokenAuth {
+    fn from_config(config: Option<&TokenAuthConfig>) -> io::Result<TokenAuth> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(TokenAuth::default()),
+        };
+        let mut tokens: Vec<(String, String)> = config
+            .tokens
+            .iter()
+            .map(|(token, name)| (token.clone(), name.clone()))
+            .collect();
+        if let Some(path) = &config.token_file {
+            for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                match line.split_once(char::is_whitespace) {
+                    Some((token, name)) => {
+                        tokens.push((token.to_string(), name.trim().to_string()))
+                    }
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "{}:{}: expected '<token> <identity>'",
+                                path.display(),
+                                number + 1
+                            ),
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(TokenAuth {
+            enabled: true,
+            tokens,
+        })
+    }
+
+    fn authenticate(&self, token: Option<&str>) -> Result<Option<String>, AuthError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let token = token.ok_or(AuthError::Unauthenticated)?;
+        // Compare against every token so the response time does not reveal
+        // how many tokens, or which prefix, matched.
+        let mut found = None;
+        for (candidate, identity) in &self.tokens {
+            if constant_time_eq(candidate.as_bytes(), token.as_bytes()) {
+                found = Some(identity);
+            }
+        }
+        found.cloned().map(Some).ok_or(AuthError::Unauthenticated)
+    }
+}
+
+/// Role-based access control. Disabled, allowing everything, when no
+/// identities are configured.
+#[derive(Debug, Default)]
+struct Roles {
+    identities: HashMap<String, IdentityConfig>,
+}
+
+impl Roles {
+    fn identity(&self, name: String) -> Identity {
+        let config = self.identities.get(&name);
+        Identity {
+            role: config.map(|config| config.role),
+            accounts: config
+                .map(|config| config.accounts.clone())
+                .unwrap_or_default(),
+            name,
+        }
+    }
+
+    fn role(&self, identity: Option<&Identity>) -> Result<Role, AuthError> {
+        identity
+            .ok_or(AuthError::Unauthenticated)?
+            .role
+            .ok_or(AuthError::Forbidden)
+    }
+
+    fn permits_instruction(
+        &self,
+        identity: Option<&Identity>,
+        instruction: &str,
+    ) -> Result<(), AuthError> {
+        if self.identities.is_empty() {
+            return Ok(());
+        }
+        match (self.role(identity)?, instruction) {
+            (Role::Admin, _)
+            | (
+                _,
+                "t" | "b" | "p" | "w" | "o" | "l" | "s" | "i" | "h" | "v" | "e" | "u" | "k" | "n",
+            ) => Ok(()),
+            _ => Err(AuthError::Forbidden),
+        }
+    }
+
+    /// Only the arbiter itself and admins.
+    fn permits_arbiter(&self, identity: Option<&Identity>, arbiter: &str) -> Result<(), AuthError> {
+        if self.identities.is_empty() {
+            return Ok(());
+        }
+        match (self.role(identity)?, identity) {
+            (Role::Admin, _) => Ok(()),
+            (_, Some(identity)) if identity.name == arbiter => Ok(()),
+            _ => Err(AuthError::Forbidden),
+        }
+    }
+
+    fn permits_admin(&self, identity: Option<&Identity>) -> Result<(), AuthError> {
+        if self.identities.is_empty() {
+            return Ok(());
+        }
+        match self.role(identity)? {
+            Role::Admin => Ok(()),
+            Role::Teller | Role::Customer => Err(AuthError::Forbidden),
+        }
+    }
+
+    fn permits_all_accounts(&self, identity: Option<&Identity>) -> Result<(), AuthError> {
+        if self.identities.is_empty() {
+            return Ok(());
+        }
+        match self.role(identity)? {
+            Role::Admin | Role::Teller => Ok(()),
+            Role::Customer => Err(AuthError::Forbidden),
+        }
+    }
+
+    /// Customers may only act on their own accounts.
+    fn permits_account(
+        &self,
+        identity: Option<&Identity>,
+        account_name: &str,
+    ) -> Result<(), AuthError> {
+        if self.identities.is_empty() {
+            return Ok(());
+        }
+        match self.role(identity)? {
+            Role::Admin | Role::Teller => Ok(()),
+            Role::Customer => {
+                let identity = identity.ok_or(AuthError::Unauthenticated)?;
+                if identity
+                    .accounts
+                    .iter()
+                    .any(|account| account == account_name)
+                {
+                    Ok(())
+                } else {
+                    Err(AuthError::Forbidden)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Backups of a running bank, and restoring them to the point they were
+//! taken or to an earlier transaction.
+//!
+//! The `backup` admin command writes a backup to a new directory: a
+//! snapshot of the bank as of its last event and a copy of the audit log,
+//! see `audit`, which holds every event up to it. The server serves nothing
+//! else meanwhile, so the two agree, but it keeps running. A backup is a
+//! data directory the server can be started on as it is.
+//!
+//! `bank restore <backup> <data_dir> [<transaction_id>]` checks a backup's
+//! audit log and replays it into a new data directory, up to the point of
+//! the backup or up to the transaction given and its fee.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::audit::{self, AuditEntry, AuditError};
+use crate::clock::SystemClock;
+use crate::crypto::Digest;
+use crate::encryption;
+use crate::events::{Event, EventLogError};
+use crate::ledger::TransactionId;
+use crate::store::{Snapshot, Store, StoreError, DEFAULT_SNAPSHOT_EVERY};
+use crate::Bank;
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error("The bank is not kept in a data directory")]
+    NoDataDir,
+    #[error("The bank has events still to be stored, try again")]
+    Unstored,
+    #[error("{} already exists", path.display())]
+    Exists { path: PathBuf },
+    #[error("Unable to write {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Transaction {0} is not in the backup")]
+    TransactionNotFound(TransactionId),
+    #[error("Unable to replay the backup")]
+    Events(#[from] EventLogError),
+    #[error(transparent)]
+    Audit(#[from] AuditError),
+    #[error(transparent)]
+    Store(#[from] StoreError),
+}
+
+/// Where a backup was written and the event it goes up to.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupReceipt {
+    pub path: PathBuf,
+    pub sequence: u64,
+    /// Hex-encoded hash of the audit entry for event `sequence`.
+    pub audit_head: String,
+}
+
+/// The event a data directory was restored up to.
+#[derive(Debug, Clone)]
+pub struct Restored {
+    pub sequence: u64,
+    pub audit_head: Digest,
+}
+
+impl Bank {
+    /// Write a backup of the bank to the directory `path`, which must not
+    /// exist yet.
+    pub fn backup(&self, path: &Path) -> Result<BackupReceipt, BackupError> {
+        let store = self.store.as_ref().ok_or(BackupError::NoDataDir)?;
+        if self.has_unstored_events() {
+            return Err(BackupError::Unstored);
+        }
+        create_new_dir(path)?;
+        let audit_log = path.join(audit::FILE);
+        fs::copy(store.dir().join(audit::FILE), &audit_log).map_err(io_error(&audit_log))?;
+        let snapshot = self.snapshot(store.audit_head());
+        write_snapshot(path, &snapshot)?;
+        Ok(BackupReceipt {
+            path: path.to_owned(),
+            sequence: snapshot.sequence,
+            audit_head: snapshot.audit_hash,
+        })
+    }
+}
+
+/// Rebuild the bank backed up in `backup` in the new directory `data_dir`,
+/// up to the transaction `until` if given.
+pub fn restore_backup(
+    backup: &Path,
+    data_dir: &Path,
+    until: Option<TransactionId>,
+) -> Result<Restored, BackupError> {
+    audit::verify_audit_log(backup)?;
+    let entries = read_entries(backup)?;
+    let through = match until {
+        Some(transaction_id) => last_event_of(&entries, transaction_id)
+            .ok_or(BackupError::TransactionNotFound(transaction_id))?,
+        None => entries.len(),
+    };
+    let entries = &entries[..through];
+    create_new_dir(data_dir)?;
+    let audit_log = data_dir.join(audit::FILE);
+    let mut file = File::create(&audit_log).map_err(io_error(&audit_log))?;
+    for entry in entries {
+        let json = serde_json::to_vec(entry).expect("audit entries serialize");
+        let mut line = encryption::encode(json).map_err(io_error(&audit_log))?;
+        line.push(b'\n');
+        file.write_all(&line).map_err(io_error(&audit_log))?;
+    }
+    file.sync_all().map_err(io_error(&audit_log))?;
+    let mut bank = Bank::with_clock(Arc::new(SystemClock));
+    bank.apply_records(entries.iter().map(|entry| entry.record.clone()).collect())?;
+    let audit_head = entries.iter().fold(audit::GENESIS, |head, entry| {
+        audit::chain(&head, &entry.record)
+    });
+    write_snapshot(data_dir, &bank.snapshot(&audit_head))?;
+    Ok(Restored {
+        sequence: bank.events.last_sequence(),
+        audit_head,
+    })
+}
+
+/// How many entries, from the first, take in the transaction and its fee.
+fn last_event_of(entries: &[AuditEntry], transaction_id: TransactionId) -> Option<usize> {
+    let transferred = |entry: &AuditEntry| match &entry.record.event {
+        Event::FundsTransferred {
+            transaction_id,
+            fee_for,
+            ..
+        } => Some((*transaction_id, *fee_for)),
+        Event::AccountMerged {
+            transaction_id: Some(transaction_id),
+            ..
+        } => Some((*transaction_id, None)),
+        _ => None,
+    };
+    let position = entries.iter().position(|entry| {
+        transferred(entry).is_some_and(|(recorded, _)| recorded == transaction_id)
+    })?;
+    let fee = entries
+        .get(position + 1)
+        .and_then(transferred)
+        .is_some_and(|(_, fee_for)| fee_for == Some(transaction_id));
+    Some(position + 1 + usize::from(fee))
+}
+
+fn read_entries(dir: &Path) -> Result<Vec<AuditEntry>, AuditError> {
+    let path = dir.join(audit::FILE);
+    let io_error = |source| AuditError::Io {
+        path: path.clone(),
+        source,
+    };
+    let file = File::open(&path).map_err(io_error)?;
+    let mut entries = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(io_error)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line = encryption::decode(&line).map_err(|source| AuditError::Sealed {
+            line: index + 1,
+            source,
+        })?;
+        entries.push(
+            serde_json::from_str(&line).map_err(|source| AuditError::Parse {
+                line: index + 1,
+                source,
+            })?,
+        );
+    }
+    Ok(entries)
+}
+
+fn create_new_dir(path: &Path) -> Result<(), BackupError> {
+    if path.exists() {
+        return Err(BackupError::Exists {
+            path: path.to_owned(),
+        });
+    }
+    fs::create_dir_all(path).map_err(io_error(path))
+}
+
+/// Make `dir`, holding an audit log, a data directory starting from
+/// `snapshot`.
+fn write_snapshot(dir: &Path, snapshot: &Snapshot) -> Result<(), BackupError> {
+    let (mut store, _, _) = Store::open(dir, DEFAULT_SNAPSHOT_EVERY)?;
+    store.save_snapshot(snapshot).map_err(io_error(dir))
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> BackupError {
+    let path = path.to_owned();
+    move |source| BackupError::Io { path, source }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::{Balance, Currency, Money};
+    use crate::{load_bank, AccountRef, TxInfo};
+
+    fn pay(bank: &mut Bank, minor: u64) -> TransactionId {
+        bank.handle_transaction(TxInfo {
+            from: AccountRef::Name("a".to_string()),
+            to: AccountRef::Name("b".to_string()),
+            amount: Money::from_minor(minor),
+            currency: None,
+            convert: false,
+            pin: None,
+            memo: None,
+            external_ref: None,
+            idempotency_key: None,
+            dry_run: false,
+        })
+        .unwrap()
+        .transaction_id
+    }
+
+    fn balance(bank: &Bank, name: &str) -> i64 {
+        let id = bank.account_ids[name];
+        bank.accounts[&id].balance.minor_units()
+    }
+
+    #[test]
+    fn a_backup_restores_to_its_point_or_to_an_earlier_transaction() {
+        let dir = std::env::temp_dir().join(format!("bank-backup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut bank = Bank::open(Arc::new(SystemClock), &dir.join("data"), 3).unwrap();
+        for name in ["a", "b"] {
+            bank.open_account(name.to_string(), Balance::from_minor(100), Currency::EUR)
+                .unwrap();
+        }
+        let first = pay(&mut bank, 10);
+        pay(&mut bank, 20);
+        bank.snapshot_if_due();
+        let receipt = bank.backup(&dir.join("backup")).unwrap();
+        assert_eq!(receipt.sequence, bank.events.last_sequence());
+        pay(&mut bank, 30);
+        assert!(matches!(
+            bank.backup(&dir.join("backup")),
+            Err(BackupError::Exists { .. })
+        ));
+
+        // The backup is a data directory as it is.
+        assert_eq!(balance(&load_bank(&dir.join("backup")).unwrap(), "a"), 70);
+        let restored = restore_backup(&dir.join("backup"), &dir.join("latest"), None).unwrap();
+        assert_eq!(restored.sequence, receipt.sequence);
+        assert_eq!(balance(&load_bank(&dir.join("latest")).unwrap(), "a"), 70);
+        restore_backup(&dir.join("backup"), &dir.join("earlier"), Some(first)).unwrap();
+        let earlier = load_bank(&dir.join("earlier")).unwrap();
+        assert_eq!(balance(&earlier, "a"), 90);
+        assert_eq!(balance(&earlier, "b"), 110);
+        audit::verify_audit_log(&dir.join("earlier")).unwrap();
+        assert!(matches!(
+            restore_backup(
+                &dir.join("backup"),
+                &dir.join("later"),
+                Some(TransactionId(99))
+            ),
+            Err(BackupError::TransactionNotFound(_))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
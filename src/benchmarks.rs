@@ -0,0 +1,182 @@
+//! Throughput measurements run by `cargo bench`, see `benches/throughput.rs`:
+//! transfers through `Bank::handle_transaction` at various account counts,
+//! in memory and kept in a data directory, and the serialization of
+//! transfers and receipts.
+
+use std::fmt::{self, Display};
+use std::hint::black_box;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::actors::{Actors, Contention};
+use crate::clock::SystemClock;
+use crate::money::{Balance, Currency, Money};
+use crate::store;
+use crate::{AccountRef, Bank, TxInfo};
+
+pub struct Measurement {
+    pub name: String,
+    pub iterations: u64,
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    fn time(name: String, iterations: u64, mut run: impl FnMut(u64)) -> Measurement {
+        let started = Instant::now();
+        for iteration in 0..iterations {
+            run(iteration);
+        }
+        Measurement {
+            name,
+            iterations,
+            elapsed: started.elapsed(),
+        }
+    }
+}
+
+impl Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let iterations = self.iterations.max(1);
+        let per_iteration = self.elapsed.as_nanos() / u128::from(iterations);
+        let per_second = iterations as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON);
+        write!(
+            f,
+            "{:<40} {per_iteration:>10} ns/iter {per_second:>12.0} /s",
+            self.name
+        )
+    }
+}
+
+fn transfer(from: usize, to: usize) -> TxInfo {
+    TxInfo {
+        from: AccountRef::Name(format!("account{from}")),
+        to: AccountRef::Name(format!("account{to}")),
+        amount: Money::from_minor(1),
+        currency: None,
+        convert: false,
+        pin: None,
+        memo: None,
+        external_ref: None,
+        idempotency_key: None,
+        dry_run: false,
+    }
+}
+
+/// `iterations` transfers between `accounts` accounts, kept in `data_dir`
+/// if given, which should be empty.
+pub fn transfers(accounts: usize, iterations: u64, data_dir: Option<&Path>) -> Measurement {
+    let clock = Arc::new(SystemClock);
+    let mut bank = match data_dir {
+        Some(data_dir) => Bank::open(clock, data_dir, store::DEFAULT_SNAPSHOT_EVERY)
+            .expect("unable to open the benchmarked bank"),
+        None => Bank::with_clock(clock),
+    };
+    for number in 0..accounts {
+        bank.open_account(
+            format!("account{number}"),
+            Balance::from_minor(iterations as i64),
+            Currency::EUR,
+        )
+        .unwrap();
+    }
+    let kept = if data_dir.is_some() {
+        "kept"
+    } else {
+        "in memory"
+    };
+    let accounts = accounts.max(1);
+    Measurement::time(
+        format!("transfer, {accounts} accounts, {kept}"),
+        iterations,
+        |iteration| {
+            let from = iteration as usize % accounts;
+            let to = (iteration as usize * 7 + 1) % accounts;
+            black_box(bank.handle_transaction(transfer(from, to))).unwrap();
+        },
+    )
+}
+
+/// `iterations` transfers between `accounts` accounts made by their
+/// actors, see `actors`, from `threads` threads. One in four pays the first
+/// account, which shows in its contention, the first of those returned.
+pub fn actor_transfers(
+    accounts: usize,
+    iterations: u64,
+    threads: usize,
+) -> (Measurement, Vec<Contention>) {
+    let mut bank = Bank::new();
+    for number in 0..accounts {
+        bank.open_account(
+            format!("account{number}"),
+            Balance::from_minor(iterations as i64),
+            Currency::EUR,
+        )
+        .unwrap();
+    }
+    let actors = Actors::start(bank);
+    let (accounts, threads) = (accounts.max(2), threads.max(1));
+    let started = Instant::now();
+    thread::scope(|scope| {
+        for worker in 0..threads {
+            let actors = &actors;
+            scope.spawn(move || {
+                for iteration in (worker as u64..iterations).step_by(threads) {
+                    let from = iteration as usize % accounts;
+                    let to = match iteration % 4 {
+                        0 => 0,
+                        _ => (iteration as usize * 7 + 1) % accounts,
+                    };
+                    if from != to {
+                        black_box(actors.transfer(transfer(from, to))).unwrap();
+                    }
+                }
+            });
+        }
+    });
+    let elapsed = started.elapsed();
+    let (_, contention) = actors.finish();
+    let measurement = Measurement {
+        name: format!("actor transfer, {accounts} accounts, {threads} threads"),
+        iterations,
+        elapsed,
+    };
+    (measurement, contention)
+}
+
+/// Parsing the payload of the `t` instruction and writing the receipt.
+pub fn serialization(iterations: u64) -> Vec<Measurement> {
+    let payload = serde_json::to_vec(&transfer(0, 1)).unwrap();
+    let mut bank = Bank::new();
+    for number in 0..2 {
+        bank.open_account(
+            format!("account{number}"),
+            Balance::from_minor(1),
+            Currency::EUR,
+        )
+        .unwrap();
+    }
+    let receipt = bank.handle_transaction(transfer(0, 1)).unwrap();
+    vec![
+        Measurement::time("parse transfer".to_string(), iterations, |_| {
+            black_box(serde_json::from_slice::<TxInfo>(black_box(&payload))).unwrap();
+        }),
+        Measurement::time("write receipt".to_string(), iterations, |_| {
+            black_box(serde_json::to_string(black_box(&receipt))).unwrap();
+        }),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measurements_count_their_iterations() {
+        let measurement = transfers(3, 20, None);
+        assert_eq!(measurement.iterations, 20);
+        assert!(measurement.to_string().contains("3 accounts, in memory"));
+        assert_eq!(serialization(5).len(), 2);
+    }
+}
@@ -0,0 +1,388 @@
+//! Load generator for a running server: `concurrency` clients send a mix of
+//! requests at a given total rate, then the latency percentiles and error
+//! rates are reported, e.g.
+//!
+//! `bank-loadgen /tmp/server2client.sock --concurrency 8 --rate 500
+//! --duration 30 --mix transfer=80,history=15,health=5`
+//!
+//! Transfers move one minor unit between random `--accounts`, the demo
+//! accounts by default, so that they keep succeeding.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::json;
+
+const USAGE: &str = "Usage: bank-loadgen <socket> [--concurrency N] [--rate N] \
+    [--duration SECS | --requests N] [--mix KIND=WEIGHT,...] [--accounts NAME,...] \
+    [--token TOKEN] [--tenant NAME] [--timeout-ms N]
+Kinds: transfer, dry_run, history, info, health";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Transfer,
+    DryRun,
+    History,
+    Info,
+    Health,
+}
+
+impl Kind {
+    fn parse(name: &str) -> Result<Kind> {
+        Ok(match name {
+            "transfer" => Kind::Transfer,
+            "dry_run" => Kind::DryRun,
+            "history" => Kind::History,
+            "info" => Kind::Info,
+            "health" => Kind::Health,
+            _ => bail!("Unknown request kind '{name}'\n{USAGE}"),
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Transfer => "transfer",
+            Kind::DryRun => "dry_run",
+            Kind::History => "history",
+            Kind::Info => "info",
+            Kind::Health => "health",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Options {
+    socket: PathBuf,
+    concurrency: usize,
+    /// Requests per second over all clients, as fast as possible if absent.
+    rate: Option<f64>,
+    duration: Option<Duration>,
+    requests: Option<u64>,
+    mix: Vec<(Kind, u32)>,
+    accounts: Vec<String>,
+    token: Option<String>,
+    tenant: Option<String>,
+    timeout: Duration,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options> {
+        let socket = args.next().ok_or_else(|| anyhow!(USAGE))?;
+        let mut options = Options {
+            socket: PathBuf::from(socket),
+            concurrency: 4,
+            rate: None,
+            duration: None,
+            requests: None,
+            mix: vec![(Kind::Transfer, 1)],
+            accounts: ["patko", "siska", "sofka"].map(String::from).to_vec(),
+            token: None,
+            tenant: None,
+            timeout: Duration::from_secs(2),
+        };
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("{flag} needs a value\n{USAGE}"))?;
+            let invalid = || format!("Invalid {flag} '{value}'");
+            match flag.as_str() {
+                "--concurrency" => options.concurrency = value.parse().with_context(invalid)?,
+                "--rate" => options.rate = Some(value.parse().with_context(invalid)?),
+                "--duration" => {
+                    options.duration = Some(Duration::from_secs_f64(
+                        value.parse().with_context(invalid)?,
+                    ))
+                }
+                "--requests" => options.requests = Some(value.parse().with_context(invalid)?),
+                "--mix" => {
+                    options.mix = value
+                        .split(',')
+                        .map(|part| {
+                            let (kind, weight) = part.split_once('=').unwrap_or((part, "1"));
+                            Ok((Kind::parse(kind)?, weight.parse().with_context(invalid)?))
+                        })
+                        .collect::<Result<_>>()?
+                }
+                "--accounts" => options.accounts = value.split(',').map(String::from).collect(),
+                "--token" => options.token = Some(value),
+                "--tenant" => options.tenant = Some(value),
+                "--timeout-ms" => {
+                    options.timeout = Duration::from_millis(value.parse().with_context(invalid)?)
+                }
+                _ => bail!("Unknown option {flag}\n{USAGE}"),
+            }
+        }
+        if options.concurrency == 0 || options.mix.iter().all(|&(_, weight)| weight == 0) {
+            bail!("Nothing to send\n{USAGE}");
+        }
+        options.accounts.sort();
+        options.accounts.dedup();
+        if options.accounts.len() < 2 {
+            bail!("Transfers need at least two accounts");
+        }
+        if options.duration.is_none() && options.requests.is_none() {
+            options.duration = Some(Duration::from_secs(10));
+        }
+        Ok(options)
+    }
+}
+
+/// xorshift64*, to pick requests without a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % bound
+    }
+}
+
+/// What one client saw.
+#[derive(Debug, Default)]
+struct Results {
+    /// In microseconds, by kind.
+    latencies: BTreeMap<Kind, Vec<u64>>,
+    /// By kind and status, "timeout" when there was no reply.
+    errors: BTreeMap<(Kind, String), u64>,
+}
+
+impl Results {
+    fn merge(&mut self, other: Results) {
+        for (kind, latencies) in other.latencies {
+            self.latencies.entry(kind).or_default().extend(latencies);
+        }
+        for (key, count) in other.errors {
+            *self.errors.entry(key).or_default() += count;
+        }
+    }
+}
+
+struct Client<'a> {
+    options: &'a Options,
+    socket: UnixDatagram,
+    path: PathBuf,
+    rng: Rng,
+}
+
+impl Drop for Client<'_> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Client<'_> {
+    fn new(options: &Options, number: usize) -> Result<Client<'_>> {
+        let path = env::temp_dir().join(format!("bank-loadgen-{}-{number}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)
+            .with_context(|| format!("Unable to bind {}", path.display()))?;
+        socket.set_read_timeout(Some(options.timeout))?;
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_nanos() as u64);
+        Ok(Client {
+            options,
+            socket,
+            path,
+            rng: Rng(seed ^ (number as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1),
+        })
+    }
+
+    fn pick_kind(&mut self) -> Kind {
+        let total: u64 = self.options.mix.iter().map(|&(_, w)| u64::from(w)).sum();
+        let mut pick = self.rng.below(total);
+        for &(kind, weight) in &self.options.mix {
+            if pick < u64::from(weight) {
+                return kind;
+            }
+            pick -= u64::from(weight);
+        }
+        unreachable!("the pick is below the total weight")
+    }
+
+    fn pick_account(&mut self) -> &str {
+        let accounts = &self.options.accounts;
+        &accounts[self.rng.below(accounts.len() as u64) as usize]
+    }
+
+    /// The request for `kind`: the instruction and its payload, if any.
+    fn request(&mut self, kind: Kind) -> (&'static str, Option<String>) {
+        let from = self.pick_account().to_string();
+        let mut to = self.pick_account().to_string();
+        while to == from {
+            to = self.pick_account().to_string();
+        }
+        match kind {
+            Kind::Transfer | Kind::DryRun => {
+                let transfer = json!({
+                    "from": from,
+                    "to": to,
+                    "amount": "0.01",
+                    "dry_run": kind == Kind::DryRun,
+                });
+                ("t", Some(transfer.to_string()))
+            }
+            Kind::History => (
+                "h",
+                Some(json!({ "account": from, "limit": 10 }).to_string()),
+            ),
+            Kind::Info => ("i", None),
+            Kind::Health => ("k", None),
+        }
+    }
+
+    fn header(&self) -> String {
+        let mut header = serde_json::Map::new();
+        if let Some(token) = &self.options.token {
+            header.insert("token".to_string(), json!(token));
+        }
+        if let Some(tenant) = &self.options.tenant {
+            header.insert("tenant".to_string(), json!(tenant));
+        }
+        if header.is_empty() {
+            String::new()
+        } else {
+            serde_json::Value::Object(header).to_string()
+        }
+    }
+
+    /// Send one request and wait for its reply, returning the error status
+    /// if it failed.
+    fn send(&mut self, instruction: &str, payload: Option<&str>) -> Option<String> {
+        let datagram = format!("{instruction}{}", self.header());
+        let mut buffer = vec![0; 65_536];
+        let mut exchange = |message: &[u8]| -> Option<String> {
+            if let Err(e) = self.socket.send_to(message, &self.options.socket) {
+                return Some(format!("send: {}", e.kind()));
+            }
+            match self.socket.recv(&mut buffer) {
+                Ok(length) => Some(String::from_utf8_lossy(&buffer[..length]).into_owned()),
+                Err(_) => Some("timeout".to_string()),
+            }
+        };
+        let first = exchange(datagram.as_bytes())?;
+        let reply = match payload {
+            Some(payload) if first == "200" => exchange(payload.as_bytes())?,
+            _ => first,
+        };
+        is_error(&reply).then_some(reply)
+    }
+
+    fn run(mut self, deadline: Option<Instant>, requests: Option<u64>) -> Results {
+        let mut results = Results::default();
+        // Each client sends its share of the rate, evenly spaced.
+        let interval = self
+            .options
+            .rate
+            .map(|rate| Duration::from_secs_f64(self.options.concurrency as f64 / rate));
+        let mut next = Instant::now();
+        let mut sent = 0;
+        while requests.is_none_or(|requests| sent < requests)
+            && deadline.is_none_or(|deadline| Instant::now() < deadline)
+        {
+            if let Some(interval) = interval {
+                if let Some(wait) = next.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+                next += interval;
+            }
+            let kind = self.pick_kind();
+            let (instruction, payload) = self.request(kind);
+            let started = Instant::now();
+            let error = self.send(instruction, payload.as_deref());
+            let latency = started.elapsed().as_micros() as u64;
+            results.latencies.entry(kind).or_default().push(latency);
+            if let Some(status) = error {
+                *results.errors.entry((kind, status)).or_default() += 1;
+            }
+            sent += 1;
+        }
+        results
+    }
+}
+
+/// Replies that are a bare status other than "200", or no reply.
+fn is_error(reply: &str) -> bool {
+    reply == "timeout"
+        || reply.starts_with("send: ")
+        || (reply.len() == 3 && reply.bytes().all(|b| b.is_ascii_digit()) && reply != "200")
+}
+
+fn percentile(sorted: &[u64], percent: usize) -> u64 {
+    let index = (sorted.len() * percent).div_ceil(100).max(1) - 1;
+    sorted[index.min(sorted.len() - 1)]
+}
+
+fn report(results: &mut Results, elapsed: Duration) {
+    let total: usize = results.latencies.values().map(Vec::len).sum();
+    let errors: u64 = results.errors.values().sum();
+    println!(
+        "{total} requests in {:.1} s, {:.0} requests/s, {errors} errors ({:.2} %)",
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        errors as f64 * 100.0 / total.max(1) as f64
+    );
+    println!(
+        "{:<10} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "kind", "requests", "errors", "p50 µs", "p90 µs", "p99 µs", "max µs"
+    );
+    for (kind, latencies) in &mut results.latencies {
+        latencies.sort_unstable();
+        let errors: u64 = results
+            .errors
+            .iter()
+            .filter(|((error_kind, _), _)| error_kind == kind)
+            .map(|(_, count)| count)
+            .sum();
+        println!(
+            "{:<10} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+            kind.name(),
+            latencies.len(),
+            errors,
+            percentile(latencies, 50),
+            percentile(latencies, 90),
+            percentile(latencies, 99),
+            latencies.last().copied().unwrap_or_default()
+        );
+    }
+    for ((kind, status), count) in &results.errors {
+        println!("{:<10} {count:>8} x {status}", kind.name());
+    }
+}
+
+fn main() -> Result<()> {
+    let options = Options::parse(env::args().skip(1))?;
+    if !Path::new(&options.socket).exists() {
+        bail!("No server socket at {}", options.socket.display());
+    }
+    let started = Instant::now();
+    let deadline = options.duration.map(|duration| started + duration);
+    let mut results = Results::default();
+    thread::scope(|scope| -> Result<()> {
+        let mut workers = Vec::new();
+        for number in 0..options.concurrency {
+            let client = Client::new(&options, number)?;
+            // Spread the request count over the clients.
+            let requests = options.requests.map(|requests| {
+                let share = requests / options.concurrency as u64;
+                share + u64::from((number as u64) < requests % options.concurrency as u64)
+            });
+            workers.push(scope.spawn(move || client.run(deadline, requests)));
+        }
+        for worker in workers {
+            results.merge(worker.join().map_err(|_| anyhow!("A client panicked"))?);
+        }
+        Ok(())
+    })?;
+    report(&mut results, started.elapsed());
+    Ok(())
+}
@@ -0,0 +1,275 @@
+//! Servers replicating one bank with Raft, see `raft`, so that it survives
+//! the loss of a minority of them. Needs the `replication` feature.
+//!
+//! Each server of `Config::cluster` exchanges Raft messages with the others
+//! on a socket of its own. The leader serves requests as a single server
+//! would, but rather than store the events its bank emits, it appends them
+//! to the Raft log, one entry per event, and holds every reply back until
+//! the events before it are committed, that is replicated to a majority.
+//! Events are stored once committed, on every server. Followers apply the
+//! committed events to their banks and serve the read instructions, at most
+//! a second behind; anything else they refuse with "421", as they do
+//! requests reaching a leader still catching up.
+//!
+//! A leader that loses its majority before its events are committed may
+//! have them dropped by the next leader. Its bank then no longer matches
+//! the cluster's, so it stops with an error, to be restarted from what it
+//! stored, which is only ever committed events.
+//!
+//! Only the default bank is replicated, tenants and sandboxes are kept by
+//! each server on its own. Every server starts from a copy of the same data
+//! directory, and the log starts over at every restart of a leader, so a
+//! follower further behind than that has to be copied the data directory of
+//! another server. Entries are acknowledged once in memory: losing a
+//! majority of the servers at once can lose the last transfers.
+//!
+//! Short of a cluster, `Config::standby` pairs a primary with a standby,
+//! see `standby`, which is replicated to the same way but has the standby
+//! take over only when promoted. Either way the server loop and the bank
+//! only see `Replication`.
+
+#[cfg(feature = "replication")]
+mod node;
+#[cfg(feature = "replication")]
+pub mod raft;
+#[cfg(feature = "replication")]
+mod standby;
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+#[cfg(feature = "replication")]
+use std::fs;
+use std::io;
+#[cfg(feature = "replication")]
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(feature = "replication")]
+use std::sync::Arc;
+
+#[cfg(feature = "replication")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::events::EventRecord;
+#[cfg(feature = "replication")]
+use crate::socket::PeerCredentials;
+#[cfg(feature = "replication")]
+use crate::transport::Transport;
+
+#[cfg(feature = "replication")]
+pub use node::Cluster;
+#[cfg(feature = "replication")]
+pub use standby::Pair;
+
+#[derive(Error, Debug)]
+#[cfg_attr(not(feature = "replication"), allow(dead_code))]
+pub enum ClusterError {
+    #[error("This server, '{0}', is not one of the cluster's nodes")]
+    UnknownNode(String),
+    #[error("Unable to read or write the replication state in {}", path.display())]
+    State {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Invalid replication state in {}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Unable to set up the replication socket")]
+    Socket(#[from] io::Error),
+}
+
+/// What the server loop and the bank need of whatever replicates the
+/// default bank. Events are numbered by their sequence numbers.
+#[cfg_attr(not(feature = "replication"), allow(dead_code))]
+pub trait Replication: Debug + Send + Sync {
+    /// Replicate an event the bank emitted. Events this server cannot
+    /// replicate are dropped, see `dropped`.
+    fn propose(&self, record: EventRecord);
+
+    /// Wait until the events this server proposed are committed.
+    fn wait_committed(&self) -> io::Result<()>;
+
+    /// Whether this server may change a bank whose last event is
+    /// `last_sequence`.
+    fn accepts_writes(&self, last_sequence: u64) -> bool;
+
+    /// The server that does accept writes, as far as this one knows.
+    fn leader(&self) -> Option<String>;
+
+    /// The events after `applied` a bank should apply.
+    fn events_after(&self, applied: u64) -> Vec<EventRecord>;
+
+    /// Last event committed.
+    fn commit_index(&self) -> u64;
+
+    /// Last event stored with the bank.
+    fn stored(&self) -> u64;
+
+    /// Note that the events through `sequence` are stored with the bank.
+    fn set_stored(&self, sequence: u64) -> Result<(), ClusterError>;
+
+    /// Lowest event of a bank whose last event is `last_sequence` that was
+    /// dropped, if any. Those it does not have do not matter.
+    fn dropped(&self, last_sequence: u64) -> Option<u64>;
+
+    /// Take over writes, if this server is a standby. Returns whether it did.
+    fn promote(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    /// Name of this server, one of `nodes`.
+    pub node: String,
+    /// Socket each server of the cluster, this one included, takes the Raft
+    /// messages of the others on, by name. It gets the mode and owners of
+    /// the server's socket.
+    pub nodes: BTreeMap<String, PathBuf>,
+    /// Milliseconds without hearing from a leader before a server stands
+    /// for election, plus a random part of up to as much again.
+    #[serde(default = "default_election_timeout_ms")]
+    pub election_timeout_ms: u64,
+    /// Milliseconds between the leader's messages when there is nothing to
+    /// replicate.
+    #[serde(default = "default_heartbeat_ms")]
+    pub heartbeat_ms: u64,
+    /// Milliseconds a reply waits for the events before it to be committed.
+    /// It is not sent if they are not.
+    #[serde(default = "default_commit_timeout_ms")]
+    pub commit_timeout_ms: u64,
+}
+
+fn default_election_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_heartbeat_ms() -> u64 {
+    100
+}
+
+fn default_commit_timeout_ms() -> u64 {
+    5000
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StandbyRole {
+    Primary,
+    Standby,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StandbyConfig {
+    /// What this server starts as. The role it last took is kept in its
+    /// data directory and wins over this one. Reloading the configuration
+    /// with the standby's changed to `primary` promotes it.
+    pub role: StandbyRole,
+    /// Socket this server takes the other's messages on. It gets the mode
+    /// and owners of the server's socket.
+    pub socket_path: PathBuf,
+    /// Socket of the other server.
+    pub peer_socket_path: PathBuf,
+    /// Milliseconds between the primary's messages when there is nothing to
+    /// stream.
+    #[serde(default = "default_heartbeat_ms")]
+    pub heartbeat_ms: u64,
+    /// Milliseconds without hearing from the primary before the standby
+    /// promotes itself. Without it, the standby is only promoted by hand.
+    #[serde(default)]
+    pub failover_after_ms: Option<u64>,
+    /// Milliseconds a reply waits for the standby to have the events before
+    /// it. It is not sent if the standby does not.
+    #[serde(default = "default_commit_timeout_ms")]
+    pub commit_timeout_ms: u64,
+}
+
+/// Whether followers serve the instruction. They refuse the others.
+pub fn serves_on_follower(instruction: &str) -> bool {
+    matches!(
+        instruction,
+        "i" | "j" | "h" | "v" | "e" | "f" | "g" | "y" | "c" | "m" | "k" | "n" | "q" | "d"
+    )
+}
+
+/// Sends replies only once the events before them are committed, so that
+/// no client hears of a transfer that could still be lost.
+#[cfg(feature = "replication")]
+#[derive(Debug)]
+pub struct CommittedReplies {
+    transport: Arc<dyn Transport>,
+    replication: Arc<dyn Replication>,
+}
+
+#[cfg(feature = "replication")]
+impl CommittedReplies {
+    pub fn new(
+        transport: Arc<dyn Transport>,
+        replication: Arc<dyn Replication>,
+    ) -> CommittedReplies {
+        CommittedReplies {
+            transport,
+            replication,
+        }
+    }
+}
+
+#[cfg(feature = "replication")]
+impl Transport for CommittedReplies {
+    fn recv_from(
+        &self,
+        buffer: &mut [u8],
+    ) -> io::Result<(usize, Option<PathBuf>, Option<PeerCredentials>)> {
+        self.transport.recv_from(buffer)
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.transport.recv(buffer)
+    }
+
+    fn send_to(&self, message: &[u8], path: &Path) -> io::Result<()> {
+        self.replication.wait_committed()?;
+        self.transport.send_to(message, path)
+    }
+
+    fn send_to_nowait(&self, message: &[u8], path: &Path) -> io::Result<()> {
+        self.replication.wait_committed()?;
+        self.transport.send_to_nowait(message, path)
+    }
+}
+
+/// Read the replication state kept at `path`, the default if there is none.
+#[cfg(feature = "replication")]
+fn read_state<T: Default + DeserializeOwned>(path: &Path) -> Result<T, ClusterError> {
+    match fs::read(path) {
+        Ok(contents) => serde_json::from_slice(&contents).map_err(|source| ClusterError::Parse {
+            path: path.to_owned(),
+            source,
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(source) => Err(ClusterError::State {
+            path: path.to_owned(),
+            source,
+        }),
+    }
+}
+
+/// Replace the replication state kept at `path`.
+#[cfg(feature = "replication")]
+fn write_state<T: Serialize>(path: &Path, state: &T) -> Result<(), ClusterError> {
+    let error = |source| ClusterError::State {
+        path: path.to_owned(),
+        source,
+    };
+    let temporary = path.with_extension("json.tmp");
+    let contents = serde_json::to_vec(state).map_err(io::Error::from);
+    fs::write(&temporary, contents.map_err(error)?).map_err(error)?;
+    fs::rename(&temporary, path).map_err(error)
+}
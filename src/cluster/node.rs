@@ -0,0 +1,355 @@
+//! This server's part in the cluster: a thread exchanging Raft messages on
+//! the cluster socket, and the handle the server loop and the bank use.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Debug};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::raft::{HardState, Message, NodeId, Outgoing, RaftNode, Role, Timing};
+use super::{read_state, write_state, ClusterConfig, ClusterError, Replication};
+use crate::events::EventRecord;
+use crate::socket;
+
+/// Where the Raft state is kept in the data directory.
+const STATE_FILE: &str = "raft.json";
+
+/// Largest message taken on the cluster socket.
+const MAX_MESSAGE_SIZE: usize = 256 * 1024;
+
+/// What is kept across restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Persisted {
+    #[serde(flatten)]
+    hard_state: HardState,
+    /// Last entry stored with the bank, and its term.
+    stored: u64,
+    stored_term: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    from: NodeId,
+    message: Message<EventRecord>,
+}
+
+#[derive(Debug)]
+struct State {
+    raft: RaftNode<EventRecord>,
+    persisted: Persisted,
+    /// Last entry this server proposed.
+    proposed: u64,
+    /// Lowest event this server proposed or applied that the cluster
+    /// dropped.
+    dropped: Option<u64>,
+    /// Followers too far behind to be brought up to date, warned about.
+    lagging: BTreeSet<NodeId>,
+    role: Role,
+}
+
+impl State {
+    fn note_dropped(&mut self, sequence: u64) {
+        self.dropped = Some(
+            self.dropped
+                .map_or(sequence, |dropped| dropped.min(sequence)),
+        );
+    }
+}
+
+pub struct Cluster {
+    node: NodeId,
+    nodes: BTreeMap<NodeId, PathBuf>,
+    socket: UnixDatagram,
+    state: Mutex<State>,
+    /// Signalled when entries are committed or dropped.
+    changed: Condvar,
+    state_path: PathBuf,
+    commit_timeout: Duration,
+}
+
+impl Debug for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cluster")
+            .field("node", &self.node)
+            .field("nodes", &self.nodes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Cluster {
+    /// Join the cluster on `socket`, the one of `config.node`, with a bank
+    /// whose stored events end at `base`, and exchange Raft messages on a
+    /// thread of its own until the returned handle is dropped.
+    pub fn start(
+        config: &ClusterConfig,
+        socket: UnixDatagram,
+        data_dir: &Path,
+        base: u64,
+    ) -> Result<Arc<Cluster>, ClusterError> {
+        let state_path = data_dir.join(STATE_FILE);
+        let persisted: Persisted = read_state(&state_path)?;
+        // Without the term of the last event stored, it counts as older
+        // than any, which is safe.
+        let base_term = if persisted.stored == base {
+            persisted.stored_term
+        } else {
+            0
+        };
+        let peers = config
+            .nodes
+            .keys()
+            .filter(|&node| *node != config.node)
+            .cloned()
+            .collect();
+        let timing = Timing {
+            election_timeout_ms: config.election_timeout_ms,
+            heartbeat_ms: config.heartbeat_ms,
+        };
+        let raft = RaftNode::new(
+            config.node.clone(),
+            peers,
+            base,
+            base_term,
+            persisted.hard_state.clone(),
+            timing,
+        );
+        socket.set_read_timeout(Some(Duration::from_millis(config.heartbeat_ms.max(1))))?;
+        let cluster = Arc::new(Cluster {
+            node: config.node.clone(),
+            nodes: config.nodes.clone(),
+            socket,
+            state: Mutex::new(State {
+                role: raft.role(),
+                raft,
+                persisted: Persisted {
+                    stored: base,
+                    stored_term: base_term,
+                    ..persisted
+                },
+                proposed: 0,
+                dropped: None,
+                lagging: BTreeSet::new(),
+            }),
+            changed: Condvar::new(),
+            state_path,
+            commit_timeout: Duration::from_millis(config.commit_timeout_ms),
+        });
+        let handle = Arc::downgrade(&cluster);
+        thread::spawn(move || run(&handle));
+        info!("Joined the cluster as '{}'", config.node);
+        Ok(cluster)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn send(&self, outgoing: Outgoing<EventRecord>) {
+        for (to, message) in outgoing {
+            let envelope = Envelope {
+                from: self.node.clone(),
+                message,
+            };
+            let Ok(datagram) = serde_json::to_vec(&envelope) else {
+                continue;
+            };
+            // Servers that are down are what Raft is for.
+            if let Err(e) = socket::send_to_nowait(&self.socket, &datagram, &self.nodes[&to]) {
+                debug!("Unable to reach cluster node '{to}': {e}");
+            }
+        }
+    }
+
+    /// The next message from another server of the cluster, if one comes
+    /// in before the heartbeat is due.
+    fn receive(&self, buffer: &mut [u8]) -> Option<(NodeId, Message<EventRecord>)> {
+        let (length, sender, _) = match socket::recv_with_credentials(&self.socket, buffer) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return None
+            }
+            Err(e) => {
+                warn!("Unable to receive a cluster message: {e}");
+                return None;
+            }
+        };
+        if length > buffer.len() {
+            warn!("Ignored a cluster message of {length} bytes, over the limit");
+            return None;
+        }
+        let envelope: Envelope = match serde_json::from_slice(&buffer[..length]) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                warn!("Ignored a malformed cluster message: {e}");
+                return None;
+            }
+        };
+        // Only the node's own socket speaks for it.
+        if sender.as_deref() != self.nodes.get(&envelope.from).map(PathBuf::as_path)
+            || envelope.from == self.node
+        {
+            warn!(
+                "Ignored a cluster message claiming to be from '{}'",
+                envelope.from
+            );
+            return None;
+        }
+        Some((envelope.from, envelope.message))
+    }
+
+    /// Save what changed, tell about it and wake up whoever waits for it.
+    fn after_step(&self, state: &mut State) {
+        if let Some(dropped) = state.raft.take_dropped() {
+            warn!("The cluster dropped the entries from {dropped} on");
+            state.note_dropped(dropped);
+        }
+        let hard_state = state.raft.hard_state();
+        if hard_state != state.persisted.hard_state {
+            state.persisted.hard_state = hard_state;
+            if let Err(e) = write_state(&self.state_path, &state.persisted) {
+                warn!("{e}");
+            }
+        }
+        if state.raft.role() != state.role {
+            state.role = state.raft.role();
+            info!(
+                "This server is now {:?} in term {}",
+                state.role,
+                state.raft.term()
+            );
+        }
+        let lagging: BTreeSet<NodeId> = state.raft.lagging().cloned().collect();
+        for node in lagging.difference(&state.lagging) {
+            warn!("Cluster node '{node}' is too far behind, copy it the data directory of another");
+        }
+        state.lagging = lagging;
+        self.changed.notify_all();
+    }
+}
+
+fn run(cluster: &Weak<Cluster>) {
+    let mut buffer = vec![0; MAX_MESSAGE_SIZE];
+    let mut clock = Instant::now();
+    while let Some(cluster) = cluster.upgrade() {
+        let received = cluster.receive(&mut buffer);
+        let mut state = cluster.lock();
+        let mut outgoing = Vec::new();
+        if let Some((from, message)) = received {
+            outgoing.extend(state.raft.handle(&from, message));
+        }
+        // Whole milliseconds only, the rest counts with the next step.
+        let elapsed = clock.elapsed().as_millis() as u64;
+        clock += Duration::from_millis(elapsed);
+        outgoing.extend(state.raft.tick(elapsed));
+        cluster.after_step(&mut state);
+        drop(state);
+        cluster.send(outgoing);
+    }
+}
+
+impl Replication for Cluster {
+    /// Append an event of the leader's bank to the log.
+    fn propose(&self, record: EventRecord) {
+        let sequence = record.sequence;
+        let mut state = self.lock();
+        let index = if state.raft.last_index() + 1 == sequence {
+            state.raft.propose(record)
+        } else {
+            None
+        };
+        let Some(index) = index else {
+            warn!("Event {sequence} was not replicated, this server is not the leader");
+            state.note_dropped(sequence);
+            self.changed.notify_all();
+            return;
+        };
+        state.proposed = index;
+        let outgoing = state.raft.replicate();
+        drop(state);
+        self.send(outgoing);
+    }
+
+    fn wait_committed(&self) -> io::Result<()> {
+        let deadline = Instant::now() + self.commit_timeout;
+        let mut state = self.lock();
+        loop {
+            if state.raft.commit_index() >= state.proposed {
+                return Ok(());
+            }
+            if let Some(dropped) = state.dropped {
+                return Err(io::Error::other(format!(
+                    "event {dropped} was dropped by the cluster"
+                )));
+            }
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("event {} was not committed in time", state.proposed),
+                ));
+            };
+            state = self.changed.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+
+    /// Whether this server leads the cluster and its bank has everything in
+    /// the log.
+    fn accepts_writes(&self, last_sequence: u64) -> bool {
+        let state = self.lock();
+        state.raft.role() == Role::Leader
+            && state.raft.last_index() == last_sequence
+            && state.dropped.is_none()
+    }
+
+    fn leader(&self) -> Option<NodeId> {
+        self.lock().raft.leader().cloned()
+    }
+
+    /// Those committed, or on the leader all of them.
+    fn events_after(&self, applied: u64) -> Vec<EventRecord> {
+        let state = self.lock();
+        let through = match state.raft.role() {
+            Role::Leader => state.raft.last_index(),
+            _ => state.raft.commit_index(),
+        };
+        (applied + 1..=through)
+            .map_while(|index| state.raft.entry(index))
+            .map(|entry| entry.data.clone())
+            .collect()
+    }
+
+    fn commit_index(&self) -> u64 {
+        self.lock().raft.commit_index()
+    }
+
+    fn stored(&self) -> u64 {
+        self.lock().persisted.stored
+    }
+
+    fn set_stored(&self, sequence: u64) -> Result<(), ClusterError> {
+        let mut state = self.lock();
+        let term = state.raft.term_at(sequence).unwrap_or(0);
+        state.persisted.stored = sequence;
+        state.persisted.stored_term = term;
+        write_state(&self.state_path, &state.persisted)
+    }
+
+    fn dropped(&self, last_sequence: u64) -> Option<u64> {
+        let mut state = self.lock();
+        if state.dropped > Some(last_sequence) {
+            state.dropped = None;
+        }
+        state.dropped
+    }
+}
@@ -0,0 +1,641 @@
+//! Raft, as described by Ongaro and Ousterhout in "In Search of an
+//! Understandable Consensus Algorithm": leader election and log replication,
+//! without membership changes or log compaction.
+//!
+//! `RaftNode` keeps the state of one server and follows the rules, nothing
+//! more. Its caller delivers the messages of the other servers to `handle`,
+//! keeps time with `tick` and sends out the messages both return.
+//!
+//! The log starts after `base`, entries every server is known to have
+//! committed already, so entries up to it always match.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+pub type NodeId = String;
+
+/// Most entries sent in one message.
+const MAX_ENTRIES: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry<T> {
+    pub term: u64,
+    pub data: T,
+}
+
+/// What a server must remember across restarts to vote at most once a
+/// term.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardState {
+    pub term: u64,
+    pub voted_for: Option<NodeId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message<T> {
+    RequestVote {
+        term: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry<T>>,
+        leader_commit: u64,
+    },
+    /// Answers `AppendEntries`. `last_index` is the last entry known to
+    /// match the leader's when it succeeded, a guess at it when it failed.
+    Appended {
+        term: u64,
+        success: bool,
+        last_index: u64,
+    },
+}
+
+impl<T> Message<T> {
+    fn term(&self) -> u64 {
+        match self {
+            Message::RequestVote { term, .. }
+            | Message::Vote { term, .. }
+            | Message::AppendEntries { term, .. }
+            | Message::Appended { term, .. } => *term,
+        }
+    }
+}
+
+/// Messages to send, by the server they are for.
+pub type Outgoing<T> = Vec<(NodeId, Message<T>)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    /// Time without hearing from a leader before standing for election,
+    /// plus a random part of up to as much again.
+    pub election_timeout_ms: u64,
+    /// Time between the leader's messages when there is nothing new.
+    pub heartbeat_ms: u64,
+}
+
+#[derive(Debug)]
+pub struct RaftNode<T> {
+    id: NodeId,
+    peers: Vec<NodeId>,
+    timing: Timing,
+    role: Role,
+    term: u64,
+    voted_for: Option<NodeId>,
+    leader: Option<NodeId>,
+    base: u64,
+    base_term: u64,
+    /// Entry `base + 1` first.
+    log: Vec<Entry<T>>,
+    commit_index: u64,
+    votes: BTreeSet<NodeId>,
+    next_index: BTreeMap<NodeId, u64>,
+    match_index: BTreeMap<NodeId, u64>,
+    /// Since the leader was last heard from, or for a leader since it last
+    /// sent to every follower.
+    elapsed_ms: u64,
+    election_timeout_ms: u64,
+    /// Lowest entry dropped for conflicting with the leader's log since
+    /// `take_dropped`.
+    dropped: Option<u64>,
+}
+
+impl<T: Clone> RaftNode<T> {
+    /// A follower whose log continues after entry `base`, of term
+    /// `base_term`.
+    pub fn new(
+        id: NodeId,
+        peers: Vec<NodeId>,
+        base: u64,
+        base_term: u64,
+        hard_state: HardState,
+        timing: Timing,
+    ) -> RaftNode<T> {
+        let mut node = RaftNode {
+            id,
+            peers,
+            timing,
+            role: Role::Follower,
+            term: hard_state.term.max(base_term),
+            voted_for: hard_state.voted_for,
+            leader: None,
+            base,
+            base_term,
+            log: Vec::new(),
+            commit_index: base,
+            votes: BTreeSet::new(),
+            next_index: BTreeMap::new(),
+            match_index: BTreeMap::new(),
+            elapsed_ms: 0,
+            election_timeout_ms: 0,
+            dropped: None,
+        };
+        node.reset_election_timeout();
+        node
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn leader(&self) -> Option<&NodeId> {
+        self.leader.as_ref()
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    pub fn hard_state(&self) -> HardState {
+        HardState {
+            term: self.term,
+            voted_for: self.voted_for.clone(),
+        }
+    }
+
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.base + self.log.len() as u64
+    }
+
+    /// The entry at `index`, if it is in the log.
+    pub fn entry(&self, index: u64) -> Option<&Entry<T>> {
+        let position = index.checked_sub(self.base + 1)?;
+        self.log.get(position as usize)
+    }
+
+    /// Term of the entry at `index`, `None` if it is not in the log.
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.base {
+            return Some(self.base_term);
+        }
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    /// Followers whose logs end before the base, which this server cannot
+    /// bring up to date.
+    pub fn lagging(&self) -> impl Iterator<Item = &NodeId> + '_ {
+        self.next_index
+            .iter()
+            .filter(|&(_, &next)| next <= self.base)
+            .map(|(peer, _)| peer)
+    }
+
+    /// The lowest entry dropped since the last call, if any were.
+    pub fn take_dropped(&mut self) -> Option<u64> {
+        self.dropped.take()
+    }
+
+    /// Append `data` to the log of the leader. Returns its index, or `None`
+    /// if this server is not the leader. It is only sent with `replicate`
+    /// or the next heartbeat.
+    pub fn propose(&mut self, data: T) -> Option<u64> {
+        if self.role != Role::Leader {
+            return None;
+        }
+        self.log.push(Entry {
+            term: self.term,
+            data,
+        });
+        self.advance_commit_index();
+        Some(self.last_index())
+    }
+
+    /// Send what the followers are missing now rather than with the next
+    /// heartbeat.
+    pub fn replicate(&mut self) -> Outgoing<T> {
+        if self.role != Role::Leader {
+            return Vec::new();
+        }
+        self.elapsed_ms = 0;
+        self.peers
+            .clone()
+            .into_iter()
+            .map(|peer| {
+                let message = self.append_entries(&peer);
+                (peer, message)
+            })
+            .collect()
+    }
+
+    /// Let `elapsed_ms` pass.
+    pub fn tick(&mut self, elapsed_ms: u64) -> Outgoing<T> {
+        self.elapsed_ms += elapsed_ms;
+        match self.role {
+            Role::Leader if self.elapsed_ms >= self.timing.heartbeat_ms => self.replicate(),
+            Role::Follower | Role::Candidate if self.elapsed_ms >= self.election_timeout_ms => {
+                self.stand_for_election()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Take in `message` from the server `from`.
+    pub fn handle(&mut self, from: &str, message: Message<T>) -> Outgoing<T> {
+        if message.term() > self.term {
+            self.become_follower(message.term());
+        }
+        match message {
+            Message::RequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            } => {
+                let up_to_date = (last_log_term, last_log_index) >= self.last_log_position();
+                let granted = term == self.term
+                    && up_to_date
+                    && self.voted_for.as_deref().is_none_or(|voted| voted == from);
+                if granted {
+                    self.voted_for = Some(from.to_string());
+                    self.elapsed_ms = 0;
+                }
+                let vote = Message::Vote {
+                    term: self.term,
+                    granted,
+                };
+                vec![(from.to_string(), vote)]
+            }
+            Message::Vote { term, granted } => {
+                if self.role != Role::Candidate || term != self.term || !granted {
+                    return Vec::new();
+                }
+                self.votes.insert(from.to_string());
+                if self.votes.len() >= self.majority() {
+                    return self.become_leader();
+                }
+                Vec::new()
+            }
+            Message::AppendEntries {
+                term,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                if term < self.term {
+                    return vec![(from.to_string(), self.appended(false, self.last_index()))];
+                }
+                self.role = Role::Follower;
+                self.leader = Some(from.to_string());
+                self.elapsed_ms = 0;
+                let reply = self.append(prev_log_index, prev_log_term, entries, leader_commit);
+                vec![(from.to_string(), reply)]
+            }
+            Message::Appended {
+                term,
+                success,
+                last_index,
+            } => {
+                if self.role != Role::Leader || term != self.term {
+                    return Vec::new();
+                }
+                if success {
+                    let matched = self.match_index.entry(from.to_string()).or_default();
+                    *matched = (*matched).max(last_index);
+                    self.next_index.insert(from.to_string(), last_index + 1);
+                    self.advance_commit_index();
+                    if last_index >= self.last_index() {
+                        return Vec::new();
+                    }
+                } else {
+                    let next = self.next_index.entry(from.to_string()).or_insert(1);
+                    *next = (*next - 1).min(last_index + 1).max(1);
+                }
+                let message = self.append_entries(from);
+                vec![(from.to_string(), message)]
+            }
+        }
+    }
+
+    fn majority(&self) -> usize {
+        self.peers.len().div_ceil(2) + 1
+    }
+
+    fn last_log_position(&self) -> (u64, u64) {
+        let last_index = self.last_index();
+        (self.term_at(last_index).unwrap_or(0), last_index)
+    }
+
+    fn reset_election_timeout(&mut self) {
+        let timeout = self.timing.election_timeout_ms;
+        self.election_timeout_ms = timeout + jitter(&self.id, self.term, timeout);
+        self.elapsed_ms = 0;
+    }
+
+    fn become_follower(&mut self, term: u64) {
+        self.term = term;
+        self.role = Role::Follower;
+        self.voted_for = None;
+        self.leader = None;
+        self.reset_election_timeout();
+    }
+
+    fn stand_for_election(&mut self) -> Outgoing<T> {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.id.clone());
+        self.leader = None;
+        self.votes = BTreeSet::from([self.id.clone()]);
+        self.reset_election_timeout();
+        if self.votes.len() >= self.majority() {
+            return self.become_leader();
+        }
+        let (last_log_term, last_log_index) = self.last_log_position();
+        self.peers
+            .iter()
+            .map(|peer| {
+                let request = Message::RequestVote {
+                    term: self.term,
+                    last_log_index,
+                    last_log_term,
+                };
+                (peer.clone(), request)
+            })
+            .collect()
+    }
+
+    fn become_leader(&mut self) -> Outgoing<T> {
+        self.role = Role::Leader;
+        self.leader = Some(self.id.clone());
+        let next = self.last_index() + 1;
+        self.next_index = self.peers.iter().map(|peer| (peer.clone(), next)).collect();
+        self.match_index = self.peers.iter().map(|peer| (peer.clone(), 0)).collect();
+        self.replicate()
+    }
+
+    fn append_entries(&self, peer: &str) -> Message<T> {
+        let next = self.next_index.get(peer).copied().unwrap_or(1);
+        // The leader does not have what comes before its base, a follower
+        // that needs it is left where it is.
+        let prev_log_index = (next - 1).clamp(self.base, self.last_index());
+        let start = (prev_log_index - self.base) as usize;
+        Message::AppendEntries {
+            term: self.term,
+            prev_log_index,
+            prev_log_term: self.term_at(prev_log_index).unwrap_or(0),
+            entries: self.log[start..]
+                .iter()
+                .take(MAX_ENTRIES)
+                .cloned()
+                .collect(),
+            leader_commit: self.commit_index,
+        }
+    }
+
+    fn append(
+        &mut self,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry<T>>,
+        leader_commit: u64,
+    ) -> Message<T> {
+        if prev_log_index > self.last_index() {
+            return self.appended(false, self.last_index());
+        }
+        if prev_log_index > self.base && self.term_at(prev_log_index) != Some(prev_log_term) {
+            return self.appended(false, prev_log_index - 1);
+        }
+        let last_new = prev_log_index + entries.len() as u64;
+        for (index, entry) in (prev_log_index + 1..).zip(entries) {
+            if index <= self.base {
+                continue;
+            }
+            match self.term_at(index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => {
+                    self.log.truncate((index - self.base - 1) as usize);
+                    self.dropped = Some(self.dropped.map_or(index, |dropped| dropped.min(index)));
+                }
+                None => {}
+            }
+            self.log.push(entry);
+        }
+        if leader_commit > self.commit_index {
+            self.commit_index = leader_commit.min(last_new).max(self.commit_index);
+        }
+        self.appended(true, last_new)
+    }
+
+    fn appended(&self, success: bool, last_index: u64) -> Message<T> {
+        Message::Appended {
+            term: self.term,
+            success,
+            last_index,
+        }
+    }
+
+    /// Commit the last entry of this term a majority has, and with it the
+    /// entries before it.
+    fn advance_commit_index(&mut self) {
+        for index in (self.commit_index + 1..=self.last_index()).rev() {
+            if self.term_at(index) != Some(self.term) {
+                break;
+            }
+            let replicated = 1 + self
+                .match_index
+                .values()
+                .filter(|&&matched| matched >= index)
+                .count();
+            if replicated >= self.majority() {
+                self.commit_index = index;
+                break;
+            }
+        }
+    }
+}
+
+/// A number below `range` that differs between servers and terms, so that
+/// servers do not keep standing for election at the same time.
+fn jitter(id: &str, term: u64, range: u64) -> u64 {
+    // FNV-1a.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in id.bytes().chain(term.to_le_bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash % range.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    const TIMING: Timing = Timing {
+        election_timeout_ms: 100,
+        heartbeat_ms: 20,
+    };
+
+    /// Servers that exchange messages in order, apart from those that are
+    /// down.
+    struct Network {
+        nodes: BTreeMap<NodeId, RaftNode<u64>>,
+        down: BTreeSet<NodeId>,
+        in_flight: VecDeque<(NodeId, NodeId, Message<u64>)>,
+    }
+
+    impl Network {
+        fn new(names: &[&str]) -> Network {
+            let nodes = names
+                .iter()
+                .map(|&name| {
+                    let peers = names
+                        .iter()
+                        .filter(|&&peer| peer != name)
+                        .map(|peer| peer.to_string())
+                        .collect();
+                    let node =
+                        RaftNode::new(name.to_string(), peers, 0, 0, HardState::default(), TIMING);
+                    (name.to_string(), node)
+                })
+                .collect();
+            Network {
+                nodes,
+                down: BTreeSet::new(),
+                in_flight: VecDeque::new(),
+            }
+        }
+
+        fn send(&mut self, from: &str, outgoing: Outgoing<u64>) {
+            for (to, message) in outgoing {
+                self.in_flight.push_back((from.to_string(), to, message));
+            }
+        }
+
+        /// Let `ms` pass, 10 at a time, delivering every message.
+        fn run(&mut self, ms: u64) {
+            for _ in 0..ms / 10 {
+                let names: Vec<NodeId> = self.nodes.keys().cloned().collect();
+                for name in &names {
+                    if !self.down.contains(name) {
+                        let outgoing = self.nodes.get_mut(name).unwrap().tick(10);
+                        self.send(name, outgoing);
+                    }
+                }
+                while let Some((from, to, message)) = self.in_flight.pop_front() {
+                    if self.down.contains(&from) || self.down.contains(&to) {
+                        continue;
+                    }
+                    let outgoing = self.nodes.get_mut(&to).unwrap().handle(&from, message);
+                    self.send(&to, outgoing);
+                }
+            }
+        }
+
+        fn leaders(&self) -> Vec<NodeId> {
+            self.nodes
+                .iter()
+                .filter(|(name, node)| !self.down.contains(*name) && node.role() == Role::Leader)
+                .map(|(name, _)| name.clone())
+                .collect()
+        }
+
+        fn propose(&mut self, leader: &str, data: u64) -> u64 {
+            let node = self.nodes.get_mut(leader).unwrap();
+            let index = node.propose(data).unwrap();
+            let outgoing = node.replicate();
+            self.send(leader, outgoing);
+            index
+        }
+
+        fn committed(&self, name: &str) -> Vec<u64> {
+            let node = &self.nodes[name];
+            (1..=node.commit_index())
+                .map(|index| node.entry(index).unwrap().data)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn committed_entries_survive_the_loss_of_the_leader() {
+        let mut network = Network::new(&["a", "b", "c"]);
+        network.run(500);
+        let leaders = network.leaders();
+        assert_eq!(leaders.len(), 1);
+        let first = leaders[0].clone();
+        network.propose(&first, 1);
+        network.propose(&first, 2);
+        network.run(100);
+        for name in ["a", "b", "c"] {
+            assert_eq!(network.committed(name), [1, 2]);
+        }
+
+        network.down.insert(first.clone());
+        network.run(500);
+        let second = network.leaders().pop().unwrap();
+        assert_ne!(second, first);
+        network.propose(&second, 3);
+        network.run(100);
+        let running: Vec<&NodeId> = network
+            .nodes
+            .keys()
+            .filter(|&name| *name != first)
+            .collect();
+        for name in running {
+            assert_eq!(network.committed(name), [1, 2, 3]);
+        }
+
+        // The old leader catches up once it is back.
+        network.down.remove(&first);
+        network.run(300);
+        assert_eq!(network.leaders(), [second]);
+        assert_eq!(network.committed(&first), [1, 2, 3]);
+    }
+
+    #[test]
+    fn a_leader_cut_off_from_the_majority_commits_nothing() {
+        let mut network = Network::new(&["a", "b", "c"]);
+        network.run(500);
+        let first = network.leaders().pop().unwrap();
+        network.propose(&first, 1);
+        network.run(100);
+
+        // The others go down, then come back without it.
+        let others: Vec<NodeId> = network
+            .nodes
+            .keys()
+            .filter(|&name| *name != first)
+            .cloned()
+            .collect();
+        network.down.extend(others.iter().cloned());
+        network.propose(&first, 2);
+        network.run(500);
+        assert_eq!(network.nodes[&first].commit_index(), 1);
+
+        network.down = BTreeSet::from([first.clone()]);
+        network.run(500);
+        let second = network.leaders().pop().unwrap();
+        network.propose(&second, 3);
+        network.run(100);
+
+        // Its uncommitted entry gives way to the new leader's.
+        network.down.clear();
+        network.run(300);
+        assert_eq!(network.committed(&first), [1, 3]);
+        assert_eq!(
+            network.nodes.get_mut(&first).unwrap().take_dropped(),
+            Some(2)
+        );
+    }
+}
@@ -0,0 +1,565 @@
+//! A primary streaming the events of its bank to a standby, which takes
+//! over when promoted, by hand or once it stops hearing from the primary.
+//!
+//! The primary holds every reply back until the standby has the events
+//! before it, so a promoted standby has every transfer a client heard of.
+//! Each promotion starts a new epoch. A primary learning of a later one,
+//! from the standby's answers or the new primary's events, stops accepting
+//! writes: it becomes the new primary's standby, or, if its bank has events
+//! the standby never got, stops with an error, to be restarted from what it
+//! stored. A primary cut off from a standby that took over can therefore
+//! make no transfer stick.
+//!
+//! A promoted standby goes on alone until the other server comes back as
+//! its standby and catches up. The first primary does not: while its
+//! standby is down its replies time out. Restart it without `standby` to
+//! go on without one.
+
+use std::fmt::{self, Debug};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{read_state, write_state, ClusterError, Replication, StandbyConfig, StandbyRole};
+use crate::events::EventRecord;
+use crate::socket;
+
+/// Where the role and epoch are kept in the data directory.
+const STATE_FILE: &str = "standby.json";
+
+/// Largest message taken on the standby socket.
+const MAX_MESSAGE_SIZE: usize = 256 * 1024;
+
+/// Most events streamed in one message.
+const MAX_EVENTS: usize = 64;
+
+/// What is kept across restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Persisted {
+    /// Role last taken, which wins over the configured one.
+    role: Option<StandbyRole>,
+    epoch: u64,
+    /// Whether the primary goes on without a standby, after a promotion.
+    alone: bool,
+    /// Last event stored with the bank.
+    stored: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    /// The primary's events following on from `previous`, none for a
+    /// heartbeat.
+    Events {
+        epoch: u64,
+        previous: u64,
+        events: Vec<EventRecord>,
+    },
+    /// The standby has the events through `last`.
+    Have { epoch: u64, last: u64 },
+}
+
+#[derive(Debug)]
+struct State {
+    persisted: Persisted,
+    role: StandbyRole,
+    /// Events after `base`, made or received since this server started.
+    base: u64,
+    log: Vec<EventRecord>,
+    commit_index: u64,
+    /// Last event this server proposed.
+    proposed: u64,
+    /// Last event the standby has, once it answered the primary.
+    acked: Option<u64>,
+    /// Whether the primary warned that the standby is too far behind.
+    lagging: bool,
+    /// When the standby last heard from the primary.
+    heard: Instant,
+    /// Lowest event this server made that was dropped.
+    dropped: Option<u64>,
+}
+
+impl State {
+    fn last(&self) -> u64 {
+        self.base + self.log.len() as u64
+    }
+
+    fn note_dropped(&mut self, sequence: u64) {
+        self.dropped = Some(
+            self.dropped
+                .map_or(sequence, |dropped| dropped.min(sequence)),
+        );
+    }
+}
+
+pub struct Pair {
+    peer: PathBuf,
+    socket: UnixDatagram,
+    state: Mutex<State>,
+    /// Signalled when events are committed or dropped.
+    changed: Condvar,
+    state_path: PathBuf,
+    heartbeat: Duration,
+    failover_after: Option<Duration>,
+    commit_timeout: Duration,
+}
+
+impl Debug for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pair")
+            .field("peer", &self.peer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Pair {
+    /// Pair up on `socket` with the other server, with a bank whose stored
+    /// events end at `base`, and exchange messages on a thread of its own
+    /// until the returned handle is dropped.
+    pub fn start(
+        config: &StandbyConfig,
+        socket: UnixDatagram,
+        data_dir: &Path,
+        base: u64,
+    ) -> Result<Arc<Pair>, ClusterError> {
+        let state_path = data_dir.join(STATE_FILE);
+        let persisted: Persisted = read_state(&state_path)?;
+        let role = persisted.role.unwrap_or(config.role);
+        let heartbeat = Duration::from_millis(config.heartbeat_ms.max(1));
+        socket.set_read_timeout(Some(heartbeat))?;
+        let pair = Arc::new(Pair {
+            peer: config.peer_socket_path.clone(),
+            socket,
+            state: Mutex::new(State {
+                persisted: Persisted {
+                    stored: base,
+                    ..persisted
+                },
+                role,
+                base,
+                log: Vec::new(),
+                commit_index: base,
+                proposed: base,
+                acked: None,
+                lagging: false,
+                heard: Instant::now(),
+                dropped: None,
+            }),
+            changed: Condvar::new(),
+            state_path,
+            heartbeat,
+            failover_after: config.failover_after_ms.map(Duration::from_millis),
+            commit_timeout: Duration::from_millis(config.commit_timeout_ms),
+        });
+        let handle = Arc::downgrade(&pair);
+        thread::spawn(move || run(&handle));
+        info!(
+            "Paired with {} as the {role:?}",
+            config.peer_socket_path.display()
+        );
+        Ok(pair)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn save(&self, persisted: &Persisted) {
+        if let Err(e) = write_state(&self.state_path, persisted) {
+            warn!("{e}");
+        }
+    }
+
+    /// What the primary streams next: the events the standby is missing,
+    /// or a heartbeat if it has not answered or is too far behind.
+    fn next_events(&self, state: &mut State) -> Message {
+        let epoch = state.persisted.epoch;
+        let acked = match state.acked {
+            Some(acked) if acked >= state.base => acked,
+            acked => {
+                if acked.is_some() && !state.lagging {
+                    warn!(
+                        "The standby is too far behind, copy it the data directory of the primary"
+                    );
+                    state.lagging = true;
+                }
+                return Message::Events {
+                    epoch,
+                    previous: state.last(),
+                    events: Vec::new(),
+                };
+            }
+        };
+        let from = (acked - state.base) as usize;
+        Message::Events {
+            epoch,
+            previous: acked,
+            events: state.log[from..].iter().take(MAX_EVENTS).cloned().collect(),
+        }
+    }
+
+    /// Answer a message of the other server.
+    fn handle(&self, state: &mut State, message: Message) -> Option<Message> {
+        match message {
+            Message::Events {
+                epoch,
+                previous,
+                events,
+            } => {
+                if epoch < state.persisted.epoch {
+                    // Tells the sender it was superseded.
+                    return Some(Message::Have {
+                        epoch: state.persisted.epoch,
+                        last: state.last(),
+                    });
+                }
+                if state.role == StandbyRole::Primary {
+                    if epoch == state.persisted.epoch {
+                        warn!("Both servers are primary in epoch {epoch}, ignoring the other");
+                        return None;
+                    }
+                    self.step_down(state, epoch);
+                }
+                if epoch > state.persisted.epoch {
+                    state.persisted.epoch = epoch;
+                    self.save(&state.persisted);
+                }
+                state.heard = Instant::now();
+                if previous <= state.last() {
+                    for record in events {
+                        if record.sequence == state.last() + 1 {
+                            state.log.push(record);
+                        }
+                    }
+                    state.commit_index = state.last();
+                    self.changed.notify_all();
+                }
+                Some(Message::Have {
+                    epoch,
+                    last: state.last(),
+                })
+            }
+            Message::Have { epoch, last } => {
+                if epoch > state.persisted.epoch {
+                    if state.role == StandbyRole::Primary {
+                        self.step_down(state, epoch);
+                    }
+                    return None;
+                }
+                if epoch < state.persisted.epoch || state.role != StandbyRole::Primary {
+                    return None;
+                }
+                if last > state.last() {
+                    warn!(
+                        "The standby has events {} to {last} this primary does not",
+                        state.last() + 1
+                    );
+                    return None;
+                }
+                state.acked = Some(last);
+                if state.persisted.alone && last >= state.commit_index {
+                    info!("The standby caught up, replies wait for it again");
+                    state.persisted.alone = false;
+                    self.save(&state.persisted);
+                }
+                if !state.persisted.alone && last > state.commit_index {
+                    state.commit_index = last;
+                    self.changed.notify_all();
+                }
+                (last < state.last()).then(|| self.next_events(state))
+            }
+        }
+    }
+
+    /// Stop being the primary, the other server having been promoted in
+    /// `epoch`. Events the standby never got are dropped.
+    fn step_down(&self, state: &mut State, epoch: u64) {
+        warn!("The other server was promoted in epoch {epoch}, this one is now its standby");
+        if state.last() > state.commit_index {
+            state.note_dropped(state.commit_index + 1);
+            state
+                .log
+                .truncate((state.commit_index - state.base) as usize);
+        }
+        state.role = StandbyRole::Standby;
+        state.acked = None;
+        state.heard = Instant::now();
+        state.persisted.role = Some(StandbyRole::Standby);
+        state.persisted.epoch = epoch;
+        state.persisted.alone = false;
+        self.save(&state.persisted);
+        self.changed.notify_all();
+    }
+
+    fn promote_locked(&self, state: &mut State, reason: &str) -> bool {
+        if state.role == StandbyRole::Primary {
+            return false;
+        }
+        state.role = StandbyRole::Primary;
+        state.commit_index = state.last();
+        state.proposed = state.last();
+        state.acked = None;
+        state.persisted.role = Some(StandbyRole::Primary);
+        state.persisted.epoch += 1;
+        state.persisted.alone = true;
+        self.save(&state.persisted);
+        warn!(
+            "Promoted to primary in epoch {}, {reason}",
+            state.persisted.epoch
+        );
+        true
+    }
+
+    fn send(&self, message: Message) {
+        let Ok(datagram) = serde_json::to_vec(&message) else {
+            return;
+        };
+        // The other server being down is what the pair is for.
+        if let Err(e) = socket::send_to_nowait(&self.socket, &datagram, &self.peer) {
+            debug!("Unable to reach {}: {e}", self.peer.display());
+        }
+    }
+
+    /// The next message from the other server, if one comes in before the
+    /// heartbeat is due.
+    fn receive(&self, buffer: &mut [u8]) -> Option<Message> {
+        let (length, sender, _) = match socket::recv_with_credentials(&self.socket, buffer) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return None
+            }
+            Err(e) => {
+                warn!("Unable to receive a standby message: {e}");
+                return None;
+            }
+        };
+        if length > buffer.len() {
+            warn!("Ignored a standby message of {length} bytes, over the limit");
+            return None;
+        }
+        // Only the other server's socket speaks for it.
+        if sender.as_deref() != Some(self.peer.as_path()) {
+            warn!("Ignored a standby message from {sender:?}");
+            return None;
+        }
+        match serde_json::from_slice(&buffer[..length]) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                warn!("Ignored a malformed standby message: {e}");
+                None
+            }
+        }
+    }
+}
+
+fn run(pair: &Weak<Pair>) {
+    let mut buffer = vec![0; MAX_MESSAGE_SIZE];
+    let mut sent = Instant::now();
+    while let Some(pair) = pair.upgrade() {
+        let received = pair.receive(&mut buffer);
+        let mut state = pair.lock();
+        let mut outgoing = Vec::new();
+        if let Some(message) = received {
+            outgoing.extend(pair.handle(&mut state, message));
+        }
+        match state.role {
+            StandbyRole::Primary if sent.elapsed() >= pair.heartbeat => {
+                outgoing.push(pair.next_events(&mut state));
+            }
+            StandbyRole::Standby
+                if pair
+                    .failover_after
+                    .is_some_and(|after| state.heard.elapsed() >= after) =>
+            {
+                let reason = "the primary went quiet";
+                if pair.promote_locked(&mut state, reason) {
+                    pair.changed.notify_all();
+                }
+            }
+            _ => {}
+        }
+        drop(state);
+        if !outgoing.is_empty() {
+            sent = Instant::now();
+        }
+        for message in outgoing {
+            pair.send(message);
+        }
+    }
+}
+
+impl Replication for Pair {
+    fn propose(&self, record: EventRecord) {
+        let sequence = record.sequence;
+        let mut state = self.lock();
+        if state.role != StandbyRole::Primary
+            || state.dropped.is_some()
+            || state.last() + 1 != sequence
+        {
+            warn!("Event {sequence} was not streamed, this server is not the primary");
+            state.note_dropped(sequence);
+            self.changed.notify_all();
+            return;
+        }
+        state.log.push(record);
+        state.proposed = sequence;
+        if state.persisted.alone {
+            state.commit_index = sequence;
+            self.changed.notify_all();
+        }
+        let message = state.acked.map(|_| self.next_events(&mut state));
+        drop(state);
+        if let Some(message) = message {
+            self.send(message);
+        }
+    }
+
+    fn wait_committed(&self) -> io::Result<()> {
+        let deadline = Instant::now() + self.commit_timeout;
+        let mut state = self.lock();
+        loop {
+            if state.commit_index >= state.proposed {
+                return Ok(());
+            }
+            if let Some(dropped) = state.dropped {
+                return Err(io::Error::other(format!(
+                    "event {dropped} was dropped, the standby took over"
+                )));
+            }
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("event {} did not reach the standby in time", state.proposed),
+                ));
+            };
+            state = self.changed.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+
+    /// Whether this server is the primary and its bank has everything in
+    /// the log.
+    fn accepts_writes(&self, last_sequence: u64) -> bool {
+        let state = self.lock();
+        state.role == StandbyRole::Primary
+            && state.last() == last_sequence
+            && state.dropped.is_none()
+    }
+
+    /// The socket of the primary, on the standby.
+    fn leader(&self) -> Option<String> {
+        (self.lock().role == StandbyRole::Standby).then(|| self.peer.display().to_string())
+    }
+
+    fn events_after(&self, applied: u64) -> Vec<EventRecord> {
+        let state = self.lock();
+        let from = applied.saturating_sub(state.base) as usize;
+        state.log.get(from..).unwrap_or_default().to_vec()
+    }
+
+    fn commit_index(&self) -> u64 {
+        self.lock().commit_index
+    }
+
+    fn stored(&self) -> u64 {
+        self.lock().persisted.stored
+    }
+
+    fn set_stored(&self, sequence: u64) -> Result<(), ClusterError> {
+        let mut state = self.lock();
+        state.persisted.stored = sequence;
+        write_state(&self.state_path, &state.persisted)
+    }
+
+    fn dropped(&self, last_sequence: u64) -> Option<u64> {
+        let mut state = self.lock();
+        if state.dropped > Some(last_sequence) {
+            state.dropped = None;
+        }
+        state.dropped
+    }
+
+    fn promote(&self) -> bool {
+        let mut state = self.lock();
+        let promoted = self.promote_locked(&mut state, "as configured");
+        self.changed.notify_all();
+        promoted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::events::Event;
+    use crate::{AccountId, AccountStatus};
+
+    fn record(sequence: u64) -> EventRecord {
+        EventRecord {
+            sequence,
+            timestamp: 0,
+            event: Event::StatusChanged {
+                account: AccountId(1),
+                status: AccountStatus::Frozen,
+            },
+        }
+    }
+
+    fn start(dir: &Path, name: &str, peer: &str, role: StandbyRole) -> Arc<Pair> {
+        let config = StandbyConfig {
+            role,
+            socket_path: dir.join(format!("{name}.sock")),
+            peer_socket_path: dir.join(format!("{peer}.sock")),
+            heartbeat_ms: 10,
+            failover_after_ms: None,
+            commit_timeout_ms: 2000,
+        };
+        let data_dir = dir.join(name);
+        fs::create_dir_all(&data_dir).unwrap();
+        let socket = UnixDatagram::bind(&config.socket_path).unwrap();
+        Pair::start(&config, socket, &data_dir, 0).unwrap()
+    }
+
+    #[test]
+    fn a_promoted_standby_has_every_committed_event_and_fences_the_primary() {
+        let dir = std::env::temp_dir().join(format!("bank-standby-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let primary = start(&dir, "primary", "standby", StandbyRole::Primary);
+        let standby = start(&dir, "standby", "primary", StandbyRole::Standby);
+
+        for sequence in 1..=3 {
+            primary.propose(record(sequence));
+            primary.wait_committed().unwrap();
+        }
+        assert_eq!(standby.events_after(0).len(), 3);
+        assert!(!standby.accepts_writes(3));
+        assert!(standby.leader().is_some());
+
+        assert!(standby.promote());
+        assert!(standby.accepts_writes(3));
+        // The old primary learns of the promotion from its next event,
+        // which is dropped, as the standby never takes it.
+        primary.propose(record(4));
+        assert!(primary.wait_committed().is_err());
+        assert_eq!(primary.dropped(4), Some(4));
+        assert!(!primary.accepts_writes(4));
+        // The new primary goes on alone.
+        standby.propose(record(4));
+        standby.wait_committed().unwrap();
+        assert_eq!(standby.commit_index(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
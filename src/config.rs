@@ -0,0 +1,349 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+use crate::auth::{IdentityConfig, PeerAuthConfig, TokenAuthConfig};
+use crate::cluster::{ClusterConfig, StandbyConfig};
+use crate::crypto::ed25519::{self, SigningKey};
+use crate::crypto::{self, argon2};
+use crate::encryption::Cipher;
+use crate::fees::FeeConfig;
+use crate::fraud::FraudRule;
+use crate::fx::{self, Rate};
+use crate::hardening::HardeningConfig;
+use crate::holds;
+use crate::interbank::PeerBankConfig;
+use crate::interest;
+use crate::limits::TransferLimits;
+use crate::logging::LogFormat;
+use crate::money::Money;
+use crate::noise::NoiseConfig;
+use crate::protocol;
+use crate::ratelimit::RateLimitConfig;
+use crate::settlement;
+use crate::signing::SigningConfig;
+use crate::store;
+use crate::webhooks::WebhookConfig;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Unable to read configuration file {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Invalid configuration file {}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// Settings that do not go together, found when the server starts.
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+}
+
+/// Server configuration, read from a JSON file.
+///
+/// Everything except the `socket_*` settings, `vsock_port`, `udp_address`,
+/// `replica_socket_path`, `run_as_*`, `data_dir`, `encryption_key`, `noise`, `hardening`,
+/// `tenants`, `bank_name`, `peer_banks`, `cluster` and `standby` is re-read
+/// on SIGHUP.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Log filter directives in `RUST_LOG` syntax, e.g. `"info"` or `"bank=debug"`.
+    pub log_level: Option<String>,
+    /// `"text"` or `"json"`, see `logging`.
+    pub log_format: LogFormat,
+    pub socket_path: PathBuf,
+    /// Permission bits applied to the socket file after binding, written as
+    /// an octal string such as `"0660"`. Defaults to whatever the umask gives.
+    #[serde(deserialize_with = "deserialize_mode")]
+    pub socket_mode: Option<u32>,
+    /// User (name or uid) that should own the socket file.
+    pub socket_owner: Option<String>,
+    /// Group (name or gid) that should own the socket file.
+    pub socket_group: Option<String>,
+    /// Listen on this vsock port instead of at `socket_path`, for clients
+    /// in virtual machines, see `vsock`.
+    pub vsock_port: Option<u32>,
+    /// Listen for UDP datagrams at this address, e.g. `"0.0.0.0:7070"`,
+    /// instead of at `socket_path`, see `udp`.
+    pub udp_address: Option<SocketAddr>,
+    /// User (name or uid) to run as once the sockets are bound, for a
+    /// server started as root, see `privileges`.
+    pub run_as_user: Option<String>,
+    /// Group (name or gid) to run as, by default the primary group of
+    /// `run_as_user`.
+    pub run_as_group: Option<String>,
+    /// Second socket, serving only the read instructions `i` and `h` from a
+    /// copy of the bank, see `replica`. It gets the mode and owners of the
+    /// server's socket.
+    pub replica_socket_path: Option<PathBuf>,
+    /// Restrict instructions to the listed local users. Every local user
+    /// that can open the socket is allowed when this is absent.
+    pub peer_auth: Option<PeerAuthConfig>,
+    /// Require a bearer token with every request.
+    pub token_auth: Option<TokenAuthConfig>,
+    /// Require every request to be signed, see `signing`.
+    pub request_signing: Option<SigningConfig>,
+    /// Encrypt the server's and the replica's sockets, see `noise`.
+    pub noise: Option<NoiseConfig>,
+    /// Confine the server to the files and system calls it needs, see
+    /// `hardening`.
+    pub hardening: Option<HardeningConfig>,
+    /// Roles of the identities established by tokens or peer credentials.
+    /// When set, every request needs an identity with a role.
+    pub identities: HashMap<String, IdentityConfig>,
+    /// Throttle each client, identified by its identity or socket path.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Largest request or payload datagram accepted, in bytes. Larger ones
+    /// are refused with "413".
+    pub max_message_size: usize,
+    /// Threads that check the transfers of independent batches, see
+    /// `shards`. Zero starts one per CPU.
+    pub workers: usize,
+    /// Argon2id PHC hashes of account PINs, by account name, as printed by
+    /// `bank hash-pin`. Transfers out of these accounts must carry the PIN.
+    #[serde(deserialize_with = "deserialize_pin_hashes")]
+    pub account_pins: HashMap<String, String>,
+    /// Exchange rates by currency pair, e.g. `{"EUR/USD": "1.0854"}` for
+    /// 1.0854 USD per EUR. A pair is also used in the reverse direction.
+    #[serde(deserialize_with = "deserialize_fx_rates")]
+    pub fx_rates: HashMap<String, Rate>,
+    /// Seconds before an uncaptured hold expires and releases its funds.
+    /// Changes apply to holds placed afterwards.
+    pub hold_ttl_secs: u64,
+    /// Length of the periods interest accrues over, in seconds. Interest is
+    /// posted when a period ends.
+    pub interest_period_secs: u64,
+    /// Fees charged on transfers, none if not set.
+    pub fees: Option<FeeConfig>,
+    /// Limits for accounts without limits of their own.
+    pub transfer_limits: TransferLimits,
+    /// Rules every transfer is screened against, see `fraud`.
+    pub fraud_rules: Vec<FraudRule>,
+    /// Script of transaction policies, see `policy`. Needs the `scripting`
+    /// feature.
+    pub policy_script: Option<PathBuf>,
+    /// Directory the bank is kept in across restarts, see `store`. Without
+    /// it the bank starts with the initial accounts every time.
+    pub data_dir: Option<PathBuf>,
+    /// CSV or JSON file of the accounts a new bank starts with, see
+    /// `import`. Without it there are three demo accounts.
+    pub accounts_file: Option<PathBuf>,
+    /// Number of events after which the bank is snapshotted and its event
+    /// log started over.
+    pub snapshot_every: u64,
+    /// Hex-encoded 32-byte Ed25519 seed that balance attestations are signed
+    /// with, see `attestation`. Attestations are refused without it.
+    #[serde(deserialize_with = "deserialize_attestation_key")]
+    pub attestation_key: Option<SigningKey>,
+    /// Hex-encoded 32-byte key the data directory is encrypted with, see
+    /// `encryption`. Takes precedence over `BANK_ENCRYPTION_KEY`.
+    #[serde(deserialize_with = "deserialize_encryption_key")]
+    pub encryption_key: Option<Cipher>,
+    /// OpenTelemetry collector to export request traces to, over OTLP/HTTP,
+    /// e.g. `"http://localhost:4318"`. Needs the `otlp` feature.
+    pub otlp_endpoint: Option<String>,
+    /// Endpoints committed transfers are posted to, see `webhooks`.
+    pub webhooks: Vec<WebhookConfig>,
+    /// Further banks served next to this one, by tenant name. Requests
+    /// naming a tenant in their header go to its bank, with accounts and
+    /// ledger of its own; the other settings apply to every bank.
+    pub tenants: BTreeMap<String, TenantConfig>,
+    /// Name the default bank goes by with its `peer_banks`.
+    pub bank_name: String,
+    /// Other bank servers that transfers can be sent to and received from,
+    /// by the name they go by, see `interbank`.
+    pub peer_banks: BTreeMap<String, PeerBankConfig>,
+    /// How often what is owed to and by the peer banks is netted and
+    /// settled, see `settlement`. Zero settles only on demand.
+    pub settlement_period_secs: u64,
+    /// Transfers of more than this, in the sending account's currency, wait
+    /// for an admin to approve them, see `review`.
+    pub review_threshold: Option<Money>,
+    /// Servers replicating the default bank with this one, see `cluster`.
+    /// Needs the `replication` feature and a `data_dir`.
+    pub cluster: Option<ClusterConfig>,
+    /// The other server of a primary and standby pair, see `cluster`. Needs
+    /// the `replication` feature and a `data_dir`. Only its `role` is
+    /// re-read on SIGHUP, to promote the standby.
+    pub standby: Option<StandbyConfig>,
+    #[serde(skip)]
+    source: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            log_level: None,
+            log_format: LogFormat::default(),
+            socket_path: PathBuf::from("/tmp/server2client.sock"),
+            socket_mode: None,
+            socket_owner: None,
+            socket_group: None,
+            vsock_port: None,
+            udp_address: None,
+            run_as_user: None,
+            run_as_group: None,
+            replica_socket_path: None,
+            peer_auth: None,
+            token_auth: None,
+            request_signing: None,
+            noise: None,
+            hardening: None,
+            identities: HashMap::new(),
+            rate_limit: None,
+            max_message_size: protocol::DEFAULT_MAX_MESSAGE_SIZE,
+            workers: 0,
+            account_pins: HashMap::new(),
+            fx_rates: HashMap::new(),
+            hold_ttl_secs: holds::DEFAULT_TTL_SECS,
+            interest_period_secs: interest::DEFAULT_PERIOD_SECS,
+            fees: None,
+            transfer_limits: TransferLimits::default(),
+            fraud_rules: Vec::new(),
+            policy_script: None,
+            data_dir: None,
+            accounts_file: None,
+            snapshot_every: store::DEFAULT_SNAPSHOT_EVERY,
+            attestation_key: None,
+            encryption_key: None,
+            otlp_endpoint: None,
+            webhooks: Vec::new(),
+            tenants: BTreeMap::new(),
+            bank_name: "bank".to_string(),
+            peer_banks: BTreeMap::new(),
+            settlement_period_secs: settlement::DEFAULT_PERIOD_SECS,
+            review_threshold: None,
+            cluster: None,
+            standby: None,
+            source: None,
+        }
+    }
+}
+
+/// A bank served next to the default one, see `Config::tenants`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TenantConfig {
+    /// Like `Config::data_dir`, but no two banks may share one.
+    pub data_dir: Option<PathBuf>,
+    /// Like `Config::accounts_file`.
+    pub accounts_file: Option<PathBuf>,
+    /// Endpoints the tenant's committed transfers are posted to, instead of
+    /// the top-level `webhooks`.
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        let mut config: Config =
+            serde_json::from_str(&contents).map_err(|source| ConfigError::Parse {
+                path: path.to_owned(),
+                source,
+            })?;
+        config.source = Some(path.to_owned());
+        Ok(config)
+    }
+
+    /// Re-read the file this configuration was loaded from. Returns `None` for
+    /// a configuration that did not come from a file.
+    pub fn reload(&self) -> Option<Result<Config, ConfigError>> {
+        self.source.as_deref().map(Config::load)
+    }
+
+    /// The file this configuration was loaded from.
+    pub(crate) fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+}
+
+fn deserialize_mode<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mode = match Option::<String>::deserialize(deserializer)? {
+        Some(mode) => mode,
+        None => return Ok(None),
+    };
+    match u32::from_str_radix(&mode, 8) {
+        Ok(bits) if bits <= 0o7777 => Ok(Some(bits)),
+        _ => Err(serde::de::Error::custom(format!(
+            "invalid file mode '{mode}', expected an octal string like \"0660\""
+        ))),
+    }
+}
+
+fn deserialize_pin_hashes<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pin_hashes = HashMap::<String, String>::deserialize(deserializer)?;
+    if let Some(account) = pin_hashes
+        .iter()
+        .find_map(|(account, hash)| (!argon2::is_valid_hash(hash)).then_some(account))
+    {
+        return Err(serde::de::Error::custom(format!(
+            "PIN of account '{account}' is not an Argon2id PHC string"
+        )));
+    }
+    Ok(pin_hashes)
+}
+
+fn deserialize_fx_rates<'de, D>(deserializer: D) -> Result<HashMap<String, Rate>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rates = HashMap::<String, Rate>::deserialize(deserializer)?;
+    for pair in rates.keys() {
+        match fx::parse_pair(pair) {
+            Some((from, to)) if from != to => {}
+            _ => {
+                return Err(serde::de::Error::custom(format!(
+                    "invalid currency pair '{pair}', expected two different currencies like \"EUR/USD\""
+                )))
+            }
+        }
+    }
+    Ok(rates)
+}
+
+fn deserialize_attestation_key<'de, D>(deserializer: D) -> Result<Option<SigningKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let seed = match Option::<String>::deserialize(deserializer)? {
+        Some(seed) => seed,
+        None => return Ok(None),
+    };
+    match crypto::from_hex(&seed).and_then(|seed| <[u8; ed25519::SEED_LENGTH]>::try_from(seed).ok())
+    {
+        Some(seed) => Ok(Some(SigningKey::from_seed(&seed))),
+        None => Err(serde::de::Error::custom(format!(
+            "attestation key must be {} hex-encoded bytes",
+            ed25519::SEED_LENGTH
+        ))),
+    }
+}
+
+fn deserialize_encryption_key<'de, D>(deserializer: D) -> Result<Option<Cipher>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|key| Cipher::from_hex(&key).map_err(serde::de::Error::custom))
+        .transpose()
+}
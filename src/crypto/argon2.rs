@@ -0,0 +1,407 @@
+//! Argon2id (RFC 9106, version 0x13) password hashing, with PHC string
+//! encoding for storage.
+
+use super::blake2b::{Blake2b, MAX_OUTPUT_LENGTH};
+use super::{base64_decode, base64_encode, constant_time_eq, random_bytes};
+
+const VERSION: u32 = 0x13;
+const ARGON2ID: u32 = 2;
+const BLOCK_WORDS: usize = 128;
+const SYNC_POINTS: usize = 4;
+const SALT_LENGTH: usize = 16;
+const TAG_LENGTH: usize = 32;
+/// Most memory, in KiB, and passes a stored hash may ask for, so that a bad
+/// hash in the configuration cannot exhaust the server.
+const MAX_MEMORY: u32 = 1024 * 1024;
+const MAX_ITERATIONS: u32 = 16;
+const MAX_LANES: u32 = 16;
+
+type Block = [u64; BLOCK_WORDS];
+
+/// Cost parameters. The defaults follow the OWASP recommendation for
+/// Argon2id (19 MiB, two passes, one lane).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Params {
+    /// Memory in KiB.
+    pub memory: u32,
+    pub iterations: u32,
+    pub lanes: u32,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            memory: 19 * 1024,
+            iterations: 2,
+            lanes: 1,
+        }
+    }
+}
+
+/// Raw Argon2id with optional secret and associated data.
+pub fn argon2id(
+    password: &[u8],
+    salt: &[u8],
+    secret: &[u8],
+    associated_data: &[u8],
+    params: Params,
+    tag_length: usize,
+) -> Vec<u8> {
+    let lanes = params.lanes.max(1) as usize;
+    let memory = (params.memory as usize).max(2 * SYNC_POINTS * lanes);
+    let lane_length = memory / (SYNC_POINTS * lanes) * SYNC_POINTS;
+    let segment_length = lane_length / SYNC_POINTS;
+    let iterations = params.iterations.max(1) as usize;
+
+    let mut h0 = Blake2b::new(64);
+    for value in [
+        lanes as u32,
+        tag_length as u32,
+        params.memory,
+        iterations as u32,
+        VERSION,
+        ARGON2ID,
+    ] {
+        h0.update(&value.to_le_bytes());
+    }
+    for input in [password, salt, secret, associated_data] {
+        h0.update(&(input.len() as u32).to_le_bytes());
+        h0.update(input);
+    }
+    let h0 = h0.finalize();
+
+    let mut memory_blocks = vec![[0u64; BLOCK_WORDS]; lanes * lane_length];
+    for lane in 0..lanes {
+        for column in 0..2u32 {
+            let mut seed = h0.clone();
+            seed.extend_from_slice(&column.to_le_bytes());
+            seed.extend_from_slice(&(lane as u32).to_le_bytes());
+            let bytes = variable_hash(&seed, BLOCK_WORDS * 8);
+            memory_blocks[lane * lane_length + column as usize] = block_from_bytes(&bytes);
+        }
+    }
+
+    let geometry = Geometry {
+        lanes,
+        lane_length,
+        segment_length,
+        iterations,
+        total_blocks: lanes * lane_length,
+    };
+    for pass in 0..iterations {
+        for slice in 0..SYNC_POINTS {
+            for lane in 0..lanes {
+                fill_segment(&mut memory_blocks, &geometry, pass, slice, lane);
+            }
+        }
+    }
+
+    let mut last = memory_blocks[lane_length - 1];
+    for lane in 1..lanes {
+        let block = &memory_blocks[lane * lane_length + lane_length - 1];
+        for (word, other) in last.iter_mut().zip(block) {
+            *word ^= other;
+        }
+    }
+    let bytes: Vec<u8> = last.iter().flat_map(|word| word.to_le_bytes()).collect();
+    variable_hash(&bytes, tag_length)
+}
+
+struct Geometry {
+    lanes: usize,
+    lane_length: usize,
+    segment_length: usize,
+    iterations: usize,
+    total_blocks: usize,
+}
+
+fn fill_segment(memory: &mut [Block], geometry: &Geometry, pass: usize, slice: usize, lane: usize) {
+    let data_independent = pass == 0 && slice < SYNC_POINTS / 2;
+    let zero_block = [0u64; BLOCK_WORDS];
+    let mut input_block = [0u64; BLOCK_WORDS];
+    let mut address_block = [0u64; BLOCK_WORDS];
+    if data_independent {
+        input_block[0] = pass as u64;
+        input_block[1] = lane as u64;
+        input_block[2] = slice as u64;
+        input_block[3] = geometry.total_blocks as u64;
+        input_block[4] = geometry.iterations as u64;
+        input_block[5] = ARGON2ID as u64;
+    }
+
+    let starting_index = if pass == 0 && slice == 0 { 2 } else { 0 };
+    if data_independent && starting_index == 2 {
+        next_addresses(&mut address_block, &mut input_block, &zero_block);
+    }
+
+    let segment_start = lane * geometry.lane_length + slice * geometry.segment_length;
+    for index in starting_index..geometry.segment_length {
+        let current = segment_start + index;
+        let previous = if current.is_multiple_of(geometry.lane_length) {
+            current + geometry.lane_length - 1
+        } else {
+            current - 1
+        };
+        let pseudo_random = if data_independent {
+            if index % BLOCK_WORDS == 0 {
+                next_addresses(&mut address_block, &mut input_block, &zero_block);
+            }
+            address_block[index % BLOCK_WORDS]
+        } else {
+            memory[previous][0]
+        };
+
+        let reference_lane = if pass == 0 && slice == 0 {
+            lane
+        } else {
+            ((pseudo_random >> 32) as usize) % geometry.lanes
+        };
+        let reference_index = reference_index(
+            geometry,
+            pass,
+            slice,
+            index,
+            pseudo_random & 0xffff_ffff,
+            reference_lane == lane,
+        );
+        let reference = reference_lane * geometry.lane_length + reference_index;
+
+        let result = compress(&memory[previous], &memory[reference]);
+        if pass == 0 {
+            memory[current] = result;
+        } else {
+            for (word, new) in memory[current].iter_mut().zip(result) {
+                *word ^= new;
+            }
+        }
+    }
+}
+
+fn reference_index(
+    geometry: &Geometry,
+    pass: usize,
+    slice: usize,
+    index: usize,
+    pseudo_random: u64,
+    same_lane: bool,
+) -> usize {
+    let segment_length = geometry.segment_length;
+    let area_size = if pass == 0 {
+        if slice == 0 || same_lane {
+            slice * segment_length + index - 1
+        } else {
+            slice * segment_length - usize::from(index == 0)
+        }
+    } else if same_lane {
+        geometry.lane_length - segment_length + index - 1
+    } else {
+        geometry.lane_length - segment_length - usize::from(index == 0)
+    };
+    let relative = (pseudo_random * pseudo_random) >> 32;
+    let relative = area_size as u64 - 1 - ((area_size as u64 * relative) >> 32);
+    let start = if pass == 0 || slice == SYNC_POINTS - 1 {
+        0
+    } else {
+        (slice + 1) * segment_length
+    };
+    (start + relative as usize) % geometry.lane_length
+}
+
+fn next_addresses(address_block: &mut Block, input_block: &mut Block, zero_block: &Block) {
+    input_block[6] += 1;
+    let first = compress(zero_block, input_block);
+    *address_block = compress(zero_block, &first);
+}
+
+/// The compression function G.
+fn compress(x: &Block, y: &Block) -> Block {
+    let mut r = [0u64; BLOCK_WORDS];
+    for i in 0..BLOCK_WORDS {
+        r[i] = x[i] ^ y[i];
+    }
+    let mut q = r;
+    for row in 0..8 {
+        let mut indices = [0; 16];
+        for (i, index) in indices.iter_mut().enumerate() {
+            *index = row * 16 + i;
+        }
+        permute(&mut q, indices);
+    }
+    for column in 0..8 {
+        let mut indices = [0; 16];
+        for (i, index) in indices.iter_mut().enumerate() {
+            *index = 2 * column + (i / 2) * 16 + i % 2;
+        }
+        permute(&mut q, indices);
+    }
+    for i in 0..BLOCK_WORDS {
+        q[i] ^= r[i];
+    }
+    q
+}
+
+fn permute(block: &mut Block, indices: [usize; 16]) {
+    let mut v = indices.map(|index| block[index]);
+    mix(&mut v, 0, 4, 8, 12);
+    mix(&mut v, 1, 5, 9, 13);
+    mix(&mut v, 2, 6, 10, 14);
+    mix(&mut v, 3, 7, 11, 15);
+    mix(&mut v, 0, 5, 10, 15);
+    mix(&mut v, 1, 6, 11, 12);
+    mix(&mut v, 2, 7, 8, 13);
+    mix(&mut v, 3, 4, 9, 14);
+    for (index, value) in indices.into_iter().zip(v) {
+        block[index] = value;
+    }
+}
+
+fn blamka(x: u64, y: u64) -> u64 {
+    let product = (x & 0xffff_ffff) * (y & 0xffff_ffff);
+    x.wrapping_add(y).wrapping_add(product.wrapping_mul(2))
+}
+
+fn mix(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize) {
+    v[a] = blamka(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = blamka(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = blamka(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = blamka(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+/// H' from the specification: BLAKE2b extended to arbitrary output lengths.
+fn variable_hash(input: &[u8], output_length: usize) -> Vec<u8> {
+    let length_prefix = (output_length as u32).to_le_bytes();
+    if output_length <= MAX_OUTPUT_LENGTH {
+        let mut hasher = Blake2b::new(output_length);
+        hasher.update(&length_prefix);
+        hasher.update(input);
+        return hasher.finalize();
+    }
+    let mut output = Vec::with_capacity(output_length);
+    let mut hasher = Blake2b::new(MAX_OUTPUT_LENGTH);
+    hasher.update(&length_prefix);
+    hasher.update(input);
+    let mut v = hasher.finalize();
+    loop {
+        output.extend_from_slice(&v[..32]);
+        let remaining = output_length - output.len();
+        let mut hasher = Blake2b::new(remaining.min(MAX_OUTPUT_LENGTH));
+        hasher.update(&v);
+        v = hasher.finalize();
+        if remaining <= MAX_OUTPUT_LENGTH {
+            output.extend_from_slice(&v);
+            return output;
+        }
+    }
+}
+
+fn block_from_bytes(bytes: &[u8]) -> Block {
+    let mut block = [0u64; BLOCK_WORDS];
+    for (word, chunk) in block.iter_mut().zip(bytes.chunks_exact(8)) {
+        *word = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    block
+}
+
+/// Hash a password with a fresh random salt, returning a PHC string such as
+/// `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`.
+pub fn hash_password(password: &str) -> std::io::Result<String> {
+    let params = Params::default();
+    let salt = random_bytes(SALT_LENGTH)?;
+    let tag = argon2id(password.as_bytes(), &salt, &[], &[], params, TAG_LENGTH);
+    Ok(format!(
+        "$argon2id$v={VERSION}$m={},t={},p={}${}${}",
+        params.memory,
+        params.iterations,
+        params.lanes,
+        base64_encode(&salt),
+        base64_encode(&tag)
+    ))
+}
+
+/// Check a password against a PHC string produced by `hash_password`.
+/// Malformed hashes never verify.
+pub fn verify_password(password: &str, phc: &str) -> bool {
+    match parse_phc(phc) {
+        Some((params, salt, tag)) => {
+            let computed = argon2id(password.as_bytes(), &salt, &[], &[], params, tag.len());
+            constant_time_eq(&computed, &tag)
+        }
+        None => false,
+    }
+}
+
+/// Whether `phc` is a well-formed Argon2id PHC string.
+pub fn is_valid_hash(phc: &str) -> bool {
+    parse_phc(phc).is_some()
+}
+
+fn parse_phc(phc: &str) -> Option<(Params, Vec<u8>, Vec<u8>)> {
+    let mut fields = phc.strip_prefix('$')?.split('$');
+    if fields.next()? != "argon2id" || fields.next()? != format!("v={VERSION}") {
+        return None;
+    }
+    let mut params = Params {
+        memory: 0,
+        iterations: 0,
+        lanes: 0,
+    };
+    for param in fields.next()?.split(',') {
+        let (name, value) = param.split_once('=')?;
+        let value: u32 = value.parse().ok()?;
+        match name {
+            "m" => params.memory = value,
+            "t" => params.iterations = value,
+            "p" => params.lanes = value,
+            _ => return None,
+        }
+    }
+    if !(1..=MAX_LANES).contains(&params.lanes)
+        || !(8 * params.lanes..=MAX_MEMORY).contains(&params.memory)
+        || !(1..=MAX_ITERATIONS).contains(&params.iterations)
+    {
+        return None;
+    }
+    let salt = base64_decode(fields.next()?)?;
+    let tag = base64_decode(fields.next()?)?;
+    if fields.next().is_some() || tag.len() < 4 {
+        return None;
+    }
+    Some((params, salt, tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+
+    #[test]
+    fn hashes_like_rfc_9106() {
+        let params = Params {
+            memory: 32,
+            iterations: 3,
+            lanes: 4,
+        };
+        let tag = argon2id(&[1; 32], &[2; 16], &[3; 8], &[4; 12], params, 32);
+        assert_eq!(
+            crypto::to_hex(&tag),
+            "0d640df58d78766c08c037a34a8b53c9d01ef0452d75b65eb52520e96b01e659"
+        );
+    }
+
+    #[test]
+    fn costly_hashes_are_refused() {
+        let salt = base64_encode(&[2; 16]);
+        let tag = base64_encode(&[0; 32]);
+        let phc = |params: &str| format!("$argon2id$v=19${params}${salt}${tag}");
+        assert!(is_valid_hash(&phc("m=19456,t=2,p=1")));
+        assert!(is_valid_hash(&phc("m=1048576,t=16,p=16")));
+        assert!(!is_valid_hash(&phc("m=4294967295,t=2,p=1")));
+        assert!(!is_valid_hash(&phc("m=19456,t=4294967295,p=1")));
+        assert!(!is_valid_hash(&phc("m=19456,t=2,p=17")));
+        assert!(!is_valid_hash(&phc("m=7,t=2,p=1")));
+    }
+}
@@ -0,0 +1,150 @@
+//! BLAKE2b (RFC 7693), unkeyed, with variable output length.
+
+const IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+const SIGMA: [[usize; 16]; 12] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+];
+
+const BLOCK_LENGTH: usize = 128;
+pub const MAX_OUTPUT_LENGTH: usize = 64;
+
+#[derive(Clone)]
+pub struct Blake2b {
+    state: [u64; 8],
+    buffer: [u8; BLOCK_LENGTH],
+    buffered: usize,
+    counter: u128,
+    output_length: usize,
+}
+
+impl Blake2b {
+    /// `output_length` must be between 1 and 64 bytes.
+    pub fn new(output_length: usize) -> Blake2b {
+        assert!((1..=MAX_OUTPUT_LENGTH).contains(&output_length));
+        let mut state = IV;
+        state[0] ^= 0x01010000 ^ output_length as u64;
+        Blake2b {
+            state,
+            buffer: [0; BLOCK_LENGTH],
+            buffered: 0,
+            counter: 0,
+            output_length,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // The last block is compressed in `finalize` with the final flag
+            // set, so a full buffer is only flushed once more data arrives.
+            if self.buffered == BLOCK_LENGTH {
+                self.counter += BLOCK_LENGTH as u128;
+                let block = self.buffer;
+                self.compress(&block, false);
+                self.buffered = 0;
+            }
+            let take = (BLOCK_LENGTH - self.buffered).min(data.len());
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+        }
+    }
+
+    pub fn finalize(mut self) -> Vec<u8> {
+        self.counter += self.buffered as u128;
+        self.buffer[self.buffered..].fill(0);
+        let block = self.buffer;
+        self.compress(&block, true);
+        self.state
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .take(self.output_length)
+            .collect()
+    }
+
+    fn compress(&mut self, block: &[u8; BLOCK_LENGTH], last: bool) {
+        let mut message = [0u64; 16];
+        for (word, bytes) in message.iter_mut().zip(block.chunks_exact(8)) {
+            *word = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        let mut v = [0u64; 16];
+        v[..8].copy_from_slice(&self.state);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.counter as u64;
+        v[13] ^= (self.counter >> 64) as u64;
+        if last {
+            v[14] = !v[14];
+        }
+        for sigma in &SIGMA {
+            mix(&mut v, 0, 4, 8, 12, message[sigma[0]], message[sigma[1]]);
+            mix(&mut v, 1, 5, 9, 13, message[sigma[2]], message[sigma[3]]);
+            mix(&mut v, 2, 6, 10, 14, message[sigma[4]], message[sigma[5]]);
+            mix(&mut v, 3, 7, 11, 15, message[sigma[6]], message[sigma[7]]);
+            mix(&mut v, 0, 5, 10, 15, message[sigma[8]], message[sigma[9]]);
+            mix(&mut v, 1, 6, 11, 12, message[sigma[10]], message[sigma[11]]);
+            mix(&mut v, 2, 7, 8, 13, message[sigma[12]], message[sigma[13]]);
+            mix(&mut v, 3, 4, 9, 14, message[sigma[14]], message[sigma[15]]);
+        }
+        for i in 0..8 {
+            self.state[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+}
+
+#[allow(clippy::many_single_char_names)]
+fn mix(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+
+    fn blake2b(output_length: usize, data: &[u8]) -> String {
+        let mut hash = Blake2b::new(output_length);
+        hash.update(data);
+        crypto::to_hex(&hash.finalize())
+    }
+
+    #[test]
+    fn hashes_like_rfc_7693() {
+        assert_eq!(
+            blake2b(64, b"abc"),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+        // Several blocks, a short output.
+        assert_eq!(
+            blake2b(32, &[b'a'; 1000]),
+            "e00b0ddbf1e2cdaf5c898e1a5e8826ea3a2c339bcf2a478da2e5fca9ff126672"
+        );
+    }
+}
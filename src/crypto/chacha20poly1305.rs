@@ -0,0 +1,235 @@
+//! ChaCha20-Poly1305 authenticated encryption (RFC 8439).
+
+use super::constant_time_eq;
+
+pub const KEY_LENGTH: usize = 32;
+pub const NONCE_LENGTH: usize = 12;
+pub const TAG_LENGTH: usize = 16;
+
+pub type Key = [u8; KEY_LENGTH];
+pub type Nonce = [u8; NONCE_LENGTH];
+
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+/// 26-bit limbs of the Poly1305 accumulator.
+const LIMB: u32 = 0x3ff_ffff;
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+fn chacha20_block(key: &Key, counter: u32, nonce: &Nonce) -> [u8; 64] {
+    let mut initial = [0u32; 16];
+    initial[..4].copy_from_slice(&CONSTANTS);
+    for (word, bytes) in initial[4..12].iter_mut().zip(key.chunks(4)) {
+        *word = le32(bytes);
+    }
+    initial[12] = counter;
+    for (word, bytes) in initial[13..].iter_mut().zip(nonce.chunks(4)) {
+        *word = le32(bytes);
+    }
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    let mut block = [0; 64];
+    for (index, (word, initial)) in state.iter().zip(initial).enumerate() {
+        block[index * 4..index * 4 + 4].copy_from_slice(&word.wrapping_add(initial).to_le_bytes());
+    }
+    block
+}
+
+/// XOR `data` with the key stream from block `counter` on.
+fn chacha20(key: &Key, counter: u32, nonce: &Nonce, data: &mut [u8]) {
+    for (index, chunk) in data.chunks_mut(64).enumerate() {
+        let block = chacha20_block(key, counter.wrapping_add(index as u32), nonce);
+        for (byte, key_byte) in chunk.iter_mut().zip(block) {
+            *byte ^= key_byte;
+        }
+    }
+}
+
+/// Poly1305 of `message` under the one-time `key`.
+fn poly1305(key: &[u8; 32], message: &[u8]) -> [u8; TAG_LENGTH] {
+    let r = [
+        le32(&key[0..]) & 0x3ff_ffff,
+        (le32(&key[3..]) >> 2) & 0x3ff_ff03,
+        (le32(&key[6..]) >> 4) & 0x3ff_c0ff,
+        (le32(&key[9..]) >> 6) & 0x3f0_3fff,
+        (le32(&key[12..]) >> 8) & 0x00f_ffff,
+    ];
+    let s = [r[1] * 5, r[2] * 5, r[3] * 5, r[4] * 5];
+    let mut h = [0u32; 5];
+    for chunk in message.chunks(16) {
+        let mut block = [0u8; 17];
+        block[..chunk.len()].copy_from_slice(chunk);
+        block[chunk.len()] = 1;
+        h[0] += le32(&block[0..]) & LIMB;
+        h[1] += (le32(&block[3..]) >> 2) & LIMB;
+        h[2] += (le32(&block[6..]) >> 4) & LIMB;
+        h[3] += (le32(&block[9..]) >> 6) & LIMB;
+        h[4] += (le32(&block[12..]) >> 8) | (u32::from(block[16]) << 24);
+
+        let m = |a: u32, b: u32| u64::from(a) * u64::from(b);
+        let d = [
+            m(h[0], r[0]) + m(h[1], s[3]) + m(h[2], s[2]) + m(h[3], s[1]) + m(h[4], s[0]),
+            m(h[0], r[1]) + m(h[1], r[0]) + m(h[2], s[3]) + m(h[3], s[2]) + m(h[4], s[1]),
+            m(h[0], r[2]) + m(h[1], r[1]) + m(h[2], r[0]) + m(h[3], s[3]) + m(h[4], s[2]),
+            m(h[0], r[3]) + m(h[1], r[2]) + m(h[2], r[1]) + m(h[3], r[0]) + m(h[4], s[3]),
+            m(h[0], r[4]) + m(h[1], r[3]) + m(h[2], r[2]) + m(h[3], r[1]) + m(h[4], r[0]),
+        ];
+        let mut carry = 0;
+        for (limb, product) in h.iter_mut().zip(d) {
+            let product = product + carry;
+            *limb = product as u32 & LIMB;
+            carry = product >> 26;
+        }
+        h[0] += carry as u32 * 5;
+        h[1] += h[0] >> 26;
+        h[0] &= LIMB;
+    }
+
+    // Fully carry, then subtract p = 2^130 - 5 if h is not below it.
+    let mut carry = 0;
+    for limb in h.iter_mut().skip(1) {
+        *limb += carry;
+        carry = *limb >> 26;
+        *limb &= LIMB;
+    }
+    h[0] += carry * 5;
+    h[1] += h[0] >> 26;
+    h[0] &= LIMB;
+    let mut g = [0u32; 5];
+    let mut carry = 5;
+    for (g, h) in g.iter_mut().zip(h) {
+        *g = h + carry;
+        carry = *g >> 26;
+        *g &= LIMB;
+    }
+    g[4] = g[4].wrapping_add(carry << 26).wrapping_sub(1 << 26);
+    // All ones if h + 5 - 2^130 did not go below zero.
+    let select = (g[4] >> 31).wrapping_sub(1);
+    for (h, g) in h.iter_mut().zip(g) {
+        *h = (*h & !select) | (g & select);
+    }
+
+    let words = [
+        h[0] | (h[1] << 26),
+        (h[1] >> 6) | (h[2] << 20),
+        (h[2] >> 12) | (h[3] << 14),
+        (h[3] >> 18) | (h[4] << 8),
+    ];
+    let mut tag = [0; TAG_LENGTH];
+    let mut carry = 0u64;
+    for (index, word) in words.iter().enumerate() {
+        let sum = u64::from(*word) + u64::from(le32(&key[16 + index * 4..])) + carry;
+        tag[index * 4..index * 4 + 4].copy_from_slice(&(sum as u32).to_le_bytes());
+        carry = sum >> 32;
+    }
+    tag
+}
+
+fn tag(key: &Key, nonce: &Nonce, aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LENGTH] {
+    let block = chacha20_block(key, 0, nonce);
+    let pad = |length: usize| vec![0; (16 - length % 16) % 16];
+    let mut message = Vec::with_capacity(aad.len() + ciphertext.len() + 48);
+    message.extend_from_slice(aad);
+    message.extend(pad(aad.len()));
+    message.extend_from_slice(ciphertext);
+    message.extend(pad(ciphertext.len()));
+    message.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+    message.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly1305(block[..32].try_into().unwrap(), &message)
+}
+
+/// Encrypt `plaintext`, authenticating it and `aad`. Returns the ciphertext
+/// followed by the tag. Never use a nonce twice with a key.
+pub fn seal(key: &Key, nonce: &Nonce, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut sealed = plaintext.to_vec();
+    chacha20(key, 1, nonce, &mut sealed);
+    let tag = tag(key, nonce, aad, &sealed);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+/// Decrypt what `seal` returned, `None` if it or `aad` was tampered with or
+/// the key is not the one it was sealed with.
+pub fn open(key: &Key, nonce: &Nonce, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    let split = sealed.len().checked_sub(TAG_LENGTH)?;
+    let (ciphertext, received) = sealed.split_at(split);
+    if !constant_time_eq(&tag(key, nonce, aad, ciphertext), received) {
+        return None;
+    }
+    let mut plaintext = ciphertext.to_vec();
+    chacha20(key, 1, nonce, &mut plaintext);
+    Some(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+
+    #[test]
+    fn seals_like_rfc_8439() {
+        let key: Key =
+            crypto::from_hex("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let nonce: Nonce = crypto::from_hex("070000004041424344454647")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let aad = crypto::from_hex("50515253c0c1c2c3c4c5c6c7").unwrap();
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+                          only one tip for the future, sunscreen would be it.";
+        let sealed = seal(&key, &nonce, &aad, plaintext);
+        assert_eq!(
+            crypto::to_hex(&sealed),
+            "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+             3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+             92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+             3ff4def08e4b7a9de576d26586cec64b6116\
+             1ae10b594f09e26a7e902ecbd0600691"
+        );
+        assert_eq!(open(&key, &nonce, &aad, &sealed).unwrap(), plaintext);
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(open(&key, &nonce, &aad, &tampered).is_none());
+        assert!(open(&key, &nonce, b"", &sealed).is_none());
+    }
+
+    #[test]
+    fn authenticates_like_rfc_8439() {
+        let key =
+            crypto::from_hex("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b")
+                .unwrap();
+        assert_eq!(
+            crypto::to_hex(&poly1305(
+                key[..].try_into().unwrap(),
+                b"Cryptographic Forum Research Group"
+            )),
+            "a8061dc1305136c6c22b8baf0c0127a9"
+        );
+    }
+}
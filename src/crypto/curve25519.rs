@@ -0,0 +1,148 @@
+//! Arithmetic in GF(2^255 - 19), the field of Curve25519, shared by
+//! Ed25519 and X25519. Elements are five 51-bit limbs.
+
+const MASK: u64 = (1 << 51) - 1;
+
+/// An element of GF(2^255 - 19).
+#[derive(Clone, Copy)]
+pub(super) struct Fe(pub(super) [u64; 5]);
+
+pub(super) const ZERO: Fe = Fe([0, 0, 0, 0, 0]);
+pub(super) const ONE: Fe = Fe([1, 0, 0, 0, 0]);
+
+impl Fe {
+    /// Bring every limb below 2^51, up to a small excess in the second.
+    pub(super) fn carry(mut limbs: [u64; 5]) -> Fe {
+        for i in 0..4 {
+            limbs[i + 1] += limbs[i] >> 51;
+            limbs[i] &= MASK;
+        }
+        limbs[0] += 19 * (limbs[4] >> 51);
+        limbs[4] &= MASK;
+        limbs[1] += limbs[0] >> 51;
+        limbs[0] &= MASK;
+        Fe(limbs)
+    }
+
+    /// The element encoded in `bytes`, little-endian, ignoring the top bit.
+    pub(super) fn from_bytes(bytes: &[u8; 32]) -> Fe {
+        let load =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        Fe([
+            load(0) & MASK,
+            (load(6) >> 3) & MASK,
+            (load(12) >> 6) & MASK,
+            (load(19) >> 1) & MASK,
+            (load(24) >> 12) & MASK,
+        ])
+    }
+
+    pub(super) fn add(self, other: Fe) -> Fe {
+        let mut limbs = self.0;
+        for (limb, other) in limbs.iter_mut().zip(other.0) {
+            *limb += other;
+        }
+        Fe::carry(limbs)
+    }
+
+    /// Adds 2p first so that no limb goes below zero.
+    pub(super) fn sub(self, other: Fe) -> Fe {
+        let two_p = [
+            0xfffffffffffda,
+            0xffffffffffffe,
+            0xffffffffffffe,
+            0xffffffffffffe,
+            0xffffffffffffe,
+        ];
+        let mut limbs = self.0;
+        for ((limb, other), two_p) in limbs.iter_mut().zip(other.0).zip(two_p) {
+            *limb = *limb + two_p - other;
+        }
+        Fe::carry(limbs)
+    }
+
+    pub(super) fn mul(self, other: Fe) -> Fe {
+        let a = self.0.map(u128::from);
+        let b = other.0.map(u128::from);
+        let b19 = b.map(|limb| limb * 19);
+        let mut r = [
+            a[0] * b[0] + a[1] * b19[4] + a[2] * b19[3] + a[3] * b19[2] + a[4] * b19[1],
+            a[0] * b[1] + a[1] * b[0] + a[2] * b19[4] + a[3] * b19[3] + a[4] * b19[2],
+            a[0] * b[2] + a[1] * b[1] + a[2] * b[0] + a[3] * b19[4] + a[4] * b19[3],
+            a[0] * b[3] + a[1] * b[2] + a[2] * b[1] + a[3] * b[0] + a[4] * b19[4],
+            a[0] * b[4] + a[1] * b[3] + a[2] * b[2] + a[3] * b[1] + a[4] * b[0],
+        ];
+        for i in 0..4 {
+            r[i + 1] += r[i] >> 51;
+            r[i] &= u128::from(MASK);
+        }
+        r[0] += 19 * (r[4] >> 51);
+        r[4] &= u128::from(MASK);
+        r[1] += r[0] >> 51;
+        r[0] &= u128::from(MASK);
+        Fe(r.map(|limb| limb as u64))
+    }
+
+    pub(super) fn square(self) -> Fe {
+        self.mul(self)
+    }
+
+    /// self^(p - 2), the inverse of a non-zero element.
+    pub(super) fn invert(self) -> Fe {
+        // p - 2 = 2^255 - 21: bits 254 down to 5 set, then 0b01011.
+        let mut result = ONE;
+        for bit in (0..255).rev() {
+            result = result.square();
+            if bit >= 5 || (0b01011 >> bit) & 1 == 1 {
+                result = result.mul(self);
+            }
+        }
+        result
+    }
+
+    /// Swap `a` and `b` when `swap` is 1, without branching on it.
+    pub(super) fn swap(a: &mut Fe, b: &mut Fe, swap: u64) {
+        let mask = swap.wrapping_neg();
+        for (a, b) in a.0.iter_mut().zip(b.0.iter_mut()) {
+            let difference = (*a ^ *b) & mask;
+            *a ^= difference;
+            *b ^= difference;
+        }
+    }
+
+    /// Little-endian encoding of the fully reduced element.
+    pub(super) fn to_bytes(self) -> [u8; 32] {
+        let mut limbs = Fe::carry(Fe::carry(self.0).0).0;
+        // Add 19 and see whether that carries past 2^255, i.e. whether the
+        // value is at least p; if so, take p off by keeping the sum.
+        let mut carry = (limbs[0] + 19) >> 51;
+        for limb in &limbs[1..] {
+            carry = (limb + carry) >> 51;
+        }
+        limbs[0] += 19 * carry;
+        for i in 0..4 {
+            limbs[i + 1] += limbs[i] >> 51;
+            limbs[i] &= MASK;
+        }
+        limbs[4] &= MASK;
+
+        let mut bytes = [0u8; 32];
+        let mut buffer: u128 = 0;
+        let mut bits = 0;
+        let mut position = 0;
+        for limb in limbs {
+            buffer |= u128::from(limb) << bits;
+            bits += 51;
+            while bits >= 8 && position < 32 {
+                bytes[position] = buffer as u8;
+                buffer >>= 8;
+                bits -= 8;
+                position += 1;
+            }
+        }
+        if position < 32 {
+            bytes[position] = buffer as u8;
+        }
+        bytes
+    }
+}
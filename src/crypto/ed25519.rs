@@ -0,0 +1,271 @@
+//! Ed25519 signatures (RFC 8032), signing only. Signatures are checked by
+//! whoever receives them, with any Ed25519 implementation.
+//!
+//! Points use extended coordinates, over the field in `curve25519`, and
+//! the secret scalar is multiplied in with a ladder that does the same
+//! work for every bit.
+
+use std::fmt::{self, Debug};
+
+use super::curve25519::{Fe, ONE, ZERO};
+use super::sha512::Sha512;
+
+pub const SEED_LENGTH: usize = 32;
+pub const PUBLIC_KEY_LENGTH: usize = 32;
+pub const SIGNATURE_LENGTH: usize = 64;
+
+/// 2 * d, with d = -121665 / 121666 from the curve equation.
+const D2: Fe = Fe([
+    0x69b9426b2f159,
+    0x35050762add7a,
+    0x3cf44c0038052,
+    0x6738cc7407977,
+    0x2406d9dc56dff,
+]);
+const BASE_X: Fe = Fe([
+    0x62d608f25d51a,
+    0x412a4b4f6592a,
+    0x75b7171a4b31d,
+    0x1ff60527118fe,
+    0x216936d3cd6e5,
+]);
+const BASE_Y: Fe = Fe([
+    0x6666666666658,
+    0x4cccccccccccc,
+    0x1999999999999,
+    0x3333333333333,
+    0x6666666666666,
+]);
+const BASE_T: Fe = Fe([
+    0x68ab3a5b7dda3,
+    0x00eea2a5eadbb,
+    0x2af8df483c27e,
+    0x332b375274732,
+    0x67875f0fd78b7,
+]);
+
+/// The group order, 2^252 + 27742317777372353535851937790883648493, in
+/// little-endian 64-bit words.
+const ORDER: [u64; 4] = [
+    0x5812631a5cf5d3ed,
+    0x14def9dea2f79cd6,
+    0x0000000000000000,
+    0x1000000000000000,
+];
+
+/// A curve point in extended coordinates: x = X/Z, y = Y/Z, x * y = T/Z.
+#[derive(Clone, Copy)]
+struct Point {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+    t: Fe,
+}
+
+const IDENTITY: Point = Point {
+    x: ZERO,
+    y: ONE,
+    z: ONE,
+    t: ZERO,
+};
+
+const BASE: Point = Point {
+    x: BASE_X,
+    y: BASE_Y,
+    z: ONE,
+    t: BASE_T,
+};
+
+impl Point {
+    /// Addition as in RFC 8032, section 5.1.4; also doubles.
+    fn add(self, other: Point) -> Point {
+        let a = self.y.sub(self.x).mul(other.y.sub(other.x));
+        let b = self.y.add(self.x).mul(other.y.add(other.x));
+        let c = self.t.mul(D2).mul(other.t);
+        let d = self.z.add(self.z).mul(other.z);
+        let e = b.sub(a);
+        let f = d.sub(c);
+        let g = d.add(c);
+        let h = b.add(a);
+        Point {
+            x: e.mul(f),
+            y: g.mul(h),
+            z: f.mul(g),
+            t: e.mul(h),
+        }
+    }
+
+    fn swap(a: &mut Point, b: &mut Point, swap: u64) {
+        Fe::swap(&mut a.x, &mut b.x, swap);
+        Fe::swap(&mut a.y, &mut b.y, swap);
+        Fe::swap(&mut a.z, &mut b.z, swap);
+        Fe::swap(&mut a.t, &mut b.t, swap);
+    }
+
+    /// `scalar` (little-endian) times the base point.
+    fn base_times(scalar: &[u8; 32]) -> Point {
+        let mut low = IDENTITY;
+        let mut high = BASE;
+        for bit in (0..256).rev() {
+            let set = u64::from((scalar[bit / 8] >> (bit % 8)) & 1);
+            Point::swap(&mut low, &mut high, set);
+            high = low.add(high);
+            low = low.add(low);
+            Point::swap(&mut low, &mut high, set);
+        }
+        low
+    }
+
+    fn to_bytes(self) -> [u8; 32] {
+        let z_inverse = self.z.invert();
+        let mut bytes = self.y.mul(z_inverse).to_bytes();
+        bytes[31] |= (self.x.mul(z_inverse).to_bytes()[0] & 1) << 7;
+        bytes
+    }
+}
+
+/// `value` reduced modulo the group order, as 32 little-endian bytes.
+fn reduce(value: &[u64; 8]) -> [u8; 32] {
+    let mut remainder = [0u64; 4];
+    for bit in (0..512).rev() {
+        // remainder = 2 * remainder + bit, which stays below 2^254.
+        for i in (1..4).rev() {
+            remainder[i] = (remainder[i] << 1) | (remainder[i - 1] >> 63);
+        }
+        remainder[0] = (remainder[0] << 1) | ((value[bit / 64] >> (bit % 64)) & 1);
+        // Subtract the order unless that would go below zero.
+        let mut difference = [0u64; 4];
+        let mut borrow = 0;
+        for i in 0..4 {
+            let (partial, borrowed) = remainder[i].overflowing_sub(ORDER[i]);
+            let (partial, borrowed_again) = partial.overflowing_sub(borrow);
+            difference[i] = partial;
+            borrow = u64::from(borrowed | borrowed_again);
+        }
+        let keep = borrow.wrapping_neg();
+        for i in 0..4 {
+            remainder[i] = (remainder[i] & keep) | (difference[i] & !keep);
+        }
+    }
+    let mut bytes = [0u8; 32];
+    for (chunk, word) in bytes.chunks_exact_mut(8).zip(remainder) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    bytes
+}
+
+fn words(bytes: &[u8]) -> [u64; 8] {
+    let mut words = [0u64; 8];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks(8)) {
+        let mut buffer = [0u8; 8];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        *word = u64::from_le_bytes(buffer);
+    }
+    words
+}
+
+fn hash_to_scalar(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    reduce(&words(&hasher.finalize()))
+}
+
+/// (a + b * c) modulo the group order, all little-endian.
+fn mul_add(a: &[u8; 32], b: &[u8; 32], c: &[u8; 32]) -> [u8; 32] {
+    let (b, c) = (words(b), words(c));
+    let mut product = [0u128; 9];
+    for i in 0..4 {
+        let mut carry = 0u128;
+        for j in 0..4 {
+            let sum = product[i + j] + u128::from(b[i]) * u128::from(c[j]) + carry;
+            product[i + j] = sum & u128::from(u64::MAX);
+            carry = sum >> 64;
+        }
+        product[i + 4] += carry;
+    }
+    let mut carry = 0u128;
+    for (limb, a) in product.iter_mut().zip(words(a)) {
+        let sum = *limb + u128::from(a) + carry;
+        *limb = sum & u128::from(u64::MAX);
+        carry = sum >> 64;
+    }
+    let mut value = [0u64; 8];
+    for (word, limb) in value.iter_mut().zip(product) {
+        *word = limb as u64;
+    }
+    reduce(&value)
+}
+
+#[derive(Clone)]
+pub struct SigningKey {
+    /// Clamped secret scalar.
+    scalar: [u8; 32],
+    /// Second half of the hashed seed, for deriving nonces.
+    prefix: [u8; 32],
+    public_key: [u8; PUBLIC_KEY_LENGTH],
+}
+
+impl SigningKey {
+    pub fn from_seed(seed: &[u8; SEED_LENGTH]) -> SigningKey {
+        let mut hasher = Sha512::new();
+        hasher.update(seed);
+        let hash = hasher.finalize();
+        let mut scalar: [u8; 32] = hash[..32].try_into().unwrap();
+        scalar[0] &= 248;
+        scalar[31] &= 127;
+        scalar[31] |= 64;
+        SigningKey {
+            scalar,
+            prefix: hash[32..].try_into().unwrap(),
+            public_key: Point::base_times(&scalar).to_bytes(),
+        }
+    }
+
+    pub fn public_key(&self) -> &[u8; PUBLIC_KEY_LENGTH] {
+        &self.public_key
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_LENGTH] {
+        let nonce = hash_to_scalar(&[&self.prefix, message]);
+        let commitment = Point::base_times(&nonce).to_bytes();
+        let challenge = hash_to_scalar(&[&commitment, &self.public_key, message]);
+        let response = mul_add(&nonce, &challenge, &self.scalar);
+        let mut signature = [0u8; SIGNATURE_LENGTH];
+        signature[..32].copy_from_slice(&commitment);
+        signature[32..].copy_from_slice(&response);
+        signature
+    }
+}
+
+impl Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("public_key", &super::to_hex(&self.public_key))
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+
+    #[test]
+    fn signs_like_rfc_8032() {
+        let seed =
+            crypto::from_hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+                .unwrap();
+        let key = SigningKey::from_seed(&seed.try_into().unwrap());
+        assert_eq!(
+            crypto::to_hex(key.public_key()),
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        assert_eq!(
+            crypto::to_hex(&key.sign(b"")),
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065\
+             224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        );
+    }
+}
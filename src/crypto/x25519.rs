@@ -0,0 +1,86 @@
+//! X25519 Diffie-Hellman (RFC 7748).
+
+use super::curve25519::{Fe, ONE, ZERO};
+
+pub const KEY_LENGTH: usize = 32;
+
+pub type SecretKey = [u8; KEY_LENGTH];
+pub type PublicKey = [u8; KEY_LENGTH];
+
+/// The u-coordinate of the base point.
+const BASE: PublicKey = {
+    let mut base = [0; KEY_LENGTH];
+    base[0] = 9;
+    base
+};
+
+/// (486662 - 2) / 4, from the curve equation.
+const A24: Fe = Fe([121665, 0, 0, 0, 0]);
+
+/// The public key of `secret`.
+pub fn public_key(secret: &SecretKey) -> PublicKey {
+    x25519(secret, &BASE)
+}
+
+/// The secret shared by the holders of `secret` and of `public`'s secret.
+pub fn x25519(secret: &SecretKey, public: &PublicKey) -> [u8; KEY_LENGTH] {
+    let mut scalar = *secret;
+    scalar[0] &= 248;
+    scalar[31] &= 127;
+    scalar[31] |= 64;
+
+    let x1 = Fe::from_bytes(public);
+    let (mut x2, mut z2, mut x3, mut z3) = (ONE, ZERO, x1, ONE);
+    let mut swap = 0;
+    for bit in (0..255).rev() {
+        let bit = u64::from((scalar[bit / 8] >> (bit % 8)) & 1);
+        swap ^= bit;
+        Fe::swap(&mut x2, &mut x3, swap);
+        Fe::swap(&mut z2, &mut z3, swap);
+        swap = bit;
+
+        let a = x2.add(z2);
+        let aa = a.square();
+        let b = x2.sub(z2);
+        let bb = b.square();
+        let e = aa.sub(bb);
+        let c = x3.add(z3);
+        let d = x3.sub(z3);
+        let da = d.mul(a);
+        let cb = c.mul(b);
+        x3 = da.add(cb).square();
+        z3 = x1.mul(da.sub(cb).square());
+        x2 = aa.mul(bb);
+        z2 = e.mul(aa.add(A24.mul(e)));
+    }
+    Fe::swap(&mut x2, &mut x3, swap);
+    Fe::swap(&mut z2, &mut z3, swap);
+    x2.mul(z2.invert()).to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+
+    fn key(hex: &str) -> [u8; KEY_LENGTH] {
+        crypto::from_hex(hex).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn agrees_like_rfc_7748() {
+        let alice = key("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = key("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        assert_eq!(
+            crypto::to_hex(&public_key(&alice)),
+            "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+        );
+        assert_eq!(
+            crypto::to_hex(&public_key(&bob)),
+            "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"
+        );
+        let shared = "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742";
+        assert_eq!(crypto::to_hex(&x25519(&alice, &public_key(&bob))), shared);
+        assert_eq!(crypto::to_hex(&x25519(&bob, &public_key(&alice))), shared);
+    }
+}
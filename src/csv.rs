@@ -0,0 +1,100 @@
+//! Reading and writing CSV as described in RFC 4180.
+
+use std::borrow::Cow;
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("Quote opened on line {line} is not closed")]
+pub struct UnclosedQuoteError {
+    pub line: usize,
+}
+
+/// A record and the line it starts on, counting from 1.
+pub type Record = (usize, Vec<String>);
+
+/// `value` as a CSV field, quoted if it contains a separator, quote or line
+/// break.
+pub fn field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// The records of `text`. Blank lines are skipped, fields are not trimmed.
+pub fn parse(text: &str) -> Result<Vec<Record>, UnclosedQuoteError> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                line += 1;
+                field.push(c);
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                if fields.len() > 1 || !fields[0].is_empty() {
+                    records.push((start, std::mem::take(&mut fields)));
+                }
+                fields.clear();
+                line += 1;
+                start = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(UnclosedQuoteError { line: start });
+    }
+    if !fields.is_empty() || !field.is_empty() {
+        fields.push(field);
+        records.push((start, fields));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_quoted_when_needed() {
+        assert_eq!(field("rent"), "rent");
+        assert_eq!(field("rent, March"), "\"rent, March\"");
+        assert_eq!(field("the \"flat\""), "\"the \"\"flat\"\"\"");
+    }
+
+    #[test]
+    fn quoted_fields_may_hold_anything() {
+        let text = "name,memo\r\n\na,\"rent, \"\"flat\"\"\nMarch\"\nb,";
+        let records = parse(text).unwrap();
+        assert_eq!(
+            records[0],
+            (1, vec!["name".to_string(), "memo".to_string()])
+        );
+        assert_eq!(
+            records[1],
+            (
+                3,
+                vec!["a".to_string(), "rent, \"flat\"\nMarch".to_string()]
+            )
+        );
+        assert_eq!(records[2], (5, vec!["b".to_string(), String::new()]));
+        assert_eq!(parse("a,\"b\n"), Err(UnclosedQuoteError { line: 1 }));
+    }
+}
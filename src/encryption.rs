@@ -0,0 +1,180 @@
+//! Encryption of the data directory, so that balances and transfers are not
+//! readable by whoever can read its files. It is on when a key is set, 32
+//! bytes hex-encoded, in `encryption_key` of the configuration or in the
+//! `BANK_ENCRYPTION_KEY` environment variable. The `bank` commands that
+//! read a data directory take the key from the environment.
+//!
+//! Each line of the event and audit logs, and the snapshot as a whole, is
+//! sealed with ChaCha20-Poly1305 under a random nonce and written as the
+//! base64 of the nonce, the ciphertext and the tag. Sealed and plain JSON
+//! are told apart on reading, so a key can be set for an existing data
+//! directory: what is written from then on is sealed, the next snapshot
+//! included, while the lines of the audit log written before stay as they
+//! are. The audit hashes cover the events, not how they are written.
+//!
+//! Whoever loses the key loses the bank.
+
+use std::borrow::Cow;
+use std::env;
+use std::fmt::{self, Debug};
+use std::io;
+use std::sync::OnceLock;
+
+use thiserror::Error;
+
+use crate::crypto::chacha20poly1305::{self, Key, Nonce, NONCE_LENGTH};
+use crate::crypto::{self, base64_decode, base64_encode};
+
+pub const KEY_VARIABLE: &str = "BANK_ENCRYPTION_KEY";
+
+#[derive(Error, Debug)]
+pub enum EncryptionError {
+    #[error("Invalid encryption key, expected 32 bytes hex-encoded")]
+    InvalidKey,
+    #[error("The data is encrypted, set {KEY_VARIABLE} to read it")]
+    MissingKey,
+    #[error(
+        "Unable to decrypt, the key is not the one it was encrypted with or the data was modified"
+    )]
+    Corrupt,
+}
+
+/// Encrypts with the key it holds.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Cipher {
+    key: Key,
+}
+
+impl Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cipher").finish_non_exhaustive()
+    }
+}
+
+impl Cipher {
+    pub fn from_hex(key: &str) -> Result<Cipher, EncryptionError> {
+        let key = crypto::from_hex(key.trim())
+            .and_then(|key| Key::try_from(key).ok())
+            .ok_or(EncryptionError::InvalidKey)?;
+        Ok(Cipher { key })
+    }
+
+    /// `plaintext` sealed under a new nonce, base64-encoded.
+    pub fn seal(&self, plaintext: &[u8]) -> io::Result<String> {
+        let nonce: Nonce = crypto::random_bytes(NONCE_LENGTH)?
+            .try_into()
+            .expect("nonce length");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(chacha20poly1305::seal(&self.key, &nonce, b"", plaintext));
+        Ok(base64_encode(&sealed))
+    }
+
+    pub fn open(&self, sealed: &str) -> Result<Vec<u8>, EncryptionError> {
+        let sealed = base64_decode(sealed.trim()).ok_or(EncryptionError::Corrupt)?;
+        if sealed.len() < NONCE_LENGTH {
+            return Err(EncryptionError::Corrupt);
+        }
+        let (nonce, sealed) = sealed.split_at(NONCE_LENGTH);
+        chacha20poly1305::open(&self.key, nonce.try_into().unwrap(), b"", sealed)
+            .ok_or(EncryptionError::Corrupt)
+    }
+
+    /// `json` as written to the data directory.
+    fn encode(&self, json: Vec<u8>) -> io::Result<Vec<u8>> {
+        self.seal(&json).map(String::into_bytes)
+    }
+}
+
+static CIPHER: OnceLock<Option<Cipher>> = OnceLock::new();
+
+/// Encrypt the data directory with `cipher` from now on, or with the key in
+/// the environment if none is given. Only the first call counts.
+pub fn configure_encryption(cipher: Option<Cipher>) -> Result<(), EncryptionError> {
+    let cipher = match cipher {
+        Some(cipher) => Some(cipher),
+        None => env::var(KEY_VARIABLE)
+            .ok()
+            .map(|key| Cipher::from_hex(&key))
+            .transpose()?,
+    };
+    let _ = CIPHER.set(cipher);
+    Ok(())
+}
+
+fn cipher() -> Option<&'static Cipher> {
+    CIPHER.get().and_then(Option::as_ref)
+}
+
+/// `json` as written to the data directory: sealed if there is a key.
+pub fn encode(json: Vec<u8>) -> io::Result<Vec<u8>> {
+    match cipher() {
+        Some(cipher) => cipher.encode(json),
+        None => Ok(json),
+    }
+}
+
+/// JSON as read from the data directory, opened if it was sealed.
+pub fn decode(text: &str) -> Result<Cow<'_, str>, EncryptionError> {
+    decode_with(cipher(), text)
+}
+
+fn decode_with<'a>(
+    cipher: Option<&Cipher>,
+    text: &'a str,
+) -> Result<Cow<'a, str>, EncryptionError> {
+    // Whatever is written in the clear is a JSON object.
+    if text.trim_start().starts_with('{') {
+        return Ok(Cow::Borrowed(text));
+    }
+    let opened = cipher.ok_or(EncryptionError::MissingKey)?.open(text)?;
+    String::from_utf8(opened)
+        .map(Cow::Owned)
+        .map_err(|_| EncryptionError::Corrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_lines_open_only_with_their_key() {
+        let key = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+        let cipher = Cipher::from_hex(key).unwrap();
+        let json = br#"{"sequence":1}"#.to_vec();
+        let sealed = String::from_utf8(cipher.encode(json.clone()).unwrap()).unwrap();
+        assert!(!sealed.contains("sequence"));
+        assert_ne!(
+            sealed,
+            String::from_utf8(cipher.encode(json).unwrap()).unwrap()
+        );
+        assert_eq!(
+            decode_with(Some(&cipher), &sealed).unwrap(),
+            r#"{"sequence":1}"#
+        );
+        // Plain JSON reads as it is, with or without a key.
+        assert_eq!(
+            decode_with(Some(&cipher), r#"{"a":1}"#).unwrap(),
+            r#"{"a":1}"#
+        );
+        assert!(matches!(
+            decode_with(None, &sealed),
+            Err(EncryptionError::MissingKey)
+        ));
+
+        let other = Cipher::from_hex(&key.replace("00", "ff")).unwrap();
+        assert!(matches!(
+            decode_with(Some(&other), &sealed),
+            Err(EncryptionError::Corrupt)
+        ));
+        let mut tampered = sealed.into_bytes();
+        tampered[20] = if tampered[20] == b'A' { b'B' } else { b'A' };
+        assert!(matches!(
+            decode_with(Some(&cipher), std::str::from_utf8(&tampered).unwrap()),
+            Err(EncryptionError::Corrupt)
+        ));
+        assert!(matches!(
+            Cipher::from_hex("00"),
+            Err(EncryptionError::InvalidKey)
+        ));
+    }
+}
@@ -0,0 +1,144 @@
+//! Escrow: money taken from the payer and kept in the escrow system account
+//! until an arbiter releases it to the beneficiary or refunds it to the
+//! payer. An escrow nobody decides on by its deadline is settled as its
+//! `on_timeout` says, a refund by default.
+//!
+//! Sent as the JSON payload of the `w` instruction, e.g.
+//! `{"op": "open", "from": "patko", "to": "siska", "amount": "20",
+//! "arbiter": "notary"}`, then `{"op": "release", "escrow_id": 1}`. The
+//! arbiter is an identity; only it and admins may release or refund. Without
+//! an arbiter, whoever may transfer from the payer's account decides.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ledger::TransactionId;
+use crate::money::Currency;
+use crate::{AccountId, AccountRef, Amount};
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct EscrowId(u64);
+
+impl Display for EscrowId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "escrow{}", self.0)
+    }
+}
+
+/// Where the money of an escrow goes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EscrowOutcome {
+    /// To the beneficiary.
+    Release,
+    /// Back to the payer.
+    #[default]
+    Refund,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum EscrowCommand {
+    Open {
+        from: AccountRef,
+        to: AccountRef,
+        amount: Amount,
+        /// Must match both accounts when given.
+        #[serde(default)]
+        currency: Option<Currency>,
+        /// Identity that decides on the escrow.
+        #[serde(default)]
+        arbiter: Option<String>,
+        /// Seconds until `on_timeout` applies, 30 days by default.
+        #[serde(default)]
+        timeout_secs: Option<u64>,
+        #[serde(default)]
+        on_timeout: EscrowOutcome,
+        #[serde(default)]
+        pin: Option<String>,
+        #[serde(default)]
+        memo: Option<String>,
+    },
+    Release {
+        escrow_id: EscrowId,
+    },
+    Refund {
+        escrow_id: EscrowId,
+    },
+    Get {
+        escrow_id: EscrowId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Escrow {
+    pub id: EscrowId,
+    pub payer: AccountId,
+    pub beneficiary: AccountId,
+    pub amount: Amount,
+    pub currency: Currency,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arbiter: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub expires_at: u64,
+    pub on_timeout: EscrowOutcome,
+    /// Transaction that took the money from the payer.
+    pub opened_by: TransactionId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+/// Reply to a release or refund.
+#[derive(Debug, Serialize)]
+pub struct EscrowReceipt {
+    pub escrow_id: EscrowId,
+    pub outcome: EscrowOutcome,
+    pub transaction_id: TransactionId,
+    pub to: AccountId,
+    pub amount: Amount,
+    pub currency: Currency,
+}
+
+/// Open escrows.
+#[derive(Debug, Default)]
+pub struct Escrows {
+    escrows: BTreeMap<EscrowId, Escrow>,
+    last_id: u64,
+}
+
+impl Escrows {
+    pub fn next_id(&self) -> EscrowId {
+        EscrowId(self.last_id + 1)
+    }
+
+    pub fn insert(&mut self, escrow: Escrow) {
+        self.last_id = self.last_id.max(escrow.id.0);
+        self.escrows.insert(escrow.id, escrow);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Escrow> {
+        self.escrows.values()
+    }
+
+    pub fn get(&self, id: EscrowId) -> Option<&Escrow> {
+        self.escrows.get(&id)
+    }
+
+    pub fn remove(&mut self, id: EscrowId) -> Option<Escrow> {
+        self.escrows.remove(&id)
+    }
+
+    /// The escrows whose deadline passed by `now`, oldest first.
+    pub fn expired(&self, now: u64) -> Vec<EscrowId> {
+        self.escrows
+            .values()
+            .filter(|escrow| escrow.expires_at <= now)
+            .map(|escrow| escrow.id)
+            .collect()
+    }
+}
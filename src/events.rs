@@ -0,0 +1,296 @@
+//! The bank's state as a sequence of events. Every change to accounts,
+//! balances, holds and the ledger is recorded as an event before it takes
+//! effect, and applying the events in order to an empty bank rebuilds it.
+//!
+//! Configuration (PINs, exchange rates, fees, limits and rules from the
+//! config file) is not part of the stream, exchange rates set by an admin
+//! are.
+//!
+//! Written out as JSON, one event per line.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::encryption::{self, EncryptionError};
+use crate::escrow::{Escrow, EscrowId};
+use crate::fraud::FlaggedTransaction;
+use crate::fx::Rate;
+use crate::goals::{Goal, GoalId};
+use crate::holds::{Hold, HoldId};
+use crate::interbank::UnconfirmedTransfer;
+use crate::interest::Accrual;
+use crate::ledger::{Movement, TransactionId};
+use crate::limits::TransferLimits;
+use crate::loans::{Loan, LoanId};
+use crate::money::Currency;
+use crate::owners::{ApprovalId, ApprovalRequest};
+use crate::review::{ParkedTransfer, ReviewId};
+use crate::scheduler::{ScheduleId, ScheduledTransfer, StandingOrder, StandingOrderId};
+use crate::{AccountId, AccountMetadata, AccountStatus, Amount};
+
+#[derive(Error, Debug)]
+pub enum EventLogError {
+    #[error("Unable to read the event log")]
+    Io(#[from] io::Error),
+    #[error("Invalid event on line {line} of the event log")]
+    Parse {
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Unable to read line {line} of the event log")]
+    Sealed {
+        line: usize,
+        #[source]
+        source: EncryptionError,
+    },
+    #[error("Event {sequence} does not follow from the events before it")]
+    Inconsistent { sequence: u64 },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// Accounts open with a zero balance, any opening balance is a transfer
+    /// of its own.
+    AccountOpened {
+        account: AccountId,
+        name: String,
+        currency: Currency,
+    },
+    /// In the currency of its parent.
+    SubAccountOpened {
+        account: AccountId,
+        name: String,
+        parent: AccountId,
+    },
+    /// Money moved, in one ledger transaction.
+    FundsTransferred {
+        transaction_id: TransactionId,
+        movements: Vec<Movement>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reverses: Option<TransactionId>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fee_for: Option<TransactionId>,
+    },
+    /// The old name is free for other accounts from then on.
+    AccountRenamed {
+        account: AccountId,
+        name: String,
+    },
+    /// The account's balance moved into `into` in one ledger transaction,
+    /// none if it was zero, and the account closed.
+    AccountMerged {
+        account: AccountId,
+        into: AccountId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        transaction_id: Option<TransactionId>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        movements: Vec<Movement>,
+    },
+    StatusChanged {
+        account: AccountId,
+        status: AccountStatus,
+    },
+    OverdraftLimitSet {
+        account: AccountId,
+        limit: Amount,
+    },
+    TransferLimitsSet {
+        account: AccountId,
+        limits: Option<TransferLimits>,
+    },
+    MetadataUpdated {
+        account: AccountId,
+        metadata: AccountMetadata,
+    },
+    /// The interest rate changed or interest was accrued.
+    InterestChanged {
+        account: AccountId,
+        interest: Option<Accrual>,
+    },
+    HoldPlaced {
+        hold: Hold,
+    },
+    /// The hold was captured, cancelled or expired.
+    HoldReleased {
+        hold_id: HoldId,
+    },
+    /// Follows the transfer into escrow.
+    EscrowOpened {
+        escrow: Escrow,
+    },
+    /// Follows the transfer out of escrow, to the beneficiary or the payer.
+    EscrowClosed {
+        escrow_id: EscrowId,
+    },
+    OwnersChanged {
+        account: AccountId,
+        owners: BTreeSet<String>,
+        approvals_required: u32,
+    },
+    /// A transfer out of a joint account, approved by its sender.
+    ApprovalRequested {
+        request: ApprovalRequest,
+    },
+    TransferApproved {
+        approval_id: ApprovalId,
+        owner: String,
+    },
+    /// Follows the transfer once approved, or the rejection.
+    ApprovalClosed {
+        approval_id: ApprovalId,
+    },
+    /// Follows the transfer of the principal to the borrower.
+    LoanIssued {
+        loan: Loan,
+    },
+    /// Follows the interest charged for the installment.
+    LoanInterestCharged {
+        loan_id: LoanId,
+        installments_charged: u32,
+    },
+    /// Follows the opening of its sub-account.
+    GoalCreated {
+        goal: Goal,
+    },
+    /// Follows the sweep, if anything was swept.
+    GoalSwept {
+        goal_id: GoalId,
+        next_sweep_at: u64,
+    },
+    GoalDeleted {
+        goal_id: GoalId,
+    },
+    TransferParked {
+        parked: ParkedTransfer,
+    },
+    /// Follows the transfer once approved, or the rejection.
+    ReviewClosed {
+        review_id: ReviewId,
+    },
+    TransferScheduled {
+        scheduled: ScheduledTransfer,
+    },
+    /// Follows the transfer once due, whether it was made or failed, or
+    /// the cancellation.
+    ScheduledTransferClosed {
+        schedule_id: ScheduleId,
+    },
+    StandingOrderCreated {
+        order: StandingOrder,
+    },
+    /// Follows the payment, if one was made.
+    StandingOrderRescheduled {
+        order_id: StandingOrderId,
+        next_run_at: u64,
+        retry_at: Option<u64>,
+    },
+    StandingOrderDeleted {
+        order_id: StandingOrderId,
+    },
+    /// Set by an admin; the configured rates are applied on top on startup.
+    ExchangeRateSet {
+        from: Currency,
+        to: Currency,
+        rate: Rate,
+    },
+    /// Follows the transfer.
+    TransactionFlagged {
+        flagged: FlaggedTransaction,
+    },
+    /// Follows the transfer to the peer's settlement account.
+    PeerTransferUnconfirmed {
+        unconfirmed: UnconfirmedTransfer,
+    },
+    /// Follows the reversal, if the peer rejected the transfer.
+    PeerTransferConfirmed {
+        reference: TransactionId,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EventRecord {
+    /// Position in the log, starting at 1.
+    pub sequence: u64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub event: Event,
+}
+
+/// The events since the last snapshot.
+#[derive(Debug, Default)]
+pub struct EventLog {
+    records: Vec<EventRecord>,
+    /// Sequence number of the last event covered by the snapshot.
+    compacted: u64,
+}
+
+impl EventLog {
+    /// Continue a log whose events up to `sequence` are in a snapshot.
+    pub fn after(sequence: u64) -> EventLog {
+        EventLog {
+            records: Vec::new(),
+            compacted: sequence,
+        }
+    }
+
+    pub fn append(&mut self, timestamp: u64, event: Event) -> &EventRecord {
+        let record = EventRecord {
+            sequence: self.last_sequence() + 1,
+            timestamp,
+            event,
+        };
+        self.records.push(record);
+        self.records.last().unwrap()
+    }
+
+    /// Sequence number of the last event, 0 if there were none.
+    pub fn last_sequence(&self) -> u64 {
+        self.compacted + self.records.len() as u64
+    }
+
+    /// The event `sequence`, if it is in the log.
+    pub fn get(&self, sequence: u64) -> Option<&EventRecord> {
+        let position = sequence.checked_sub(self.compacted + 1)?;
+        self.records.get(position as usize)
+    }
+
+    /// Drop the events, which a snapshot now covers.
+    pub fn compact(&mut self) {
+        self.compacted = self.last_sequence();
+        self.records.clear();
+    }
+
+    pub fn write(&self, mut out: impl Write) -> io::Result<()> {
+        for record in &self.records {
+            serde_json::to_writer(&mut out, record)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()
+    }
+}
+
+/// Read events written by `EventLog::write`. Blank lines are skipped.
+pub fn read(input: impl BufRead) -> Result<Vec<EventRecord>, EventLogError> {
+    let mut records = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line = encryption::decode(&line).map_err(|source| EventLogError::Sealed {
+            line: index + 1,
+            source,
+        })?;
+        let record = serde_json::from_str(&line).map_err(|source| EventLogError::Parse {
+            line: index + 1,
+            source,
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
@@ -0,0 +1,134 @@
+//! Export of the whole ledger to a file for analysis in external tools, as
+//! CSV or, with the `parquet` feature, as Parquet. One row per ledger entry,
+//! in ledger order, with these columns:
+//!
+//! - `sequence`, `transaction_id`
+//! - `timestamp`: seconds since the Unix epoch in CSV, a timestamp in
+//!   Parquet.
+//! - `from`, `to`: account IDs, empty where money enters or leaves the
+//!   accounts.
+//! - `amount`: in major units with two decimals, `currency`
+//! - `rate`, `memo`, `external_ref`, `reverses`, `fee_for`: empty unless set.
+
+#[cfg(feature = "parquet")]
+mod parquet;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::csv;
+use crate::ledger::LedgerEntry;
+
+const CSV_HEADER: &str =
+    "sequence,transaction_id,timestamp,from,to,amount,currency,rate,memo,external_ref,reverses,fee_for";
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("Unable to write ledger export {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Parquet export needs the parquet feature")]
+    ParquetDisabled,
+    #[error("Unknown export format '{0}', expected \"csv\" or \"parquet\"")]
+    Format(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+impl FromStr for ExportFormat {
+    type Err = ExportError;
+
+    fn from_str(s: &str) -> Result<ExportFormat, ExportError> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(ExportError::Format(s.to_string())),
+        }
+    }
+}
+
+/// Write `entries` to the file at `path`, replacing it.
+pub fn export(
+    entries: &[LedgerEntry],
+    path: &Path,
+    format: ExportFormat,
+) -> Result<(), ExportError> {
+    let write: fn(&[LedgerEntry], &mut BufWriter<File>) -> io::Result<()> = match format {
+        ExportFormat::Csv => |entries, out| write_csv(entries, out),
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => |entries, out| parquet::write(entries, out),
+        #[cfg(not(feature = "parquet"))]
+        ExportFormat::Parquet => return Err(ExportError::ParquetDisabled),
+    };
+    let io_error = |source| ExportError::Io {
+        path: path.to_owned(),
+        source,
+    };
+    let mut out = BufWriter::new(File::create(path).map_err(io_error)?);
+    write(entries, &mut out)
+        .and_then(|()| out.flush())
+        .map_err(io_error)
+}
+
+pub fn write_csv(entries: &[LedgerEntry], mut out: impl Write) -> io::Result<()> {
+    let optional = |value: Option<u64>| value.map_or_else(String::new, |value| value.to_string());
+    writeln!(out, "{CSV_HEADER}")?;
+    for entry in entries {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            entry.sequence,
+            entry.transaction_id.0,
+            entry.timestamp,
+            optional(entry.from.map(|id| id.0)),
+            optional(entry.to.map(|id| id.0)),
+            entry.amount,
+            entry.currency,
+            entry.rate.map_or_else(String::new, |rate| rate.to_string()),
+            csv::field(entry.memo.as_deref().unwrap_or_default()),
+            csv::field(entry.external_ref.as_deref().unwrap_or_default()),
+            optional(entry.reverses.map(|id| id.0)),
+            optional(entry.fee_for.map(|id| id.0)),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bank;
+
+    #[test]
+    fn csv_has_a_row_per_entry() {
+        let mut bank = Bank::new();
+        bank.open_account(
+            "a".to_string(),
+            crate::Balance::from_minor(250),
+            Default::default(),
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        write_csv(bank.ledger().entries(), &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[0], CSV_HEADER);
+        assert!(rows[1].starts_with("1,1,"));
+        assert!(rows[1].ends_with(",,1,2.50,EUR,,,,,"));
+        assert_eq!(rows.len(), 2);
+    }
+}
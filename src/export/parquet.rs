@@ -0,0 +1,387 @@
+//! Just enough of the Parquet format to write the ledger: one row group,
+//! one uncompressed data page per column, values in the plain encoding.
+//! Metadata is encoded with the Thrift compact protocol, as the format
+//! requires.
+//!
+//! Amounts are decimals with two places, timestamps are in milliseconds and
+//! columns that may be empty are optional.
+
+use std::io::{self, Write};
+
+use crate::ledger::LedgerEntry;
+use crate::money::MINOR_UNITS_PER_MAJOR;
+
+const MAGIC: &[u8] = b"PAR1";
+
+// Physical types.
+const INT64: i32 = 2;
+const BYTE_ARRAY: i32 = 6;
+
+// Converted types.
+const UTF8: i32 = 0;
+const DECIMAL: i32 = 5;
+const TIMESTAMP_MILLIS: i32 = 9;
+
+const REQUIRED: i32 = 0;
+const OPTIONAL: i32 = 1;
+
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+const UNCOMPRESSED: i32 = 0;
+const DATA_PAGE: i32 = 0;
+
+/// Decimal places of amounts.
+const AMOUNT_SCALE: i32 = MINOR_UNITS_PER_MAJOR.ilog10() as i32;
+/// Most digits of an amount that fits in an INT64.
+const AMOUNT_PRECISION: i32 = 18;
+
+enum Values {
+    Int64(Vec<Option<i64>>),
+    Text(Vec<Option<String>>),
+}
+
+impl Values {
+    fn len(&self) -> usize {
+        match self {
+            Values::Int64(values) => values.len(),
+            Values::Text(values) => values.len(),
+        }
+    }
+
+    fn is_present(&self, index: usize) -> bool {
+        match self {
+            Values::Int64(values) => values[index].is_some(),
+            Values::Text(values) => values[index].is_some(),
+        }
+    }
+}
+
+struct Column {
+    name: &'static str,
+    optional: bool,
+    converted_type: Option<i32>,
+    values: Values,
+}
+
+impl Column {
+    fn physical_type(&self) -> i32 {
+        match self.values {
+            Values::Int64(_) => INT64,
+            Values::Text(_) => BYTE_ARRAY,
+        }
+    }
+}
+
+fn columns(entries: &[LedgerEntry]) -> io::Result<Vec<Column>> {
+    let int64 = |name, optional, value: &dyn Fn(&LedgerEntry) -> Option<u64>| {
+        let values = entries
+            .iter()
+            .map(|entry| value(entry).map(i64::try_from).transpose())
+            .collect::<Result<_, _>>()
+            .map_err(|_| io::Error::other(format!("{name} does not fit in a Parquet INT64")))?;
+        Ok::<_, io::Error>(Column {
+            name,
+            optional,
+            converted_type: None,
+            values: Values::Int64(values),
+        })
+    };
+    let text = |name, optional, value: &dyn Fn(&LedgerEntry) -> Option<String>| Column {
+        name,
+        optional,
+        converted_type: Some(UTF8),
+        values: Values::Text(entries.iter().map(value).collect()),
+    };
+    Ok(vec![
+        int64("sequence", false, &|entry| Some(entry.sequence))?,
+        int64("transaction_id", false, &|entry| {
+            Some(entry.transaction_id.0)
+        })?,
+        Column {
+            converted_type: Some(TIMESTAMP_MILLIS),
+            ..int64("timestamp", false, &|entry| {
+                entry.timestamp.checked_mul(1000)
+            })?
+        },
+        int64("from", true, &|entry| entry.from.map(|id| id.0))?,
+        int64("to", true, &|entry| entry.to.map(|id| id.0))?,
+        Column {
+            converted_type: Some(DECIMAL),
+            ..int64("amount", false, &|entry| Some(entry.amount.minor_units()))?
+        },
+        text("currency", false, &|entry| Some(entry.currency.to_string())),
+        text("rate", true, &|entry| {
+            entry.rate.map(|rate| rate.to_string())
+        }),
+        text("memo", true, &|entry| entry.memo.clone()),
+        text("external_ref", true, &|entry| entry.external_ref.clone()),
+        int64("reverses", true, &|entry| entry.reverses.map(|id| id.0))?,
+        int64("fee_for", true, &|entry| entry.fee_for.map(|id| id.0))?,
+    ])
+}
+
+pub fn write(entries: &[LedgerEntry], mut out: impl Write) -> io::Result<()> {
+    let columns = columns(entries)?;
+    let rows = entries.len() as i64;
+    out.write_all(MAGIC)?;
+    let mut offset = MAGIC.len() as i64;
+    let mut chunks = Vec::new();
+    if rows > 0 {
+        for column in &columns {
+            let page = page(column);
+            out.write_all(&page)?;
+            chunks.push((offset, page.len() as i64));
+            offset += page.len() as i64;
+        }
+    }
+    let footer = file_metadata(&columns, rows, &chunks);
+    out.write_all(&footer)?;
+    out.write_all(&(footer.len() as u32).to_le_bytes())?;
+    out.write_all(MAGIC)
+}
+
+/// A data page with its header.
+fn page(column: &Column) -> Vec<u8> {
+    let mut data = Vec::new();
+    if column.optional {
+        let levels = definition_levels(column);
+        data.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+        data.extend_from_slice(&levels);
+    }
+    match &column.values {
+        Values::Int64(values) => {
+            for value in values.iter().flatten() {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        Values::Text(values) => {
+            for value in values.iter().flatten() {
+                data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                data.extend_from_slice(value.as_bytes());
+            }
+        }
+    }
+    let mut header = Compact::default();
+    header.i32(1, DATA_PAGE);
+    header.i32(2, data.len() as i32);
+    header.i32(3, data.len() as i32);
+    header.begin_struct(5);
+    header.i32(1, column.values.len() as i32);
+    header.i32(2, PLAIN);
+    header.i32(3, RLE);
+    header.i32(4, RLE);
+    header.end_struct();
+    let mut page = header.finish();
+    page.extend_from_slice(&data);
+    page
+}
+
+/// Whether each value is present, as runs in the RLE hybrid encoding with a
+/// bit width of 1.
+fn definition_levels(column: &Column) -> Vec<u8> {
+    let mut levels = Vec::new();
+    let mut index = 0;
+    while index < column.values.len() {
+        let present = column.values.is_present(index);
+        let run = (index..column.values.len())
+            .take_while(|&next| column.values.is_present(next) == present)
+            .count();
+        varint(&mut levels, (run as u64) << 1);
+        levels.push(u8::from(present));
+        index += run;
+    }
+    levels
+}
+
+/// `chunks` holds the offset and length of each column's page.
+fn file_metadata(columns: &[Column], rows: i64, chunks: &[(i64, i64)]) -> Vec<u8> {
+    let mut metadata = Compact::default();
+    metadata.i32(1, 1);
+    metadata.begin_list(2, Compact::STRUCT, columns.len() + 1);
+    metadata.begin_element();
+    metadata.binary(4, b"ledger");
+    metadata.i32(5, columns.len() as i32);
+    metadata.end_struct();
+    for column in columns {
+        metadata.begin_element();
+        metadata.i32(1, column.physical_type());
+        metadata.i32(3, if column.optional { OPTIONAL } else { REQUIRED });
+        metadata.binary(4, column.name.as_bytes());
+        if let Some(converted_type) = column.converted_type {
+            metadata.i32(6, converted_type);
+        }
+        if column.converted_type == Some(DECIMAL) {
+            metadata.i32(7, AMOUNT_SCALE);
+            metadata.i32(8, AMOUNT_PRECISION);
+        }
+        metadata.end_struct();
+    }
+    metadata.i64(3, rows);
+    let row_groups = if chunks.is_empty() { 0 } else { 1 };
+    metadata.begin_list(4, Compact::STRUCT, row_groups);
+    if row_groups == 1 {
+        metadata.begin_element();
+        metadata.begin_list(1, Compact::STRUCT, columns.len());
+        for (column, &(offset, length)) in columns.iter().zip(chunks) {
+            metadata.begin_element();
+            metadata.i64(2, offset);
+            metadata.begin_struct(3);
+            metadata.i32(1, column.physical_type());
+            let encodings: &[i32] = if column.optional {
+                &[PLAIN, RLE]
+            } else {
+                &[PLAIN]
+            };
+            metadata.begin_list(2, Compact::I32, encodings.len());
+            for &encoding in encodings {
+                metadata.list_i32(encoding);
+            }
+            metadata.begin_list(3, Compact::BINARY, 1);
+            metadata.list_binary(column.name.as_bytes());
+            metadata.i32(4, UNCOMPRESSED);
+            metadata.i64(5, column.values.len() as i64);
+            metadata.i64(6, length);
+            metadata.i64(7, length);
+            metadata.i64(9, offset);
+            metadata.end_struct();
+            metadata.end_struct();
+        }
+        let total: i64 = chunks.iter().map(|&(_, length)| length).sum();
+        metadata.i64(2, total);
+        metadata.i64(3, rows);
+        metadata.end_struct();
+    }
+    metadata.binary(6, concat!("bank ", env!("CARGO_PKG_VERSION")).as_bytes());
+    metadata.finish()
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Writer of Thrift structs in the compact protocol.
+#[derive(Default)]
+struct Compact {
+    out: Vec<u8>,
+    /// ID of the last field written, for each struct being written.
+    last_field: Vec<i16>,
+    current: i16,
+}
+
+impl Compact {
+    const I32: u8 = 5;
+    const I64: u8 = 6;
+    const BINARY: u8 = 8;
+    const LIST: u8 = 9;
+    const STRUCT: u8 = 12;
+
+    fn field(&mut self, id: i16, field_type: u8) {
+        let delta = id - self.current;
+        if (1..=15).contains(&delta) {
+            self.out.push((delta as u8) << 4 | field_type);
+        } else {
+            self.out.push(field_type);
+            varint(&mut self.out, zigzag(id.into()));
+        }
+        self.current = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, Compact::I32);
+        varint(&mut self.out, zigzag(value.into()));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, Compact::I64);
+        varint(&mut self.out, zigzag(value));
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, Compact::BINARY);
+        self.list_binary(value);
+    }
+
+    fn begin_struct(&mut self, id: i16) {
+        self.field(id, Compact::STRUCT);
+        self.begin_element();
+    }
+
+    /// Start a struct that is an element of a list.
+    fn begin_element(&mut self) {
+        self.last_field.push(self.current);
+        self.current = 0;
+    }
+
+    fn end_struct(&mut self) {
+        self.out.push(0);
+        self.current = self.last_field.pop().unwrap_or(0);
+    }
+
+    fn begin_list(&mut self, id: i16, element_type: u8, len: usize) {
+        self.field(id, Compact::LIST);
+        if len < 15 {
+            self.out.push((len as u8) << 4 | element_type);
+        } else {
+            self.out.push(0xf0 | element_type);
+            varint(&mut self.out, len as u64);
+        }
+    }
+
+    fn list_i32(&mut self, value: i32) {
+        varint(&mut self.out, zigzag(value.into()));
+    }
+
+    fn list_binary(&mut self, value: &[u8]) {
+        varint(&mut self.out, value.len() as u64);
+        self.out.extend_from_slice(value);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.out.push(0);
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thrift_fields_are_delta_encoded() {
+        let mut compact = Compact::default();
+        compact.i32(1, -1);
+        compact.begin_struct(3);
+        compact.i64(20, 300);
+        compact.end_struct();
+        compact.binary(4, b"ab");
+        assert_eq!(
+            compact.finish(),
+            [
+                0x15, 0x01, // field 1, i32, zigzag(-1)
+                0x2c, // field 3, struct
+                0x06, 0x28, 0xd8, 0x04, 0x00, // field 20 in full, i64 600, stop
+                0x18, 0x02, b'a', b'b', // field 4, binary
+                0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn definition_levels_are_run_length_encoded() {
+        let column = Column {
+            name: "memo",
+            optional: true,
+            converted_type: Some(UTF8),
+            values: Values::Text(vec![None, None, Some("rent".to_string()), None]),
+        };
+        assert_eq!(definition_levels(&column), [4, 0, 2, 1, 2, 0]);
+    }
+}
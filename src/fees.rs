@@ -0,0 +1,100 @@
+//! Fees charged on transfers, configured as the `fees` setting, e.g.
+//! `{"account": "fees", "rules": [{"account_tag": "business", "flat": "1"},
+//! {"max_amount": "100", "flat": "0.25"}, {"rate": "0.005"}]}`.
+//!
+//! The first rule matching a transfer decides its fee: the flat part plus
+//! `rate` times the amount. The payer is charged on top of the amount, and
+//! the fee is credited to the fee account as a separate transaction linked
+//! to the transfer.
+
+use std::collections::BTreeSet;
+
+use serde::Deserialize;
+
+use crate::fx::Rate;
+use crate::money::Money;
+
+pub const MEMO: &str = "fee";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeConfig {
+    /// Name of the account fees are credited to. Its own transfers are free.
+    pub account: String,
+    #[serde(default)]
+    pub rules: Vec<FeeRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeRule {
+    /// Applies to transfers of at least this amount.
+    #[serde(default)]
+    pub min_amount: Option<Money>,
+    /// Applies to transfers below this amount.
+    #[serde(default)]
+    pub max_amount: Option<Money>,
+    /// Applies to transfers from accounts with this tag, such as
+    /// `"business"`.
+    #[serde(default)]
+    pub account_tag: Option<String>,
+    #[serde(default)]
+    pub flat: Money,
+    /// Share of the amount, e.g. `"0.005"` for 0.5 %.
+    #[serde(default)]
+    pub rate: Option<Rate>,
+}
+
+impl FeeRule {
+    fn matches(&self, amount: Money, tags: &BTreeSet<String>) -> bool {
+        self.min_amount.is_none_or(|min| amount >= min)
+            && self.max_amount.is_none_or(|max| amount < max)
+            && self
+                .account_tag
+                .as_ref()
+                .is_none_or(|tag| tags.contains(tag))
+    }
+
+    fn fee(&self, amount: Money) -> Option<Money> {
+        let share = match self.rate {
+            Some(rate) => rate.prorate(amount, 1, 1)?,
+            None => Money::ZERO,
+        };
+        self.flat.checked_add(share)
+    }
+}
+
+impl FeeConfig {
+    /// Fee for transferring `amount` from an account with `tags`, `None`
+    /// when no rule matches, the fee is zero or it overflows.
+    pub fn fee(&self, amount: Money, tags: &BTreeSet<String>) -> Option<Money> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(amount, tags))?
+            .fee(amount)
+            .filter(|fee| !fee.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let config: FeeConfig = serde_json::from_str(
+            r#"{"account": "fees", "rules": [
+                {"account_tag": "business", "flat": "1"},
+                {"max_amount": "100", "flat": "0.25"},
+                {"flat": "0.10", "rate": "0.005"}
+            ]}"#,
+        )
+        .unwrap();
+        let personal = BTreeSet::new();
+        let business = BTreeSet::from(["business".to_string()]);
+        let fee = |amount, tags| config.fee(Money::from_minor(amount), tags);
+        assert_eq!(fee(5_000, &business), Some(Money::from_minor(100)));
+        assert_eq!(fee(9_999, &personal), Some(Money::from_minor(25)));
+        assert_eq!(fee(20_000, &personal), Some(Money::from_minor(110)));
+    }
+}
@@ -0,0 +1,88 @@
+//! Screening of transfers against configurable rules, set with the
+//! `fraud_rules` setting, e.g. `[{"condition": {"velocity": {"max_transfers":
+//! 5, "window_secs": 60}}, "action": "reject"}, {"condition": {"new_payee":
+//! {"min_age_secs": 86400}}, "action": "flag"}]`.
+//!
+//! A rejected transfer is not made. A flagged one is made and kept for
+//! review; admins list flagged transactions with the `f` instruction.
+
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ledger::TransactionId;
+use crate::money::Money;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FraudRule {
+    pub condition: Condition,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Condition {
+    /// The paying account already made `max_transfers` transfers within the
+    /// last `window_secs`.
+    Velocity {
+        max_transfers: usize,
+        window_secs: u64,
+    },
+    /// The amount is less than `margin` below `threshold`, or below the
+    /// paying account's single transfer limit if no threshold is given.
+    JustUnder {
+        #[serde(default)]
+        threshold: Option<Money>,
+        margin: Money,
+    },
+    /// The receiving account was opened less than `min_age_secs` ago.
+    NewPayee { min_age_secs: u64 },
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Velocity {
+                max_transfers,
+                window_secs,
+            } => write!(f, "more than {max_transfers} transfers in {window_secs} s"),
+            Condition::JustUnder {
+                threshold: Some(threshold),
+                margin,
+            } => write!(f, "amount within {margin} under {threshold}"),
+            Condition::JustUnder {
+                threshold: None,
+                margin,
+            } => write!(f, "amount within {margin} under the transfer limit"),
+            Condition::NewPayee { min_age_secs } => {
+                write!(f, "payee opened less than {min_age_secs} s ago")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Flag,
+    Reject,
+}
+
+/// A transaction that matched rules with the `flag` action.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FlaggedTransaction {
+    pub transaction_id: TransactionId,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The conditions that matched.
+    pub reasons: Vec<String>,
+}
+
+/// Whether `amount` is less than `margin` below `threshold`.
+pub fn just_under(amount: Money, threshold: Money, margin: Money) -> bool {
+    amount < threshold
+        && threshold
+            .checked_sub(amount)
+            .is_some_and(|gap| gap < margin)
+}
@@ -0,0 +1,88 @@
+//! Entry points for the fuzz targets in `fuzz/`, run with e.g.
+//! `cargo fuzz run request`. They take what a client could send and must
+//! neither panic nor hang, whatever it is.
+
+use serde::de::DeserializeOwned;
+
+use crate::escrow::EscrowCommand;
+use crate::goals::GoalCommand;
+use crate::holds::HoldCommand;
+use crate::interbank::InterbankTransfer;
+use crate::owners::OwnersCommand;
+use crate::protocol;
+use crate::sandbox::SandboxCommand;
+use crate::scheduler::ScheduleCommand;
+use crate::{
+    init_bank, AdminCommand, AttestationQuery, BatchRequest, HistoryQuery, ReversalRequest,
+    StatementQuery, SubscriptionRequest, TxInfo,
+};
+
+/// A request as the server reads it: the datagram with the instruction and
+/// header, then, after the first newline, the payload.
+pub fn request(data: &[u8]) {
+    let (datagram, payload) = match data.iter().position(|&byte| byte == b'\n') {
+        Some(newline) => (&data[..newline], &data[newline + 1..]),
+        None => (data, &[][..]),
+    };
+    let Some(instruction) = protocol::parse_instruction(datagram) else {
+        return;
+    };
+    if protocol::parse_header(&datagram[1..]).is_err() || !protocol::has_payload(instruction) {
+        return;
+    }
+    match instruction {
+        "t" => transfer(payload),
+        "a" => parse::<AdminCommand>(payload),
+        "b" => parse::<BatchRequest>(payload),
+        "p" => parse::<HoldCommand>(payload),
+        "w" => parse::<EscrowCommand>(payload),
+        "o" => parse::<OwnersCommand>(payload),
+        "l" => parse::<GoalCommand>(payload),
+        "z" => parse::<SandboxCommand>(payload),
+        "s" => parse::<ScheduleCommand>(payload),
+        "r" => parse::<ReversalRequest>(payload),
+        "x" => parse::<InterbankTransfer>(payload),
+        "h" => parse::<HistoryQuery>(payload),
+        "v" => parse::<AttestationQuery>(payload),
+        "e" => parse::<StatementQuery>(payload),
+        "u" => parse::<SubscriptionRequest>(payload),
+        _ => {}
+    }
+}
+
+/// The payload of the `t` instruction, made as a transfer between the demo
+/// accounts if it parses.
+pub fn transfer(payload: &[u8]) {
+    let Ok(tx_info) = serde_json::from_slice::<TxInfo>(payload) else {
+        return;
+    };
+    let mut bank = init_bank(None).expect("the demo accounts open");
+    let _ = bank.handle_transaction(tx_info);
+}
+
+fn parse<T: DeserializeOwned>(payload: &[u8]) {
+    let _ = serde_json::from_slice::<T>(payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_requests_are_refused_without_panicking() {
+        let inputs: [&[u8]; 9] = [
+            b"",
+            b"\xff",
+            b"\xc3\xa9{}",
+            b"t{\"tenant\":",
+            b"t\n{\"from\":\"patko\",\"to\":\"siska\",\"amount\":\"99999999999999999999\"}",
+            b"t\n{\"from\":\"patko\",\"to\":\"patko\",\"amount\":\"-1\"}",
+            b"t\n{\"from\":{\"Id\":18446744073709551615},\"to\":\"siska\",\"amount\":\"1\"}",
+            b"a\n{\"op\":\"issue_loan\",\"installments\":-1}",
+            b"z\n\x00",
+        ];
+        for input in inputs {
+            request(input);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Exchange rates for cross-currency transfers.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::money::{Currency, Money};
+
+const RATE_DECIMAL_PLACES: u32 = 8;
+const RATE_SCALE: u128 = 10u128.pow(RATE_DECIMAL_PLACES);
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("'{0}' is not a valid exchange rate")]
+pub struct ParseRateError(String);
+
+/// Units of the target currency per unit of the source currency, with eight
+/// decimal places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rate(u64);
+
+impl Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = RATE_SCALE as u64;
+        write!(f, "{}.{:08}", self.0 / scale, self.0 % scale)
+    }
+}
+
+impl FromStr for Rate {
+    type Err = ParseRateError;
+
+    fn from_str(rate: &str) -> Result<Rate, ParseRateError> {
+        let invalid = || ParseRateError(rate.to_string());
+        let (whole, fraction) = rate.trim().split_once('.').unwrap_or((rate.trim(), ""));
+        let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if whole.is_empty()
+            || !all_digits(whole)
+            || !all_digits(fraction)
+            || fraction.len() > RATE_DECIMAL_PLACES as usize
+        {
+            return Err(invalid());
+        }
+        let whole: u64 = whole.parse().map_err(|_| invalid())?;
+        let fraction: u64 = format!("{fraction:0<8}").parse().map_err(|_| invalid())?;
+        let scaled = whole
+            .checked_mul(RATE_SCALE as u64)
+            .and_then(|scaled| scaled.checked_add(fraction))
+            .ok_or_else(invalid)?;
+        if scaled == 0 {
+            return Err(invalid());
+        }
+        Ok(Rate(scaled))
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Rate, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawRate {
+            Text(String),
+            Number(f64),
+        }
+        let text = match RawRate::deserialize(deserializer)? {
+            RawRate::Text(text) => text,
+            RawRate::Number(number) => number.to_string(),
+        };
+        text.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Rate {
+    /// `amount` times the rate for `numerator / denominator` of the period
+    /// the rate is quoted for, rounded to the nearest minor unit, ties to
+    /// even. Returns `None` on overflow.
+    pub fn prorate(self, amount: Money, numerator: u64, denominator: u64) -> Option<Money> {
+        let scaled = (amount.minor_units() as u128)
+            .checked_mul(self.0 as u128)?
+            .checked_mul(numerator as u128)?;
+        let prorated = scale_rounded(scaled, RATE_SCALE * denominator as u128);
+        u64::try_from(prorated).ok().map(Money::from_minor)
+    }
+}
+
+/// Multiply with round-half-to-even, the same rule `Money` uses for input.
+fn scale_rounded(numerator: u128, denominator: u128) -> u128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    match (remainder * 2).cmp(&denominator) {
+        std::cmp::Ordering::Greater => quotient + 1,
+        std::cmp::Ordering::Equal if quotient % 2 == 1 => quotient + 1,
+        _ => quotient,
+    }
+}
+
+/// Rate table keyed by currency pair. A pair can be used in both directions;
+/// the reverse direction divides by the rate.
+#[derive(Debug, Default, Clone)]
+pub struct FxRates {
+    rates: HashMap<(Currency, Currency), Rate>,
+}
+
+impl FxRates {
+    pub fn set(&mut self, from: Currency, to: Currency, rate: Rate) {
+        self.rates.remove(&(to, from));
+        self.rates.insert((from, to), rate);
+    }
+
+    /// The rates as set, ordered by pair.
+    pub fn rates(&self) -> Vec<(Currency, Currency, Rate)> {
+        let mut rates: Vec<_> = self
+            .rates
+            .iter()
+            .map(|(&(from, to), &rate)| (from, to, rate))
+            .collect();
+        rates.sort();
+        rates
+    }
+
+    /// Effective rate from `from` to `to`, inverting the reverse pair when
+    /// only that one is known.
+    pub fn rate(&self, from: Currency, to: Currency) -> Option<Rate> {
+        match (self.rates.get(&(from, to)), self.rates.get(&(to, from))) {
+            (Some(rate), _) => Some(*rate),
+            (None, Some(rate)) => {
+                let inverse = scale_rounded(RATE_SCALE * RATE_SCALE, rate.0 as u128);
+                Some(Rate(u64::try_from(inverse).unwrap_or(u64::MAX).max(1)))
+            }
+            (None, None) => None,
+        }
+    }
+
+    /// Convert `amount` of `from` into `to`, rounded to the nearest minor
+    /// unit, ties to even. Returns `None` without a rate for the pair or on
+    /// overflow.
+    pub fn convert(&self, amount: Money, from: Currency, to: Currency) -> Option<Money> {
+        if from == to {
+            return Some(amount);
+        }
+        let minor = amount.minor_units() as u128;
+        let converted = match (self.rates.get(&(from, to)), self.rates.get(&(to, from))) {
+            (Some(rate), _) => scale_rounded(minor * rate.0 as u128, RATE_SCALE),
+            (None, Some(rate)) => scale_rounded(minor * RATE_SCALE, rate.0 as u128),
+            (None, None) => return None,
+        };
+        u64::try_from(converted).ok().map(Money::from_minor)
+    }
+}
+
+/// Parse a `"EUR/USD"` style pair, as used for keys of the `fx_rates` setting.
+pub fn parse_pair(pair: &str) -> Option<(Currency, Currency)> {
+    let (from, to) = pair.split_once('/')?;
+    Some((from.parse().ok()?, to.parse().ok()?))
+}
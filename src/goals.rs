@@ -0,0 +1,184 @@
+//! Savings goals. A goal saves towards a target in a sub-account of the
+//! account it is attached to. Every `period_secs` whatever the account holds
+//! above `keep` is swept into the goal's sub-account, until the target is
+//! reached. Deleting a goal stops the sweeps; the savings stay in the
+//! sub-account.
+//!
+//! Sent as the JSON payload of the `l` instruction, e.g.
+//! `{"op": "create", "account": "patko", "name": "bicycle", "target": "500",
+//! "keep": "100"}`, which saves in `patko/bicycle`, then `{"op": "get",
+//! "goal_id": 1}` for its progress.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::money::Money;
+use crate::{AccountId, AccountRef, Amount};
+
+pub const DEFAULT_PERIOD_SECS: u64 = 24 * 60 * 60;
+
+pub const MEMO: &str = "savings goal";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct GoalId(u64);
+
+impl Display for GoalId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "goal{}", self.0)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum GoalError {
+    #[error("A savings goal needs a target above zero")]
+    InvalidTarget,
+    #[error("Goal {0} not found")]
+    NotFound(GoalId),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum GoalCommand {
+    Create {
+        account: AccountRef,
+        /// Of the goal's sub-account, after the account's name and a slash.
+        name: String,
+        target: Amount,
+        /// Left in the account by the sweeps.
+        #[serde(default)]
+        keep: Amount,
+        /// Between sweeps, a day by default. The first is a period from now.
+        #[serde(default)]
+        period_secs: Option<u64>,
+    },
+    Get {
+        goal_id: GoalId,
+    },
+    /// The goals of an account.
+    List {
+        account: AccountRef,
+    },
+    Delete {
+        goal_id: GoalId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Goal {
+    pub id: GoalId,
+    pub account: AccountId,
+    /// Where the savings are kept.
+    pub sub_account: AccountId,
+    pub target: Amount,
+    pub keep: Amount,
+    pub period_secs: u64,
+    /// Seconds since the Unix epoch.
+    pub next_sweep_at: u64,
+}
+
+impl Goal {
+    /// What is left to save once `saved` is.
+    pub fn remaining(&self, saved: Money) -> Money {
+        self.target.checked_sub(saved).unwrap_or(Money::ZERO)
+    }
+}
+
+/// Reply to the `get` and `list` commands.
+#[derive(Debug, Serialize)]
+pub struct GoalProgress {
+    #[serde(flatten)]
+    pub goal: Goal,
+    pub saved: Money,
+    pub remaining: Money,
+    /// Of the target saved, in whole percent up to 100.
+    pub percent: u64,
+    pub reached: bool,
+}
+
+impl GoalProgress {
+    pub fn new(goal: Goal, saved: Money) -> GoalProgress {
+        let remaining = goal.remaining(saved);
+        let percent = (u128::from(saved.minor_units()) * 100
+            / u128::from(goal.target.minor_units().max(1)))
+        .min(100) as u64;
+        GoalProgress {
+            goal,
+            saved,
+            remaining,
+            percent,
+            reached: remaining.is_zero(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Goals {
+    goals: BTreeMap<GoalId, Goal>,
+    last_id: u64,
+}
+
+impl Goals {
+    pub fn next_id(&self) -> GoalId {
+        GoalId(self.last_id + 1)
+    }
+
+    pub fn insert(&mut self, goal: Goal) {
+        self.last_id = self.last_id.max(goal.id.0);
+        self.goals.insert(goal.id, goal);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Goal> {
+        self.goals.values()
+    }
+
+    pub fn get(&self, id: GoalId) -> Option<&Goal> {
+        self.goals.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: GoalId) -> Option<&mut Goal> {
+        self.goals.get_mut(&id)
+    }
+
+    pub fn remove(&mut self, id: GoalId) -> Option<Goal> {
+        self.goals.remove(&id)
+    }
+
+    /// The goals due for a sweep by `now`.
+    pub fn due(&self, now: u64) -> Vec<GoalId> {
+        self.goals
+            .values()
+            .filter(|goal| goal.next_sweep_at <= now)
+            .map(|goal| goal.id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_is_capped_at_the_target() {
+        let goal = Goal {
+            id: GoalId(1),
+            account: AccountId(1),
+            sub_account: AccountId(2),
+            target: Money::from_minor(50_000),
+            keep: Money::ZERO,
+            period_secs: DEFAULT_PERIOD_SECS,
+            next_sweep_at: 0,
+        };
+        let halfway = GoalProgress::new(goal.clone(), Money::from_minor(25_000));
+        assert_eq!(halfway.percent, 50);
+        assert_eq!(halfway.remaining, Money::from_minor(25_000));
+        assert!(!halfway.reached);
+        let over = GoalProgress::new(goal, Money::from_minor(60_000));
+        assert_eq!(over.percent, 100);
+        assert!(over.remaining.is_zero());
+        assert!(over.reached);
+    }
+}
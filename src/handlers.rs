@@ -0,0 +1,1094 @@
+//! The instructions `serve` dispatches, one handler each. A handler gets
+//! the request once it passed the checks every instruction gets, see
+//! `serve`, and receives its payload, if it has one, into the buffer it is
+//! handed.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Result;
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+
+use crate::admin::AdminCommand;
+use crate::auth::{Auth, AuthError, Identity};
+use crate::escrow::{EscrowCommand, EscrowOutcome};
+use crate::goals::GoalCommand;
+use crate::health;
+use crate::holds::HoldCommand;
+use crate::idempotency::Lookup;
+use crate::interbank::InterbankTransfer;
+use crate::invariants;
+use crate::listing::AccountFilter;
+use crate::logging;
+use crate::metrics::{self, Metrics};
+use crate::owners::{OwnersCommand, OwnersInfo, OwnershipError};
+use crate::protocol::{self, RequestHeader};
+use crate::sandbox::SandboxCommand;
+use crate::scheduler::ScheduleCommand;
+use crate::shards;
+use crate::statistics::{self, AggregateQuery};
+use crate::subscriptions::SubscriptionAction;
+use crate::trace::{Phase, RequestTrace};
+use crate::transport::Transport;
+use crate::{
+    answer_account_search, answer_history_query, failure_status, recv_payload,
+    reply_transaction_outcome, respond, respond_error, run_sandbox_command, AccountRef,
+    ApprovalOutcome, AttestationQuery, Bank, BatchRequest, Checks, Config, CustomError, ErrorReply,
+    IndependentBatchReceipt, Receipt, ReversalRequest, StatementQuery, SubscriptionRequest, Tenant,
+    TransferResult, TxInfo,
+};
+
+/// A request being handled, with what `serve` keeps across requests that
+/// handlers need.
+pub struct Request<'a> {
+    pub socket: &'a dyn Transport,
+    pub sender: &'a Option<PathBuf>,
+    pub trace: &'a mut RequestTrace,
+    pub instruction: &'a str,
+    pub header: &'a RequestHeader,
+    pub version: u32,
+    /// Extended with the accounts owned once the bank is known, see
+    /// `extend_identity`.
+    pub identity: Option<Identity>,
+    /// Key of the client for rate limiting and idempotency, see
+    /// `client_key`.
+    pub client: &'a str,
+    pub auth: &'a mut Auth,
+    pub config: &'a Config,
+    pub metrics: &'a mut Metrics,
+}
+
+impl Request<'_> {
+    pub fn respond(&mut self, message: &[u8]) -> Result<()> {
+        Ok(respond(self.socket, self.sender, self.trace, message)?)
+    }
+
+    /// Refuse the request with the status `error` calls for.
+    fn refuse(&mut self, error: AuthError) -> Result<()> {
+        self.respond(error.status().as_bytes())
+    }
+
+    fn respond_error(&mut self, status: &str, error: &CustomError) -> Result<()> {
+        let failure = ErrorReply::new(status, error);
+        respond_error(self.socket, self.sender, self.trace, self.version, &failure)
+    }
+
+    fn reply_transaction_outcome(&mut self, outcome: &Result<Receipt, ErrorReply>) -> Result<()> {
+        reply_transaction_outcome(self.socket, self.sender, self.trace, self.version, outcome)
+    }
+
+    /// Receive the payload into `buffer` and check its signature. `None`
+    /// when it did not come or was refused, with the reason replied; `what`
+    /// is the payload as logged.
+    pub fn receive<'b>(&mut self, buffer: &'b mut [u8], what: &str) -> Result<Option<&'b [u8]>> {
+        let payload = match recv_payload(self.socket, self.sender, self.trace, buffer) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Error while receiving {what}: {e:?}");
+                return Ok(None);
+            }
+        };
+        if let Err(e) = self
+            .auth
+            .verify_signature(self.instruction, self.header, payload)
+        {
+            warn!("Rejected {what}: {e}");
+            self.refuse(e)?;
+            return Ok(None);
+        }
+        Ok(Some(payload))
+    }
+
+    /// Parse `payload` as JSON, refusing it with "400" if it is malformed.
+    fn parse<T: DeserializeOwned>(&mut self, payload: &[u8], what: &str) -> Result<Option<T>> {
+        match serde_json::from_slice(payload) {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(e) => {
+                warn!("Rejected malformed {what}: {e}");
+                self.respond("400".as_bytes())?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// `receive` and `parse` in one.
+    fn receive_json<T: DeserializeOwned>(
+        &mut self,
+        buffer: &mut [u8],
+        what: &str,
+    ) -> Result<Option<T>> {
+        match self.receive(buffer, what)? {
+            Some(payload) => self.parse(payload, what),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The name of `account`, or how it was given if there is no such account.
+/// Roles name accounts, so IDs are resolved before authorizing.
+fn account_name(bank: &Bank, account: &AccountRef) -> String {
+    bank.account_name(account)
+        .map_or_else(|| account.to_string(), str::to_string)
+}
+
+/// `t`: a transfer, made, or left waiting for the owners of a joint account
+/// or for review.
+pub fn transfer(request: &mut Request, tenant: &mut Tenant, buffer: &mut [u8]) -> Result<()> {
+    let Tenant {
+        bank,
+        idempotency,
+        peers,
+        ..
+    } = tenant;
+    let Some(payload) = request.receive(buffer, "transaction")? else {
+        return Ok(());
+    };
+    info!("Received transaction details from client");
+    let Some(tx_info) = request.parse::<TxInfo>(payload, "transaction")? else {
+        return Ok(());
+    };
+    logging::set_field("from", &tx_info.from);
+    logging::set_field("to", &tx_info.to);
+    logging::set_field("amount", tx_info.amount);
+    if let Some(currency) = tx_info.currency {
+        logging::set_field("currency", currency);
+    }
+    // Unknown accounts are left to fail in the transaction.
+    let from_name = account_name(bank, &tx_info.from);
+    if let Err(e) = request
+        .auth
+        .authorize_transfer(request.identity.as_ref(), &from_name)
+    {
+        logging::set_field("outcome", "unauthorized");
+        warn!("Rejected transfer from '{from_name}': {e}");
+        request.metrics.transfers_rejected("unauthorized", 1);
+        return request.refuse(e);
+    }
+    // Dry runs check the transfer itself, not whether it would wait for
+    // owners or an admin.
+    let dry_run = tx_info.dry_run;
+    if !dry_run && bank.needs_approvals(&tx_info.from) {
+        request.trace.phase(Phase::Apply);
+        let now = bank.now();
+        let owner = request
+            .identity
+            .as_ref()
+            .map(|identity| identity.name.as_str());
+        match bank.request_approval(tx_info, owner, now) {
+            Ok(approval) => {
+                logging::set_field("outcome", "pending");
+                info!(
+                    "Transfer from '{from_name}' waits for approvals as {}",
+                    approval.approval_id
+                );
+                request.respond(serde_json::to_string(&approval)?.as_bytes())?;
+            }
+            Err(e) => refuse_transfer(request, e)?,
+        }
+        return Ok(());
+    }
+    if !dry_run && bank.needs_review(&tx_info) && peers.route(&tx_info.to).is_none() {
+        request.trace.phase(Phase::Apply);
+        let requested_by = request
+            .identity
+            .as_ref()
+            .map(|identity| identity.name.as_str());
+        match bank.park_transfer(tx_info, requested_by, Checks::Pin) {
+            Ok(parked) => {
+                logging::set_field("outcome", "parked");
+                info!(
+                    "Transfer from '{from_name}' waits for review as {}",
+                    parked.review_id
+                );
+                request.respond(serde_json::to_string(&parked)?.as_bytes())?;
+            }
+            Err(e) => refuse_transfer(request, e)?,
+        }
+        return Ok(());
+    }
+    let idempotency_key = if dry_run {
+        None
+    } else {
+        tx_info.idempotency_key.clone()
+    };
+    if let Some(key) = &idempotency_key {
+        match idempotency.lookup(request.client, key, payload) {
+            Lookup::New => {}
+            Lookup::Duplicate(outcome) => {
+                info!("Not repeating transaction with idempotency key '{key}'");
+                return request.reply_transaction_outcome(outcome);
+            }
+            Lookup::Mismatch => {
+                warn!(
+                    "Rejected transaction reusing idempotency key '{key}' for a different request"
+                );
+                return request.respond("409".as_bytes());
+            }
+        }
+    }
+    request.trace.phase(Phase::Apply);
+    let outcome = match peers.route(&tx_info.to) {
+        Some((peer, account)) => {
+            let route = (peer.to_string(), account.to_string());
+            info!("Sending transfer to peer bank '{}'", route.0);
+            bank.send_to_peer(
+                peers,
+                &request.config.bank_name,
+                (&route.0, &route.1),
+                tx_info,
+            )
+        }
+        None => bank.handle_transaction(tx_info),
+    };
+    match &outcome {
+        _ if dry_run => logging::set_field("outcome", "dry_run"),
+        Ok(_) => {
+            request.metrics.transfers_accepted(1);
+            logging::set_field("outcome", "accepted");
+        }
+        Err(e) => {
+            let reason = metrics::rejection_reason(e);
+            request.metrics.transfers_rejected(reason, 1);
+            logging::set_field("outcome", reason);
+        }
+    }
+    let outcome = outcome.map_err(ErrorReply::from);
+    match &outcome {
+        Ok(receipt) if receipt.dry_run => info!("Dry run of transfer from '{from_name}' passed"),
+        Ok(receipt) => info!(
+            "Successfully performed transaction {}",
+            receipt.transaction_id
+        ),
+        Err(failure) => error!("Transaction failed: {}", failure.message),
+    }
+    request.reply_transaction_outcome(&outcome)?;
+    if let Some(key) = idempotency_key {
+        idempotency.insert(request.client, &key, payload, outcome);
+    }
+    Ok(())
+}
+
+/// Reply why a transfer could not even wait for approval or review.
+fn refuse_transfer(request: &mut Request, error: CustomError) -> Result<()> {
+    let reason = metrics::rejection_reason(&error);
+    request.metrics.transfers_rejected(reason, 1);
+    logging::set_field("outcome", reason);
+    error!("Transaction failed: {error}");
+    request.reply_transaction_outcome(&Err(ErrorReply::from(error)))
+}
+
+/// `a`: an administrative command.
+pub fn admin(request: &mut Request, bank: &mut Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(command) = request.receive_json::<AdminCommand>(buffer, "admin command")? else {
+        return Ok(());
+    };
+    info!("Received admin command from client");
+    request.trace.phase(Phase::Apply);
+    match command.execute(bank) {
+        Ok(reply) => {
+            info!("Successfully performed admin command");
+            if let Some(reply) = reply {
+                request.respond(reply.as_bytes())?;
+            }
+        }
+        Err(e) => {
+            error!("Admin command failed: {e}");
+            // Clients of version 1 get no reply.
+            if request.version >= protocol::ERROR_REPLY_VERSION {
+                request.respond_error("422", &e)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `b`: transfers made all or none, or each on its own if independent.
+pub fn batch(request: &mut Request, bank: &mut Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(batch) = request.receive_json::<BatchRequest>(buffer, "batch")? else {
+        return Ok(());
+    };
+    info!("Received batch of transactions from client");
+    let forbidden = batch.transfers.iter().find_map(|tx_info| {
+        let from_name = account_name(bank, &tx_info.from);
+        request
+            .auth
+            .authorize_transfer(request.identity.as_ref(), &from_name)
+            .err()
+            .map(|e| (from_name, e))
+    });
+    let transfers = batch.transfers.len() as u64;
+    logging::set_field("transfers", transfers);
+    if let Some((from_name, e)) = forbidden {
+        logging::set_field("outcome", "unauthorized");
+        warn!("Rejected batch with transfer from '{from_name}': {e}");
+        request
+            .metrics
+            .transfers_rejected("unauthorized", transfers);
+        return request.refuse(e);
+    }
+    request.trace.phase(Phase::Apply);
+    if batch.independent {
+        let workers = match request.config.workers {
+            0 => shards::default_workers(),
+            workers => workers,
+        };
+        let outcomes = bank.handle_independent(batch.transfers, workers);
+        let mut made = 0;
+        let results: Vec<TransferResult> = outcomes
+            .into_iter()
+            .map(|outcome| match outcome {
+                Ok(receipt) => {
+                    made += 1;
+                    TransferResult::Made(receipt)
+                }
+                Err(e) => {
+                    let reason = metrics::rejection_reason(&e);
+                    request.metrics.transfers_rejected(reason, 1);
+                    TransferResult::Failed {
+                        status: failure_status(&e),
+                        code: e.code(),
+                        error: e.to_string(),
+                    }
+                }
+            })
+            .collect();
+        request.metrics.transfers_accepted(made);
+        logging::set_field("outcome", "independent");
+        info!("Performed {made} of {transfers} independent transfers");
+        let serialized = serde_json::to_string(&IndependentBatchReceipt { results })?;
+        return request.respond(serialized.as_bytes());
+    }
+    match bank.handle_batch(batch.transfers) {
+        Ok(receipt) => {
+            logging::set_field("outcome", "accepted");
+            info!(
+                "Successfully performed batch {} of {} transfers",
+                receipt.transaction_id, receipt.transfers
+            );
+            request.metrics.transfers_accepted(transfers);
+            request.respond(serde_json::to_string(&receipt)?.as_bytes())
+        }
+        Err(e) => {
+            let reason = metrics::rejection_reason(&e);
+            logging::set_field("outcome", reason);
+            error!("Batch failed: {e}");
+            request.metrics.transfers_rejected(reason, transfers);
+            request.respond_error(failure_status(&e), &e)
+        }
+    }
+}
+
+/// `p`: holds placed, captured and cancelled.
+pub fn hold(request: &mut Request, bank: &mut Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(command) = request.receive_json::<HoldCommand>(buffer, "hold command")? else {
+        return Ok(());
+    };
+    // Captures and cancels need the same rights as the transfer that was
+    // held.
+    let from = match &command {
+        HoldCommand::Hold(tx_info) => Some(tx_info.from.clone()),
+        HoldCommand::Capture { hold_id, .. } | HoldCommand::Cancel { hold_id } => {
+            bank.hold(*hold_id).map(|hold| AccountRef::Id(hold.account))
+        }
+    };
+    if let Some(from) = from {
+        let from_name = account_name(bank, &from);
+        if let Err(e) = request
+            .auth
+            .authorize_transfer(request.identity.as_ref(), &from_name)
+        {
+            warn!("Rejected hold command on '{from_name}': {e}");
+            return request.refuse(e);
+        }
+    }
+    request.trace.phase(Phase::Apply);
+    let now = bank.now();
+    let result = match command {
+        HoldCommand::Hold(tx_info) => bank
+            .place_hold(tx_info, now)
+            .map(|receipt| serde_json::to_string(&receipt)),
+        HoldCommand::Capture { hold_id, amount } => bank
+            .capture_hold(hold_id, amount, now)
+            .map(|receipt| serde_json::to_string(&receipt)),
+        HoldCommand::Cancel { hold_id } => bank
+            .cancel_hold(hold_id, now)
+            .map(|()| Ok("200".to_string())),
+    };
+    match result {
+        Ok(response) => {
+            info!("Successfully performed hold command");
+            request.respond(response?.as_bytes())
+        }
+        Err(e @ CustomError::HoldNotFoundError(_)) => {
+            warn!("Hold command failed: {e}");
+            request.respond_error("404", &e)
+        }
+        Err(e) => {
+            error!("Hold command failed: {e}");
+            request.respond_error("422", &e)
+        }
+    }
+}
+
+/// `w`: escrows opened, released, refunded and looked at.
+pub fn escrow(request: &mut Request, bank: &mut Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(command) = request.receive_json::<EscrowCommand>(buffer, "escrow command")? else {
+        return Ok(());
+    };
+    let escrow = match &command {
+        EscrowCommand::Open { .. } => None,
+        EscrowCommand::Release { escrow_id }
+        | EscrowCommand::Refund { escrow_id }
+        | EscrowCommand::Get { escrow_id } => match bank.escrow(*escrow_id) {
+            Some(escrow) => Some(escrow.clone()),
+            None => {
+                warn!("Escrow {escrow_id} not found");
+                return request.respond("404".as_bytes());
+            }
+        },
+    };
+    let auth = &*request.auth;
+    let identity = request.identity.as_ref();
+    // Opening needs the rights of a transfer from the payer, deciding those
+    // of the arbiter, looking those of either party.
+    let authorized = match (&command, &escrow) {
+        (EscrowCommand::Open { from, .. }, _) => {
+            auth.authorize_transfer(identity, &account_name(bank, from))
+        }
+        (EscrowCommand::Get { .. }, Some(escrow)) => {
+            let party =
+                |id| auth.authorize_account(identity, &account_name(bank, &AccountRef::Id(id)));
+            party(escrow.payer)
+                .or_else(|_| party(escrow.beneficiary))
+                .or_else(|e| match &escrow.arbiter {
+                    Some(arbiter) => auth.authorize_arbiter(identity, arbiter),
+                    None => Err(e),
+                })
+        }
+        (_, Some(escrow)) => match &escrow.arbiter {
+            Some(arbiter) => auth.authorize_arbiter(identity, arbiter),
+            None => auth
+                .authorize_transfer(identity, &account_name(bank, &AccountRef::Id(escrow.payer))),
+        },
+        (_, None) => unreachable!("looked up above"),
+    };
+    if let Err(e) = authorized {
+        warn!("Rejected escrow command: {e}");
+        return request.refuse(e);
+    }
+    request.trace.phase(Phase::Apply);
+    let now = bank.now();
+    let result = match command {
+        EscrowCommand::Open {
+            from,
+            to,
+            amount,
+            currency,
+            arbiter,
+            timeout_secs,
+            on_timeout,
+            pin,
+            memo,
+        } => {
+            let tx_info = TxInfo {
+                from,
+                to,
+                amount,
+                currency,
+                convert: false,
+                pin,
+                memo,
+                external_ref: None,
+                idempotency_key: None,
+                dry_run: false,
+            };
+            bank.open_escrow(tx_info, arbiter, timeout_secs, on_timeout, now)
+                .map(|escrow| serde_json::to_string(&escrow))
+        }
+        EscrowCommand::Release { escrow_id } => bank
+            .close_escrow(escrow_id, EscrowOutcome::Release)
+            .map(|receipt| serde_json::to_string(&receipt)),
+        EscrowCommand::Refund { escrow_id } => bank
+            .close_escrow(escrow_id, EscrowOutcome::Refund)
+            .map(|receipt| serde_json::to_string(&receipt)),
+        EscrowCommand::Get { .. } => Ok(serde_json::to_string(&escrow)),
+    };
+    match result {
+        Ok(response) => {
+            info!("Successfully performed escrow command");
+            request.respond(response?.as_bytes())
+        }
+        Err(e) => {
+            error!("Escrow command failed: {e}");
+            request.respond_error("422", &e)
+        }
+    }
+}
+
+/// `o`: owners of joint accounts, and their approvals.
+pub fn owners(request: &mut Request, bank: &mut Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(command) = request.receive_json::<OwnersCommand>(buffer, "owners command")? else {
+        return Ok(());
+    };
+    // Managing owners needs the rights of a transfer from the account,
+    // looking at them those of its history. Once transfers need several
+    // owners, so would changing who they are or how many, which only
+    // admins may do then. Approvals are checked against the owners by the
+    // bank.
+    if let Some(account) = command.account() {
+        let account_name = account_name(bank, account);
+        let identity = request.identity.as_ref();
+        let authorized = match command {
+            OwnersCommand::List { .. } | OwnersCommand::Pending { .. } => {
+                request.auth.authorize_account(identity, &account_name)
+            }
+            _ if bank.needs_approvals(account) => request.auth.authorize_admin(identity),
+            _ => request.auth.authorize_transfer(identity, &account_name),
+        };
+        if let Err(e) = authorized {
+            warn!("Rejected owners command on '{account_name}': {e}");
+            return request.refuse(e);
+        }
+    }
+    request.trace.phase(Phase::Apply);
+    let owner = request
+        .identity
+        .as_ref()
+        .map(|identity| identity.name.as_str());
+    let result = match command {
+        OwnersCommand::AddOwner { account, owner } => bank
+            .add_owner(&account, owner)
+            .map(|()| Ok("200".to_string())),
+        OwnersCommand::RemoveOwner { account, owner } => bank
+            .remove_owner(&account, &owner)
+            .map(|()| Ok("200".to_string())),
+        OwnersCommand::SetApprovalsRequired { account, required } => bank
+            .set_approvals_required(&account, required)
+            .map(|()| Ok("200".to_string())),
+        OwnersCommand::List { account } => bank.account(&account).map(|account| {
+            serde_json::to_string(&OwnersInfo {
+                account: account.id,
+                owners: &account.owners,
+                approvals_required: account.approvals_required,
+            })
+        }),
+        OwnersCommand::Pending { account } => bank
+            .pending_approvals(&account)
+            .map(|requests| serde_json::to_string(&requests)),
+        OwnersCommand::Approve { approval_id } => {
+            let outcome = bank.approve_transfer(approval_id, owner);
+            if let Ok(ApprovalOutcome::Made(_)) = outcome {
+                request.metrics.transfers_accepted(1);
+            }
+            outcome.map(|outcome| serde_json::to_string(&outcome))
+        }
+        OwnersCommand::Reject { approval_id } => bank
+            .reject_transfer(approval_id, owner)
+            .map(|()| Ok("200".to_string())),
+    };
+    match result {
+        Ok(response) => {
+            info!("Successfully performed owners command");
+            request.respond(response?.as_bytes())
+        }
+        Err(e) => {
+            error!("Owners command failed: {e}");
+            let status = match e {
+                CustomError::OwnershipError(OwnershipError::ApprovalNotFound(_)) => "404",
+                _ => failure_status(&e),
+            };
+            request.respond_error(status, &e)
+        }
+    }
+}
+
+/// `l`: savings goals.
+pub fn goal(request: &mut Request, bank: &mut Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(command) = request.receive_json::<GoalCommand>(buffer, "goal command")? else {
+        return Ok(());
+    };
+    let account = match &command {
+        GoalCommand::Create { account, .. } | GoalCommand::List { account } => account.clone(),
+        GoalCommand::Get { goal_id } | GoalCommand::Delete { goal_id } => {
+            match bank.goal(*goal_id) {
+                Ok(goal) => AccountRef::Id(goal.account),
+                Err(e) => {
+                    warn!("{e}");
+                    return request.respond_error("404", &e);
+                }
+            }
+        }
+    };
+    // Changing goals needs the rights of a transfer from the account,
+    // looking at them those of its history.
+    let account_name = account_name(bank, &account);
+    let identity = request.identity.as_ref();
+    let authorized = match command {
+        GoalCommand::Get { .. } | GoalCommand::List { .. } => {
+            request.auth.authorize_account(identity, &account_name)
+        }
+        _ => request.auth.authorize_transfer(identity, &account_name),
+    };
+    if let Err(e) = authorized {
+        warn!("Rejected goal command on '{account_name}': {e}");
+        return request.refuse(e);
+    }
+    request.trace.phase(Phase::Apply);
+    let result = match command {
+        GoalCommand::Create {
+            account,
+            name,
+            target,
+            keep,
+            period_secs,
+        } => bank
+            .create_goal(&account, &name, target, keep, period_secs)
+            .map(|goal| serde_json::to_string(&bank.goal_progress(&goal))),
+        GoalCommand::Get { goal_id } => bank
+            .goal(goal_id)
+            .map(|goal| serde_json::to_string(&bank.goal_progress(goal))),
+        GoalCommand::List { account } => bank
+            .goals_of(&account)
+            .map(|goals| serde_json::to_string(&goals)),
+        GoalCommand::Delete { goal_id } => {
+            bank.delete_goal(goal_id).map(|()| Ok("200".to_string()))
+        }
+    };
+    match result {
+        Ok(response) => {
+            info!("Successfully performed goal command");
+            request.respond(response?.as_bytes())
+        }
+        Err(e) => {
+            error!("Goal command failed: {e}");
+            request.respond_error("422", &e)
+        }
+    }
+}
+
+/// `s`: scheduled transfers and standing orders.
+pub fn schedule(request: &mut Request, bank: &mut Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(command) = request.receive_json::<ScheduleCommand>(buffer, "schedule command")? else {
+        return Ok(());
+    };
+    // Every command acts on the paying account.
+    let account = match &command {
+        ScheduleCommand::Schedule { transfer, .. } => Some(transfer.from.clone()),
+        ScheduleCommand::List { account } => Some(account.clone()),
+        ScheduleCommand::Cancel { schedule_id } => bank
+            .scheduled_transfer(*schedule_id)
+            .map(|scheduled| AccountRef::Id(scheduled.account())),
+        ScheduleCommand::CreateStandingOrder { transfer, .. } => Some(transfer.from.clone()),
+        ScheduleCommand::ListStandingOrders { account } => Some(account.clone()),
+        ScheduleCommand::DeleteStandingOrder { order_id } => bank
+            .standing_order(*order_id)
+            .map(|order| AccountRef::Id(order.account())),
+    };
+    if let Some(account) = account {
+        let account_name = account_name(bank, &account);
+        if let Err(e) = request
+            .auth
+            .authorize_transfer(request.identity.as_ref(), &account_name)
+        {
+            warn!("Rejected schedule command on '{account_name}': {e}");
+            return request.refuse(e);
+        }
+    }
+    request.trace.phase(Phase::Apply);
+    let result = match command {
+        ScheduleCommand::Schedule {
+            execute_at,
+            transfer,
+        } => bank
+            .schedule_transfer(execute_at, transfer)
+            .map(|scheduled| serde_json::to_string(&scheduled)),
+        ScheduleCommand::List { account } => bank
+            .scheduled_transfers(&account)
+            .map(|scheduled| serde_json::to_string(&scheduled)),
+        ScheduleCommand::Cancel { schedule_id } => bank
+            .cancel_scheduled_transfer(schedule_id)
+            .map(|()| Ok("200".to_string())),
+        ScheduleCommand::CreateStandingOrder {
+            every,
+            starting_at,
+            if_insufficient_funds,
+            transfer,
+        } => bank
+            .create_standing_order(
+                every,
+                starting_at.unwrap_or_else(|| bank.now()),
+                if_insufficient_funds,
+                transfer,
+            )
+            .map(|order| serde_json::to_string(&order)),
+        ScheduleCommand::ListStandingOrders { account } => bank
+            .standing_orders(&account)
+            .map(|orders| serde_json::to_string(&orders)),
+        ScheduleCommand::DeleteStandingOrder { order_id } => bank
+            .delete_standing_order(order_id)
+            .map(|()| Ok("200".to_string())),
+    };
+    match result {
+        Ok(response) => {
+            info!("Successfully performed schedule command");
+            request.respond(response?.as_bytes())
+        }
+        Err(
+            e @ (CustomError::ScheduledTransferNotFoundError(_)
+            | CustomError::StandingOrderNotFoundError(_)
+            | CustomError::AccountDoesNotExistError(_)),
+        ) => {
+            warn!("Schedule command failed: {e}");
+            request.respond_error("404", &e)
+        }
+        Err(e) => {
+            error!("Schedule command failed: {e}");
+            request.respond_error("422", &e)
+        }
+    }
+}
+
+/// `r`: a transaction reversed.
+pub fn reversal(request: &mut Request, bank: &mut Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(reversal) = request.receive_json::<ReversalRequest>(buffer, "reversal")? else {
+        return Ok(());
+    };
+    request.trace.phase(Phase::Apply);
+    match bank.reverse(reversal.transaction_id) {
+        Ok(receipt) => {
+            info!(
+                "Reversed transaction {} with {}",
+                receipt.reverses, receipt.transaction_id
+            );
+            request.respond(serde_json::to_string(&receipt)?.as_bytes())
+        }
+        Err(e @ CustomError::TransactionNotFoundError(_)) => {
+            warn!("Reversal failed: {e}");
+            request.respond_error("404", &e)
+        }
+        Err(e) => {
+            error!("Reversal failed: {e}");
+            request.respond_error("422", &e)
+        }
+    }
+}
+
+/// `x`: a transfer from a peer bank, see `interbank`.
+pub fn interbank(request: &mut Request, tenant: &mut Tenant, buffer: &mut [u8]) -> Result<()> {
+    let Tenant { bank, peers, .. } = tenant;
+    let Some(transfer) = request.receive_json::<InterbankTransfer>(buffer, "interbank transfer")?
+    else {
+        return Ok(());
+    };
+    logging::set_field("peer_bank", &transfer.bank);
+    if peers.get(&transfer.bank).is_none() {
+        warn!(
+            "Rejected transfer from unknown peer bank '{}'",
+            transfer.bank
+        );
+        return request.respond("403".as_bytes());
+    }
+    request.trace.phase(Phase::Apply);
+    let outcome = bank.receive_from_peer(peers, transfer);
+    match &outcome {
+        Ok(receipt) => {
+            request.metrics.transfers_accepted(1);
+            info!(
+                "Booked transfer from peer bank as transaction {}",
+                receipt.transaction_id
+            );
+        }
+        Err(e) => {
+            request
+                .metrics
+                .transfers_rejected(metrics::rejection_reason(e), 1);
+            error!("Transfer from peer bank failed: {e}");
+        }
+    }
+    request.reply_transaction_outcome(&outcome.map_err(ErrorReply::from))
+}
+
+/// `h`: the history of an account.
+pub fn history(request: &mut Request, bank: &Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(payload) = request.receive(buffer, "history query")? else {
+        return Ok(());
+    };
+    answer_history_query(
+        request.socket,
+        request.sender,
+        request.trace,
+        request.auth,
+        request.identity.as_ref(),
+        bank,
+        payload,
+    )
+}
+
+/// `v`: a signed attestation of a balance.
+pub fn attestation(request: &mut Request, bank: &Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(query) = request.receive_json::<AttestationQuery>(buffer, "attestation request")?
+    else {
+        return Ok(());
+    };
+    let account_name = account_name(bank, &query.account);
+    if let Err(e) = request
+        .auth
+        .authorize_account(request.identity.as_ref(), &account_name)
+    {
+        warn!("Rejected attestation request for '{account_name}': {e}");
+        return request.refuse(e);
+    }
+    request.trace.phase(Phase::Apply);
+    match bank.attest(&query) {
+        Ok(attestation) => {
+            info!(
+                "Attested balance of '{account_name}' as of ledger entry {}",
+                attestation.ledger_sequence
+            );
+            request.respond(serde_json::to_string(&attestation)?.as_bytes())
+        }
+        Err(
+            e @ (CustomError::AccountDoesNotExistError(_)
+            | CustomError::LedgerEntryNotFoundError(_)),
+        ) => {
+            warn!("Attestation failed: {e}");
+            request.respond_error("404", &e)
+        }
+        Err(e) => {
+            error!("Attestation failed: {e}");
+            request.respond_error("422", &e)
+        }
+    }
+}
+
+/// `e`: the statement of an account for a period.
+pub fn statement(request: &mut Request, bank: &Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(query) = request.receive_json::<StatementQuery>(buffer, "statement request")? else {
+        return Ok(());
+    };
+    let account_name = account_name(bank, &query.account);
+    if let Err(e) = request
+        .auth
+        .authorize_account(request.identity.as_ref(), &account_name)
+    {
+        warn!("Rejected statement request for '{account_name}': {e}");
+        return request.refuse(e);
+    }
+    request.trace.phase(Phase::Apply);
+    match bank.statement(&account_name, query.from, query.to) {
+        Ok(statement) => {
+            info!(
+                "Generated statement of '{account_name}' with {} lines",
+                statement.lines.len()
+            );
+            request.respond(statement.render(query.format).as_bytes())
+        }
+        Err(e @ CustomError::AccountDoesNotExistError(_)) => {
+            warn!("Statement failed: {e}");
+            request.respond_error("404", &e)
+        }
+        Err(e @ CustomError::InvalidPeriodError(_)) => {
+            warn!("Statement failed: {e}");
+            request.respond_error("400", &e)
+        }
+        Err(e) => {
+            error!("Statement failed: {e}");
+            request.respond_error("422", &e)
+        }
+    }
+}
+
+/// `u`: subscriptions to the transfers of an account, or of all.
+pub fn subscription(request: &mut Request, tenant: &mut Tenant, buffer: &mut [u8]) -> Result<()> {
+    let Tenant {
+        bank,
+        subscriptions,
+        ..
+    } = tenant;
+    let Some(subscription) =
+        request.receive_json::<SubscriptionRequest>(buffer, "subscription request")?
+    else {
+        return Ok(());
+    };
+    let Some(path) = request.sender else {
+        warn!("Rejected subscription request from an unnamed socket");
+        return Ok(());
+    };
+    if subscription.action == SubscriptionAction::Unsubscribe {
+        request.trace.phase(Phase::Apply);
+        if subscriptions.unsubscribe(path) {
+            info!("Unsubscribed {}", path.display());
+        }
+        return request.respond("200".as_bytes());
+    }
+    let identity = request.identity.as_ref();
+    let account = match &subscription.account {
+        Some(account) => {
+            let account_name = account_name(bank, account);
+            if let Err(e) = request.auth.authorize_account(identity, &account_name) {
+                warn!("Rejected subscription to '{account_name}': {e}");
+                return request.refuse(e);
+            }
+            match bank.resolve(account) {
+                Some(id) => Some(id),
+                None => {
+                    warn!("Rejected subscription to unknown account '{account}'");
+                    return request.respond("404".as_bytes());
+                }
+            }
+        }
+        None => {
+            if let Err(e) = request.auth.authorize_all_accounts(identity) {
+                warn!("Rejected subscription to all accounts: {e}");
+                return request.refuse(e);
+            }
+            None
+        }
+    };
+    request.trace.phase(Phase::Apply);
+    subscriptions.subscribe(path.clone(), account);
+    match account {
+        Some(id) => info!("Subscribed {} to account {id}", path.display()),
+        None => info!("Subscribed {} to all accounts", path.display()),
+    }
+    request.respond("200".as_bytes())
+}
+
+/// `i`: the accounts.
+pub fn list_accounts(request: &mut Request, bank: &Bank) -> Result<()> {
+    let mut filter = AccountFilter::default();
+    let identity = request.identity.as_ref();
+    if let Err(e) = request.auth.authorize_all_accounts(identity) {
+        // Customers see their own accounts.
+        let Some(identity) = identity else {
+            warn!("Rejected account listing: {e}");
+            return request.refuse(e);
+        };
+        filter.names = Some(identity.accounts.clone());
+    }
+    request.trace.phase(Phase::Apply);
+    let format = request.header.format.unwrap_or_default();
+    let listing = bank.list_accounts(format, request.header.page(), &filter)?;
+    request.respond(listing.as_bytes())
+}
+
+/// `j`: the accounts that match a filter.
+pub fn search_accounts(request: &mut Request, bank: &Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(payload) = request.receive(buffer, "account search")? else {
+        return Ok(());
+    };
+    answer_account_search(
+        request.socket,
+        request.sender,
+        request.trace,
+        request.header,
+        bank,
+        payload,
+    )
+}
+
+/// `f`: the transactions flagged by fraud rules.
+pub fn flagged(request: &mut Request, bank: &Bank) -> Result<()> {
+    request.trace.phase(Phase::Apply);
+    let serialized = serde_json::to_string(bank.flagged_transactions())?;
+    request.respond(serialized.as_bytes())
+}
+
+/// `m`: the metrics, in the Prometheus text format.
+pub fn metrics(request: &mut Request, bank: &Bank) -> Result<()> {
+    request.trace.phase(Phase::Apply);
+    let rendered = request.metrics.render(bank);
+    request.respond(rendered.as_bytes())
+}
+
+/// `g`: statistics of the bank.
+pub fn statistics(request: &mut Request, bank: &Bank) -> Result<()> {
+    request.trace.phase(Phase::Apply);
+    let serialized = serde_json::to_string(&statistics::statistics(bank))?;
+    request.respond(serialized.as_bytes())
+}
+
+/// `y`: totals of the ledger, grouped as asked.
+pub fn aggregates(request: &mut Request, bank: &Bank, buffer: &mut [u8]) -> Result<()> {
+    let Some(query) = request.receive_json::<AggregateQuery>(buffer, "aggregate query")? else {
+        return Ok(());
+    };
+    request.trace.phase(Phase::Apply);
+    let aggregates = statistics::aggregates(bank, &query);
+    request.respond(serde_json::to_string(&aggregates)?.as_bytes())
+}
+
+/// `c`: a check of the invariants of the bank.
+pub fn consistency(request: &mut Request, bank: &Bank) -> Result<()> {
+    request.trace.phase(Phase::Apply);
+    let report = invariants::check(bank);
+    if report.consistent {
+        info!("Consistency check passed");
+    } else {
+        for violation in &report.violations {
+            error!("Consistency check failed: {violation:?}");
+        }
+    }
+    request.respond(serde_json::to_string(&report)?.as_bytes())
+}
+
+/// `k`: whether the server is healthy.
+pub fn health(request: &mut Request, bank: &Bank, started: Instant) -> Result<()> {
+    let serialized = serde_json::to_string(&health::health(bank, started.elapsed()))?;
+    request.respond(serialized.as_bytes())
+}
+
+/// `n`: the version of the server.
+pub fn version(request: &mut Request, bank: &Bank) -> Result<()> {
+    let serialized = serde_json::to_string(&health::version(bank))?;
+    request.respond(serialized.as_bytes())
+}
+
+/// Instructions of plugins, and unknown ones.
+pub fn plugin(request: &mut Request, bank: &mut Bank, buffer: &mut [u8]) -> Result<()> {
+    let instruction = request.instruction;
+    let Some(plugin) = bank.plugin_for(instruction) else {
+        warn!("Rejected unknown instruction '{instruction}'");
+        return request.respond("400".as_bytes());
+    };
+    let Some(payload) = request.receive(buffer, &format!("'{instruction}' instruction"))? else {
+        return Ok(());
+    };
+    request.trace.phase(Phase::Apply);
+    match plugin.handle(instruction, payload, bank) {
+        Ok(response) => request.respond(&response),
+        Err(e) => {
+            warn!("Plugin instruction '{instruction}' failed: {e}");
+            request.respond("422".as_bytes())
+        }
+    }
+}
+
+/// `z`: sandboxes, which come and go with the tenants they sit next to.
+pub fn sandbox(
+    request: &mut Request,
+    tenants: &mut BTreeMap<Option<String>, Tenant>,
+    transport: &Arc<dyn Transport>,
+    buffer: &mut [u8],
+) -> Result<()> {
+    let Some(command) = request.receive_json::<SandboxCommand>(buffer, "sandbox command")? else {
+        return Ok(());
+    };
+    request.trace.phase(Phase::Apply);
+    match run_sandbox_command(
+        command,
+        &request.header.tenant,
+        tenants,
+        transport,
+        request.config,
+    ) {
+        Ok(reply) => request.respond(reply.as_bytes()),
+        Err(e) => {
+            error!("Sandbox command failed: {e}");
+            request.respond(e.status().as_bytes())
+        }
+    }
+}
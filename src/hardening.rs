@@ -0,0 +1,452 @@
+//! Confinement of the server process, so that a bug in parsing a request
+//! cannot be turned into access to the rest of the host. It is on when the
+//! configuration has `hardening`, on Linux.
+//!
+//! Before anything else, `run_app` takes away access to files with
+//! Landlock, but for the data directories, the directories of the sockets
+//! (to bind them), the files the configuration names and read access to
+//! the system's libraries and settings, plus `hardening.writable_paths`
+//! and `hardening.readable_paths`. Backups and exports can only go to the
+//! former. Threads started later are confined with the server.
+//!
+//! Once every socket is bound and privileges are dropped, a seccomp filter
+//! on every thread leaves only the system calls the server makes. Others
+//! fail with `ENOSYS`, as if the kernel did not have them, so running
+//! programs, tracing other processes or changing users is no longer
+//! possible.
+//!
+//! Both are best effort: a kernel without Landlock or seccomp leaves the
+//! server as it is, with a warning.
+
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::Config;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HardeningConfig {
+    /// Directories the server may write to besides the data directories,
+    /// e.g. for backups.
+    pub writable_paths: Vec<PathBuf>,
+    /// Files and directories the server may read besides those configured.
+    pub readable_paths: Vec<PathBuf>,
+}
+
+/// Read-only for the user and host lookups, time zones and shared libraries.
+const SYSTEM_PATHS: [&str; 4] = ["/etc", "/usr", "/lib", "/lib64"];
+
+// Landlock, see linux/landlock.h.
+const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+const ACCESS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_MAKE_SOCK: u64 = 1 << 9;
+/// Every right of the first version.
+const ACCESS_V1: u64 = (1 << 13) - 1;
+const ACCESS_REFER: u64 = 1 << 13;
+const ACCESS_TRUNCATE: u64 = 1 << 14;
+const ACCESS_IOCTL_DEV: u64 = 1 << 15;
+/// The rights that apply to files rather than directories.
+const ACCESS_FILE: u64 =
+    ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE | ACCESS_TRUNCATE | ACCESS_IOCTL_DEV;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// The file rights the kernel's Landlock knows, none without it.
+fn handled_access() -> u64 {
+    // SAFETY: asking for the version takes no attributes.
+    let version = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    match version {
+        ..=0 => 0,
+        1 => ACCESS_V1,
+        2 => ACCESS_V1 | ACCESS_REFER,
+        3 | 4 => ACCESS_V1 | ACCESS_REFER | ACCESS_TRUNCATE,
+        _ => ACCESS_V1 | ACCESS_REFER | ACCESS_TRUNCATE | ACCESS_IOCTL_DEV,
+    }
+}
+
+fn check(result: libc::c_long) -> io::Result<libc::c_long> {
+    match result {
+        0.. => Ok(result),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Where a Unix socket at `path` is made and removed.
+fn socket_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// The paths the server needs and what it needs to do with them.
+fn rules(config: &Config, hardening: &HardeningConfig) -> Vec<(PathBuf, u64)> {
+    const WRITE: u64 = u64::MAX;
+    const READ: u64 = ACCESS_READ_FILE | ACCESS_READ_DIR;
+    const BIND: u64 = ACCESS_MAKE_SOCK | ACCESS_REMOVE_FILE;
+    let mut rules = Vec::new();
+    let data_dirs = config.data_dir.iter().chain(
+        config
+            .tenants
+            .values()
+            .filter_map(|tenant| tenant.data_dir.as_ref()),
+    );
+    for path in data_dirs.chain(&hardening.writable_paths) {
+        rules.push((path.clone(), WRITE));
+    }
+    let mut sockets = vec![config.socket_path.clone()];
+    sockets.extend(config.replica_socket_path.clone());
+    #[cfg(feature = "replication")]
+    {
+        if let Some(cluster) = &config.cluster {
+            sockets.extend(cluster.nodes.get(&cluster.node).cloned());
+        }
+        if let Some(standby) = &config.standby {
+            sockets.push(standby.socket_path.clone());
+        }
+    }
+    for socket in sockets {
+        rules.push((socket_dir(&socket), BIND));
+    }
+    let mut readable: Vec<&Path> = config.source().into_iter().collect();
+    readable.extend(config.policy_script.as_deref());
+    readable.extend(config.accounts_file.as_deref());
+    readable.extend(
+        config
+            .token_auth
+            .as_ref()
+            .and_then(|token_auth| token_auth.token_file.as_deref()),
+    );
+    readable.extend(
+        config
+            .tenants
+            .values()
+            .filter_map(|tenant| tenant.accounts_file.as_deref()),
+    );
+    readable.extend(SYSTEM_PATHS.iter().map(Path::new));
+    readable.extend(hardening.readable_paths.iter().map(PathBuf::as_path));
+    for path in readable {
+        rules.push((path.to_owned(), READ));
+    }
+    rules
+}
+
+/// Take away access to the files the server does not need, for the calling
+/// thread and the threads it starts from now on.
+pub fn confine_files(config: &Config) -> io::Result<()> {
+    let Some(hardening) = &config.hardening else {
+        return Ok(());
+    };
+    let handled = handled_access();
+    if handled == 0 {
+        warn!("Not confined to its files, the kernel does not support Landlock");
+        return Ok(());
+    }
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    // SAFETY: the attributes are valid for the size given.
+    let ruleset = check(unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    })?;
+    // SAFETY: the kernel returned a new file descriptor, ours to close.
+    let ruleset = unsafe { OwnedFd::from_raw_fd(ruleset as i32) };
+    for (path, access) in rules(config, hardening) {
+        // Data directories are made if they do not exist yet, anything
+        // else missing is not needed.
+        if access == u64::MAX {
+            fs::create_dir_all(&path)?;
+        }
+        let file = match fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let mut allowed = access & handled;
+        if !File::metadata(&file)?.is_dir() {
+            allowed &= ACCESS_FILE;
+        }
+        let rule = PathBeneathAttr {
+            allowed_access: allowed,
+            parent_fd: file.as_raw_fd(),
+        };
+        // SAFETY: the rule is valid for the call and names an open file.
+        check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &rule,
+                0,
+            )
+        })
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+    }
+    no_new_privileges()?;
+    // SAFETY: plain system call on our own ruleset.
+    check(unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) })?;
+    info!("Confined the server to the files it needs");
+    Ok(())
+}
+
+/// Needed to confine a process without being root, and a good idea anyway.
+fn no_new_privileges() -> io::Result<()> {
+    // SAFETY: plain system call.
+    check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) }.into()).map(drop)
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// System calls the server makes once it is set up.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    // Files.
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_lseek,
+    libc::SYS_fcntl,
+    libc::SYS_fchmod,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_ftruncate,
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+    libc::SYS_unlinkat,
+    libc::SYS_mkdirat,
+    libc::SYS_getdents64,
+    libc::SYS_getcwd,
+    libc::SYS_copy_file_range,
+    libc::SYS_sendfile,
+    // Sockets.
+    libc::SYS_socket,
+    libc::SYS_connect,
+    libc::SYS_accept4,
+    libc::SYS_sendto,
+    libc::SYS_recvfrom,
+    libc::SYS_sendmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_shutdown,
+    libc::SYS_ppoll,
+    // Memory, threads and time.
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_brk,
+    libc::SYS_futex,
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clock_gettime,
+    libc::SYS_gettimeofday,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    // Signals and the process.
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_restart_syscall,
+    libc::SYS_tgkill,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_prctl,
+    libc::SYS_getrandom,
+    libc::SYS_uname,
+];
+
+/// System calls of x86_64 only, made by older C libraries for the same.
+#[cfg(target_arch = "x86_64")]
+const ALLOWED_LEGACY_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_open,
+    libc::SYS_stat,
+    libc::SYS_lstat,
+    libc::SYS_rename,
+    libc::SYS_unlink,
+    libc::SYS_mkdir,
+    libc::SYS_poll,
+];
+#[cfg(target_arch = "aarch64")]
+const ALLOWED_LEGACY_SYSCALLS: &[libc::c_long] = &[];
+
+/// The filter: anything but the allowed system calls of this architecture
+/// fails with `ENOSYS`.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn syscall_filter() -> Vec<libc::sock_filter> {
+    let statement = |code: u32, k: u32| libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jump = |k: u32, jt: u8, jf: u8| libc::sock_filter {
+        code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    };
+    let load = |offset: u32| statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset);
+    let ret = |action: u32| statement(libc::BPF_RET | libc::BPF_K, action);
+    let refuse = libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32;
+
+    // seccomp_data starts with the number, then the architecture.
+    let mut filter = vec![
+        load(4),
+        jump(AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(0),
+    ];
+    // The x32 ABI on x86_64 numbers its calls from 2^30.
+    if cfg!(target_arch = "x86_64") {
+        filter.push(libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16,
+            jt: 0,
+            jf: 1,
+            k: 0x4000_0000,
+        });
+        filter.push(ret(refuse));
+    }
+    for &syscall in ALLOWED_SYSCALLS.iter().chain(ALLOWED_LEGACY_SYSCALLS) {
+        filter.push(jump(syscall as u32, 0, 1));
+        filter.push(ret(libc::SECCOMP_RET_ALLOW));
+    }
+    filter.push(ret(refuse));
+    filter
+}
+
+/// Leave every thread only the system calls the server makes.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub fn confine_syscalls(config: &Config) -> io::Result<()> {
+    if config.hardening.is_none() {
+        return Ok(());
+    }
+    let filter = syscall_filter();
+    let program = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut libc::sock_filter,
+    };
+    no_new_privileges()?;
+    // SAFETY: the program points to `filter`, which outlives the call.
+    let installed = check(unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &program,
+        )
+    });
+    match installed {
+        Ok(0) => info!("Confined the server to the system calls it makes"),
+        Ok(thread) => {
+            return Err(io::Error::other(format!(
+                "thread {thread} could not take the system call filter"
+            )))
+        }
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            warn!("Not confined to its system calls, the kernel does not support seccomp filters")
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub fn confine_syscalls(config: &Config) -> io::Result<()> {
+    if config.hardening.is_some() {
+        warn!("Not confined to its system calls, not supported on this architecture");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_server_keeps_its_data_sockets_and_configured_files() {
+        let mut config = Config::default();
+        config.socket_path = PathBuf::from("/run/bank/server.sock");
+        config.data_dir = Some(PathBuf::from("/var/lib/bank"));
+        config.policy_script = Some(PathBuf::from("/etc/bank/policy.rhai"));
+        let hardening = HardeningConfig {
+            writable_paths: vec![PathBuf::from("/srv/backups")],
+            readable_paths: Vec::new(),
+        };
+        let rules = rules(&config, &hardening);
+        let access = |path: &str| {
+            rules
+                .iter()
+                .filter(|(rule, _)| rule == Path::new(path))
+                .fold(0, |access, (_, allowed)| access | allowed)
+        };
+        assert_eq!(access("/var/lib/bank"), u64::MAX);
+        assert_eq!(access("/srv/backups"), u64::MAX);
+        assert_eq!(access("/run/bank"), ACCESS_MAKE_SOCK | ACCESS_REMOVE_FILE);
+        assert_eq!(access("/etc/bank/policy.rhai") & ACCESS_WRITE_FILE, 0);
+        assert_ne!(access("/etc/bank/policy.rhai") & ACCESS_READ_FILE, 0);
+        assert_eq!(access("/home"), 0);
+    }
+}
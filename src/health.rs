@@ -0,0 +1,133 @@
+//! Replies of the `k` (health) and `n` (version) instructions, for
+//! monitoring tools and for clients to check that the server is up and
+//! speaks their protocol before sending transactions.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{protocol, Bank};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    /// Serving requests, but changes may not survive a restart.
+    Degraded,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    pub status: Status,
+    pub uptime_secs: u64,
+    pub persistence: Persistence,
+    pub queues: QueueDepth,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Persistence {
+    /// Whether the bank is kept in a data directory.
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<PathBuf>,
+    /// Sequence number of the last event.
+    pub last_event: u64,
+    /// Events a restart would replay on top of the snapshot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub events_since_snapshot: Option<u64>,
+    /// Last failure to write to the data directory, until a snapshot
+    /// succeeds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Work the bank is holding for later.
+#[derive(Debug, Serialize)]
+pub struct QueueDepth {
+    pub scheduled_transfers: usize,
+    pub standing_orders: usize,
+    pub holds: usize,
+    pub flagged_transactions: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    pub protocol: u32,
+    /// Oldest protocol version still served, see `protocol`.
+    pub min_protocol: u32,
+    pub instructions: Vec<&'static str>,
+    /// Instructions handled by plugins.
+    pub plugin_instructions: Vec<String>,
+    /// Optional features the server was built with.
+    pub features: Vec<&'static str>,
+}
+
+pub fn health(bank: &Bank, uptime: Duration) -> Health {
+    let persistence = Persistence {
+        enabled: bank.store.is_some(),
+        data_dir: bank.store.as_ref().map(|store| store.dir().to_owned()),
+        last_event: bank.events.last_sequence(),
+        events_since_snapshot: bank
+            .store
+            .as_ref()
+            .map(|store| store.events_since_snapshot()),
+        error: bank.persistence_error.clone(),
+    };
+    Health {
+        status: match persistence.error {
+            Some(_) => Status::Degraded,
+            None => Status::Ok,
+        },
+        uptime_secs: uptime.as_secs(),
+        persistence,
+        queues: QueueDepth {
+            scheduled_transfers: bank.scheduler.pending_count(),
+            standing_orders: bank.scheduler.standing_order_count(),
+            holds: bank.holds.iter().count(),
+            flagged_transactions: bank.flagged.len(),
+        },
+    }
+}
+
+pub fn version(bank: &Bank) -> VersionInfo {
+    let mut plugin_instructions: Vec<String> = bank.plugin_instructions.keys().cloned().collect();
+    plugin_instructions.sort();
+    let features = [
+        ("scripting", cfg!(feature = "scripting")),
+        ("otlp", cfg!(feature = "otlp")),
+        ("parquet", cfg!(feature = "parquet")),
+    ];
+    VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        protocol: protocol::VERSION,
+        min_protocol: protocol::MIN_VERSION,
+        instructions: protocol::BUILTIN_INSTRUCTIONS.to_vec(),
+        plugin_instructions,
+        features: features
+            .into_iter()
+            .filter_map(|(feature, enabled)| enabled.then_some(feature))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_writes_degrade_health() {
+        let mut bank = Bank::new();
+        let health = health(&bank, Duration::from_secs(90));
+        assert!(matches!(health.status, Status::Ok));
+        assert_eq!(health.uptime_secs, 90);
+        assert!(!health.persistence.enabled);
+
+        bank.persistence_error = Some("Unable to store event 3".to_string());
+        assert!(matches!(
+            super::health(&bank, Duration::ZERO).status,
+            Status::Degraded
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+//! Two-phase transfers: a hold reserves funds on the paying account, and a
+//! later capture moves them or a cancel releases them. Holds that are
+//! neither captured nor cancelled in time expire and release their funds.
+//!
+//! Sent as the JSON payload of the `p` instruction, e.g.
+//! `{"op": "hold", "from": "patko", "to": "siska", "amount": "20"}`, then
+//! `{"op": "capture", "hold_id": 1}`.
+
+use std::fmt::{self, Display};
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{AccountId, Amount, TxInfo};
+
+pub const DEFAULT_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct HoldId(u64);
+
+impl Display for HoldId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "hold{}", self.0)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum HoldCommand {
+    /// Reserve the transfer's amount on the `from` account.
+    Hold(TxInfo),
+    /// Transfer the held funds, or part of them; the rest is released.
+    Capture {
+        hold_id: HoldId,
+        #[serde(default)]
+        amount: Option<Amount>,
+    },
+    Cancel {
+        hold_id: HoldId,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Hold {
+    pub id: HoldId,
+    /// The transfer to make on capture, with both accounts given by ID and
+    /// the PIN, already checked, removed.
+    pub transfer: TxInfo,
+    pub account: AccountId,
+    pub amount: Amount,
+    /// Seconds since the Unix epoch.
+    pub expires_at: u64,
+}
+
+/// Reply to an accepted hold.
+#[derive(Debug, Serialize)]
+pub struct HoldReceipt {
+    pub hold_id: HoldId,
+    pub amount: Amount,
+    pub expires_at: u64,
+}
+
+#[derive(Debug)]
+pub struct Holds {
+    holds: HashMap<HoldId, Hold>,
+    last_id: u64,
+    ttl_secs: u64,
+}
+
+impl Default for Holds {
+    fn default() -> Self {
+        Holds {
+            holds: HashMap::new(),
+            last_id: 0,
+            ttl_secs: DEFAULT_TTL_SECS,
+        }
+    }
+}
+
+impl Holds {
+    /// Lifetime of holds placed from now on.
+    pub fn set_ttl(&mut self, ttl_secs: u64) {
+        self.ttl_secs = ttl_secs;
+    }
+
+    /// A new hold, which takes effect once inserted.
+    pub fn create(&self, account: AccountId, transfer: TxInfo, now: u64) -> Hold {
+        Hold {
+            id: HoldId(self.last_id + 1),
+            account,
+            amount: transfer.amount,
+            transfer,
+            expires_at: now.saturating_add(self.ttl_secs),
+        }
+    }
+
+    pub fn insert(&mut self, hold: Hold) {
+        self.last_id = self.last_id.max(hold.id.0);
+        self.holds.insert(hold.id, hold);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Hold> {
+        self.holds.values()
+    }
+
+    pub fn get(&self, id: HoldId) -> Option<&Hold> {
+        self.holds.get(&id)
+    }
+
+    pub fn remove(&mut self, id: HoldId) -> Option<Hold> {
+        self.holds.remove(&id)
+    }
+
+    /// The holds that expired by `now`.
+    pub fn expired(&self, now: u64) -> Vec<HoldId> {
+        let mut expired: Vec<HoldId> = self
+            .holds
+            .values()
+            .filter(|hold| hold.expires_at <= now)
+            .map(|hold| hold.id)
+            .collect();
+        expired.sort();
+        expired
+    }
+}
@@ -0,0 +1,105 @@
+//! Callbacks that library users attach to a `Bank` to add business logic to
+//! transfers: hooks run before a transfer is validated and can veto it, and
+//! hooks run after a transfer is committed, e.g. to notify or audit.
+//!
+//! Hooks see every transfer, however it was requested: single, in a batch,
+//! captured from a hold or made by the scheduler.
+
+use std::fmt::{self, Debug};
+
+use serde::Serialize;
+
+use crate::ledger::TransactionId;
+use crate::money::{Currency, Money};
+use crate::AccountId;
+
+/// A transfer as requested, before anything about it was checked.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferRequest {
+    /// Account name, or the reference as given if there is no such account.
+    pub from: String,
+    pub to: String,
+    pub amount: Money,
+    pub currency: Option<Currency>,
+    pub memo: Option<String>,
+    pub external_ref: Option<String>,
+}
+
+/// A transfer that was made.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommittedTransfer {
+    /// Shared by all transfers of a batch.
+    pub transaction_id: TransactionId,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub from: AccountId,
+    pub to: AccountId,
+    pub amount: Money,
+    pub currency: Currency,
+    pub credited_amount: Money,
+    pub credited_currency: Currency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee: Option<Money>,
+}
+
+/// A committed transfer as announced to clients and external systems, e.g.
+/// `{"event":"transfer_committed","transaction_id":7,...}`.
+#[derive(Debug, Serialize)]
+pub struct TransferEvent<'a> {
+    event: &'static str,
+    #[serde(flatten)]
+    transfer: &'a CommittedTransfer,
+}
+
+impl TransferEvent<'_> {
+    pub fn committed(transfer: &CommittedTransfer) -> TransferEvent<'_> {
+        TransferEvent {
+            event: "transfer_committed",
+            transfer,
+        }
+    }
+}
+
+/// Returns the reason for vetoing the transfer, which fails with it.
+pub type PreTransactionHook = Box<dyn Fn(&TransferRequest) -> Result<(), String> + Send + Sync>;
+
+pub type PostCommitHook = Box<dyn Fn(&CommittedTransfer) + Send + Sync>;
+
+#[derive(Default)]
+pub struct Hooks {
+    pre_transaction: Vec<PreTransactionHook>,
+    post_commit: Vec<PostCommitHook>,
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("pre_transaction", &self.pre_transaction.len())
+            .field("post_commit", &self.post_commit.len())
+            .finish()
+    }
+}
+
+impl Hooks {
+    pub fn add_pre_transaction(&mut self, hook: PreTransactionHook) {
+        self.pre_transaction.push(hook);
+    }
+
+    pub fn add_post_commit(&mut self, hook: PostCommitHook) {
+        self.post_commit.push(hook);
+    }
+
+    /// Run the pre-transaction hooks in the order they were added, stopping
+    /// at the first veto.
+    pub fn check(&self, request: &TransferRequest) -> Result<(), String> {
+        self.pre_transaction
+            .iter()
+            .try_for_each(|hook| hook(request))
+    }
+
+    pub fn notify(&self, transfer: &CommittedTransfer) {
+        for hook in &self.post_commit {
+            hook(transfer);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Just enough of an HTTP/1.1 client to post JSON to a local service, for
+//! trace export and webhooks. Only plain `http://` URLs are supported, put a
+//! local proxy in front of anything else.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where requests are posted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    /// `host:port`.
+    pub address: String,
+    pub host: String,
+    pub path: String,
+}
+
+impl Endpoint {
+    /// Parse a URL like `http://localhost:8080/hooks`, the port defaulting
+    /// to 80 and the path to `/`.
+    pub fn parse(url: &str) -> Option<Endpoint> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return None;
+        }
+        let address = match authority.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => authority.to_string(),
+            Some(_) => return None,
+            None => format!("{authority}:80"),
+        };
+        Some(Endpoint {
+            address,
+            host: authority.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Post `body` as JSON, failing unless the reply has a 2xx status.
+pub fn post_json(endpoint: &Endpoint, body: &[u8]) -> io::Result<()> {
+    let mut stream = TcpStream::connect(&endpoint.address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        endpoint.path,
+        endpoint.host,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let status_line = response.split(|&byte| byte == b'\r').next().unwrap_or(&[]);
+    let status_line = String::from_utf8_lossy(status_line);
+    match status_line.split(' ').nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "{} replied '{status_line}'",
+            endpoint.host
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_are_parsed() {
+        assert_eq!(
+            Endpoint::parse("http://localhost:4318/v1/traces"),
+            Some(Endpoint {
+                address: "localhost:4318".to_string(),
+                host: "localhost:4318".to_string(),
+                path: "/v1/traces".to_string(),
+            })
+        );
+        let endpoint = Endpoint::parse("http://hooks").unwrap();
+        assert_eq!(endpoint.address, "hooks:80");
+        assert_eq!(endpoint.path, "/");
+        assert_eq!(Endpoint::parse("https://hooks:443"), None);
+        assert_eq!(Endpoint::parse("http://hooks:port"), None);
+        assert_eq!(Endpoint::parse("http:///path"), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! Outcomes of recent transfers by idempotency key.
+//!
+//! Datagrams can get lost, so clients retry. A transfer carrying the same
+//! key as an earlier one from the same client is not applied again; the
+//! earlier outcome stands instead. Only the most recent keys are kept.
+
+use std::collections::VecDeque;
+
+use hashbrown::HashMap;
+
+use crate::crypto::{self, Digest};
+
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Client and the key it chose.
+type CacheKey = (String, String);
+
+#[derive(Debug)]
+struct Entry<T> {
+    request_digest: Digest,
+    outcome: T,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Lookup<'a, T> {
+    /// Key not seen recently, go ahead.
+    New,
+    /// Same key and same request as before.
+    Duplicate(&'a T),
+    /// Same key but a different request.
+    Mismatch,
+}
+
+#[derive(Debug)]
+pub struct IdempotencyCache<T> {
+    capacity: usize,
+    entries: HashMap<CacheKey, Entry<T>>,
+    /// Insertion order, oldest first, for eviction.
+    order: VecDeque<CacheKey>,
+}
+
+impl<T> IdempotencyCache<T> {
+    pub fn new(capacity: usize) -> IdempotencyCache<T> {
+        IdempotencyCache {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn lookup(&self, client: &str, key: &str, request: &[u8]) -> Lookup<'_, T> {
+        match self.entries.get(&(client.to_string(), key.to_string())) {
+            None => Lookup::New,
+            Some(entry) if entry.request_digest == crypto::sha256(request) => {
+                Lookup::Duplicate(&entry.outcome)
+            }
+            Some(_) => Lookup::Mismatch,
+        }
+    }
+
+    pub fn insert(&mut self, client: &str, key: &str, request: &[u8], outcome: T) {
+        let cache_key = (client.to_string(), key.to_string());
+        let entry = Entry {
+            request_digest: crypto::sha256(request),
+            outcome,
+        };
+        if self.entries.insert(cache_key.clone(), entry).is_none() {
+            self.order.push_back(cache_key);
+        }
+        while self.entries.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_returns_original_outcome() {
+        let mut cache = IdempotencyCache::new(2);
+        assert_eq!(cache.lookup("c", "k", b"req"), Lookup::New);
+        cache.insert("c", "k", b"req", 1);
+        assert_eq!(cache.lookup("c", "k", b"req"), Lookup::Duplicate(&1));
+        assert_eq!(cache.lookup("c", "k", b"other"), Lookup::Mismatch);
+        assert_eq!(cache.lookup("d", "k", b"req"), Lookup::New);
+    }
+
+    #[test]
+    fn oldest_keys_are_evicted() {
+        let mut cache = IdempotencyCache::new(2);
+        cache.insert("c", "1", b"", 1);
+        cache.insert("c", "2", b"", 2);
+        cache.insert("c", "3", b"", 3);
+        assert_eq!(cache.lookup("c", "1", b""), Lookup::New);
+        assert_eq!(cache.lookup("c", "3", b""), Lookup::Duplicate(&3));
+    }
+}
@@ -0,0 +1,195 @@
+//! Opening accounts in bulk from a file, at startup with `accounts_file` or
+//! with the `import_accounts` admin command.
+//!
+//! A JSON file holds an array of objects, a CSV file a header row naming the
+//! columns and a row per account. Either way accounts have these fields,
+//! all but `name` optional:
+//!
+//! - `name`
+//! - `balance`: opening balance, zero by default.
+//! - `currency`: EUR by default.
+//! - `display_name`, `email`
+//! - `tags`: an array in JSON, separated by `;` in CSV.
+//!
+//! Rows that are invalid or name an existing account are rejected and
+//! reported, the others are imported.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::csv::{self, UnclosedQuoteError};
+use crate::money::{Currency, Money};
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("Unable to read accounts file {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Accounts file {} is not a JSON array", path.display())]
+    Json {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Invalid CSV in accounts file {}", path.display())]
+    Csv {
+        path: PathBuf,
+        #[source]
+        source: UnclosedQuoteError,
+    },
+    #[error("Accounts file {} is neither .csv nor .json", path.display())]
+    Format { path: PathBuf },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountRecord {
+    pub name: String,
+    #[serde(default)]
+    pub balance: Money,
+    #[serde(default)]
+    pub currency: Currency,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+}
+
+/// A row of the file, counting from 1: the line it starts on in CSV, its
+/// position in the array in JSON.
+pub type Row = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RejectedRow {
+    pub row: Row,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub reason: String,
+}
+
+/// Outcome of an import.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub rejected: Vec<RejectedRow>,
+}
+
+impl ImportReport {
+    pub fn reject(&mut self, row: Row, name: Option<String>, reason: impl ToString) {
+        self.rejected.push(RejectedRow {
+            row,
+            name,
+            reason: reason.to_string(),
+        });
+    }
+}
+
+/// Read the accounts in the file at `path`, which must end in `.csv` or
+/// `.json`. Rows that cannot be read are added to `report`.
+pub fn read(
+    path: &Path,
+    report: &mut ImportReport,
+) -> Result<Vec<(Row, AccountRecord)>, ImportError> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let is_csv = match extension.map(str::to_ascii_lowercase).as_deref() {
+        Some("csv") => true,
+        Some("json") => false,
+        _ => {
+            return Err(ImportError::Format {
+                path: path.to_owned(),
+            })
+        }
+    };
+    let text = fs::read_to_string(path).map_err(|source| ImportError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    let rows = if is_csv {
+        csv_rows(&text).map_err(|source| ImportError::Csv {
+            path: path.to_owned(),
+            source,
+        })?
+    } else {
+        let rows: Vec<Value> = serde_json::from_str(&text).map_err(|source| ImportError::Json {
+            path: path.to_owned(),
+            source,
+        })?;
+        (1..).zip(rows).collect()
+    };
+    let mut records = Vec::new();
+    for (row, value) in rows {
+        let name = value["name"].as_str().map(str::to_string);
+        match serde_json::from_value::<AccountRecord>(value) {
+            Ok(record) if record.name.trim().is_empty() => {
+                report.reject(row, None, "The name is empty")
+            }
+            Ok(record) => records.push((row, record)),
+            Err(e) => report.reject(row, name, e),
+        }
+    }
+    Ok(records)
+}
+
+/// Rows of CSV as JSON objects keyed by the header, leaving out empty
+/// fields so they take their defaults.
+fn csv_rows(text: &str) -> Result<Vec<(Row, Value)>, UnclosedQuoteError> {
+    let mut records = csv::parse(text)?.into_iter();
+    let Some((_, header)) = records.next() else {
+        return Ok(Vec::new());
+    };
+    let header: Vec<String> = header
+        .iter()
+        .map(|column| column.trim().to_string())
+        .collect();
+    Ok(records
+        .map(|(line, fields)| {
+            let mut object = Map::new();
+            for (column, field) in header.iter().zip(fields) {
+                if field.is_empty() {
+                    continue;
+                }
+                let value = match column.as_str() {
+                    "tags" => field
+                        .split(';')
+                        .map(str::trim)
+                        .filter(|tag| !tag.is_empty())
+                        .map(Value::from)
+                        .collect(),
+                    _ => Value::String(field),
+                };
+                object.insert(column.clone(), value);
+            }
+            (line, Value::Object(object))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows_take_defaults_for_empty_fields() {
+        let text = "name,balance,currency,tags\njozko,12.50,,vip; staff\n,1,EUR,\n";
+        let rows = csv_rows(text).unwrap();
+        assert_eq!(rows.len(), 2);
+        let record: AccountRecord = serde_json::from_value(rows[0].1.clone()).unwrap();
+        assert_eq!(record.name, "jozko");
+        assert_eq!(record.balance, Money::from_minor(1_250));
+        assert_eq!(record.currency, Currency::EUR);
+        assert_eq!(record.tags.len(), 2);
+        assert_eq!(rows[1].0, 3);
+        assert!(serde_json::from_value::<AccountRecord>(rows[1].1.clone()).is_err());
+    }
+}
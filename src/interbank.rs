@@ -0,0 +1,290 @@
+//! Transfers between bank servers. A transfer to `otherbank:alice`, where
+//! `otherbank` is one of the configured `peer_banks`, is booked to the
+//! settlement account kept for that bank and forwarded to its server with
+//! the `x` instruction, which books it from the settlement account kept there
+//! for this bank to `alice`. If the peer rejects the transfer, or the
+//! transfer could not be sent to it, it is reversed here.
+//!
+//! A peer that took the transfer but did not answer in time may have booked
+//! it, so it is neither reversed nor taken as booked. It is kept as
+//! unconfirmed and sent again every `RETRY_SECS` until the peer answers.
+//! The peer books a transfer only once by its `reference` and answers a
+//! repeated one with the receipt of the booking.
+//!
+//! Every transfer is sent from a reply socket of its own, so that a reply
+//! arriving late is never taken for the reply to another transfer.
+//!
+//! A settlement account's balance is what this bank owes the peer, or when
+//! negative what the peer owes it. How far the peer may run into debt is its
+//! `credit_limit`, the overdraft limit of the account.
+//!
+//! Both servers must list each other under the name the other one sends as
+//! `bank_name`, and the `x` instruction needs the admin role where roles are
+//! configured, so a peer presents its `token`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::ledger::TransactionId;
+use crate::money::{Currency, Money};
+use crate::AccountRef;
+
+/// How long to wait for each reply of a peer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often unconfirmed transfers are sent again.
+pub const RETRY_SECS: u64 = 60;
+
+#[derive(Error, Debug)]
+pub enum InterbankError {
+    #[error("Unable to reach peer bank '{peer}'")]
+    Io {
+        peer: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Peer bank '{peer}' refused the instruction with status {status}")]
+    Refused { peer: String, status: String },
+    #[error("Peer bank '{peer}' rejected the transfer with status {status}")]
+    Rejected { peer: String, status: String },
+    #[error("Unknown peer bank '{0}'")]
+    UnknownPeer(String),
+    /// The transfer was sent but not answered. It stays booked until the
+    /// peer confirms or rejects it.
+    #[error("Peer bank '{peer}' has not confirmed transaction {reference} yet")]
+    Unconfirmed {
+        peer: String,
+        reference: TransactionId,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{original}, and reversing transaction {transaction_id} failed: {reversal}")]
+    NotReversed {
+        transaction_id: TransactionId,
+        original: String,
+        reversal: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerBankConfig {
+    /// Socket the peer's server listens on.
+    pub socket_path: PathBuf,
+    /// Account kept for the peer, opened on startup if missing.
+    pub settlement_account: String,
+    #[serde(default)]
+    pub currency: Currency,
+    /// How much the peer may owe this bank, unlimited by default.
+    #[serde(default)]
+    pub credit_limit: Option<Money>,
+    /// Token presented to the peer.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// A transfer as forwarded to the receiving bank.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterbankTransfer {
+    /// `bank_name` of the sending bank.
+    pub bank: String,
+    /// Sending account, at the sending bank.
+    pub from: String,
+    /// Receiving account, at the receiving bank.
+    pub to: String,
+    pub amount: Money,
+    pub currency: Currency,
+    /// Transaction at the sending bank.
+    pub reference: TransactionId,
+    #[serde(default)]
+    pub memo: Option<String>,
+}
+
+/// A transfer sent to `peer` that it has not answered, see `Peers::forward`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UnconfirmedTransfer {
+    pub peer: String,
+    pub transfer: InterbankTransfer,
+}
+
+impl InterbankTransfer {
+    /// Kept as the external reference of the transfer at the receiving bank.
+    pub fn external_ref(&self) -> String {
+        format!("{}:{}", self.bank, self.reference)
+    }
+}
+
+/// The configured peers and where their replies come to.
+#[derive(Debug, Default)]
+pub struct Peers {
+    configs: BTreeMap<String, PeerBankConfig>,
+    /// Reply sockets are bound here, with the number of the transfer
+    /// appended.
+    reply_path: PathBuf,
+    sent: AtomicU64,
+    /// How long to wait for each reply, `TIMEOUT` unless set.
+    timeout: Option<Duration>,
+    /// When unconfirmed transfers are next sent again.
+    next_retry_at: u64,
+}
+
+impl Peers {
+    pub fn new(configs: BTreeMap<String, PeerBankConfig>, reply_path: &Path) -> Peers {
+        Peers {
+            configs,
+            reply_path: reply_path.to_path_buf(),
+            sent: AtomicU64::new(0),
+            timeout: None,
+            next_retry_at: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_timeout(self, timeout: Duration) -> Peers {
+        Peers {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Whether unconfirmed transfers are due to be sent again at `now`,
+    /// and if so, when they are next.
+    pub fn retry_due(&mut self, now: u64) -> bool {
+        if self.configs.is_empty() || now < self.next_retry_at {
+            return false;
+        }
+        self.next_retry_at = now + RETRY_SECS;
+        true
+    }
+
+    pub fn get(&self, peer: &str) -> Option<&PeerBankConfig> {
+        self.configs.get(peer)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &PeerBankConfig)> {
+        self.configs
+            .iter()
+            .map(|(name, config)| (name.as_str(), config))
+    }
+
+    /// The peer and its account if `account` is at another bank.
+    pub fn route<'a>(&self, account: &'a AccountRef) -> Option<(&str, &'a str)> {
+        let AccountRef::Name(name) = account else {
+            return None;
+        };
+        let (peer, remote) = name.split_once(':')?;
+        let (peer, _) = self.configs.get_key_value(peer)?;
+        Some((peer, remote))
+    }
+
+    /// Send `transfer` to `peer` and wait for it to be booked there. Any
+    /// error but `Unconfirmed` means the peer did not book it this time,
+    /// and `Rejected` that it did not before either.
+    pub fn forward(&self, peer: &str, transfer: &InterbankTransfer) -> Result<(), InterbankError> {
+        let config = self
+            .get(peer)
+            .ok_or_else(|| InterbankError::UnknownPeer(peer.to_string()))?;
+        let io_error = |source| InterbankError::Io {
+            peer: peer.to_string(),
+            source,
+        };
+        let mut reply_path = self.reply_path.clone().into_os_string();
+        reply_path.push(format!(".{}", self.sent.fetch_add(1, Ordering::Relaxed)));
+        let timeout = self.timeout.unwrap_or(TIMEOUT);
+        let socket = ReplySocket::bind(reply_path.into(), timeout).map_err(io_error)?;
+        let mut instruction = b"x".to_vec();
+        if let Some(token) = &config.token {
+            instruction.extend(json!({ "token": token }).to_string().bytes());
+        }
+        let payload = serde_json::to_vec(transfer).expect("transfers serialize");
+        let mut reply = vec![0; 512];
+        let mut receive = || -> io::Result<String> {
+            let length = socket.socket.recv(&mut reply)?;
+            Ok(String::from_utf8_lossy(&reply[..length]).into_owned())
+        };
+        let send = |message: &[u8]| socket.socket.send_to(message, &config.socket_path);
+        send(&instruction).map_err(io_error)?;
+        let status = receive().map_err(io_error)?;
+        if status != "200" {
+            return Err(InterbankError::Refused {
+                peer: peer.to_string(),
+                status,
+            });
+        }
+        send(&payload).map_err(io_error)?;
+        // From here on the peer may have booked the transfer.
+        let outcome = receive().map_err(|source| InterbankError::Unconfirmed {
+            peer: peer.to_string(),
+            reference: transfer.reference,
+            source,
+        })?;
+        // A receipt on success, a status otherwise.
+        if !outcome.starts_with('{') {
+            return Err(InterbankError::Rejected {
+                peer: peer.to_string(),
+                status: outcome,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A socket for the replies to one transfer, removed when dropped.
+struct ReplySocket {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl ReplySocket {
+    fn bind(path: PathBuf, timeout: Duration) -> io::Result<ReplySocket> {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        let socket = UnixDatagram::bind(&path)?;
+        socket.set_read_timeout(Some(timeout))?;
+        Ok(ReplySocket { socket, path })
+    }
+}
+
+impl Drop for ReplySocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_accounts_of_configured_peers_are_routed() {
+        let config = PeerBankConfig {
+            socket_path: PathBuf::from("/nonexistent"),
+            settlement_account: "due_other".to_string(),
+            currency: Currency::EUR,
+            credit_limit: None,
+            token: None,
+        };
+        let peers = Peers::new(
+            BTreeMap::from([("other".to_string(), config)]),
+            Path::new("/nonexistent"),
+        );
+        let remote = AccountRef::Name("other:alice".to_string());
+        assert_eq!(peers.route(&remote), Some(("other", "alice")));
+        assert_eq!(
+            peers.route(&AccountRef::Name("else:alice".to_string())),
+            None
+        );
+        assert_eq!(peers.route(&AccountRef::Name("alice".to_string())), None);
+    }
+}
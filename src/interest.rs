@@ -0,0 +1,80 @@
+//! Interest on savings-style accounts. Each account can have an annual
+//! rate; positive balances accrue simple interest over whole accrual
+//! periods, posted as newly created money with one ledger transaction per
+//! account and posting.
+
+use serde::{Deserialize, Serialize};
+
+use crate::fx::Rate;
+use crate::money::{Balance, Money};
+
+pub const DEFAULT_PERIOD_SECS: u64 = 24 * 60 * 60;
+
+pub const YEAR_SECS: u64 = 365 * 24 * 60 * 60;
+
+pub const MEMO: &str = "interest";
+
+/// Where an account stands with interest.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Accrual {
+    /// Annual rate, e.g. `0.035` for 3.5 %.
+    pub rate: Rate,
+    /// End of the last period interest was posted for, in seconds since the
+    /// Unix epoch.
+    pub accrued_until: u64,
+}
+
+impl Accrual {
+    pub fn new(rate: Rate, now: u64) -> Accrual {
+        Accrual {
+            rate,
+            accrued_until: now,
+        }
+    }
+
+    /// Work out the interest on `balance` for the whole periods of
+    /// `period_secs` that ended by `now`, and move past them. Balances at
+    /// or below zero earn nothing. `None` if no period has ended or on
+    /// overflow.
+    pub fn accrue(&mut self, balance: Balance, period_secs: u64, now: u64) -> Option<Money> {
+        let periods = now.saturating_sub(self.accrued_until) / period_secs.max(1);
+        if periods == 0 {
+            return None;
+        }
+        let elapsed = periods * period_secs.max(1);
+        self.accrued_until += elapsed;
+        let principal = balance.to_money()?;
+        self.rate
+            .prorate(principal, elapsed, YEAR_SECS)
+            .filter(|interest| !interest.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accrues_whole_periods_only() {
+        let mut accrual = Accrual::new("0.0365".parse().unwrap(), 0);
+        let balance = Balance::from_minor(100_000);
+        assert_eq!(
+            accrual.accrue(balance, DEFAULT_PERIOD_SECS, DEFAULT_PERIOD_SECS - 1),
+            None
+        );
+        assert_eq!(
+            accrual.accrue(balance, DEFAULT_PERIOD_SECS, 2 * DEFAULT_PERIOD_SECS + 5),
+            Some(Money::from_minor(20))
+        );
+        assert_eq!(accrual.accrued_until, 2 * DEFAULT_PERIOD_SECS);
+        assert_eq!(
+            accrual.accrue(
+                Balance::from_minor(-500),
+                DEFAULT_PERIOD_SECS,
+                3 * DEFAULT_PERIOD_SECS
+            ),
+            None
+        );
+        assert_eq!(accrual.accrued_until, 3 * DEFAULT_PERIOD_SECS);
+    }
+}
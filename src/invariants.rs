@@ -0,0 +1,196 @@
+//! Consistency check for the `c` instruction: the invariants the bank
+//! relies on, verified against its current state. Any that do not hold are
+//! reported as violations, which point at a bug or at a tampered data
+//! directory.
+//!
+//! - The postings of every transaction balance, in each currency.
+//! - In each currency the balances add up to the money that entered the
+//!   accounts from the bank's own, opening balances included, less the
+//!   money that left to them.
+//! - Replaying the ledger gives every account the balance it has.
+//! - No balance is further below zero than the account's overdraft limit.
+//! - Ledger entries are numbered from 1 without gaps and their transactions
+//!   do not go back.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::ledger::{LedgerAccount, TransactionId};
+use crate::money::{Balance, Currency, Money, Total};
+use crate::{AccountId, Bank};
+
+#[derive(Debug, Serialize)]
+pub struct InvariantReport {
+    pub consistent: bool,
+    pub accounts: usize,
+    pub ledger_entries: usize,
+    pub currencies: Vec<CurrencyTotals>,
+    pub violations: Vec<Violation>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CurrencyTotals {
+    pub currency: Currency,
+    pub total_balance: Total,
+    /// Credited to the accounts from the bank's own.
+    pub inflow: Total,
+    /// Debited from the accounts to the bank's own.
+    pub outflow: Total,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "invariant", rename_all = "snake_case")]
+pub enum Violation {
+    UnbalancedTransaction {
+        transaction_id: TransactionId,
+    },
+    TotalMismatch {
+        currency: Currency,
+        total_balance: Total,
+        /// Inflow less outflow.
+        expected: Total,
+    },
+    BalanceMismatch {
+        account: AccountId,
+        balance: Balance,
+        replayed: Total,
+    },
+    OverdraftExceeded {
+        account: AccountId,
+        balance: Balance,
+        overdraft_limit: Money,
+    },
+    LedgerOutOfOrder {
+        sequence: u64,
+    },
+}
+
+pub fn check(bank: &Bank) -> InvariantReport {
+    let mut violations = Vec::new();
+    let entries = bank.ledger.entries();
+    let mut previous = TransactionId(0);
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.sequence != index as u64 + 1 || entry.transaction_id < previous {
+            violations.push(Violation::LedgerOutOfOrder {
+                sequence: entry.sequence,
+            });
+        }
+        previous = entry.transaction_id;
+    }
+    for journal_entry in bank.ledger.journal() {
+        if !journal_entry.is_balanced() {
+            violations.push(Violation::UnbalancedTransaction {
+                transaction_id: journal_entry.transaction_id,
+            });
+        }
+    }
+
+    let mut currencies: BTreeMap<Currency, CurrencyTotals> = BTreeMap::new();
+    for entry in entries {
+        let [debit, credit] = entry.postings();
+        match (debit.account, credit.account) {
+            (LedgerAccount::System(_), LedgerAccount::Customer(_)) => {
+                totals(&mut currencies, entry.currency)
+                    .inflow
+                    .add_amount(entry.amount);
+            }
+            (LedgerAccount::Customer(_), LedgerAccount::System(_)) => {
+                totals(&mut currencies, entry.currency)
+                    .outflow
+                    .add_amount(entry.amount);
+            }
+            _ => {}
+        }
+    }
+    let replayed = bank.ledger.balances();
+    let mut accounts: Vec<_> = bank.accounts.values().collect();
+    accounts.sort_by_key(|account| account.id);
+    for account in accounts {
+        totals(&mut currencies, account.currency)
+            .total_balance
+            .add_balance(account.balance);
+        let key = (LedgerAccount::Customer(account.id), account.currency);
+        let replayed = replayed.get(&key).copied().unwrap_or_default();
+        if replayed.to_balance() != Some(account.balance) {
+            violations.push(Violation::BalanceMismatch {
+                account: account.id,
+                balance: account.balance,
+                replayed,
+            });
+        }
+        if !account.balance.is_within(account.overdraft_limit) {
+            violations.push(Violation::OverdraftExceeded {
+                account: account.id,
+                balance: account.balance,
+                overdraft_limit: account.overdraft_limit,
+            });
+        }
+    }
+    for totals in currencies.values() {
+        let mut expected = totals.inflow;
+        expected.sub_total(totals.outflow);
+        if totals.total_balance != expected {
+            violations.push(Violation::TotalMismatch {
+                currency: totals.currency,
+                total_balance: totals.total_balance,
+                expected,
+            });
+        }
+    }
+
+    InvariantReport {
+        consistent: violations.is_empty(),
+        accounts: bank.accounts.len(),
+        ledger_entries: entries.len(),
+        currencies: currencies.into_values().collect(),
+        violations,
+    }
+}
+
+fn totals(
+    currencies: &mut BTreeMap<Currency, CurrencyTotals>,
+    currency: Currency,
+) -> &mut CurrencyTotals {
+    currencies
+        .entry(currency)
+        .or_insert_with(|| CurrencyTotals {
+            currency,
+            ..CurrencyTotals::default()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tampered_balances_are_reported() {
+        let mut bank = Bank::new();
+        for name in ["a", "b"] {
+            bank.open_account(name.to_string(), Balance::from_minor(500), Currency::EUR)
+                .unwrap();
+        }
+        let report = check(&bank);
+        assert!(report.consistent, "{:?}", report.violations);
+        assert_eq!(report.currencies[0].inflow.to_string(), "10.00");
+
+        let id = bank.account_ids["a"];
+        bank.accounts.get_mut(&id).unwrap().balance = Balance::from_minor(400);
+        let report = check(&bank);
+        assert!(!report.consistent);
+        let [Violation::BalanceMismatch {
+            account, replayed, ..
+        }, Violation::TotalMismatch {
+            total_balance,
+            expected,
+            ..
+        }] = &report.violations[..]
+        else {
+            panic!("unexpected violations {:?}", report.violations);
+        };
+        assert_eq!((*account, replayed.to_string()), (id, "5.00".to_string()));
+        assert_eq!(total_balance.to_string(), "9.00");
+        assert_eq!(expected.to_string(), "10.00");
+    }
+}
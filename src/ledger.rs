@@ -0,0 +1,413 @@
+//! Record of every committed movement of money, kept in double entry:
+//! each ledger entry debits one account and credits another by the same
+//! amount. Money entering or leaving the customer accounts is booked against
+//! one of the bank's own `SystemAccount`s, so the postings of every
+//! transaction balance and account balances follow from the postings alone.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::fx::Rate;
+use crate::money::{Currency, Money, Total};
+use crate::AccountId;
+
+/// Identifies a committed transaction, which consists of one or more ledger
+/// entries. Assigned in increasing order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct TransactionId(pub u64);
+
+impl Display for TransactionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tx{}", self.0)
+    }
+}
+
+/// Books of the bank itself, on the other side of money that enters or
+/// leaves the customer accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemAccount {
+    /// Opening balances and minted money.
+    Capital,
+    /// Both legs of currency exchange.
+    FxDesk,
+    /// Interest paid to accounts and charged on loans.
+    Interest,
+    /// Settlement with peer banks, see `settlement`.
+    Clearing,
+    /// Money kept until an escrow is released or refunded.
+    Escrow,
+}
+
+impl SystemAccount {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SystemAccount::Capital => "capital",
+            SystemAccount::FxDesk => "fx_desk",
+            SystemAccount::Interest => "interest",
+            SystemAccount::Clearing => "clearing",
+            SystemAccount::Escrow => "escrow",
+        }
+    }
+}
+
+impl Display for SystemAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An account postings are made to: a customer's, by ID, or one of the
+/// bank's own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(untagged)]
+pub enum LedgerAccount {
+    Customer(AccountId),
+    System(SystemAccount),
+}
+
+impl Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAccount::Customer(id) => write!(f, "{id}"),
+            LedgerAccount::System(account) => write!(f, "{account}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    /// Takes money out of the account.
+    Debit,
+    /// Puts money into the account.
+    Credit,
+}
+
+/// One line of a journal entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Posting {
+    pub account: LedgerAccount,
+    pub side: Side,
+    pub amount: Money,
+    pub currency: Currency,
+}
+
+impl Posting {
+    /// Add the posting to `balance`, which grows with credits.
+    pub fn apply_to(&self, balance: &mut Total) {
+        match self.side {
+            Side::Debit => balance.sub_amount(self.amount),
+            Side::Credit => balance.add_amount(self.amount),
+        }
+    }
+}
+
+/// The debit and credit lines of moving `amount` from `from` to `to`, with
+/// `system` standing in for the side that is not a customer account.
+fn postings(
+    from: Option<AccountId>,
+    to: Option<AccountId>,
+    system: SystemAccount,
+    amount: Money,
+    currency: Currency,
+) -> [Posting; 2] {
+    let line = |account: Option<AccountId>, side| Posting {
+        account: account.map_or(LedgerAccount::System(system), LedgerAccount::Customer),
+        side,
+        amount,
+        currency,
+    };
+    [line(from, Side::Debit), line(to, Side::Credit)]
+}
+
+/// The system account of an entry that leaves `from` or `to` empty. Entries
+/// recorded before system accounts were tracked name none; their legs with
+/// a rate went through the FX desk, the rest came from capital.
+fn system_account(system: Option<SystemAccount>, rate: Option<Rate>) -> SystemAccount {
+    system.unwrap_or(match rate {
+        Some(_) => SystemAccount::FxDesk,
+        None => SystemAccount::Capital,
+    })
+}
+
+/// One movement of money. A plain transfer is a single entry, a
+/// cross-currency transfer is two legs through the FX desk.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LedgerEntry {
+    /// Position in the ledger, starting at 1.
+    pub sequence: u64,
+    pub transaction_id: TransactionId,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Account debited, `None` when the money comes from outside the
+    /// accounts (minting, opening balances, the FX desk).
+    pub from: Option<AccountId>,
+    /// Account credited, `None` when the money leaves the accounts.
+    pub to: Option<AccountId>,
+    /// The bank's account on the side `from` or `to` leaves empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<SystemAccount>,
+    pub amount: Money,
+    pub currency: Currency,
+    /// Rate applied, on both legs of a cross-currency transfer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Rate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    /// Identifier the client gave the transfer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_ref: Option<String>,
+    /// Transaction this entry compensates for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reverses: Option<TransactionId>,
+    /// Transaction this entry charges a fee for. Reversing that transaction
+    /// does not refund the fee; the fee's own transaction can be reversed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_for: Option<TransactionId>,
+}
+
+impl LedgerEntry {
+    /// The entry's debit and credit lines.
+    pub fn postings(&self) -> [Posting; 2] {
+        postings(
+            self.from,
+            self.to,
+            system_account(self.system, self.rate),
+            self.amount,
+            self.currency,
+        )
+    }
+}
+
+/// What to record; the ledger assigns the sequence numbers.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Movement {
+    pub from: Option<AccountId>,
+    pub to: Option<AccountId>,
+    /// The bank's account on the side `from` or `to` leaves empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<SystemAccount>,
+    pub amount: Money,
+    pub currency: Currency,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Rate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_ref: Option<String>,
+}
+
+impl Movement {
+    /// The movement's debit and credit lines.
+    pub fn postings(&self) -> [Posting; 2] {
+        postings(
+            self.from,
+            self.to,
+            system_account(self.system, self.rate),
+            self.amount,
+            self.currency,
+        )
+    }
+}
+
+/// A transaction as the lines it posts.
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalEntry {
+    pub transaction_id: TransactionId,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub postings: Vec<Posting>,
+}
+
+impl JournalEntry {
+    /// Whether debits equal credits in each currency.
+    pub fn is_balanced(&self) -> bool {
+        let mut totals: BTreeMap<Currency, Total> = BTreeMap::new();
+        for posting in &self.postings {
+            posting.apply_to(totals.entry(posting.currency).or_default());
+        }
+        totals.values().all(|total| *total == Total::default())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+    last_transaction_id: u64,
+    /// Reversing transaction by reversed transaction.
+    reversals: HashMap<TransactionId, TransactionId>,
+}
+
+impl Ledger {
+    /// A ledger holding `entries`, as returned by `entries`.
+    pub fn from_entries(entries: Vec<LedgerEntry>) -> Ledger {
+        let reversals = entries
+            .iter()
+            .filter_map(|entry| Some((entry.reverses?, entry.transaction_id)))
+            .collect();
+        Ledger {
+            last_transaction_id: entries.last().map_or(0, |entry| entry.transaction_id.0),
+            entries,
+            reversals,
+        }
+    }
+
+    /// ID the next transaction recorded gets.
+    pub fn next_transaction_id(&self) -> TransactionId {
+        TransactionId(self.last_transaction_id + 1)
+    }
+
+    /// Record the movements as transaction `transaction_id`, which must be
+    /// `next_transaction_id()`, and return its entries. `reverses` is the
+    /// transaction it compensates for, `fee_for` the one it charges fees for.
+    pub fn append(
+        &mut self,
+        transaction_id: TransactionId,
+        timestamp: u64,
+        movements: Vec<Movement>,
+        reverses: Option<TransactionId>,
+        fee_for: Option<TransactionId>,
+    ) -> &[LedgerEntry] {
+        debug_assert_eq!(transaction_id, self.next_transaction_id());
+        self.last_transaction_id = transaction_id.0;
+        if let Some(original) = reverses {
+            self.reversals.insert(original, transaction_id);
+        }
+        let first = self.entries.len();
+        for movement in movements {
+            let entry = LedgerEntry {
+                sequence: self.entries.len() as u64 + 1,
+                transaction_id,
+                timestamp,
+                from: movement.from,
+                to: movement.to,
+                system: movement.system,
+                amount: movement.amount,
+                currency: movement.currency,
+                rate: movement.rate,
+                memo: movement.memo,
+                external_ref: movement.external_ref,
+                reverses,
+                fee_for,
+            };
+            self.entries.push(entry);
+        }
+        &self.entries[first..]
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    /// Entries of a transaction, `None` if there is no such transaction.
+    pub fn transaction(&self, id: TransactionId) -> Option<&[LedgerEntry]> {
+        // Transaction IDs only increase along the ledger.
+        let start = self
+            .entries
+            .partition_point(|entry| entry.transaction_id < id);
+        let end = self
+            .entries
+            .partition_point(|entry| entry.transaction_id <= id);
+        (start < end).then(|| &self.entries[start..end])
+    }
+
+    /// Each transaction as a journal entry, in ledger order.
+    pub fn journal(&self) -> impl Iterator<Item = JournalEntry> + '_ {
+        self.entries
+            .chunk_by(|a, b| a.transaction_id == b.transaction_id)
+            .map(|entries| JournalEntry {
+                transaction_id: entries[0].transaction_id,
+                timestamp: entries[0].timestamp,
+                postings: entries.iter().flat_map(LedgerEntry::postings).collect(),
+            })
+    }
+
+    /// Balance of every account in every currency it was posted in, from
+    /// the postings. Credits count up, so the balances of each currency add
+    /// up to zero and the bank's own accounts hold the opposite of what
+    /// entered the customer accounts through them.
+    pub fn balances(&self) -> BTreeMap<(LedgerAccount, Currency), Total> {
+        let mut balances: BTreeMap<_, Total> = BTreeMap::new();
+        for posting in self.entries.iter().flat_map(LedgerEntry::postings) {
+            posting.apply_to(
+                balances
+                    .entry((posting.account, posting.currency))
+                    .or_default(),
+            );
+        }
+        balances
+    }
+
+    /// The transaction charging the fee for `id`, if it had one.
+    pub fn fee_of(&self, id: TransactionId) -> Option<TransactionId> {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.transaction_id <= id);
+        self.entries[start..]
+            .iter()
+            .find(|entry| entry.fee_for == Some(id))
+            .map(|entry| entry.transaction_id)
+    }
+
+    /// The latest transaction out of `from` with `external_ref` that is not
+    /// a reversal, if any.
+    pub fn with_external_ref(&self, from: AccountId, external_ref: &str) -> Option<TransactionId> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| {
+                entry.from == Some(from)
+                    && entry.reverses.is_none()
+                    && entry.external_ref.as_deref() == Some(external_ref)
+            })
+            .map(|entry| entry.transaction_id)
+    }
+
+    /// The transaction that reversed `id`, if any.
+    pub fn reversed_by(&self, id: TransactionId) -> Option<TransactionId> {
+        self.reversals.get(&id).copied()
+    }
+
+    /// Total debited from `account` later than `after`, leaving out fees and
+    /// reversals.
+    pub fn outflow_after(&self, account: AccountId, after: u64) -> Money {
+        self.sent_after(account, after)
+            .fold(Money::ZERO, |total, entry| {
+                total.checked_add(entry.amount).unwrap_or(Money::MAX)
+            })
+    }
+
+    /// Number of transfers from `account` later than `after`, counted like
+    /// `outflow_after`.
+    pub fn transfers_after(&self, account: AccountId, after: u64) -> usize {
+        self.sent_after(account, after).count()
+    }
+
+    fn sent_after(&self, account: AccountId, after: u64) -> impl Iterator<Item = &LedgerEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .take_while(move |entry| entry.timestamp > after)
+            .filter(move |entry| {
+                entry.from == Some(account) && entry.fee_for.is_none() && entry.reverses.is_none()
+            })
+    }
+
+    /// The last `limit` entries debiting or crediting `account`, oldest first.
+    pub fn history(&self, account: AccountId, limit: usize) -> Vec<&LedgerEntry> {
+        let mut entries: Vec<&LedgerEntry> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.from == Some(account) || entry.to == Some(account))
+            .take(limit)
+            .collect();
+        entries.reverse();
+        entries
+    }
+}
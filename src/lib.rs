@@ -1,81 +1,658 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap as VanillaHashMap;
-use std::error::Error;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Display;
 use std::io;
-use std::os::unix::net::{UnixDatagram};
-use std::path::Path;
-use std::{fs, str};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use crypto::argon2;
 use hashbrown::HashMap;
-use log::{info, debug, error};
-use serde::{Deserialize};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use serde_json::{self, Error as SerdeError};
 use thiserror::Error;
 
-pub fn init_bank() -> Bank {
-    Bank::new(vec![
-        Account::new("patko".to_string(), 1000),
-        Account::new("siska".to_string(), 1000),
-        Account::new("sofka".to_string(), 1000),
-    ])
+mod actors;
+mod admin;
+mod attestation;
+mod audit;
+mod auth;
+mod backup;
+#[doc(hidden)]
+pub mod benchmarks;
+pub mod clock;
+mod cluster;
+mod config;
+pub mod crypto;
+mod csv;
+mod encryption;
+mod escrow;
+mod events;
+mod export;
+mod fees;
+mod fraud;
+#[doc(hidden)]
+pub mod fuzzing;
+pub mod fx;
+mod goals;
+mod handlers;
+mod hardening;
+mod health;
+mod holds;
+pub mod hooks;
+mod http;
+mod idempotency;
+mod import;
+mod interbank;
+mod interest;
+mod invariants;
+pub mod ledger;
+mod limits;
+mod listing;
+mod loans;
+pub mod logging;
+mod metrics;
+pub mod money;
+mod names;
+mod noise;
+mod owners;
+mod pain001;
+pub mod plugins;
+#[cfg(feature = "scripting")]
+mod policy;
+mod privileges;
+#[cfg(test)]
+mod properties;
+mod protocol;
+mod ratelimit;
+mod replay;
+mod replica;
+mod review;
+mod sandbox;
+mod scheduler;
+mod settlement;
+mod shards;
+mod signals;
+pub mod signing;
+#[cfg(test)]
+mod simulation;
+mod socket;
+pub mod statement;
+mod statistics;
+mod store;
+mod subscriptions;
+mod systemd;
+mod trace;
+mod transport;
+mod udp;
+mod users;
+mod vsock;
+mod webhooks;
+mod xml;
+
+pub use actors::Contention;
+pub use audit::{verify_audit_log, AuditError, AuditSummary};
+pub use auth::{IdentityConfig, PeerAuthConfig, Role, TokenAuthConfig};
+pub use backup::{restore_backup, BackupError, BackupReceipt, Restored};
+pub use cluster::{ClusterConfig, StandbyConfig, StandbyRole};
+pub use config::{Config, ConfigError, TenantConfig};
+pub use encryption::{configure_encryption, Cipher, EncryptionError};
+pub use events::EventLogError;
+pub use export::{ExportError, ExportFormat};
+pub use fees::{FeeConfig, FeeRule};
+pub use fraud::FraudRule;
+pub use fx::Rate;
+pub use hardening::HardeningConfig;
+pub use import::{ImportError, ImportReport, RejectedRow};
+pub use interbank::{InterbankError, PeerBankConfig};
+pub use listing::ListingFormat;
+pub use money::{Balance, Currency, Money};
+pub use names::InvalidNameError;
+pub use noise::{NoiseConfig, NoiseError};
+pub use pain001::PaymentFileError;
+pub use ratelimit::RateLimitConfig;
+pub use replay::{replay_log, AccountBalance, Discrepancy, Replay, ReplayError};
+pub use signing::SigningConfig;
+pub use store::StoreError;
+pub use webhooks::{WebhookConfig, WebhookError};
+
+use admin::AdminCommand;
+use attestation::Attestation;
+use auth::{Auth, Identity};
+use clock::{Clock, SystemClock};
+use cluster::Replication;
+use crypto::ed25519::SigningKey;
+use escrow::{Escrow, EscrowId, EscrowOutcome, EscrowReceipt, Escrows};
+use events::{Event, EventLog, EventRecord};
+use fraud::{Action, Condition, FlaggedTransaction};
+use fx::FxRates;
+use goals::{Goal, GoalError, GoalId, GoalProgress, Goals};
+use handlers::Request;
+use holds::{Hold, HoldId, HoldReceipt, Holds};
+use hooks::{CommittedTransfer, Hooks, TransferRequest};
+use idempotency::IdempotencyCache;
+use interbank::{InterbankTransfer, Peers, UnconfirmedTransfer};
+use interest::Accrual;
+use ledger::{Ledger, LedgerAccount, LedgerEntry, Movement, Side, SystemAccount, TransactionId};
+use limits::{LimitKind, TransferLimits};
+use listing::{AccountFilter, Page};
+use loans::{Loan, LoanError, LoanId, LoanInfo, Loans};
+use metrics::Metrics;
+use money::Total;
+use noise::NoiseTransport;
+use owners::{ApprovalId, ApprovalRequest, Approvals, OwnershipError};
+use plugins::{Guarded, Plugin, PluginError};
+use ratelimit::RateLimiter;
+use replica::Replica;
+use review::{ParkedTransfer, ReviewId, ReviewNotFoundError, Reviews};
+use sandbox::{SandboxCommand, SandboxError, SandboxInfo};
+use scheduler::{
+    Every, InsufficientFunds, ScheduleId, ScheduledTransfer, Scheduler, StandingOrder,
+    StandingOrderId,
+};
+use settlement::SettlementBatch;
+use socket::PeerCredentials;
+use statement::{Date, Statement, StatementFormat};
+use statistics::Activity;
+use store::{Snapshot, Store};
+use subscriptions::{SubscriptionAction, Subscriptions};
+use trace::{Phase, RequestTrace, Tracer};
+use transport::Transport;
+use udp::UdpTransport;
+use vsock::VsockTransport;
+use webhooks::Webhooks;
+
+/// A bank with the accounts of `accounts_file`, see `import`, or with the
+/// demo accounts if there is none.
+pub fn init_bank(accounts_file: Option<&Path>) -> Result<Bank, ImportError> {
+    let mut bank = Bank::new();
+    open_initial_accounts(&mut bank, accounts_file)?;
+    Ok(bank)
 }
 
+/// Load the bank kept in `data_dir`, or start one there with the initial
+/// accounts, as for `init_bank`, if it holds none yet.
+pub fn open_bank(
+    data_dir: &Path,
+    snapshot_every: u64,
+    accounts_file: Option<&Path>,
+) -> Result<Bank> {
+    let mut bank = Bank::open(Arc::new(SystemClock), data_dir, snapshot_every)?;
+    if bank.events.last_sequence() == 0 {
+        open_initial_accounts(&mut bank, accounts_file)?;
+    }
+    Ok(bank)
+}
 
-type Amount = u64;
+/// Load the bank kept in `data_dir` without changing anything there, e.g.
+/// to export it while the server is running.
+pub fn load_bank(data_dir: &Path) -> Result<Bank, StoreError> {
+    Bank::load(Arc::new(SystemClock), data_dir)
+}
 
-#[derive(Debug)]
+fn open_initial_accounts(bank: &mut Bank, accounts_file: Option<&Path>) -> Result<(), ImportError> {
+    match accounts_file {
+        Some(path) => {
+            bank.import_accounts(path)?;
+        }
+        None => {
+            for name in ["patko", "siska", "sofka"] {
+                bank.open_account(
+                    name.to_string(),
+                    Balance::from_minor(100_000),
+                    Currency::EUR,
+                )
+                .expect("initial account names are unique");
+            }
+        }
+    }
+    Ok(())
+}
+
+type Amount = Money;
+
+/// Permanent identifier of an account, assigned in sequence on creation.
+/// Unlike the name it never changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct AccountId(u64);
+
+impl Display for AccountId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// An account as addressed by clients: a JSON number is an account ID, a
+/// string is an account name.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+enum AccountRef {
+    Id(AccountId),
+    Name(String),
+}
+
+impl Display for AccountRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountRef::Id(id) => write!(f, "{id}"),
+            AccountRef::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Frozen accounts can receive money but not send it, closed accounts take
+/// part in no transactions at all.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    Frozen,
+    Closed,
+}
+
+impl AccountStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Closed => "closed",
+        }
+    }
+}
+
+/// Descriptive details that play no part in transactions.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+struct AccountMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    /// Seconds since the Unix epoch.
+    created_at: u64,
+    tags: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct Account {
-    name: String,
-    balance: Amount,
+    id: AccountId,
+    name: AccountName,
+    balance: Balance,
+    currency: Currency,
+    /// How far below zero the balance may go.
+    overdraft_limit: Amount,
+    /// Total of the holds on the account, which is not available for
+    /// spending.
+    held: Amount,
+    status: AccountStatus,
+    metadata: AccountMetadata,
+    interest: Option<Accrual>,
+    /// Overrides the bank's default limits.
+    limits: Option<TransferLimits>,
+    /// Argon2id PHC string. Transfers out of the account must present the
+    /// matching PIN when set.
+    #[serde(skip)]
+    pin_hash: Option<String>,
+    /// Identities that may act on the account, see `owners`.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    owners: BTreeSet<String>,
+    /// Owners that must approve each transfer out of the account.
+    #[serde(
+        default = "owners::default_approvals_required",
+        skip_serializing_if = "owners::is_default_approvals_required"
+    )]
+    approvals_required: u32,
+    /// Set on sub-accounts, the envelopes of a parent account's money. The
+    /// parent's total is its own balance and those of its sub-accounts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<AccountId>,
+    /// The account this closed one was merged into. Its history stays with
+    /// it, the merge's transaction is in the history of both.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    merged_into: Option<AccountId>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct TxInfo {
-    from: String,
-    to: String,
-    amount: u64,
+    from: AccountRef,
+    to: AccountRef,
+    amount: Amount,
+    /// Currency of `amount`. Must match the sending account when given, and
+    /// the receiving account too unless `convert` is set.
+    #[serde(default)]
+    currency: Option<Currency>,
+    /// Convert into the receiving account's currency at the current rate.
+    #[serde(default)]
+    convert: bool,
+    #[serde(default, skip_serializing)]
+    pin: Option<String>,
+    /// Free text kept with the transfer in the ledger.
+    #[serde(default)]
+    memo: Option<String>,
+    /// The client's own identifier for the transfer, kept in the ledger.
+    #[serde(default)]
+    external_ref: Option<String>,
+    /// Retries with the same key get the first attempt's outcome instead of
+    /// transferring again, see `idempotency`.
+    #[serde(default)]
+    idempotency_key: Option<String>,
+    /// Only check the transfer and reply with the receipt it would get,
+    /// without moving any money.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Reply to an accepted transfer.
+#[derive(Debug, Clone, Serialize)]
+struct Receipt {
+    transaction_id: TransactionId,
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+    from: AccountId,
+    to: AccountId,
+    amount: Amount,
+    currency: Currency,
+    /// What `to` received, which differs from `amount` after conversion.
+    credited_amount: Amount,
+    credited_currency: Currency,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate: Option<Rate>,
+    /// Charged to `from` on top of `amount`, in its currency.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fee: Option<Amount>,
+    from_balance: Balance,
+    to_balance: Balance,
+    /// Set for a dry run, where nothing was transferred and the transaction
+    /// ID and balances are the ones the transfer would have led to.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    dry_run: bool,
+}
+
+/// What approving a transfer out of a joint account led to.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum ApprovalOutcome {
+    /// More owners have to approve it.
+    Pending(ApprovalRequest),
+    Made(Receipt),
+    /// It waits for an admin, see `review`.
+    Parked(ParkedTransfer),
+}
+
+/// What `Bank::plan_transfer` checks besides the transfer itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Checks {
+    /// The PIN, and that the transfer need not wait for the owners of a
+    /// joint account or for an admin. Those that must are refused, they
+    /// can only be sent on their own with the `t` instruction.
+    All,
+    /// Only the PIN, for transfers about to wait for approval or review,
+    /// and dry runs.
+    Pin,
+    /// Only the waits, for transfers whose PIN was checked when they were
+    /// accepted, such as captured holds and scheduled transfers.
+    Waits,
+    /// Neither, for transfers the owners or an admin approved and those a
+    /// peer bank booked.
+    Nothing,
+}
+
+/// What a transfer moves, see `Bank::plan_transfer`.
+#[derive(Debug, Clone)]
+struct PlannedTransfer {
+    from: AccountId,
+    to: AccountId,
+    amount: Amount,
+    currency: Currency,
+    credited_amount: Amount,
+    credited_currency: Currency,
+    rate: Option<Rate>,
+    movements: Vec<Movement>,
+    fee: Option<Amount>,
+    /// Recorded separately from `movements`, linked to their transaction.
+    fee_movements: Vec<Movement>,
+}
+
+/// Payload of the `b` instruction.
+#[derive(Debug, Deserialize)]
+struct BatchRequest {
+    transfers: Vec<TxInfo>,
+    /// Apply each transfer on its own rather than all or none, checking
+    /// those between accounts of the same shard in parallel, see `shards`.
+    #[serde(default)]
+    independent: bool,
+}
+
+/// Reply to a batch of independent transfers: the outcome of each, in
+/// order.
+#[derive(Debug, Serialize)]
+struct IndependentBatchReceipt {
+    results: Vec<TransferResult>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum TransferResult {
+    Made(Receipt),
+    Failed {
+        status: &'static str,
+        code: &'static str,
+        error: String,
+    },
+}
+
+/// Reply to an accepted batch.
+#[derive(Debug, Clone, Serialize)]
+struct BatchReceipt {
+    transaction_id: TransactionId,
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+    /// Number of transfers applied.
+    transfers: usize,
+}
+
+/// Reply to an accepted reversal.
+#[derive(Debug, Clone, Serialize)]
+struct ReversalReceipt {
+    transaction_id: TransactionId,
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+    reverses: TransactionId,
+}
+
+/// Payload of the `r` instruction.
+#[derive(Debug, Deserialize)]
+struct ReversalRequest {
+    transaction_id: TransactionId,
+}
+
+/// Payload of the `h` instruction.
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    account: AccountRef,
+    /// Number of most recent entries to return, capped at `MAX_HISTORY_LIMIT`.
+    #[serde(default = "default_history_limit")]
+    limit: usize,
+}
+
+const MAX_HISTORY_LIMIT: usize = 100;
+
+/// Payload of the `v` instruction.
+#[derive(Debug, Deserialize)]
+struct AttestationQuery {
+    account: AccountRef,
+    /// Ledger entry the balance is as of, the latest if absent.
+    #[serde(default)]
+    sequence: Option<u64>,
+}
+
+/// Payload of the `e` instruction.
+#[derive(Debug, Deserialize)]
+struct StatementQuery {
+    account: AccountRef,
+    /// First day, the account's opening if absent.
+    #[serde(default)]
+    from: Option<Date>,
+    /// Last day, today if absent.
+    #[serde(default)]
+    to: Option<Date>,
+    #[serde(default)]
+    format: StatementFormat,
+}
+
+/// Payload of the `u` instruction.
+#[derive(Debug, Deserialize)]
+struct SubscriptionRequest {
+    action: SubscriptionAction,
+    /// Account whose transfers to subscribe to, all accounts if absent.
+    #[serde(default)]
+    account: Option<AccountRef>,
+}
+
+fn default_history_limit() -> usize {
+    20
+}
+
+#[derive(Debug, Serialize)]
+struct AccountInfo {
+    id: AccountId,
+    balance: Balance,
+    currency: Currency,
+    overdraft_limit: Amount,
+    held: Amount,
+    status: AccountStatus,
+    metadata: AccountMetadata,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interest_rate: Option<Rate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limits: Option<TransferLimits>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    owners: BTreeSet<String>,
+    #[serde(skip_serializing_if = "owners::is_default_approvals_required")]
+    approvals_required: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<AccountId>,
+    /// Names of the sub-accounts, if any.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    sub_accounts: Vec<AccountName>,
+    /// The balance with those of the sub-accounts, if there are any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<Balance>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merged_into: Option<AccountId>,
 }
 
 impl Account {
-    fn new(name: String, balance: Amount) -> Account {
-        Account { name, balance }
+    fn new(id: AccountId, name: AccountName, currency: Currency, created_at: u64) -> Account {
+        Account {
+            id,
+            name,
+            balance: Balance::default(),
+            currency,
+            overdraft_limit: Money::ZERO,
+            held: Money::ZERO,
+            status: AccountStatus::Active,
+            metadata: AccountMetadata {
+                created_at,
+                ..AccountMetadata::default()
+            },
+            interest: None,
+            limits: None,
+            pin_hash: None,
+            owners: BTreeSet::new(),
+            approvals_required: owners::default_approvals_required(),
+            parent: None,
+            merged_into: None,
+        }
+    }
+
+    fn ensure_can_send(&self) -> Result<(), CustomError> {
+        match self.status {
+            AccountStatus::Active => Ok(()),
+            AccountStatus::Frozen => Err(CustomError::AccountFrozenError(AccountFrozenError {
+                account_name: self.name.clone(),
+            })),
+            AccountStatus::Closed => Err(CustomError::AccountClosedError(AccountClosedError {
+                account_name: self.name.clone(),
+            })),
+        }
+    }
+
+    fn ensure_can_receive(&self) -> Result<(), CustomError> {
+        match self.status {
+            AccountStatus::Active | AccountStatus::Frozen => Ok(()),
+            AccountStatus::Closed => Err(CustomError::AccountClosedError(AccountClosedError {
+                account_name: self.name.clone(),
+            })),
+        }
     }
 
-    fn has_sufficient_funds(&self, amount: Amount) -> bool {
-        self.balance - amount >= 0
+    fn verify_pin(&self, pin: Option<&str>) -> bool {
+        match (&self.pin_hash, pin) {
+            (None, _) => true,
+            (Some(pin_hash), Some(pin)) => argon2::verify_password(pin, pin_hash),
+            (Some(_), None) => false,
+        }
     }
 
-    fn subtract_funds(&mut self, amount: Amount) {
-        self.balance -= amount;
+    /// Balance after taking `amount` out of the account, which may be
+    /// negative down to the overdraft limit. Held funds are not available.
+    fn balance_after_withdrawal(&self, amount: Amount) -> Result<Balance, CustomError> {
+        let balance = self.balance.checked_sub(amount);
+        let available = balance.and_then(|balance| balance.checked_sub(self.held));
+        match (balance, available) {
+            (Some(balance), Some(available)) if available.is_within(self.overdraft_limit) => {
+                Ok(balance)
+            }
+            _ => Err(CustomError::InsufficientFundsError(
+                InsufficientFundsError {
+                    account_name: self.name.clone(),
+                },
+            )),
+        }
     }
 
-    fn add_funds(&mut self, amount: Amount) {
-        self.balance += amount;
+    /// Balance after putting `amount` into the account.
+    fn balance_after_deposit(&self, amount: Amount) -> Result<Balance, CustomError> {
+        self.balance.checked_add(amount).ok_or_else(|| {
+            CustomError::BalanceOverflowError(BalanceOverflowError {
+                account_name: self.name.clone(),
+            })
+        })
     }
 }
 
 impl Display for Account {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}, {}", self.name, self.balance)
+        write!(f, "{}, {} {}", self.name, self.balance, self.currency)
     }
 }
 
 #[derive(Error, Debug)]
 #[error("Account {} has insufficient funds", account_name)]
 pub struct InsufficientFundsError {
-    account_name: String,
+    account_name: AccountName,
 }
 
+/// Name of an account, shared by the account, the index of names and the
+/// errors about it rather than copied for each.
+type AccountName = Arc<str>;
+
 #[derive(Debug)]
 struct AccountNamesTuple(String, String);
 
 impl Display for AccountNamesTuple {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.0.is_empty() {
-            write!(f, "'{}'", self.0)
-        } else if self.1.is_empty() {
             write!(f, "'{}'", self.1)
+        } else if self.1.is_empty() {
+            write!(f, "'{}'", self.0)
         } else {
             write!(f, "'{}' and '{}'", self.0, self.1)
         }
@@ -88,172 +665,7070 @@ pub struct AccountDoesNotExistError {
     account_name: AccountNamesTuple,
 }
 
+#[derive(Error, Debug)]
+#[error("Balance of account {} would overflow", account_name)]
+pub struct BalanceOverflowError {
+    account_name: AccountName,
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "Transfer in {} between accounts in {} and {}",
+    tx_currency,
+    from_currency,
+    to_currency
+)]
+pub struct CurrencyMismatchError {
+    tx_currency: Currency,
+    from_currency: Currency,
+    to_currency: Currency,
+}
+
+#[derive(Error, Debug)]
+#[error("No exchange rate from {} to {}", from_currency, to_currency)]
+pub struct NoExchangeRateError {
+    from_currency: Currency,
+    to_currency: Currency,
+}
+
+#[derive(Error, Debug)]
+#[error("Cannot set an exchange rate from {} to itself", currency)]
+pub struct InvalidExchangeRateError {
+    currency: Currency,
+}
+
+#[derive(Error, Debug)]
+#[error("Account {} already exists", account_name)]
+pub struct AccountAlreadyExistsError {
+    account_name: AccountName,
+}
+
+#[derive(Error, Debug)]
+#[error("Account {} is frozen", account_name)]
+pub struct AccountFrozenError {
+    account_name: AccountName,
+}
+
+#[derive(Error, Debug)]
+#[error("Account {} is closed", account_name)]
+pub struct AccountClosedError {
+    account_name: AccountName,
+}
+
+#[derive(Error, Debug)]
+#[error("Transaction {} not found", transaction_id)]
+pub struct TransactionNotFoundError {
+    transaction_id: TransactionId,
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "Transaction {} was already reversed by {}",
+    transaction_id,
+    reversed_by
+)]
+pub struct AlreadyReversedError {
+    transaction_id: TransactionId,
+    reversed_by: TransactionId,
+}
+
+#[derive(Error, Debug)]
+#[error("Transaction {} is a reversal and cannot be reversed", transaction_id)]
+pub struct IrreversibleTransactionError {
+    transaction_id: TransactionId,
+}
+
+#[derive(Error, Debug)]
+#[error("Hold {} not found", hold_id)]
+pub struct HoldNotFoundError {
+    hold_id: HoldId,
+}
+
+#[derive(Error, Debug)]
+#[error("Account {account_name} is a sub-account of {parent} and cannot have sub-accounts")]
+pub struct NestedSubAccountError {
+    account_name: AccountName,
+    parent: AccountId,
+}
+
+#[derive(Error, Debug)]
+#[error("Escrow {} not found", escrow_id)]
+pub struct EscrowNotFoundError {
+    escrow_id: EscrowId,
+}
+
+#[derive(Error, Debug)]
+#[error("Capture of {} exceeds the {} held by {}", amount, held, hold_id)]
+pub struct CaptureExceedsHoldError {
+    hold_id: HoldId,
+    amount: Amount,
+    held: Amount,
+}
+
+#[derive(Error, Debug)]
+#[error("Scheduled transfer {} not found", schedule_id)]
+pub struct ScheduledTransferNotFoundError {
+    schedule_id: ScheduleId,
+}
+
+#[derive(Error, Debug)]
+#[error("Standing order {} not found", order_id)]
+pub struct StandingOrderNotFoundError {
+    order_id: StandingOrderId,
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "Transfer from '{}' exceeds its {} limit of {}",
+    account_name,
+    kind,
+    limit
+)]
+pub struct LimitExceededError {
+    account_name: AccountName,
+    kind: LimitKind,
+    limit: Amount,
+}
+
+#[derive(Error, Debug)]
+#[error("Transfer from '{}' rejected: {}", account_name, reason)]
+pub struct TransferRejectedError {
+    account_name: AccountName,
+    reason: String,
+}
+
+#[derive(Error, Debug)]
+#[error("Transfer from '{}' vetoed: {}", account_name, reason)]
+pub struct TransferVetoedError {
+    account_name: AccountName,
+    reason: String,
+}
+
+#[derive(Error, Debug)]
+#[error("Batch contains no transfers")]
+pub struct EmptyBatchError;
+
+#[derive(Error, Debug)]
+#[error("Transfer from '{}' to itself", account_name)]
+pub struct SelfTransferError {
+    account_name: AccountName,
+}
+
+#[derive(Error, Debug)]
+#[error("Transfer from '{}' moves no money", account_name)]
+pub struct ZeroAmountError {
+    account_name: AccountName,
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "Transfers from '{}' need the approval of {} owners and can only be sent on their own",
+    account_name,
+    approvals_required
+)]
+pub struct ApprovalsRequiredError {
+    account_name: AccountName,
+    approvals_required: u32,
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "Transfers of more than {} from '{}' wait for review and can only be sent on their own",
+    threshold,
+    account_name
+)]
+pub struct ReviewRequiredError {
+    account_name: AccountName,
+    threshold: Amount,
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "Account name '{}' is referred to by the configuration and cannot be renamed",
+    account_name
+)]
+pub struct ConfiguredNameError {
+    account_name: AccountName,
+}
+
+#[derive(Error, Debug)]
+#[error("Account '{}' cannot be merged: {}", account_name, reason)]
+pub struct UnmergeableAccountError {
+    account_name: AccountName,
+    reason: &'static str,
+}
+
+#[derive(Error, Debug)]
+#[error("Ledger entry {} not found", sequence)]
+pub struct LedgerEntryNotFoundError {
+    sequence: u64,
+}
+
+#[derive(Error, Debug)]
+#[error("No attestation key is configured")]
+pub struct AttestationKeyMissingError;
+
+#[derive(Error, Debug)]
+#[error("Statement period ends on {} before it starts on {}", to, from)]
+pub struct InvalidPeriodError {
+    from: Date,
+    to: Date,
+}
+
+#[derive(Error, Debug)]
+#[error("Invalid PIN for account {}", account_name)]
+pub struct InvalidPinError {
+    account_name: AccountName,
+}
+
 #[derive(Error, Debug)]
 pub enum CustomError {
     #[error(transparent)]
     AccountDoesNotExistError(#[from] AccountDoesNotExistError),
     #[error(transparent)]
+    AccountAlreadyExistsError(#[from] AccountAlreadyExistsError),
+    #[error(transparent)]
     InsufficientFundsError(#[from] InsufficientFundsError),
+    #[error(transparent)]
+    BalanceOverflowError(#[from] BalanceOverflowError),
+    #[error(transparent)]
+    CurrencyMismatchError(#[from] CurrencyMismatchError),
+    #[error(transparent)]
+    NoExchangeRateError(#[from] NoExchangeRateError),
+    #[error(transparent)]
+    InvalidExchangeRateError(#[from] InvalidExchangeRateError),
+    #[error(transparent)]
+    AccountFrozenError(#[from] AccountFrozenError),
+    #[error(transparent)]
+    AccountClosedError(#[from] AccountClosedError),
+    #[error(transparent)]
+    InvalidPinError(#[from] InvalidPinError),
+    #[error(transparent)]
+    TransactionNotFoundError(#[from] TransactionNotFoundError),
+    #[error(transparent)]
+    AlreadyReversedError(#[from] AlreadyReversedError),
+    #[error(transparent)]
+    IrreversibleTransactionError(#[from] IrreversibleTransactionError),
+    #[error(transparent)]
+    EmptyBatchError(#[from] EmptyBatchError),
+    #[error(transparent)]
+    HoldNotFoundError(#[from] HoldNotFoundError),
+    #[error(transparent)]
+    EscrowNotFoundError(#[from] EscrowNotFoundError),
+    #[error(transparent)]
+    OwnershipError(#[from] OwnershipError),
+    #[error(transparent)]
+    NestedSubAccountError(#[from] NestedSubAccountError),
+    #[error(transparent)]
+    LoanError(#[from] LoanError),
+    #[error(transparent)]
+    GoalError(#[from] GoalError),
+    #[error(transparent)]
+    ReviewNotFoundError(#[from] ReviewNotFoundError),
+    #[error(transparent)]
+    CaptureExceedsHoldError(#[from] CaptureExceedsHoldError),
+    #[error(transparent)]
+    ScheduledTransferNotFoundError(#[from] ScheduledTransferNotFoundError),
+    #[error(transparent)]
+    StandingOrderNotFoundError(#[from] StandingOrderNotFoundError),
+    #[error(transparent)]
+    LimitExceededError(#[from] LimitExceededError),
+    #[error(transparent)]
+    TransferRejectedError(#[from] TransferRejectedError),
+    #[error(transparent)]
+    TransferVetoedError(#[from] TransferVetoedError),
+    #[error(transparent)]
+    SelfTransferError(#[from] SelfTransferError),
+    #[error(transparent)]
+    ZeroAmountError(#[from] ZeroAmountError),
+    #[error(transparent)]
+    UnmergeableAccountError(#[from] UnmergeableAccountError),
+    #[error(transparent)]
+    ConfiguredNameError(#[from] ConfiguredNameError),
+    #[error(transparent)]
+    ApprovalsRequiredError(#[from] ApprovalsRequiredError),
+    #[error(transparent)]
+    ReviewRequiredError(#[from] ReviewRequiredError),
+    #[error(transparent)]
+    InvalidNameError(#[from] InvalidNameError),
+    #[error(transparent)]
+    LedgerEntryNotFoundError(#[from] LedgerEntryNotFoundError),
+    #[error(transparent)]
+    AttestationKeyMissingError(#[from] AttestationKeyMissingError),
+    #[error(transparent)]
+    InvalidPeriodError(#[from] InvalidPeriodError),
+    #[error(transparent)]
+    ExportError(#[from] ExportError),
+    #[error(transparent)]
+    BackupError(#[from] BackupError),
+    #[error(transparent)]
+    ImportError(#[from] ImportError),
+    #[error(transparent)]
+    PaymentFileError(#[from] PaymentFileError),
+    #[error(transparent)]
+    InterbankError(#[from] InterbankError),
     #[error("Custom I/O Error")]
     IOError(#[from] std::io::Error),
     #[error("Incorrect amount")]
     ParseIntError(#[from] std::num::ParseIntError),
 }
 
+impl CustomError {
+    /// Stable code of the error for clients to tell errors apart by, e.g.
+    /// `INSUFFICIENT_FUNDS`. Codes are never changed or reused; new errors
+    /// get new ones.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CustomError::AccountDoesNotExistError(_) => "ACCOUNT_NOT_FOUND",
+            CustomError::AccountAlreadyExistsError(_) => "ACCOUNT_EXISTS",
+            CustomError::InsufficientFundsError(_) => "INSUFFICIENT_FUNDS",
+            CustomError::BalanceOverflowError(_) => "BALANCE_OVERFLOW",
+            CustomError::CurrencyMismatchError(_) => "CURRENCY_MISMATCH",
+            CustomError::NoExchangeRateError(_) => "NO_EXCHANGE_RATE",
+            CustomError::InvalidExchangeRateError(_) => "INVALID_EXCHANGE_RATE",
+            CustomError::AccountFrozenError(_) => "ACCOUNT_FROZEN",
+            CustomError::AccountClosedError(_) => "ACCOUNT_CLOSED",
+            CustomError::InvalidPinError(_) => "INVALID_PIN",
+            CustomError::TransactionNotFoundError(_) => "TRANSACTION_NOT_FOUND",
+            CustomError::AlreadyReversedError(_) => "ALREADY_REVERSED",
+            CustomError::IrreversibleTransactionError(_) => "IRREVERSIBLE_TRANSACTION",
+            CustomError::EmptyBatchError(_) => "EMPTY_BATCH",
+            CustomError::HoldNotFoundError(_) => "HOLD_NOT_FOUND",
+            CustomError::EscrowNotFoundError(_) => "ESCROW_NOT_FOUND",
+            CustomError::OwnershipError(_) => "OWNERSHIP",
+            CustomError::NestedSubAccountError(_) => "NESTED_SUB_ACCOUNT",
+            CustomError::LoanError(_) => "LOAN",
+            CustomError::GoalError(_) => "GOAL",
+            CustomError::ReviewNotFoundError(_) => "REVIEW_NOT_FOUND",
+            CustomError::CaptureExceedsHoldError(_) => "CAPTURE_EXCEEDS_HOLD",
+            CustomError::ScheduledTransferNotFoundError(_) => "SCHEDULED_TRANSFER_NOT_FOUND",
+            CustomError::StandingOrderNotFoundError(_) => "STANDING_ORDER_NOT_FOUND",
+            CustomError::LimitExceededError(_) => "LIMIT_EXCEEDED",
+            CustomError::TransferRejectedError(_) => "TRANSFER_REJECTED",
+            CustomError::TransferVetoedError(_) => "TRANSFER_VETOED",
+            CustomError::SelfTransferError(_) => "SELF_TRANSFER",
+            CustomError::ZeroAmountError(_) => "ZERO_AMOUNT",
+            CustomError::UnmergeableAccountError(_) => "UNMERGEABLE_ACCOUNT",
+            CustomError::ConfiguredNameError(_) => "CONFIGURED_NAME",
+            CustomError::ApprovalsRequiredError(_) => "APPROVALS_REQUIRED",
+            CustomError::ReviewRequiredError(_) => "REVIEW_REQUIRED",
+            CustomError::InvalidNameError(_) => "INVALID_ACCOUNT_NAME",
+            CustomError::LedgerEntryNotFoundError(_) => "LEDGER_ENTRY_NOT_FOUND",
+            CustomError::AttestationKeyMissingError(_) => "ATTESTATION_KEY_MISSING",
+            CustomError::InvalidPeriodError(_) => "INVALID_PERIOD",
+            CustomError::ExportError(_) => "EXPORT_FAILED",
+            CustomError::BackupError(_) => "BACKUP_FAILED",
+            CustomError::ImportError(_) => "IMPORT_FAILED",
+            CustomError::PaymentFileError(_) => "INVALID_PAYMENT_FILE",
+            CustomError::InterbankError(_) => "PEER_BANK_FAILED",
+            CustomError::IOError(_) => "IO_ERROR",
+            CustomError::ParseIntError(_) => "INVALID_AMOUNT",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Bank {
-    accounts: HashMap<String, Account>,
+    accounts: HashMap<AccountId, Account>,
+    /// Current name of every account, for lookups by name.
+    account_ids: HashMap<AccountName, AccountId>,
+    /// Accounts by the skeleton of their name, see `names::skeleton`. More
+    /// than one only for accounts opened before names were compared so.
+    skeletons: HashMap<String, BTreeSet<AccountId>>,
+    next_account_id: u64,
+    fx: FxRates,
+    ledger: Ledger,
+    holds: Holds,
+    escrows: Escrows,
+    /// Transfers out of joint accounts waiting for their owners.
+    approvals: Approvals,
+    loans: Loans,
+    goals: Goals,
+    /// Transfers waiting for an admin, see `review`.
+    reviews: Reviews,
+    review_threshold: Option<Money>,
+    /// Every change to accounts, the ledger and holds, see `events`.
+    events: EventLog,
+    /// Where the events are kept, if anywhere.
+    store: Option<Store>,
+    /// Last failure to write to the store. Cleared by a snapshot, which
+    /// has everything.
+    persistence_error: Option<String>,
+    /// Transactions committed since startup, replayed ones excluded.
+    activity: Activity,
+    scheduler: Scheduler,
+    fees: Option<FeeConfig>,
+    /// For accounts without limits of their own.
+    transfer_limits: TransferLimits,
+    fraud_rules: Vec<FraudRule>,
+    flagged: Vec<FlaggedTransaction>,
+    /// Transfers sent to peer banks that have not answered, by transaction,
+    /// see `interbank`.
+    unconfirmed: BTreeMap<TransactionId, UnconfirmedTransfer>,
+    hooks: Hooks,
+    /// Plugins by the instructions they handle.
+    plugin_instructions: HashMap<String, Arc<Guarded>>,
+    #[cfg(feature = "scripting")]
+    policy: policy::Policy,
+    interest_period_secs: u64,
+    /// Accounts kept for peer banks, see `interbank`.
+    settlement_accounts: BTreeSet<AccountId>,
+    /// Account names the configuration refers to, for PINs, fees and the
+    /// accounts of identities. Accounts are not renamed to or from them.
+    configured_names: BTreeSet<String>,
+    /// Zero settles only on demand.
+    settlement_period_secs: u64,
+    /// When the settlement accounts are next settled. Not kept across
+    /// restarts, the first period starts when the bank does.
+    next_settlement_at: Option<u64>,
+    /// Signs balance attestations, see `attestation`.
+    attestation_key: Option<SigningKey>,
+    webhooks: Webhooks,
+    /// Replicates the events instead of the store, see `cluster`.
+    #[cfg(feature = "replication")]
+    cluster: Option<Arc<dyn Replication>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl Bank {
-    fn new(accounts: Vec<Account>) -> Bank {
-        let mut bank = Bank {
-            accounts: HashMap::new(),
-        };
-        for account in accounts {
-            bank.accounts.insert(account.name.to_owned(), account);
-        }
-        bank
+    fn new() -> Bank {
+        Bank::with_clock(Arc::new(SystemClock))
     }
 
-    fn handle_transaction(&mut self, tx_info: TxInfo) -> Result<(), CustomError> {
-        if let Some([from, to]) = self.accounts.get_many_mut([&tx_info.from, &tx_info.to]) {
-            if from.has_sufficient_funds(tx_info.amount) {
-                from.subtract_funds(tx_info.amount);
-                to.add_funds(tx_info.amount);
-            } else {
-                return Err(CustomError::InsufficientFundsError(
-                    InsufficientFundsError {
-                        account_name: tx_info.from,
-                    },
-                ));
-            }
-        } else {
-            // Return proper error message
-            match (
-                self.accounts.contains_key(&tx_info.from),
-                self.accounts.contains_key(&tx_info.to),
-            ) {
-                (false, true) => {
-                    return Err(CustomError::AccountDoesNotExistError(
-                        AccountDoesNotExistError {
-                            account_name: AccountNamesTuple(tx_info.from, "".to_string()),
-                        },
-                    ))
-                }
-                (true, false) => {
-                    return Err(CustomError::AccountDoesNotExistError(
-                        AccountDoesNotExistError {
-                            account_name: AccountNamesTuple("".to_string(), tx_info.to),
-                        },
-                    ))
-                }
-                (false, false) => {
-                    return Err(CustomError::AccountDoesNotExistError(
-                        AccountDoesNotExistError {
-                            account_name: AccountNamesTuple(tx_info.from, tx_info.to),
-                        },
-                    ))
-                }
-                (true, true) => unreachable!(),
-            }
+    /// A bank without accounts that takes the time from `clock`.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Bank {
+        Bank {
+            accounts: HashMap::new(),
+            account_ids: HashMap::new(),
+            skeletons: HashMap::new(),
+            next_account_id: 1,
+            fx: FxRates::default(),
+            ledger: Ledger::default(),
+            holds: Holds::default(),
+            escrows: Escrows::default(),
+            approvals: Approvals::default(),
+            loans: Loans::default(),
+            goals: Goals::default(),
+            reviews: Reviews::default(),
+            review_threshold: None,
+            events: EventLog::default(),
+            store: None,
+            scheduler: Scheduler::default(),
+            fees: None,
+            transfer_limits: TransferLimits::default(),
+            fraud_rules: Vec::new(),
+            flagged: Vec::new(),
+            unconfirmed: BTreeMap::new(),
+            hooks: Hooks::default(),
+            plugin_instructions: HashMap::new(),
+            #[cfg(feature = "scripting")]
+            policy: policy::Policy::default(),
+            interest_period_secs: interest::DEFAULT_PERIOD_SECS,
+            settlement_accounts: BTreeSet::new(),
+            configured_names: BTreeSet::new(),
+            settlement_period_secs: settlement::DEFAULT_PERIOD_SECS,
+            next_settlement_at: None,
+            attestation_key: None,
+            webhooks: Webhooks::default(),
+            persistence_error: None,
+            activity: Activity::default(),
+            #[cfg(feature = "replication")]
+            cluster: None,
+            clock,
         }
-        Ok(())
     }
 
-    fn get_serialized_account_info(&self) -> Result<String, SerdeError> {
-        let mut accounts_map = VanillaHashMap::new();
-        for (_, acc) in &self.accounts {
-            accounts_map.insert(acc.name.as_str(), acc.balance);
-        }
-        serde_json::to_string(&accounts_map)
+    /// Seconds since the Unix epoch, by the bank's clock.
+    pub fn now(&self) -> u64 {
+        self.clock.now()
     }
-}
-
-#[derive(Debug)]
-struct BankError;
 
-impl Error for BankError {}
+    /// Run the work that is due: expiring holds and escrows, scheduled
+    /// transfers, standing orders, savings sweeps, interest, loan interest,
+    /// settlement and snapshots.
+    pub fn run_due_jobs(&mut self) {
+        let now = self.now();
+        self.expire_holds(now);
+        self.expire_escrows(now);
+        self.run_scheduled_transfers(now);
+        self.run_standing_orders(now);
+        self.sweep_to_goals(now);
+        self.accrue_interest(now);
+        self.charge_loan_interest(now);
+        self.settle_if_due(now);
+        self.snapshot_if_due();
+    }
 
-impl Display for BankError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Generic bank error")
+    /// Rebuild a bank from the events written by `write_events`.
+    pub fn replay(clock: Arc<dyn Clock>, input: impl io::BufRead) -> Result<Bank, EventLogError> {
+        let mut bank = Bank::with_clock(clock);
+        bank.apply_records(events::read(input)?)?;
+        Ok(bank)
     }
-}
 
-impl Display for Bank {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        self.accounts.iter().fold(Ok(()), |result, k_v| {
-            result.and_then(|_| writeln!(f, "{}, {}", k_v.1.name, k_v.1.balance))
-        })
+    /// Load the bank kept in `data_dir` and keep the changes to it there,
+    /// snapshotting it every `snapshot_every` events.
+    fn open(
+        clock: Arc<dyn Clock>,
+        data_dir: &Path,
+        snapshot_every: u64,
+    ) -> Result<Bank, StoreError> {
+        let (store, snapshot, records) = Store::open(data_dir, snapshot_every)?;
+        let mut bank = Bank::restore(clock, data_dir, snapshot, records)?;
+        bank.store = Some(store);
+        Ok(bank)
     }
-}
 
-fn create_socket(socket_location: &str) -> io::Result<UnixDatagram> {
-    let socket_path = Path::new(socket_location);
-    if socket_path.exists() {
-        fs::remove_file(socket_path)?;
+    /// The bank kept in `data_dir`, without writing to it.
+    fn load(clock: Arc<dyn Clock>, data_dir: &Path) -> Result<Bank, StoreError> {
+        // Reading a missing directory would give an empty bank.
+        data_dir.metadata().map_err(|source| StoreError::Io {
+            path: data_dir.to_owned(),
+            source,
+        })?;
+        let (snapshot, records) = store::read(data_dir)?;
+        Bank::restore(clock, data_dir, snapshot, records)
     }
-    match UnixDatagram::bind(socket_path) {
-        Err(e) => return Err(e),
-        Ok(listener) => return Ok(listener),
-    };
-}
 
-pub fn run_app(mut bank: Bank) -> Result<i8> {
-    info!("Entered the main loop of the program");
-    // Create the socket
-    const SOCK_SRC: &str = "/tmp/server2client.sock";
-    let socket = create_socket(SOCK_SRC)?;
-    info!("Created the socket");
+    fn restore(
+        clock: Arc<dyn Clock>,
+        data_dir: &Path,
+        snapshot: Option<Snapshot>,
+        records: Vec<EventRecord>,
+    ) -> Result<Bank, StoreError> {
+        let mut bank = match snapshot {
+            Some(snapshot) => Bank::from_snapshot(clock, snapshot),
+            None => Bank::with_clock(clock),
+        };
+        let replayed = records.len();
+        bank.apply_records(records)
+            .map_err(|source| StoreError::Events {
+                path: data_dir.to_owned(),
+                source,
+            })?;
+        info!(
+            "Loaded the bank up to event {}, replaying {replayed} events",
+            bank.events.last_sequence()
+        );
+        Ok(bank)
+    }
 
-    loop {
-        let mut instruction_buffer = vec![0; 1];
+    fn apply_records(&mut self, records: Vec<EventRecord>) -> Result<(), EventLogError> {
+        for record in records {
+            let sequence = record.sequence;
+            if sequence != self.events.last_sequence() + 1
+                || self.apply_event(&record.event, record.timestamp).is_none()
+            {
+                return Err(EventLogError::Inconsistent { sequence });
+            }
+            self.events.append(record.timestamp, record.event);
+        }
+        Ok(())
+    }
 
-        match socket.recv_from(instruction_buffer.as_mut_slice()) {
-            Ok((_, sender)) => {
-                let instruction = str::from_utf8(&instruction_buffer)?;
-                info!("Received '{instruction}' instruction from client");
+    /// Write out the events since the last snapshot, one JSON object per
+    /// line.
+    pub fn write_events(&self, out: impl io::Write) -> io::Result<()> {
+        self.events.write(out)
+    }
 
-                match instruction {
-                    "t" => {
-                        // Send OK response to client
-                        if let Some(sender_path) = sender.as_pathname() {
-                            socket.send_to("200".as_bytes(), sender_path)?;
-                            debug!("Sent '200' message to client");
-                        } else {
-                            error!("Unable to get client's socket path");
-                        }
-
-                        let mut tx_info_buffer = vec![0; 512];
-
-                        match socket.recv_from(tx_info_buffer.as_mut_slice()) {
-                            Ok(_) => {
-                                info!("Received transaction details from client");
-                                // Trim trailing 0 characters
-                                let tx_info = str::from_utf8(&tx_info_buffer)?
-                                    .trim_end_matches(char::from(0));
-                                let tx_info: TxInfo = serde_json::from_str(tx_info)?;
-                                bank.handle_transaction(tx_info)?;
-                                info!("Successfully performed transaction");
-                            }
-                            Err(e) => error!("Error while receiving transaction info: {e:?}"),
-                        }
-                    }
-                    "i" => {
-                        let serialized_acc_info = bank.get_serialized_account_info()?;
-                        if let Some(sender_path) = sender.as_pathname() {
-                            socket.send_to(serialized_acc_info.as_bytes(), sender_path)?;
-                        } else {
-                            println!("Unable to send message to client");
-                        }
+    /// Record `event` and apply it. The caller has checked that it can be
+    /// applied.
+    fn emit(&mut self, event: Event) -> u64 {
+        let timestamp = self.now();
+        let applied = self.apply_event(&event, timestamp);
+        debug_assert!(applied.is_some(), "inapplicable event {event:?}");
+        self.activity.record(&event);
+        let record = self.events.append(timestamp, event);
+        #[cfg(feature = "replication")]
+        if let Some(cluster) = &self.cluster {
+            // Stored once committed.
+            cluster.propose(record.clone());
+            return timestamp;
+        }
+        if let Some(store) = &mut self.store {
+            if let Err(e) = store.append(record) {
+                error!("Unable to store event {}: {e}", record.sequence);
+                self.persistence_error =
+                    Some(format!("Unable to store event {}: {e}", record.sequence));
+            }
+        }
+        timestamp
+    }
+
+    /// Replicate the events from now on rather than store them, see
+    /// `cluster`.
+    #[cfg(feature = "replication")]
+    fn set_cluster(&mut self, cluster: Arc<dyn Replication>) {
+        self.cluster = Some(cluster);
+    }
+
+    /// Apply events replicated by the cluster's leader, skipping those the
+    /// bank has, which are the same.
+    fn apply_replicated(&mut self, records: Vec<EventRecord>) -> Result<(), EventLogError> {
+        for record in records {
+            let sequence = record.sequence;
+            if sequence <= self.events.last_sequence() {
+                continue;
+            }
+            if sequence != self.events.last_sequence() + 1
+                || self.apply_event(&record.event, record.timestamp).is_none()
+            {
+                return Err(EventLogError::Inconsistent { sequence });
+            }
+            self.activity.record(&record.event);
+            self.events.append(record.timestamp, record.event);
+        }
+        Ok(())
+    }
+
+    /// Store the events after `stored` through `through`, which the cluster
+    /// committed.
+    fn store_committed(&mut self, stored: u64, through: u64) {
+        let Some(store) = &mut self.store else {
+            return;
+        };
+        for sequence in stored + 1..=through {
+            let Some(record) = self.events.get(sequence) else {
+                continue;
+            };
+            if let Err(e) = store.append(record) {
+                error!("Unable to store event {sequence}: {e}");
+                self.persistence_error = Some(format!("Unable to store event {sequence}: {e}"));
+            }
+        }
+    }
+
+    /// Whether events the bank applied are still to be stored, which a
+    /// snapshot must not take in before they are.
+    #[cfg(feature = "replication")]
+    fn has_unstored_events(&self) -> bool {
+        self.cluster
+            .as_ref()
+            .is_some_and(|cluster| cluster.stored() < self.events.last_sequence())
+    }
+
+    #[cfg(not(feature = "replication"))]
+    fn has_unstored_events(&self) -> bool {
+        false
+    }
+
+    fn snapshot(&self, audit_head: &crypto::Digest) -> Snapshot {
+        let mut accounts: Vec<Account> = self.accounts.values().cloned().collect();
+        accounts.sort_by_key(|account| account.id);
+        let mut holds: Vec<Hold> = self.holds.iter().cloned().collect();
+        holds.sort_by_key(|hold| hold.id);
+        Snapshot {
+            escrows: self.escrows.iter().cloned().collect(),
+            approvals: self.approvals.iter().cloned().collect(),
+            loans: self.loans.iter().cloned().collect(),
+            goals: self.goals.iter().cloned().collect(),
+            parked: self.reviews.iter().cloned().collect(),
+            scheduled: self.scheduler.iter().cloned().collect(),
+            standing_orders: self.scheduler.standing_orders().cloned().collect(),
+            last_schedule_ids: self.scheduler.last_ids(),
+            fx_rates: self.fx.rates(),
+            flagged: self.flagged.clone(),
+            unconfirmed: self.unconfirmed.values().cloned().collect(),
+            sequence: self.events.last_sequence(),
+            timestamp: self.now(),
+            accounts,
+            ledger: self.ledger.entries().to_vec(),
+            holds,
+            audit_hash: crypto::to_hex(audit_head),
+        }
+    }
+
+    fn from_snapshot(clock: Arc<dyn Clock>, snapshot: Snapshot) -> Bank {
+        let mut bank = Bank::with_clock(clock);
+        for account in snapshot.accounts {
+            bank.next_account_id = bank.next_account_id.max(account.id.0 + 1);
+            bank.index_name(account.name.clone(), account.id);
+            bank.accounts.insert(account.id, account);
+        }
+        bank.ledger = Ledger::from_entries(snapshot.ledger);
+        // Balances follow from the postings, whatever the snapshot says.
+        let balances = bank.ledger.balances();
+        for account in bank.accounts.values_mut() {
+            let key = (LedgerAccount::Customer(account.id), account.currency);
+            let total = balances.get(&key).copied().unwrap_or_default();
+            let balance = total.to_balance().unwrap_or(Balance::MAX);
+            if balance != account.balance {
+                warn!(
+                    "Snapshot has balance {} for account {}, its postings {balance}",
+                    account.balance, account.id
+                );
+                account.balance = balance;
+            }
+        }
+        for hold in snapshot.holds {
+            bank.holds.insert(hold);
+        }
+        for escrow in snapshot.escrows {
+            bank.escrows.insert(escrow);
+        }
+        for request in snapshot.approvals {
+            bank.approvals.insert(request);
+        }
+        for loan in snapshot.loans {
+            bank.loans.insert(loan);
+        }
+        for goal in snapshot.goals {
+            bank.goals.insert(goal);
+        }
+        for parked in snapshot.parked {
+            bank.reviews.insert(parked);
+        }
+        for scheduled in snapshot.scheduled {
+            bank.scheduler.insert(scheduled);
+        }
+        for order in snapshot.standing_orders {
+            bank.scheduler.insert_standing_order(order);
+        }
+        bank.scheduler.skip_ids(snapshot.last_schedule_ids);
+        for (from, to, rate) in snapshot.fx_rates {
+            bank.fx.set(from, to, rate);
+        }
+        bank.flagged = snapshot.flagged;
+        for unconfirmed in snapshot.unconfirmed {
+            bank.unconfirmed
+                .insert(unconfirmed.transfer.reference, unconfirmed);
+        }
+        bank.events = EventLog::after(snapshot.sequence);
+        bank
+    }
+
+    /// A copy of the bank, or a bank without accounts if `empty`, kept in
+    /// memory only, see `sandbox`.
+    fn sandbox(&self, empty: bool) -> Bank {
+        if empty {
+            return Bank::with_clock(Arc::clone(&self.clock));
+        }
+        Bank::from_snapshot(Arc::clone(&self.clock), self.snapshot(&audit::GENESIS))
+    }
+
+    /// Snapshot the bank and start the event log over if enough events
+    /// were stored since the last snapshot.
+    pub fn snapshot_if_due(&mut self) {
+        if self.has_unstored_events() {
+            return;
+        }
+        let Some(store) = self.store.as_ref().filter(|store| store.snapshot_due()) else {
+            return;
+        };
+        let snapshot = self.snapshot(store.audit_head());
+        let store = self.store.as_mut().unwrap();
+        match store.save_snapshot(&snapshot) {
+            Ok(()) => {
+                info!("Snapshotted the bank at event {}", snapshot.sequence);
+                self.events.compact();
+                self.persistence_error = None;
+            }
+            Err(e) => {
+                error!("Unable to snapshot the bank: {e}");
+                self.persistence_error = Some(format!("Unable to snapshot the bank: {e}"));
+            }
+        }
+    }
+
+    pub fn set_snapshot_every(&mut self, snapshot_every: u64) {
+        if let Some(store) = &mut self.store {
+            store.set_snapshot_every(snapshot_every);
+        }
+    }
+
+    /// The only place the state recorded by events changes. `None` if the
+    /// event refers to accounts, holds or transactions that do not exist,
+    /// in which case nothing changed.
+    fn apply_transfer(
+        &mut self,
+        transaction_id: TransactionId,
+        timestamp: u64,
+        movements: &[Movement],
+        reverses: Option<TransactionId>,
+        fee_for: Option<TransactionId>,
+    ) -> Option<()> {
+        if transaction_id != self.ledger.next_transaction_id() {
+            return None;
+        }
+        let mut balances = VanillaHashMap::new();
+        for posting in movements.iter().flat_map(Movement::postings) {
+            let LedgerAccount::Customer(id) = posting.account else {
+                continue;
+            };
+            let balance = match balances.get(&id) {
+                Some(balance) => *balance,
+                None => self.accounts.get(&id)?.balance,
+            };
+            let balance = match posting.side {
+                Side::Credit => balance.checked_add(posting.amount)?,
+                Side::Debit => balance.checked_sub(posting.amount)?,
+            };
+            balances.insert(id, balance);
+        }
+        for (id, balance) in balances {
+            self.accounts.get_mut(&id).unwrap().balance = balance;
+        }
+        self.ledger.append(
+            transaction_id,
+            timestamp,
+            movements.to_vec(),
+            reverses,
+            fee_for,
+        );
+        Some(())
+    }
+
+    fn apply_event(&mut self, event: &Event, timestamp: u64) -> Option<()> {
+        match event {
+            Event::AccountOpened {
+                account,
+                name,
+                currency,
+            } => {
+                if self.accounts.contains_key(account)
+                    || self.account_ids.contains_key(name.as_str())
+                {
+                    return None;
+                }
+                let opened = Account::new(*account, name.as_str().into(), *currency, timestamp);
+                self.index_name(opened.name.clone(), *account);
+                self.accounts.insert(*account, opened);
+                self.next_account_id = self.next_account_id.max(account.0 + 1);
+            }
+            Event::SubAccountOpened {
+                account,
+                name,
+                parent,
+            } => {
+                if self.accounts.contains_key(account)
+                    || self.account_ids.contains_key(name.as_str())
+                {
+                    return None;
+                }
+                let currency = self.accounts.get(parent)?.currency;
+                let mut opened = Account::new(*account, name.as_str().into(), currency, timestamp);
+                opened.parent = Some(*parent);
+                self.index_name(opened.name.clone(), *account);
+                self.accounts.insert(*account, opened);
+                self.next_account_id = self.next_account_id.max(account.0 + 1);
+            }
+            Event::FundsTransferred {
+                transaction_id,
+                movements,
+                reverses,
+                fee_for,
+            } => self.apply_transfer(*transaction_id, timestamp, movements, *reverses, *fee_for)?,
+            Event::AccountRenamed { account, name } => {
+                if self.account_ids.contains_key(name.as_str()) {
+                    return None;
+                }
+                let old = self.accounts.get(account)?.name.clone();
+                let name: AccountName = name.as_str().into();
+                self.account_ids.remove(&old);
+                let skeleton = names::skeleton(&old);
+                if let Some(accounts) = self.skeletons.get_mut(&skeleton) {
+                    accounts.remove(account);
+                    if accounts.is_empty() {
+                        self.skeletons.remove(&skeleton);
                     }
-                    "q" => return Ok(1),
-                    _ => unreachable!(),
+                }
+                self.index_name(name.clone(), *account);
+                self.accounts.get_mut(account)?.name = name;
+            }
+            Event::AccountMerged {
+                account,
+                into,
+                transaction_id,
+                movements,
+            } => {
+                if account == into || !self.accounts.contains_key(into) {
+                    return None;
+                }
+                self.accounts.get(account)?;
+                if let Some(transaction_id) = transaction_id {
+                    self.apply_transfer(*transaction_id, timestamp, movements, None, None)?;
+                }
+                let merged = self.accounts.get_mut(account).unwrap();
+                merged.status = AccountStatus::Closed;
+                merged.merged_into = Some(*into);
+            }
+            Event::StatusChanged { account, status } => {
+                self.accounts.get_mut(account)?.status = *status;
+            }
+            Event::OverdraftLimitSet { account, limit } => {
+                self.accounts.get_mut(account)?.overdraft_limit = *limit;
+            }
+            Event::TransferLimitsSet { account, limits } => {
+                self.accounts.get_mut(account)?.limits = *limits;
+            }
+            Event::MetadataUpdated { account, metadata } => {
+                self.accounts.get_mut(account)?.metadata = metadata.clone();
+            }
+            Event::InterestChanged { account, interest } => {
+                self.accounts.get_mut(account)?.interest = *interest;
+            }
+            Event::HoldPlaced { hold } => {
+                let account = self.accounts.get_mut(&hold.account)?;
+                account.held = account.held.checked_add(hold.amount)?;
+                self.holds.insert(hold.clone());
+            }
+            Event::HoldReleased { hold_id } => {
+                let hold = self.holds.get(*hold_id)?;
+                let account = self.accounts.get_mut(&hold.account)?;
+                account.held = account.held.checked_sub(hold.amount).unwrap_or(Money::ZERO);
+                self.holds.remove(*hold_id);
+            }
+            Event::EscrowOpened { escrow } => {
+                if self.escrows.get(escrow.id).is_some()
+                    || !self.accounts.contains_key(&escrow.payer)
+                    || !self.accounts.contains_key(&escrow.beneficiary)
+                {
+                    return None;
+                }
+                self.escrows.insert(escrow.clone());
+            }
+            Event::EscrowClosed { escrow_id } => {
+                self.escrows.remove(*escrow_id)?;
+            }
+            Event::OwnersChanged {
+                account,
+                owners,
+                approvals_required,
+            } => {
+                let account = self.accounts.get_mut(account)?;
+                account.owners = owners.clone();
+                account.approvals_required = *approvals_required;
+            }
+            Event::ApprovalRequested { request } => {
+                if self.approvals.get(request.approval_id).is_some()
+                    || !self.accounts.contains_key(&request.account)
+                {
+                    return None;
+                }
+                self.approvals.insert(request.clone());
+            }
+            Event::TransferApproved { approval_id, owner } => {
+                let request = self.approvals.get_mut(*approval_id)?;
+                request.approved_by.insert(owner.clone());
+            }
+            Event::ApprovalClosed { approval_id } => {
+                self.approvals.remove(*approval_id)?;
+            }
+            Event::LoanIssued { loan } => {
+                if self.loans.get(loan.id).is_some()
+                    || !self.accounts.contains_key(&loan.account)
+                    || !self.accounts.contains_key(&loan.borrower)
+                {
+                    return None;
+                }
+                self.loans.insert(loan.clone());
+            }
+            Event::LoanInterestCharged {
+                loan_id,
+                installments_charged,
+            } => {
+                self.loans.get_mut(*loan_id)?.installments_charged = *installments_charged;
+            }
+            Event::GoalCreated { goal } => {
+                if self.goals.get(goal.id).is_some()
+                    || !self.accounts.contains_key(&goal.account)
+                    || !self.accounts.contains_key(&goal.sub_account)
+                {
+                    return None;
+                }
+                self.goals.insert(goal.clone());
+            }
+            Event::GoalSwept {
+                goal_id,
+                next_sweep_at,
+            } => {
+                self.goals.get_mut(*goal_id)?.next_sweep_at = *next_sweep_at;
+            }
+            Event::GoalDeleted { goal_id } => {
+                self.goals.remove(*goal_id)?;
+            }
+            Event::TransferParked { parked } => {
+                if self.reviews.get(parked.review_id).is_some() {
+                    return None;
+                }
+                self.reviews.insert(parked.clone());
+            }
+            Event::ReviewClosed { review_id } => {
+                self.reviews.remove(*review_id)?;
+            }
+            Event::TransferScheduled { scheduled } => {
+                if self.scheduler.get(scheduled.schedule_id).is_some() {
+                    return None;
+                }
+                self.scheduler.insert(scheduled.clone());
+            }
+            Event::ScheduledTransferClosed { schedule_id } => {
+                self.scheduler.remove(*schedule_id)?;
+            }
+            Event::StandingOrderCreated { order } => {
+                if self.scheduler.standing_order(order.order_id).is_some() {
+                    return None;
+                }
+                self.scheduler.insert_standing_order(order.clone());
+            }
+            Event::StandingOrderRescheduled {
+                order_id,
+                next_run_at,
+                retry_at,
+            } => {
+                let order = self.scheduler.standing_order_mut(*order_id)?;
+                order.next_run_at = *next_run_at;
+                order.retry_at = *retry_at;
+            }
+            Event::StandingOrderDeleted { order_id } => {
+                self.scheduler.remove_standing_order(*order_id)?;
+            }
+            Event::ExchangeRateSet { from, to, rate } => {
+                if from == to {
+                    return None;
+                }
+                self.fx.set(*from, *to, *rate);
+            }
+            Event::TransactionFlagged { flagged } => {
+                self.flagged.push(flagged.clone());
+            }
+            Event::PeerTransferUnconfirmed { unconfirmed } => {
+                self.unconfirmed
+                    .insert(unconfirmed.transfer.reference, unconfirmed.clone());
+            }
+            Event::PeerTransferConfirmed { reference } => {
+                self.unconfirmed.remove(reference)?;
+            }
+        }
+        Some(())
+    }
+
+    fn resolve(&self, account: &AccountRef) -> Option<AccountId> {
+        match account {
+            AccountRef::Id(id) => self.accounts.contains_key(id).then_some(*id),
+            AccountRef::Name(name) => self
+                .account_ids
+                .get(name.as_str())
+                .or_else(|| self.account_ids.get(names::normalize(name).as_str()))
+                .copied(),
+        }
+    }
+
+    fn account(&self, account: &AccountRef) -> Result<&Account, CustomError> {
+        match self.resolve(account) {
+            Some(id) => Ok(&self.accounts[&id]),
+            None => Err(CustomError::AccountDoesNotExistError(
+                AccountDoesNotExistError {
+                    account_name: AccountNamesTuple(account.to_string(), "".to_string()),
+                },
+            )),
+        }
+    }
+
+    fn index_name(&mut self, name: AccountName, account: AccountId) {
+        self.skeletons
+            .entry(names::skeleton(&name))
+            .or_default()
+            .insert(account);
+        self.account_ids.insert(name, account);
+    }
+
+    /// `name` as normalized, if it is valid and no account but `renamed`
+    /// has it or a name that looks like it.
+    fn new_name(&self, name: &str, renamed: Option<AccountId>) -> Result<String, CustomError> {
+        let name = names::validate(name)?;
+        if self.account_ids.contains_key(name.as_str()) {
+            return Err(CustomError::AccountAlreadyExistsError(
+                AccountAlreadyExistsError {
+                    account_name: name.into(),
+                },
+            ));
+        }
+        let look_alike = self
+            .skeletons
+            .get(&names::skeleton(&name))
+            .and_then(|accounts| accounts.iter().find(|id| Some(**id) != renamed));
+        if let Some(id) = look_alike {
+            let account_name = self.accounts[id].name.to_string();
+            return Err(InvalidNameError::LooksLike(account_name).into());
+        }
+        Ok(name)
+    }
+
+    /// Current name of the account, if it exists.
+    fn account_name(&self, account: &AccountRef) -> Option<&str> {
+        self.resolve(account).map(|id| &*self.accounts[&id].name)
+    }
+
+    fn open_account(
+        &mut self,
+        name: String,
+        balance: Balance,
+        currency: Currency,
+    ) -> Result<AccountId, CustomError> {
+        let name = self.new_name(&name, None)?;
+        let id = AccountId(self.next_account_id);
+        self.emit(Event::AccountOpened {
+            account: id,
+            name,
+            currency,
+        });
+        // Opening balances are never negative.
+        if let Some(amount) = balance.to_money().filter(|amount| !amount.is_zero()) {
+            let movement = Movement {
+                from: None,
+                to: Some(id),
+                system: Some(SystemAccount::Capital),
+                amount,
+                currency,
+                rate: None,
+                memo: None,
+                external_ref: None,
+            };
+            self.record_transfer(vec![movement], Vec::new(), None);
+        }
+        Ok(id)
+    }
+
+    /// Check a transfer against everything but the balances, which depend on
+    /// whatever else is applied with it, and work out what it would move.
+    fn plan_transfer(
+        &self,
+        tx_info: TxInfo,
+        checks: Checks,
+    ) -> Result<PlannedTransfer, CustomError> {
+        let account_name = |account: &AccountRef| {
+            self.account_name(account)
+                .map_or_else(|| account.to_string(), str::to_string)
+        };
+        let request = TransferRequest {
+            from: account_name(&tx_info.from),
+            to: account_name(&tx_info.to),
+            amount: tx_info.amount,
+            currency: tx_info.currency,
+            memo: tx_info.memo.clone(),
+            external_ref: tx_info.external_ref.clone(),
+        };
+        self.hooks.check(&request).map_err(|reason| {
+            CustomError::TransferVetoedError(TransferVetoedError {
+                account_name: request.from.as_str().into(),
+                reason,
+            })
+        })?;
+        let (from, to) = match (self.resolve(&tx_info.from), self.resolve(&tx_info.to)) {
+            (Some(from_id), Some(to_id)) => (&self.accounts[&from_id], &self.accounts[&to_id]),
+            // Return proper error message
+            (None, Some(_)) => {
+                return Err(CustomError::AccountDoesNotExistError(
+                    AccountDoesNotExistError {
+                        account_name: AccountNamesTuple(tx_info.from.to_string(), "".to_string()),
+                    },
+                ))
+            }
+            (Some(_), None) => {
+                return Err(CustomError::AccountDoesNotExistError(
+                    AccountDoesNotExistError {
+                        account_name: AccountNamesTuple("".to_string(), tx_info.to.to_string()),
+                    },
+                ))
+            }
+            (None, None) => {
+                return Err(CustomError::AccountDoesNotExistError(
+                    AccountDoesNotExistError {
+                        account_name: AccountNamesTuple(
+                            tx_info.from.to_string(),
+                            tx_info.to.to_string(),
+                        ),
+                    },
+                ))
+            }
+        };
+        if from.id == to.id {
+            return Err(CustomError::SelfTransferError(SelfTransferError {
+                account_name: from.name.clone(),
+            }));
+        }
+        if tx_info.amount.is_zero() {
+            return Err(CustomError::ZeroAmountError(ZeroAmountError {
+                account_name: from.name.clone(),
+            }));
+        }
+        from.ensure_can_send()?;
+        to.ensure_can_receive()?;
+        if matches!(checks, Checks::All | Checks::Pin) && !from.verify_pin(tx_info.pin.as_deref()) {
+            return Err(CustomError::InvalidPinError(InvalidPinError {
+                account_name: from.name.clone(),
+            }));
+        }
+        if matches!(checks, Checks::All | Checks::Waits) {
+            self.ensure_need_not_wait(from, tx_info.amount)?;
+        }
+        let tx_currency = tx_info.currency.unwrap_or(from.currency);
+        if tx_currency != from.currency || (tx_currency != to.currency && !tx_info.convert) {
+            return Err(CustomError::CurrencyMismatchError(CurrencyMismatchError {
+                tx_currency,
+                from_currency: from.currency,
+                to_currency: to.currency,
+            }));
+        }
+        let (credited, rate) = if from.currency == to.currency {
+            (tx_info.amount, None)
+        } else {
+            let rate = self.fx.rate(from.currency, to.currency).ok_or(
+                CustomError::NoExchangeRateError(NoExchangeRateError {
+                    from_currency: from.currency,
+                    to_currency: to.currency,
+                }),
+            )?;
+            let converted = self
+                .fx
+                .convert(tx_info.amount, from.currency, to.currency)
+                .ok_or_else(|| {
+                    CustomError::BalanceOverflowError(BalanceOverflowError {
+                        account_name: to.name.clone(),
+                    })
+                })?;
+            (converted, Some(rate))
+        };
+        let movements = match rate {
+            None => vec![Movement {
+                from: Some(from.id),
+                to: Some(to.id),
+                system: None,
+                amount: tx_info.amount,
+                currency: tx_currency,
+                rate: None,
+                memo: tx_info.memo,
+                external_ref: tx_info.external_ref,
+            }],
+            // Both legs go through the FX desk, outside of the accounts.
+            Some(rate) => vec![
+                Movement {
+                    from: Some(from.id),
+                    to: None,
+                    system: Some(SystemAccount::FxDesk),
+                    amount: tx_info.amount,
+                    currency: from.currency,
+                    rate: Some(rate),
+                    memo: tx_info.memo.clone(),
+                    external_ref: tx_info.external_ref.clone(),
+                },
+                Movement {
+                    from: None,
+                    to: Some(to.id),
+                    system: Some(SystemAccount::FxDesk),
+                    amount: credited,
+                    currency: to.currency,
+                    rate: Some(rate),
+                    memo: tx_info.memo,
+                    external_ref: tx_info.external_ref,
+                },
+            ],
+        };
+        // Moving money between envelopes is free.
+        let (fee, fee_movements) = if Bank::same_family(from, to) {
+            (None, Vec::new())
+        } else {
+            self.plan_fee(from, tx_info.amount)?
+        };
+        Ok(PlannedTransfer {
+            from: from.id,
+            to: to.id,
+            amount: tx_info.amount,
+            currency: from.currency,
+            credited_amount: credited,
+            credited_currency: to.currency,
+            rate,
+            movements,
+            fee,
+            fee_movements,
+        })
+    }
+
+    /// The fee for sending `amount` from `from` and the movements that
+    /// credit it to the fee account, converted if that account keeps
+    /// another currency.
+    fn plan_fee(
+        &self,
+        from: &Account,
+        amount: Amount,
+    ) -> Result<(Option<Amount>, Vec<Movement>), CustomError> {
+        let Some(config) = &self.fees else {
+            return Ok((None, Vec::new()));
+        };
+        let Some(fee_account) = self
+            .account_ids
+            .get(config.account.as_str())
+            .map(|id| &self.accounts[id])
+            .filter(|fee_account| fee_account.id != from.id)
+        else {
+            return Ok((None, Vec::new()));
+        };
+        let Some(fee) = config.fee(amount, &from.metadata.tags) else {
+            return Ok((None, Vec::new()));
+        };
+        let memo = Some(fees::MEMO.to_string());
+        if from.currency == fee_account.currency {
+            let movement = Movement {
+                from: Some(from.id),
+                to: Some(fee_account.id),
+                system: None,
+                amount: fee,
+                currency: from.currency,
+                rate: None,
+                memo,
+                external_ref: None,
+            };
+            return Ok((Some(fee), vec![movement]));
+        }
+        let rate = self.fx.rate(from.currency, fee_account.currency).ok_or(
+            CustomError::NoExchangeRateError(NoExchangeRateError {
+                from_currency: from.currency,
+                to_currency: fee_account.currency,
+            }),
+        )?;
+        let converted = self
+            .fx
+            .convert(fee, from.currency, fee_account.currency)
+            .ok_or_else(|| {
+                CustomError::BalanceOverflowError(BalanceOverflowError {
+                    account_name: fee_account.name.clone(),
+                })
+            })?;
+        let movements = vec![
+            Movement {
+                from: Some(from.id),
+                to: None,
+                system: Some(SystemAccount::FxDesk),
+                amount: fee,
+                currency: from.currency,
+                rate: Some(rate),
+                memo: memo.clone(),
+                external_ref: None,
+            },
+            Movement {
+                from: None,
+                to: Some(fee_account.id),
+                system: Some(SystemAccount::FxDesk),
+                amount: converted,
+                currency: fee_account.currency,
+                rate: Some(rate),
+                memo,
+                external_ref: None,
+            },
+        ];
+        Ok((Some(fee), movements))
+    }
+
+    /// Remember the account names the configuration refers to, see
+    /// `rename_account`.
+    pub fn set_configured_names(&mut self, names: BTreeSet<String>) {
+        self.configured_names = names;
+    }
+
+    /// Charge fees as configured; `None` makes transfers free.
+    pub fn set_fees(&mut self, fees: Option<FeeConfig>) {
+        if let Some(config) = &fees {
+            if !self.account_ids.contains_key(config.account.as_str()) {
+                warn!(
+                    "Fee account '{}' does not exist, no fees are charged",
+                    config.account
+                );
+            }
+        }
+        self.fees = fees;
+    }
+
+    /// Run `hook` before every transfer is validated; an error vetoes the
+    /// transfer with that reason.
+    pub fn add_pre_transaction_hook(
+        &mut self,
+        hook: impl Fn(&TransferRequest) -> Result<(), String> + Send + Sync + 'static,
+    ) {
+        self.hooks.add_pre_transaction(Box::new(hook));
+    }
+
+    /// Run `hook` after every transfer is committed.
+    pub fn add_post_commit_hook(
+        &mut self,
+        hook: impl Fn(&CommittedTransfer) + Send + Sync + 'static,
+    ) {
+        self.hooks.add_post_commit(Box::new(hook));
+    }
+
+    /// Attach a plugin: it can veto transfers before they are validated,
+    /// sees them after they are committed and handles its instructions.
+    pub fn register_plugin(&mut self, plugin: impl Plugin + 'static) -> Result<(), PluginError> {
+        let plugin = Guarded::new(Box::new(plugin));
+        let instructions = plugin.instructions(|instruction| {
+            self.plugin_instructions
+                .get(instruction)
+                .map(|taken| taken.name().to_string())
+        })?;
+        for instruction in instructions {
+            self.plugin_instructions
+                .insert(instruction, Arc::clone(&plugin));
+        }
+        let checker = Arc::clone(&plugin);
+        self.add_pre_transaction_hook(move |request| checker.check_transfer(request));
+        self.add_post_commit_hook(move |transfer| plugin.transfer_committed(transfer));
+        Ok(())
+    }
+
+    fn plugin_for(&self, instruction: &str) -> Option<Arc<Guarded>> {
+        self.plugin_instructions.get(instruction).cloned()
+    }
+
+    fn notify_committed(
+        &self,
+        transfer: &PlannedTransfer,
+        transaction_id: TransactionId,
+        timestamp: u64,
+    ) {
+        let committed = CommittedTransfer {
+            transaction_id,
+            timestamp,
+            from: transfer.from,
+            to: transfer.to,
+            amount: transfer.amount,
+            currency: transfer.currency,
+            credited_amount: transfer.credited_amount,
+            credited_currency: transfer.credited_currency,
+            fee: transfer.fee,
+        };
+        self.hooks.notify(&committed);
+        self.webhooks.deliver(&committed);
+    }
+
+    fn handle_transaction(&mut self, tx_info: TxInfo) -> Result<Receipt, CustomError> {
+        let dry_run = tx_info.dry_run;
+        // Dry runs check the transfer itself, not whether it would wait.
+        let checks = if dry_run { Checks::Pin } else { Checks::All };
+        let transfer = self.plan_transfer(tx_info, checks)?;
+        if dry_run {
+            return self.preview_transfer(transfer);
+        }
+        self.commit_transfer(transfer, None)
+    }
+
+    /// Book a transfer to `account` at `peer` to the peer's settlement
+    /// account and forward it, see `interbank`. The transfer and its fee are
+    /// reversed if the peer does not book it, and kept as unconfirmed if it
+    /// does not say.
+    fn send_to_peer(
+        &mut self,
+        peers: &Peers,
+        bank_name: &str,
+        (peer, account): (&str, &str),
+        mut tx_info: TxInfo,
+    ) -> Result<Receipt, CustomError> {
+        let config = peers
+            .get(peer)
+            .ok_or_else(|| InterbankError::UnknownPeer(peer.to_string()))?;
+        tx_info.to = AccountRef::Name(config.settlement_account.clone());
+        let memo = tx_info.memo.clone();
+        let receipt = self.handle_transaction(tx_info)?;
+        if receipt.dry_run {
+            return Ok(receipt);
+        }
+        let transfer = InterbankTransfer {
+            bank: bank_name.to_string(),
+            from: self.accounts[&receipt.from].name.to_string(),
+            to: account.to_string(),
+            amount: receipt.credited_amount,
+            currency: receipt.credited_currency,
+            reference: receipt.transaction_id,
+            memo,
+        };
+        match peers.forward(peer, &transfer) {
+            Ok(()) => Ok(receipt),
+            Err(e @ InterbankError::Unconfirmed { .. }) => {
+                warn!("{e}, keeping it as unconfirmed");
+                let unconfirmed = UnconfirmedTransfer {
+                    peer: peer.to_string(),
+                    transfer,
                 };
+                self.emit(Event::PeerTransferUnconfirmed { unconfirmed });
+                Err(e.into())
+            }
+            Err(e) => Err(self.reverse_peer_transfer(receipt.transaction_id, e).into()),
+        }
+    }
+
+    /// Reverse a transfer to a peer's settlement account and its fee, for
+    /// `error`, the reason the peer did not book it. Returns `error`, with
+    /// why the reversal failed if it did.
+    fn reverse_peer_transfer(
+        &mut self,
+        transaction_id: TransactionId,
+        error: InterbankError,
+    ) -> InterbankError {
+        warn!("Reversing transaction {transaction_id} not booked by peer bank: {error}");
+        let fee = self.ledger.fee_of(transaction_id);
+        let failures: Vec<String> = [Some(transaction_id), fee]
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.reverse(id).err())
+            .map(|e| e.to_string())
+            .collect();
+        if failures.is_empty() {
+            return error;
+        }
+        let error = InterbankError::NotReversed {
+            transaction_id,
+            original: error.to_string(),
+            reversal: failures.join(", "),
+        };
+        error!("{error}");
+        error
+    }
+
+    /// Send the unconfirmed transfers to their peers again, and reverse
+    /// those the peers reject. A peer that cannot be reached or refuses the
+    /// instruction may still have booked them earlier.
+    fn confirm_peer_transfers(&mut self, peers: &Peers) {
+        let unconfirmed: Vec<UnconfirmedTransfer> = self.unconfirmed.values().cloned().collect();
+        for UnconfirmedTransfer { peer, transfer } in unconfirmed {
+            let reference = transfer.reference;
+            match peers.forward(&peer, &transfer) {
+                Ok(()) => info!("Peer bank '{peer}' confirmed transaction {reference}"),
+                Err(e @ InterbankError::Rejected { .. }) => {
+                    self.reverse_peer_transfer(reference, e);
+                }
+                Err(e) => {
+                    warn!("Transaction {reference} stays unconfirmed: {e}");
+                    continue;
+                }
+            }
+            self.emit(Event::PeerTransferConfirmed { reference });
+        }
+    }
+
+    /// Book a transfer forwarded by a peer bank from its settlement account.
+    /// It is free, the sending bank charges its own fees. A transfer booked
+    /// before, which the peer sends again for not having had an answer, is
+    /// not booked again, its receipt is the one of the booking.
+    fn receive_from_peer(
+        &mut self,
+        peers: &Peers,
+        transfer: InterbankTransfer,
+    ) -> Result<Receipt, CustomError> {
+        let config = peers
+            .get(&transfer.bank)
+            .ok_or_else(|| InterbankError::UnknownPeer(transfer.bank.clone()))?;
+        let external_ref = transfer.external_ref();
+        let settlement_account = AccountRef::Name(config.settlement_account.clone());
+        let from = self.account(&settlement_account)?.id;
+        if let Some(booked) = self.ledger.with_external_ref(from, &external_ref) {
+            info!("Transfer {external_ref} from peer bank was booked as transaction {booked}");
+            return Ok(self.receipt_of(booked));
+        }
+        let tx_info = TxInfo {
+            from: settlement_account,
+            to: AccountRef::Name(transfer.to),
+            amount: transfer.amount,
+            currency: Some(transfer.currency),
+            convert: true,
+            pin: None,
+            memo: transfer.memo,
+            external_ref: Some(external_ref),
+            idempotency_key: None,
+            dry_run: false,
+        };
+        let mut planned = self.plan_transfer(tx_info, Checks::Nothing)?;
+        planned.fee = None;
+        planned.fee_movements.clear();
+        self.commit_transfer(planned, None)
+    }
+
+    /// Receipt of a transfer made earlier, with the balances as they are
+    /// now.
+    fn receipt_of(&self, transaction_id: TransactionId) -> Receipt {
+        let entries = self
+            .ledger
+            .transaction(transaction_id)
+            .expect("transaction is in the ledger");
+        let (first, last) = (&entries[0], &entries[entries.len() - 1]);
+        let (from, to) = (first.from.unwrap(), last.to.unwrap());
+        Receipt {
+            transaction_id,
+            timestamp: first.timestamp,
+            from,
+            to,
+            amount: first.amount,
+            currency: first.currency,
+            credited_amount: last.amount,
+            credited_currency: last.currency,
+            rate: entries.iter().find_map(|entry| entry.rate),
+            fee: None,
+            from_balance: self.accounts[&from].balance,
+            to_balance: self.accounts[&to].balance,
+            dry_run: false,
+        }
+    }
+
+    /// Open the settlement account kept for a peer bank if it is missing,
+    /// and give it the peer's credit limit as its overdraft limit.
+    fn open_settlement_account(&mut self, config: &PeerBankConfig) -> Result<(), CustomError> {
+        let name = &config.settlement_account;
+        if !self.account_ids.contains_key(name.as_str()) {
+            self.open_account(name.clone(), Balance::ZERO, config.currency)?;
+        }
+        let limit = config.credit_limit.unwrap_or(Money::MAX);
+        let account = AccountRef::Name(name.clone());
+        let id = self.account(&account)?.id;
+        if self.accounts[&id].overdraft_limit != limit {
+            self.set_overdraft_limit(&account, limit)?;
+        }
+        self.settlement_accounts.insert(id);
+        Ok(())
+    }
+
+    pub fn set_settlement_period(&mut self, period_secs: u64) {
+        if period_secs != self.settlement_period_secs {
+            self.next_settlement_at = None;
+        }
+        self.settlement_period_secs = period_secs;
+    }
+
+    fn settle_if_due(&mut self, now: u64) {
+        if self.settlement_period_secs == 0 || self.settlement_accounts.is_empty() {
+            return;
+        }
+        match self.next_settlement_at {
+            Some(due) if due <= now => {
+                self.settle();
+            }
+            Some(_) => return,
+            None => {}
+        }
+        self.next_settlement_at = Some(now + self.settlement_period_secs);
+    }
+
+    /// Net the obligations of every settlement account and settle them with
+    /// a transfer from or to the clearing account, see `settlement`. Returns
+    /// the batches closed.
+    fn settle(&mut self) -> Vec<SettlementBatch> {
+        let mut settled = Vec::new();
+        for id in self.settlement_accounts.clone() {
+            let account = &self.accounts[&id];
+            let currency = account.currency;
+            let (from, to, amount) = match account.balance.to_money() {
+                Some(amount) => (Some(id), None, amount),
+                None => (None, Some(id), account.balance.magnitude()),
+            };
+            if amount.is_zero() {
+                continue;
+            }
+            let movement = Movement {
+                from,
+                to,
+                system: Some(SystemAccount::Clearing),
+                amount,
+                currency,
+                rate: None,
+                memo: Some(settlement::MEMO.to_string()),
+                external_ref: None,
+            };
+            if let Err(e) = self.check_movements(std::slice::from_ref(&movement), None) {
+                error!("Could not settle account {id}: {e}");
+                continue;
+            }
+            self.record_transfer(vec![movement], Vec::new(), None);
+            let batch = settlement::batches(self.ledger.entries(), id, currency)
+                .pop()
+                .expect("the settling transfer closes a batch");
+            info!(
+                "Settled {} obligations of account {id}, net {} {currency}",
+                batch.obligations.len(),
+                batch.net
+            );
+            settled.push(batch);
+        }
+        settled
+    }
+
+    /// Settled and open batches of a settlement account, oldest first.
+    fn settlement_batches(
+        &self,
+        account: &AccountRef,
+    ) -> Result<Vec<SettlementBatch>, CustomError> {
+        let account = self.account(account)?;
+        Ok(settlement::batches(
+            self.ledger.entries(),
+            account.id,
+            account.currency,
+        ))
+    }
+
+    /// Check that `from` may send `amount` on top of `pending`, which is
+    /// about to be sent but not in the ledger yet.
+    fn check_limits(
+        &self,
+        from: AccountId,
+        amount: Amount,
+        pending: Amount,
+    ) -> Result<(), CustomError> {
+        let account = &self.accounts[&from];
+        let limits = account.limits.unwrap_or(self.transfer_limits);
+        let window_start = self.now().saturating_sub(limits::WINDOW_SECS);
+        let outflow = self
+            .ledger
+            .outflow_after(from, window_start)
+            .checked_add(pending)
+            .unwrap_or(Money::MAX);
+        limits.check(amount, outflow).map_err(|(kind, limit)| {
+            CustomError::LimitExceededError(LimitExceededError {
+                account_name: account.name.clone(),
+                kind,
+                limit,
+            })
+        })
+    }
+
+    /// Screen a transfer against the fraud rules. Fails if a rejecting rule
+    /// matches, otherwise returns why it should be flagged, if at all.
+    fn screen(&self, transfer: &PlannedTransfer) -> Result<Vec<String>, CustomError> {
+        let now = self.now();
+        let from = &self.accounts[&transfer.from];
+        let limits = from.limits.unwrap_or(self.transfer_limits);
+        let mut reasons = Vec::new();
+        for rule in &self.fraud_rules {
+            let matches = match &rule.condition {
+                Condition::Velocity {
+                    max_transfers,
+                    window_secs,
+                } => {
+                    let window_start = now.saturating_sub(*window_secs);
+                    self.ledger.transfers_after(from.id, window_start) >= *max_transfers
+                }
+                Condition::JustUnder { threshold, margin } => {
+                    threshold.or(limits.max_transfer).is_some_and(|threshold| {
+                        fraud::just_under(transfer.amount, threshold, *margin)
+                    })
+                }
+                Condition::NewPayee { min_age_secs } => {
+                    let opened_at = self.accounts[&transfer.to].metadata.created_at;
+                    now.saturating_sub(opened_at) < *min_age_secs
+                }
+            };
+            if !matches {
+                continue;
+            }
+            match rule.action {
+                Action::Reject => {
+                    return Err(CustomError::TransferRejectedError(TransferRejectedError {
+                        account_name: from.name.clone(),
+                        reason: rule.condition.to_string(),
+                    }))
+                }
+                Action::Flag => reasons.push(rule.condition.to_string()),
+            }
+        }
+        #[cfg(feature = "scripting")]
+        {
+            let to = &self.accounts[&transfer.to];
+            fn account_facts(account: &Account) -> policy::AccountFacts<'_> {
+                policy::AccountFacts {
+                    name: &account.name,
+                    status: account.status.as_str(),
+                    balance: account.balance,
+                    tags: &account.metadata.tags,
+                }
             }
-            Err(e) => println!("accept function failed: {e:?}"),
+            let memo = transfer
+                .movements
+                .first()
+                .and_then(|movement| movement.memo.as_deref());
+            let facts = policy::Facts {
+                amount: transfer.amount,
+                currency: transfer.currency,
+                memo,
+                from: account_facts(from),
+                to: account_facts(to),
+            };
+            for rule in self.policy.matching(&facts) {
+                match rule.action {
+                    Action::Reject => {
+                        return Err(CustomError::TransferRejectedError(TransferRejectedError {
+                            account_name: from.name.clone(),
+                            reason: rule.reason.clone(),
+                        }))
+                    }
+                    Action::Flag => reasons.push(rule.reason.clone()),
+                }
+            }
+        }
+        Ok(reasons)
+    }
+
+    #[cfg(feature = "scripting")]
+    fn set_policy(&mut self, policy: policy::Policy) {
+        self.policy = policy;
+    }
+
+    fn flag(&mut self, transaction_id: TransactionId, timestamp: u64, reasons: Vec<String>) {
+        if reasons.is_empty() {
+            return;
         }
+        warn!(
+            "Flagged transaction {transaction_id} for review: {}",
+            reasons.join(", ")
+        );
+        self.emit(Event::TransactionFlagged {
+            flagged: FlaggedTransaction {
+                transaction_id,
+                timestamp,
+                reasons,
+            },
+        });
+    }
+
+    pub fn set_review_threshold(&mut self, threshold: Option<Money>) {
+        self.review_threshold = threshold;
+    }
+
+    /// Whether the transfer waits for an admin, see `review`.
+    fn needs_review(&self, tx_info: &TxInfo) -> bool {
+        self.review_threshold
+            .is_some_and(|threshold| tx_info.amount > threshold)
+    }
+
+    /// Refuse to send `amount` from `from` other than through the approval
+    /// of its owners or the review of an admin, when it needs either.
+    fn ensure_need_not_wait(&self, from: &Account, amount: Amount) -> Result<(), CustomError> {
+        if from.approvals_required > 1 {
+            return Err(CustomError::ApprovalsRequiredError(
+                ApprovalsRequiredError {
+                    account_name: from.name.clone(),
+                    approvals_required: from.approvals_required,
+                },
+            ));
+        }
+        match self.review_threshold {
+            Some(threshold) if amount > threshold => {
+                Err(CustomError::ReviewRequiredError(ReviewRequiredError {
+                    account_name: from.name.clone(),
+                    threshold,
+                }))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the transfer and keep it until an admin approves or rejects
+    /// it.
+    fn park_transfer(
+        &mut self,
+        tx_info: TxInfo,
+        requested_by: Option<&str>,
+        checks: Checks,
+    ) -> Result<ParkedTransfer, CustomError> {
+        let transfer = self.plan_transfer(tx_info.clone(), checks)?;
+        let parked = ParkedTransfer {
+            review_id: self.reviews.next_id(),
+            transfer: TxInfo {
+                from: AccountRef::Id(transfer.from),
+                to: AccountRef::Id(transfer.to),
+                pin: None,
+                ..tx_info
+            },
+            requested_by: requested_by.map(str::to_string),
+            parked_at: self.now(),
+        };
+        self.emit(Event::TransferParked {
+            parked: parked.clone(),
+        });
+        Ok(parked)
+    }
+
+    fn parked_transfers(&self) -> Vec<&ParkedTransfer> {
+        self.reviews.iter().collect()
+    }
+
+    fn approve_parked(&mut self, review_id: ReviewId) -> Result<Receipt, CustomError> {
+        let parked = self
+            .reviews
+            .get(review_id)
+            .ok_or(ReviewNotFoundError(review_id))?;
+        let transfer = self.plan_transfer(parked.transfer.clone(), Checks::Nothing)?;
+        let receipt = self.commit_transfer(transfer, None)?;
+        self.emit(Event::ReviewClosed { review_id });
+        Ok(receipt)
+    }
+
+    fn reject_parked(&mut self, review_id: ReviewId) -> Result<(), CustomError> {
+        if self.reviews.get(review_id).is_none() {
+            return Err(ReviewNotFoundError(review_id).into());
+        }
+        self.emit(Event::ReviewClosed { review_id });
+        Ok(())
+    }
+
+    /// Transactions flagged by the fraud rules, oldest first.
+    fn flagged_transactions(&self) -> &[FlaggedTransaction] {
+        &self.flagged
+    }
+
+    pub fn set_fraud_rules(&mut self, rules: Vec<FraudRule>) {
+        self.fraud_rules = rules;
+    }
+
+    pub fn set_attestation_key(&mut self, key: Option<SigningKey>) {
+        self.attestation_key = key;
+    }
+
+    /// Deliver committed transfers to `webhooks` from now on.
+    fn set_webhooks(&mut self, webhooks: Webhooks) {
+        self.webhooks = webhooks;
+    }
+
+    /// Wait for the webhooks of committed transfers to be delivered.
+    fn finish_webhooks(self) {
+        self.webhooks.finish();
+    }
+
+    /// Make a planned transfer, releasing `released` first when it is the
+    /// hold being captured.
+    fn commit_transfer(
+        &mut self,
+        transfer: PlannedTransfer,
+        released: Option<&Hold>,
+    ) -> Result<Receipt, CustomError> {
+        self.check_limits(transfer.from, transfer.amount, Money::ZERO)?;
+        let reasons = self.screen(&transfer)?;
+        let all_movements: Vec<Movement> = transfer
+            .movements
+            .iter()
+            .chain(&transfer.fee_movements)
+            .cloned()
+            .collect();
+        self.check_movements(&all_movements, released)?;
+        let (transaction_id, timestamp) = self.record_transfer(
+            transfer.movements.clone(),
+            transfer.fee_movements.clone(),
+            released.map(|hold| hold.id),
+        );
+        self.flag(transaction_id, timestamp, reasons);
+        self.notify_committed(&transfer, transaction_id, timestamp);
+        Ok(self.receipt(&transfer, transaction_id, timestamp))
+    }
+
+    /// Receipt of a transfer just committed.
+    fn receipt(
+        &self,
+        transfer: &PlannedTransfer,
+        transaction_id: TransactionId,
+        timestamp: u64,
+    ) -> Receipt {
+        Receipt {
+            transaction_id,
+            timestamp,
+            from: transfer.from,
+            to: transfer.to,
+            amount: transfer.amount,
+            currency: transfer.currency,
+            credited_amount: transfer.credited_amount,
+            credited_currency: transfer.credited_currency,
+            rate: transfer.rate,
+            fee: transfer.fee,
+            from_balance: self.accounts[&transfer.from].balance,
+            to_balance: self.accounts[&transfer.to].balance,
+            dry_run: false,
+        }
+    }
+
+    /// Check a transfer like `commit_transfer` does, without committing it.
+    /// The receipt is the one committing it now would give.
+    fn preview_transfer(&self, transfer: PlannedTransfer) -> Result<Receipt, CustomError> {
+        self.check_limits(transfer.from, transfer.amount, Money::ZERO)?;
+        self.screen(&transfer)?;
+        let all_movements: Vec<Movement> = transfer
+            .movements
+            .iter()
+            .chain(&transfer.fee_movements)
+            .cloned()
+            .collect();
+        let accounts = self.simulate_movements(&all_movements, None)?;
+        let balance = |id: AccountId| {
+            accounts
+                .get(&id)
+                .map_or(self.accounts[&id].balance, |account| account.balance)
+        };
+        Ok(Receipt {
+            transaction_id: self.ledger.next_transaction_id(),
+            timestamp: self.now(),
+            from: transfer.from,
+            to: transfer.to,
+            amount: transfer.amount,
+            currency: transfer.currency,
+            credited_amount: transfer.credited_amount,
+            credited_currency: transfer.credited_currency,
+            rate: transfer.rate,
+            fee: transfer.fee,
+            from_balance: balance(transfer.from),
+            to_balance: balance(transfer.to),
+            dry_run: true,
+        })
+    }
+
+    /// Record checked movements as a transaction, with the fees charged for
+    /// it as another, after releasing the hold `released`. Returns the
+    /// transaction's ID and timestamp.
+    fn record_transfer(
+        &mut self,
+        movements: Vec<Movement>,
+        fee_movements: Vec<Movement>,
+        released: Option<HoldId>,
+    ) -> (TransactionId, u64) {
+        if let Some(hold_id) = released {
+            self.emit(Event::HoldReleased { hold_id });
+        }
+        let transaction_id = self.ledger.next_transaction_id();
+        let timestamp = self.emit(Event::FundsTransferred {
+            transaction_id,
+            movements,
+            reverses: None,
+            fee_for: None,
+        });
+        if !fee_movements.is_empty() {
+            self.emit(Event::FundsTransferred {
+                transaction_id: self.ledger.next_transaction_id(),
+                movements: fee_movements,
+                reverses: None,
+                fee_for: Some(transaction_id),
+            });
+        }
+        (transaction_id, timestamp)
+    }
+
+    /// Apply all transfers or, if any of them fails, none. Later transfers
+    /// see the balances left by earlier ones. The batch is a single
+    /// transaction in the ledger.
+    fn handle_batch(&mut self, transfers: Vec<TxInfo>) -> Result<BatchReceipt, CustomError> {
+        if transfers.is_empty() {
+            return Err(CustomError::EmptyBatchError(EmptyBatchError));
+        }
+        let count = transfers.len();
+        let mut movements = Vec::new();
+        let mut fee_movements = Vec::new();
+        let mut pending: VanillaHashMap<AccountId, Amount> = VanillaHashMap::new();
+        let mut reasons = Vec::new();
+        let mut planned = Vec::with_capacity(count);
+        for tx_info in transfers {
+            let transfer = self.plan_transfer(tx_info, Checks::All)?;
+            let sent = pending.entry(transfer.from).or_insert(Money::ZERO);
+            self.check_limits(transfer.from, transfer.amount, *sent)?;
+            *sent = sent.checked_add(transfer.amount).unwrap_or(Money::MAX);
+            // Split up, a transfer must not slip under the review threshold.
+            self.ensure_need_not_wait(&self.accounts[&transfer.from], *sent)?;
+            reasons.extend(self.screen(&transfer)?);
+            movements.extend(transfer.movements.iter().cloned());
+            fee_movements.extend(transfer.fee_movements.iter().cloned());
+            planned.push(transfer);
+        }
+        let all_movements: Vec<Movement> =
+            movements.iter().chain(&fee_movements).cloned().collect();
+        self.check_movements(&all_movements, None)?;
+        let (transaction_id, timestamp) = self.record_transfer(movements, fee_movements, None);
+        reasons.dedup();
+        self.flag(transaction_id, timestamp, reasons);
+        for transfer in &planned {
+            self.notify_committed(transfer, transaction_id, timestamp);
+        }
+        Ok(BatchReceipt {
+            transaction_id,
+            timestamp,
+            transfers: count,
+        })
+    }
+
+    /// Apply each transfer on its own, checking those within a shard on
+    /// `workers` threads, see `shards`. Returns the outcome of each, in
+    /// order.
+    fn handle_independent(
+        &mut self,
+        transfers: Vec<TxInfo>,
+        workers: usize,
+    ) -> Vec<Result<Receipt, CustomError>> {
+        let mut outcomes: Vec<Option<Result<Receipt, CustomError>>> =
+            transfers.iter().map(|_| None).collect();
+        // Positions in the batch of the transfers to check in shards.
+        let mut planned: Vec<(usize, PlannedTransfer)> = Vec::new();
+        let mut later = Vec::new();
+        for (position, tx_info) in transfers.into_iter().enumerate() {
+            if tx_info.dry_run {
+                later.push((position, tx_info));
+                continue;
+            }
+            match self.plan_transfer(tx_info, Checks::All) {
+                Ok(transfer) => planned.push((position, transfer)),
+                Err(e) => outcomes[position] = Some(Err(e)),
+            }
+        }
+        let touched: Vec<Vec<AccountId>> = planned
+            .iter()
+            .map(|(_, transfer)| {
+                transfer
+                    .movements
+                    .iter()
+                    .chain(&transfer.fee_movements)
+                    .flat_map(|movement| movement.from.into_iter().chain(movement.to))
+                    .collect()
+            })
+            .collect();
+        let partition = shards::partition(&touched, workers);
+
+        let bank = &*self;
+        let checked: Vec<(usize, Result<Vec<String>, CustomError>)> = thread::scope(|scope| {
+            let workers: Vec<_> = partition
+                .shards
+                .iter()
+                .filter(|shard| !shard.is_empty())
+                .map(|shard| scope.spawn(|| bank.check_in_shard(shard, &planned)))
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("a shard worker panicked"))
+                .collect()
+        });
+        let mut checked: VanillaHashMap<usize, Result<Vec<String>, CustomError>> =
+            checked.into_iter().collect();
+        // Checked against balances nothing else in the batch touches, so
+        // they are committed as they are.
+        for (index, (position, transfer)) in planned.iter().enumerate() {
+            let Some(result) = checked.remove(&index) else {
+                continue;
+            };
+            outcomes[*position] = Some(result.map(|reasons| {
+                let (transaction_id, timestamp) = self.record_transfer(
+                    transfer.movements.clone(),
+                    transfer.fee_movements.clone(),
+                    None,
+                );
+                self.flag(transaction_id, timestamp, reasons);
+                self.notify_committed(transfer, transaction_id, timestamp);
+                self.receipt(transfer, transaction_id, timestamp)
+            }));
+        }
+        let mut planned: Vec<Option<(usize, PlannedTransfer)>> =
+            planned.into_iter().map(Some).collect();
+        for index in partition.cross_shard {
+            let (position, transfer) = planned[index].take().unwrap();
+            outcomes[position] = Some(self.commit_transfer(transfer, None));
+        }
+        for (position, tx_info) in later {
+            outcomes[position] = Some(self.handle_transaction(tx_info));
+        }
+        outcomes
+            .into_iter()
+            .map(|outcome| outcome.expect("every transfer has an outcome"))
+            .collect()
+    }
+
+    /// Check the transfers at `positions` of `planned` in order, each
+    /// against the balances the ones before it leave, see `shards`.
+    fn check_in_shard(
+        &self,
+        positions: &[usize],
+        planned: &[(usize, PlannedTransfer)],
+    ) -> Vec<(usize, Result<Vec<String>, CustomError>)> {
+        let mut accounts: VanillaHashMap<AccountId, Account> = VanillaHashMap::new();
+        let mut pending: VanillaHashMap<AccountId, Amount> = VanillaHashMap::new();
+        positions
+            .iter()
+            .map(|&index| {
+                let transfer = &planned[index].1;
+                let sent = pending.get(&transfer.from).copied().unwrap_or(Money::ZERO);
+                let checked = self
+                    .check_limits(transfer.from, transfer.amount, sent)
+                    .and_then(|()| self.screen(transfer))
+                    .and_then(|reasons| {
+                        let all_movements: Vec<Movement> = transfer
+                            .movements
+                            .iter()
+                            .chain(&transfer.fee_movements)
+                            .cloned()
+                            .collect();
+                        let after =
+                            self.simulate_movements_over(&accounts, &all_movements, None)?;
+                        accounts.extend(after);
+                        Ok(reasons)
+                    });
+                if checked.is_ok() {
+                    pending.insert(
+                        transfer.from,
+                        sent.checked_add(transfer.amount).unwrap_or(Money::MAX),
+                    );
+                }
+                (index, checked)
+            })
+            .collect()
+    }
+
+    /// Apply the transfers of the ISO 20022 pain.001 file at `path` as one
+    /// batch, see `pain001`.
+    fn import_payments(&mut self, path: &Path) -> Result<BatchReceipt, CustomError> {
+        let file = pain001::read(path)?;
+        info!(
+            "Applying payment file {} with {} transfers",
+            file.message_id,
+            file.transfers.len()
+        );
+        let transfers = file
+            .transfers
+            .into_iter()
+            .map(|transfer| TxInfo {
+                from: AccountRef::Name(transfer.debtor_account),
+                to: AccountRef::Name(transfer.creditor_account),
+                amount: transfer.amount,
+                currency: Some(transfer.currency),
+                convert: false,
+                pin: None,
+                memo: transfer.remittance_information,
+                external_ref: transfer.end_to_end_id,
+                idempotency_key: None,
+                dry_run: false,
+            })
+            .collect();
+        self.handle_batch(transfers)
+    }
+
+    /// Open an empty sub-account of `parent`, in its currency. Sub-accounts
+    /// have no sub-accounts of their own.
+    fn open_sub_account(
+        &mut self,
+        parent: &AccountRef,
+        name: String,
+    ) -> Result<AccountId, CustomError> {
+        let parent = self.account(parent)?;
+        parent.ensure_can_receive()?;
+        if let Some(grandparent) = parent.parent {
+            return Err(CustomError::NestedSubAccountError(NestedSubAccountError {
+                account_name: parent.name.clone(),
+                parent: grandparent,
+            }));
+        }
+        let name = self.new_name(&name, None)?;
+        let parent = parent.id;
+        let id = AccountId(self.next_account_id);
+        self.emit(Event::SubAccountOpened {
+            account: id,
+            name,
+            parent,
+        });
+        Ok(id)
+    }
+
+    /// The sub-accounts of `parent`, by ID.
+    fn sub_accounts(&self, parent: AccountId) -> impl Iterator<Item = &Account> {
+        let mut sub_accounts: Vec<&Account> = self
+            .accounts
+            .values()
+            .filter(|account| account.parent == Some(parent))
+            .collect();
+        sub_accounts.sort_by_key(|account| account.id);
+        sub_accounts.into_iter()
+    }
+
+    /// Names of the sub-accounts of the accounts named `parents`.
+    fn sub_account_names(&self, parents: &[String]) -> Vec<String> {
+        parents
+            .iter()
+            .filter_map(|parent| self.account_ids.get(parent.as_str()))
+            .flat_map(|parent| self.sub_accounts(*parent))
+            .map(|account| account.name.to_string())
+            .collect()
+    }
+
+    /// The account's balance with those of its sub-accounts.
+    fn total_balance(&self, account: &Account) -> Balance {
+        let mut total = Total::default();
+        total.add_balance(account.balance);
+        for sub_account in self.sub_accounts(account.id) {
+            total.add_balance(sub_account.balance);
+        }
+        total.to_balance().unwrap_or(Balance::MAX)
+    }
+
+    /// Whether `a` and `b` are a parent and its sub-account or two
+    /// sub-accounts of the same parent.
+    fn same_family(a: &Account, b: &Account) -> bool {
+        a.parent.unwrap_or(a.id) == b.parent.unwrap_or(b.id)
+    }
+
+    fn create_account(
+        &mut self,
+        name: String,
+        balance: Amount,
+        currency: Currency,
+    ) -> Result<AccountId, CustomError> {
+        let balance = Balance::try_from(balance).map_err(|_| {
+            CustomError::BalanceOverflowError(BalanceOverflowError {
+                account_name: name.as_str().into(),
+            })
+        })?;
+        self.open_account(name, balance, currency)
+    }
+
+    /// Open the accounts listed in the file at `path`, see `import`. Rows
+    /// that cannot be imported are logged and reported.
+    pub fn import_accounts(&mut self, path: &Path) -> Result<ImportReport, ImportError> {
+        let mut report = ImportReport::default();
+        for (row, record) in import::read(path, &mut report)? {
+            let name = record.name.clone();
+            let opened = self
+                .create_account(record.name, record.balance, record.currency)
+                .and_then(|id| {
+                    if record.display_name.is_none()
+                        && record.email.is_none()
+                        && record.tags.is_empty()
+                    {
+                        return Ok(());
+                    }
+                    self.update_metadata(
+                        &AccountRef::Id(id),
+                        record.display_name,
+                        record.email,
+                        Some(record.tags),
+                    )
+                });
+            match opened {
+                Ok(()) => report.imported += 1,
+                Err(e) => report.reject(row, Some(name), e),
+            }
+        }
+        report.rejected.sort_by_key(|rejected| rejected.row);
+        for rejected in &report.rejected {
+            warn!(
+                "Rejected row {} of {}: {}",
+                rejected.row,
+                path.display(),
+                rejected.reason
+            );
+        }
+        info!(
+            "Imported {} accounts from {}, rejected {}",
+            report.imported,
+            path.display(),
+            report.rejected.len()
+        );
+        Ok(report)
+    }
+
+    fn mint(&mut self, account: &AccountRef, amount: Amount) -> Result<(), CustomError> {
+        let account = self.account(account)?;
+        let movement = Movement {
+            from: None,
+            to: Some(account.id),
+            system: Some(SystemAccount::Capital),
+            amount,
+            currency: account.currency,
+            rate: None,
+            memo: None,
+            external_ref: None,
+        };
+        self.check_movements(std::slice::from_ref(&movement), None)?;
+        self.record_transfer(vec![movement], Vec::new(), None);
+        Ok(())
+    }
+
+    /// Replace the PINs of all accounts: listed accounts get the given Argon2id
+    /// hash, every other account loses its PIN.
+    pub fn set_pins(&mut self, pin_hashes: &VanillaHashMap<String, String>) {
+        for name in pin_hashes.keys() {
+            if !self.account_ids.contains_key(name.as_str()) {
+                warn!("Ignoring PIN for unknown account '{name}'");
+            }
+        }
+        for account in self.accounts.values_mut() {
+            account.pin_hash = pin_hashes.get(&*account.name).cloned();
+        }
+    }
+
+    fn set_overdraft_limit(
+        &mut self,
+        account: &AccountRef,
+        limit: Amount,
+    ) -> Result<(), CustomError> {
+        let account = self.account(account)?.id;
+        self.emit(Event::OverdraftLimitSet { account, limit });
+        Ok(())
+    }
+
+    /// Interest accrues at the new rate from the end of the last period it
+    /// was posted for, or from now if the account earned none so far.
+    fn set_interest_rate(
+        &mut self,
+        account: &AccountRef,
+        rate: Option<Rate>,
+    ) -> Result<(), CustomError> {
+        let now = self.now();
+        let account = self.account(account)?;
+        let interest = rate.map(|rate| match account.interest {
+            Some(accrual) => Accrual { rate, ..accrual },
+            None => Accrual::new(rate, now),
+        });
+        self.emit(Event::InterestChanged {
+            account: account.id,
+            interest,
+        });
+        Ok(())
+    }
+
+    pub fn set_interest_period(&mut self, period_secs: u64) {
+        self.interest_period_secs = period_secs;
+    }
+
+    /// Post the interest for the accrual periods that ended by `now`.
+    /// Closed accounts earn nothing.
+    pub fn accrue_interest(&mut self, now: u64) {
+        let mut ids: Vec<AccountId> = self.accounts.keys().copied().collect();
+        ids.sort();
+        for id in ids {
+            let account = &self.accounts[&id];
+            let Some(mut accrual) = account.interest else {
+                continue;
+            };
+            if account.status == AccountStatus::Closed {
+                continue;
+            }
+            let accrued_until = accrual.accrued_until;
+            let interest = accrual.accrue(account.balance, self.interest_period_secs, now);
+            let currency = account.currency;
+            if accrual.accrued_until != accrued_until {
+                self.emit(Event::InterestChanged {
+                    account: id,
+                    interest: Some(accrual),
+                });
+            }
+            let Some(interest) = interest else {
+                continue;
+            };
+            let movement = Movement {
+                from: None,
+                to: Some(id),
+                system: Some(SystemAccount::Interest),
+                amount: interest,
+                currency,
+                rate: None,
+                memo: Some(interest::MEMO.to_string()),
+                external_ref: None,
+            };
+            if let Err(e) = self.check_movements(std::slice::from_ref(&movement), None) {
+                error!("Could not post interest: {e}");
+                continue;
+            }
+            self.record_transfer(vec![movement], Vec::new(), None);
+            info!("Posted {interest} {currency} interest to account {id}");
+        }
+    }
+
+    /// Attach a savings goal to `account`, saving in a new sub-account of it
+    /// named `name`, see `goals`.
+    fn create_goal(
+        &mut self,
+        account: &AccountRef,
+        name: &str,
+        target: Amount,
+        keep: Amount,
+        period_secs: Option<u64>,
+    ) -> Result<Goal, CustomError> {
+        if target.is_zero() {
+            return Err(GoalError::InvalidTarget.into());
+        }
+        let parent = self.account(account)?;
+        let sub_account_name = format!("{}/{name}", parent.name);
+        let account = parent.id;
+        let sub_account = self.open_sub_account(&AccountRef::Id(account), sub_account_name)?;
+        let period_secs = period_secs.unwrap_or(goals::DEFAULT_PERIOD_SECS).max(1);
+        let goal = Goal {
+            id: self.goals.next_id(),
+            account,
+            sub_account,
+            target,
+            keep,
+            period_secs,
+            next_sweep_at: self.now().saturating_add(period_secs),
+        };
+        self.emit(Event::GoalCreated { goal: goal.clone() });
+        Ok(goal)
+    }
+
+    fn goal(&self, goal_id: GoalId) -> Result<&Goal, CustomError> {
+        Ok(self
+            .goals
+            .get(goal_id)
+            .ok_or(GoalError::NotFound(goal_id))?)
+    }
+
+    fn goal_progress(&self, goal: &Goal) -> GoalProgress {
+        let saved = self.accounts[&goal.sub_account]
+            .balance
+            .to_money()
+            .unwrap_or(Money::ZERO);
+        GoalProgress::new(goal.clone(), saved)
+    }
+
+    fn goals_of(&self, account: &AccountRef) -> Result<Vec<GoalProgress>, CustomError> {
+        let account = self.account(account)?.id;
+        Ok(self
+            .goals
+            .iter()
+            .filter(|goal| goal.account == account)
+            .map(|goal| self.goal_progress(goal))
+            .collect())
+    }
+
+    fn delete_goal(&mut self, goal_id: GoalId) -> Result<(), CustomError> {
+        self.goal(goal_id)?;
+        self.emit(Event::GoalDeleted { goal_id });
+        Ok(())
+    }
+
+    /// Move what the accounts hold above what their goals keep into the
+    /// goals that are due for a sweep by `now`, up to their targets.
+    pub fn sweep_to_goals(&mut self, now: u64) {
+        for goal_id in self.goals.due(now) {
+            let goal = self.goals.get(goal_id).unwrap().clone();
+            let account = &self.accounts[&goal.account];
+            let surplus = account
+                .balance
+                .checked_sub(account.held)
+                .and_then(|available| available.checked_sub(goal.keep))
+                .and_then(Balance::to_money)
+                .unwrap_or(Money::ZERO);
+            let amount = surplus.min(self.goal_progress(&goal).remaining);
+            if !amount.is_zero() && account.status == AccountStatus::Active {
+                let movement = Movement {
+                    from: Some(goal.account),
+                    to: Some(goal.sub_account),
+                    system: None,
+                    amount,
+                    currency: account.currency,
+                    rate: None,
+                    memo: Some(goals::MEMO.to_string()),
+                    external_ref: None,
+                };
+                match self.check_movements(std::slice::from_ref(&movement), None) {
+                    Ok(()) => {
+                        self.record_transfer(vec![movement], Vec::new(), None);
+                        info!("Swept {amount} into goal {goal_id}");
+                    }
+                    Err(e) => error!("Could not sweep into goal {goal_id}: {e}"),
+                }
+            }
+            // Sweeps missed while the bank was down are not made up for.
+            let mut next_sweep_at = goal.next_sweep_at;
+            while next_sweep_at <= now {
+                next_sweep_at = next_sweep_at.saturating_add(goal.period_secs);
+            }
+            self.emit(Event::GoalSwept {
+                goal_id,
+                next_sweep_at,
+            });
+        }
+    }
+
+    /// Open a loan account and move `principal` from it to `borrower`, see
+    /// `loans`.
+    fn issue_loan(
+        &mut self,
+        borrower: &AccountRef,
+        principal: Money,
+        rate: Rate,
+        installments: u32,
+        period_secs: Option<u64>,
+    ) -> Result<LoanInfo, CustomError> {
+        if principal.is_zero() || installments == 0 {
+            return Err(LoanError::InvalidTerms.into());
+        }
+        let borrower = self.account(borrower)?;
+        borrower.ensure_can_receive()?;
+        let id = self.loans.next_id();
+        // Named after the loan, unless an account has that name or one like
+        // it.
+        let name = (1..)
+            .map(|n| match n {
+                1 => id.to_string(),
+                n => format!("{id}-{n}"),
+            })
+            .find_map(|name| self.new_name(&name, None).ok())
+            .expect("names run out only with the integers");
+        let loan = Loan {
+            id,
+            account: AccountId(self.next_account_id),
+            borrower: borrower.id,
+            principal,
+            currency: borrower.currency,
+            rate,
+            installments,
+            period_secs: period_secs.unwrap_or(loans::DEFAULT_PERIOD_SECS).max(1),
+            issued_at: self.now(),
+            installments_charged: 0,
+        };
+        loans::schedule(&loan).ok_or(LoanError::Unschedulable { principal })?;
+        let movement = Movement {
+            from: Some(loan.account),
+            to: Some(loan.borrower),
+            system: None,
+            amount: principal,
+            currency: loan.currency,
+            rate: None,
+            memo: Some(loans::MEMO.to_string()),
+            external_ref: None,
+        };
+        if borrower.balance.checked_add(principal).is_none() {
+            return Err(CustomError::BalanceOverflowError(BalanceOverflowError {
+                account_name: borrower.name.clone(),
+            }));
+        }
+        self.emit(Event::AccountOpened {
+            account: loan.account,
+            name,
+            currency: loan.currency,
+        });
+        // Interest adds to the debt, there is no limit to it.
+        self.emit(Event::OverdraftLimitSet {
+            account: loan.account,
+            limit: Money::MAX,
+        });
+        self.record_transfer(vec![movement], Vec::new(), None);
+        self.emit(Event::LoanIssued { loan: loan.clone() });
+        Ok(self.loan_info(&loan))
+    }
+
+    fn loan(&self, loan_id: LoanId) -> Result<LoanInfo, CustomError> {
+        let loan = self
+            .loans
+            .get(loan_id)
+            .ok_or(LoanError::NotFound(loan_id))?;
+        Ok(self.loan_info(loan))
+    }
+
+    fn loan_info(&self, loan: &Loan) -> LoanInfo {
+        let balance = self.accounts[&loan.account].balance;
+        let outstanding = if balance.is_negative() {
+            balance.magnitude()
+        } else {
+            Money::ZERO
+        };
+        LoanInfo {
+            loan: loan.clone(),
+            outstanding,
+            schedule: loans::schedule(loan).unwrap_or_default(),
+        }
+    }
+
+    /// Charge the interest for the installments that fell due by `now` on
+    /// what is outstanding of each loan. Repaid loans are charged nothing.
+    pub fn charge_loan_interest(&mut self, now: u64) {
+        let loans: Vec<Loan> = self.loans.iter().cloned().collect();
+        for loan in loans {
+            let mut charged = loan.installments_charged;
+            while loan.due_at(charged + 1) <= now {
+                charged += 1;
+                let balance = self.accounts[&loan.account].balance;
+                if !balance.is_negative() {
+                    continue;
+                }
+                let Some(interest) = loan
+                    .interest(balance.magnitude())
+                    .filter(|interest| !interest.is_zero())
+                else {
+                    continue;
+                };
+                let movement = Movement {
+                    from: Some(loan.account),
+                    to: None,
+                    system: Some(SystemAccount::Interest),
+                    amount: interest,
+                    currency: loan.currency,
+                    rate: None,
+                    memo: Some(loans::INTEREST_MEMO.to_string()),
+                    external_ref: None,
+                };
+                if let Err(e) = self.check_movements(std::slice::from_ref(&movement), None) {
+                    error!("Could not charge interest on loan {}: {e}", loan.id);
+                    continue;
+                }
+                self.record_transfer(vec![movement], Vec::new(), None);
+                info!(
+                    "Charged {interest} {} interest on loan {}",
+                    loan.currency, loan.id
+                );
+            }
+            if charged != loan.installments_charged {
+                self.emit(Event::LoanInterestCharged {
+                    loan_id: loan.id,
+                    installments_charged: charged,
+                });
+            }
+        }
+    }
+
+    fn set_transfer_limits(
+        &mut self,
+        account: &AccountRef,
+        limits: Option<TransferLimits>,
+    ) -> Result<(), CustomError> {
+        let account = self.account(account)?.id;
+        self.emit(Event::TransferLimitsSet { account, limits });
+        Ok(())
+    }
+
+    pub fn set_default_transfer_limits(&mut self, limits: TransferLimits) {
+        self.transfer_limits = limits;
+    }
+
+    fn set_status(
+        &mut self,
+        account: &AccountRef,
+        status: AccountStatus,
+    ) -> Result<(), CustomError> {
+        let account = self.account(account)?.id;
+        self.emit(Event::StatusChanged { account, status });
+        Ok(())
+    }
+
+    /// Give the account a new name. Its ID stays, and with it its history
+    /// and whatever refers to the account by ID. The configuration refers
+    /// to accounts by name, so names it mentions are neither given up nor
+    /// taken, or a reload would hand a PIN or a customer's access to
+    /// another account.
+    fn rename_account(&mut self, account: &AccountRef, name: String) -> Result<(), CustomError> {
+        let account = self.account(account)?;
+        let name = self.new_name(&name, Some(account.id))?;
+        let configured = [&*account.name, name.as_str()]
+            .into_iter()
+            .find(|name| self.configured_names.contains(*name))
+            .map(AccountName::from)
+            .or_else(|| {
+                (account.pin_hash.is_some() || self.settlement_accounts.contains(&account.id))
+                    .then(|| account.name.clone())
+            });
+        if let Some(account_name) = configured {
+            return Err(CustomError::ConfiguredNameError(ConfiguredNameError {
+                account_name,
+            }));
+        }
+        let account = account.id;
+        self.emit(Event::AccountRenamed { account, name });
+        Ok(())
+    }
+
+    /// Move the balance of `account` into `into`, even past the overdraft
+    /// limit of `into` if it was negative, and close `account`, all in one
+    /// event. Accounts that loans, peer banks, escrows and transfers still
+    /// to be made refer to are not merged, those would fail from then on.
+    fn merge_accounts(
+        &mut self,
+        account: &AccountRef,
+        into: &AccountRef,
+    ) -> Result<Option<TransactionId>, CustomError> {
+        let source = self.account(account)?;
+        let target = self.account(into)?;
+        let unmergeable = |reason| {
+            CustomError::UnmergeableAccountError(UnmergeableAccountError {
+                account_name: source.name.clone(),
+                reason,
+            })
+        };
+        if source.id == target.id {
+            return Err(unmergeable("it is the account to merge into"));
+        }
+        if source.currency != target.currency {
+            return Err(unmergeable("its currency differs"));
+        }
+        if !source.held.is_zero() {
+            return Err(unmergeable("it has money on hold"));
+        }
+        if self.sub_accounts(source.id).next().is_some() {
+            return Err(unmergeable("it has sub-accounts"));
+        }
+        // What refers to the account by ID would be left with a closed one.
+        let id = source.id;
+        let party = |transfer: &TxInfo| {
+            [&transfer.from, &transfer.to]
+                .into_iter()
+                .any(|account| *account == AccountRef::Id(id))
+        };
+        let outstanding = |loan: &Loan| self.accounts[&loan.account].balance.is_negative();
+        if self
+            .loans
+            .iter()
+            .any(|loan| loan.account == id || (loan.borrower == id && outstanding(loan)))
+        {
+            return Err(unmergeable("it has a loan"));
+        }
+        if self.settlement_accounts.contains(&id) {
+            return Err(unmergeable("it is kept for a peer bank"));
+        }
+        if self
+            .escrows
+            .iter()
+            .any(|escrow| escrow.payer == id || escrow.beneficiary == id)
+        {
+            return Err(unmergeable("it is party to an open escrow"));
+        }
+        if self
+            .approvals
+            .iter()
+            .any(|request| party(&request.transfer))
+        {
+            return Err(unmergeable("it has transfers waiting for approvals"));
+        }
+        if self.reviews.iter().any(|parked| party(&parked.transfer)) {
+            return Err(unmergeable("it has transfers waiting for review"));
+        }
+        if self
+            .scheduler
+            .iter()
+            .any(|scheduled| party(&scheduled.transfer))
+            || self
+                .scheduler
+                .standing_orders()
+                .any(|order| party(&order.transfer))
+        {
+            return Err(unmergeable("it has scheduled transfers or standing orders"));
+        }
+        for account in [source, target] {
+            if account.status == AccountStatus::Closed {
+                return Err(CustomError::AccountClosedError(AccountClosedError {
+                    account_name: account.name.clone(),
+                }));
+            }
+        }
+        let amount = source.balance.magnitude();
+        let merged = match source.balance.is_negative() {
+            true => target.balance.checked_sub(amount),
+            false => target.balance.checked_add(amount),
+        };
+        if merged.is_none() {
+            return Err(CustomError::BalanceOverflowError(BalanceOverflowError {
+                account_name: target.name.clone(),
+            }));
+        }
+        let (from, to) = match source.balance.is_negative() {
+            true => (target.id, source.id),
+            false => (source.id, target.id),
+        };
+        let movements: Vec<_> = Some(amount)
+            .filter(|amount| !amount.is_zero())
+            .map(|amount| Movement {
+                from: Some(from),
+                to: Some(to),
+                system: None,
+                amount,
+                currency: source.currency,
+                rate: None,
+                memo: Some(format!("Merge of {} into {}", source.id, target.id)),
+                external_ref: None,
+            })
+            .into_iter()
+            .collect();
+        let transaction_id = (!movements.is_empty()).then(|| self.ledger.next_transaction_id());
+        self.emit(Event::AccountMerged {
+            account: source.id,
+            into: target.id,
+            transaction_id,
+            movements,
+        });
+        Ok(transaction_id)
+    }
+
+    /// Change the given metadata fields, leaving the others as they are.
+    /// An empty string clears `display_name` or `email`.
+    fn update_metadata(
+        &mut self,
+        account: &AccountRef,
+        display_name: Option<String>,
+        email: Option<String>,
+        tags: Option<BTreeSet<String>>,
+    ) -> Result<(), CustomError> {
+        let account = self.account(account)?;
+        let mut metadata = account.metadata.clone();
+        if let Some(display_name) = display_name {
+            metadata.display_name = Some(display_name).filter(|name| !name.is_empty());
+        }
+        if let Some(email) = email {
+            metadata.email = Some(email).filter(|email| !email.is_empty());
+        }
+        if let Some(tags) = tags {
+            metadata.tags = tags;
+        }
+        self.emit(Event::MetadataUpdated {
+            account: account.id,
+            metadata,
+        });
+        Ok(())
+    }
+
+    fn set_fx_rate(&mut self, from: Currency, to: Currency, rate: Rate) -> Result<(), CustomError> {
+        if from == to {
+            return Err(CustomError::InvalidExchangeRateError(
+                InvalidExchangeRateError { currency: from },
+            ));
+        }
+        self.emit(Event::ExchangeRateSet { from, to, rate });
+        Ok(())
+    }
+
+    /// Apply the configured exchange rates, keyed by pairs like `"EUR/USD"`.
+    /// Rates for pairs missing from `rates` are left as they are.
+    pub fn set_fx_rates(&mut self, rates: &VanillaHashMap<String, Rate>) {
+        for (pair, rate) in rates {
+            match fx::parse_pair(pair) {
+                Some((from, to)) if from != to => self.fx.set(from, to, *rate),
+                _ => warn!("Ignoring exchange rate for invalid pair '{pair}'"),
+            }
+        }
+    }
+
+    /// Reserve the transfer's amount on the paying account until the hold is
+    /// captured, cancelled or expires.
+    fn place_hold(&mut self, tx_info: TxInfo, now: u64) -> Result<HoldReceipt, CustomError> {
+        self.expire_holds(now);
+        let transfer = self.plan_transfer(tx_info.clone(), Checks::All)?;
+        let account = &self.accounts[&transfer.from];
+        account.balance_after_withdrawal(transfer.amount)?;
+        account.held.checked_add(transfer.amount).ok_or_else(|| {
+            CustomError::BalanceOverflowError(BalanceOverflowError {
+                account_name: account.name.clone(),
+            })
+        })?;
+        let hold = self.holds.create(
+            transfer.from,
+            TxInfo {
+                from: AccountRef::Id(transfer.from),
+                to: AccountRef::Id(transfer.to),
+                pin: None,
+                ..tx_info
+            },
+            now,
+        );
+        let receipt = HoldReceipt {
+            hold_id: hold.id,
+            amount: hold.amount,
+            expires_at: hold.expires_at,
+        };
+        self.emit(Event::HoldPlaced { hold });
+        Ok(receipt)
+    }
+
+    /// Make the transfer of a hold, for `amount` or the whole amount held.
+    /// Whatever is not captured is released.
+    fn capture_hold(
+        &mut self,
+        hold_id: HoldId,
+        amount: Option<Amount>,
+        now: u64,
+    ) -> Result<Receipt, CustomError> {
+        self.expire_holds(now);
+        let hold = self
+            .holds
+            .get(hold_id)
+            .cloned()
+            .ok_or(CustomError::HoldNotFoundError(HoldNotFoundError {
+                hold_id,
+            }))?;
+        let amount = amount.unwrap_or(hold.amount);
+        if amount > hold.amount {
+            return Err(CustomError::CaptureExceedsHoldError(
+                CaptureExceedsHoldError {
+                    hold_id,
+                    amount,
+                    held: hold.amount,
+                },
+            ));
+        }
+        let transfer = TxInfo {
+            amount,
+            ..hold.transfer.clone()
+        };
+        let transfer = self.plan_transfer(transfer, Checks::Waits)?;
+        self.commit_transfer(transfer, Some(&hold))
+    }
+
+    fn cancel_hold(&mut self, hold_id: HoldId, now: u64) -> Result<(), CustomError> {
+        self.expire_holds(now);
+        if self.holds.get(hold_id).is_none() {
+            return Err(CustomError::HoldNotFoundError(HoldNotFoundError {
+                hold_id,
+            }));
+        }
+        self.emit(Event::HoldReleased { hold_id });
+        Ok(())
+    }
+
+    fn hold(&self, hold_id: HoldId) -> Option<&Hold> {
+        self.holds.get(hold_id)
+    }
+
+    /// Release the funds of holds that expired by `now`.
+    pub fn expire_holds(&mut self, now: u64) {
+        for hold_id in self.holds.expired(now) {
+            info!("Hold {hold_id} expired");
+            self.emit(Event::HoldReleased { hold_id });
+        }
+    }
+
+    /// Lifetime of holds placed from now on.
+    pub fn set_hold_ttl(&mut self, ttl_secs: u64) {
+        self.holds.set_ttl(ttl_secs);
+    }
+
+    /// Move the amount from the payer into escrow, see `escrow`.
+    fn open_escrow(
+        &mut self,
+        tx_info: TxInfo,
+        arbiter: Option<String>,
+        timeout_secs: Option<u64>,
+        on_timeout: EscrowOutcome,
+        now: u64,
+    ) -> Result<Escrow, CustomError> {
+        let memo = tx_info.memo.clone();
+        let transfer = self.plan_transfer(
+            TxInfo {
+                convert: false,
+                ..tx_info
+            },
+            Checks::All,
+        )?;
+        self.check_limits(transfer.from, transfer.amount, Money::ZERO)?;
+        let movement = Movement {
+            from: Some(transfer.from),
+            to: None,
+            system: Some(SystemAccount::Escrow),
+            amount: transfer.amount,
+            currency: transfer.currency,
+            rate: None,
+            memo: memo.clone(),
+            external_ref: None,
+        };
+        let all_movements: Vec<Movement> = std::iter::once(movement.clone())
+            .chain(transfer.fee_movements.iter().cloned())
+            .collect();
+        self.check_movements(&all_movements, None)?;
+        let (opened_by, _) = self.record_transfer(vec![movement], transfer.fee_movements, None);
+        let escrow = Escrow {
+            id: self.escrows.next_id(),
+            payer: transfer.from,
+            beneficiary: transfer.to,
+            amount: transfer.amount,
+            currency: transfer.currency,
+            arbiter,
+            expires_at: now.saturating_add(timeout_secs.unwrap_or(escrow::DEFAULT_TIMEOUT_SECS)),
+            on_timeout,
+            opened_by,
+            memo,
+        };
+        self.emit(Event::EscrowOpened {
+            escrow: escrow.clone(),
+        });
+        Ok(escrow)
+    }
+
+    /// Pay the escrowed amount to the beneficiary or back to the payer.
+    fn close_escrow(
+        &mut self,
+        escrow_id: EscrowId,
+        outcome: EscrowOutcome,
+    ) -> Result<EscrowReceipt, CustomError> {
+        let escrow = self
+            .escrows
+            .get(escrow_id)
+            .cloned()
+            .ok_or(EscrowNotFoundError { escrow_id })?;
+        let to = match outcome {
+            EscrowOutcome::Release => escrow.beneficiary,
+            EscrowOutcome::Refund => escrow.payer,
+        };
+        self.accounts[&to].ensure_can_receive()?;
+        let movement = Movement {
+            from: None,
+            to: Some(to),
+            system: Some(SystemAccount::Escrow),
+            amount: escrow.amount,
+            currency: escrow.currency,
+            rate: None,
+            memo: escrow.memo.clone(),
+            external_ref: None,
+        };
+        self.check_movements(std::slice::from_ref(&movement), None)?;
+        let (transaction_id, _) = self.record_transfer(vec![movement], Vec::new(), None);
+        self.emit(Event::EscrowClosed { escrow_id });
+        Ok(EscrowReceipt {
+            escrow_id,
+            outcome,
+            transaction_id,
+            to,
+            amount: escrow.amount,
+            currency: escrow.currency,
+        })
+    }
+
+    fn escrow(&self, escrow_id: EscrowId) -> Option<&Escrow> {
+        self.escrows.get(escrow_id)
+    }
+
+    /// Settle the escrows whose deadline passed by `now` as they say, or
+    /// refund them if that fails.
+    pub fn expire_escrows(&mut self, now: u64) {
+        for escrow_id in self.escrows.expired(now) {
+            let on_timeout = self.escrows.get(escrow_id).unwrap().on_timeout;
+            let result = self.close_escrow(escrow_id, on_timeout).or_else(|e| {
+                if on_timeout == EscrowOutcome::Refund {
+                    return Err(e);
+                }
+                warn!("Refunding expired escrow {escrow_id} that could not be released: {e}");
+                self.close_escrow(escrow_id, EscrowOutcome::Refund)
+            });
+            match result {
+                Ok(receipt) => info!("Escrow {escrow_id} expired, {:?}", receipt.outcome),
+                Err(e) => error!("Could not settle expired escrow {escrow_id}: {e}"),
+            }
+        }
+    }
+
+    /// Names of the accounts `identity` is an owner of, see `owners`.
+    fn accounts_owned_by(&self, identity: &str) -> Vec<String> {
+        self.accounts
+            .values()
+            .filter(|account| account.owners.contains(identity))
+            .map(|account| account.name.to_string())
+            .collect()
+    }
+
+    fn set_owners(
+        &mut self,
+        account: &AccountRef,
+        owners: BTreeSet<String>,
+        approvals_required: u32,
+    ) -> Result<(), CustomError> {
+        let account = self.account(account)?;
+        if approvals_required == 0 || approvals_required as usize > owners.len().max(1) {
+            return Err(OwnershipError::InvalidApprovals {
+                account_name: account.name.clone(),
+                required: approvals_required,
+                owners: owners.len(),
+            }
+            .into());
+        }
+        let account = account.id;
+        self.emit(Event::OwnersChanged {
+            account,
+            owners,
+            approvals_required,
+        });
+        Ok(())
+    }
+
+    fn add_owner(&mut self, account: &AccountRef, owner: String) -> Result<(), CustomError> {
+        let existing = self.account(account)?;
+        let mut owners = existing.owners.clone();
+        owners.insert(owner);
+        self.set_owners(account, owners, existing.approvals_required)
+    }
+
+    /// Fails if fewer owners than the account needs approvals would be left.
+    fn remove_owner(&mut self, account: &AccountRef, owner: &str) -> Result<(), CustomError> {
+        let existing = self.account(account)?;
+        let mut owners = existing.owners.clone();
+        if !owners.remove(owner) {
+            return Err(OwnershipError::NotAnOwner {
+                account_name: existing.name.clone(),
+                identity: owner.to_string(),
+            }
+            .into());
+        }
+        self.set_owners(account, owners, existing.approvals_required)
+    }
+
+    fn set_approvals_required(
+        &mut self,
+        account: &AccountRef,
+        required: u32,
+    ) -> Result<(), CustomError> {
+        let owners = self.account(account)?.owners.clone();
+        self.set_owners(account, owners, required)
+    }
+
+    /// Whether transfers out of `account` wait for the approvals of several
+    /// owners.
+    fn needs_approvals(&self, account: &AccountRef) -> bool {
+        self.resolve(account)
+            .is_some_and(|id| self.accounts[&id].approvals_required > 1)
+    }
+
+    /// Check a transfer out of a joint account and keep it, approved by
+    /// `owner`, until enough other owners approve it.
+    fn request_approval(
+        &mut self,
+        tx_info: TxInfo,
+        owner: Option<&str>,
+        now: u64,
+    ) -> Result<ApprovalRequest, CustomError> {
+        let transfer = self.plan_transfer(tx_info.clone(), Checks::Pin)?;
+        let account = &self.accounts[&transfer.from];
+        let owner = self.check_owner(account, owner)?;
+        let request = ApprovalRequest {
+            approval_id: self.approvals.next_id(),
+            account: account.id,
+            transfer: TxInfo {
+                from: AccountRef::Id(transfer.from),
+                to: AccountRef::Id(transfer.to),
+                pin: None,
+                ..tx_info
+            },
+            approved_by: BTreeSet::from([owner]),
+            approvals_required: account.approvals_required,
+            requested_at: now,
+        };
+        self.emit(Event::ApprovalRequested {
+            request: request.clone(),
+        });
+        Ok(request)
+    }
+
+    /// Approve a waiting transfer as `owner`, making it if that was the
+    /// last approval it needed, or parking it if it needs review too.
+    fn approve_transfer(
+        &mut self,
+        approval_id: ApprovalId,
+        owner: Option<&str>,
+    ) -> Result<ApprovalOutcome, CustomError> {
+        let request = self
+            .approvals
+            .get(approval_id)
+            .ok_or(OwnershipError::ApprovalNotFound(approval_id))?;
+        let owner = self.check_owner(&self.accounts[&request.account], owner)?;
+        if request.approved_by.contains(&owner) {
+            return Err(OwnershipError::AlreadyApproved {
+                approval_id,
+                identity: owner,
+            }
+            .into());
+        }
+        self.emit(Event::TransferApproved { approval_id, owner });
+        let request = self.approvals.get(approval_id).unwrap();
+        if request.approved_by.len() < request.approvals_required as usize {
+            return Ok(ApprovalOutcome::Pending(request.clone()));
+        }
+        let tx_info = request.transfer.clone();
+        let requested_by = request.approved_by.iter().next().cloned();
+        let result = if self.needs_review(&tx_info) {
+            self.park_transfer(tx_info, requested_by.as_deref(), Checks::Nothing)
+                .map(ApprovalOutcome::Parked)
+        } else {
+            self.plan_transfer(tx_info, Checks::Nothing)
+                .and_then(|transfer| self.commit_transfer(transfer, None))
+                .map(ApprovalOutcome::Made)
+        };
+        // A transfer that can no longer be made is dropped, not retried.
+        self.emit(Event::ApprovalClosed { approval_id });
+        result
+    }
+
+    fn reject_transfer(
+        &mut self,
+        approval_id: ApprovalId,
+        owner: Option<&str>,
+    ) -> Result<(), CustomError> {
+        let request = self
+            .approvals
+            .get(approval_id)
+            .ok_or(OwnershipError::ApprovalNotFound(approval_id))?;
+        self.check_owner(&self.accounts[&request.account], owner)?;
+        self.emit(Event::ApprovalClosed { approval_id });
+        Ok(())
+    }
+
+    fn pending_approvals(
+        &self,
+        account: &AccountRef,
+    ) -> Result<Vec<&ApprovalRequest>, CustomError> {
+        let account = self.account(account)?.id;
+        Ok(self
+            .approvals
+            .iter()
+            .filter(|request| request.account == account)
+            .collect())
+    }
+
+    fn check_owner(&self, account: &Account, owner: Option<&str>) -> Result<String, CustomError> {
+        let owner =
+            owner.ok_or_else(|| OwnershipError::IdentityRequired(account.name.to_string()))?;
+        if !account.owners.contains(owner) {
+            return Err(OwnershipError::NotAnOwner {
+                account_name: account.name.clone(),
+                identity: owner.to_string(),
+            }
+            .into());
+        }
+        Ok(owner.to_string())
+    }
+
+    /// Check the transfer now and make it once `execute_at` has passed.
+    fn schedule_transfer(
+        &mut self,
+        execute_at: u64,
+        tx_info: TxInfo,
+    ) -> Result<ScheduledTransfer, CustomError> {
+        let transfer = self.plan_transfer(tx_info.clone(), Checks::All)?;
+        let scheduled = self.scheduler.create(
+            execute_at,
+            TxInfo {
+                from: AccountRef::Id(transfer.from),
+                to: AccountRef::Id(transfer.to),
+                pin: None,
+                ..tx_info
+            },
+        );
+        self.emit(Event::TransferScheduled {
+            scheduled: scheduled.clone(),
+        });
+        Ok(scheduled)
+    }
+
+    fn scheduled_transfers(
+        &self,
+        account: &AccountRef,
+    ) -> Result<Vec<&ScheduledTransfer>, CustomError> {
+        let id = self.resolve(account).ok_or_else(|| {
+            CustomError::AccountDoesNotExistError(AccountDoesNotExistError {
+                account_name: AccountNamesTuple(account.to_string(), "".to_string()),
+            })
+        })?;
+        Ok(self.scheduler.for_account(id))
+    }
+
+    fn scheduled_transfer(&self, schedule_id: ScheduleId) -> Option<&ScheduledTransfer> {
+        self.scheduler.get(schedule_id)
+    }
+
+    fn cancel_scheduled_transfer(&mut self, schedule_id: ScheduleId) -> Result<(), CustomError> {
+        if self.scheduler.get(schedule_id).is_none() {
+            return Err(CustomError::ScheduledTransferNotFoundError(
+                ScheduledTransferNotFoundError { schedule_id },
+            ));
+        }
+        self.emit(Event::ScheduledTransferClosed { schedule_id });
+        Ok(())
+    }
+
+    /// Make the scheduled transfers due by `now`. One that fails, for lack
+    /// of funds or otherwise, is dropped.
+    pub fn run_scheduled_transfers(&mut self, now: u64) {
+        for schedule_id in self.scheduler.due(now) {
+            let transfer = self.scheduler.get(schedule_id).unwrap().transfer.clone();
+            let result = self
+                .plan_transfer(transfer, Checks::Waits)
+                .and_then(|transfer| self.commit_transfer(transfer, None));
+            match result {
+                Ok(receipt) => info!(
+                    "Performed scheduled transfer {schedule_id} as transaction {}",
+                    receipt.transaction_id
+                ),
+                Err(e) => error!("Scheduled transfer {schedule_id} failed: {e}"),
+            }
+            self.emit(Event::ScheduledTransferClosed { schedule_id });
+        }
+    }
+
+    fn create_standing_order(
+        &mut self,
+        every: Every,
+        starting_at: u64,
+        if_insufficient_funds: InsufficientFunds,
+        tx_info: TxInfo,
+    ) -> Result<StandingOrder, CustomError> {
+        let transfer = self.plan_transfer(tx_info.clone(), Checks::All)?;
+        let order = self.scheduler.create_standing_order(
+            every,
+            starting_at,
+            if_insufficient_funds,
+            TxInfo {
+                from: AccountRef::Id(transfer.from),
+                to: AccountRef::Id(transfer.to),
+                pin: None,
+                ..tx_info
+            },
+        );
+        self.emit(Event::StandingOrderCreated {
+            order: order.clone(),
+        });
+        Ok(order)
+    }
+
+    fn standing_orders(&self, account: &AccountRef) -> Result<Vec<&StandingOrder>, CustomError> {
+        let id = self.resolve(account).ok_or_else(|| {
+            CustomError::AccountDoesNotExistError(AccountDoesNotExistError {
+                account_name: AccountNamesTuple(account.to_string(), "".to_string()),
+            })
+        })?;
+        Ok(self.scheduler.standing_orders_for(id))
+    }
+
+    fn standing_order(&self, order_id: StandingOrderId) -> Option<&StandingOrder> {
+        self.scheduler.standing_order(order_id)
+    }
+
+    fn delete_standing_order(&mut self, order_id: StandingOrderId) -> Result<(), CustomError> {
+        if self.scheduler.standing_order(order_id).is_none() {
+            return Err(CustomError::StandingOrderNotFoundError(
+                StandingOrderNotFoundError { order_id },
+            ));
+        }
+        self.emit(Event::StandingOrderDeleted { order_id });
+        Ok(())
+    }
+
+    /// Make the standing order payments due by `now`.
+    pub fn run_standing_orders(&mut self, now: u64) {
+        for order_id in self.scheduler.due_standing_orders(now) {
+            let Some(mut order) = self.scheduler.standing_order(order_id).cloned() else {
+                continue;
+            };
+            let result = self
+                .plan_transfer(order.transfer.clone(), Checks::Waits)
+                .and_then(|transfer| self.commit_transfer(transfer, None));
+            let short_of_funds = matches!(result, Err(CustomError::InsufficientFundsError(_)));
+            match result {
+                Ok(receipt) => info!(
+                    "Paid standing order {order_id} as transaction {}",
+                    receipt.transaction_id
+                ),
+                Err(e) => error!("Standing order {order_id} failed: {e}"),
+            }
+            order.reschedule(now, short_of_funds);
+            self.emit(Event::StandingOrderRescheduled {
+                order_id,
+                next_run_at: order.next_run_at,
+                retry_at: order.retry_at,
+            });
+        }
+    }
+
+    /// Check that the balance changes of `movements` can be applied, in
+    /// order, after releasing the hold `released`. This only refuses closed
+    /// accounts; payments check the other status rules when they are
+    /// planned, while corrections may still debit frozen accounts.
+    fn check_movements(
+        &self,
+        movements: &[Movement],
+        released: Option<&Hold>,
+    ) -> Result<(), CustomError> {
+        self.simulate_movements(movements, released).map(drop)
+    }
+
+    /// The accounts `movements` touch as they would be after them, see
+    /// `check_movements`.
+    fn simulate_movements(
+        &self,
+        movements: &[Movement],
+        released: Option<&Hold>,
+    ) -> Result<VanillaHashMap<AccountId, Account>, CustomError> {
+        self.simulate_movements_over(&VanillaHashMap::new(), movements, released)
+    }
+
+    /// Like `simulate_movements`, but starting from the accounts in `base`
+    /// where it has them rather than from those of the bank.
+    fn simulate_movements_over(
+        &self,
+        base: &VanillaHashMap<AccountId, Account>,
+        movements: &[Movement],
+        released: Option<&Hold>,
+    ) -> Result<VanillaHashMap<AccountId, Account>, CustomError> {
+        let mut accounts: VanillaHashMap<AccountId, Account> = VanillaHashMap::new();
+        if let Some(hold) = released {
+            let mut account = self.accounts[&hold.account].clone();
+            account.held = account.held.checked_sub(hold.amount).unwrap_or(Money::ZERO);
+            accounts.insert(hold.account, account);
+        }
+        for posting in movements.iter().flat_map(Movement::postings) {
+            let LedgerAccount::Customer(id) = posting.account else {
+                continue;
+            };
+            let account = match accounts.entry(id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let account = base.get(&id).or_else(|| self.accounts.get(&id));
+                    let account = account.ok_or_else(|| {
+                        CustomError::AccountDoesNotExistError(AccountDoesNotExistError {
+                            account_name: AccountNamesTuple(id.to_string(), "".to_string()),
+                        })
+                    })?;
+                    entry.insert(account.clone())
+                }
+            };
+            account.ensure_can_receive()?;
+            account.balance = match posting.side {
+                Side::Credit => account.balance_after_deposit(posting.amount)?,
+                Side::Debit => account.balance_after_withdrawal(posting.amount)?,
+            };
+        }
+        Ok(accounts)
+    }
+
+    /// Undo a committed transaction with a compensating one that moves the
+    /// same amounts back. The accounts involved must still be open and the
+    /// ones paying back need the funds, overdrafts included.
+    fn reverse(&mut self, transaction_id: TransactionId) -> Result<ReversalReceipt, CustomError> {
+        let entries = self.ledger.transaction(transaction_id).ok_or(
+            CustomError::TransactionNotFoundError(TransactionNotFoundError { transaction_id }),
+        )?;
+        if entries[0].reverses.is_some() {
+            return Err(CustomError::IrreversibleTransactionError(
+                IrreversibleTransactionError { transaction_id },
+            ));
+        }
+        if let Some(reversed_by) = self.ledger.reversed_by(transaction_id) {
+            return Err(CustomError::AlreadyReversedError(AlreadyReversedError {
+                transaction_id,
+                reversed_by,
+            }));
+        }
+        let movements: Vec<Movement> = entries
+            .iter()
+            .map(|entry| Movement {
+                from: entry.to,
+                to: entry.from,
+                system: entry.system,
+                amount: entry.amount,
+                currency: entry.currency,
+                rate: entry.rate,
+                memo: None,
+                external_ref: entry.external_ref.clone(),
+            })
+            .collect();
+
+        self.check_movements(&movements, None)?;
+        let reversal = self.ledger.next_transaction_id();
+        let timestamp = self.emit(Event::FundsTransferred {
+            transaction_id: reversal,
+            movements,
+            reverses: Some(transaction_id),
+            fee_for: None,
+        });
+        Ok(ReversalReceipt {
+            transaction_id: reversal,
+            timestamp,
+            reverses: transaction_id,
+        })
+    }
+
+    /// Most recent ledger entries involving the account, oldest first.
+    fn history(&self, query: &HistoryQuery) -> Result<Vec<&LedgerEntry>, CustomError> {
+        let id = self.resolve(&query.account).ok_or_else(|| {
+            CustomError::AccountDoesNotExistError(AccountDoesNotExistError {
+                account_name: AccountNamesTuple(query.account.to_string(), "".to_string()),
+            })
+        })?;
+        Ok(self.ledger.history(id, query.limit.min(MAX_HISTORY_LIMIT)))
+    }
+
+    /// Signed statement of the account's balance as of a ledger entry, see
+    /// `attestation`. Accounts opened since count with a zero balance.
+    fn attest(&self, query: &AttestationQuery) -> Result<Attestation, CustomError> {
+        let key = self
+            .attestation_key
+            .as_ref()
+            .ok_or(AttestationKeyMissingError)?;
+        let id = self.account(&query.account)?.id;
+        let entries = self.ledger.entries();
+        let sequence = query.sequence.unwrap_or(entries.len() as u64);
+        let entries = entries
+            .get(..sequence as usize)
+            .ok_or(LedgerEntryNotFoundError { sequence })?;
+        let mut balances: BTreeMap<AccountId, Balance> = self
+            .accounts
+            .keys()
+            .map(|&id| (id, Balance::ZERO))
+            .collect();
+        let overflow = |id: AccountId| BalanceOverflowError {
+            account_name: self.accounts[&id].name.clone(),
+        };
+        for entry in entries {
+            if let Some(from) = entry.from {
+                let balance = balances.entry(from).or_default();
+                *balance = balance
+                    .checked_sub(entry.amount)
+                    .ok_or_else(|| overflow(from))?;
+            }
+            if let Some(to) = entry.to {
+                let balance = balances.entry(to).or_default();
+                *balance = balance
+                    .checked_add(entry.amount)
+                    .ok_or_else(|| overflow(to))?;
+            }
+        }
+        let balances: Vec<(AccountId, Currency, Balance)> = balances
+            .into_iter()
+            .map(|(id, balance)| (id, self.accounts[&id].currency, balance))
+            .collect();
+        let index = balances
+            .iter()
+            .position(|&(account, _, _)| account == id)
+            .unwrap();
+        Ok(attestation::attest(key, sequence, &balances, index))
+    }
+
+    /// Statement of the account named `account_name` over the days `from`
+    /// to `to`, both included, see `statement`.
+    pub fn statement(
+        &self,
+        account_name: &str,
+        from: Option<Date>,
+        to: Option<Date>,
+    ) -> Result<Statement, CustomError> {
+        if let (Some(from), Some(to)) = (from, to) {
+            if to < from {
+                return Err(InvalidPeriodError { from, to }.into());
+            }
+        }
+        let account = self.account(&AccountRef::Name(account_name.to_string()))?;
+        Statement::build(account, from, to, self.now(), self.ledger.entries(), |id| {
+            self.accounts.get(&id).map(|other| other.name.to_string())
+        })
+        .ok_or_else(|| {
+            BalanceOverflowError {
+                account_name: account.name.clone(),
+            }
+            .into()
+        })
+    }
+
+    /// Write the whole ledger to the file at `path`, see `export`. Returns
+    /// the number of entries written.
+    pub fn export_ledger(&self, path: &Path, format: ExportFormat) -> Result<usize, ExportError> {
+        let entries = self.ledger.entries();
+        export::export(entries, path, format)?;
+        Ok(entries.len())
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// The accounts matching `filter` in `format`, sorted by name, all of
+    /// them or a `page`.
+    fn list_accounts(
+        &self,
+        format: ListingFormat,
+        page: Option<Page>,
+        filter: &AccountFilter,
+    ) -> Result<String, SerdeError> {
+        let matching = self.accounts.values().filter(|acc| filter.matches(acc));
+        let Some(page) = page else {
+            let accounts_map = matching
+                .map(|acc| (&*acc.name, self.account_info(acc)))
+                .collect();
+            return listing::render(&accounts_map, format);
+        };
+        let mut accounts: Vec<&Account> = matching.filter(|acc| page.follows(&acc.name)).collect();
+        accounts.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let size = page.size();
+        let next = (accounts.len() > size).then(|| &*accounts[size - 1].name);
+        accounts.truncate(size);
+        let accounts_map = accounts
+            .into_iter()
+            .map(|acc| (&*acc.name, self.account_info(acc)))
+            .collect();
+        listing::render_page(&accounts_map, format, next)
+    }
+
+    fn account_info(&self, acc: &Account) -> AccountInfo {
+        AccountInfo {
+            id: acc.id,
+            balance: acc.balance,
+            currency: acc.currency,
+            overdraft_limit: acc.overdraft_limit,
+            held: acc.held,
+            status: acc.status,
+            metadata: acc.metadata.clone(),
+            interest_rate: acc.interest.map(|accrual| accrual.rate),
+            limits: acc.limits,
+            owners: acc.owners.clone(),
+            approvals_required: acc.approvals_required,
+            parent: acc.parent,
+            sub_accounts: self
+                .sub_accounts(acc.id)
+                .map(|sub_account| sub_account.name.clone())
+                .collect(),
+            total: self
+                .sub_accounts(acc.id)
+                .next()
+                .map(|_| self.total_balance(acc)),
+            merged_into: acc.merged_into,
+        }
+    }
+}
+
+impl Display for Bank {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut accounts: Vec<_> = self.accounts.values().collect();
+        accounts.sort_by(|a, b| a.name.cmp(&b.name));
+        for account in accounts {
+            writeln!(f, "{account}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The accounts, ledger, holds and the rest that events change, laid out as
+/// a `store::Snapshot`, the schema of the snapshots in the data directory.
+/// Settings from the configuration are left out.
+impl Serialize for Bank {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let audit_head = self
+            .store
+            .as_ref()
+            .map_or(&audit::GENESIS, Store::audit_head);
+        self.snapshot(audit_head).serialize(serializer)
+    }
+}
+
+/// A bank with the default settings, taking the time from the system clock
+/// and storing nothing, from what `Serialize` wrote.
+impl<'de> Deserialize<'de> for Bank {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Bank, D::Error> {
+        let snapshot = Snapshot::deserialize(deserializer)?;
+        Ok(Bank::from_snapshot(Arc::new(SystemClock), snapshot))
+    }
+}
+
+fn notify_systemd(state: &str) {
+    if let Err(e) = systemd::notify(state) {
+        warn!("Unable to notify systemd: {e:?}");
+    }
+}
+
+fn reply(socket: &dyn Transport, sender: &Option<PathBuf>, message: &[u8]) -> io::Result<()> {
+    match sender {
+        // A client that went away must not take the server down with it.
+        Some(sender_path) => {
+            if let Err(e) = socket.send_to(message, sender_path) {
+                warn!("Unable to reply to {}: {e}", sender_path.display());
+            }
+        }
+        None => error!("Unable to get client's socket path"),
+    }
+    Ok(())
+}
+
+/// Reply to the request being handled, as its `respond` phase.
+fn respond(
+    socket: &dyn Transport,
+    sender: &Option<PathBuf>,
+    trace: &mut RequestTrace,
+    message: &[u8],
+) -> io::Result<()> {
+    trace.phase(Phase::Respond);
+    reply(socket, sender, message)
+}
+
+/// Acknowledge an instruction that carries a payload and receive the payload
+/// into `buffer`, returning the part of it received. A payload that does not
+/// fit is refused with "413" and fails with `InvalidData`.
+fn recv_payload<'a>(
+    socket: &dyn Transport,
+    sender: &Option<PathBuf>,
+    trace: &mut RequestTrace,
+    buffer: &'a mut [u8],
+) -> io::Result<&'a [u8]> {
+    trace.phase(Phase::Receive);
+    reply(socket, sender, "200".as_bytes())?;
+    debug!("Sent '200' message to client");
+    let length = socket.recv(buffer)?;
+    if length > buffer.len() {
+        respond(socket, sender, trace, "413".as_bytes())?;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("payload of {length} bytes is over the limit"),
+        ));
+    }
+    trace.phase(Phase::Validate);
+    Ok(&buffer[..length])
+}
+
+/// Status replied for a transfer that failed: "403" when it breaks a
+/// transfer limit or a fraud rule or a hook vetoes it, "502" when a peer bank
+/// did not book it, "422" otherwise.
+fn failure_status(error: &CustomError) -> &'static str {
+    match error {
+        CustomError::LimitExceededError(_)
+        | CustomError::TransferRejectedError(_)
+        | CustomError::TransferVetoedError(_)
+        | CustomError::OwnershipError(
+            OwnershipError::NotAnOwner { .. } | OwnershipError::IdentityRequired(_),
+        ) => "403",
+        CustomError::InterbankError(InterbankError::Unconfirmed { .. }) => "504",
+        CustomError::InterbankError(_) => "502",
+        _ => "422",
+    }
+}
+
+/// A request the bank refused, as replied from protocol version 2 on, see
+/// `protocol`. Also what a failed transfer is remembered as for retries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorReply {
+    /// The status replied alone to clients of version 1.
+    pub status: String,
+    /// Stable code of the error, see `CustomError::code`.
+    pub code: String,
+    pub message: String,
+}
+
+impl ErrorReply {
+    pub fn new(status: &str, error: &CustomError) -> ErrorReply {
+        ErrorReply {
+            status: status.to_string(),
+            code: error.code().to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<CustomError> for ErrorReply {
+    fn from(error: CustomError) -> ErrorReply {
+        ErrorReply::new(failure_status(&error), &error)
+    }
+}
+
+/// Reply to the request being handled with `error`: its status alone to
+/// clients of protocol version 1, the whole of it to later ones.
+fn respond_error(
+    socket: &dyn Transport,
+    sender: &Option<PathBuf>,
+    trace: &mut RequestTrace,
+    version: u32,
+    error: &ErrorReply,
+) -> Result<()> {
+    match version < protocol::ERROR_REPLY_VERSION {
+        true => respond(socket, sender, trace, error.status.as_bytes())?,
+        false => respond(
+            socket,
+            sender,
+            trace,
+            serde_json::to_string(error)?.as_bytes(),
+        )?,
+    }
+    Ok(())
+}
+
+/// Send the receipt of a transfer, or why it failed.
+fn reply_transaction_outcome(
+    socket: &dyn Transport,
+    sender: &Option<PathBuf>,
+    trace: &mut RequestTrace,
+    version: u32,
+    outcome: &Result<Receipt, ErrorReply>,
+) -> Result<()> {
+    match outcome {
+        Ok(receipt) => respond(
+            socket,
+            sender,
+            trace,
+            serde_json::to_string(receipt)?.as_bytes(),
+        )?,
+        Err(failure) => respond_error(socket, sender, trace, version, failure)?,
+    }
+    Ok(())
+}
+
+/// Let the owners of joint accounts act on them like customers configured
+/// with them, and on their sub-accounts like on the parents.
+fn extend_identity(identity: &mut Identity, bank: &Bank) {
+    identity
+        .accounts
+        .extend(bank.accounts_owned_by(&identity.name));
+    let sub_accounts = bank.sub_account_names(&identity.accounts);
+    identity.accounts.extend(sub_accounts);
+}
+
+/// Answer the history query in `payload`, on the server's socket or the
+/// replica's.
+fn answer_history_query(
+    socket: &dyn Transport,
+    sender: &Option<PathBuf>,
+    trace: &mut RequestTrace,
+    auth: &Auth,
+    identity: Option<&Identity>,
+    bank: &Bank,
+    payload: &[u8],
+) -> Result<()> {
+    let query: HistoryQuery = match serde_json::from_slice(payload) {
+        Ok(query) => query,
+        Err(e) => {
+            warn!("Rejected malformed history query: {e}");
+            return Ok(respond(socket, sender, trace, "400".as_bytes())?);
+        }
+    };
+    let account_name = bank
+        .account_name(&query.account)
+        .map_or_else(|| query.account.to_string(), str::to_string);
+    if let Err(e) = auth.authorize_account(identity, &account_name) {
+        warn!("Rejected history query for '{account_name}': {e}");
+        return Ok(respond(socket, sender, trace, e.status().as_bytes())?);
+    }
+    trace.phase(Phase::Apply);
+    match bank.history(&query) {
+        Ok(entries) => {
+            let serialized = serde_json::to_string(&entries)?;
+            respond(socket, sender, trace, serialized.as_bytes())?;
+        }
+        Err(e) => {
+            warn!("History query failed: {e}");
+            respond(socket, sender, trace, "404".as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn answer_account_search(
+    socket: &dyn Transport,
+    sender: &Option<PathBuf>,
+    trace: &mut RequestTrace,
+    header: &protocol::RequestHeader,
+    bank: &Bank,
+    payload: &[u8],
+) -> Result<()> {
+    let filter: AccountFilter = match serde_json::from_slice(payload) {
+        Ok(filter) => filter,
+        Err(e) => {
+            warn!("Rejected malformed account search: {e}");
+            return Ok(respond(socket, sender, trace, "400".as_bytes())?);
+        }
+    };
+    trace.phase(Phase::Apply);
+    let format = header.format.unwrap_or_default();
+    let listing = bank.list_accounts(format, header.page(), &filter)?;
+    Ok(respond(socket, sender, trace, listing.as_bytes())?)
+}
+
+/// Key used for rate limiting: the authenticated identity if there is one,
+/// otherwise whatever identifies the sending socket.
+fn client_key(
+    identity: Option<&Identity>,
+    sender: &Option<PathBuf>,
+    credentials: Option<&PeerCredentials>,
+) -> String {
+    match (identity, sender, credentials) {
+        (Some(identity), _, _) => format!("identity:{}", identity.name),
+        (None, Some(sender_path), _) => format!("path:{}", sender_path.display()),
+        (None, None, Some(credentials)) => format!("uid:{}", credentials.uid),
+        (None, None, None) => "anonymous".to_string(),
+    }
+}
+
+/// Load the script named by `policy_script` into the bank, or clear the
+/// policy if there is none.
+#[cfg(feature = "scripting")]
+fn load_policy(bank: &mut Bank, config: &Config) -> Result<()> {
+    let policy = match &config.policy_script {
+        Some(path) => policy::Policy::load(path)?,
+        None => policy::Policy::default(),
+    };
+    bank.set_policy(policy);
+    Ok(())
+}
+
+#[cfg(not(feature = "scripting"))]
+fn load_policy(_bank: &mut Bank, config: &Config) -> Result<()> {
+    if config.policy_script.is_some() {
+        warn!("Ignoring policy_script, built without the scripting feature");
+    }
+    Ok(())
+}
+
+/// Export traces to the collector named by `otlp_endpoint`, or stop
+/// exporting if there is none.
+#[cfg(feature = "otlp")]
+fn configure_tracing(tracer: &mut Tracer, config: &Config) -> Result<()> {
+    let exporter = match &config.otlp_endpoint {
+        Some(endpoint) => Some(trace::otlp::Exporter::start(endpoint)?),
+        None => None,
+    };
+    tracer.set_exporter(exporter);
+    Ok(())
+}
+
+#[cfg(not(feature = "otlp"))]
+fn configure_tracing(_tracer: &mut Tracer, config: &Config) -> Result<()> {
+    if config.otlp_endpoint.is_some() {
+        warn!("Ignoring otlp_endpoint, built without the otlp feature");
+    }
+    Ok(())
+}
+
+/// Apply the settings shared by every bank the server hosts.
+fn configure_bank(bank: &mut Bank, config: &Config) {
+    bank.set_pins(&config.account_pins);
+    bank.set_fx_rates(&config.fx_rates);
+    bank.set_hold_ttl(config.hold_ttl_secs);
+    bank.set_interest_period(config.interest_period_secs);
+    bank.set_settlement_period(config.settlement_period_secs);
+    bank.set_review_threshold(config.review_threshold);
+    bank.set_fees(config.fees.clone());
+    bank.set_default_transfer_limits(config.transfer_limits);
+    bank.set_fraud_rules(config.fraud_rules.clone());
+    bank.set_snapshot_every(config.snapshot_every);
+    bank.set_attestation_key(config.attestation_key.clone());
+    let identity_accounts = config
+        .identities
+        .values()
+        .flat_map(|identity| identity.accounts.iter());
+    let fee_account = config.fees.iter().map(|fees| &fees.account);
+    bank.set_configured_names(
+        config
+            .account_pins
+            .keys()
+            .chain(identity_accounts)
+            .chain(fee_account)
+            .cloned()
+            .collect(),
+    );
+}
+
+/// A bank served by `run_app` with the state kept for its requests. The
+/// default bank has no tenant name, the others are named in `tenants`.
+struct Tenant {
+    bank: Bank,
+    subscriptions: Arc<Subscriptions>,
+    idempotency: IdempotencyCache<Result<Receipt, ErrorReply>>,
+    /// Only the default bank has peer banks.
+    peers: Peers,
+    /// Started with the `z` instruction, see `sandbox`.
+    sandbox: bool,
+}
+
+impl Tenant {
+    fn new(
+        mut bank: Bank,
+        transport: &Arc<dyn Transport>,
+        config: &Config,
+        webhooks: &[WebhookConfig],
+        peers: Peers,
+    ) -> Result<Tenant> {
+        configure_bank(&mut bank, config);
+        for (_, peer) in peers.iter() {
+            bank.open_settlement_account(peer)?;
+        }
+        bank.set_webhooks(Webhooks::start(webhooks)?);
+        load_policy(&mut bank, config)?;
+        let subscriptions = Arc::new(Subscriptions::new(Arc::clone(transport)));
+        bank.add_post_commit_hook({
+            let subscriptions = Arc::clone(&subscriptions);
+            move |transfer| subscriptions.publish(transfer)
+        });
+        Ok(Tenant {
+            bank,
+            subscriptions,
+            idempotency: IdempotencyCache::new(idempotency::DEFAULT_CAPACITY),
+            peers,
+            sandbox: false,
+        })
+    }
+}
+
+/// Carry out a sandbox command sent for the bank `tenant`, see `sandbox`.
+/// Returns the reply.
+fn run_sandbox_command(
+    command: SandboxCommand,
+    tenant: &Option<String>,
+    tenants: &mut BTreeMap<Option<String>, Tenant>,
+    transport: &Arc<dyn Transport>,
+    config: &Config,
+) -> Result<String, SandboxError> {
+    match command {
+        SandboxCommand::Reset { name, empty } => {
+            let key = Some(name.clone());
+            if tenants.get(&key).is_some_and(|existing| !existing.sandbox) {
+                return Err(SandboxError::NotASandbox(name));
+            }
+            let source = tenants
+                .get(tenant)
+                .ok_or_else(|| SandboxError::UnknownTenant(tenant.clone().unwrap_or_default()))?;
+            let bank = source.bank.sandbox(empty);
+            // Sandboxes neither notify the webhooks nor reach peer banks.
+            let tenant =
+                Tenant::new(bank, transport, config, &[], Peers::default()).map_err(|e| {
+                    SandboxError::Start {
+                        name: name.clone(),
+                        reason: format!("{e:#}"),
+                    }
+                })?;
+            let info = SandboxInfo {
+                accounts: tenant.bank.accounts.len(),
+                name,
+            };
+            tenants.insert(
+                key,
+                Tenant {
+                    sandbox: true,
+                    ..tenant
+                },
+            );
+            info!(
+                "Reset sandbox '{}' with {} accounts",
+                info.name, info.accounts
+            );
+            Ok(serde_json::to_string(&info).unwrap_or_default())
+        }
+        SandboxCommand::Drop { name } => {
+            let key = Some(name.clone());
+            match tenants.get(&key) {
+                Some(existing) if existing.sandbox => {
+                    tenants.remove(&key);
+                    info!("Dropped sandbox '{name}'");
+                    Ok("200".to_string())
+                }
+                Some(_) => Err(SandboxError::NotASandbox(name)),
+                None => Err(SandboxError::NotFound(name)),
+            }
+        }
+        SandboxCommand::List => {
+            let names: Vec<&String> = tenants
+                .iter()
+                .filter(|(_, tenant)| tenant.sandbox)
+                .filter_map(|(name, _)| name.as_ref())
+                .collect();
+            Ok(serde_json::to_string(&names).unwrap_or_default())
+        }
+    }
+}
+
+/// Join the configured cluster or standby pair, if any, with the default
+/// bank, see `cluster`. Replies then wait for the events before them to be
+/// committed.
+#[cfg(feature = "replication")]
+fn join_cluster(
+    bank: &mut Bank,
+    config: &Config,
+    transport: &mut Arc<dyn Transport>,
+) -> Result<Option<Arc<dyn Replication>>> {
+    if config.cluster.is_none() && config.standby.is_none() {
+        return Ok(None);
+    }
+    let data_dir = config
+        .data_dir
+        .as_deref()
+        .ok_or_else(|| ConfigError::Invalid("replication needs a data_dir".to_string()))?;
+    let base = bank.events.last_sequence();
+    let replication: Arc<dyn Replication> = match (&config.cluster, &config.standby) {
+        (Some(cluster_config), None) => {
+            let socket_path = cluster_config
+                .nodes
+                .get(&cluster_config.node)
+                .ok_or_else(|| cluster::ClusterError::UnknownNode(cluster_config.node.clone()))?;
+            let socket = socket::create_socket_at(socket_path, config)?;
+            cluster::Cluster::start(cluster_config, socket, data_dir, base)?
+        }
+        (None, Some(standby_config)) => {
+            let socket = socket::create_socket_at(&standby_config.socket_path, config)?;
+            cluster::Pair::start(standby_config, socket, data_dir, base)?
+        }
+        _ => {
+            return Err(ConfigError::Invalid(
+                "configure either a cluster or a standby, not both".to_string(),
+            )
+            .into())
+        }
+    };
+    bank.set_cluster(Arc::clone(&replication));
+    *transport = Arc::new(cluster::CommittedReplies::new(
+        Arc::clone(transport),
+        Arc::clone(&replication),
+    ));
+    Ok(Some(replication))
+}
+
+#[cfg(not(feature = "replication"))]
+fn join_cluster(
+    _bank: &mut Bank,
+    config: &Config,
+    _transport: &mut Arc<dyn Transport>,
+) -> Result<Option<Arc<dyn Replication>>> {
+    if config.cluster.is_some() || config.standby.is_some() {
+        warn!("Ignoring cluster and standby, built without the replication feature");
+    }
+    Ok(None)
+}
+
+/// Apply what was replicated to the default bank and store what was
+/// committed. Fails once the bank has events that were dropped, which a
+/// restart undoes as they were never stored.
+fn sync_with_cluster(bank: &mut Bank, cluster: &dyn Replication) -> Result<()> {
+    if let Some(dropped) = cluster.dropped(bank.events.last_sequence()) {
+        return Err(anyhow!(
+            "Event {dropped} of this server was dropped, restart it to catch up"
+        ));
+    }
+    bank.apply_replicated(cluster.events_after(bank.events.last_sequence()))?;
+    let stored = cluster.stored();
+    let committed = cluster.commit_index().min(bank.events.last_sequence());
+    if committed > stored {
+        bank.store_committed(stored, committed);
+        cluster.set_stored(committed)?;
+        bank.snapshot_if_due();
+    }
+    Ok(())
+}
+
+/// Open the bank of each of the `tenants`, like the default one but in its
+/// own data directory.
+fn open_tenant_banks(config: &Config) -> Result<BTreeMap<String, Bank>> {
+    let mut data_dirs: BTreeSet<&Path> = config.data_dir.as_deref().into_iter().collect();
+    let mut banks = BTreeMap::new();
+    for (name, tenant) in &config.tenants {
+        let accounts_file = tenant.accounts_file.as_deref();
+        let bank = match &tenant.data_dir {
+            Some(data_dir) => {
+                if !data_dirs.insert(data_dir) {
+                    return Err(ConfigError::Invalid(format!(
+                        "tenant '{name}' shares the data directory {}",
+                        data_dir.display()
+                    ))
+                    .into());
+                }
+                open_bank(data_dir, config.snapshot_every, accounts_file)?
+            }
+            None => init_bank(accounts_file)?,
+        };
+        info!("Opened the bank of tenant '{name}'");
+        banks.insert(name.clone(), bank);
+    }
+    Ok(banks)
+}
+
+fn reload_config(
+    config: &mut Config,
+    auth: &mut Auth,
+    tenants: &mut BTreeMap<Option<String>, Tenant>,
+    rate_limiter: &mut RateLimiter,
+    tracer: &mut Tracer,
+    cluster: Option<&dyn Replication>,
+) {
+    notify_systemd("RELOADING=1");
+    let reloaded = config.reload().map(|new_config| {
+        let new_config = new_config?;
+        let new_auth = Auth::from_config(&new_config)?;
+        anyhow::Ok((new_config, new_auth))
+    });
+    match reloaded {
+        Some(Ok((new_config, new_auth))) => {
+            if new_config.socket_path != config.socket_path
+                || new_config.socket_mode != config.socket_mode
+                || new_config.socket_owner != config.socket_owner
+                || new_config.socket_group != config.socket_group
+                || new_config.vsock_port != config.vsock_port
+                || new_config.udp_address != config.udp_address
+                || new_config.replica_socket_path != config.replica_socket_path
+            {
+                warn!("Socket settings only take effect after a restart");
+            }
+            if new_config.data_dir != config.data_dir {
+                warn!("The data directory only changes after a restart");
+            }
+            if new_config.run_as_user != config.run_as_user
+                || new_config.run_as_group != config.run_as_group
+            {
+                warn!("The user and group to run as only change after a restart");
+            }
+            if new_config.noise != config.noise {
+                warn!("Noise settings only take effect after a restart");
+            }
+            if new_config.hardening != config.hardening {
+                warn!("Hardening settings only take effect after a restart");
+            }
+            if new_config.encryption_key != config.encryption_key {
+                warn!("The encryption key only changes after a restart");
+            }
+            if new_config.tenants != config.tenants {
+                warn!("Tenants only change after a restart");
+            }
+            if new_config.cluster != config.cluster {
+                warn!("The cluster only changes after a restart");
+            }
+            if new_config.standby != config.standby {
+                let promoted = new_config
+                    .standby
+                    .as_ref()
+                    .is_some_and(|standby| standby.role == StandbyRole::Primary)
+                    && cluster.is_some_and(|cluster| cluster.promote());
+                if !promoted {
+                    warn!("The standby settings only change after a restart, but for promoting it");
+                }
+            }
+            logging::configure(new_config.log_level.as_deref(), new_config.log_format);
+            let previous_webhooks = std::mem::replace(config, new_config).webhooks;
+            let previous_auth = std::mem::replace(auth, new_auth);
+            auth.carry_over(previous_auth);
+            for (name, Tenant { bank, .. }) in tenants.iter_mut() {
+                configure_bank(bank, config);
+                if name.is_none() && config.webhooks != previous_webhooks {
+                    match Webhooks::start(&config.webhooks) {
+                        Ok(webhooks) => bank.set_webhooks(webhooks),
+                        Err(e) => error!("Keeping previous webhooks: {e:?}"),
+                    }
+                }
+                if let Err(e) = load_policy(bank, config) {
+                    error!("Keeping previous policy: {e:?}");
+                }
+            }
+            if let Err(e) = configure_tracing(tracer, config) {
+                error!("Keeping previous trace export: {e:?}");
+            }
+            rate_limiter.set_config(config.rate_limit.clone());
+            info!("Reloaded configuration");
+            notify_systemd("READY=1");
+        }
+        Some(Err(e)) => {
+            error!("Keeping previous configuration: {e:?}");
+            notify_systemd("READY=1");
+        }
+        None => warn!("Received SIGHUP but no configuration file was given"),
+    }
+}
+
+/// How often the main loop wakes up without requests.
+const TICK: Duration = Duration::from_secs(1);
+
+/// Why the server stopped serving, when it did not fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// A client sent the `q` instruction.
+    Quit,
+    /// A client sent the `d` instruction, and the requests and webhook
+    /// deliveries in hand were finished.
+    Drained,
+}
+
+/// Serve `bank` and the banks of the configured `tenants` until told to
+/// stop. Fails with a `ConfigError` for settings that do not go together.
+pub fn run_app(bank: Bank, config: Config) -> Result<ShutdownReason> {
+    info!("Entered the main loop of the program");
+    // Before any thread is started, so that they are all confined.
+    hardening::confine_files(&config)?;
+    signals::install_reload_handler()?;
+    let transport = listen(&config)?;
+    let replica = match &config.replica_socket_path {
+        Some(path) => {
+            let replica_socket = socket::create_socket_at(path, &config)?;
+            socket::enable_credentials(&replica_socket)?;
+            // Notice the replica was dropped even without requests.
+            replica_socket.set_read_timeout(Some(TICK))?;
+            info!("Created the replica socket");
+            let auth = Auth::from_config(&config)?;
+            Some(Replica::start(
+                encrypt(Arc::new(replica_socket), &config)?,
+                auth,
+                config.max_message_size,
+            ))
+        }
+        None => None,
+    };
+    let transport = encrypt(transport, &config)?;
+    serve(bank, config, transport, replica)
+}
+
+/// The transport requests come in on: the socket at `socket_path`, or the
+/// one systemd passed, unless vsock or UDP is configured. Receiving on it
+/// gives up every `TICK`, so that scheduled work gets done without
+/// requests.
+fn listen(config: &Config) -> Result<Arc<dyn Transport>> {
+    let transport: Arc<dyn Transport> = match (config.vsock_port, config.udp_address) {
+        (Some(_), Some(_)) => {
+            return Err(ConfigError::Invalid(
+                "configure either vsock_port or udp_address, not both".to_string(),
+            )
+            .into())
+        }
+        (Some(port), None) => {
+            let transport = VsockTransport::bind(port, TICK)?;
+            info!("Listening on vsock port {port}");
+            Arc::new(transport)
+        }
+        (None, Some(address)) => {
+            let transport = UdpTransport::bind(address, TICK)?;
+            info!("Listening on UDP {address}");
+            Arc::new(transport)
+        }
+        (None, None) => {
+            let socket = match systemd::listen_socket()? {
+                Some(socket) => {
+                    info!("Using the socket passed by systemd");
+                    socket
+                }
+                None => {
+                    let socket = socket::create_socket(config)?;
+                    info!("Created the socket");
+                    socket
+                }
+            };
+            socket::enable_credentials(&socket)?;
+            socket.set_read_timeout(Some(TICK))?;
+            Arc::new(socket)
+        }
+    };
+    Ok(transport)
+}
+
+/// `transport` in Noise sessions if they are configured, see `noise`.
+fn encrypt(transport: Arc<dyn Transport>, config: &Config) -> Result<Arc<dyn Transport>> {
+    Ok(match &config.noise {
+        Some(noise) => Arc::new(NoiseTransport::new(transport, noise)?),
+        None => transport,
+    })
+}
+
+/// Serve requests coming in on `transport` until the `q` instruction, or
+/// the `d` instruction and no more requests, and keep the copy of the
+/// default bank `replica` serves up to date.
+fn serve(
+    bank: Bank,
+    mut config: Config,
+    mut transport: Arc<dyn Transport>,
+    mut replica: Option<Replica>,
+) -> Result<ShutdownReason> {
+    let mut auth = Auth::from_config(&config)?;
+    let mut tenants = BTreeMap::new();
+    // Replies of peer banks come to sockets of their own, next to ours.
+    let mut reply_path = config.socket_path.clone().into_os_string();
+    reply_path.push(".interbank");
+    let peers = Peers::new(config.peer_banks.clone(), Path::new(&reply_path));
+    let tenant = Tenant::new(bank, &transport, &config, &config.webhooks, peers)?;
+    tenants.insert(None, tenant);
+    for (name, bank) in open_tenant_banks(&config)? {
+        let webhooks = &config.tenants[&name].webhooks;
+        let tenant = Tenant::new(bank, &transport, &config, webhooks, Peers::default())?;
+        tenants.insert(Some(name), tenant);
+    }
+    let default_bank = &mut tenants.get_mut(&None).unwrap().bank;
+    let cluster = join_cluster(default_bank, &config, &mut transport)?;
+    // Every socket is bound by now.
+    privileges::drop_privileges(&config)?;
+    hardening::confine_syscalls(&config)?;
+    let socket = &*transport;
+    let mut rate_limiter = RateLimiter::new(config.rate_limit.clone());
+    let started = Instant::now();
+    let mut metrics = Metrics::new();
+    let mut tracer = Tracer::new();
+    configure_tracing(&mut tracer, &config)?;
+    // Request being handled, traced until the loop comes round again.
+    let mut in_flight: Option<RequestTrace> = None;
+    notify_systemd("READY=1");
+    // Reused by every request rather than allocated for each.
+    let mut request_buffer = Vec::new();
+    let mut payload_buffer = Vec::new();
+    // Since the `d` instruction, only what followers serve is served.
+    let mut draining = false;
+    let mut accepts_writes = false;
+
+    loop {
+        if let Some(trace) = in_flight.take() {
+            let request = trace.finish();
+            metrics.observe_request(&request.instruction, request.duration);
+            tracer.record(request);
+            logging::clear_fields();
+        }
+        if signals::take_reload_request() {
+            reload_config(
+                &mut config,
+                &mut auth,
+                &mut tenants,
+                &mut rate_limiter,
+                &mut tracer,
+                cluster.as_deref(),
+            );
+            if let Some(replica) = &replica {
+                match Auth::from_config(&config) {
+                    Ok(replica_auth) => replica.set_auth(replica_auth),
+                    Err(e) => error!("Keeping previous replica authentication: {e:?}"),
+                }
+            }
+        }
+        if let Some(cluster) = &cluster {
+            let bank = &mut tenants.get_mut(&None).unwrap().bank;
+            sync_with_cluster(bank, &**cluster)?;
+            // The server writes took over from may have served requests
+            // this one never saw.
+            let writes = cluster.accepts_writes(bank.events.last_sequence());
+            if writes && !accepts_writes {
+                auth.refuse_earlier_requests();
+            }
+            accepts_writes = writes;
+        }
+        for (name, tenant) in tenants.iter_mut() {
+            // Followers take the work's events from the leader.
+            let follower = name.is_none()
+                && cluster.as_ref().is_some_and(|cluster| {
+                    !cluster.accepts_writes(tenant.bank.events.last_sequence())
+                });
+            if !follower {
+                tenant.bank.run_due_jobs();
+                if tenant.peers.retry_due(tenant.bank.now()) {
+                    tenant.bank.confirm_peer_transfers(&tenant.peers);
+                }
+            }
+        }
+        if let Some(replica) = &mut replica {
+            replica.publish(&tenants[&None].bank);
+        }
+        request_buffer.resize(config.max_message_size, 0);
+        payload_buffer.resize(config.max_message_size, 0);
+
+        match socket.recv_from(&mut request_buffer) {
+            Ok((length, sender, credentials)) => {
+                if length > request_buffer.len() {
+                    warn!("Rejected request of {length} bytes, over the limit");
+                    reply(socket, &sender, "413".as_bytes())?;
+                    continue;
+                }
+                let Some(instruction) = protocol::parse_instruction(&request_buffer[..length])
+                else {
+                    warn!("Rejected request without an instruction");
+                    reply(socket, &sender, "400".as_bytes())?;
+                    continue;
+                };
+                info!("Received '{instruction}' instruction from client");
+                let trace = in_flight.insert(RequestTrace::start(instruction));
+                logging::set_field("instruction", instruction);
+                if let Some(sender) = &sender {
+                    logging::set_field("sender", sender.display());
+                }
+
+                let header = match protocol::parse_header(&request_buffer[1..length]) {
+                    Ok(header) => header,
+                    Err(e) => {
+                        warn!("Rejected '{instruction}' instruction with malformed header: {e}");
+                        respond(socket, &sender, trace, "400".as_bytes())?;
+                        continue;
+                    }
+                };
+                let version = match header.protocol_version() {
+                    Ok(version) => version,
+                    Err(e) => {
+                        warn!("Rejected '{instruction}' instruction: {e}");
+                        respond(socket, &sender, trace, "505".as_bytes())?;
+                        continue;
+                    }
+                };
+                trace.phase(Phase::Validate);
+                let identity = match auth.authorize(instruction, &header, credentials.as_ref()) {
+                    Ok(identity) => identity,
+                    Err(e) => {
+                        warn!(
+                            "Rejected '{instruction}' instruction from peer {credentials:?}: {e}"
+                        );
+                        respond(socket, &sender, trace, e.status().as_bytes())?;
+                        continue;
+                    }
+                };
+                if let Some(identity) = &identity {
+                    debug!("Request authenticated as '{identity}'");
+                    logging::set_field("identity", identity);
+                }
+                let client = client_key(identity.as_ref(), &sender, credentials.as_ref());
+                if !rate_limiter.allow(&client, Instant::now()) {
+                    warn!("Rate limited '{instruction}' instruction from {client}");
+                    respond(socket, &sender, trace, "429".as_bytes())?;
+                    continue;
+                }
+                if draining && !cluster::serves_on_follower(instruction) {
+                    warn!("Refused '{instruction}' instruction while draining");
+                    respond(socket, &sender, trace, "503".as_bytes())?;
+                    continue;
+                }
+                let mut request = Request {
+                    socket,
+                    sender: &sender,
+                    trace,
+                    instruction,
+                    header: &header,
+                    version,
+                    identity,
+                    client: &client,
+                    auth: &mut auth,
+                    config: &config,
+                    metrics: &mut metrics,
+                };
+                let buffer = &mut payload_buffer[..];
+                if instruction == "z" {
+                    handlers::sandbox(&mut request, &mut tenants, &transport, buffer)?;
+                    continue;
+                }
+                let Some(tenant) = tenants.get_mut(&header.tenant) else {
+                    warn!("Rejected '{instruction}' instruction for unknown tenant");
+                    request.respond("404".as_bytes())?;
+                    continue;
+                };
+                if let Some(tenant) = &header.tenant {
+                    logging::set_field("tenant", tenant);
+                }
+                if let Some(cluster) = cluster.as_ref().filter(|_| header.tenant.is_none()) {
+                    if !cluster::serves_on_follower(instruction)
+                        && !cluster.accepts_writes(tenant.bank.events.last_sequence())
+                    {
+                        warn!(
+                            "Refused '{instruction}' instruction, writes go to {:?}",
+                            cluster.leader()
+                        );
+                        request.respond("421".as_bytes())?;
+                        continue;
+                    }
+                }
+                if let Some(identity) = &mut request.identity {
+                    extend_identity(identity, &tenant.bank);
+                }
+                if !protocol::has_payload(instruction)
+                    && tenant.bank.plugin_for(instruction).is_none()
+                {
+                    if let Err(e) = request.auth.verify_signature(instruction, &header, &[]) {
+                        warn!("Rejected '{instruction}' instruction: {e}");
+                        request.respond(e.status().as_bytes())?;
+                        continue;
+                    }
+                }
+
+                let bank = &mut tenant.bank;
+                match instruction {
+                    "t" => handlers::transfer(&mut request, tenant, buffer)?,
+                    "a" => handlers::admin(&mut request, bank, buffer)?,
+                    "b" => handlers::batch(&mut request, bank, buffer)?,
+                    "p" => handlers::hold(&mut request, bank, buffer)?,
+                    "w" => handlers::escrow(&mut request, bank, buffer)?,
+                    "o" => handlers::owners(&mut request, bank, buffer)?,
+                    "l" => handlers::goal(&mut request, bank, buffer)?,
+                    "s" => handlers::schedule(&mut request, bank, buffer)?,
+                    "r" => handlers::reversal(&mut request, bank, buffer)?,
+                    "x" => handlers::interbank(&mut request, tenant, buffer)?,
+                    "h" => handlers::history(&mut request, bank, buffer)?,
+                    "v" => handlers::attestation(&mut request, bank, buffer)?,
+                    "e" => handlers::statement(&mut request, bank, buffer)?,
+                    "u" => handlers::subscription(&mut request, tenant, buffer)?,
+                    "i" => handlers::list_accounts(&mut request, bank)?,
+                    "j" => handlers::search_accounts(&mut request, bank, buffer)?,
+                    "f" => handlers::flagged(&mut request, bank)?,
+                    "m" => handlers::metrics(&mut request, bank)?,
+                    "g" => handlers::statistics(&mut request, bank)?,
+                    "y" => handlers::aggregates(&mut request, bank, buffer)?,
+                    "c" => handlers::consistency(&mut request, bank)?,
+                    "k" => handlers::health(&mut request, bank, started)?,
+                    "n" => handlers::version(&mut request, bank)?,
+                    "q" => {
+                        notify_systemd("STOPPING=1");
+                        return Ok(ShutdownReason::Quit);
+                    }
+                    "d" => {
+                        info!("Draining, refusing transactions from now on");
+                        notify_systemd("STOPPING=1");
+                        draining = true;
+                        request.respond("200".as_bytes())?;
+                    }
+                    _ => handlers::plugin(&mut request, bank, buffer)?,
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::Interrupted
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                ) =>
+            {
+                if draining && e.kind() != io::ErrorKind::Interrupted {
+                    for tenant in tenants.into_values() {
+                        tenant.bank.finish_webhooks();
+                    }
+                    info!("Drained");
+                    return Ok(ShutdownReason::Drained);
+                }
+            }
+            Err(e) => error!("Unable to receive a request: {e:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bank_with(balances: &[(&str, i64)]) -> Bank {
+        let mut bank = Bank::new();
+        for (name, balance) in balances {
+            bank.open_account(
+                name.to_string(),
+                Balance::from_minor(*balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        bank
+    }
+
+    fn name(name: &str) -> AccountRef {
+        AccountRef::Name(name.to_string())
+    }
+
+    fn tx_info(from: AccountRef, to: AccountRef, amount: u64) -> TxInfo {
+        TxInfo {
+            from,
+            to,
+            amount: Money::from_minor(amount),
+            currency: None,
+            convert: false,
+            pin: None,
+            memo: None,
+            external_ref: None,
+            idempotency_key: None,
+            dry_run: false,
+        }
+    }
+
+    fn transfer(bank: &mut Bank, from: &str, to: &str, amount: u64) -> Result<(), CustomError> {
+        bank.handle_transaction(tx_info(name(from), name(to), amount))
+            .map(|_| ())
+    }
+
+    fn balance(bank: &Bank, account: &str) -> i64 {
+        let id = bank.resolve(&name(account)).unwrap();
+        bank.accounts[&id].balance.minor_units()
+    }
+
+    #[test]
+    fn account_names_are_shared_rather_than_copied() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        let (key, id) = bank.account_ids.get_key_value("a").unwrap();
+        let name = &bank.accounts[id].name;
+        assert!(Arc::ptr_eq(key, name));
+        let name = name.clone();
+
+        bank.set_status(&self::name("a"), AccountStatus::Frozen)
+            .unwrap();
+        let Err(CustomError::AccountFrozenError(e)) = transfer(&mut bank, "a", "b", 1) else {
+            panic!("transfer out of a frozen account was made");
+        };
+        assert!(Arc::ptr_eq(&e.account_name, &name));
+    }
+
+    #[test]
+    fn transfer_of_entire_balance_leaves_zero() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        transfer(&mut bank, "a", "b", 100).unwrap();
+        assert_eq!(balance(&bank, "a"), 0);
+        assert_eq!(balance(&bank, "b"), 100);
+    }
+
+    #[test]
+    fn transfer_exceeding_balance_is_rejected() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        let result = transfer(&mut bank, "a", "b", 101);
+        assert!(matches!(
+            result,
+            Err(CustomError::InsufficientFundsError(_))
+        ));
+        assert_eq!(balance(&bank, "a"), 100);
+        assert_eq!(balance(&bank, "b"), 0);
+    }
+
+    #[test]
+    fn transfer_from_empty_account_is_rejected() {
+        let mut bank = bank_with(&[("a", 0), ("b", 0)]);
+        let result = transfer(&mut bank, "a", "b", 1);
+        assert!(matches!(
+            result,
+            Err(CustomError::InsufficientFundsError(_))
+        ));
+    }
+
+    #[test]
+    fn transfer_up_to_max_balance_succeeds() {
+        let mut bank = bank_with(&[("a", 1), ("b", i64::MAX - 1)]);
+        transfer(&mut bank, "a", "b", 1).unwrap();
+        assert_eq!(balance(&bank, "b"), i64::MAX);
+    }
+
+    #[test]
+    fn transfer_overflowing_recipient_is_rejected_without_side_effects() {
+        let mut bank = bank_with(&[("a", 10), ("b", i64::MAX - 1)]);
+        let result = transfer(&mut bank, "a", "b", 2);
+        assert!(matches!(result, Err(CustomError::BalanceOverflowError(_))));
+        assert_eq!(balance(&bank, "a"), 10);
+        assert_eq!(balance(&bank, "b"), i64::MAX - 1);
+    }
+
+    #[test]
+    fn mint_overflow_is_rejected() {
+        let mut bank = bank_with(&[("a", i64::MAX)]);
+        let result = bank.mint(&name("a"), Money::from_minor(1));
+        assert!(matches!(result, Err(CustomError::BalanceOverflowError(_))));
+        assert_eq!(balance(&bank, "a"), i64::MAX);
+    }
+
+    #[test]
+    fn overdraft_allows_negative_balance_up_to_limit() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        bank.set_overdraft_limit(&name("a"), Money::from_minor(50))
+            .unwrap();
+        transfer(&mut bank, "a", "b", 150).unwrap();
+        assert_eq!(balance(&bank, "a"), -50);
+        let result = transfer(&mut bank, "a", "b", 1);
+        assert!(matches!(
+            result,
+            Err(CustomError::InsufficientFundsError(_))
+        ));
+        assert_eq!(balance(&bank, "a"), -50);
+        assert_eq!(balance(&bank, "b"), 150);
+    }
+
+    fn convert(bank: &mut Bank, from: &str, to: &str, amount: u64) -> Result<(), CustomError> {
+        bank.handle_transaction(TxInfo {
+            convert: true,
+            ..tx_info(name(from), name(to), amount)
+        })
+        .map(|_| ())
+    }
+
+    #[test]
+    fn cross_currency_transfer_converts_and_records_both_legs() {
+        let mut bank = bank_with(&[("a", 10_000)]);
+        bank.create_account("b".to_string(), Money::ZERO, Currency::USD)
+            .unwrap();
+        bank.set_fx_rate(Currency::EUR, Currency::USD, "1.0854".parse().unwrap())
+            .unwrap();
+        let recorded = bank.ledger().entries().len();
+        convert(&mut bank, "a", "b", 1_000).unwrap();
+        assert_eq!(balance(&bank, "a"), 9_000);
+        assert_eq!(balance(&bank, "b"), 1_085);
+        let legs = &bank.ledger().entries()[recorded..];
+        assert_eq!(legs.len(), 2);
+        assert_eq!(legs[0].currency, Currency::EUR);
+        assert_eq!(legs[1].amount, Money::from_minor(1_085));
+        assert_eq!(legs[1].currency, Currency::USD);
+    }
+
+    #[test]
+    fn postings_balance_and_give_the_account_balances() {
+        let mut bank = bank_with(&[("a", 10_000)]);
+        bank.create_account("b".to_string(), Money::ZERO, Currency::USD)
+            .unwrap();
+        bank.set_fx_rate(Currency::EUR, Currency::USD, "1.0854".parse().unwrap())
+            .unwrap();
+        convert(&mut bank, "a", "b", 1_000).unwrap();
+        bank.mint(&name("b"), Money::from_minor(15)).unwrap();
+
+        assert!(bank.ledger().journal().all(|entry| entry.is_balanced()));
+        let balances = bank.ledger().balances();
+        let total = |account, currency| balances[&(account, currency)].to_string();
+        let fx_desk = LedgerAccount::System(SystemAccount::FxDesk);
+        let capital = LedgerAccount::System(SystemAccount::Capital);
+        assert_eq!(total(fx_desk, Currency::EUR), "10.00");
+        assert_eq!(total(fx_desk, Currency::USD), "-10.85");
+        assert_eq!(total(capital, Currency::EUR), "-100.00");
+        assert_eq!(total(capital, Currency::USD), "-0.15");
+        for account in bank.accounts.values() {
+            let key = (LedgerAccount::Customer(account.id), account.currency);
+            assert_eq!(balances[&key].to_balance(), Some(account.balance));
+        }
+    }
+
+    #[test]
+    fn cross_currency_transfer_needs_rate_and_opt_in() {
+        let mut bank = bank_with(&[("a", 10_000)]);
+        bank.create_account("b".to_string(), Money::ZERO, Currency::USD)
+            .unwrap();
+        assert!(matches!(
+            convert(&mut bank, "a", "b", 1_000),
+            Err(CustomError::NoExchangeRateError(_))
+        ));
+        bank.set_fx_rate(Currency::USD, Currency::EUR, "0.5".parse().unwrap())
+            .unwrap();
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 1_000),
+            Err(CustomError::CurrencyMismatchError(_))
+        ));
+        convert(&mut bank, "a", "b", 1_000).unwrap();
+        assert_eq!(balance(&bank, "b"), 2_000);
+    }
+
+    #[test]
+    fn frozen_account_can_receive_but_not_send() {
+        let mut bank = bank_with(&[("a", 100), ("b", 100)]);
+        bank.set_status(&name("a"), AccountStatus::Frozen).unwrap();
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 10),
+            Err(CustomError::AccountFrozenError(_))
+        ));
+        transfer(&mut bank, "b", "a", 10).unwrap();
+        assert_eq!(balance(&bank, "a"), 110);
+    }
+
+    #[test]
+    fn closed_account_rejects_everything() {
+        let mut bank = bank_with(&[("a", 100), ("b", 100)]);
+        bank.set_status(&name("a"), AccountStatus::Closed).unwrap();
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 10),
+            Err(CustomError::AccountClosedError(_))
+        ));
+        assert!(matches!(
+            transfer(&mut bank, "b", "a", 10),
+            Err(CustomError::AccountClosedError(_))
+        ));
+        assert!(matches!(
+            bank.mint(&name("a"), Money::from_minor(10)),
+            Err(CustomError::AccountClosedError(_))
+        ));
+        assert_eq!(balance(&bank, "a"), 100);
+    }
+
+    #[test]
+    fn transfer_addresses_accounts_by_id_or_name() {
+        let mut bank = bank_with(&[("a", 100)]);
+        let id = bank
+            .create_account("b".to_string(), Money::ZERO, Currency::EUR)
+            .unwrap();
+        bank.handle_transaction(tx_info(name("a"), AccountRef::Id(id), 40))
+            .unwrap();
+        assert_eq!(balance(&bank, "b"), 40);
+        assert!(matches!(
+            bank.mint(&AccountRef::Id(AccountId(99)), Money::from_minor(1)),
+            Err(CustomError::AccountDoesNotExistError(_))
+        ));
+    }
+
+    #[test]
+    fn history_returns_memo_and_reference_of_recent_entries() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0), ("c", 0)]);
+        transfer(&mut bank, "a", "c", 10).unwrap();
+        bank.handle_transaction(TxInfo {
+            memo: Some("rent".to_string()),
+            external_ref: Some("INV-7".to_string()),
+            ..tx_info(name("a"), name("b"), 20)
+        })
+        .unwrap();
+        transfer(&mut bank, "b", "a", 5).unwrap();
+        let query = HistoryQuery {
+            account: name("b"),
+            limit: 1,
+        };
+        let entries = bank.history(&query).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].amount, Money::from_minor(5));
+        let query = HistoryQuery {
+            account: name("b"),
+            limit: 10,
+        };
+        let entries = bank.history(&query).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].memo.as_deref(), Some("rent"));
+        assert_eq!(entries[0].external_ref.as_deref(), Some("INV-7"));
+    }
+
+    #[test]
+    fn attestation_proves_balance_as_of_ledger_entry() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0), ("c", 0)]);
+        let query = |sequence| AttestationQuery {
+            account: name("c"),
+            sequence,
+        };
+        assert!(matches!(
+            bank.attest(&query(None)),
+            Err(CustomError::AttestationKeyMissingError(_))
+        ));
+        let key = SigningKey::from_seed(&[7; 32]);
+        bank.set_attestation_key(Some(key.clone()));
+        let before = bank.ledger().entries().len() as u64;
+        transfer(&mut bank, "a", "c", 10).unwrap();
+
+        let latest = bank.attest(&query(None)).unwrap();
+        assert_eq!(latest.ledger_sequence, before + 1);
+        assert_eq!(latest.balance, Balance::from_minor(10));
+        assert_eq!(latest.accounts, 3);
+        assert_eq!(latest.public_key, crypto::to_hex(key.public_key()));
+        let leaf = attestation::leaf(latest.account, Currency::EUR, latest.balance);
+        let root = attestation::fold(leaf, &latest.proof).unwrap();
+        assert_eq!(latest.merkle_root, crypto::to_hex(&root));
+        let message = attestation::message(latest.ledger_sequence, &root);
+        assert_eq!(latest.signature, crypto::to_hex(&key.sign(&message)));
+
+        let earlier = bank.attest(&query(Some(before))).unwrap();
+        assert_eq!(earlier.balance, Balance::ZERO);
+        assert_ne!(earlier.merkle_root, latest.merkle_root);
+        assert!(matches!(
+            bank.attest(&query(Some(before + 2))),
+            Err(CustomError::LedgerEntryNotFoundError(_))
+        ));
+    }
+
+    #[test]
+    fn statement_runs_from_opening_to_closing_balance() {
+        const DAY: u64 = 24 * 60 * 60;
+        // 2024-03-01 12:00 UTC.
+        let clock = Arc::new(clock::ManualClock::new(1_709_294_400));
+        let mut bank = Bank::with_clock(clock.clone());
+        for (account, balance) in [("a", 1_000), ("b", 0)] {
+            bank.open_account(
+                account.to_string(),
+                Balance::from_minor(balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        transfer(&mut bank, "a", "b", 100).unwrap();
+        clock.advance(DAY);
+        transfer(&mut bank, "b", "a", 30).unwrap();
+        transfer(&mut bank, "a", "b", 50).unwrap();
+        clock.advance(DAY);
+        transfer(&mut bank, "a", "b", 1).unwrap();
+
+        let day = |date: &str| Some(date.parse().unwrap());
+        let statement = bank
+            .statement("a", day("2024-03-02"), day("2024-03-02"))
+            .unwrap();
+        assert_eq!(statement.opening_balance, Balance::from_minor(900));
+        let balances: Vec<i64> = statement
+            .lines
+            .iter()
+            .map(|line| line.balance.minor_units())
+            .collect();
+        assert_eq!(balances, [930, 880]);
+        assert_eq!(statement.lines[0].counterparty.as_deref(), Some("b"));
+        assert_eq!(statement.closing_balance, Balance::from_minor(880));
+        let csv = statement.render(StatementFormat::Csv);
+        assert_eq!(csv.lines().count(), 5);
+        assert!(csv.ends_with("2024-03-02,,,,Closing balance,,,8.80,EUR\n"));
+        let ofx = statement.render(StatementFormat::Ofx);
+        assert!(ofx.contains("<DTSTART>20240302000000</DTSTART><DTEND>20240302235959</DTEND>"));
+        assert!(ofx.contains(
+            "<TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20240302120000</DTPOSTED><TRNAMT>-0.50</TRNAMT>"
+        ));
+        assert!(ofx.contains("<LEDGERBAL><BALAMT>8.80</BALAMT>"));
+        let qif = statement.render(StatementFormat::Qif);
+        assert!(qif.starts_with("!Type:Bank\nD03/02/2024\nT0.30\n"));
+        assert_eq!(qif.matches("^\n").count(), 2);
+
+        let statement = bank.statement("a", None, None).unwrap();
+        assert_eq!(statement.opening_balance, Balance::ZERO);
+        assert_eq!(statement.lines.len(), 5);
+        assert_eq!(statement.closing_balance, Balance::from_minor(879));
+        assert!(matches!(
+            bank.statement("a", day("2024-03-02"), day("2024-03-01")),
+            Err(CustomError::InvalidPeriodError(_))
+        ));
+    }
+
+    #[test]
+    fn import_opens_valid_rows_and_reports_the_rest() {
+        let path = std::env::temp_dir().join(format!("bank-import-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "name,balance,currency,email,tags\n\
+             jozko,12.50,,jozko@example.com,vip;staff\n\
+             janko,-1,EUR,,\n\
+             jozko,1,EUR,,\n\
+             ferko,,USD,,\n",
+        )
+        .unwrap();
+        let mut bank = Bank::new();
+        let report = bank.import_accounts(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.imported, 2);
+        let rejected: Vec<_> = report.rejected.iter().map(|row| row.row).collect();
+        assert_eq!(rejected, [3, 4]);
+        let jozko = bank
+            .account(&AccountRef::Name("jozko".to_string()))
+            .unwrap();
+        assert_eq!(jozko.balance, Balance::from_minor(1_250));
+        assert_eq!(jozko.metadata.email.as_deref(), Some("jozko@example.com"));
+        assert_eq!(jozko.metadata.tags.len(), 2);
+        let ferko = bank
+            .account(&AccountRef::Name("ferko".to_string()))
+            .unwrap();
+        assert_eq!(
+            (ferko.balance, ferko.currency),
+            (Balance::ZERO, Currency::USD)
+        );
+    }
+
+    #[test]
+    fn receipt_has_increasing_id_and_resulting_balances() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        let first = bank
+            .handle_transaction(tx_info(name("a"), name("b"), 30))
+            .unwrap();
+        let second = bank
+            .handle_transaction(tx_info(name("b"), name("a"), 10))
+            .unwrap();
+        assert!(second.transaction_id > first.transaction_id);
+        assert_eq!(first.from_balance, Balance::from_minor(70));
+        assert_eq!(first.to_balance, Balance::from_minor(30));
+        assert_eq!(second.to_balance, Balance::from_minor(80));
+    }
+
+    #[test]
+    fn reversal_moves_money_back_once() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        let receipt = bank
+            .handle_transaction(tx_info(name("a"), name("b"), 30))
+            .unwrap();
+        let reversal = bank.reverse(receipt.transaction_id).unwrap();
+        assert_eq!(reversal.reverses, receipt.transaction_id);
+        assert_eq!(balance(&bank, "a"), 100);
+        assert_eq!(balance(&bank, "b"), 0);
+        assert!(matches!(
+            bank.reverse(receipt.transaction_id),
+            Err(CustomError::AlreadyReversedError(_))
+        ));
+        assert!(matches!(
+            bank.reverse(reversal.transaction_id),
+            Err(CustomError::IrreversibleTransactionError(_))
+        ));
+    }
+
+    #[test]
+    fn reversal_without_funds_changes_nothing() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0), ("c", 0)]);
+        let receipt = bank
+            .handle_transaction(tx_info(name("a"), name("b"), 30))
+            .unwrap();
+        transfer(&mut bank, "b", "c", 20).unwrap();
+        assert!(matches!(
+            bank.reverse(receipt.transaction_id),
+            Err(CustomError::InsufficientFundsError(_))
+        ));
+        assert_eq!(balance(&bank, "a"), 70);
+        assert_eq!(balance(&bank, "b"), 10);
+    }
+
+    #[test]
+    fn batch_is_all_or_nothing() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0), ("c", 0)]);
+        let result = bank.handle_batch(vec![
+            tx_info(name("a"), name("b"), 60),
+            tx_info(name("b"), name("c"), 50),
+            tx_info(name("b"), name("c"), 20),
+        ]);
+        assert!(matches!(
+            result,
+            Err(CustomError::InsufficientFundsError(_))
+        ));
+        assert_eq!(balance(&bank, "a"), 100);
+        assert_eq!(balance(&bank, "b"), 0);
+        assert_eq!(balance(&bank, "c"), 0);
+
+        let receipt = bank
+            .handle_batch(vec![
+                tx_info(name("a"), name("b"), 60),
+                tx_info(name("b"), name("c"), 50),
+            ])
+            .unwrap();
+        assert_eq!(receipt.transfers, 2);
+        assert_eq!(balance(&bank, "a"), 40);
+        assert_eq!(balance(&bank, "b"), 10);
+        assert_eq!(balance(&bank, "c"), 50);
+    }
+
+    #[test]
+    fn payment_file_is_applied_as_one_batch() {
+        let path = std::env::temp_dir().join(format!("bank-pain001-{}.xml", std::process::id()));
+        let transfer = |to: &str, amount: &str| {
+            format!(
+                "<CdtTrfTxInf><PmtId><EndToEndId>{to}</EndToEndId></PmtId>\
+                 <Amt><InstdAmt Ccy=\"EUR\">{amount}</InstdAmt></Amt>\
+                 <CdtrAcct><Id><Othr><Id>{to}</Id></Othr></Id></CdtrAcct></CdtTrfTxInf>"
+            )
+        };
+        std::fs::write(
+            &path,
+            format!(
+                "<Document><CstmrCdtTrfInitn><GrpHdr><MsgId>1</MsgId><NbOfTxs>2</NbOfTxs>\
+                 </GrpHdr><PmtInf><DbtrAcct><Id><Othr><Id>a</Id></Othr></Id></DbtrAcct>\
+                 {}{}</PmtInf></CstmrCdtTrfInitn></Document>",
+                transfer("b", "0.60"),
+                transfer("c", "0.30")
+            ),
+        )
+        .unwrap();
+        let mut bank = bank_with(&[("a", 100), ("b", 0), ("c", 0)]);
+        let receipt = bank.import_payments(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(receipt.transfers, 2);
+        assert_eq!(balance(&bank, "a"), 10);
+        assert_eq!(balance(&bank, "c"), 30);
+        let entries = bank.ledger().entries();
+        assert_eq!(entries.last().unwrap().external_ref.as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn hold_reserves_funds_until_captured() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        let hold = bank
+            .place_hold(tx_info(name("a"), name("b"), 80), 1_000)
+            .unwrap();
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 30),
+            Err(CustomError::InsufficientFundsError(_))
+        ));
+        assert_eq!(balance(&bank, "a"), 100);
+        let receipt = bank
+            .capture_hold(hold.hold_id, Some(Money::from_minor(50)), 1_001)
+            .unwrap();
+        assert_eq!(receipt.to_balance, Balance::from_minor(50));
+        // The uncaptured rest is released.
+        transfer(&mut bank, "a", "b", 50).unwrap();
+        assert!(matches!(
+            bank.capture_hold(hold.hold_id, None, 1_002),
+            Err(CustomError::HoldNotFoundError(_))
+        ));
+    }
+
+    #[test]
+    fn expired_hold_releases_funds() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        bank.set_hold_ttl(60);
+        let hold = bank
+            .place_hold(tx_info(name("a"), name("b"), 100), 1_000)
+            .unwrap();
+        bank.expire_holds(1_060);
+        assert!(matches!(
+            bank.capture_hold(hold.hold_id, None, 1_060),
+            Err(CustomError::HoldNotFoundError(_))
+        ));
+        transfer(&mut bank, "a", "b", 100).unwrap();
+    }
+
+    #[test]
+    fn scheduled_transfer_runs_when_due_and_checks_funds_then() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        let first = bank
+            .schedule_transfer(2_000, tx_info(name("a"), name("b"), 60))
+            .unwrap();
+        bank.schedule_transfer(3_000, tx_info(name("a"), name("b"), 60))
+            .unwrap();
+        bank.run_scheduled_transfers(1_999);
+        assert_eq!(balance(&bank, "b"), 0);
+        assert_eq!(bank.scheduled_transfers(&name("a")).unwrap().len(), 2);
+        bank.run_scheduled_transfers(2_000);
+        assert_eq!(balance(&bank, "b"), 60);
+        assert!(bank.scheduled_transfer(first.schedule_id).is_none());
+        // Only 40 left when the second one is due.
+        bank.run_scheduled_transfers(3_000);
+        assert_eq!(balance(&bank, "a"), 40);
+        assert!(bank.scheduled_transfers(&name("a")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn standing_order_retries_short_payment_until_next_one_is_due() {
+        let mut bank = bank_with(&[("a", 0), ("b", 0)]);
+        let order_id = bank
+            .create_standing_order(
+                Every::Day,
+                1_000,
+                InsufficientFunds::Retry,
+                tx_info(name("a"), name("b"), 50),
+            )
+            .unwrap()
+            .order_id;
+        bank.run_standing_orders(1_000);
+        assert_eq!(balance(&bank, "b"), 0);
+        let retry_at = bank.standing_order(order_id).unwrap().retry_at;
+        assert_eq!(retry_at, Some(1_000 + scheduler::RETRY_INTERVAL_SECS));
+
+        bank.mint(&name("a"), Amount::from_minor(80)).unwrap();
+        bank.run_standing_orders(retry_at.unwrap());
+        assert_eq!(balance(&bank, "b"), 50);
+        let order = bank.standing_order(order_id).unwrap();
+        assert_eq!(order.retry_at, None);
+        assert_eq!(order.next_run_at, 1_000 + 24 * 60 * 60);
+
+        // Short again, and skipped rather than retried.
+        bank.scheduler
+            .standing_order_mut(order_id)
+            .unwrap()
+            .if_insufficient_funds = InsufficientFunds::Skip;
+        bank.run_standing_orders(1_000 + 24 * 60 * 60);
+        assert_eq!(balance(&bank, "b"), 50);
+        let order = bank.standing_order(order_id).unwrap();
+        assert_eq!(order.retry_at, None);
+        assert_eq!(order.next_run_at, 1_000 + 2 * 24 * 60 * 60);
+    }
+
+    #[test]
+    fn interest_is_posted_when_accrual_periods_end() {
+        let clock = Arc::new(clock::ManualClock::new(1_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        bank.open_account("a".to_string(), Balance::from_minor(100_000), Currency::EUR)
+            .unwrap();
+        bank.set_interest_rate(&name("a"), Some("0.0365".parse().unwrap()))
+            .unwrap();
+        clock.advance(interest::DEFAULT_PERIOD_SECS - 1);
+        bank.run_due_jobs();
+        assert_eq!(balance(&bank, "a"), 100_000);
+        clock.advance(1);
+        bank.run_due_jobs();
+        assert_eq!(balance(&bank, "a"), 100_010);
+        let posting = bank.ledger().entries().last().unwrap();
+        assert_eq!(posting.memo.as_deref(), Some(interest::MEMO));
+        assert_eq!(posting.timestamp, 1_000 + interest::DEFAULT_PERIOD_SECS);
+    }
+
+    #[test]
+    fn timed_work_follows_the_bank_clock() {
+        let clock = Arc::new(clock::ManualClock::new(1_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        for (name, balance) in [("a", 1_000), ("b", 0)] {
+            bank.open_account(
+                name.to_string(),
+                Balance::from_minor(balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        bank.set_hold_ttl(60);
+        let hold = bank
+            .place_hold(tx_info(name("a"), name("b"), 100), bank.now())
+            .unwrap();
+        bank.schedule_transfer(1_100, tx_info(name("a"), name("b"), 200))
+            .unwrap();
+
+        clock.advance(59);
+        bank.run_due_jobs();
+        assert!(bank.hold(hold.hold_id).is_some());
+        assert_eq!(balance(&bank, "b"), 0);
+
+        clock.advance(1);
+        bank.run_due_jobs();
+        assert!(bank.hold(hold.hold_id).is_none());
+        assert_eq!(balance(&bank, "b"), 0);
+
+        clock.set(1_100);
+        bank.run_due_jobs();
+        assert_eq!(balance(&bank, "a"), 800);
+        assert_eq!(balance(&bank, "b"), 200);
+        let entry = bank.ledger.entries().last().unwrap();
+        assert_eq!(entry.timestamp, 1_100);
+    }
+
+    #[test]
+    fn fee_is_charged_to_payer_and_linked_to_transfer() {
+        let mut bank = bank_with(&[("a", 10_000), ("b", 0), ("fees", 0)]);
+        bank.set_fees(Some(FeeConfig {
+            account: "fees".to_string(),
+            rules: vec![FeeRule {
+                min_amount: None,
+                max_amount: None,
+                account_tag: None,
+                flat: Money::from_minor(50),
+                rate: Some("0.01".parse().unwrap()),
+            }],
+        }));
+        let receipt = bank
+            .handle_transaction(tx_info(name("a"), name("b"), 1_000))
+            .unwrap();
+        assert_eq!(receipt.fee, Some(Money::from_minor(60)));
+        assert_eq!(balance(&bank, "a"), 8_940);
+        assert_eq!(balance(&bank, "b"), 1_000);
+        assert_eq!(balance(&bank, "fees"), 60);
+        let fee_entry = bank.ledger().entries().last().unwrap();
+        assert_eq!(fee_entry.fee_for, Some(receipt.transaction_id));
+        assert_ne!(fee_entry.transaction_id, receipt.transaction_id);
+
+        // The fee counts towards the funds needed.
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 8_900),
+            Err(CustomError::InsufficientFundsError(_))
+        ));
+    }
+
+    #[test]
+    fn transfer_limits_cap_single_transfers_and_daily_outflow() {
+        let clock = Arc::new(clock::ManualClock::new(1_000_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        for account in ["a", "b"] {
+            bank.open_account(
+                account.to_string(),
+                Balance::from_minor(10_000),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        bank.set_default_transfer_limits(TransferLimits {
+            max_transfer: Some(Money::from_minor(1_000)),
+            max_daily_outflow: Some(Money::from_minor(1_500)),
+        });
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 1_001),
+            Err(CustomError::LimitExceededError(_))
+        ));
+        transfer(&mut bank, "a", "b", 1_000).unwrap();
+        clock.advance(limits::WINDOW_SECS - 1);
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 501),
+            Err(CustomError::LimitExceededError(_))
+        ));
+        transfer(&mut bank, "a", "b", 500).unwrap();
+        clock.advance(1);
+        transfer(&mut bank, "a", "b", 1_000).unwrap();
+
+        // Accounts can have their own limits.
+        bank.set_transfer_limits(&name("b"), Some(TransferLimits::default()))
+            .unwrap();
+        transfer(&mut bank, "b", "a", 5_000).unwrap();
+    }
+
+    #[test]
+    fn fraud_rules_reject_or_flag_transfers() {
+        let clock = Arc::new(clock::ManualClock::new(1_000_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        for account in ["a", "b"] {
+            bank.open_account(
+                account.to_string(),
+                Balance::from_minor(10_000),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        bank.set_fraud_rules(vec![
+            FraudRule {
+                condition: Condition::Velocity {
+                    max_transfers: 2,
+                    window_secs: 60,
+                },
+                action: Action::Reject,
+            },
+            FraudRule {
+                condition: Condition::NewPayee {
+                    min_age_secs: 3_600,
+                },
+                action: Action::Flag,
+            },
+        ]);
+        let flagged = transfer(&mut bank, "a", "b", 100);
+        assert!(flagged.is_ok());
+        assert_eq!(bank.flagged_transactions().len(), 1);
+        clock.advance(3_600);
+        transfer(&mut bank, "a", "b", 100).unwrap();
+        transfer(&mut bank, "a", "b", 100).unwrap();
+        assert_eq!(bank.flagged_transactions().len(), 1);
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 100),
+            Err(CustomError::TransferRejectedError(_))
+        ));
+        clock.advance(60);
+        transfer(&mut bank, "a", "b", 100).unwrap();
+    }
+
+    #[test]
+    fn hooks_can_veto_transfers_and_see_commits() {
+        use std::sync::Mutex;
+
+        let mut bank = bank_with(&[("a", 1_000), ("b", 0)]);
+        bank.add_pre_transaction_hook(|request| {
+            if request.to == "a" {
+                return Err("no transfers to a".to_string());
+            }
+            Ok(())
+        });
+        let committed = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&committed);
+        bank.add_post_commit_hook(move |transfer| {
+            seen.lock().unwrap().push(transfer.transaction_id);
+        });
+        let receipt = bank
+            .handle_transaction(tx_info(name("a"), name("b"), 100))
+            .unwrap();
+        assert!(matches!(
+            transfer(&mut bank, "b", "a", 100),
+            Err(CustomError::TransferVetoedError(_))
+        ));
+        assert_eq!(balance(&bank, "b"), 100);
+        assert_eq!(*committed.lock().unwrap(), vec![receipt.transaction_id]);
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn policy_script_rejects_transfers() {
+        let mut bank = bank_with(&[("a", 100_000), ("b", 0)]);
+        bank.update_metadata(
+            &name("a"),
+            None,
+            None,
+            Some(BTreeSet::from(["frozen".to_string()])),
+        )
+        .unwrap();
+        bank.set_policy(
+            r#"reject "over 500 from frozen" when amount > 500 and from.tags contains "frozen""#
+                .parse()
+                .unwrap(),
+        );
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 50_001),
+            Err(CustomError::TransferRejectedError(_))
+        ));
+        transfer(&mut bank, "a", "b", 50_000).unwrap();
+    }
+
+    #[test]
+    fn plugins_veto_transfers_and_are_disabled_when_they_panic() {
+        struct Picky;
+
+        impl Plugin for Picky {
+            fn name(&self) -> &str {
+                "picky"
+            }
+
+            fn check_transfer(&self, request: &TransferRequest) -> Result<(), String> {
+                match request.amount.minor_units() {
+                    13 => Err("unlucky amount".to_string()),
+                    666 => panic!("plugin bug"),
+                    _ => Ok(()),
+                }
+            }
+
+            fn instructions(&self) -> Vec<String> {
+                vec!["t".to_string()]
+            }
+        }
+
+        let mut bank = bank_with(&[("a", 1_000), ("b", 0)]);
+        assert!(matches!(
+            bank.register_plugin(Picky),
+            Err(PluginError::BuiltinInstruction { .. })
+        ));
+
+        struct Quiet(Picky);
+
+        impl Plugin for Quiet {
+            fn name(&self) -> &str {
+                self.0.name()
+            }
+
+            fn check_transfer(&self, request: &TransferRequest) -> Result<(), String> {
+                self.0.check_transfer(request)
+            }
+        }
+
+        bank.register_plugin(Quiet(Picky)).unwrap();
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 13),
+            Err(CustomError::TransferVetoedError(_))
+        ));
+        transfer(&mut bank, "a", "b", 666).unwrap();
+        transfer(&mut bank, "a", "b", 13).unwrap();
+        assert_eq!(balance(&bank, "b"), 679);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn process_plugins_talk_over_their_standard_input_and_output() {
+        // Vetoes 0.13, hangs on 6.66, and replies to "Q" with the balance
+        // of the account named in the payload.
+        let script = r#"
+            while read -r call; do
+                case "$call" in
+                    '"instructions"') echo '{"instructions":["Q"]}' ;;
+                    *'"amount":"0.13"'*) echo '{"veto":"unlucky amount"}' ;;
+                    *'"amount":"6.66"'*) sleep 5 ;;
+                    '{"handle":'*)
+                        echo '{"account":"a"}'
+                        read -r account
+                        balance=${account#*'"balance":"'}
+                        echo "{\"reply\":\"${balance%%\"*}\"}" ;;
+                    *) echo '"ok"' ;;
+                esac
+            done
+        "#;
+        let plugin = plugins::ProcessPlugin::spawn(
+            "picky",
+            "/bin/sh",
+            &["-c".to_string(), script.to_string()],
+            Duration::from_millis(500),
+        )
+        .unwrap();
+        let mut bank = bank_with(&[("a", 1_000), ("b", 0)]);
+        bank.register_plugin(plugin).unwrap();
+
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 13),
+            Err(CustomError::TransferVetoedError(_))
+        ));
+        transfer(&mut bank, "a", "b", 100).unwrap();
+        let plugin = bank.plugin_for("Q").unwrap();
+        assert_eq!(plugin.handle("Q", b"", &bank).unwrap(), b"9.00");
+
+        // Killed and disabled for taking too long.
+        transfer(&mut bank, "a", "b", 666).unwrap();
+        transfer(&mut bank, "a", "b", 13).unwrap();
+        assert_eq!(balance(&bank, "b"), 779);
+        assert!(plugin.handle("Q", b"", &bank).is_err());
+    }
+
+    #[test]
+    fn replaying_events_rebuilds_the_bank() {
+        let clock = Arc::new(clock::ManualClock::new(1_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        for (name, balance) in [("a", 1_000), ("b", 0)] {
+            bank.open_account(
+                name.to_string(),
+                Balance::from_minor(balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        transfer(&mut bank, "a", "b", 300).unwrap();
+        clock.advance(10);
+        let reversed = bank
+            .handle_transaction(tx_info(name("b"), name("a"), 100))
+            .unwrap();
+        bank.reverse(reversed.transaction_id).unwrap();
+        let hold = bank
+            .place_hold(tx_info(name("a"), name("b"), 200), bank.now())
+            .unwrap();
+        let open_hold = bank
+            .place_hold(tx_info(name("b"), name("a"), 50), bank.now())
+            .unwrap();
+        bank.capture_hold(hold.hold_id, Some(Money::from_minor(150)), bank.now())
+            .unwrap();
+        bank.set_status(&name("b"), AccountStatus::Frozen).unwrap();
+
+        let mut log = Vec::new();
+        bank.write_events(&mut log).unwrap();
+        let replayed = Bank::replay(clock, log.as_slice()).unwrap();
+        let accounts = |bank: &Bank| -> serde_json::Value {
+            serde_json::from_str(
+                &bank
+                    .list_accounts(ListingFormat::Json, None, &AccountFilter::default())
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(accounts(&replayed), accounts(&bank));
+        assert_eq!(
+            serde_json::to_value(replayed.ledger().entries()).unwrap(),
+            serde_json::to_value(bank.ledger().entries()).unwrap()
+        );
+        assert_eq!(balance(&replayed, "a"), 550);
+        assert!(replayed.hold(open_hold.hold_id).is_some());
+    }
+
+    #[test]
+    fn bank_is_restored_from_snapshot_and_later_events() {
+        let data_dir = std::env::temp_dir().join(format!("bank-store-{}", std::process::id()));
+        let clock = Arc::new(clock::ManualClock::new(1_000));
+        let mut bank = Bank::open(clock.clone(), &data_dir, 4).unwrap();
+        for (name, balance) in [("a", 1_000), ("b", 0)] {
+            bank.open_account(
+                name.to_string(),
+                Balance::from_minor(balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        transfer(&mut bank, "a", "b", 300).unwrap();
+        bank.snapshot_if_due();
+        let mut log = Vec::new();
+        bank.write_events(&mut log).unwrap();
+        assert!(log.is_empty());
+        transfer(&mut bank, "b", "a", 100).unwrap();
+        bank.set_status(&name("b"), AccountStatus::Frozen).unwrap();
+        drop(bank);
+
+        let mut reopened = Bank::open(clock, &data_dir, 4).unwrap();
+        std::fs::remove_dir_all(&data_dir).unwrap();
+        assert_eq!(reopened.events.last_sequence(), 6);
+        assert_eq!(balance(&reopened, "a"), 800);
+        assert_eq!(balance(&reopened, "b"), 200);
+        assert_eq!(reopened.ledger().entries().len(), 3);
+        assert!(matches!(
+            transfer(&mut reopened, "b", "a", 1),
+            Err(CustomError::AccountFrozenError(_))
+        ));
+    }
+
+    #[test]
+    fn audit_log_detects_edits_and_truncation() {
+        let data_dir = std::env::temp_dir().join(format!("bank-audit-{}", std::process::id()));
+        let clock = Arc::new(clock::ManualClock::new(1_000));
+        let mut bank = Bank::open(clock, &data_dir, 3).unwrap();
+        for (name, balance) in [("a", 1_000), ("b", 0)] {
+            bank.open_account(
+                name.to_string(),
+                Balance::from_minor(balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        transfer(&mut bank, "a", "b", 300).unwrap();
+        bank.snapshot_if_due();
+        transfer(&mut bank, "b", "a", 100).unwrap();
+        drop(bank);
+        assert_eq!(verify_audit_log(&data_dir).unwrap().events, 5);
+
+        let path = data_dir.join(audit::FILE);
+        let original = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, original.replacen("\"3.00\"", "\"30.00\"", 1)).unwrap();
+        let edited = verify_audit_log(&data_dir);
+        let lines: Vec<&str> = original.lines().collect();
+        std::fs::write(&path, lines[..4].join("\n")).unwrap();
+        let truncated = verify_audit_log(&data_dir);
+        std::fs::remove_dir_all(&data_dir).unwrap();
+        assert!(matches!(edited, Err(AuditError::Broken { sequence: 4 })));
+        assert!(matches!(
+            truncated,
+            Err(AuditError::Truncated {
+                audited: 4,
+                expected: 5
+            })
+        ));
+    }
+
+    #[test]
+    fn tenants_get_banks_of_their_own() {
+        let data_dir = std::env::temp_dir().join(format!("bank-tenant-{}", std::process::id()));
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "tenants": {
+                "acme": {},
+                "globex": { "data_dir": data_dir },
+            }
+        }))
+        .unwrap();
+        let mut banks = open_tenant_banks(&config).unwrap();
+        std::fs::remove_dir_all(&data_dir).unwrap();
+        let acme = banks.get_mut("acme").unwrap();
+        transfer(acme, "patko", "siska", 100).unwrap();
+        assert_eq!(balance(acme, "patko"), 99_900);
+        assert_eq!(balance(&banks["globex"], "patko"), 100_000);
+
+        config.data_dir = Some(data_dir);
+        assert!(open_tenant_banks(&config).is_err());
+    }
+
+    #[test]
+    fn interbank_transfers_go_through_settlement_accounts() {
+        let mut bank = bank_with(&[("a", 1_000), ("b", 0)]);
+        let peer = PeerBankConfig {
+            socket_path: PathBuf::from("/nonexistent/bank.sock"),
+            settlement_account: "due_other".to_string(),
+            currency: Currency::EUR,
+            credit_limit: Some(Money::from_minor(500)),
+            token: None,
+        };
+        let reply_path =
+            std::env::temp_dir().join(format!("bank-interbank-{}.sock", std::process::id()));
+        let peers = Peers::new(BTreeMap::from([("other".to_string(), peer)]), &reply_path);
+        bank.open_settlement_account(peers.get("other").unwrap())
+            .unwrap();
+
+        let incoming = InterbankTransfer {
+            bank: "other".to_string(),
+            from: "alice".to_string(),
+            to: "b".to_string(),
+            amount: Money::from_minor(300),
+            currency: Currency::EUR,
+            reference: TransactionId(7),
+            memo: None,
+        };
+        let receipt = bank.receive_from_peer(&peers, incoming.clone()).unwrap();
+        assert_eq!(balance(&bank, "b"), 300);
+        assert_eq!(balance(&bank, "due_other"), -300);
+        let entry = bank.ledger.transaction(receipt.transaction_id).unwrap();
+        assert_eq!(entry[0].external_ref.as_deref(), Some("other:tx7"));
+        // Sent again, it is not booked again.
+        let again = bank.receive_from_peer(&peers, incoming.clone()).unwrap();
+        assert_eq!(again.transaction_id, receipt.transaction_id);
+        assert_eq!(balance(&bank, "b"), 300);
+        let over_limit = InterbankTransfer {
+            reference: TransactionId(8),
+            ..incoming
+        };
+        assert!(bank.receive_from_peer(&peers, over_limit).is_err());
+
+        let outgoing = tx_info(name("a"), name("other:alice"), 100);
+        let route = peers.route(&outgoing.to).unwrap();
+        let result = bank.send_to_peer(&peers, "bank", route, outgoing.clone());
+        assert!(matches!(result, Err(CustomError::InterbankError(_))));
+        assert_eq!(balance(&bank, "a"), 1_000);
+        assert_eq!(balance(&bank, "due_other"), -300);
+
+        // The fee is reversed with the transfer, and with a zero fee there
+        // is nothing more to reverse.
+        bank.open_account("fees".to_string(), Balance::ZERO, Currency::EUR)
+            .unwrap();
+        for flat in [25, 0] {
+            bank.set_fees(Some(FeeConfig {
+                account: "fees".to_string(),
+                rules: vec![FeeRule {
+                    min_amount: None,
+                    max_amount: None,
+                    account_tag: None,
+                    flat: Money::from_minor(flat),
+                    rate: None,
+                }],
+            }));
+            transfer(&mut bank, "b", "a", 10).unwrap();
+            let entries = bank.ledger.entries().len();
+            let result = bank.send_to_peer(&peers, "bank", route, outgoing.clone());
+            assert!(matches!(result, Err(CustomError::InterbankError(_))));
+            assert_eq!(balance(&bank, "fees"), 25);
+            assert_eq!(balance(&bank, "due_other"), -300);
+            let reversals = bank.ledger.entries()[entries..]
+                .iter()
+                .filter(|entry| entry.reverses.is_some())
+                .count();
+            assert_eq!(reversals, if flat == 0 { 1 } else { 2 });
+        }
+        assert_eq!(balance(&bank, "a"), 1_020);
+        assert_eq!(balance(&bank, "b"), 255);
+    }
+
+    #[test]
+    fn unanswered_interbank_transfers_are_kept_until_the_peer_answers() {
+        use std::os::unix::net::UnixDatagram;
+
+        let dir = std::env::temp_dir().join(format!("bank-unconfirmed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let peer_socket = UnixDatagram::bind(dir.join("peer.sock")).unwrap();
+        // Acknowledges every instruction, and answers the transfers as
+        // listed, `None` not at all.
+        let outcomes = [None, Some(r#"{"transaction_id":1}"#), None, Some("422")];
+        let peer = std::thread::spawn(move || {
+            let mut buffer = [0; 512];
+            for outcome in outcomes {
+                let (_, sender) = peer_socket.recv_from(&mut buffer).unwrap();
+                let sender = sender.as_pathname().unwrap();
+                peer_socket.send_to(b"200", sender).unwrap();
+                peer_socket.recv_from(&mut buffer).unwrap();
+                if let Some(outcome) = outcome {
+                    peer_socket.send_to(outcome.as_bytes(), sender).unwrap();
+                }
+            }
+        });
+        let config = PeerBankConfig {
+            socket_path: dir.join("peer.sock"),
+            settlement_account: "due_other".to_string(),
+            currency: Currency::EUR,
+            credit_limit: None,
+            token: None,
+        };
+        let peers = Peers::new(
+            BTreeMap::from([("other".to_string(), config)]),
+            &dir.join("bank.sock.interbank"),
+        )
+        .with_timeout(Duration::from_millis(200));
+        let mut bank = bank_with(&[("a", 1_000)]);
+        bank.open_settlement_account(peers.get("other").unwrap())
+            .unwrap();
+        let outgoing = tx_info(name("a"), name("other:alice"), 100);
+        let route = peers.route(&outgoing.to).unwrap();
+
+        // The peer may have booked it, so it is not reversed.
+        let result = bank.send_to_peer(&peers, "bank", route, outgoing.clone());
+        assert!(matches!(
+            result,
+            Err(CustomError::InterbankError(
+                InterbankError::Unconfirmed { .. }
+            ))
+        ));
+        assert_eq!(balance(&bank, "due_other"), 100);
+        let mut log = Vec::new();
+        bank.write_events(&mut log).unwrap();
+        let replayed = Bank::replay(Arc::new(clock::SystemClock), log.as_slice()).unwrap();
+        assert_eq!(replayed.unconfirmed.len(), 1);
+        bank.confirm_peer_transfers(&peers);
+        assert!(bank.unconfirmed.is_empty());
+        assert_eq!(balance(&bank, "due_other"), 100);
+
+        // Rejected when sent again, it is reversed.
+        assert!(bank
+            .send_to_peer(&peers, "bank", route, outgoing.clone())
+            .is_err());
+        assert_eq!(bank.unconfirmed.len(), 1);
+        bank.confirm_peer_transfers(&peers);
+        assert!(bank.unconfirmed.is_empty());
+        assert_eq!(balance(&bank, "due_other"), 100);
+        assert_eq!(balance(&bank, "a"), 900);
+        peer.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn settlement_nets_obligations_into_one_transfer() {
+        let mut bank = bank_with(&[("a", 1_000), ("b", 0)]);
+        bank.open_settlement_account(&PeerBankConfig {
+            socket_path: PathBuf::from("/nonexistent/bank.sock"),
+            settlement_account: "due_other".to_string(),
+            currency: Currency::EUR,
+            credit_limit: None,
+            token: None,
+        })
+        .unwrap();
+        transfer(&mut bank, "due_other", "b", 300).unwrap();
+        transfer(&mut bank, "a", "due_other", 100).unwrap();
+
+        let settled = bank.settle();
+        let [batch] = &settled[..] else {
+            panic!("expected one batch, got {settled:?}");
+        };
+        assert_eq!(batch.obligations.len(), 2);
+        assert_eq!(batch.owed_to_peer.to_string(), "1.00");
+        assert_eq!(batch.owed_by_peer.to_string(), "3.00");
+        assert_eq!(batch.net.to_string(), "-2.00");
+        assert_eq!(balance(&bank, "due_other"), 0);
+        assert!(bank.settle().is_empty());
+
+        transfer(&mut bank, "a", "due_other", 50).unwrap();
+        let batches = bank.settlement_batches(&name("due_other")).unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].settled_by, batch.settled_by);
+        assert_eq!(batches[1].settled_by, None);
+        assert!(invariants::check(&bank).consistent);
+    }
+
+    #[test]
+    fn escrow_is_released_refunded_or_settled_on_timeout() {
+        let clock = Arc::new(clock::ManualClock::new(1_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        for (name, balance) in [("a", 1_000), ("b", 0)] {
+            bank.open_account(
+                name.to_string(),
+                Balance::from_minor(balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        let open = |bank: &mut Bank, amount, on_timeout| {
+            bank.open_escrow(
+                tx_info(name("a"), name("b"), amount),
+                Some("notary".to_string()),
+                Some(60),
+                on_timeout,
+                bank.now(),
+            )
+            .unwrap()
+        };
+        let released = open(&mut bank, 300, EscrowOutcome::Refund);
+        let refunded = open(&mut bank, 200, EscrowOutcome::Refund);
+        let expiring = open(&mut bank, 100, EscrowOutcome::Release);
+        assert_eq!(balance(&bank, "a"), 400);
+        assert_eq!(balance(&bank, "b"), 0);
+
+        let receipt = bank
+            .close_escrow(released.id, EscrowOutcome::Release)
+            .unwrap();
+        assert_eq!(receipt.to, bank.account_ids["b"]);
+        bank.close_escrow(refunded.id, EscrowOutcome::Refund)
+            .unwrap();
+        assert!(matches!(
+            bank.close_escrow(refunded.id, EscrowOutcome::Release),
+            Err(CustomError::EscrowNotFoundError(_))
+        ));
+        assert_eq!(balance(&bank, "a"), 600);
+        assert_eq!(balance(&bank, "b"), 300);
+
+        clock.advance(60);
+        bank.run_due_jobs();
+        assert!(bank.escrow(expiring.id).is_none());
+        assert_eq!(balance(&bank, "b"), 400);
+        assert!(invariants::check(&bank).consistent);
+    }
+
+    #[test]
+    fn joint_accounts_wait_for_enough_owners() {
+        let mut bank = bank_with(&[("joint", 1_000), ("shop", 0)]);
+        let joint = name("joint");
+        bank.add_owner(&joint, "jozko".to_string()).unwrap();
+        bank.add_owner(&joint, "marienka".to_string()).unwrap();
+        assert_eq!(bank.accounts_owned_by("jozko"), ["joint"]);
+        assert!(matches!(
+            bank.set_approvals_required(&joint, 3),
+            Err(CustomError::OwnershipError(
+                OwnershipError::InvalidApprovals { .. }
+            ))
+        ));
+        bank.set_approvals_required(&joint, 2).unwrap();
+        assert!(bank.needs_approvals(&joint));
+        assert!(bank.remove_owner(&joint, "marienka").is_err());
+
+        let now = bank.now();
+        assert!(bank
+            .request_approval(tx_info(name("joint"), name("shop"), 300), Some("juro"), now)
+            .is_err());
+        let request = bank
+            .request_approval(
+                tx_info(name("joint"), name("shop"), 300),
+                Some("jozko"),
+                now,
+            )
+            .unwrap();
+        assert!(matches!(
+            bank.approve_transfer(request.approval_id, Some("jozko")),
+            Err(CustomError::OwnershipError(
+                OwnershipError::AlreadyApproved { .. }
+            ))
+        ));
+        assert_eq!(balance(&bank, "joint"), 1_000);
+        let outcome = bank
+            .approve_transfer(request.approval_id, Some("marienka"))
+            .unwrap();
+        assert!(matches!(outcome, ApprovalOutcome::Made(_)));
+        assert!(bank.approvals.get(request.approval_id).is_none());
+        assert_eq!(balance(&bank, "shop"), 300);
+
+        let rejected = bank
+            .request_approval(
+                tx_info(name("joint"), name("shop"), 100),
+                Some("marienka"),
+                now,
+            )
+            .unwrap();
+        bank.reject_transfer(rejected.approval_id, Some("jozko"))
+            .unwrap();
+        assert!(bank.pending_approvals(&joint).unwrap().is_empty());
+        assert_eq!(balance(&bank, "joint"), 700);
+    }
+
+    #[test]
+    fn envelopes_add_up_to_their_parent_and_move_money_for_free() {
+        let mut bank = bank_with(&[("patko", 1_000), ("fees", 0)]);
+        bank.set_fees(Some(FeeConfig {
+            account: "fees".to_string(),
+            rules: vec![FeeRule {
+                min_amount: None,
+                max_amount: None,
+                account_tag: None,
+                flat: Money::from_minor(50),
+                rate: None,
+            }],
+        }));
+        let rent = bank
+            .open_sub_account(&name("patko"), "patko/rent".to_string())
+            .unwrap();
+        bank.open_sub_account(&name("patko"), "patko/food".to_string())
+            .unwrap();
+        assert!(matches!(
+            bank.open_sub_account(&AccountRef::Id(rent), "patko/rent/gas".to_string()),
+            Err(CustomError::NestedSubAccountError(_))
+        ));
+        transfer(&mut bank, "patko", "patko/rent", 600).unwrap();
+        transfer(&mut bank, "patko/rent", "patko/food", 100).unwrap();
+        assert_eq!(balance(&bank, "patko"), 400);
+        assert_eq!(balance(&bank, "patko/food"), 100);
+        assert_eq!(balance(&bank, "fees"), 0);
+
+        let info: serde_json::Value = serde_json::from_str(
+            &bank
+                .list_accounts(ListingFormat::Json, None, &AccountFilter::default())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(info["patko"]["total"], "10.00");
+        assert_eq!(
+            info["patko"]["sub_accounts"],
+            serde_json::json!(["patko/rent", "patko/food"])
+        );
+        assert_eq!(info["patko/rent"]["parent"], bank.account_ids["patko"].0);
+        assert_eq!(
+            bank.sub_account_names(&["patko".to_string()]),
+            ["patko/rent", "patko/food"]
+        );
+
+        transfer(&mut bank, "patko/food", "fees", 50).unwrap();
+        assert_eq!(balance(&bank, "fees"), 100);
+    }
+
+    #[test]
+    fn loan_interest_is_charged_on_what_is_outstanding() {
+        let clock = Arc::new(clock::ManualClock::new(1_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        bank.open_account(
+            "patko".to_string(),
+            Balance::from_minor(1_000),
+            Currency::EUR,
+        )
+        .unwrap();
+        let month = interest::YEAR_SECS / 12;
+        let loan = bank
+            .issue_loan(
+                &name("patko"),
+                Money::from_minor(100_000),
+                "0.12".parse().unwrap(),
+                12,
+                Some(month),
+            )
+            .unwrap();
+        assert_eq!(loan.outstanding, Money::from_minor(100_000));
+        assert_eq!(loan.schedule.len(), 12);
+        assert_eq!(balance(&bank, "patko"), 101_000);
+        assert_eq!(balance(&bank, "loan1"), -100_000);
+        assert!(matches!(
+            bank.issue_loan(
+                &name("patko"),
+                Money::ZERO,
+                "0.12".parse().unwrap(),
+                12,
+                None
+            ),
+            Err(CustomError::LoanError(LoanError::InvalidTerms))
+        ));
+
+        transfer(&mut bank, "patko", "loan1", 50_000).unwrap();
+        clock.advance(month);
+        bank.run_due_jobs();
+        // 1 % of the 500.00 left.
+        let loan = bank.loan(loan.loan.id).unwrap();
+        assert_eq!(loan.outstanding, Money::from_minor(50_500));
+        assert_eq!(loan.loan.installments_charged, 1);
+
+        transfer(&mut bank, "patko", "loan1", 50_500).unwrap();
+        clock.advance(month);
+        bank.run_due_jobs();
+        let loan = bank.loan(loan.loan.id).unwrap();
+        assert!(loan.outstanding.is_zero());
+        assert_eq!(loan.loan.installments_charged, 2);
+        assert!(invariants::check(&bank).consistent);
+    }
+
+    #[test]
+    fn loan_accounts_are_not_named_like_other_accounts() {
+        // "Ioan2" with a capital "I" looks like "loan2".
+        let mut bank = bank_with(&[("patko", 0), ("loan1", 0), ("Ioan2", 0)]);
+        for expected in ["loan1-2", "loan2-2", "loan3"] {
+            let loan = bank
+                .issue_loan(
+                    &name("patko"),
+                    Money::from_minor(100),
+                    "0.12".parse().unwrap(),
+                    1,
+                    None,
+                )
+                .unwrap();
+            assert_eq!(bank.accounts[&loan.loan.account].name, expected.into());
+        }
+        assert_eq!(balance(&bank, "loan1"), 0);
+    }
+
+    #[test]
+    fn surplus_is_swept_into_goals_until_the_target() {
+        let clock = Arc::new(clock::ManualClock::new(1_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        bank.open_account(
+            "patko".to_string(),
+            Balance::from_minor(50_000),
+            Currency::EUR,
+        )
+        .unwrap();
+        let goal = bank
+            .create_goal(
+                &name("patko"),
+                "bicycle",
+                Money::from_minor(30_000),
+                Money::from_minor(30_000),
+                None,
+            )
+            .unwrap();
+        assert_eq!(bank.account_ids["patko/bicycle"], goal.sub_account);
+
+        clock.advance(goals::DEFAULT_PERIOD_SECS);
+        bank.run_due_jobs();
+        assert_eq!(balance(&bank, "patko"), 30_000);
+        let progress = bank.goal_progress(bank.goal(goal.id).unwrap());
+        assert_eq!(progress.saved, Money::from_minor(20_000));
+        assert_eq!(progress.percent, 66);
+
+        bank.mint(&name("patko"), Money::from_minor(50_000))
+            .unwrap();
+        clock.advance(goals::DEFAULT_PERIOD_SECS);
+        bank.run_due_jobs();
+        // Only what the target still needs.
+        assert_eq!(balance(&bank, "patko"), 70_000);
+        let progress = &bank.goals_of(&name("patko")).unwrap()[0];
+        assert!(progress.reached);
+
+        bank.delete_goal(goal.id).unwrap();
+        assert!(bank.goal(goal.id).is_err());
+        assert_eq!(balance(&bank, "patko/bicycle"), 30_000);
+    }
+
+    /// Transfers out of `from` of `amount` minor units by every path but
+    /// the `t` instruction, with those accepted before `restrict` ran made
+    /// later. Returns the errors of all of them.
+    fn transfer_every_way(
+        bank: &mut Bank,
+        clock: &clock::ManualClock,
+        amount: u64,
+        restrict: impl FnOnce(&mut Bank),
+    ) -> Vec<CustomError> {
+        let transfer = || tx_info(name("from"), name("to"), amount);
+        let now = bank.now();
+        let hold = bank.place_hold(transfer(), now).unwrap();
+        bank.schedule_transfer(now + 10, transfer()).unwrap();
+        bank.create_standing_order(Every::Day, now + 10, InsufficientFunds::Skip, transfer())
+            .unwrap();
+        restrict(bank);
+
+        let half = || tx_info(name("from"), name("to"), amount / 2 + 1);
+        let errors = vec![
+            bank.handle_batch(vec![half(), half()]).unwrap_err(),
+            bank.handle_independent(vec![transfer()], 1)
+                .remove(0)
+                .unwrap_err(),
+            bank.place_hold(transfer(), now).unwrap_err(),
+            bank.capture_hold(hold.hold_id, None, now).unwrap_err(),
+            bank.open_escrow(transfer(), None, Some(60), EscrowOutcome::Refund, now)
+                .unwrap_err(),
+            bank.schedule_transfer(now + 10, transfer()).unwrap_err(),
+            bank.create_standing_order(Every::Day, now, InsufficientFunds::Skip, transfer())
+                .unwrap_err(),
+        ];
+        clock.advance(10);
+        bank.run_scheduled_transfers(now + 10);
+        bank.run_standing_orders(now + 10);
+        assert_eq!(balance(bank, "to"), 0);
+        let dry_run = TxInfo {
+            dry_run: true,
+            ..transfer()
+        };
+        assert!(bank.handle_transaction(dry_run).is_ok());
+        errors
+    }
+
+    #[test]
+    fn joint_accounts_need_approvals_however_money_leaves() {
+        let clock = Arc::new(clock::ManualClock::new(1_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        for (name, balance) in [("from", 10_000), ("to", 0)] {
+            bank.open_account(
+                name.to_string(),
+                Balance::from_minor(balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        let errors = transfer_every_way(&mut bank, &clock, 100, |bank| {
+            bank.add_owner(&name("from"), "jozko".to_string()).unwrap();
+            bank.add_owner(&name("from"), "marienka".to_string())
+                .unwrap();
+            bank.set_approvals_required(&name("from"), 2).unwrap();
+        });
+        assert_eq!(errors.len(), 7);
+        for e in errors {
+            assert!(matches!(e, CustomError::ApprovalsRequiredError(_)), "{e}");
+        }
+    }
+
+    #[test]
+    fn transfers_above_the_threshold_wait_for_review_however_sent() {
+        let clock = Arc::new(clock::ManualClock::new(1_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        for (name, balance) in [("from", 10_000), ("to", 0)] {
+            bank.open_account(
+                name.to_string(),
+                Balance::from_minor(balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        let errors = transfer_every_way(&mut bank, &clock, 2_000, |bank| {
+            bank.set_review_threshold(Some(Money::from_minor(1_500)));
+        });
+        assert_eq!(errors.len(), 7);
+        for e in errors {
+            assert!(matches!(e, CustomError::ReviewRequiredError(_)), "{e}");
+        }
+    }
+
+    #[test]
+    fn transfers_above_the_threshold_wait_for_review() {
+        let mut bank = bank_with(&[("a", 10_000), ("b", 0)]);
+        bank.set_review_threshold(Some(Money::from_minor(1_000)));
+        let small = tx_info(name("a"), name("b"), 1_000);
+        assert!(!bank.needs_review(&small));
+        let large = tx_info(name("a"), name("b"), 5_000);
+        assert!(bank.needs_review(&large));
+
+        let approved = bank
+            .park_transfer(large.clone(), Some("jozko"), Checks::Pin)
+            .unwrap();
+        let rejected = bank.park_transfer(large, None, Checks::Pin).unwrap();
+        assert_eq!(bank.parked_transfers().len(), 2);
+        assert_eq!(balance(&bank, "b"), 0);
+
+        let receipt = bank.approve_parked(approved.review_id).unwrap();
+        assert_eq!(receipt.amount, Money::from_minor(5_000));
+        bank.reject_parked(rejected.review_id).unwrap();
+        assert!(matches!(
+            bank.approve_parked(rejected.review_id),
+            Err(CustomError::ReviewNotFoundError(_))
+        ));
+        assert!(bank.parked_transfers().is_empty());
+        assert_eq!(balance(&bank, "a"), 5_000);
+        assert_eq!(balance(&bank, "b"), 5_000);
+    }
+
+    #[test]
+    fn independent_transfers_are_checked_shard_by_shard() {
+        // With two shards, "a" and "c" share one, "b" and "d" the other.
+        let mut bank = bank_with(&[("a", 100), ("b", 0), ("c", 100), ("d", 0)]);
+        let dry_run = TxInfo {
+            dry_run: true,
+            ..tx_info(name("c"), name("d"), 1)
+        };
+        let transfers = vec![
+            tx_info(name("a"), name("c"), 60),
+            tx_info(name("b"), name("d"), 10),
+            tx_info(name("a"), name("c"), 50),
+            tx_info(name("c"), name("a"), 30),
+            // Between shards, so after the others.
+            tx_info(name("a"), name("b"), 70),
+            tx_info(name("b"), name("d"), 70),
+            tx_info(name("a"), name("nobody"), 1),
+            dry_run,
+        ];
+
+        let outcomes = bank.handle_independent(transfers, 2);
+
+        let made: Vec<bool> = outcomes.iter().map(Result::is_ok).collect();
+        assert_eq!(made, [true, false, false, true, true, false, false, true]);
+        assert!(matches!(
+            outcomes[2],
+            Err(CustomError::InsufficientFundsError(_))
+        ));
+        assert!(outcomes[7].as_ref().unwrap().dry_run);
+        assert_eq!(outcomes[4].as_ref().unwrap().from_balance.minor_units(), 0);
+        assert_eq!(balance(&bank, "a"), 0);
+        assert_eq!(balance(&bank, "b"), 70);
+        assert_eq!(balance(&bank, "c"), 130);
+        assert_eq!(balance(&bank, "d"), 0);
+        assert!(invariants::check(&bank).consistent);
+    }
+
+    #[test]
+    fn dry_runs_check_transfers_without_making_them() {
+        let mut bank = bank_with(&[("a", 1_000), ("b", 0)]);
+        bank.set_default_transfer_limits(TransferLimits {
+            max_transfer: Some(Money::from_minor(600)),
+            max_daily_outflow: None,
+        });
+        let entries = bank.ledger.entries().len();
+        let dry_run = |amount| TxInfo {
+            dry_run: true,
+            ..tx_info(name("a"), name("b"), amount)
+        };
+
+        let receipt = bank.handle_transaction(dry_run(400)).unwrap();
+        assert!(receipt.dry_run);
+        assert_eq!(receipt.from_balance.minor_units(), 600);
+        assert_eq!(receipt.to_balance.minor_units(), 400);
+        assert!(matches!(
+            bank.handle_transaction(dry_run(700)),
+            Err(CustomError::LimitExceededError(_))
+        ));
+        bank.set_default_transfer_limits(TransferLimits::default());
+        assert!(matches!(
+            bank.handle_transaction(dry_run(1_001)),
+            Err(CustomError::InsufficientFundsError(_))
+        ));
+        assert_eq!(bank.ledger.entries().len(), entries);
+        assert_eq!(balance(&bank, "a"), 1_000);
+        assert_eq!(balance(&bank, "b"), 0);
+
+        // The receipt is the one the transfer gets.
+        let made = bank
+            .handle_transaction(tx_info(name("a"), name("b"), 400))
+            .unwrap();
+        assert!(!made.dry_run);
+        assert_eq!(made.transaction_id, receipt.transaction_id);
+        assert_eq!(made.from_balance, receipt.from_balance);
+    }
+
+    #[test]
+    fn requests_are_served_over_any_transport() {
+        let transport = Arc::new(transport::MockTransport::default());
+        transport.send("/client", "k");
+        transport.send("/client", "t");
+        transport.send("/client", r#"{"from":"patko","to":"siska","amount":"5"}"#);
+        transport.send("/client", "t");
+        transport.send("/client", "{not json");
+        transport.send("/client", "t");
+        transport.send("/client", r#"{"from":"patko","to":"siska","amount":"500"}"#);
+        transport.send("/client", b"\xff".to_vec());
+        transport.send("/other", "q");
+        let bank = bank_with(&[("patko", 10_000), ("siska", 0)]);
+
+        let served = serve(bank, Config::default(), transport.clone(), None);
+
+        assert_eq!(served.unwrap(), ShutdownReason::Quit);
+        let replies = transport.replies("/client");
+        assert_eq!(replies.len(), 8, "{replies:?}");
+        assert!(replies[0].contains("\"status\""), "{}", replies[0]);
+        assert_eq!(replies[1], "200");
+        let receipt: serde_json::Value = serde_json::from_str(&replies[2]).unwrap();
+        assert_eq!(receipt["from_balance"], "95.00");
+        assert_eq!(replies[3..], ["200", "400", "200", "422", "400"]);
+        assert!(transport.replies("/other").is_empty());
+    }
+
+    #[test]
+    fn requests_in_versions_the_server_does_not_speak_are_refused() {
+        let transport = Arc::new(transport::MockTransport::default());
+        transport.send("/client", "n");
+        transport.send("/client", r#"n{"version":1}"#);
+        transport.send(
+            "/client",
+            format!(r#"t{{"version":{}}}"#, protocol::VERSION + 1),
+        );
+        transport.send("/client", r#"n{"version":0}"#);
+        transport.send("/client", "q");
+
+        let served = serve(bank_with(&[]), Config::default(), transport.clone(), None);
+
+        assert_eq!(served.unwrap(), ShutdownReason::Quit);
+        let replies = transport.replies("/client");
+        let version: serde_json::Value = serde_json::from_str(&replies[0]).unwrap();
+        assert_eq!(version["protocol"], protocol::VERSION);
+        assert_eq!(version["min_protocol"], protocol::MIN_VERSION);
+        assert_eq!(replies[1], replies[0]);
+        assert_eq!(replies[2..], ["505", "505"]);
+    }
+
+    #[test]
+    fn clients_of_version_2_learn_why_a_request_was_refused() {
+        let transport = Arc::new(transport::MockTransport::default());
+        let transfer = r#"{"from":"patko","to":"siska","amount":"500"}"#;
+        transport.send("/old", "t");
+        transport.send("/old", transfer);
+        transport.send("/new", r#"t{"version":2}"#);
+        transport.send("/new", transfer);
+        transport.send("/new", r#"a{"version":2}"#);
+        transport.send("/new", r#"{"op":"mint","account":"nobody","amount":"1"}"#);
+        transport.send("/new", "q");
+
+        let bank = bank_with(&[("patko", 100), ("siska", 0)]);
+        let served = serve(bank, Config::default(), transport.clone(), None);
+
+        assert_eq!(served.unwrap(), ShutdownReason::Quit);
+        assert_eq!(transport.replies("/old"), ["200", "422"]);
+        let replies = transport.replies("/new");
+        let refused: ErrorReply = serde_json::from_str(&replies[1]).unwrap();
+        assert_eq!(refused.status, "422");
+        assert_eq!(refused.code, "INSUFFICIENT_FUNDS");
+        assert!(refused.message.contains("patko"), "{}", refused.message);
+        let refused: ErrorReply = serde_json::from_str(&replies[3]).unwrap();
+        assert_eq!(refused.code, "ACCOUNT_NOT_FOUND");
+    }
+
+    #[test]
+    fn draining_refuses_transactions_and_stops_once_quiet() {
+        let transport = Arc::new(transport::MockTransport::default());
+        transport.send("/client", "t");
+        transport.send("/client", r#"{"from":"patko","to":"siska","amount":"5"}"#);
+        transport.send("/admin", "d");
+        transport.send("/client", "t");
+        transport.send("/client", "k");
+        transport.pause();
+
+        let bank = bank_with(&[("patko", 1_000), ("siska", 0)]);
+        let served = serve(bank, Config::default(), transport.clone(), None);
+
+        assert_eq!(served.unwrap(), ShutdownReason::Drained);
+        assert_eq!(transport.replies("/admin"), ["200"]);
+        let replies = transport.replies("/client");
+        assert!(replies[1].contains("transaction_id"), "{}", replies[1]);
+        assert_eq!(replies[2], "503");
+        assert!(replies[3].contains("\"status\""), "{}", replies[3]);
+    }
+
+    #[test]
+    fn without_configured_admins_only_the_local_ones_stop_the_server() {
+        let auth = Auth::from_config(&Config::default()).unwrap();
+        let header = protocol::RequestHeader::default();
+        let uid = unsafe { libc::geteuid() };
+        let stranger = PeerCredentials {
+            pid: 1,
+            uid: uid + 1,
+            gid: 0,
+        };
+        let server = PeerCredentials { uid, ..stranger };
+
+        for instruction in ["q", "d"] {
+            assert_eq!(
+                auth.authorize(instruction, &header, Some(&stranger)),
+                Err(auth::AuthError::Forbidden)
+            );
+            assert_eq!(
+                auth.authorize(instruction, &header, None),
+                Err(auth::AuthError::Forbidden)
+            );
+            assert!(auth.authorize(instruction, &header, Some(&server)).is_ok());
+        }
+        assert!(auth.authorize("k", &header, Some(&stranger)).is_ok());
+    }
+
+    #[test]
+    fn accounts_are_listed_by_name_in_every_format() {
+        let mut bank = bank_with(&[("siska", 250), ("patko", 10_000)]);
+        bank.open_account(
+            "jozko, ml.".to_string(),
+            Balance::from_minor(5),
+            Currency::EUR,
+        )
+        .unwrap();
+
+        let json = bank
+            .list_accounts(ListingFormat::Json, None, &AccountFilter::default())
+            .unwrap();
+        let names: Vec<_> = ["jozko", "patko", "siska"]
+            .iter()
+            .map(|name| json.find(name).unwrap())
+            .collect();
+        assert!(names.is_sorted(), "{json}");
+        assert_eq!(
+            bank.to_string().lines().next(),
+            Some("jozko, ml., 0.05 EUR")
+        );
+        assert_eq!(
+            bank.list_accounts(ListingFormat::Table, None, &AccountFilter::default())
+                .unwrap(),
+            "name        id  balance  currency  held  status\n\
+             jozko, ml.   3     0.05  EUR       0.00  active\n\
+             patko        2   100.00  EUR       0.00  active\n\
+             siska        1     2.50  EUR       0.00  active\n"
+        );
+        assert_eq!(
+            bank.list_accounts(ListingFormat::Csv, None, &AccountFilter::default())
+                .unwrap(),
+            "name,id,balance,currency,held,status\n\
+             \"jozko, ml.\",3,0.05,EUR,0.00,active\n\
+             patko,2,100.00,EUR,0.00,active\n\
+             siska,1,2.50,EUR,0.00,active\n"
+        );
+    }
+
+    #[test]
+    fn accounts_are_listed_a_page_at_a_time() {
+        let transport = Arc::new(transport::MockTransport::default());
+        transport.send("/client", r#"i{"page_size":2}"#);
+        transport.send("/client", r#"i{"page_size":2,"cursor":"patko"}"#);
+        transport.send("/client", r#"i{"page_size":1,"format":"csv"}"#);
+        transport.send("/client", "q");
+        let bank = bank_with(&[("siska", 0), ("patko", 0), ("jozko", 0)]);
+
+        let served = serve(bank, Config::default(), transport.clone(), None);
+
+        assert_eq!(served.unwrap(), ShutdownReason::Quit);
+        let replies = transport.replies("/client");
+        let page: serde_json::Value = serde_json::from_str(&replies[0]).unwrap();
+        let names: Vec<_> = page["accounts"].as_object().unwrap().keys().collect();
+        assert_eq!(names, ["jozko", "patko"]);
+        assert_eq!(page["next"], "patko");
+        let page: serde_json::Value = serde_json::from_str(&replies[1]).unwrap();
+        let names: Vec<_> = page["accounts"].as_object().unwrap().keys().collect();
+        assert_eq!(names, ["siska"]);
+        assert!(page.get("next").is_none(), "{page}");
+        assert_eq!(
+            replies[2],
+            "name,id,balance,currency,held,status\n\
+             jozko,3,0.00,EUR,0.00,active\n\
+             \n\
+             next: jozko\n"
+        );
+    }
+
+    #[test]
+    fn accounts_are_searched_on_the_server() {
+        let transport = Arc::new(transport::MockTransport::default());
+        transport.send("/admin", "a");
+        transport.send(
+            "/admin",
+            r#"{"op":"update_metadata","account":"checking","tags":["family"]}"#,
+        );
+        transport.send("/client", "j");
+        transport.send(
+            "/client",
+            r#"{"name_contains":"SAV","min_balance":"10.00"}"#,
+        );
+        transport.send("/client", r#"j{"format":"csv"}"#);
+        transport.send("/client", r#"{"tag":"family","status":"active"}"#);
+        transport.send("/client", "j");
+        transport.send("/client", r#"{"name_prefix":"Sav"}"#);
+        transport.send("/client", "j");
+        transport.send("/client", r#"{"balance":"1"}"#);
+        transport.send("/client", "q");
+        let bank = bank_with(&[("savings", 50_000), ("Sava", 100), ("checking", 90_000)]);
+
+        let served = serve(bank, Config::default(), transport.clone(), None);
+
+        assert_eq!(served.unwrap(), ShutdownReason::Quit);
+        let replies = transport.replies("/client");
+        let found: serde_json::Value = serde_json::from_str(&replies[1]).unwrap();
+        let names: Vec<_> = found.as_object().unwrap().keys().collect();
+        assert_eq!(names, ["savings"]);
+        assert_eq!(
+            replies[3],
+            "name,id,balance,currency,held,status\n\
+             checking,3,900.00,EUR,0.00,active\n"
+        );
+        let found: serde_json::Value = serde_json::from_str(&replies[5]).unwrap();
+        let names: Vec<_> = found.as_object().unwrap().keys().collect();
+        assert_eq!(names, ["Sava"]);
+        assert_eq!(replies[6..], ["200", "400"]);
+    }
+
+    #[test]
+    fn names_are_looked_up_and_taken_as_normalized() {
+        let mut bank = bank_with(&[("jo\u{17E}ko", 100)]);
+
+        assert!(bank.resolve(&name("joz\u{30C}ko")).is_some());
+        assert!(matches!(
+            bank.open_account("joz\u{30C}ko".to_string(), Balance::ZERO, Currency::EUR),
+            Err(CustomError::AccountAlreadyExistsError(_))
+        ));
+        assert!(matches!(
+            bank.open_account(
+                "jo\u{200B}\u{17E}ko".to_string(),
+                Balance::ZERO,
+                Currency::EUR
+            ),
+            Err(CustomError::InvalidNameError(_))
+        ));
+    }
+
+    #[test]
+    fn names_that_look_like_others_are_refused() {
+        let mut bank = bank_with(&[("paypal", 100), ("mary", 0), ("copy", 0)]);
+
+        // A digit one for "l", and Cyrillic "с", "о", "р" and "у".
+        for (look_alike, existing) in [
+            ("paypa1", "paypal"),
+            ("\u{441}\u{43E}\u{440}\u{443}", "copy"),
+            ("rnary", "mary"),
+        ] {
+            let error = bank
+                .open_account(look_alike.to_string(), Balance::ZERO, Currency::EUR)
+                .unwrap_err();
+            assert!(matches!(
+                error,
+                CustomError::InvalidNameError(InvalidNameError::LooksLike(account_name))
+                    if account_name == existing
+            ));
+        }
+        assert!(matches!(
+            bank.open_sub_account(&name("mary"), "paypaI".to_string()),
+            Err(CustomError::InvalidNameError(_))
+        ));
+        assert!(matches!(
+            bank.rename_account(&name("mary"), "paypa1".to_string()),
+            Err(CustomError::InvalidNameError(_))
+        ));
+
+        // An account may take a name that looks like its own, and give up
+        // the one it had.
+        bank.rename_account(&name("paypal"), "PayPal".to_string())
+            .unwrap();
+        bank.rename_account(&name("mary"), "paypal".to_string())
+            .unwrap();
+        assert!(bank
+            .open_account("rnary".to_string(), Balance::ZERO, Currency::EUR)
+            .is_ok());
+    }
+
+    #[test]
+    fn banks_are_restored_from_what_they_serialize_to() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        transfer(&mut bank, "a", "b", 30).unwrap();
+        bank.place_hold(tx_info(name("a"), name("b"), 20), bank.now())
+            .unwrap();
+        bank.set_status(&name("b"), AccountStatus::Frozen).unwrap();
+
+        let json = serde_json::to_string(&bank).unwrap();
+        let mut restored: Bank = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.to_string(), bank.to_string());
+        assert_eq!(restored.ledger().entries().len(), 2);
+        restored
+            .set_status(&name("b"), AccountStatus::Active)
+            .unwrap();
+        transfer(&mut restored, "a", "b", 50).unwrap();
+        assert!(matches!(
+            transfer(&mut restored, "a", "b", 1),
+            Err(CustomError::InsufficientFundsError(_))
+        ));
+    }
+
+    #[test]
+    fn schedules_rates_and_flags_survive_a_restart() {
+        let clock = Arc::new(clock::ManualClock::new(1_000_000));
+        let mut bank = Bank::with_clock(clock.clone());
+        for (account, opening) in [("a", 1_000), ("b", 0)] {
+            bank.open_account(
+                account.to_string(),
+                Balance::from_minor(opening),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        bank.set_fraud_rules(vec![FraudRule {
+            condition: Condition::NewPayee {
+                min_age_secs: 3_600,
+            },
+            action: Action::Flag,
+        }]);
+        transfer(&mut bank, "a", "b", 100).unwrap();
+        bank.set_fraud_rules(Vec::new());
+        bank.set_fx_rate(Currency::EUR, Currency::USD, "1.0854".parse().unwrap())
+            .unwrap();
+        let scheduled = bank
+            .schedule_transfer(1_000_100, tx_info(name("a"), name("b"), 10))
+            .unwrap();
+        let cancelled = bank
+            .schedule_transfer(1_000_100, tx_info(name("a"), name("b"), 10))
+            .unwrap();
+        bank.cancel_scheduled_transfer(cancelled.schedule_id)
+            .unwrap();
+        let order = bank
+            .create_standing_order(
+                Every::Day,
+                1_000_000,
+                InsufficientFunds::Skip,
+                tx_info(name("a"), name("b"), 5),
+            )
+            .unwrap();
+        bank.run_standing_orders(1_000_000);
+
+        let mut log = Vec::new();
+        bank.write_events(&mut log).unwrap();
+        let replayed = Bank::replay(clock.clone(), log.as_slice()).unwrap();
+        let json = serde_json::to_string(&bank).unwrap();
+        let restored: Bank = serde_json::from_str(&json).unwrap();
+        for mut bank in [replayed, restored] {
+            assert_eq!(bank.flagged_transactions().len(), 1);
+            assert_eq!(
+                bank.fx.rate(Currency::EUR, Currency::USD),
+                Some("1.0854".parse().unwrap())
+            );
+            assert!(bank.scheduled_transfer(cancelled.schedule_id).is_none());
+            assert_eq!(
+                bank.standing_order(order.order_id).unwrap().next_run_at,
+                1_086_400
+            );
+            bank.run_scheduled_transfers(1_000_100);
+            assert!(bank.scheduled_transfer(scheduled.schedule_id).is_none());
+            assert_eq!(balance(&bank, "b"), 115);
+            let next = bank
+                .schedule_transfer(1_000_200, tx_info(name("a"), name("b"), 10))
+                .unwrap();
+            assert!(next.schedule_id > cancelled.schedule_id);
+        }
+    }
+
+    #[test]
+    fn accounts_others_refer_to_are_not_merged() {
+        let mut bank = bank_with(&[
+            ("borrower", 0),
+            ("peer", 0),
+            ("payer", 100),
+            ("beneficiary", 0),
+            ("joint", 100),
+            ("scheduled", 100),
+            ("ordered", 100),
+            ("parked", 100),
+            ("into", 0),
+        ]);
+        bank.issue_loan(
+            &name("borrower"),
+            Money::from_minor(100),
+            "0.12".parse().unwrap(),
+            1,
+            None,
+        )
+        .unwrap();
+        bank.open_settlement_account(&PeerBankConfig {
+            socket_path: PathBuf::from("/nonexistent/bank.sock"),
+            settlement_account: "peer".to_string(),
+            currency: Currency::EUR,
+            credit_limit: None,
+            token: None,
+        })
+        .unwrap();
+        let now = bank.now();
+        bank.open_escrow(
+            tx_info(name("payer"), name("beneficiary"), 10),
+            None,
+            Some(60),
+            EscrowOutcome::Refund,
+            now,
+        )
+        .unwrap();
+        for owner in ["jozko", "marienka"] {
+            bank.add_owner(&name("joint"), owner.to_string()).unwrap();
+        }
+        bank.set_approvals_required(&name("joint"), 2).unwrap();
+        bank.request_approval(tx_info(name("joint"), name("into"), 10), Some("jozko"), now)
+            .unwrap();
+        bank.schedule_transfer(now + 10, tx_info(name("scheduled"), name("into"), 10))
+            .unwrap();
+        bank.create_standing_order(
+            Every::Day,
+            now + 10,
+            InsufficientFunds::Skip,
+            tx_info(name("ordered"), name("into"), 10),
+        )
+        .unwrap();
+        bank.park_transfer(tx_info(name("parked"), name("into"), 10), None, Checks::All)
+            .unwrap();
+
+        for account in [
+            "borrower",
+            "loan1",
+            "peer",
+            "payer",
+            "beneficiary",
+            "joint",
+            "scheduled",
+            "ordered",
+            "parked",
+        ] {
+            assert!(
+                matches!(
+                    bank.merge_accounts(&name(account), &name("into")),
+                    Err(CustomError::UnmergeableAccountError(_))
+                ),
+                "{account}"
+            );
+        }
+    }
+
+    #[test]
+    fn merged_accounts_move_their_balance_and_close() {
+        let mut bank = bank_with(&[("a", 100), ("b", 50), ("c", 0)]);
+        bank.set_overdraft_limit(&name("c"), Money::from_minor(100))
+            .unwrap();
+        transfer(&mut bank, "c", "a", 30).unwrap();
+
+        let transaction_id = bank.merge_accounts(&name("a"), &name("b")).unwrap();
+        assert_eq!(balance(&bank, "a"), 0);
+        assert_eq!(balance(&bank, "b"), 180);
+        let a = bank.account(&name("a")).unwrap();
+        assert_eq!(a.status, AccountStatus::Closed);
+        assert_eq!(a.merged_into, bank.resolve(&name("b")));
+        assert!(bank.ledger().transaction(transaction_id.unwrap()).is_some());
+        assert!(matches!(
+            transfer(&mut bank, "a", "b", 1),
+            Err(CustomError::AccountClosedError(_))
+        ));
+
+        bank.merge_accounts(&name("c"), &name("b")).unwrap();
+        assert_eq!(balance(&bank, "b"), 150);
+        for (account, into) in [("b", "b"), ("a", "b"), ("b", "c")] {
+            assert!(bank.merge_accounts(&name(account), &name(into)).is_err());
+        }
+
+        let mut log = Vec::new();
+        bank.write_events(&mut log).unwrap();
+        let replayed = Bank::replay(Arc::new(clock::SystemClock), log.as_slice()).unwrap();
+        assert_eq!(replayed.to_string(), bank.to_string());
+        assert_eq!(
+            replayed.ledger().entries().len(),
+            bank.ledger().entries().len()
+        );
+    }
+
+    #[test]
+    fn renamed_accounts_keep_their_ids() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0)]);
+        let id = bank.resolve(&name("a")).unwrap();
+
+        bank.rename_account(&name("a"), "c".to_string()).unwrap();
+        assert!(bank.resolve(&name("a")).is_none());
+        assert_eq!(bank.resolve(&name("c")), Some(id));
+        transfer(&mut bank, "c", "b", 30).unwrap();
+        assert!(matches!(
+            bank.rename_account(&name("b"), "c".to_string()),
+            Err(CustomError::AccountAlreadyExistsError(_))
+        ));
+        assert!(matches!(
+            bank.rename_account(&name("b"), " c".to_string()),
+            Err(CustomError::InvalidNameError(_))
+        ));
+        bank.open_account("a".to_string(), Balance::ZERO, Currency::EUR)
+            .unwrap();
+
+        let mut log = Vec::new();
+        bank.write_events(&mut log).unwrap();
+        let replayed = Bank::replay(Arc::new(clock::SystemClock), log.as_slice()).unwrap();
+        assert_eq!(replayed.resolve(&name("c")), Some(id));
+        assert_eq!(replayed.to_string(), bank.to_string());
+    }
+
+    #[test]
+    fn names_in_the_configuration_are_not_renamed() {
+        let mut bank = bank_with(&[("a", 100), ("b", 0), ("c", 0)]);
+        let mut config = Config::default();
+        config.account_pins.insert(
+            "a".to_string(),
+            crypto::argon2::hash_password("1234").unwrap(),
+        );
+        config.identities.insert(
+            "carol".to_string(),
+            auth::IdentityConfig {
+                role: auth::Role::Customer,
+                accounts: vec!["b".to_string(), "d".to_string()],
+            },
+        );
+        configure_bank(&mut bank, &config);
+
+        for (account, new_name) in [("a", "x"), ("b", "x"), ("c", "d")] {
+            assert!(matches!(
+                bank.rename_account(&name(account), new_name.to_string()),
+                Err(CustomError::ConfiguredNameError(_))
+            ));
+        }
+        bank.rename_account(&name("c"), "x".to_string()).unwrap();
+
+        // After a reload the PIN still guards the account it was set for.
+        configure_bank(&mut bank, &config);
+        assert!(matches!(
+            transfer(&mut bank, "a", "x", 10),
+            Err(CustomError::InvalidPinError(_))
+        ));
+        assert!(bank.resolve(&name("d")).is_none());
+    }
+
+    /// A server for `bank` with token identities: customer `jozko` owning
+    /// `joint` and `jozko`, and admin `admin`, with tokens of their names.
+    fn serve_with_identities(bank: Bank, transport: Arc<transport::MockTransport>) {
+        let mut config = Config::default();
+        config.token_auth = Some(TokenAuthConfig {
+            tokens: ["jozko", "admin"]
+                .map(|identity| (identity.to_string(), identity.to_string()))
+                .into(),
+            token_file: None,
+        });
+        config.identities.insert(
+            "jozko".to_string(),
+            IdentityConfig {
+                role: Role::Customer,
+                accounts: vec!["joint".to_string(), "jozko".to_string()],
+            },
+        );
+        config.identities.insert(
+            "admin".to_string(),
+            IdentityConfig {
+                role: Role::Admin,
+                accounts: Vec::new(),
+            },
+        );
+        transport.send("/client", r#"q{"token":"admin"}"#);
+        let served = serve(bank, config, transport, None);
+        assert_eq!(served.unwrap(), ShutdownReason::Quit);
+    }
+
+    #[test]
+    fn one_owner_cannot_change_the_owners_of_a_joint_account() {
+        let mut bank = bank_with(&[("joint", 1_000), ("shop", 0)]);
+        let joint = name("joint");
+        for owner in ["jozko", "marienka", "ferko"] {
+            bank.add_owner(&joint, owner.to_string()).unwrap();
+        }
+        bank.set_approvals_required(&joint, 2).unwrap();
+        let transport = Arc::new(transport::MockTransport::default());
+        for (token, command) in [
+            (
+                "jozko",
+                r#"{"op":"set_approvals_required","account":"joint","required":1}"#,
+            ),
+            (
+                "jozko",
+                r#"{"op":"remove_owner","account":"joint","owner":"marienka"}"#,
+            ),
+            (
+                "jozko",
+                r#"{"op":"add_owner","account":"joint","owner":"eve"}"#,
+            ),
+            (
+                "admin",
+                r#"{"op":"set_approvals_required","account":"joint","required":1}"#,
+            ),
+            (
+                "jozko",
+                r#"{"op":"remove_owner","account":"joint","owner":"marienka"}"#,
+            ),
+        ] {
+            transport.send("/client", format!(r#"o{{"token":"{token}"}}"#));
+            transport.send("/client", command);
+        }
+
+        serve_with_identities(bank, transport.clone());
+
+        let replies = transport.replies("/client");
+        // Each instruction is acknowledged before its payload is sent.
+        let replies: Vec<_> = replies[..10].iter().skip(1).step_by(2).collect();
+        assert_eq!(replies, ["403", "403", "403", "200", "200"]);
+    }
+
+    #[test]
+    fn customers_do_not_list_other_customers_accounts() {
+        let bank = bank_with(&[("jozko", 100), ("marienka", 200)]);
+        let transport = Arc::new(transport::MockTransport::default());
+        transport.send("/client", r#"i{"token":"jozko"}"#);
+        transport.send("/client", r#"i{"token":"admin"}"#);
+
+        serve_with_identities(bank, transport.clone());
+
+        let replies = transport.replies("/client");
+        assert!(replies[0].contains("jozko"), "{}", replies[0]);
+        assert!(!replies[0].contains("marienka"), "{}", replies[0]);
+        assert!(replies[1].contains("marienka"), "{}", replies[1]);
+    }
+
+    #[test]
+    fn oversized_messages_are_refused() {
+        let transport = Arc::new(transport::MockTransport::default());
+        let memo = "x".repeat(100);
+        transport.send("/client", format!("k{{\"tenant\":\"{memo}\"}}"));
+        transport.send("/client", "t");
+        transport.send(
+            "/client",
+            format!(r#"{{"from":"patko","to":"siska","amount":"1","memo":"{memo}"}}"#),
+        );
+        transport.send("/client", "t");
+        transport.send("/client", r#"{"from":"patko","to":"siska","amount":"1"}"#);
+        transport.send("/client", "q");
+        let mut config = Config::default();
+        config.max_message_size = 64;
+
+        let served = serve(
+            bank_with(&[("patko", 100), ("siska", 0)]),
+            config,
+            transport.clone(),
+            None,
+        );
+
+        assert_eq!(served.unwrap(), ShutdownReason::Quit);
+        let replies = transport.replies("/client");
+        assert_eq!(replies[..4], ["413", "200", "413", "200"]);
+        assert!(replies[4].contains("transaction_id"), "{}", replies[4]);
+    }
+
+    #[test]
+    fn sandboxes_are_copies_that_leave_the_bank_alone() {
+        let bank = bank_with(&[("a", 1_000), ("b", 0)]);
+        let mut sandbox = bank.sandbox(false);
+        assert_eq!(balance(&sandbox, "a"), 1_000);
+        transfer(&mut sandbox, "a", "b", 400).unwrap();
+        assert_eq!(balance(&sandbox, "b"), 400);
+        assert!(invariants::check(&sandbox).consistent);
+        assert_eq!(balance(&bank, "a"), 1_000);
+        assert_eq!(balance(&bank, "b"), 0);
+
+        // Resetting it starts over from the bank.
+        let sandbox = bank.sandbox(false);
+        assert_eq!(balance(&sandbox, "b"), 0);
+        assert!(bank.sandbox(true).accounts.is_empty());
     }
-    Ok(0)
 }
@@ -0,0 +1,53 @@
+//! Caps on what an account can send: the most a single transfer may move,
+//! and the most that may leave the account within any 24 hours. Limits are
+//! set for all accounts with the `transfer_limits` setting and can be
+//! overridden per account by admins.
+
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::money::Money;
+
+/// The rolling window daily outflow is counted over.
+pub const WINDOW_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransferLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_transfer: Option<Money>,
+    /// Sent within the last 24 hours, not counting fees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_daily_outflow: Option<Money>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    Transfer,
+    DailyOutflow,
+}
+
+impl Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitKind::Transfer => write!(f, "single transfer"),
+            LimitKind::DailyOutflow => write!(f, "daily outflow"),
+        }
+    }
+}
+
+impl TransferLimits {
+    /// Check sending `amount` after `outflow` left the account within the
+    /// window. On failure, returns the limit that would be exceeded.
+    pub fn check(&self, amount: Money, outflow: Money) -> Result<(), (LimitKind, Money)> {
+        if let Some(max) = self.max_transfer.filter(|max| amount > *max) {
+            return Err((LimitKind::Transfer, max));
+        }
+        let total = outflow.checked_add(amount).unwrap_or(Money::MAX);
+        if let Some(max) = self.max_daily_outflow.filter(|max| total > *max) {
+            return Err((LimitKind::DailyOutflow, max));
+        }
+        Ok(())
+    }
+}
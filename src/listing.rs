@@ -0,0 +1,187 @@
+//! Layouts of the account listing the `i` instruction replies with, chosen
+//! by the `format` of the request header. Accounts come sorted by name in
+//! every layout, so that listings can be diffed and parsed by scripts.
+//!
+//! With a `cursor` or `page_size` in the header the listing comes a page at
+//! a time. A page that is not the last one carries the `cursor` of the next:
+//! JSON pages are `{"accounts": {...}, "next": "..."}`, table and CSV pages
+//! end with a blank line and `next: ...`. Without either the whole listing
+//! comes at once, however long.
+//!
+//! The `j` instruction replies in the same way with the accounts matching
+//! the `AccountFilter` in its payload, e.g. `{"name_prefix": "sav",
+//! "min_balance": "100.00", "status": "active"}`.
+
+use std::collections::BTreeMap;
+use std::iter;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Error as SerdeError;
+
+use crate::{csv, Account, AccountInfo, AccountStatus, Balance};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListingFormat {
+    /// An object of the accounts by name, with all their details.
+    #[default]
+    Json,
+    /// A table of the `COLUMNS`, aligned for reading.
+    Table,
+    /// CSV of the `COLUMNS`, with a header line.
+    Csv,
+}
+
+/// Accounts on a page when the request does not say.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Most accounts on a page.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Which page of the listing to reply with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Page<'a> {
+    /// The `next` of the previous page, `None` for the first page.
+    pub cursor: Option<&'a str>,
+    pub size: Option<usize>,
+}
+
+impl Page<'_> {
+    /// Accounts on the page, capped at `MAX_PAGE_SIZE`.
+    pub fn size(&self) -> usize {
+        self.size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// Whether the page comes after the account named `name`.
+    pub fn follows(&self, name: &str) -> bool {
+        self.cursor.is_none_or(|cursor| name > cursor)
+    }
+}
+
+#[derive(Serialize)]
+struct PageReply<'a, 'b> {
+    accounts: &'b BTreeMap<&'a str, AccountInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<&'a str>,
+}
+
+/// Payload of the `j` instruction. An account matches when it meets every
+/// criterion given.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountFilter {
+    /// Start of the name.
+    pub name_prefix: Option<String>,
+    /// Part of the name, in any case.
+    pub name_contains: Option<String>,
+    pub min_balance: Option<Balance>,
+    pub max_balance: Option<Balance>,
+    pub status: Option<AccountStatus>,
+    /// One of the tags.
+    pub tag: Option<String>,
+    /// One of these names. Not part of the payload, the server sets it to
+    /// the accounts of customers, who see no others.
+    #[serde(skip)]
+    pub names: Option<Vec<String>>,
+}
+
+impl AccountFilter {
+    pub fn matches(&self, account: &Account) -> bool {
+        let contains = |part: &String| account.name.to_lowercase().contains(&part.to_lowercase());
+        self.name_prefix
+            .as_ref()
+            .is_none_or(|prefix| account.name.starts_with(prefix.as_str()))
+            && self.name_contains.as_ref().is_none_or(contains)
+            && self.min_balance.is_none_or(|min| account.balance >= min)
+            && self.max_balance.is_none_or(|max| account.balance <= max)
+            && self.status.is_none_or(|status| account.status == status)
+            && self
+                .tag
+                .as_ref()
+                .is_none_or(|tag| account.metadata.tags.contains(tag))
+            && self
+                .names
+                .as_ref()
+                .is_none_or(|names| names.iter().any(|name| **name == *account.name))
+    }
+}
+
+/// Columns of the table and CSV layouts.
+const COLUMNS: [&str; 6] = ["name", "id", "balance", "currency", "held", "status"];
+
+/// Columns aligned to the right in tables.
+const NUMERIC: [bool; 6] = [false, true, true, false, true, false];
+
+/// The accounts, by name, in `format`.
+pub fn render(
+    accounts: &BTreeMap<&str, AccountInfo>,
+    format: ListingFormat,
+) -> Result<String, SerdeError> {
+    Ok(match format {
+        ListingFormat::Json => serde_json::to_string(accounts)?,
+        ListingFormat::Table => table(rows(accounts)),
+        ListingFormat::Csv => rows(accounts)
+            .map(|row| {
+                let fields: Vec<_> = row.iter().map(|field| csv::field(field)).collect();
+                fields.join(",") + "\n"
+            })
+            .collect(),
+    })
+}
+
+/// A page of the accounts, by name, in `format`, followed by the cursor of
+/// the `next` page if there is one.
+pub fn render_page(
+    accounts: &BTreeMap<&str, AccountInfo>,
+    format: ListingFormat,
+    next: Option<&str>,
+) -> Result<String, SerdeError> {
+    match (format, next) {
+        (ListingFormat::Json, _) => serde_json::to_string(&PageReply { accounts, next }),
+        (_, Some(next)) => Ok(format!("{}\nnext: {next}\n", render(accounts, format)?)),
+        (_, None) => render(accounts, format),
+    }
+}
+
+/// The header and a row for each account.
+fn rows<'a>(accounts: &'a BTreeMap<&str, AccountInfo>) -> impl Iterator<Item = [String; 6]> + 'a {
+    let header = COLUMNS.map(String::from);
+    let accounts = accounts.iter().map(|(name, account)| {
+        [
+            name.to_string(),
+            account.id.0.to_string(),
+            account.balance.to_string(),
+            account.currency.to_string(),
+            account.held.to_string(),
+            account.status.as_str().to_string(),
+        ]
+    });
+    iter::once(header).chain(accounts)
+}
+
+fn table(rows: impl Iterator<Item = [String; 6]>) -> String {
+    let rows: Vec<_> = rows.collect();
+    let mut widths = [0; 6];
+    for row in &rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in rows {
+        let fields: Vec<_> = row
+            .iter()
+            .zip(widths)
+            .zip(NUMERIC)
+            .map(|((field, width), numeric)| match numeric {
+                true => format!("{field:>width$}"),
+                false => format!("{field:<width$}"),
+            })
+            .collect();
+        table.push_str(fields.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
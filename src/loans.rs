@@ -0,0 +1,216 @@
+//! Loans. Issuing one opens a loan account named after the loan and moves
+//! the principal from it to the borrower, so the loan account's balance is
+//! minus what is outstanding. Repayments are ordinary transfers to the loan
+//! account. At every installment's due date interest on what is still
+//! outstanding is charged to the loan account.
+//!
+//! The schedule is an annuity: equal payments, the last one adjusted, that
+//! repay the principal and the interest charged on the remainder over the
+//! loan's installments. It is what the borrower pays if every installment is
+//! paid on time; paying more or less changes the interest charged.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::fx::Rate;
+use crate::interest::YEAR_SECS;
+use crate::money::{Currency, Money};
+use crate::AccountId;
+
+pub const DEFAULT_PERIOD_SECS: u64 = 30 * 24 * 60 * 60;
+
+pub const MEMO: &str = "loan";
+
+pub const INTEREST_MEMO: &str = "loan interest";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct LoanId(u64);
+
+impl Display for LoanId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "loan{}", self.0)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LoanError {
+    #[error("A loan needs a principal above zero and at least one installment")]
+    InvalidTerms,
+    #[error("The installments of a loan of {principal} cannot be worked out")]
+    Unschedulable { principal: Money },
+    #[error("Loan {0} not found")]
+    NotFound(LoanId),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Loan {
+    pub id: LoanId,
+    /// Named after the loan, with a number appended if an account had that
+    /// name. Its balance is minus what is outstanding.
+    pub account: AccountId,
+    pub borrower: AccountId,
+    pub principal: Money,
+    pub currency: Currency,
+    /// Annual rate, e.g. `0.05` for 5 %.
+    pub rate: Rate,
+    pub installments: u32,
+    pub period_secs: u64,
+    /// Seconds since the Unix epoch.
+    pub issued_at: u64,
+    /// Installments whose interest has been charged.
+    pub installments_charged: u32,
+}
+
+impl Loan {
+    /// When installment `number`, counted from 1, is due.
+    pub fn due_at(&self, number: u32) -> u64 {
+        self.issued_at
+            .saturating_add(self.period_secs.saturating_mul(number.into()))
+    }
+
+    /// Interest for one period on `outstanding`.
+    pub fn interest(&self, outstanding: Money) -> Option<Money> {
+        self.rate.prorate(outstanding, self.period_secs, YEAR_SECS)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Installment {
+    pub number: u32,
+    /// Seconds since the Unix epoch.
+    pub due_at: u64,
+    pub payment: Money,
+    /// Part of the payment that repays the principal.
+    pub principal: Money,
+    pub interest: Money,
+    /// Principal left after the payment.
+    pub remaining: Money,
+}
+
+/// Reply to issuing or looking up a loan.
+#[derive(Debug, Serialize)]
+pub struct LoanInfo {
+    #[serde(flatten)]
+    pub loan: Loan,
+    pub outstanding: Money,
+    pub schedule: Vec<Installment>,
+}
+
+/// The installments that repay `loan`, or `None` if they overflow.
+pub fn schedule(loan: &Loan) -> Option<Vec<Installment>> {
+    // The smallest payment that repays the loan in time. Interest is
+    // rounded like when it is charged, so search instead of using the
+    // annuity formula.
+    let fits = |payment: Money| {
+        repay(loan, payment)
+            .and_then(|installments| installments.last().map(|last| last.payment <= payment))
+            .unwrap_or(false)
+    };
+    let mut low = 0;
+    let mut high = loan
+        .principal
+        .checked_add(loan.interest(loan.principal)?)?
+        .minor_units();
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if fits(Money::from_minor(middle)) {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    repay(loan, Money::from_minor(high))
+}
+
+/// The installments of `payment`, the last one paying whatever is left.
+/// `None` on overflow.
+fn repay(loan: &Loan, payment: Money) -> Option<Vec<Installment>> {
+    let mut remaining = loan.principal;
+    let mut installments = Vec::new();
+    for number in 1..=loan.installments {
+        let interest = loan.interest(remaining)?;
+        let owed = remaining.checked_add(interest)?;
+        let payment = if number == loan.installments {
+            owed
+        } else {
+            payment.min(owed)
+        };
+        // Payments that do not cover the interest leave it outstanding.
+        let principal = payment.checked_sub(interest).unwrap_or(Money::ZERO);
+        remaining = owed.checked_sub(payment)?;
+        installments.push(Installment {
+            number,
+            due_at: loan.due_at(number),
+            payment,
+            principal,
+            interest,
+            remaining,
+        });
+    }
+    Some(installments)
+}
+
+/// Loans, open or repaid.
+#[derive(Debug, Default)]
+pub struct Loans {
+    loans: BTreeMap<LoanId, Loan>,
+    last_id: u64,
+}
+
+impl Loans {
+    pub fn next_id(&self) -> LoanId {
+        LoanId(self.last_id + 1)
+    }
+
+    pub fn insert(&mut self, loan: Loan) {
+        self.last_id = self.last_id.max(loan.id.0);
+        self.loans.insert(loan.id, loan);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Loan> {
+        self.loans.values()
+    }
+
+    pub fn get(&self, id: LoanId) -> Option<&Loan> {
+        self.loans.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: LoanId) -> Option<&mut Loan> {
+        self.loans.get_mut(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_payments_repay_principal_and_interest() {
+        let loan = Loan {
+            id: LoanId(1),
+            account: AccountId(2),
+            borrower: AccountId(1),
+            principal: Money::from_minor(100_000),
+            currency: Currency::EUR,
+            rate: "0.12".parse().unwrap(),
+            installments: 12,
+            period_secs: YEAR_SECS / 12,
+            issued_at: 0,
+            installments_charged: 0,
+        };
+        let schedule = schedule(&loan).unwrap();
+        assert_eq!(schedule.len(), 12);
+        // 1 % a month on 1000.00 over a year.
+        assert_eq!(schedule[0].payment, Money::from_minor(8_885));
+        assert_eq!(schedule[0].interest, Money::from_minor(1_000));
+        assert!(schedule[11].payment <= schedule[0].payment);
+        assert!(schedule[11].remaining.is_zero());
+        assert_eq!(schedule[11].due_at, YEAR_SECS);
+        let repaid: u64 = schedule.iter().map(|row| row.principal.minor_units()).sum();
+        assert_eq!(repaid, 100_000);
+    }
+}
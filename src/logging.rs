@@ -0,0 +1,133 @@
+//! Logger whose filter and format can be swapped while the server is
+//! running, so a configuration reload can change them without a restart.
+//!
+//! Lines carry the fields of the request being handled, see `set_field`.
+//! As text they follow the message as `key=value` pairs, as JSON each line
+//! is an object with `timestamp`, `level`, `target`, `message` and the
+//! fields.
+
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::RwLock;
+
+use log::{Log, Metadata, Record, SetLoggerError};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+struct ReloadableLogger {
+    inner: RwLock<Option<env_logger::Logger>>,
+}
+
+static LOGGER: ReloadableLogger = ReloadableLogger {
+    inner: RwLock::new(None),
+};
+
+thread_local! {
+    static FIELDS: RefCell<Vec<(&'static str, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match &*self.inner.read().unwrap() {
+            Some(logger) => logger.enabled(metadata),
+            None => false,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(logger) = &*self.inner.read().unwrap() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = &*self.inner.read().unwrap() {
+            logger.flush();
+        }
+    }
+}
+
+/// Install the global logger. `filters` uses the `RUST_LOG` syntax and takes
+/// precedence over the environment variable.
+pub fn init(filters: Option<&str>, format: LogFormat) -> Result<(), SetLoggerError> {
+    configure(filters, format);
+    log::set_logger(&LOGGER)
+}
+
+/// Replace the active filter directives and format.
+pub fn configure(filters: Option<&str>, format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if let Some(filters) = filters {
+        builder.parse_filters(filters);
+    }
+    match format {
+        LogFormat::Text => builder.format(|buf, record| {
+            let level = buf.default_styled_level(record.level());
+            write!(
+                buf,
+                "[{} {level:<5} {}] {}",
+                buf.timestamp(),
+                record.target(),
+                record.args()
+            )?;
+            FIELDS.with(|fields| {
+                for (key, value) in fields.borrow().iter() {
+                    if value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+                        write!(buf, " {key}={value:?}")?;
+                    } else {
+                        write!(buf, " {key}={value}")?;
+                    }
+                }
+                Ok::<_, std::io::Error>(())
+            })?;
+            writeln!(buf)
+        }),
+        LogFormat::Json => builder.format(|buf, record| {
+            let mut line = Map::new();
+            line.insert(
+                "timestamp".into(),
+                buf.timestamp_millis().to_string().into(),
+            );
+            line.insert("level".into(), record.level().as_str().into());
+            line.insert("target".into(), record.target().into());
+            line.insert("message".into(), record.args().to_string().into());
+            FIELDS.with(|fields| {
+                for (key, value) in fields.borrow().iter() {
+                    line.entry(*key).or_insert_with(|| value.clone().into());
+                }
+            });
+            serde_json::to_writer(&mut *buf, &Value::Object(line))?;
+            writeln!(buf)
+        }),
+    };
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    *LOGGER.inner.write().unwrap() = Some(logger);
+}
+
+/// Add `key` to the lines this thread logs until `clear_fields`, replacing
+/// its previous value.
+pub fn set_field(key: &'static str, value: impl Display) {
+    FIELDS.with(|fields| {
+        let mut fields = fields.borrow_mut();
+        let value = value.to_string();
+        match fields.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, existing)) => *existing = value,
+            None => fields.push((key, value)),
+        }
+    });
+}
+
+pub fn clear_fields() {
+    FIELDS.with(|fields| fields.borrow_mut().clear());
+}
@@ -1,10 +1,175 @@
-use bank::{init_bank, run_app};
-use log::{debug, info};
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process::ExitCode;
 
-fn main() -> Result<(), std::io::Error> {
-    env_logger::init();
-    let bank = init_bank();
+use anyhow::{anyhow, Result};
+use bank::crypto::{self, argon2, x25519};
+use bank::ledger::TransactionId;
+use bank::{
+    configure_encryption, init_bank, load_bank, logging, open_bank, replay_log, restore_backup,
+    run_app, verify_audit_log, Config, ConfigError, ExportFormat, ShutdownReason,
+};
+use log::info;
+
+/// Exit code after a fatal error.
+const EXIT_FAILURE: u8 = 1;
+/// Exit code when the configuration cannot be read or its settings do not
+/// go together.
+const EXIT_CONFIG: u8 = 2;
+
+/// Exits with 0 once the server was told to quit or a command is done,
+/// `EXIT_CONFIG` for a configuration error and `EXIT_FAILURE` for any other
+/// error.
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            match e.chain().any(|cause| cause.is::<ConfigError>()) {
+                true => ExitCode::from(EXIT_CONFIG),
+                false => ExitCode::from(EXIT_FAILURE),
+            }
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    let argument = env::args().nth(1);
+    if argument.as_deref() == Some("hash-pin") {
+        return hash_pin();
+    }
+    if argument.as_deref() == Some("noise-key") {
+        return noise_key();
+    }
+    if matches!(
+        argument.as_deref(),
+        Some("verify-audit" | "export-ledger" | "replay" | "restore")
+    ) {
+        configure_encryption(None)?;
+    }
+    if argument.as_deref() == Some("verify-audit") {
+        return verify_audit();
+    }
+    if argument.as_deref() == Some("export-ledger") {
+        return export_ledger();
+    }
+    if argument.as_deref() == Some("replay") {
+        return replay();
+    }
+    if argument.as_deref() == Some("restore") {
+        return restore();
+    }
+    let config = match argument.or_else(|| env::var("BANK_CONFIG").ok()) {
+        Some(path) => Config::load(Path::new(&path))?,
+        None => Config::default(),
+    };
+    logging::init(config.log_level.as_deref(), config.log_format)?;
+    configure_encryption(config.encryption_key.clone())?;
+    let bank = match &config.data_dir {
+        Some(data_dir) => open_bank(
+            data_dir,
+            config.snapshot_every,
+            config.accounts_file.as_deref(),
+        )?,
+        None => init_bank(config.accounts_file.as_deref())?,
+    };
     info!("Created the Bank object");
-    run_app(bank).unwrap();
+    match run_app(bank, config)? {
+        ShutdownReason::Quit => info!("Stopped serving as a client asked"),
+        ShutdownReason::Drained => info!("Stopped serving once drained"),
+    }
+    Ok(())
+}
+
+/// Read a PIN from stdin and print its hash for the `account_pins` setting.
+fn hash_pin() -> Result<()> {
+    let mut pin = String::new();
+    io::stdin().read_line(&mut pin)?;
+    println!(
+        "{}",
+        argon2::hash_password(pin.trim_end_matches(['\r', '\n']))?
+    );
+    Ok(())
+}
+
+/// Print a new secret key for the `noise` setting and its public key, for
+/// the clients.
+fn noise_key() -> Result<()> {
+    let secret: x25519::SecretKey = crypto::random_bytes(x25519::KEY_LENGTH)?
+        .try_into()
+        .expect("key length");
+    println!("secret_key {}", crypto::to_hex(&secret));
+    println!(
+        "public_key {}",
+        crypto::to_hex(&x25519::public_key(&secret))
+    );
+    Ok(())
+}
+
+/// Check the audit log of the data directory given after `verify-audit`.
+fn verify_audit() -> Result<()> {
+    let data_dir = env::args()
+        .nth(2)
+        .ok_or_else(|| anyhow!("Usage: bank verify-audit <data_dir>"))?;
+    let summary = verify_audit_log(Path::new(&data_dir))?;
+    println!(
+        "Audit log intact: {} events, head {}",
+        summary.events,
+        crypto::to_hex(&summary.head)
+    );
+    Ok(())
+}
+
+/// Write the ledger of the data directory given after `export-ledger` to a
+/// file, as CSV unless the format is given.
+fn export_ledger() -> Result<()> {
+    let usage = || anyhow!("Usage: bank export-ledger <data_dir> <output> [csv|parquet]");
+    let data_dir = env::args().nth(2).ok_or_else(usage)?;
+    let output = env::args().nth(3).ok_or_else(usage)?;
+    let format: ExportFormat = match env::args().nth(4) {
+        Some(format) => format.parse()?,
+        None => ExportFormat::default(),
+    };
+    let bank = load_bank(Path::new(&data_dir))?;
+    let entries = bank.export_ledger(Path::new(&output), format)?;
+    println!("Exported {entries} ledger entries to {output}");
+    Ok(())
+}
+
+/// Rebuild the backup given after `restore` in a new data directory, up to
+/// the transaction given after that if any.
+fn restore() -> Result<()> {
+    let usage = || anyhow!("Usage: bank restore <backup> <data_dir> [transaction_id]");
+    let backup = env::args().nth(2).ok_or_else(usage)?;
+    let data_dir = env::args().nth(3).ok_or_else(usage)?;
+    let until = env::args()
+        .nth(4)
+        .map(|until| until.parse().map(TransactionId))
+        .transpose()?;
+    let restored = restore_backup(Path::new(&backup), Path::new(&data_dir), until)?;
+    println!(
+        "Restored {data_dir} up to event {}, audit head {}",
+        restored.sequence,
+        crypto::to_hex(&restored.audit_head)
+    );
+    Ok(())
+}
+
+/// Rebuild the bank from the log given after `replay`, a data directory or a
+/// log file, and print its balances, up to the event given after it if any.
+fn replay() -> Result<()> {
+    let path = env::args()
+        .nth(2)
+        .ok_or_else(|| anyhow!("Usage: bank replay <data_dir|log> [sequence]"))?;
+    let until = env::args().nth(3).map(|until| until.parse()).transpose()?;
+    let replay = replay_log(Path::new(&path), until)?;
+    print!("{replay}");
+    if replay
+        .discrepancies
+        .is_some_and(|discrepancies| !discrepancies.is_empty())
+    {
+        return Err(anyhow!("The kept bank differs from its log"));
+    }
     Ok(())
 }
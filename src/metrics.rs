@@ -0,0 +1,219 @@
+//! Metrics in the Prometheus text exposition format, returned by the `m`
+//! instruction:
+//!
+//! - `bank_transactions_accepted_total`: transfers made for `t` and `b`
+//!   requests.
+//! - `bank_transactions_rejected_total{reason}`: transfers refused for `t`
+//!   and `b` requests, by reason. A failed batch counts all its transfers.
+//! - `bank_accounts`: open, frozen and closed accounts.
+//! - `bank_balance_total{currency}`: sum of the balances in each currency.
+//! - `bank_request_duration_seconds{instruction}`: histogram of the time
+//!   taken to handle requests, payload included.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::money::Total;
+use crate::{Bank, CustomError};
+
+/// Upper bounds of the latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    accepted: u64,
+    rejected: BTreeMap<&'static str, u64>,
+    latency: BTreeMap<String, Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations in each bucket, not including those of lower buckets.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn transfers_accepted(&mut self, count: u64) {
+        self.accepted += count;
+    }
+
+    pub fn transfers_rejected(&mut self, reason: &'static str, count: u64) {
+        *self.rejected.entry(reason).or_default() += count;
+    }
+
+    pub fn observe_request(&mut self, instruction: &str, duration: Duration) {
+        let histogram = self.latency.entry(instruction.to_string()).or_default();
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    pub fn render(&self, bank: &Bank) -> String {
+        let mut out = String::new();
+        // Writing to a String does not fail.
+        let _ = self.write(&mut out, bank);
+        out
+    }
+
+    fn write(&self, out: &mut String, bank: &Bank) -> std::fmt::Result {
+        writeln!(
+            out,
+            "# HELP bank_transactions_accepted_total Transfers made for client requests."
+        )?;
+        writeln!(out, "# TYPE bank_transactions_accepted_total counter")?;
+        writeln!(out, "bank_transactions_accepted_total {}", self.accepted)?;
+
+        writeln!(
+            out,
+            "# HELP bank_transactions_rejected_total Transfers refused for client requests, by reason."
+        )?;
+        writeln!(out, "# TYPE bank_transactions_rejected_total counter")?;
+        for (reason, count) in &self.rejected {
+            writeln!(
+                out,
+                "bank_transactions_rejected_total{{reason=\"{reason}\"}} {count}"
+            )?;
+        }
+
+        writeln!(out, "# HELP bank_accounts Accounts, closed ones included.")?;
+        writeln!(out, "# TYPE bank_accounts gauge")?;
+        writeln!(out, "bank_accounts {}", bank.accounts.len())?;
+
+        let mut totals: BTreeMap<&str, Total> = BTreeMap::new();
+        for account in bank.accounts.values() {
+            totals
+                .entry(account.currency.as_str())
+                .or_default()
+                .add_balance(account.balance);
+        }
+        writeln!(
+            out,
+            "# HELP bank_balance_total Sum of the account balances, in major units."
+        )?;
+        writeln!(out, "# TYPE bank_balance_total gauge")?;
+        for (currency, total) in totals {
+            writeln!(out, "bank_balance_total{{currency=\"{currency}\"}} {total}")?;
+        }
+
+        writeln!(
+            out,
+            "# HELP bank_request_duration_seconds Time taken to handle requests."
+        )?;
+        writeln!(out, "# TYPE bank_request_duration_seconds histogram")?;
+        for (instruction, histogram) in &self.latency {
+            let instruction = escape_label(instruction);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "bank_request_duration_seconds_bucket{{instruction=\"{instruction}\",le=\"{bound}\"}} {cumulative}"
+                )?;
+            }
+            writeln!(
+                out,
+                "bank_request_duration_seconds_bucket{{instruction=\"{instruction}\",le=\"+Inf\"}} {}",
+                histogram.count
+            )?;
+            writeln!(
+                out,
+                "bank_request_duration_seconds_sum{{instruction=\"{instruction}\"}} {}",
+                histogram.sum
+            )?;
+            writeln!(
+                out,
+                "bank_request_duration_seconds_count{{instruction=\"{instruction}\"}} {}",
+                histogram.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Label for the reason a transfer failed.
+pub fn rejection_reason(error: &CustomError) -> &'static str {
+    match error {
+        CustomError::AccountDoesNotExistError(_) => "account_not_found",
+        CustomError::InsufficientFundsError(_) => "insufficient_funds",
+        CustomError::BalanceOverflowError(_) => "balance_overflow",
+        CustomError::CurrencyMismatchError(_) => "currency_mismatch",
+        CustomError::NoExchangeRateError(_) | CustomError::InvalidExchangeRateError(_) => {
+            "exchange_rate"
+        }
+        CustomError::AccountFrozenError(_) => "account_frozen",
+        CustomError::AccountClosedError(_) => "account_closed",
+        CustomError::InvalidPinError(_) => "invalid_pin",
+        CustomError::EmptyBatchError(_) => "empty_batch",
+        CustomError::LimitExceededError(_) => "limit_exceeded",
+        CustomError::TransferRejectedError(_) => "fraud_rule",
+        CustomError::TransferVetoedError(_) => "vetoed",
+        CustomError::SelfTransferError(_) => "self_transfer",
+        CustomError::ZeroAmountError(_) => "zero_amount",
+        CustomError::ApprovalsRequiredError(_) => "approvals_required",
+        CustomError::ReviewRequiredError(_) => "review_required",
+        CustomError::InterbankError(_) => "peer_bank",
+        CustomError::OwnershipError(_) => "ownership",
+        _ => "other",
+    }
+}
+
+/// Instructions are single characters, but a client may send a quote or a
+/// backslash.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::{Balance, Currency};
+
+    #[test]
+    fn renders_counters_gauges_and_cumulative_buckets() {
+        let mut bank = Bank::new();
+        for (name, balance) in [("a", 1050), ("b", 2000)] {
+            bank.open_account(
+                name.to_string(),
+                Balance::from_minor(balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        let mut metrics = Metrics::new();
+        metrics.transfers_accepted(2);
+        metrics.transfers_rejected("insufficient_funds", 1);
+        metrics.observe_request("t", Duration::from_micros(700));
+        metrics.observe_request("t", Duration::from_secs(2));
+        let text = metrics.render(&bank);
+        for line in [
+            "bank_transactions_accepted_total 2",
+            "bank_transactions_rejected_total{reason=\"insufficient_funds\"} 1",
+            "bank_accounts 2",
+            "bank_balance_total{currency=\"EUR\"} 30.50",
+            "bank_request_duration_seconds_bucket{instruction=\"t\",le=\"0.0005\"} 0",
+            "bank_request_duration_seconds_bucket{instruction=\"t\",le=\"1\"} 1",
+            "bank_request_duration_seconds_bucket{instruction=\"t\",le=\"+Inf\"} 2",
+            "bank_request_duration_seconds_count{instruction=\"t\"} 2",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {line:?} in\n{text}"
+            );
+        }
+    }
+}
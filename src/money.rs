@@ -0,0 +1,375 @@
+//! Fixed-point money amounts.
+//!
+//! Amounts carry no currency of their own; accounts do, see `Currency`.
+//! Account balances are a separate, signed `Balance`, since an account with
+//! an overdraft can go below zero.
+//!
+//! Amounts are stored as a whole number of minor units (cents), two decimal
+//! places per major unit. On input, amounts with more than two decimal
+//! places are rounded to the nearest minor unit, ties to even ("banker's
+//! rounding"), so `0.125` becomes `0.12` and `0.135` becomes `0.14`.
+//! Amounts are serialized as decimal strings such as `"12.50"`; plain JSON
+//! integers are read as whole major units.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+pub const MINOR_UNITS_PER_MAJOR: u64 = 100;
+const DECIMAL_PLACES: usize = 2;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ParseMoneyError {
+    #[error("'{0}' is not a valid amount")]
+    Invalid(String),
+    #[error("amount must not be negative")]
+    Negative,
+    #[error("amount is too large")]
+    Overflow,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(u64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+    pub const MAX: Money = Money(u64::MAX);
+
+    pub const fn from_minor(minor_units: u64) -> Money {
+        Money(minor_units)
+    }
+
+    /// Whole major units, e.g. `from_major(5)` is 5.00.
+    pub fn from_major(major_units: u64) -> Option<Money> {
+        major_units.checked_mul(MINOR_UNITS_PER_MAJOR).map(Money)
+    }
+
+    pub const fn minor_units(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        self.0.checked_sub(other.0).map(Money)
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:02}",
+            self.0 / MINOR_UNITS_PER_MAJOR,
+            self.0 % MINOR_UNITS_PER_MAJOR
+        )
+    }
+}
+
+impl FromStr for Money {
+    type Err = ParseMoneyError;
+
+    fn from_str(amount: &str) -> Result<Money, ParseMoneyError> {
+        let invalid = || ParseMoneyError::Invalid(amount.to_string());
+        let trimmed = amount.trim();
+        if trimmed.starts_with('-') {
+            return Err(ParseMoneyError::Negative);
+        }
+        let (whole, fraction) = trimmed.split_once('.').unwrap_or((trimmed, ""));
+        let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if (whole.is_empty() && fraction.is_empty()) || !all_digits(whole) || !all_digits(fraction)
+        {
+            return Err(invalid());
+        }
+
+        let mut minor: u64 = 0;
+        for position in 0..DECIMAL_PLACES {
+            let digit = fraction
+                .as_bytes()
+                .get(position)
+                .map_or(0, |byte| byte - b'0');
+            minor = minor * 10 + digit as u64;
+        }
+        let whole: u64 = match whole {
+            "" => 0,
+            digits => digits.parse().map_err(|_| ParseMoneyError::Overflow)?,
+        };
+        let mut total = whole
+            .checked_mul(MINOR_UNITS_PER_MAJOR)
+            .and_then(|major| major.checked_add(minor))
+            .ok_or(ParseMoneyError::Overflow)?;
+
+        let rest = fraction.get(DECIMAL_PLACES..).unwrap_or("");
+        if let Some(first) = rest.bytes().next() {
+            let beyond_half = rest.bytes().skip(1).any(|byte| byte != b'0');
+            let round_up = match first {
+                b'6'..=b'9' => true,
+                b'5' => beyond_half || total % 2 == 1,
+                _ => false,
+            };
+            if round_up {
+                total = total.checked_add(1).ok_or(ParseMoneyError::Overflow)?;
+            }
+        }
+        Ok(Money(total))
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct MoneyVisitor;
+
+impl<'de> Visitor<'de> for MoneyVisitor {
+    type Value = Money;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a non-negative amount as a decimal string or number")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Money, E> {
+        Money::from_major(value).ok_or_else(|| E::custom(ParseMoneyError::Overflow))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Money, E> {
+        match u64::try_from(value) {
+            Ok(value) => self.visit_u64(value),
+            Err(_) => Err(E::custom(ParseMoneyError::Negative)),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Money, E> {
+        if !value.is_finite() {
+            return Err(E::custom(ParseMoneyError::Invalid(value.to_string())));
+        }
+        // Display prints the shortest decimal that round-trips, which is what
+        // the client most likely wrote.
+        self.visit_str(&value.to_string())
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Money, E> {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+/// Signed account balance in minor units.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Balance(i64);
+
+impl Balance {
+    pub const ZERO: Balance = Balance(0);
+    pub const MAX: Balance = Balance(i64::MAX);
+
+    pub const fn from_minor(minor_units: i64) -> Balance {
+        Balance(minor_units)
+    }
+
+    pub const fn minor_units(self) -> i64 {
+        self.0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// The balance as an amount, `None` when it is negative.
+    pub fn to_money(self) -> Option<Money> {
+        u64::try_from(self.0).ok().map(Money)
+    }
+
+    /// How far the balance is from zero, either way.
+    pub fn magnitude(self) -> Money {
+        Money(self.0.unsigned_abs())
+    }
+
+    pub fn checked_add(self, amount: Money) -> Option<Balance> {
+        i64::try_from(amount.0)
+            .ok()
+            .and_then(|amount| self.0.checked_add(amount))
+            .map(Balance)
+    }
+
+    pub fn checked_sub(self, amount: Money) -> Option<Balance> {
+        i64::try_from(amount.0)
+            .ok()
+            .and_then(|amount| self.0.checked_sub(amount))
+            .map(Balance)
+    }
+
+    /// Whether the balance is no further below zero than `overdraft_limit`.
+    pub fn is_within(self, overdraft_limit: Money) -> bool {
+        self.0 as i128 >= -(overdraft_limit.0 as i128)
+    }
+}
+
+impl TryFrom<Money> for Balance {
+    type Error = ParseMoneyError;
+
+    fn try_from(amount: Money) -> Result<Balance, ParseMoneyError> {
+        Balance::ZERO
+            .checked_add(amount)
+            .ok_or(ParseMoneyError::Overflow)
+    }
+}
+
+impl Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        write!(f, "{sign}{}", Money(self.0.unsigned_abs()))
+    }
+}
+
+impl FromStr for Balance {
+    type Err = ParseMoneyError;
+
+    fn from_str(balance: &str) -> Result<Balance, ParseMoneyError> {
+        let trimmed = balance.trim();
+        match trimmed.strip_prefix('-') {
+            Some(magnitude) => {
+                let magnitude: Money = magnitude.parse()?;
+                Balance::ZERO
+                    .checked_sub(magnitude)
+                    .ok_or(ParseMoneyError::Overflow)
+            }
+            None => Balance::try_from(trimmed.parse::<Money>()?),
+        }
+    }
+}
+
+impl Serialize for Balance {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Balance {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Balance, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Sum of balances or amounts in minor units, which may be more than a
+/// single balance can hold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Total(i128);
+
+impl Total {
+    pub fn add_balance(&mut self, balance: Balance) {
+        self.0 += i128::from(balance.0);
+    }
+
+    pub fn add_amount(&mut self, amount: Money) {
+        self.0 += i128::from(amount.0);
+    }
+
+    pub fn sub_amount(&mut self, amount: Money) {
+        self.0 -= i128::from(amount.0);
+    }
+
+    pub fn sub_total(&mut self, total: Total) {
+        self.0 -= total.0;
+    }
+
+    /// The total as a balance, `None` if it does not fit in one.
+    pub fn to_balance(self) -> Option<Balance> {
+        i64::try_from(self.0).ok().map(Balance)
+    }
+
+    /// The mean of `count` balances adding up to the total, rounded toward
+    /// zero. It lies between the smallest and the largest, so it fits.
+    pub fn average(self, count: usize) -> Balance {
+        Balance((self.0 / count.max(1) as i128) as i64)
+    }
+}
+
+impl Display for Total {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = u128::from(MINOR_UNITS_PER_MAJOR);
+        let total = self.0.unsigned_abs();
+        write!(f, "{sign}{}.{:02}", total / units, total % units)
+    }
+}
+
+impl Serialize for Total {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("'{0}' is not a three-letter currency code")]
+pub struct ParseCurrencyError(String);
+
+/// ISO 4217 style currency code, three uppercase ASCII letters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const EUR: Currency = Currency(*b"EUR");
+    pub const USD: Currency = Currency(*b"USD");
+
+    pub fn as_str(&self) -> &str {
+        // Only ever constructed from ASCII letters.
+        std::str::from_utf8(&self.0).unwrap()
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency::EUR
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    fn from_str(code: &str) -> Result<Currency, ParseCurrencyError> {
+        match <[u8; 3]>::try_from(code.as_bytes()) {
+            Ok(bytes) if bytes.iter().all(u8::is_ascii_alphabetic) => {
+                Ok(Currency(bytes.map(|byte| byte.to_ascii_uppercase())))
+            }
+            _ => Err(ParseCurrencyError(code.to_string())),
+        }
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Currency, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
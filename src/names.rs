@@ -0,0 +1,402 @@
+//! Checks on the names of new accounts, so that no account can pass for
+//! another by looking like its name.
+//!
+//! Names are brought to Unicode NFC first, so that `jožko` typed with a
+//! combining caron is the same name as with a precomposed `ž`. Only Latin,
+//! Greek and Cyrillic letters with the usual diacritics are composed here;
+//! other combining marks are refused rather than normalized, and so are
+//! other precomposed letters of those scripts, which NFC could have made
+//! of a letter and a mark. Then a name must be 1 to `MAX_LENGTH`
+//! characters of letters, digits, ASCII punctuation and single spaces
+//! between words. Invisible characters are refused, so that no two names
+//! differ only by them, as are characters that only look like ASCII ones,
+//! e.g. fullwidth or mathematical letters, and names mixing Latin, Greek
+//! and Cyrillic letters, which share look-alikes.
+//!
+//! A name written in one script can still look like one in another, e.g.
+//! Cyrillic `рау` like Latin `pay`, or `paypa1` like `paypal`. Such names
+//! have the same `skeleton`, and the bank refuses a name whose skeleton is
+//! that of another account's name.
+//!
+//! Lookups by name are normalized the same way. Accounts opened before
+//! these checks keep their names.
+
+use std::ops::RangeInclusive;
+
+use thiserror::Error;
+
+/// Longest name, in characters.
+pub const MAX_LENGTH: usize = 64;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InvalidNameError {
+    #[error("Account name is empty")]
+    Empty,
+    #[error("Account name is longer than {MAX_LENGTH} characters")]
+    TooLong,
+    #[error("Account name starts or ends with a space or has two in a row")]
+    Spacing,
+    #[error("Account name has the invisible character U+{0:04X}")]
+    Invisible(u32),
+    #[error("Account name has the combining mark U+{0:04X}, write the letter precomposed")]
+    Uncomposed(u32),
+    #[error("Account name has the character U+{0:04X}, which is not allowed")]
+    NotAllowed(u32),
+    #[error("Account name has the letter U+{0:04X}, which names are not normalized for")]
+    NotNormalized(u32),
+    #[error("Account name mixes Latin, Greek or Cyrillic letters")]
+    MixedScripts,
+    #[error("Account name looks like the name of account '{0}'")]
+    LooksLike(String),
+}
+
+/// Combining marks and the letters they compose with, in NFC.
+const COMPOSITIONS: [(char, &str, &str); 19] = [
+    ('\u{300}', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    (
+        '\u{301}',
+        "aceilnorsuyzACEILNORSUYZ",
+        "áćéíĺńóŕśúýźÁĆÉÍĹŃÓŔŚÚÝŹ",
+    ),
+    (
+        '\u{302}',
+        "aceghijosuwyACEGHIJOSUWY",
+        "âĉêĝĥîĵôŝûŵŷÂĈÊĜĤÎĴÔŜÛŴŶ",
+    ),
+    ('\u{303}', "ainouAINOU", "ãĩñõũÃĨÑÕŨ"),
+    ('\u{304}', "aeiouAEIOU", "āēīōūĀĒĪŌŪ"),
+    ('\u{306}', "aegiouAEGIOU", "ăĕğĭŏŭĂĔĞĬŎŬ"),
+    ('\u{307}', "cegzCEGIZ", "ċėġżĊĖĠİŻ"),
+    ('\u{308}', "aeiouyAEIOUY", "äëïöüÿÄËÏÖÜŸ"),
+    ('\u{30A}', "auAU", "åůÅŮ"),
+    ('\u{30B}', "ouOU", "őűŐŰ"),
+    ('\u{30C}', "cdelnrstzCDELNRSTZ", "čďěľňřšťžČĎĚĽŇŘŠŤŽ"),
+    ('\u{327}', "cgklnrstCGKLNRST", "çģķļņŗşţÇĢĶĻŅŖŞŢ"),
+    ('\u{328}', "aeiuAEIU", "ąęįųĄĘĮŲ"),
+    // Greek tonos and dialytika.
+    ('\u{301}', "αεηιουωΑΕΗΙΟΥΩ", "άέήίόύώΆΈΉΊΌΎΏ"),
+    ('\u{308}', "ιυΙΥ", "ϊϋΪΫ"),
+    // Cyrillic.
+    ('\u{300}', "еиЕИ", "ѐѝЀЍ"),
+    ('\u{301}', "гкГК", "ѓќЃЌ"),
+    ('\u{306}', "иуИУ", "йўЙЎ"),
+    ('\u{308}', "еіЕІ", "ёїЁЇ"),
+];
+
+/// Letters of the Latin, Greek and Cyrillic scripts beyond ASCII that NFC
+/// leaves as they are. Those scripts' other letters are accepted only as
+/// composed by `COMPOSITIONS`.
+const UNDECOMPOSED: [RangeInclusive<char>; 28] = [
+    'Æ'..='Æ',
+    'Ð'..='Ð',
+    'Ø'..='Ø',
+    'Þ'..='ß',
+    'æ'..='æ',
+    'ð'..='ð',
+    'ø'..='ø',
+    'þ'..='þ',
+    'Đ'..='đ',
+    'Ħ'..='ħ',
+    'ı'..='ı',
+    'ĸ'..='ĸ',
+    'Ł'..='ł',
+    'Ŋ'..='ŋ',
+    'Œ'..='œ',
+    'Ŧ'..='ŧ',
+    'Α'..='Ω',
+    'α'..='ω',
+    'Ђ'..='Ђ',
+    'Є'..='І',
+    'Ј'..='Ћ',
+    'Џ'..='И',
+    'К'..='и',
+    'к'..='я',
+    'ђ'..='ђ',
+    'є'..='і',
+    'ј'..='ћ',
+    'џ'..='џ',
+];
+
+const COMBINING: [RangeInclusive<char>; 5] = [
+    '\u{300}'..='\u{36F}',
+    '\u{1AB0}'..='\u{1AFF}',
+    '\u{1DC0}'..='\u{1DFF}',
+    '\u{20D0}'..='\u{20FF}',
+    '\u{FE20}'..='\u{FE2F}',
+];
+
+/// Format characters, fillers and selectors that show as nothing.
+const INVISIBLE: [RangeInclusive<char>; 14] = [
+    '\u{AD}'..='\u{AD}',
+    '\u{61C}'..='\u{61C}',
+    '\u{115F}'..='\u{1160}',
+    '\u{17B4}'..='\u{17B5}',
+    '\u{180B}'..='\u{180F}',
+    '\u{200B}'..='\u{200F}',
+    '\u{202A}'..='\u{202E}',
+    '\u{2060}'..='\u{206F}',
+    '\u{3164}'..='\u{3164}',
+    '\u{FE00}'..='\u{FE0F}',
+    '\u{FEFF}'..='\u{FEFF}',
+    '\u{FFA0}'..='\u{FFA0}',
+    '\u{1D173}'..='\u{1D17A}',
+    '\u{E0000}'..='\u{E0FFF}',
+];
+
+/// Letters and digits that NFC or NFKC would change, mostly look-alikes of
+/// ASCII ones.
+const LOOK_ALIKES: [RangeInclusive<char>; 17] = [
+    '\u{AA}'..='\u{AA}',
+    '\u{B2}'..='\u{B3}',
+    '\u{B9}'..='\u{BA}',
+    '\u{BC}'..='\u{BE}',
+    '\u{17F}'..='\u{17F}',
+    '\u{958}'..='\u{95F}',
+    '\u{1100}'..='\u{11FF}',
+    '\u{2070}'..='\u{209F}',
+    '\u{2100}'..='\u{218F}',
+    '\u{2460}'..='\u{24FF}',
+    '\u{3131}'..='\u{318E}',
+    '\u{A960}'..='\u{A97F}',
+    '\u{D7B0}'..='\u{D7FF}',
+    '\u{F900}'..='\u{FAFF}',
+    '\u{FF00}'..='\u{FFEF}',
+    '\u{1D400}'..='\u{1D7FF}',
+    '\u{2F800}'..='\u{2FA1F}',
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+fn script(c: char) -> Option<Script> {
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{C0}'..='\u{24F}' | '\u{1E00}'..='\u{1EFF}' => {
+            Some(Script::Latin)
+        }
+        '\u{370}'..='\u{3FF}' | '\u{1F00}'..='\u{1FFF}' => Some(Script::Greek),
+        '\u{400}'..='\u{52F}' | '\u{1C80}'..='\u{1C8F}' => Some(Script::Cyrillic),
+        _ => None,
+    }
+}
+
+/// Whether NFC could have made `c` of a letter and a mark that
+/// `COMPOSITIONS` do not compose.
+fn not_normalized(c: char) -> bool {
+    !c.is_ascii()
+        && script(c).is_some()
+        && !within(&UNDECOMPOSED, c)
+        && !COMPOSITIONS
+            .iter()
+            .any(|(_, _, composed)| composed.contains(c))
+}
+
+fn within(ranges: &[RangeInclusive<char>], c: char) -> bool {
+    ranges.iter().any(|range| range.contains(&c))
+}
+
+/// `name` with the Latin letters followed by a combining mark composed.
+pub fn normalize(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.chars() {
+        let composed = COMPOSITIONS
+            .iter()
+            .filter(|(mark, _, _)| *mark == c)
+            .find_map(|(_, bases, composed)| {
+                let base = normalized.chars().next_back()?;
+                let index = bases.chars().position(|b| b == base)?;
+                composed.chars().nth(index)
+            });
+        match composed {
+            Some(composed) => {
+                normalized.pop();
+                normalized.push(composed);
+            }
+            None => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// `name` normalized, if it is allowed for a new account.
+pub fn validate(name: &str) -> Result<String, InvalidNameError> {
+    let name = normalize(name);
+    let length = name.chars().count();
+    if length == 0 {
+        return Err(InvalidNameError::Empty);
+    }
+    if length > MAX_LENGTH {
+        return Err(InvalidNameError::TooLong);
+    }
+    if name.starts_with(' ') || name.ends_with(' ') || name.contains("  ") {
+        return Err(InvalidNameError::Spacing);
+    }
+    let mut first_script = None;
+    for c in name.chars() {
+        if c.is_control() || within(&INVISIBLE, c) {
+            return Err(InvalidNameError::Invisible(c.into()));
+        }
+        if within(&COMBINING, c) {
+            return Err(InvalidNameError::Uncomposed(c.into()));
+        }
+        let allowed = c == ' ' || c.is_ascii_punctuation() || c.is_alphanumeric();
+        if !allowed || within(&LOOK_ALIKES, c) {
+            return Err(InvalidNameError::NotAllowed(c.into()));
+        }
+        if not_normalized(c) {
+            return Err(InvalidNameError::NotNormalized(c.into()));
+        }
+        if let Some(script) = script(c).filter(|_| c.is_alphabetic()) {
+            if *first_script.get_or_insert(script) != script {
+                return Err(InvalidNameError::MixedScripts);
+            }
+        }
+    }
+    Ok(name)
+}
+
+/// Letters of Greek and Cyrillic, and ASCII characters, that look like
+/// others, and the one they are folded into.
+const CONFUSABLES: [(char, char); 47] = [
+    ('1', 'l'),
+    ('I', 'l'),
+    ('|', 'l'),
+    ('0', 'O'),
+    ('α', 'a'),
+    ('γ', 'y'),
+    ('ι', 'i'),
+    ('κ', 'k'),
+    ('ν', 'v'),
+    ('ο', 'o'),
+    ('ρ', 'p'),
+    ('υ', 'u'),
+    ('χ', 'x'),
+    ('Α', 'A'),
+    ('Β', 'B'),
+    ('Ε', 'E'),
+    ('Ζ', 'Z'),
+    ('Η', 'H'),
+    ('Ι', 'l'),
+    ('Κ', 'K'),
+    ('Μ', 'M'),
+    ('Ν', 'N'),
+    ('Ο', 'O'),
+    ('Ρ', 'P'),
+    ('Τ', 'T'),
+    ('Υ', 'Y'),
+    ('Χ', 'X'),
+    ('а', 'a'),
+    ('с', 'c'),
+    ('е', 'e'),
+    ('і', 'i'),
+    ('ј', 'j'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('ѕ', 's'),
+    ('у', 'y'),
+    ('х', 'x'),
+    ('А', 'A'),
+    ('В', 'B'),
+    ('С', 'C'),
+    ('Е', 'E'),
+    ('І', 'l'),
+    ('Ј', 'J'),
+    ('К', 'K'),
+    ('М', 'M'),
+    ('Н', 'H'),
+    ('О', 'O'),
+];
+
+/// What `name`, as normalized, looks like: its letters that look like
+/// others folded into those, after UTS #39 but for the common cases only.
+/// Names that look alike have the same skeleton.
+pub fn skeleton(name: &str) -> String {
+    let mut skeleton = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            'm' => skeleton.push_str("rn"),
+            'Р' => skeleton.push('P'),
+            'Т' => skeleton.push('T'),
+            'Х' => skeleton.push('X'),
+            _ => skeleton.push(
+                CONFUSABLES
+                    .iter()
+                    .find(|(confusable, _)| *confusable == c)
+                    .map_or(c, |(_, folded)| *folded),
+            ),
+        }
+    }
+    skeleton
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn look_alike_names_are_refused() {
+        assert_eq!(validate("jozko, ml.").unwrap(), "jozko, ml.");
+        assert_eq!(validate("jo\u{17E}ko").unwrap(), "jožko");
+        assert_eq!(validate("joz\u{30C}ko").unwrap(), "jožko");
+        assert_eq!(validate("Ζωή").unwrap(), "Ζωή");
+        assert_eq!(validate("東京 1").unwrap(), "東京 1");
+
+        assert_eq!(validate(""), Err(InvalidNameError::Empty));
+        assert_eq!(validate(&"a".repeat(65)), Err(InvalidNameError::TooLong));
+        assert_eq!(validate("bob "), Err(InvalidNameError::Spacing));
+        assert_eq!(
+            validate("bo\u{200B}b"),
+            Err(InvalidNameError::Invisible(0x200B))
+        );
+        assert_eq!(validate("bob\n"), Err(InvalidNameError::Invisible(0xA)));
+        assert_eq!(
+            validate("q\u{30C}"),
+            Err(InvalidNameError::Uncomposed(0x30C))
+        );
+        assert_eq!(validate("ｂob"), Err(InvalidNameError::NotAllowed(0xFF42)));
+        assert_eq!(
+            validate("bob\u{212A}"),
+            Err(InvalidNameError::NotAllowed(0x212A))
+        );
+        assert_eq!(
+            validate("b\u{2024}b"),
+            Err(InvalidNameError::NotAllowed(0x2024))
+        );
+        // With a Cyrillic "о".
+        assert_eq!(validate("b\u{43E}b"), Err(InvalidNameError::MixedScripts));
+        // "ǎ" and "ṡ" precomposed, which NFC would make of "a" and "s" and a
+        // mark not composed here.
+        assert_eq!(
+            validate("\u{1CE}"),
+            Err(InvalidNameError::NotNormalized(0x1CE))
+        );
+        assert_eq!(
+            validate("\u{1E61}"),
+            Err(InvalidNameError::NotNormalized(0x1E61))
+        );
+    }
+
+    #[test]
+    fn greek_and_cyrillic_letters_are_composed() {
+        assert_eq!(validate("\u{3B7}\u{301}").unwrap(), "ή");
+        assert_eq!(validate("\u{438}\u{306}").unwrap(), "й");
+        assert_eq!(validate("Зое").unwrap(), "Зое");
+        assert_eq!(validate("Ђорђе").unwrap(), "Ђорђе");
+    }
+
+    #[test]
+    fn look_alike_names_have_the_same_skeleton() {
+        assert_eq!(skeleton("paypal"), skeleton("paypa1"));
+        assert_eq!(skeleton("paypal"), skeleton("paypaI"));
+        assert_eq!(skeleton("modem"), skeleton("rnodern"));
+        assert_eq!(skeleton("BOB"), skeleton("B0B"));
+        // Cyrillic, and Greek.
+        assert_eq!(skeleton("pay"), skeleton("рау"));
+        assert_eq!(skeleton("KOT"), skeleton("ΚΟΤ"));
+        assert_ne!(skeleton("bob"), skeleton("Bob"));
+        assert_ne!(skeleton("jozko"), skeleton("jožko"));
+    }
+}
@@ -0,0 +1,708 @@
+//! Encrypted sessions on the server socket, so that other users of the host
+//! who can reach it can neither read nor forge requests and replies. Each
+//! client runs the Noise handshake `Noise_XX_25519_ChaChaPoly_SHA256`, see
+//! noiseprotocol.org, with the server first, with the prologue `bank`.
+//!
+//! Handshake messages go one per datagram, prefixed by their number, 1 to
+//! 3. The server's static key is `noise.secret_key`, whose public key the
+//! clients check it against; `bank noise-key` prints a new pair. Clients
+//! whose static keys are not in `noise.client_keys` are turned away, unless
+//! the list is empty.
+//!
+//! After the handshake each datagram of the protocol, either way, goes as
+//! the byte 4, the nonce as 8 little-endian bytes, and the datagram sealed
+//! under that nonce and the session key for its direction. Nonces count up
+//! from 0 and a datagram whose nonce is not above the last one is dropped,
+//! so none can be sent again. Sessions are kept, in memory, by the client's
+//! socket path. A handshake under way is kept apart from the session, which
+//! it replaces only once it completed, so that handshake messages from
+//! whoever can send from the client's address do not end the session.
+//! Plain datagrams are answered with "426" in the clear,
+//! unless `noise.allow_plaintext`, and so are encrypted ones without a
+//! session, e.g. after a restart, for the client to handshake again.
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::crypto::chacha20poly1305::{self, Key, Nonce, TAG_LENGTH};
+use crate::crypto::x25519::{self, PublicKey, SecretKey, KEY_LENGTH};
+use crate::crypto::{self, hmac_sha256, Digest, HmacSha256, Sha256};
+use crate::socket::PeerCredentials;
+use crate::transport::Transport;
+
+const PROTOCOL_NAME: &[u8; 32] = b"Noise_XX_25519_ChaChaPoly_SHA256";
+const PROLOGUE: &[u8] = b"bank";
+
+/// First byte of an encrypted datagram, after those of the handshake.
+const TRANSPORT: u8 = 4;
+const NONCE_LENGTH: usize = 8;
+/// What encrypting adds to a datagram.
+const OVERHEAD: usize = 1 + NONCE_LENGTH + TAG_LENGTH;
+
+/// Sessions kept at most, the least recently used go first.
+const MAX_SESSIONS: usize = 1024;
+
+#[derive(Error, Debug)]
+pub enum NoiseError {
+    #[error("Invalid Noise key '{0}', expected 32 bytes hex-encoded")]
+    InvalidKey(String),
+    #[error("Malformed handshake message")]
+    Malformed,
+    #[error("Handshake message out of order")]
+    OutOfOrder,
+    #[error("Handshake message failed to decrypt")]
+    Decrypt,
+    #[error("Client key {0} is not let in")]
+    UnknownClient(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoiseConfig {
+    /// Hex-encoded X25519 secret key of the server.
+    pub secret_key: String,
+    /// Hex-encoded public keys of the clients let in, any if empty.
+    #[serde(default)]
+    pub client_keys: Vec<String>,
+    /// Serve clients that do not encrypt too.
+    #[serde(default)]
+    pub allow_plaintext: bool,
+}
+
+fn parse_key(hex: &str) -> Result<[u8; KEY_LENGTH], NoiseError> {
+    crypto::from_hex(hex)
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| NoiseError::InvalidKey(hex.to_string()))
+}
+
+/// A new secret key.
+pub fn generate_key() -> io::Result<SecretKey> {
+    Ok(crypto::random_bytes(KEY_LENGTH)?
+        .try_into()
+        .expect("key length"))
+}
+
+/// Encrypts one way with a key and a counter.
+#[derive(Clone)]
+struct CipherState {
+    key: Key,
+    nonce: u64,
+}
+
+impl CipherState {
+    fn new(key: Key) -> CipherState {
+        CipherState { key, nonce: 0 }
+    }
+
+    /// ChaCha20-Poly1305 nonces are 4 zero bytes and the counter.
+    fn nonce(counter: u64) -> Nonce {
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&counter.to_le_bytes());
+        nonce
+    }
+
+    fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let sealed = chacha20poly1305::seal(&self.key, &Self::nonce(self.nonce), ad, plaintext);
+        self.nonce += 1;
+        sealed
+    }
+
+    /// Decrypt what was sealed under `nonce`, which must not be below the
+    /// counter, and count on from it.
+    fn decrypt_at(&mut self, nonce: u64, ad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        if nonce < self.nonce {
+            return None;
+        }
+        let plaintext = chacha20poly1305::open(&self.key, &Self::nonce(nonce), ad, ciphertext)?;
+        self.nonce = nonce + 1;
+        Some(plaintext)
+    }
+}
+
+/// HKDF with HMAC-SHA256, for two outputs.
+fn hkdf(chaining_key: &Digest, input: &[u8]) -> (Digest, Digest) {
+    let temporary = hmac_sha256(chaining_key, input);
+    let first = hmac_sha256(&temporary, &[1]);
+    let mut mac = HmacSha256::new(&temporary);
+    mac.update(&first);
+    mac.update(&[2]);
+    (first, mac.finalize())
+}
+
+struct SymmetricState {
+    chaining_key: Digest,
+    hash: Digest,
+    cipher: Option<CipherState>,
+}
+
+impl SymmetricState {
+    fn new() -> SymmetricState {
+        let mut state = SymmetricState {
+            chaining_key: *PROTOCOL_NAME,
+            hash: *PROTOCOL_NAME,
+            cipher: None,
+        };
+        state.mix_hash(PROLOGUE);
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.hash);
+        hasher.update(data);
+        self.hash = hasher.finalize();
+    }
+
+    fn mix_key(&mut self, input: &[u8]) {
+        let (chaining_key, key) = hkdf(&self.chaining_key, input);
+        self.chaining_key = chaining_key;
+        self.cipher = Some(CipherState::new(key));
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let ciphertext = match &mut self.cipher {
+            Some(cipher) => cipher.encrypt(&self.hash, plaintext),
+            None => plaintext.to_vec(),
+        };
+        self.mix_hash(&ciphertext);
+        ciphertext
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        let plaintext = match &mut self.cipher {
+            Some(cipher) => cipher
+                .decrypt_at(cipher.nonce, &self.hash, ciphertext)
+                .ok_or(NoiseError::Decrypt)?,
+            None => ciphertext.to_vec(),
+        };
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    /// The cipher states from the initiator and from the responder.
+    fn split(&self) -> (CipherState, CipherState) {
+        let (first, second) = hkdf(&self.chaining_key, &[]);
+        (CipherState::new(first), CipherState::new(second))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    E,
+    S,
+    Ee,
+    Es,
+    Se,
+}
+
+/// The XX pattern: -> e; <- e, ee, s, es; -> s, se.
+const MESSAGES: [&[Token]; 3] = [
+    &[Token::E],
+    &[Token::E, Token::Ee, Token::S, Token::Es],
+    &[Token::S, Token::Se],
+];
+
+struct Handshake {
+    initiator: bool,
+    symmetric: SymmetricState,
+    s: SecretKey,
+    e: Option<SecretKey>,
+    rs: Option<PublicKey>,
+    re: Option<PublicKey>,
+    /// Messages written or read so far.
+    messages: usize,
+}
+
+impl Handshake {
+    fn new(initiator: bool, s: SecretKey) -> Handshake {
+        Handshake {
+            initiator,
+            symmetric: SymmetricState::new(),
+            s,
+            e: None,
+            rs: None,
+            re: None,
+            messages: 0,
+        }
+    }
+
+    fn write_message(&mut self, payload: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        let mut message = Vec::new();
+        for &token in MESSAGES[self.messages] {
+            match token {
+                Token::E => {
+                    let e = generate_key()?;
+                    let public = x25519::public_key(&e);
+                    self.symmetric.mix_hash(&public);
+                    message.extend_from_slice(&public);
+                    self.e = Some(e);
+                }
+                Token::S => {
+                    let public = x25519::public_key(&self.s);
+                    message.extend(self.symmetric.encrypt_and_hash(&public));
+                }
+                _ => self.mix_dh(token)?,
+            }
+        }
+        message.extend(self.symmetric.encrypt_and_hash(payload));
+        self.messages += 1;
+        Ok(message)
+    }
+
+    fn read_message(&mut self, mut message: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        let mut take = |length: usize| {
+            if message.len() < length {
+                return Err(NoiseError::Malformed);
+            }
+            let (taken, rest) = message.split_at(length);
+            message = rest;
+            Ok(taken)
+        };
+        for &token in MESSAGES[self.messages] {
+            match token {
+                Token::E => {
+                    let re = take(KEY_LENGTH)?;
+                    self.symmetric.mix_hash(re);
+                    self.re = Some(re.try_into().unwrap());
+                }
+                Token::S => {
+                    let tag = if self.symmetric.cipher.is_some() {
+                        TAG_LENGTH
+                    } else {
+                        0
+                    };
+                    let rs = self.symmetric.decrypt_and_hash(take(KEY_LENGTH + tag)?)?;
+                    self.rs = Some(rs.try_into().unwrap());
+                }
+                _ => self.mix_dh(token)?,
+            }
+        }
+        let payload = self.symmetric.decrypt_and_hash(message)?;
+        self.messages += 1;
+        Ok(payload)
+    }
+
+    fn mix_dh(&mut self, token: Token) -> Result<(), NoiseError> {
+        let (secret, public) = match (token, self.initiator) {
+            (Token::Ee, _) => (self.e, self.re),
+            (Token::Es, true) | (Token::Se, false) => (self.e, self.rs),
+            (Token::Es, false) | (Token::Se, true) => (Some(self.s), self.re),
+            (Token::E | Token::S, _) => unreachable!("not a Diffie-Hellman token"),
+        };
+        let (Some(secret), Some(public)) = (secret, public) else {
+            return Err(NoiseError::Malformed);
+        };
+        self.symmetric.mix_key(&x25519::x25519(&secret, &public));
+        Ok(())
+    }
+
+    /// The cipher states to send and to receive with, once all messages
+    /// are through.
+    fn split(&self) -> (CipherState, CipherState) {
+        let (initiator, responder) = self.symmetric.split();
+        if self.initiator {
+            (initiator, responder)
+        } else {
+            (responder, initiator)
+        }
+    }
+}
+
+struct Session {
+    send: CipherState,
+    receive: CipherState,
+}
+
+struct Entry<T> {
+    used: Instant,
+    value: T,
+}
+
+/// Insert `value` for `path`, making room by dropping the least recently
+/// used entry if there are `MAX_SESSIONS`.
+fn insert<T>(entries: &mut HashMap<PathBuf, Entry<T>>, path: &Path, value: T) {
+    if entries.len() >= MAX_SESSIONS && !entries.contains_key(path) {
+        let oldest = entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(path, _)| path.clone());
+        if let Some(oldest) = oldest {
+            entries.remove(&oldest);
+        }
+    }
+    let used = Instant::now();
+    entries.insert(path.to_owned(), Entry { used, value });
+}
+
+/// Serves `transport` to clients in Noise sessions.
+pub struct NoiseTransport {
+    transport: Arc<dyn Transport>,
+    secret_key: SecretKey,
+    client_keys: Vec<PublicKey>,
+    allow_plaintext: bool,
+    sessions: Mutex<HashMap<PathBuf, Entry<Arc<Mutex<Session>>>>>,
+    /// Handshakes after the first message, by client.
+    handshakes: Mutex<HashMap<PathBuf, Entry<Box<Handshake>>>>,
+}
+
+impl Debug for NoiseTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseTransport")
+            .field("transport", &self.transport)
+            .field("allow_plaintext", &self.allow_plaintext)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NoiseTransport {
+    pub fn new(
+        transport: Arc<dyn Transport>,
+        config: &NoiseConfig,
+    ) -> Result<NoiseTransport, NoiseError> {
+        let secret_key = parse_key(&config.secret_key)?;
+        let client_keys = config
+            .client_keys
+            .iter()
+            .map(|key| parse_key(key))
+            .collect::<Result<_, _>>()?;
+        info!(
+            "Serving Noise sessions with the public key {}",
+            crypto::to_hex(&x25519::public_key(&secret_key))
+        );
+        Ok(NoiseTransport {
+            transport,
+            secret_key,
+            client_keys,
+            allow_plaintext: config.allow_plaintext,
+            sessions: Mutex::new(HashMap::new()),
+            handshakes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn session(&self, path: &Path) -> Option<Arc<Mutex<Session>>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let entry = sessions.get_mut(path)?;
+        entry.used = Instant::now();
+        Some(Arc::clone(&entry.value))
+    }
+
+    /// Tell a client to handshake first.
+    fn refuse(&self, sender: Option<&Path>) {
+        let Some(sender) = sender else {
+            return;
+        };
+        if let Err(e) = self.transport.send_to_nowait(b"426", sender) {
+            debug!("Unable to refuse {}: {e}", sender.display());
+        }
+    }
+
+    /// What `datagram`, `length` bytes long before it was cut off, holds
+    /// for the server, put in `buffer`: its length, or `None` if it was
+    /// for the session itself or dropped.
+    fn receive(
+        &self,
+        datagram: &[u8],
+        length: usize,
+        sender: Option<&Path>,
+        buffer: &mut [u8],
+    ) -> Option<usize> {
+        match datagram.first() {
+            Some(&number @ 1..=3) => {
+                let sender = sender?;
+                if let Err(e) = self.handshake(number, &datagram[1..], sender) {
+                    warn!("Noise handshake with {} failed: {e}", sender.display());
+                }
+                None
+            }
+            Some(&TRANSPORT) => self.decrypt(datagram, length, sender, buffer),
+            _ if self.allow_plaintext => {
+                let copied = datagram.len().min(buffer.len());
+                buffer[..copied].copy_from_slice(&datagram[..copied]);
+                Some(length)
+            }
+            _ => {
+                debug!("Refused a plain datagram from {sender:?}");
+                self.refuse(sender);
+                None
+            }
+        }
+    }
+
+    fn handshake(&self, number: u8, message: &[u8], sender: &Path) -> Result<(), NoiseError> {
+        match number {
+            1 => {
+                let mut handshake = Handshake::new(false, self.secret_key);
+                handshake.read_message(message)?;
+                let mut reply = vec![2];
+                reply.extend(handshake.write_message(&[])?);
+                let mut handshakes = self.handshakes.lock().unwrap();
+                insert(&mut handshakes, sender, Box::new(handshake));
+                drop(handshakes);
+                self.transport.send_to_nowait(&reply, sender)?;
+            }
+            3 => {
+                // Tried once, whatever the outcome.
+                let mut handshake = self
+                    .handshakes
+                    .lock()
+                    .unwrap()
+                    .remove(sender)
+                    .ok_or(NoiseError::OutOfOrder)?
+                    .value;
+                handshake.read_message(message)?;
+                let client = handshake.rs.expect("sent with the third message");
+                if !self.client_keys.is_empty() && !self.client_keys.contains(&client) {
+                    return Err(NoiseError::UnknownClient(crypto::to_hex(&client)));
+                }
+                let (send, receive) = handshake.split();
+                let session = Arc::new(Mutex::new(Session { send, receive }));
+                insert(&mut self.sessions.lock().unwrap(), sender, session);
+                debug!(
+                    "Established a Noise session with {} for key {}",
+                    sender.display(),
+                    crypto::to_hex(&client)
+                );
+            }
+            _ => return Err(NoiseError::OutOfOrder),
+        }
+        Ok(())
+    }
+
+    fn decrypt(
+        &self,
+        datagram: &[u8],
+        length: usize,
+        sender: Option<&Path>,
+        buffer: &mut [u8],
+    ) -> Option<usize> {
+        let Some(session) = sender.and_then(|sender| self.session(sender)) else {
+            self.refuse(sender);
+            return None;
+        };
+        let Session { receive, .. } = &mut *session.lock().unwrap();
+        // Too long for the server either way, it says so.
+        if length > datagram.len() {
+            return Some(length - OVERHEAD);
+        }
+        let nonce = datagram
+            .get(1..1 + NONCE_LENGTH)
+            .map(|nonce| u64::from_le_bytes(nonce.try_into().unwrap()))?;
+        match receive.decrypt_at(nonce, &[], &datagram[1 + NONCE_LENGTH..]) {
+            Some(plaintext) => {
+                buffer[..plaintext.len()].copy_from_slice(&plaintext);
+                Some(plaintext.len())
+            }
+            None => {
+                warn!("Dropped a datagram from {sender:?} that failed to decrypt or was replayed");
+                None
+            }
+        }
+    }
+
+    fn send(&self, message: &[u8], path: &Path, nowait: bool) -> io::Result<()> {
+        let send = |datagram: &[u8]| match nowait {
+            true => self.transport.send_to_nowait(datagram, path),
+            false => self.transport.send_to(datagram, path),
+        };
+        if let Some(session) = self.session(path) {
+            let Session { send: cipher, .. } = &mut *session.lock().unwrap();
+            let mut datagram = Vec::with_capacity(message.len() + OVERHEAD);
+            datagram.push(TRANSPORT);
+            datagram.extend_from_slice(&cipher.nonce.to_le_bytes());
+            datagram.extend(cipher.encrypt(&[], message));
+            // Under the session's lock, so that nonces go out in order.
+            return send(&datagram);
+        }
+        if self.allow_plaintext {
+            return send(message);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("no Noise session with {}", path.display()),
+        ))
+    }
+}
+
+impl Transport for NoiseTransport {
+    fn recv_from(
+        &self,
+        buffer: &mut [u8],
+    ) -> io::Result<(usize, Option<PathBuf>, Option<PeerCredentials>)> {
+        let mut datagram = vec![0; buffer.len() + OVERHEAD];
+        loop {
+            let (length, sender, credentials) = self.transport.recv_from(&mut datagram)?;
+            let received = &datagram[..length.min(datagram.len())];
+            if let Some(length) = self.receive(received, length, sender.as_deref(), buffer) {
+                return Ok((length, sender, credentials));
+            }
+        }
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.recv_from(buffer).map(|(length, _, _)| length)
+    }
+
+    fn send_to(&self, message: &[u8], path: &Path) -> io::Result<()> {
+        self.send(message, path, false)
+    }
+
+    fn send_to_nowait(&self, message: &[u8], path: &Path) -> io::Result<()> {
+        self.send(message, path, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MockTransport;
+
+    /// The datagram the server sends `client` next, after `seen` of them.
+    fn reply(transport: &MockTransport, client: &str, seen: usize) -> Vec<u8> {
+        transport.sent_to(client)[seen].clone()
+    }
+
+    #[test]
+    fn clients_are_served_only_in_their_session() {
+        let server_key = generate_key().unwrap();
+        let client_key = generate_key().unwrap();
+        let mock = Arc::new(MockTransport::default());
+        let config = NoiseConfig {
+            secret_key: crypto::to_hex(&server_key),
+            client_keys: vec![crypto::to_hex(&x25519::public_key(&client_key))],
+            allow_plaintext: false,
+        };
+        let noise = NoiseTransport::new(mock.clone(), &config).unwrap();
+        let mut buffer = [0; 64];
+        let mut deliver = |datagram: &[u8], client: &str| {
+            noise
+                .receive(
+                    datagram,
+                    datagram.len(),
+                    Some(Path::new(client)),
+                    &mut buffer,
+                )
+                .map(|length| buffer[..length].to_vec())
+        };
+
+        let mut client = Handshake::new(true, client_key);
+        let first = [&[1], &client.write_message(&[]).unwrap()[..]].concat();
+        assert_eq!(deliver(&first, "/client"), None);
+        let second = reply(&mock, "/client", 0);
+        assert_eq!(second[0], 2);
+        client.read_message(&second[1..]).unwrap();
+        assert_eq!(client.rs, Some(x25519::public_key(&server_key)));
+        let third = [&[3], &client.write_message(&[]).unwrap()[..]].concat();
+        assert_eq!(deliver(&third, "/client"), None);
+        let (mut send, mut receive) = client.split();
+
+        let request = [
+            &[TRANSPORT],
+            &0u64.to_le_bytes()[..],
+            &send.encrypt(&[], b"k"),
+        ]
+        .concat();
+        assert_eq!(deliver(&request, "/client").unwrap(), b"k");
+        // Neither sent again nor read by anyone else.
+        assert_eq!(deliver(&request, "/client"), None);
+        assert_eq!(deliver(&request, "/other"), None);
+        assert_eq!(mock.replies("/other"), ["426"]);
+        assert_eq!(deliver(b"k", "/client"), None);
+        assert_eq!(mock.replies("/client")[1], "426");
+
+        noise.send_to(b"200", Path::new("/client")).unwrap();
+        let sealed = reply(&mock, "/client", 2);
+        assert_eq!(sealed[0], TRANSPORT);
+        let nonce = u64::from_le_bytes(sealed[1..9].try_into().unwrap());
+        assert_eq!(
+            receive.decrypt_at(nonce, &[], &sealed[9..]).unwrap(),
+            b"200"
+        );
+        assert!(noise.send_to(b"200", Path::new("/other")).is_err());
+
+        // A client with another key does not get through the handshake.
+        let mut stranger = Handshake::new(true, generate_key().unwrap());
+        let first = [&[1], &stranger.write_message(&[]).unwrap()[..]].concat();
+        deliver(&first, "/stranger");
+        stranger
+            .read_message(&reply(&mock, "/stranger", 0)[1..])
+            .unwrap();
+        let third = [&[3], &stranger.write_message(&[]).unwrap()[..]].concat();
+        deliver(&third, "/stranger");
+        let (mut send, _) = stranger.split();
+        let request = [
+            &[TRANSPORT],
+            &0u64.to_le_bytes()[..],
+            &send.encrypt(&[], b"k"),
+        ]
+        .concat();
+        assert_eq!(deliver(&request, "/stranger"), None);
+        assert_eq!(mock.replies("/stranger")[1], "426");
+    }
+
+    #[test]
+    fn handshake_messages_do_not_end_a_session() {
+        let server_key = generate_key().unwrap();
+        let mock = Arc::new(MockTransport::default());
+        let config = NoiseConfig {
+            secret_key: crypto::to_hex(&server_key),
+            client_keys: Vec::new(),
+            allow_plaintext: false,
+        };
+        let noise = NoiseTransport::new(mock.clone(), &config).unwrap();
+        let mut buffer = [0; 64];
+        let mut deliver = |datagram: &[u8]| {
+            noise
+                .receive(
+                    datagram,
+                    datagram.len(),
+                    Some(Path::new("/client")),
+                    &mut buffer,
+                )
+                .map(|length| buffer[..length].to_vec())
+        };
+        let sealed = |send: &mut CipherState, nonce: u64, message: &[u8]| {
+            [
+                &[TRANSPORT],
+                &nonce.to_le_bytes()[..],
+                &send.encrypt(&[], message),
+            ]
+            .concat()
+        };
+
+        let mut client = Handshake::new(true, generate_key().unwrap());
+        deliver(&[&[1], &client.write_message(&[]).unwrap()[..]].concat());
+        client
+            .read_message(&reply(&mock, "/client", 0)[1..])
+            .unwrap();
+        deliver(&[&[3], &client.write_message(&[]).unwrap()[..]].concat());
+        let (mut send, _) = client.split();
+        assert_eq!(deliver(&sealed(&mut send, 0, b"k")).unwrap(), b"k");
+
+        // Someone else starts a handshake from the client's address, and
+        // sends a third message that does not complete it.
+        let mut forger = Handshake::new(true, generate_key().unwrap());
+        deliver(&[&[1], &forger.write_message(&[]).unwrap()[..]].concat());
+        deliver(&[3, 0, 0, 0]);
+        assert_eq!(deliver(&sealed(&mut send, 1, b"t")).unwrap(), b"t");
+
+        // A handshake that completes replaces the session.
+        let mut client = Handshake::new(true, generate_key().unwrap());
+        deliver(&[&[1], &client.write_message(&[]).unwrap()[..]].concat());
+        let second = reply(&mock, "/client", mock.sent_to("/client").len() - 1);
+        client.read_message(&second[1..]).unwrap();
+        deliver(&[&[3], &client.write_message(&[]).unwrap()[..]].concat());
+        let (mut renewed, _) = client.split();
+        assert_eq!(deliver(&sealed(&mut send, 2, b"h")), None);
+        assert_eq!(deliver(&sealed(&mut renewed, 0, b"h")).unwrap(), b"h");
+    }
+}
@@ -0,0 +1,165 @@
+//! Joint accounts: identities recorded as owners of an account may act on
+//! it like customers configured with it in `identities`. An account can
+//! require transfers out of it to be approved by several owners; the owner
+//! who sends the transfer approves it, and it is made once enough others
+//! have approved it too.
+//!
+//! Sent as the JSON payload of the `o` instruction, e.g.
+//! `{"op": "add_owner", "account": "patko", "owner": "jozko"}`,
+//! `{"op": "set_approvals_required", "account": "patko", "required": 2}`
+//! or `{"op": "approve", "approval_id": 1}`. Owners are managed by whoever
+//! may transfer from the account.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{AccountId, AccountName, AccountRef, TxInfo};
+
+pub fn default_approvals_required() -> u32 {
+    1
+}
+
+pub fn is_default_approvals_required(required: &u32) -> bool {
+    *required == default_approvals_required()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct ApprovalId(u64);
+
+impl Display for ApprovalId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "approval{}", self.0)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum OwnershipError {
+    #[error("Account {account_name} needs between 1 and {owners} approvals, not {required}")]
+    InvalidApprovals {
+        account_name: AccountName,
+        required: u32,
+        owners: usize,
+    },
+    #[error("{identity} is not an owner of account {account_name}")]
+    NotAnOwner {
+        account_name: AccountName,
+        identity: String,
+    },
+    #[error("{identity} already approved {approval_id}")]
+    AlreadyApproved {
+        approval_id: ApprovalId,
+        identity: String,
+    },
+    #[error("Approval {0} not found")]
+    ApprovalNotFound(ApprovalId),
+    #[error("Transfers from account {0} need approvals by authenticated owners")]
+    IdentityRequired(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum OwnersCommand {
+    AddOwner {
+        account: AccountRef,
+        owner: String,
+    },
+    RemoveOwner {
+        account: AccountRef,
+        owner: String,
+    },
+    /// Owners that must approve each transfer out of the account, 1 to let
+    /// any owner transfer alone.
+    SetApprovalsRequired {
+        account: AccountRef,
+        required: u32,
+    },
+    /// Reply with the owners of an account.
+    List {
+        account: AccountRef,
+    },
+    /// Reply with the transfers out of an account waiting for approvals.
+    Pending {
+        account: AccountRef,
+    },
+    Approve {
+        approval_id: ApprovalId,
+    },
+    /// Drop a transfer waiting for approvals.
+    Reject {
+        approval_id: ApprovalId,
+    },
+}
+
+impl OwnersCommand {
+    /// The account the command acts on, unless it acts on an approval.
+    pub fn account(&self) -> Option<&AccountRef> {
+        match self {
+            OwnersCommand::AddOwner { account, .. }
+            | OwnersCommand::RemoveOwner { account, .. }
+            | OwnersCommand::SetApprovalsRequired { account, .. }
+            | OwnersCommand::List { account }
+            | OwnersCommand::Pending { account } => Some(account),
+            OwnersCommand::Approve { .. } | OwnersCommand::Reject { .. } => None,
+        }
+    }
+}
+
+/// Reply to `list`.
+#[derive(Debug, Serialize)]
+pub struct OwnersInfo<'a> {
+    pub account: AccountId,
+    pub owners: &'a BTreeSet<String>,
+    pub approvals_required: u32,
+}
+
+/// A transfer waiting for the approvals of the account's owners.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApprovalRequest {
+    pub approval_id: ApprovalId,
+    pub account: AccountId,
+    /// The transfer to make, with both accounts given by ID and the PIN,
+    /// already checked, removed.
+    pub transfer: TxInfo,
+    /// Owners who approved so far, the sender first among them.
+    pub approved_by: BTreeSet<String>,
+    pub approvals_required: u32,
+    /// Seconds since the Unix epoch.
+    pub requested_at: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Approvals {
+    requests: BTreeMap<ApprovalId, ApprovalRequest>,
+    last_id: u64,
+}
+
+impl Approvals {
+    pub fn next_id(&self) -> ApprovalId {
+        ApprovalId(self.last_id + 1)
+    }
+
+    pub fn insert(&mut self, request: ApprovalRequest) {
+        self.last_id = self.last_id.max(request.approval_id.0);
+        self.requests.insert(request.approval_id, request);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ApprovalRequest> {
+        self.requests.values()
+    }
+
+    pub fn get(&self, id: ApprovalId) -> Option<&ApprovalRequest> {
+        self.requests.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: ApprovalId) -> Option<&mut ApprovalRequest> {
+        self.requests.get_mut(&id)
+    }
+
+    pub fn remove(&mut self, id: ApprovalId) -> Option<ApprovalRequest> {
+        self.requests.remove(&id)
+    }
+}
@@ -0,0 +1,227 @@
+//! Payment files in the ISO 20022 pain.001 format, customer credit transfer
+//! initiation, as banking software and ERP systems export them. Such a file
+//! is applied as one batch, see `Bank::import_payments`.
+//!
+//! Only the part of the format that fits the bank is read. Accounts are
+//! given by name in `Othr/Id`, IBANs are not supported. Of each transfer
+//! these are used:
+//!
+//! - `PmtInf/DbtrAcct/Id/Othr/Id`: the sending account.
+//! - `CdtTrfTxInf/CdtrAcct/Id/Othr/Id`: the receiving account.
+//! - `CdtTrfTxInf/Amt/InstdAmt` and its `Ccy`.
+//! - `CdtTrfTxInf/PmtId/EndToEndId`: kept as the external reference unless
+//!   it is `NOTPROVIDED`.
+//! - `CdtTrfTxInf/RmtInf/Ustrd`: kept as the memo.
+//!
+//! `GrpHdr/NbOfTxs` and, if given, `GrpHdr/CtrlSum` must match the
+//! transfers.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::money::{Currency, Money, ParseCurrencyError, ParseMoneyError, Total};
+use crate::xml::{self, Element, XmlError};
+
+const NOT_PROVIDED: &str = "NOTPROVIDED";
+
+#[derive(Error, Debug)]
+pub enum PaymentFileError {
+    #[error("Unable to read payment file {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    Xml(#[from] XmlError),
+    #[error("Payment file is not a pain.001 credit transfer initiation")]
+    NotPain001,
+    #[error("Payment file lacks {0}")]
+    Missing(&'static str),
+    #[error("Invalid amount in payment file")]
+    Amount(#[from] ParseMoneyError),
+    #[error("Invalid currency in payment file")]
+    Currency(#[from] ParseCurrencyError),
+    #[error("Payment file declares {declared} transfers but holds {found}")]
+    NumberOfTransactions { declared: String, found: usize },
+    #[error("Payment file declares a control sum of {declared} but its amounts add up to {found}")]
+    ControlSum { declared: String, found: Total },
+}
+
+/// A transfer of the file, with accounts by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreditTransfer {
+    pub debtor_account: String,
+    pub creditor_account: String,
+    pub amount: Money,
+    pub currency: Currency,
+    pub end_to_end_id: Option<String>,
+    pub remittance_information: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentFile {
+    /// `GrpHdr/MsgId`, unique per file.
+    pub message_id: String,
+    pub transfers: Vec<CreditTransfer>,
+}
+
+pub fn read(path: &Path) -> Result<PaymentFile, PaymentFileError> {
+    let text = fs::read_to_string(path).map_err(|source| PaymentFileError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    parse(&text)
+}
+
+pub fn parse(text: &str) -> Result<PaymentFile, PaymentFileError> {
+    let document = xml::parse(text)?;
+    let initiation = document
+        .child("CstmrCdtTrfInitn")
+        .filter(|_| document.name == "Document")
+        .ok_or(PaymentFileError::NotPain001)?;
+    let header = initiation
+        .child("GrpHdr")
+        .ok_or(PaymentFileError::Missing("GrpHdr"))?;
+    let message_id = required(header, &["MsgId"], "GrpHdr/MsgId")?.to_string();
+
+    let mut transfers = Vec::new();
+    for payment in initiation.children("PmtInf") {
+        let debtor_account = required(
+            payment,
+            &["DbtrAcct", "Id", "Othr", "Id"],
+            "PmtInf/DbtrAcct/Id/Othr/Id",
+        )?;
+        for transaction in payment.children("CdtTrfTxInf") {
+            let amount = transaction
+                .find(&["Amt", "InstdAmt"])
+                .ok_or(PaymentFileError::Missing("CdtTrfTxInf/Amt/InstdAmt"))?;
+            let currency = amount
+                .attribute("Ccy")
+                .ok_or(PaymentFileError::Missing("InstdAmt/@Ccy"))?;
+            transfers.push(CreditTransfer {
+                debtor_account: debtor_account.to_string(),
+                creditor_account: required(
+                    transaction,
+                    &["CdtrAcct", "Id", "Othr", "Id"],
+                    "CdtTrfTxInf/CdtrAcct/Id/Othr/Id",
+                )?
+                .to_string(),
+                amount: amount.text().parse()?,
+                currency: currency.parse()?,
+                end_to_end_id: optional(transaction, &["PmtId", "EndToEndId"])
+                    .filter(|id| id != NOT_PROVIDED),
+                remittance_information: optional(transaction, &["RmtInf", "Ustrd"]),
+            });
+        }
+    }
+
+    let declared = required(header, &["NbOfTxs"], "GrpHdr/NbOfTxs")?;
+    if declared.parse() != Ok(transfers.len()) {
+        return Err(PaymentFileError::NumberOfTransactions {
+            declared: declared.to_string(),
+            found: transfers.len(),
+        });
+    }
+    if let Some(declared) = optional(header, &["CtrlSum"]) {
+        let mut expected = Total::default();
+        expected.add_amount(declared.parse()?);
+        let mut found = Total::default();
+        for transfer in &transfers {
+            found.add_amount(transfer.amount);
+        }
+        if found != expected {
+            return Err(PaymentFileError::ControlSum { declared, found });
+        }
+    }
+    Ok(PaymentFile {
+        message_id,
+        transfers,
+    })
+}
+
+fn required<'a>(
+    element: &'a Element,
+    path: &[&str],
+    name: &'static str,
+) -> Result<&'a str, PaymentFileError> {
+    element
+        .find(path)
+        .map(Element::text)
+        .filter(|text| !text.is_empty())
+        .ok_or(PaymentFileError::Missing(name))
+}
+
+fn optional(element: &Element, path: &[&str]) -> Option<String> {
+    element
+        .find(path)
+        .map(Element::text)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYMENTS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.09">
+  <CstmrCdtTrfInitn>
+    <GrpHdr>
+      <MsgId>MSG-1</MsgId>
+      <CreDtTm>2024-03-01T12:00:00</CreDtTm>
+      <NbOfTxs>2</NbOfTxs>
+      <CtrlSum>3.50</CtrlSum>
+      <InitgPty><Nm>Patko</Nm></InitgPty>
+    </GrpHdr>
+    <PmtInf>
+      <PmtInfId>PMT-1</PmtInfId>
+      <PmtMtd>TRF</PmtMtd>
+      <DbtrAcct><Id><Othr><Id>patko</Id></Othr></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>E2E-1</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">1.00</InstdAmt></Amt>
+        <CdtrAcct><Id><Othr><Id>siska</Id></Othr></Id></CdtrAcct>
+        <RmtInf><Ustrd>Rent &amp; bills</Ustrd></RmtInf>
+      </CdtTrfTxInf>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>NOTPROVIDED</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">2.50</InstdAmt></Amt>
+        <CdtrAcct><Id><Othr><Id>sofka</Id></Othr></Id></CdtrAcct>
+      </CdtTrfTxInf>
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>"#;
+
+    #[test]
+    fn transfers_are_read_from_payment_information() {
+        let file = parse(PAYMENTS).unwrap();
+        assert_eq!(file.message_id, "MSG-1");
+        assert_eq!(
+            file.transfers[0],
+            CreditTransfer {
+                debtor_account: "patko".to_string(),
+                creditor_account: "siska".to_string(),
+                amount: Money::from_minor(100),
+                currency: Currency::EUR,
+                end_to_end_id: Some("E2E-1".to_string()),
+                remittance_information: Some("Rent & bills".to_string()),
+            }
+        );
+        assert_eq!(file.transfers[1].end_to_end_id, None);
+
+        let miscounted = PAYMENTS.replace("<NbOfTxs>2", "<NbOfTxs>3");
+        assert!(matches!(
+            parse(&miscounted),
+            Err(PaymentFileError::NumberOfTransactions { .. })
+        ));
+        let missummed = PAYMENTS.replace("<CtrlSum>3.50", "<CtrlSum>3.00");
+        assert!(matches!(
+            parse(&missummed),
+            Err(PaymentFileError::ControlSum { .. })
+        ));
+    }
+}
@@ -0,0 +1,417 @@
+//! Extensions registered by library users. A plugin sees transfers before
+//! they are validated and can veto them, is told about committed transfers,
+//! and can handle instructions of its own.
+//!
+//! A `Plugin` runs in the server's process and is trusted like the rest of
+//! it: the interface hands it copies of transfer details and a read-only
+//! view of accounts, and a plugin that panics is disabled instead of taking
+//! the server down, but nothing keeps it from blocking, aborting the
+//! process or reaching into memory with `unsafe` code.
+//!
+//! Plugins that are not trusted so run as a `ProcessPlugin`, a program of
+//! their own the server talks to over its standard input and output. All
+//! such a plugin can do to the bank is what the messages below carry: veto
+//! a transfer, reply to one of its instructions and look up accounts while
+//! it handles one. A plugin program that exits, replies out of turn or
+//! takes longer than its timeout is killed and disabled like a plugin that
+//! panics. It runs as the server's user; keep it off the server's files
+//! with the operating system's means, e.g. another user.
+//!
+//! The messages are JSON, one per line. The server sends `"instructions"`
+//! once the program started, and the program replies
+//! `{"instructions": ["Q"]}` with the instructions it handles. Then the
+//! server sends
+//!
+//! - `{"check_transfer": {...}}`, a `TransferRequest`, replied to with
+//!   `"ok"` or `{"veto": "reason"}`;
+//! - `{"transfer_committed": {...}}`, a `CommittedTransfer`, replied to
+//!   with `"ok"`;
+//! - `{"handle": {"instruction": "Q", "payload": "..."}}`, replied to with
+//!   `{"reply": "..."}` or `{"error": "reason"}`. Before that, the program
+//!   may send `{"account": "name"}` as often as it likes, and is sent
+//!   `{"account": {...}}`, an `AccountView`, or `{"account": null}`.
+//!
+//! Payloads and replies are text, payloads that are not UTF-8 are refused.
+
+use std::fmt::{self, Debug};
+use std::io::{self, BufRead, BufReader, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::hooks::{CommittedTransfer, TransferRequest};
+use crate::money::{Balance, Currency};
+use crate::{protocol, AccountStatus, Bank};
+
+#[derive(Error, Debug)]
+pub enum PluginError {
+    #[error("Plugin '{plugin}' cannot take over built-in instruction '{instruction}'")]
+    BuiltinInstruction { plugin: String, instruction: String },
+    #[error("Instruction '{instruction}' is already handled by plugin '{plugin}'")]
+    InstructionTaken { plugin: String, instruction: String },
+    #[error("Instructions are single ASCII characters, not '{0}'")]
+    InvalidInstruction(String),
+}
+
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Called before a transfer is validated; an error vetoes it.
+    fn check_transfer(&self, _request: &TransferRequest) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn transfer_committed(&self, _transfer: &CommittedTransfer) {}
+
+    /// Instructions the plugin handles. They are sent with a payload, like
+    /// `t`, and need an admin role when roles are configured.
+    fn instructions(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Handle one of the plugin's instructions and return the reply.
+    fn handle(
+        &self,
+        _instruction: &str,
+        _payload: &[u8],
+        _bank: &BankView,
+    ) -> Result<Vec<u8>, String> {
+        Err("not supported".to_string())
+    }
+}
+
+/// What plugins can see of an account.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountView {
+    pub name: String,
+    pub balance: Balance,
+    pub currency: Currency,
+    pub status: AccountStatus,
+}
+
+/// Read-only access to the bank for plugins.
+pub struct BankView<'a> {
+    bank: &'a Bank,
+}
+
+impl BankView<'_> {
+    pub fn account(&self, name: &str) -> Option<AccountView> {
+        let id = self.bank.account_ids.get(name)?;
+        let account = &self.bank.accounts[id];
+        Some(AccountView {
+            name: account.name.to_string(),
+            balance: account.balance,
+            currency: account.currency,
+            status: account.status,
+        })
+    }
+}
+
+/// A registered plugin, switched off for good once it panics.
+pub struct Guarded {
+    plugin: Box<dyn Plugin>,
+    enabled: AtomicBool,
+}
+
+impl Guarded {
+    pub fn new(plugin: Box<dyn Plugin>) -> Arc<Guarded> {
+        Arc::new(Guarded {
+            plugin,
+            enabled: AtomicBool::new(true),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.plugin.name()
+    }
+
+    /// Run `call` on the plugin, `None` if it is disabled or panics.
+    fn call<T>(&self, call: impl FnOnce(&dyn Plugin) -> T) -> Option<T> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(|| call(self.plugin.as_ref()))) {
+            Ok(result) => Some(result),
+            Err(_) => {
+                error!("Plugin '{}' panicked and was disabled", self.name());
+                self.enabled.store(false, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// A disabled plugin vetoes nothing.
+    pub fn check_transfer(&self, request: &TransferRequest) -> Result<(), String> {
+        self.call(|plugin| plugin.check_transfer(request))
+            .unwrap_or(Ok(()))
+            .map_err(|reason| format!("{}: {reason}", self.name()))
+    }
+
+    pub fn transfer_committed(&self, transfer: &CommittedTransfer) {
+        self.call(|plugin| plugin.transfer_committed(transfer));
+    }
+
+    pub fn handle(
+        &self,
+        instruction: &str,
+        payload: &[u8],
+        bank: &Bank,
+    ) -> Result<Vec<u8>, String> {
+        let view = BankView { bank };
+        self.call(|plugin| plugin.handle(instruction, payload, &view))
+            .unwrap_or_else(|| Err(format!("plugin '{}' is disabled", self.name())))
+    }
+
+    /// The plugin's instructions, checked against the built-in ones and
+    /// `taken`.
+    pub fn instructions(
+        &self,
+        taken: impl Fn(&str) -> Option<String>,
+    ) -> Result<Vec<String>, PluginError> {
+        let instructions = self
+            .call(|plugin| plugin.instructions())
+            .unwrap_or_default();
+        for instruction in &instructions {
+            if instruction.len() != 1 || !instruction.is_ascii() {
+                return Err(PluginError::InvalidInstruction(instruction.clone()));
+            }
+            if protocol::is_builtin(instruction) {
+                return Err(PluginError::BuiltinInstruction {
+                    plugin: self.name().to_string(),
+                    instruction: instruction.clone(),
+                });
+            }
+            if let Some(plugin) = taken(instruction) {
+                return Err(PluginError::InstructionTaken {
+                    plugin,
+                    instruction: instruction.clone(),
+                });
+            }
+        }
+        Ok(instructions)
+    }
+}
+
+impl Debug for Guarded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Guarded")
+            .field("name", &self.name())
+            .field("enabled", &self.enabled.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+/// What the server sends a `ProcessPlugin`, see the module documentation.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Call<'a> {
+    Instructions,
+    CheckTransfer(&'a TransferRequest),
+    TransferCommitted(&'a CommittedTransfer),
+    Handle {
+        instruction: &'a str,
+        payload: &'a str,
+    },
+    Account(Option<AccountView>),
+}
+
+/// What a `ProcessPlugin` answers.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Answer {
+    Instructions(Vec<String>),
+    Ok,
+    Veto(String),
+    Reply(String),
+    Error(String),
+    Account(String),
+}
+
+/// A running plugin program.
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    /// Lines of its standard output, read by a thread of their own so that
+    /// waiting for them can time out.
+    replies: Receiver<io::Result<String>>,
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // It may have exited already.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A plugin running as a program of its own, see the module documentation.
+pub struct ProcessPlugin {
+    name: String,
+    instructions: Vec<String>,
+    timeout: Duration,
+    /// `None` once the program was killed.
+    process: Mutex<Option<Process>>,
+}
+
+impl ProcessPlugin {
+    /// Start `program` with `args` and an empty environment, and ask it for
+    /// its instructions. Every reply must come within `timeout`.
+    pub fn spawn(
+        name: &str,
+        program: &str,
+        args: &[String],
+        timeout: Duration,
+    ) -> io::Result<ProcessPlugin> {
+        let mut child = Command::new(program)
+            .args(args)
+            .env_clear()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, replies) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut plugin = ProcessPlugin {
+            name: name.to_string(),
+            instructions: Vec::new(),
+            timeout,
+            process: Mutex::new(Some(Process {
+                child,
+                stdin,
+                replies,
+            })),
+        };
+        plugin.instructions = match plugin.exchange(&Call::Instructions, None) {
+            Ok(Answer::Instructions(instructions)) => instructions,
+            Ok(_) => return Err(io::Error::other("plugin did not list its instructions")),
+            Err(error) => return Err(io::Error::other(error)),
+        };
+        Ok(plugin)
+    }
+
+    /// Send `call` and wait for the reply, answering account lookups with
+    /// `bank` if there is one. On any failure the program is killed.
+    fn exchange(&self, call: &Call, bank: Option<&BankView>) -> Result<Answer, String> {
+        let mut process = self.process.lock().unwrap_or_else(|e| e.into_inner());
+        let running = process.as_mut().ok_or("plugin program was killed")?;
+        let result = (|| {
+            let mut call = serde_json::to_string(call).map_err(|e| e.to_string())?;
+            loop {
+                call.push('\n');
+                running
+                    .stdin
+                    .write_all(call.as_bytes())
+                    .and_then(|()| running.stdin.flush())
+                    .map_err(|e| format!("cannot write to plugin program: {e}"))?;
+                let line = match running.replies.recv_timeout(self.timeout) {
+                    Ok(line) => line.map_err(|e| format!("cannot read plugin program: {e}"))?,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        return Err("plugin program timed out".to_string())
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        return Err("plugin program exited".to_string())
+                    }
+                };
+                let reply = serde_json::from_str(&line)
+                    .map_err(|e| format!("plugin program replied {line:?}: {e}"))?;
+                match (reply, bank) {
+                    (Answer::Account(name), Some(bank)) => {
+                        call = serde_json::to_string(&Call::Account(bank.account(&name)))
+                            .map_err(|e| e.to_string())?;
+                    }
+                    (Answer::Account(_), None) => {
+                        return Err("plugin program looked up an account out of turn".to_string())
+                    }
+                    (reply, _) => return Ok(reply),
+                }
+            }
+        })();
+        if result.is_err() {
+            *process = None;
+        }
+        result
+    }
+
+    /// Kill the program for a reply that does not fit the call, and panic,
+    /// which disables the plugin.
+    fn out_of_turn(&self) -> ! {
+        *self.process.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        panic!("plugin '{}' replied out of turn", self.name)
+    }
+
+    /// `exchange`, but a failure panics, which disables the plugin.
+    fn call(&self, call: &Call, bank: Option<&BankView>) -> Answer {
+        match self.exchange(call, bank) {
+            Ok(reply) => reply,
+            Err(error) => panic!("plugin '{}': {error}", self.name),
+        }
+    }
+}
+
+impl Plugin for ProcessPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check_transfer(&self, request: &TransferRequest) -> Result<(), String> {
+        match self.call(&Call::CheckTransfer(request), None) {
+            Answer::Ok => Ok(()),
+            Answer::Veto(reason) => Err(reason),
+            _ => self.out_of_turn(),
+        }
+    }
+
+    fn transfer_committed(&self, transfer: &CommittedTransfer) {
+        match self.call(&Call::TransferCommitted(transfer), None) {
+            Answer::Ok => {}
+            _ => self.out_of_turn(),
+        }
+    }
+
+    fn instructions(&self) -> Vec<String> {
+        self.instructions.clone()
+    }
+
+    fn handle(
+        &self,
+        instruction: &str,
+        payload: &[u8],
+        bank: &BankView,
+    ) -> Result<Vec<u8>, String> {
+        let payload =
+            std::str::from_utf8(payload).map_err(|_| "payload is not UTF-8".to_string())?;
+        let call = Call::Handle {
+            instruction,
+            payload,
+        };
+        match self.call(&call, Some(bank)) {
+            Answer::Reply(reply) => Ok(reply.into_bytes()),
+            Answer::Error(error) => Err(error),
+            _ => self.out_of_turn(),
+        }
+    }
+}
+
+impl Debug for ProcessPlugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcessPlugin")
+            .field("name", &self.name)
+            .field("instructions", &self.instructions)
+            .finish()
+    }
+}
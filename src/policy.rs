@@ -0,0 +1,516 @@
+//! Transaction policies written in a small rule language, loaded from the
+//! file named by the `policy_script` setting and re-read on SIGHUP. Built
+//! with the `scripting` feature.
+//!
+//! A script has one rule per line; `#` starts a comment:
+//!
+//! ```text
+//! reject "over 500 from frozen accounts" when amount > 500 and from.tags contains "frozen"
+//! flag when to.tags contains "crypto" or memo == "gift"
+//! ```
+//!
+//! Every transfer is checked against every rule. A matching `reject` rule
+//! fails the transfer and a matching `flag` rule flags it for review, like
+//! the fraud rules do. The quoted reason is optional and defaults to the
+//! rule itself.
+//!
+//! Conditions combine comparisons with `and`, `or`, `not` and parentheses.
+//! `amount`, `from.balance` and `to.balance` compare to numbers in major
+//! units with `==`, `!=`, `<`, `<=`, `>` and `>=`; `currency`, `memo`,
+//! `from.name`, `from.status` and their `to.` counterparts compare to
+//! quoted text with `==` and `!=`; `from.tags` and `to.tags` are tested with
+//! `contains`.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::fraud::Action;
+use crate::money::{Balance, Currency, Money};
+
+#[derive(Error, Debug)]
+pub enum PolicyError {
+    #[error("Unable to read policy script {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Policy script line {line}: {message}")]
+    Syntax { line: usize, message: String },
+}
+
+/// What a transfer looks like to the rules.
+#[derive(Debug, Clone, Copy)]
+pub struct Facts<'a> {
+    pub amount: Money,
+    pub currency: Currency,
+    pub memo: Option<&'a str>,
+    pub from: AccountFacts<'a>,
+    pub to: AccountFacts<'a>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AccountFacts<'a> {
+    pub name: &'a str,
+    pub status: &'a str,
+    pub balance: Balance,
+    pub tags: &'a BTreeSet<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub action: Action,
+    pub reason: String,
+    condition: Expr,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Policy, PolicyError> {
+        let script = fs::read_to_string(path).map_err(|source| PolicyError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        script.parse()
+    }
+
+    /// The rules matching a transfer, in script order.
+    pub fn matching<'a>(&'a self, facts: &'a Facts<'a>) -> impl Iterator<Item = &'a Rule> {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.condition.eval(facts))
+    }
+}
+
+impl std::str::FromStr for Policy {
+    type Err = PolicyError;
+
+    fn from_str(script: &str) -> Result<Policy, PolicyError> {
+        let mut rules = Vec::new();
+        for (index, line) in script.lines().enumerate() {
+            let syntax_error = |message: String| PolicyError::Syntax {
+                line: index + 1,
+                message,
+            };
+            let tokens = tokenize(line).map_err(syntax_error)?;
+            if tokens.is_empty() {
+                continue;
+            }
+            let rule = Parser {
+                tokens,
+                position: 0,
+            }
+            .rule(line.trim())
+            .map_err(syntax_error)?;
+            rules.push(rule);
+        }
+        Ok(Policy { rules })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    From,
+    To,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Amount,
+    Currency,
+    Memo,
+    Name(Side),
+    Status(Side),
+    Balance(Side),
+    Tags(Side),
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        let field = match name {
+            "amount" => Field::Amount,
+            "currency" => Field::Currency,
+            "memo" => Field::Memo,
+            _ => {
+                let (side, attribute) = name.split_once('.')?;
+                let side = match side {
+                    "from" => Side::From,
+                    "to" => Side::To,
+                    _ => return None,
+                };
+                match attribute {
+                    "name" => Field::Name(side),
+                    "status" => Field::Status(side),
+                    "balance" => Field::Balance(side),
+                    "tags" => Field::Tags(side),
+                    _ => return None,
+                }
+            }
+        };
+        Some(field)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Number,
+    Text,
+    Tags,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Field(Field),
+    /// In minor units.
+    Number(i64),
+    Text(String),
+}
+
+impl Operand {
+    fn kind(&self) -> Kind {
+        match self {
+            Operand::Field(Field::Amount | Field::Balance(_)) | Operand::Number(_) => Kind::Number,
+            Operand::Field(Field::Tags(_)) => Kind::Tags,
+            Operand::Field(_) | Operand::Text(_) => Kind::Text,
+        }
+    }
+
+    fn number(&self, facts: &Facts) -> i64 {
+        match self {
+            Operand::Field(Field::Amount) => {
+                i64::try_from(facts.amount.minor_units()).unwrap_or(i64::MAX)
+            }
+            Operand::Field(Field::Balance(side)) => facts.account(*side).balance.minor_units(),
+            Operand::Number(number) => *number,
+            _ => unreachable!("operands are type checked when parsed"),
+        }
+    }
+
+    fn text<'a>(&'a self, facts: &'a Facts<'a>) -> &'a str {
+        match self {
+            Operand::Field(Field::Currency) => facts.currency.as_str(),
+            Operand::Field(Field::Memo) => facts.memo.unwrap_or(""),
+            Operand::Field(Field::Name(side)) => facts.account(*side).name,
+            Operand::Field(Field::Status(side)) => facts.account(*side).status,
+            Operand::Text(text) => text,
+            _ => unreachable!("operands are type checked when parsed"),
+        }
+    }
+}
+
+impl<'a> Facts<'a> {
+    fn account(&self, side: Side) -> &AccountFacts<'a> {
+        match side {
+            Side::From => &self.from,
+            Side::To => &self.to,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Compare(Operand, Comparison, Operand),
+    Contains(Side, Operand),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, facts: &Facts) -> bool {
+        match self {
+            Expr::Compare(left, comparison, right) => {
+                let ordering = match left.kind() {
+                    Kind::Number => left.number(facts).cmp(&right.number(facts)),
+                    _ => left.text(facts).cmp(right.text(facts)),
+                };
+                match comparison {
+                    Comparison::Eq => ordering.is_eq(),
+                    Comparison::Ne => ordering.is_ne(),
+                    Comparison::Lt => ordering.is_lt(),
+                    Comparison::Le => ordering.is_le(),
+                    Comparison::Gt => ordering.is_gt(),
+                    Comparison::Ge => ordering.is_ge(),
+                }
+            }
+            Expr::Contains(side, tag) => facts.account(*side).tags.contains(tag.text(facts)),
+            Expr::And(left, right) => left.eval(facts) && right.eval(facts),
+            Expr::Or(left, right) => left.eval(facts) || right.eval(facts),
+            Expr::Not(inner) => !inner.eval(facts),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Number(i64),
+    Text(String),
+    Comparison(Comparison),
+    Open,
+    Close,
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            '#' => break,
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => text.push(escaped),
+                            None => return Err("unterminated text".to_string()),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err("unterminated text".to_string()),
+                    }
+                }
+                tokens.push(Token::Text(text));
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let followed_by_equals = chars.next_if(|&(_, c)| c == '=').is_some();
+                let comparison = match (c, followed_by_equals) {
+                    ('=', true) => Comparison::Eq,
+                    ('!', true) => Comparison::Ne,
+                    ('<', false) => Comparison::Lt,
+                    ('<', true) => Comparison::Le,
+                    ('>', false) => Comparison::Gt,
+                    ('>', true) => Comparison::Ge,
+                    _ => return Err(format!("unexpected '{c}'")),
+                };
+                tokens.push(Token::Comparison(comparison));
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut end = start;
+                while let Some((index, c)) =
+                    chars.next_if(|&(_, c)| c.is_ascii_digit() || c == '.' || c == '-')
+                {
+                    end = index + c.len_utf8();
+                }
+                let number = &line[start..end];
+                let balance: Balance = number
+                    .parse()
+                    .map_err(|_| format!("'{number}' is not a number"))?;
+                tokens.push(Token::Number(balance.minor_units()));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = start;
+                while let Some((index, c)) =
+                    chars.next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+                {
+                    end = index + c.len_utf8();
+                }
+                tokens.push(Token::Word(line[start..end].to_string()));
+            }
+            c => return Err(format!("unexpected '{c}'")),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_is_word(&mut self, word: &str) -> bool {
+        let matches = matches!(self.tokens.get(self.position), Some(Token::Word(w)) if w == word);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn rule(mut self, source: &str) -> Result<Rule, String> {
+        let action = match self.next() {
+            Some(Token::Word(word)) if word == "reject" => Action::Reject,
+            Some(Token::Word(word)) if word == "flag" => Action::Flag,
+            _ => return Err("rules start with 'reject' or 'flag'".to_string()),
+        };
+        let reason = match self.tokens.get(self.position) {
+            Some(Token::Text(reason)) => {
+                let reason = reason.clone();
+                self.position += 1;
+                reason
+            }
+            _ => source.to_string(),
+        };
+        if !self.next_is_word("when") {
+            return Err("expected 'when'".to_string());
+        }
+        let condition = self.or()?;
+        if self.position < self.tokens.len() {
+            return Err("unexpected input after the condition".to_string());
+        }
+        Ok(Rule {
+            action,
+            reason,
+            condition,
+        })
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.next_is_word("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.next_is_word("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.next_is_word("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.tokens.get(self.position) == Some(&Token::Open) {
+            self.position += 1;
+            let expr = self.or()?;
+            if self.next() != Some(Token::Close) {
+                return Err("expected ')'".to_string());
+            }
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.operand()?;
+        if self.next_is_word("contains") {
+            let tag = self.operand()?;
+            return match (&left, tag.kind()) {
+                (Operand::Field(Field::Tags(side)), Kind::Text) => Ok(Expr::Contains(*side, tag)),
+                _ => Err("'contains' needs tags on the left and text on the right".to_string()),
+            };
+        }
+        let comparison = match self.next() {
+            Some(Token::Comparison(comparison)) => comparison,
+            _ => return Err("expected a comparison".to_string()),
+        };
+        let right = self.operand()?;
+        match (left.kind(), right.kind()) {
+            (Kind::Number, Kind::Number) => {}
+            (Kind::Text, Kind::Text) if matches!(comparison, Comparison::Eq | Comparison::Ne) => {}
+            (Kind::Text, Kind::Text) => return Err("text only compares with == and !=".to_string()),
+            _ => return Err("comparing values of different kinds".to_string()),
+        }
+        Ok(Expr::Compare(left, comparison, right))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Operand::Number(number)),
+            Some(Token::Text(text)) => Ok(Operand::Text(text)),
+            Some(Token::Word(word)) => Field::parse(&word)
+                .map(Operand::Field)
+                .ok_or_else(|| format!("unknown field '{word}'")),
+            _ => Err("expected a field, number or text".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts<'a>(
+        amount: u64,
+        from_tags: &'a BTreeSet<String>,
+        none: &'a BTreeSet<String>,
+    ) -> Facts<'a> {
+        Facts {
+            amount: Money::from_minor(amount),
+            currency: Currency::EUR,
+            memo: None,
+            from: AccountFacts {
+                name: "patko",
+                status: "active",
+                balance: Balance::from_minor(-100),
+                tags: from_tags,
+            },
+            to: AccountFacts {
+                name: "siska",
+                status: "active",
+                balance: Balance::ZERO,
+                tags: none,
+            },
+        }
+    }
+
+    #[test]
+    fn rules_match_transfers() {
+        let policy: Policy = r#"
+            # Comments and blank lines are skipped.
+            reject "over 500 from frozen" when amount > 500 and from.tags contains "frozen"
+            flag when not (to.name == "siska" or from.balance >= -0.50)
+        "#
+        .parse()
+        .unwrap();
+        let frozen = BTreeSet::from(["frozen".to_string()]);
+        let none = BTreeSet::new();
+        let reasons = |amount, tags| {
+            policy
+                .matching(&facts(amount, tags, &none))
+                .map(|rule| rule.reason.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(reasons(50_001, &frozen), ["over 500 from frozen"]);
+        assert!(reasons(50_000, &frozen).is_empty());
+        assert!(reasons(50_001, &none).is_empty());
+    }
+
+    #[test]
+    fn type_errors_are_reported_with_the_line() {
+        let error = "\nreject when amount == \"big\""
+            .parse::<Policy>()
+            .unwrap_err();
+        assert!(matches!(error, PolicyError::Syntax { line: 2, .. }));
+        assert!("flag when memo < \"a\"".parse::<Policy>().is_err());
+        assert!("flag when amount >".parse::<Policy>().is_err());
+    }
+}
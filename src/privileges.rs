@@ -0,0 +1,60 @@
+//! Dropping root once the sockets are bound, for a server started as root
+//! to bind where only root may. The server then runs as `run_as_user` and
+//! `run_as_group`, with the supplementary groups of the user, or of none
+//! but the group when only it is given. The data directory, and the
+//! configuration file and what it names for reloads, must be usable by
+//! them.
+
+use std::io;
+
+use log::info;
+
+use crate::users::{self, Account};
+use crate::Config;
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Switch to the configured user and group, if any, for good.
+pub fn drop_privileges(config: &Config) -> io::Result<()> {
+    if config.run_as_user.is_none() && config.run_as_group.is_none() {
+        return Ok(());
+    }
+    let account: Option<Account> = config
+        .run_as_user
+        .as_deref()
+        .map(users::resolve_account)
+        .transpose()?;
+    let gid = match (&config.run_as_group, &account) {
+        (Some(group), _) => users::resolve_group(group)?,
+        (None, Some(account)) => account.gid,
+        (None, None) => unreachable!("checked above"),
+    };
+    // Groups first, while still allowed to change them.
+    // SAFETY: plain system calls, the name is NUL-terminated and the group
+    // list is one valid element long.
+    unsafe {
+        match &account {
+            Some(account) => check(libc::initgroups(account.name.as_ptr(), gid))?,
+            None => check(libc::setgroups(1, &gid))?,
+        }
+        check(libc::setgid(gid))?;
+        if let Some(account) = &account {
+            check(libc::setuid(account.uid))?;
+        }
+    }
+    // SAFETY: as above.
+    let (uid, euid) = unsafe { (libc::getuid(), libc::geteuid()) };
+    if uid != 0 && (euid == 0 || unsafe { libc::setuid(0) } == 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "root privileges could be regained after dropping them",
+        ));
+    }
+    info!("Running as uid {uid} and gid {gid}");
+    Ok(())
+}
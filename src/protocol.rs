@@ -0,0 +1,134 @@
+//! Wire format of requests.
+//!
+//! A request starts with a one-byte instruction, optionally followed by a JSON
+//! header in the same datagram, e.g. `t{"token":"secret"}`.
+//!
+//! The header's `version` says which version of the protocol the client
+//! speaks. The `n` instruction reports the versions the server speaks,
+//! `min_protocol` to `protocol`, so a client picks the highest one both
+//! know. Requests in any other version are refused with "505" rather than
+//! misunderstood, and requests without one are taken to be in version 1,
+//! the one spoken before there were versions.
+//!
+//! Requests and payloads longer than `Config::max_message_size` are refused
+//! with "413".
+
+use std::str;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::listing::{ListingFormat, Page};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestHeader {
+    /// Bearer token, required when token authentication is configured.
+    pub token: Option<String>,
+    /// Signing key used for `signature`.
+    pub key_id: Option<String>,
+    /// Milliseconds since the Unix epoch when signed, see `signing`.
+    pub nonce: Option<u64>,
+    /// Hex-encoded HMAC over the request, see `signing`.
+    pub signature: Option<String>,
+    /// Bank the request is for, one of the configured `tenants` or a
+    /// sandbox, see `sandbox`. Requests without it go to the default bank.
+    pub tenant: Option<String>,
+    /// Protocol version the request is in, see `VERSION`.
+    pub version: Option<u32>,
+    /// Layout of the account listing the `i` and `j` instructions reply
+    /// with, JSON unless given.
+    pub format: Option<ListingFormat>,
+    /// Page of the `i` or `j` listing to reply with, the `next` of the
+    /// previous page, see `listing`.
+    pub cursor: Option<String>,
+    /// Accounts on a page of the `i` or `j` listing, see `listing`.
+    pub page_size: Option<usize>,
+}
+
+impl RequestHeader {
+    /// The protocol version the request is in, if the server speaks it.
+    pub fn protocol_version(&self) -> Result<u32, UnsupportedVersion> {
+        let version = self.version.unwrap_or(UNVERSIONED);
+        match (MIN_VERSION..=VERSION).contains(&version) {
+            true => Ok(version),
+            false => Err(UnsupportedVersion(version)),
+        }
+    }
+
+    /// The page of the `i` or `j` listing asked for, `None` for all of it.
+    pub fn page(&self) -> Option<Page<'_>> {
+        if self.cursor.is_none() && self.page_size.is_none() {
+            return None;
+        }
+        Some(Page {
+            cursor: self.cursor.as_deref(),
+            size: self.page_size,
+        })
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Protocol version {0} is not supported, only {MIN_VERSION} to {VERSION} are")]
+pub struct UnsupportedVersion(pub u32);
+
+/// Bumped whenever requests or replies change in a way older clients or
+/// servers would not understand. Reported by the `n` instruction.
+pub const VERSION: u32 = 2;
+
+/// Oldest version still served. Raised when the server stops adapting to
+/// clients of a version.
+pub const MIN_VERSION: u32 = 1;
+
+/// The version of requests that do not name one.
+const UNVERSIONED: u32 = 1;
+
+/// First version replying to requests the bank refuses with an
+/// `ErrorReply`, JSON with the `status`, a `code` and a `message`, where
+/// version 1 replies with the status alone, and replying to failed admin
+/// commands at all.
+pub const ERROR_REPLY_VERSION: u32 = 2;
+
+/// Default for `Config::max_message_size`.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4096;
+
+/// Instructions the server itself handles, as opposed to plugins.
+pub const BUILTIN_INSTRUCTIONS: [&str; 26] = [
+    "t", "a", "b", "h", "p", "w", "r", "s", "v", "e", "u", "x", "o", "l", "z", "i", "j", "f", "g",
+    "y", "c", "m", "k", "n", "q", "d",
+];
+
+/// Instructions followed by a second datagram carrying their payload,
+/// after the server acknowledges them with "200".
+const PAYLOAD_INSTRUCTIONS: [&str; 17] = [
+    "t", "a", "b", "h", "p", "w", "r", "s", "v", "e", "u", "x", "o", "l", "z", "j", "y",
+];
+
+/// Whether the instruction is followed by a second datagram carrying its
+/// payload, after the server acknowledges it with "200".
+pub fn has_payload(instruction: &str) -> bool {
+    PAYLOAD_INSTRUCTIONS.contains(&instruction)
+}
+
+/// Whether the server itself handles the instruction, as opposed to a
+/// plugin.
+pub fn is_builtin(instruction: &str) -> bool {
+    BUILTIN_INSTRUCTIONS.contains(&instruction)
+}
+
+/// The instruction a request starts with, `None` if it does not start with
+/// an ASCII character.
+pub fn parse_instruction(datagram: &[u8]) -> Option<&str> {
+    datagram
+        .first()
+        .filter(|byte| byte.is_ascii())
+        .and_then(|_| str::from_utf8(&datagram[..1]).ok())
+}
+
+/// Parse the bytes following the instruction. An empty header is allowed.
+pub fn parse_header(bytes: &[u8]) -> serde_json::Result<RequestHeader> {
+    if bytes.is_empty() {
+        return Ok(RequestHeader::default());
+    }
+    serde_json::from_slice(bytes)
+}
@@ -0,0 +1,82 @@
+//! Token-bucket rate limiting per client, so one client flooding the socket
+//! cannot starve the others.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use serde::Deserialize;
+
+/// Buckets beyond this many trigger a sweep of clients that have been idle
+/// long enough to be back at a full bucket.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second allowed for each client.
+    pub requests_per_second: f64,
+    /// How many requests a client may send at once before being throttled.
+    #[serde(default = "default_burst")]
+    pub burst: f64,
+}
+
+fn default_burst() -> f64 {
+    10.0
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    config: Option<RateLimitConfig>,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: Option<RateLimitConfig>) -> RateLimiter {
+        RateLimiter {
+            config,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Apply new limits, keeping the state of clients already being tracked.
+    pub fn set_config(&mut self, config: Option<RateLimitConfig>) {
+        if config.is_none() {
+            self.buckets.clear();
+        }
+        self.config = config;
+    }
+
+    /// Take one request from `client`'s bucket. Returns false when the client
+    /// is over its limit.
+    pub fn allow(&mut self, client: &str, now: Instant) -> bool {
+        let config = match &self.config {
+            Some(config) => config,
+            None => return true,
+        };
+        if self.buckets.len() >= MAX_TRACKED_CLIENTS && !self.buckets.contains_key(client) {
+            self.buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens + elapsed * config.requests_per_second < config.burst
+            });
+        }
+        let bucket = self.buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: config.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * config.requests_per_second).min(config.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! `bank replay <data_dir|log> [<sequence>]` rebuilds a bank from nothing
+//! but its log and prints the balances it ends up with, to check that the
+//! log alone recovers the bank or to find the event a discrepancy starts at.
+//!
+//! Given a data directory it replays the audit log there, which is never
+//! compacted, and compares the result with the bank kept in the directory.
+//! Given a file it replays that, either an audit log or an event log that
+//! starts at the first event; pass the sequence number of an event to stop
+//! after it. The chain of the audit log is not checked, `bank verify-audit`
+//! does that.
+
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::audit::{self, AuditEntry};
+use crate::clock::SystemClock;
+use crate::encryption::{self, EncryptionError};
+use crate::events::{EventLogError, EventRecord};
+use crate::money::{Balance, Currency};
+use crate::store::StoreError;
+use crate::{load_bank, AccountId, AccountStatus, Bank};
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("Unable to read {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Line {line} of {} is neither an audit entry nor an event", path.display())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Unable to read line {line} of {}", path.display())]
+    Sealed {
+        path: PathBuf,
+        line: usize,
+        #[source]
+        source: EncryptionError,
+    },
+    #[error("Unable to replay {}", path.display())]
+    Events {
+        path: PathBuf,
+        #[source]
+        source: EventLogError,
+    },
+    #[error(transparent)]
+    Store(#[from] StoreError),
+}
+
+/// A line of a log: audit logs wrap each event in its hashes.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Audit(AuditEntry),
+    Event(EventRecord),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountBalance {
+    pub id: AccountId,
+    pub name: String,
+    pub currency: Currency,
+    pub balance: Balance,
+    pub status: AccountStatus,
+}
+
+/// An account whose replayed balance differs from the kept one, or that
+/// only one of them has.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discrepancy {
+    pub id: AccountId,
+    pub name: String,
+    pub replayed: Option<Balance>,
+    pub kept: Option<Balance>,
+}
+
+#[derive(Debug)]
+pub struct Replay {
+    /// Sequence number of the last event replayed.
+    pub last_event: u64,
+    /// By account ID.
+    pub accounts: Vec<AccountBalance>,
+    /// Against the bank kept in the data directory, if one was replayed.
+    pub discrepancies: Option<Vec<Discrepancy>>,
+}
+
+/// Replay the log at `path`, a data directory or a log file, up to and
+/// including the event `until` if given.
+pub fn replay_log(path: &Path, until: Option<u64>) -> Result<Replay, ReplayError> {
+    let data_dir = path.is_dir().then_some(path);
+    let log = match data_dir {
+        Some(data_dir) => data_dir.join(audit::FILE),
+        None => path.to_owned(),
+    };
+    let mut records = read(&log)?;
+    if let Some(until) = until {
+        records.retain(|record| record.sequence <= until);
+    }
+    let mut bank = Bank::with_clock(Arc::new(SystemClock));
+    bank.apply_records(records)
+        .map_err(|source| ReplayError::Events {
+            path: log.clone(),
+            source,
+        })?;
+    let accounts = balances(&bank);
+    // An earlier state is expected to differ from the kept one.
+    let discrepancies = match data_dir {
+        Some(data_dir) if until.is_none() => {
+            Some(compare(&accounts, &balances(&load_bank(data_dir)?)))
+        }
+        _ => None,
+    };
+    Ok(Replay {
+        last_event: bank.events.last_sequence(),
+        accounts,
+        discrepancies,
+    })
+}
+
+fn read(path: &Path) -> Result<Vec<EventRecord>, ReplayError> {
+    let io_error = |source| ReplayError::Io {
+        path: path.to_owned(),
+        source,
+    };
+    let file = File::open(path).map_err(io_error)?;
+    let mut records = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(io_error)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line = encryption::decode(&line).map_err(|source| ReplayError::Sealed {
+            path: path.to_owned(),
+            line: index + 1,
+            source,
+        })?;
+        let line = serde_json::from_str(&line).map_err(|source| ReplayError::Parse {
+            path: path.to_owned(),
+            line: index + 1,
+            source,
+        })?;
+        records.push(match line {
+            Line::Audit(entry) => entry.record,
+            Line::Event(record) => record,
+        });
+    }
+    Ok(records)
+}
+
+fn balances(bank: &Bank) -> Vec<AccountBalance> {
+    let mut accounts: Vec<AccountBalance> = bank
+        .accounts
+        .values()
+        .map(|account| AccountBalance {
+            id: account.id,
+            name: account.name.to_string(),
+            currency: account.currency,
+            balance: account.balance,
+            status: account.status,
+        })
+        .collect();
+    accounts.sort_by_key(|account| account.id);
+    accounts
+}
+
+fn compare(replayed: &[AccountBalance], kept: &[AccountBalance]) -> Vec<Discrepancy> {
+    let find = |accounts: &[AccountBalance], id| {
+        accounts
+            .iter()
+            .find(|account: &&AccountBalance| account.id == id)
+            .cloned()
+    };
+    let mut ids: Vec<AccountId> = replayed
+        .iter()
+        .chain(kept)
+        .map(|account| account.id)
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids.into_iter()
+        .filter_map(|id| {
+            let (replayed, kept) = (find(replayed, id), find(kept, id));
+            let balance =
+                |account: &Option<AccountBalance>| account.as_ref().map(|account| account.balance);
+            if balance(&replayed) == balance(&kept) {
+                return None;
+            }
+            let name = replayed.as_ref().or(kept.as_ref()).unwrap().name.clone();
+            Some(Discrepancy {
+                id,
+                name,
+                replayed: balance(&replayed),
+                kept: balance(&kept),
+            })
+        })
+        .collect()
+}
+
+impl Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Replayed {} events", self.last_event)?;
+        for account in &self.accounts {
+            writeln!(
+                f,
+                "{:>6} {:<24} {:>16} {} {}",
+                account.id.to_string(),
+                account.name,
+                account.balance.to_string(),
+                account.currency,
+                account.status.as_str()
+            )?;
+        }
+        let Some(discrepancies) = &self.discrepancies else {
+            return Ok(());
+        };
+        if discrepancies.is_empty() {
+            return writeln!(f, "The kept bank agrees with the log");
+        }
+        let show = |balance: Option<Balance>| match balance {
+            Some(balance) => balance.to_string(),
+            None => "missing".to_string(),
+        };
+        for discrepancy in discrepancies {
+            writeln!(
+                f,
+                "Discrepancy in {} {}: replayed {}, kept {}",
+                discrepancy.id,
+                discrepancy.name,
+                show(discrepancy.replayed),
+                show(discrepancy.kept)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::money::Money;
+    use crate::{AccountRef, TxInfo};
+
+    #[test]
+    fn replaying_the_audit_log_recovers_the_kept_balances() {
+        let dir = std::env::temp_dir().join(format!("bank-replay-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        {
+            // Snapshotting clears the event log, not the audit log.
+            let mut bank = Bank::open(Arc::new(SystemClock), &dir, 2).unwrap();
+            for name in ["a", "b"] {
+                bank.open_account(name.to_string(), Balance::from_minor(100), Currency::EUR)
+                    .unwrap();
+            }
+            for _ in 0..3 {
+                bank.handle_transaction(TxInfo {
+                    from: AccountRef::Name("a".to_string()),
+                    to: AccountRef::Name("b".to_string()),
+                    amount: Money::from_minor(10),
+                    currency: None,
+                    convert: false,
+                    pin: None,
+                    memo: None,
+                    external_ref: None,
+                    idempotency_key: None,
+                    dry_run: false,
+                })
+                .unwrap();
+                bank.snapshot_if_due();
+            }
+        }
+
+        let replay = replay_log(&dir, None).unwrap();
+        let balances: Vec<(&str, i64)> = replay
+            .accounts
+            .iter()
+            .map(|account| (account.name.as_str(), account.balance.minor_units()))
+            .collect();
+        assert_eq!(balances, [("a", 70), ("b", 130)]);
+        assert_eq!(replay.discrepancies, Some(Vec::new()));
+
+        let replay = replay_log(&dir, Some(2)).unwrap();
+        assert_eq!(replay.last_event, 2);
+        assert!(replay
+            .accounts
+            .iter()
+            .all(|account| account.balance.minor_units() == 100));
+        assert!(replay.discrepancies.is_none());
+
+        let replay = replay_log(&dir.join(audit::FILE), None).unwrap();
+        assert!(replay.discrepancies.is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+//! A second socket, `Config::replica_socket_path`, serving only the read
+//! instructions `i`, `j` and `h`, so that reporting clients reading a lot do not
+//! hold up the transfers queued on the server's socket.
+//!
+//! The replica answers from a copy of the default bank, taken by the main
+//! loop after the requests and scheduled work that changed it. Its answers
+//! are at most one request behind the bank. Other instructions and requests
+//! for tenants are refused with "403" and "404". Requests are authenticated
+//! and authorized as on the server's socket, but not rate limited; nonces
+//! of signed requests are tracked apart from the server's.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread;
+
+use anyhow::Result;
+use log::{error, info, warn};
+
+use crate::auth::Auth;
+use crate::listing::AccountFilter;
+use crate::protocol;
+use crate::socket::PeerCredentials;
+use crate::trace::{Phase, RequestTrace, Tracer};
+use crate::transport::Transport;
+use crate::{
+    answer_account_search, answer_history_query, extend_identity, recv_payload, reply, respond,
+    Bank,
+};
+
+/// Instructions the replica serves.
+pub const INSTRUCTIONS: [&str; 3] = ["i", "j", "h"];
+
+/// What the main loop hands over to the replica.
+struct Shared {
+    view: RwLock<Arc<Bank>>,
+    /// Settings of a reloaded configuration, until the replica picks them up.
+    auth: Mutex<Option<Auth>>,
+}
+
+/// Handle the main loop keeps on the replica. The replica stops when it is
+/// dropped.
+pub struct Replica {
+    shared: Arc<Shared>,
+    /// Sequence number of the last event in the copy published.
+    published: Option<u64>,
+}
+
+impl Replica {
+    /// Serve read requests coming in on `transport` on a thread of their
+    /// own. Nothing is served until the first `publish`.
+    pub fn start(transport: Arc<dyn Transport>, auth: Auth, max_message_size: usize) -> Replica {
+        let shared = Arc::new(Shared {
+            view: RwLock::new(Arc::new(Bank::new())),
+            auth: Mutex::new(None),
+        });
+        let mut server = Server::new(transport, auth, max_message_size);
+        let handle = Arc::downgrade(&shared);
+        thread::spawn(move || run(&handle, &mut server));
+        Replica {
+            shared,
+            published: None,
+        }
+    }
+
+    /// Replace the copy the replica answers from if `bank` changed since it
+    /// was taken.
+    pub fn publish(&mut self, bank: &Bank) {
+        let sequence = bank.events.last_sequence();
+        if self.published == Some(sequence) {
+            return;
+        }
+        let copy = Arc::new(bank.sandbox(false));
+        *self.shared.view.write().unwrap() = copy;
+        self.published = Some(sequence);
+    }
+
+    /// Authenticate and authorize with `auth` from the next request on.
+    pub fn set_auth(&self, auth: Auth) {
+        *self.shared.auth.lock().unwrap() = Some(auth);
+    }
+}
+
+fn run(shared: &Weak<Shared>, server: &mut Server) {
+    info!("Serving read requests on the replica socket");
+    while let Some(shared) = shared.upgrade() {
+        if let Some(auth) = shared.auth.lock().unwrap().take() {
+            let previous = std::mem::replace(&mut server.auth, auth);
+            server.auth.carry_over(previous);
+        }
+        let view = Arc::clone(&shared.view.read().unwrap());
+        drop(shared);
+        if let Err(e) = server.serve_request(&view) {
+            error!("Replica failed to serve a request: {e:?}");
+        }
+    }
+}
+
+struct Server {
+    transport: Arc<dyn Transport>,
+    auth: Auth,
+    tracer: Tracer,
+    request_buffer: Vec<u8>,
+    payload_buffer: Vec<u8>,
+}
+
+impl Server {
+    fn new(transport: Arc<dyn Transport>, auth: Auth, max_message_size: usize) -> Server {
+        Server {
+            transport,
+            auth,
+            tracer: Tracer::new(),
+            request_buffer: vec![0; max_message_size],
+            payload_buffer: vec![0; max_message_size],
+        }
+    }
+
+    /// Wait for the next request and answer it from `bank`.
+    fn serve_request(&mut self, bank: &Bank) -> Result<()> {
+        let socket = &*self.transport;
+        let (length, sender, credentials) = match socket.recv_from(&mut self.request_buffer) {
+            Ok(received) => received,
+            Err(e) if is_timeout(&e) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if length > self.request_buffer.len() {
+            warn!("Replica rejected request of {length} bytes, over the limit");
+            return Ok(reply(socket, &sender, "413".as_bytes())?);
+        }
+        let Some(instruction) = protocol::parse_instruction(&self.request_buffer[..length]) else {
+            warn!("Replica rejected request without an instruction");
+            return Ok(reply(socket, &sender, "400".as_bytes())?);
+        };
+        let instruction = instruction.to_string();
+        let mut trace = RequestTrace::start(&instruction);
+        let result = self.answer(
+            bank,
+            &instruction,
+            length,
+            &sender,
+            credentials.as_ref(),
+            &mut trace,
+        );
+        self.tracer.record(trace.finish());
+        result
+    }
+
+    fn answer(
+        &mut self,
+        bank: &Bank,
+        instruction: &str,
+        length: usize,
+        sender: &Option<PathBuf>,
+        credentials: Option<&PeerCredentials>,
+        trace: &mut RequestTrace,
+    ) -> Result<()> {
+        let socket = &*self.transport;
+        info!("Replica received '{instruction}' instruction from client");
+        let header = match protocol::parse_header(&self.request_buffer[1..length]) {
+            Ok(header) => header,
+            Err(e) => {
+                warn!("Replica rejected '{instruction}' instruction with malformed header: {e}");
+                return Ok(respond(socket, sender, trace, "400".as_bytes())?);
+            }
+        };
+        if let Err(e) = header.protocol_version() {
+            warn!("Replica rejected '{instruction}' instruction: {e}");
+            return Ok(respond(socket, sender, trace, "505".as_bytes())?);
+        }
+        trace.phase(Phase::Validate);
+        if !INSTRUCTIONS.contains(&instruction) {
+            warn!("Replica rejected '{instruction}' instruction, it only serves reads");
+            return Ok(respond(socket, sender, trace, "403".as_bytes())?);
+        }
+        if header.tenant.is_some() {
+            warn!("Replica rejected '{instruction}' instruction for a tenant");
+            return Ok(respond(socket, sender, trace, "404".as_bytes())?);
+        }
+        let mut identity = match self.auth.authorize(instruction, &header, credentials) {
+            Ok(identity) => identity,
+            Err(e) => {
+                warn!(
+                    "Replica rejected '{instruction}' instruction from peer {credentials:?}: {e}"
+                );
+                return Ok(respond(socket, sender, trace, e.status().as_bytes())?);
+            }
+        };
+        if let Some(identity) = &mut identity {
+            extend_identity(identity, bank);
+        }
+        let payload = if protocol::has_payload(instruction) {
+            match recv_payload(socket, sender, trace, &mut self.payload_buffer) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    error!("Replica failed to receive the '{instruction}' payload: {e:?}");
+                    return Ok(());
+                }
+            }
+        } else {
+            &[]
+        };
+        if let Err(e) = self.auth.verify_signature(instruction, &header, payload) {
+            warn!("Replica rejected '{instruction}' instruction: {e}");
+            return Ok(respond(socket, sender, trace, e.status().as_bytes())?);
+        }
+        match instruction {
+            "h" => answer_history_query(
+                socket,
+                sender,
+                trace,
+                &self.auth,
+                identity.as_ref(),
+                bank,
+                payload,
+            ),
+            "j" => answer_account_search(socket, sender, trace, &header, bank, payload),
+            _ => {
+                trace.phase(Phase::Apply);
+                let format = header.format.unwrap_or_default();
+                let filter = AccountFilter::default();
+                let listing = bank.list_accounts(format, header.page(), &filter)?;
+                Ok(respond(socket, sender, trace, listing.as_bytes())?)
+            }
+        }
+    }
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::{Balance, Currency, Money};
+    use crate::transport::MockTransport;
+    use crate::{AccountRef, TxInfo};
+
+    fn view(replica: &Replica) -> Arc<Bank> {
+        Arc::clone(&replica.shared.view.read().unwrap())
+    }
+
+    #[test]
+    fn reads_are_served_from_the_copy_last_published() {
+        let mut bank = Bank::new();
+        for name in ["a", "b"] {
+            bank.open_account(name.to_string(), Balance::from_minor(100), Currency::EUR)
+                .unwrap();
+        }
+        // Without the thread, which would wait for requests.
+        let mut replica = Replica {
+            shared: Arc::new(Shared {
+                view: RwLock::new(Arc::new(Bank::new())),
+                auth: Mutex::new(None),
+            }),
+            published: None,
+        };
+        replica.publish(&bank);
+        let opened = bank.ledger().entries().len();
+        bank.handle_transaction(TxInfo {
+            from: AccountRef::Name("a".to_string()),
+            to: AccountRef::Name("b".to_string()),
+            amount: Money::from_minor(30),
+            currency: None,
+            convert: false,
+            pin: None,
+            memo: None,
+            external_ref: None,
+            idempotency_key: None,
+            dry_run: false,
+        })
+        .unwrap();
+        let before = view(&replica);
+        assert_eq!(before.ledger().entries().len(), opened);
+
+        let transport = Arc::new(MockTransport::default());
+        let mut server = Server::new(transport.clone(), Auth::default(), 4096);
+        transport.send("client", "i");
+        server.serve_request(&before).unwrap();
+        assert!(transport.replies("client")[0].contains(r#""balance":"1.00""#));
+
+        replica.publish(&bank);
+        let after = view(&replica);
+        assert_eq!(after.ledger().entries().len(), opened + 1);
+        replica.publish(&bank);
+        assert!(Arc::ptr_eq(&after, &view(&replica)));
+
+        transport.send("client", "h");
+        transport.send("client", r#"{"account":"b"}"#);
+        transport.send("client", "t");
+        transport.send("client", r#"i{"tenant":"other"}"#);
+        for _ in 0..3 {
+            server.serve_request(&after).unwrap();
+        }
+        let replies = transport.replies("client");
+        assert_eq!(replies[1], "200");
+        assert!(replies[2].contains(r#""amount":"0.30""#));
+        assert_eq!(replies[3..], ["403", "404"]);
+    }
+}
@@ -0,0 +1,75 @@
+//! Review of large transfers. A transfer of more than the configured
+//! `review_threshold`, in the sending account's currency, is checked and
+//! parked instead of made. An admin lists the parked transfers with the
+//! `parked_transfers` admin command and makes one with `approve_transfer`
+//! or drops it with `reject_transfer`, e.g.
+//! `{"op": "approve_transfer", "review_id": 1}`. Nothing moves until then.
+//!
+//! Applies to single transfers, including those out of joint accounts once
+//! their owners approved them, but not to transfers to peer banks, batches,
+//! scheduled transfers or standing orders.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::TxInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct ReviewId(u64);
+
+impl Display for ReviewId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "review{}", self.0)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Parked transfer {0} not found")]
+pub struct ReviewNotFoundError(pub ReviewId);
+
+/// A transfer waiting for an admin.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ParkedTransfer {
+    pub review_id: ReviewId,
+    /// With both accounts given by ID and the PIN, already checked, removed.
+    pub transfer: TxInfo,
+    /// Identity that sent the transfer, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_by: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub parked_at: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Reviews {
+    parked: BTreeMap<ReviewId, ParkedTransfer>,
+    last_id: u64,
+}
+
+impl Reviews {
+    pub fn next_id(&self) -> ReviewId {
+        ReviewId(self.last_id + 1)
+    }
+
+    pub fn insert(&mut self, parked: ParkedTransfer) {
+        self.last_id = self.last_id.max(parked.review_id.0);
+        self.parked.insert(parked.review_id, parked);
+    }
+
+    /// Oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &ParkedTransfer> {
+        self.parked.values()
+    }
+
+    pub fn get(&self, id: ReviewId) -> Option<&ParkedTransfer> {
+        self.parked.get(&id)
+    }
+
+    pub fn remove(&mut self, id: ReviewId) -> Option<ParkedTransfer> {
+        self.parked.remove(&id)
+    }
+}
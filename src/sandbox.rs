@@ -0,0 +1,57 @@
+//! Sandboxes: disposable banks served next to the real ones, for
+//! integration tests and demos. A sandbox has accounts and a ledger of its
+//! own, kept in memory only, and is addressed like a tenant, with the
+//! `tenant` field of the request header.
+//!
+//! Admins create and reset them with the `z` instruction, e.g.
+//! `{"op": "reset", "name": "demo"}`. A reset sandbox starts as a copy of
+//! the bank the request is for, or without accounts with `"empty": true`.
+//! Resetting it again throws away whatever happened in it since.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SandboxError {
+    #[error("'{0}' is a configured tenant, not a sandbox")]
+    NotASandbox(String),
+    #[error("Sandbox '{0}' not found")]
+    NotFound(String),
+    #[error("Tenant '{0}' not found")]
+    UnknownTenant(String),
+    #[error("Unable to start sandbox '{name}': {reason}")]
+    Start { name: String, reason: String },
+}
+
+impl SandboxError {
+    /// Reply to a failed command.
+    pub fn status(&self) -> &'static str {
+        match self {
+            SandboxError::NotFound(_) | SandboxError::UnknownTenant(_) => "404",
+            SandboxError::NotASandbox(_) => "409",
+            SandboxError::Start { .. } => "422",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SandboxCommand {
+    /// Create the sandbox, or start it over if it exists.
+    Reset {
+        name: String,
+        #[serde(default)]
+        empty: bool,
+    },
+    Drop {
+        name: String,
+    },
+    List,
+}
+
+/// Reply to the `reset` command.
+#[derive(Debug, Serialize)]
+pub struct SandboxInfo {
+    pub name: String,
+    pub accounts: usize,
+}
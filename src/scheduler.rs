@@ -0,0 +1,351 @@
+//! Transfers submitted now to be made later.
+//!
+//! Sent as the JSON payload of the `s` instruction, e.g.
+//! `{"op": "schedule", "execute_at": 1767225600, "from": "patko", "to":
+//! "siska", "amount": "20"}`, `{"op": "list", "account": "patko"}` or
+//! `{"op": "cancel", "schedule_id": 1}`. The accounts and PIN are checked on
+//! submission, the funds only when the transfer is due.
+//!
+//! Standing orders repeat a transfer, e.g. `{"op": "create_standing_order",
+//! "every": "monday", "from": "patko", "to": "siska", "amount": "100"}`, and
+//! are managed with `list_standing_orders` and `delete_standing_order`. When
+//! the funds are short a payment is skipped, or with
+//! `"if_insufficient_funds": "retry"` tried again every hour until the next
+//! one is due. Times of day and weekdays are in UTC.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AccountId, AccountRef, TxInfo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct ScheduleId(u64);
+
+impl Display for ScheduleId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "scheduled{}", self.0)
+    }
+}
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+pub const RETRY_INTERVAL_SECS: u64 = 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct StandingOrderId(u64);
+
+impl Display for StandingOrderId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "order{}", self.0)
+    }
+}
+
+/// How often a standing order pays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Every {
+    Day,
+    /// Every seven days from the first payment.
+    Week,
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Every {
+    /// The first payment at or after `start`, at the same time of day.
+    fn first_at(self, start: u64) -> u64 {
+        let weekday = match self {
+            Every::Day | Every::Week => return start,
+            Every::Monday => 0,
+            Every::Tuesday => 1,
+            Every::Wednesday => 2,
+            Every::Thursday => 3,
+            Every::Friday => 4,
+            Every::Saturday => 5,
+            Every::Sunday => 6,
+        };
+        // The epoch fell on a Thursday.
+        let start_weekday = (start / DAY_SECS + 3) % 7;
+        start + (weekday + 7 - start_weekday) % 7 * DAY_SECS
+    }
+
+    fn next_after(self, run_at: u64) -> u64 {
+        match self {
+            Every::Day => run_at + DAY_SECS,
+            _ => run_at + 7 * DAY_SECS,
+        }
+    }
+}
+
+/// What a standing order does when the paying account is short of funds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InsufficientFunds {
+    #[default]
+    Skip,
+    Retry,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ScheduleCommand {
+    Schedule {
+        /// Seconds since the Unix epoch.
+        execute_at: u64,
+        #[serde(flatten)]
+        transfer: TxInfo,
+    },
+    /// Pending transfers paid from the account.
+    List {
+        account: AccountRef,
+    },
+    Cancel {
+        schedule_id: ScheduleId,
+    },
+    CreateStandingOrder {
+        every: Every,
+        /// Seconds since the Unix epoch, now if not given.
+        #[serde(default)]
+        starting_at: Option<u64>,
+        #[serde(default)]
+        if_insufficient_funds: InsufficientFunds,
+        #[serde(flatten)]
+        transfer: TxInfo,
+    },
+    /// Standing orders paid from the account.
+    ListStandingOrders {
+        account: AccountRef,
+    },
+    DeleteStandingOrder {
+        order_id: StandingOrderId,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScheduledTransfer {
+    pub schedule_id: ScheduleId,
+    pub execute_at: u64,
+    /// With both accounts given by ID and the PIN, already checked, removed.
+    #[serde(flatten)]
+    pub transfer: TxInfo,
+}
+
+impl ScheduledTransfer {
+    pub fn account(&self) -> AccountId {
+        match self.transfer.from {
+            AccountRef::Id(id) => id,
+            AccountRef::Name(_) => unreachable!("scheduled transfers refer to accounts by ID"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StandingOrder {
+    pub order_id: StandingOrderId,
+    pub every: Every,
+    pub if_insufficient_funds: InsufficientFunds,
+    /// The next regular payment, in seconds since the Unix epoch.
+    pub next_run_at: u64,
+    /// Set while a payment that found the funds short is being retried.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<u64>,
+    /// With both accounts given by ID and the PIN, already checked, removed.
+    #[serde(flatten)]
+    pub transfer: TxInfo,
+}
+
+impl StandingOrder {
+    pub fn account(&self) -> AccountId {
+        match self.transfer.from {
+            AccountRef::Id(id) => id,
+            AccountRef::Name(_) => unreachable!("standing orders refer to accounts by ID"),
+        }
+    }
+
+    fn due_at(&self) -> u64 {
+        self.retry_at.unwrap_or(self.next_run_at)
+    }
+
+    /// Move on after a payment attempted at `now`. Payments missed while the
+    /// server was down are not made up for.
+    pub fn reschedule(&mut self, now: u64, short_of_funds: bool) {
+        while self.next_run_at <= now {
+            self.next_run_at = self.every.next_after(self.next_run_at);
+        }
+        let retry_at = now + RETRY_INTERVAL_SECS;
+        self.retry_at = (short_of_funds
+            && self.if_insufficient_funds == InsufficientFunds::Retry
+            && retry_at < self.next_run_at)
+            .then_some(retry_at);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    pending: BTreeMap<ScheduleId, ScheduledTransfer>,
+    last_id: u64,
+    standing_orders: BTreeMap<StandingOrderId, StandingOrder>,
+    last_order_id: u64,
+}
+
+impl Scheduler {
+    /// The transfer under the next ID, to be inserted with `insert`.
+    pub fn create(&self, execute_at: u64, transfer: TxInfo) -> ScheduledTransfer {
+        ScheduledTransfer {
+            schedule_id: ScheduleId(self.last_id + 1),
+            execute_at,
+            transfer,
+        }
+    }
+
+    pub fn insert(&mut self, scheduled: ScheduledTransfer) {
+        self.last_id = self.last_id.max(scheduled.schedule_id.0);
+        self.pending.insert(scheduled.schedule_id, scheduled);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ScheduledTransfer> {
+        self.pending.values()
+    }
+
+    pub fn get(&self, schedule_id: ScheduleId) -> Option<&ScheduledTransfer> {
+        self.pending.get(&schedule_id)
+    }
+
+    pub fn remove(&mut self, schedule_id: ScheduleId) -> Option<ScheduledTransfer> {
+        self.pending.remove(&schedule_id)
+    }
+
+    /// Number of transfers waiting for their time.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn standing_order_count(&self) -> usize {
+        self.standing_orders.len()
+    }
+
+    /// Pending transfers paid from `account`, in order of submission.
+    pub fn for_account(&self, account: AccountId) -> Vec<&ScheduledTransfer> {
+        self.pending
+            .values()
+            .filter(|scheduled| scheduled.account() == account)
+            .collect()
+    }
+
+    /// The transfers due by `now`, earliest first.
+    pub fn due(&self, now: u64) -> Vec<ScheduleId> {
+        let mut due: Vec<(u64, ScheduleId)> = self
+            .pending
+            .values()
+            .filter(|scheduled| scheduled.execute_at <= now)
+            .map(|scheduled| (scheduled.execute_at, scheduled.schedule_id))
+            .collect();
+        due.sort();
+        due.into_iter()
+            .map(|(_, schedule_id)| schedule_id)
+            .collect()
+    }
+
+    /// The order under the next ID, to be inserted with
+    /// `insert_standing_order`.
+    pub fn create_standing_order(
+        &self,
+        every: Every,
+        starting_at: u64,
+        if_insufficient_funds: InsufficientFunds,
+        transfer: TxInfo,
+    ) -> StandingOrder {
+        StandingOrder {
+            order_id: StandingOrderId(self.last_order_id + 1),
+            every,
+            if_insufficient_funds,
+            next_run_at: every.first_at(starting_at),
+            retry_at: None,
+            transfer,
+        }
+    }
+
+    pub fn insert_standing_order(&mut self, order: StandingOrder) {
+        self.last_order_id = self.last_order_id.max(order.order_id.0);
+        self.standing_orders.insert(order.order_id, order);
+    }
+
+    pub fn standing_orders(&self) -> impl Iterator<Item = &StandingOrder> {
+        self.standing_orders.values()
+    }
+
+    /// The last IDs given out, of transfers and of standing orders.
+    pub fn last_ids(&self) -> (u64, u64) {
+        (self.last_id, self.last_order_id)
+    }
+
+    /// Give out no IDs up to those, even if their transfers and orders are
+    /// gone.
+    pub fn skip_ids(&mut self, (last_id, last_order_id): (u64, u64)) {
+        self.last_id = self.last_id.max(last_id);
+        self.last_order_id = self.last_order_id.max(last_order_id);
+    }
+
+    pub fn standing_order(&self, order_id: StandingOrderId) -> Option<&StandingOrder> {
+        self.standing_orders.get(&order_id)
+    }
+
+    pub fn standing_order_mut(&mut self, order_id: StandingOrderId) -> Option<&mut StandingOrder> {
+        self.standing_orders.get_mut(&order_id)
+    }
+
+    pub fn remove_standing_order(&mut self, order_id: StandingOrderId) -> Option<StandingOrder> {
+        self.standing_orders.remove(&order_id)
+    }
+
+    /// Standing orders paid from `account`, in order of creation.
+    pub fn standing_orders_for(&self, account: AccountId) -> Vec<&StandingOrder> {
+        self.standing_orders
+            .values()
+            .filter(|order| order.account() == account)
+            .collect()
+    }
+
+    /// The standing orders with a payment due by `now`, earliest first.
+    pub fn due_standing_orders(&self, now: u64) -> Vec<StandingOrderId> {
+        let mut due: Vec<(u64, StandingOrderId)> = self
+            .standing_orders
+            .values()
+            .filter(|order| order.due_at() <= now)
+            .map(|order| (order.due_at(), order.order_id))
+            .collect();
+        due.sort();
+        due.into_iter().map(|(_, order_id)| order_id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2026-01-05, a Monday, at noon.
+    const MONDAY_NOON: u64 = 1_767_614_400;
+
+    #[test]
+    fn weekday_orders_start_on_that_weekday() {
+        assert_eq!(Every::Monday.first_at(MONDAY_NOON), MONDAY_NOON);
+        assert_eq!(
+            Every::Wednesday.first_at(MONDAY_NOON),
+            MONDAY_NOON + 2 * DAY_SECS
+        );
+        assert_eq!(
+            Every::Sunday.first_at(MONDAY_NOON + DAY_SECS),
+            MONDAY_NOON + 6 * DAY_SECS
+        );
+        assert_eq!(Every::Day.first_at(MONDAY_NOON), MONDAY_NOON);
+    }
+}
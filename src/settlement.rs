@@ -0,0 +1,110 @@
+//! Netting and settlement of what the bank and its peer banks owe each
+//! other, see `interbank`. Every transfer to or from a peer is an obligation
+//! queued on the peer's settlement account. Every `settlement_period_secs`,
+//! or on the `settle` admin command, the obligations of each settlement
+//! account are netted and settled with a single transfer between it and the
+//! clearing system account, which brings its balance back to zero.
+//!
+//! A batch is the obligations on an account since the previous settlement,
+//! closed by the settling transfer. Batches are read back from the ledger,
+//! so they survive restarts.
+
+use serde::Serialize;
+
+use crate::ledger::{LedgerEntry, Side, SystemAccount, TransactionId};
+use crate::money::{Currency, Money, Total};
+use crate::AccountId;
+
+pub const MEMO: &str = "settlement";
+pub const DEFAULT_PERIOD_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Obligation {
+    pub transaction_id: TransactionId,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Credit when the bank owes the amount to the peer, debit when the
+    /// peer owes it to the bank.
+    pub side: Side,
+    pub amount: Money,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SettlementBatch {
+    pub account: AccountId,
+    pub currency: Currency,
+    pub obligations: Vec<Obligation>,
+    /// Owed to the peer, before netting.
+    pub owed_to_peer: Total,
+    /// Owed by the peer, before netting.
+    pub owed_by_peer: Total,
+    /// Owed to the peer after netting, negative when the peer owes.
+    pub net: Total,
+    /// Transaction that settled the batch, `None` while it is open.
+    pub settled_by: Option<TransactionId>,
+    pub settled_at: Option<u64>,
+}
+
+impl SettlementBatch {
+    fn new(account: AccountId, currency: Currency) -> SettlementBatch {
+        SettlementBatch {
+            account,
+            currency,
+            obligations: Vec::new(),
+            owed_to_peer: Total::default(),
+            owed_by_peer: Total::default(),
+            net: Total::default(),
+            settled_by: None,
+            settled_at: None,
+        }
+    }
+}
+
+/// The batches of the settlement account `account`, oldest first, with
+/// the open one last unless it is empty.
+pub fn batches<'a>(
+    entries: impl IntoIterator<Item = &'a LedgerEntry>,
+    account: AccountId,
+    currency: Currency,
+) -> Vec<SettlementBatch> {
+    let mut batches = Vec::new();
+    let mut batch = SettlementBatch::new(account, currency);
+    for entry in entries {
+        let side = if entry.to == Some(account) {
+            Side::Credit
+        } else if entry.from == Some(account) {
+            Side::Debit
+        } else {
+            continue;
+        };
+        if entry.system == Some(SystemAccount::Clearing) {
+            batch.settled_by = Some(entry.transaction_id);
+            batch.settled_at = Some(entry.timestamp);
+            batches.push(std::mem::replace(
+                &mut batch,
+                SettlementBatch::new(account, currency),
+            ));
+            continue;
+        }
+        match side {
+            Side::Credit => {
+                batch.owed_to_peer.add_amount(entry.amount);
+                batch.net.add_amount(entry.amount);
+            }
+            Side::Debit => {
+                batch.owed_by_peer.add_amount(entry.amount);
+                batch.net.sub_amount(entry.amount);
+            }
+        }
+        batch.obligations.push(Obligation {
+            transaction_id: entry.transaction_id,
+            timestamp: entry.timestamp,
+            side,
+            amount: entry.amount,
+        });
+    }
+    if !batch.obligations.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
@@ -0,0 +1,82 @@
+//! Batches of independent transfers, checked in parallel, see
+//! `BatchRequest::independent`.
+//!
+//! The accounts are split into shards by ID, one shard per worker. A
+//! transfer whose accounts, a fee account included, are all in one shard is
+//! checked by that shard's worker, in the order of the batch and against the
+//! balances the transfers before it in the shard leave. No other worker
+//! touches those accounts, so the transfers that pass are then committed as
+//! they were checked, in the order of the batch, as the event log takes one
+//! event at a time.
+//!
+//! Transfers between shards are applied after the others, one at a time and
+//! in order, so they see the balances everything before them left. As with
+//! atomic batches, fraud rules and policies see the bank as it was before
+//! the batch.
+
+use std::num::NonZeroUsize;
+use std::thread;
+
+use crate::AccountId;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Partition {
+    /// Positions of the transfers within each shard, in order.
+    pub shards: Vec<Vec<usize>>,
+    /// Positions of the transfers between shards, in order.
+    pub cross_shard: Vec<usize>,
+}
+
+/// Workers when none are configured: one per CPU.
+pub fn default_workers() -> usize {
+    thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+pub fn shard_of(account: AccountId, shards: usize) -> usize {
+    (account.0 % shards as u64) as usize
+}
+
+/// Split transfers into `shards` by the accounts each of them touches.
+pub fn partition(touched: &[Vec<AccountId>], shards: usize) -> Partition {
+    let shards = shards.max(1);
+    let mut partition = Partition {
+        shards: vec![Vec::new(); shards],
+        cross_shard: Vec::new(),
+    };
+    for (position, accounts) in touched.iter().enumerate() {
+        let mut owning = accounts.iter().map(|&account| shard_of(account, shards));
+        let Some(shard) = owning.next() else {
+            partition.cross_shard.push(position);
+            continue;
+        };
+        if owning.all(|other| other == shard) {
+            partition.shards[shard].push(position);
+        } else {
+            partition.cross_shard.push(position);
+        }
+    }
+    partition
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfers_between_shards_are_set_apart() {
+        let touched = [
+            vec![AccountId(1), AccountId(3)],
+            vec![AccountId(2), AccountId(4)],
+            vec![AccountId(1), AccountId(2)],
+            vec![AccountId(3), AccountId(1), AccountId(5)],
+        ];
+        assert_eq!(
+            partition(&touched, 2),
+            Partition {
+                shards: vec![vec![1], vec![0, 3]],
+                cross_shard: vec![2],
+            }
+        );
+        assert_eq!(partition(&touched, 1).shards, [vec![0, 1, 2, 3]]);
+    }
+}
@@ -0,0 +1,190 @@
+//! HMAC request signing for transports where being able to reach the socket
+//! says nothing about who the sender is.
+//!
+//! A signed request carries `key_id`, `nonce` and `signature` in its header.
+//! The signature is the hex HMAC-SHA256, under the key named by `key_id`, of
+//! the tenant followed by a zero byte if the request names one, the
+//! instruction byte, the nonce as 8 big-endian bytes and the payload datagram
+//! (empty for instructions without one).
+//!
+//! The nonce is the time the request was signed, in milliseconds since the
+//! Unix epoch. The server refuses nonces more than `WINDOW_MS` from its own
+//! clock, nonces not above the last one of the key, and nonces from before
+//! it started or, in a cluster or standby pair, took over writes. A captured
+//! request so cannot be sent again, to this server or one taking over from
+//! it, even though no server keeps the nonces it saw.
+
+use std::collections::HashMap;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::auth::AuthError;
+use crate::crypto::{self, HmacSha256};
+use crate::protocol::RequestHeader;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SigningConfig {
+    /// Hex-encoded shared secrets, by key id.
+    pub keys: HashMap<String, String>,
+}
+
+/// Compute the signature a client must send.
+pub fn sign(
+    key: &[u8],
+    tenant: Option<&str>,
+    instruction: &str,
+    nonce: u64,
+    payload: &[u8],
+) -> crypto::Digest {
+    let mut mac = HmacSha256::new(key);
+    if let Some(tenant) = tenant {
+        mac.update(tenant.as_bytes());
+        mac.update(&[0]);
+    }
+    mac.update(instruction.as_bytes());
+    mac.update(&nonce.to_be_bytes());
+    mac.update(payload);
+    mac.finalize()
+}
+
+/// Milliseconds a request's nonce may be off the server's clock, either
+/// way.
+pub const WINDOW_MS: u64 = 30_000;
+
+/// Milliseconds since the Unix epoch.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64)
+}
+
+/// Verifies signatures and tracks the last nonce seen for each key.
+#[derive(Debug, Default)]
+pub struct RequestVerifier {
+    enabled: bool,
+    keys: HashMap<String, Vec<u8>>,
+    last_nonces: HashMap<String, u64>,
+    /// Nonces up to this one are refused, whatever the key.
+    refused_until: u64,
+}
+
+impl RequestVerifier {
+    pub fn from_config(config: Option<&SigningConfig>) -> io::Result<RequestVerifier> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(RequestVerifier::default()),
+        };
+        let keys = config
+            .keys
+            .iter()
+            .map(|(key_id, hex)| match crypto::from_hex(hex) {
+                Some(key) => Ok((key_id.clone(), key)),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Signing key '{key_id}' is not valid hex"),
+                )),
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(RequestVerifier {
+            enabled: true,
+            keys,
+            last_nonces: HashMap::new(),
+            refused_until: now_ms(),
+        })
+    }
+
+    /// Keep the nonces seen by `previous`, so reloading the configuration
+    /// does not reopen the replay window.
+    pub fn carry_over(&mut self, previous: RequestVerifier) {
+        self.last_nonces = previous.last_nonces;
+        if previous.enabled {
+            self.refused_until = previous.refused_until;
+        }
+    }
+
+    /// Refuse requests signed until now, which another server may have
+    /// served.
+    pub fn refuse_earlier(&mut self) {
+        self.refused_until = now_ms();
+    }
+
+    pub fn verify(
+        &mut self,
+        instruction: &str,
+        header: &RequestHeader,
+        payload: &[u8],
+    ) -> Result<(), AuthError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let (key_id, nonce, signature) = match (&header.key_id, header.nonce, &header.signature) {
+            (Some(key_id), Some(nonce), Some(signature)) => (key_id, nonce, signature),
+            _ => return Err(AuthError::Unauthenticated),
+        };
+        let key = self.keys.get(key_id).ok_or(AuthError::Unauthenticated)?;
+        let signature = crypto::from_hex(signature).ok_or(AuthError::Unauthenticated)?;
+        let expected = sign(key, header.tenant.as_deref(), instruction, nonce, payload);
+        if !crypto::constant_time_eq(&expected, &signature) {
+            return Err(AuthError::Unauthenticated);
+        }
+        if nonce.abs_diff(now_ms()) > WINDOW_MS {
+            return Err(AuthError::Expired);
+        }
+        let last_nonce = self.last_nonces.get(key_id).copied().unwrap_or(0);
+        if nonce <= last_nonce.max(self.refused_until) {
+            return Err(AuthError::Replayed);
+        }
+        self.last_nonces.insert(key_id.clone(), nonce);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn signed(verifier: &mut RequestVerifier, nonce: u64) -> Result<(), AuthError> {
+        let signature = sign(b"secret", None, "t", nonce, b"{}");
+        let header = RequestHeader {
+            key_id: Some("client".to_string()),
+            nonce: Some(nonce),
+            signature: Some(crypto::to_hex(&signature)),
+            ..RequestHeader::default()
+        };
+        verifier.verify("t", &header, b"{}")
+    }
+
+    #[test]
+    fn requests_are_refused_once_and_after_the_window() {
+        let config = SigningConfig {
+            keys: HashMap::from([("client".to_string(), crypto::to_hex(b"secret"))]),
+        };
+        let mut verifier = RequestVerifier::from_config(Some(&config)).unwrap();
+        let start = verifier.refused_until;
+        assert_eq!(signed(&mut verifier, start), Err(AuthError::Replayed));
+        assert_eq!(signed(&mut verifier, start + 1), Ok(()));
+        assert_eq!(signed(&mut verifier, start + 1), Err(AuthError::Replayed));
+        assert_eq!(
+            signed(&mut verifier, start + 2 * WINDOW_MS),
+            Err(AuthError::Expired)
+        );
+        assert_eq!(signed(&mut verifier, start + 2), Ok(()));
+
+        // A restarted server, or one taking over, has not seen the requests.
+        thread::sleep(Duration::from_millis(5));
+        let mut restarted = RequestVerifier::from_config(Some(&config)).unwrap();
+        assert_eq!(signed(&mut restarted, start + 2), Err(AuthError::Replayed));
+        verifier.refuse_earlier();
+        assert_eq!(signed(&mut verifier, start + 3), Err(AuthError::Replayed));
+        let mut reloaded = RequestVerifier::from_config(Some(&config)).unwrap();
+        reloaded.carry_over(verifier);
+        assert_eq!(signed(&mut reloaded, start + 3), Err(AuthError::Replayed));
+        assert_eq!(signed(&mut reloaded, now_ms() + 1), Ok(()));
+    }
+}
@@ -0,0 +1,329 @@
+//! Deterministic simulation of the bank. A seeded random sequence of
+//! operations, account openings, transfers, reversals, status changes,
+//! time passing and crashes, is run against a `Bank` in-process, and the
+//! invariants are checked after every step. A failure names the seed and
+//! the step, and running the same seed again replays the same operations.
+//!
+//! `cargo test simulation` runs a range of seeds; set `SIMULATION_SEED` to
+//! run only one, e.g. to reproduce a failure.
+
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::clock::ManualClock;
+use crate::invariants::{self, Violation};
+use crate::ledger::TransactionId;
+use crate::money::{Balance, Currency, Money};
+use crate::{AccountName, AccountRef, AccountStatus, Bank, TxInfo};
+
+/// Events between snapshots of a persistent bank, small so that crashes
+/// fall on both sides of them.
+const SNAPSHOT_EVERY: u64 = 5;
+
+/// splitmix64, good enough to pick operations and small enough to keep
+/// runs reproducible without a dependency.
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Below `bound`, which must not be zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Operation {
+    Open {
+        name: String,
+        balance: u64,
+    },
+    Transfer {
+        from: String,
+        to: String,
+        amount: u64,
+    },
+    Reverse(TransactionId),
+    SetStatus {
+        name: String,
+        status: AccountStatus,
+    },
+    Advance(u64),
+    RunDueJobs,
+    /// Drop the bank and open it again from its data directory.
+    Crash,
+}
+
+/// What the operations change and a crash must not lose.
+#[derive(Debug, PartialEq, Eq)]
+struct State {
+    accounts: Vec<(AccountName, Balance, AccountStatus)>,
+    ledger_entries: usize,
+    last_sequence: u64,
+}
+
+impl State {
+    fn of(bank: &Bank) -> State {
+        let mut accounts: Vec<_> = bank
+            .accounts
+            .values()
+            .map(|account| (account.name.clone(), account.balance, account.status))
+            .collect();
+        accounts.sort_by(|a, b| a.0.cmp(&b.0));
+        State {
+            accounts,
+            ledger_entries: bank.ledger.entries().len(),
+            last_sequence: bank.events.last_sequence(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Failure {
+    /// The invariants stopped holding.
+    Inconsistent(Vec<Violation>),
+    /// A refused operation changed the bank.
+    ChangedOnError(String),
+    /// The bank came back from a crash different, or not at all.
+    LostOnCrash(String),
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Inconsistent(violations) => write!(f, "violated {violations:?}"),
+            Failure::ChangedOnError(e) => write!(f, "refused with '{e}' but changed the bank"),
+            Failure::LostOnCrash(e) => write!(f, "lost in a crash: {e}"),
+        }
+    }
+}
+
+/// Where and how a run failed.
+#[derive(Debug)]
+pub struct SimulationError {
+    pub seed: u64,
+    pub step: usize,
+    operation: Operation,
+    pub failure: Failure,
+}
+
+impl Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Seed {} failed at step {} ({:?}): {}",
+            self.seed, self.step, self.operation, self.failure
+        )
+    }
+}
+
+/// A finished run, to compare runs of the same seed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Summary {
+    state: State,
+    refused: usize,
+}
+
+struct Simulation<'a> {
+    rng: Rng,
+    clock: Arc<ManualClock>,
+    bank: Bank,
+    data_dir: Option<&'a Path>,
+    names: Vec<String>,
+}
+
+impl Simulation<'_> {
+    fn operation(&mut self) -> Operation {
+        let pick = |rng: &mut Rng, names: &[String]| {
+            // Now and then an account that does not exist.
+            match rng.below(names.len() as u64 + 1) as usize {
+                index if index < names.len() => names[index].clone(),
+                _ => "missing".to_string(),
+            }
+        };
+        match self.rng.below(100) {
+            _ if self.names.len() < 2 => self.open(),
+            0..=9 => self.open(),
+            10..=64 => Operation::Transfer {
+                from: pick(&mut self.rng, &self.names),
+                to: pick(&mut self.rng, &self.names),
+                amount: self.rng.below(2_000),
+            },
+            65..=74 => {
+                let last = self.bank.ledger.next_transaction_id().0 - 1;
+                Operation::Reverse(TransactionId(self.rng.below(last + 1)))
+            }
+            75..=82 => Operation::SetStatus {
+                name: pick(&mut self.rng, &self.names),
+                status: match self.rng.below(6) {
+                    0 => AccountStatus::Frozen,
+                    1 => AccountStatus::Closed,
+                    _ => AccountStatus::Active,
+                },
+            },
+            83..=89 => Operation::Advance(self.rng.below(2 * 24 * 60 * 60)),
+            90..=94 => Operation::RunDueJobs,
+            _ if self.data_dir.is_some() => Operation::Crash,
+            _ => Operation::RunDueJobs,
+        }
+    }
+
+    fn open(&mut self) -> Operation {
+        Operation::Open {
+            name: format!("account{}", self.names.len() + 1),
+            balance: self.rng.below(5_000),
+        }
+    }
+
+    /// Apply `operation`, returning whether the bank refused it.
+    fn apply(&mut self, operation: &Operation) -> Result<bool, Failure> {
+        let before = State::of(&self.bank);
+        let result = match operation.clone() {
+            Operation::Open { name, balance } => {
+                self.names.push(name.clone());
+                self.bank
+                    .open_account(name, Balance::from_minor(balance as i64), Currency::EUR)
+                    .map(drop)
+            }
+            Operation::Transfer { from, to, amount } => self
+                .bank
+                .handle_transaction(TxInfo {
+                    from: AccountRef::Name(from),
+                    to: AccountRef::Name(to),
+                    amount: Money::from_minor(amount),
+                    currency: None,
+                    convert: false,
+                    pin: None,
+                    memo: None,
+                    external_ref: None,
+                    idempotency_key: None,
+                    dry_run: false,
+                })
+                .map(drop),
+            Operation::Reverse(transaction_id) => self.bank.reverse(transaction_id).map(drop),
+            Operation::SetStatus { name, status } => {
+                self.bank.set_status(&AccountRef::Name(name), status)
+            }
+            Operation::Advance(secs) => {
+                self.clock.advance(secs);
+                Ok(())
+            }
+            Operation::RunDueJobs => {
+                self.bank.run_due_jobs();
+                Ok(())
+            }
+            Operation::Crash => return self.crash(before).map(|()| false),
+        };
+        match result {
+            Err(e) if State::of(&self.bank) != before => {
+                Err(Failure::ChangedOnError(e.to_string()))
+            }
+            result => Ok(result.is_err()),
+        }
+    }
+
+    fn crash(&mut self, before: State) -> Result<(), Failure> {
+        let data_dir = self.data_dir.expect("crashes need a data directory");
+        // Drop the bank first, as a crashed process would have.
+        self.bank = Bank::with_clock(self.clock.clone());
+        self.bank = Bank::open(self.clock.clone(), data_dir, SNAPSHOT_EVERY)
+            .map_err(|e| Failure::LostOnCrash(e.to_string()))?;
+        let after = State::of(&self.bank);
+        if after != before {
+            return Err(Failure::LostOnCrash(format!(
+                "had {before:?}, came back with {after:?}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Run `steps` operations picked by `seed`, keeping the bank in `data_dir`
+/// if given, which has to be empty. Crashes only happen with one.
+pub fn run(seed: u64, steps: usize, data_dir: Option<&Path>) -> Result<Summary, SimulationError> {
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let bank = match data_dir {
+        Some(data_dir) => Bank::open(clock.clone(), data_dir, SNAPSHOT_EVERY)
+            .expect("unable to open the simulated bank"),
+        None => Bank::with_clock(clock.clone()),
+    };
+    let mut simulation = Simulation {
+        rng: Rng(seed),
+        clock,
+        bank,
+        data_dir,
+        names: Vec::new(),
+    };
+    let mut refused = 0;
+    for step in 1..=steps {
+        let operation = simulation.operation();
+        let fail = |failure| SimulationError {
+            seed,
+            step,
+            operation: operation.clone(),
+            failure,
+        };
+        refused += usize::from(simulation.apply(&operation).map_err(fail)?);
+        let report = invariants::check(&simulation.bank);
+        if !report.consistent {
+            return Err(fail(Failure::Inconsistent(report.violations)));
+        }
+    }
+    Ok(Summary {
+        state: State::of(&simulation.bank),
+        refused,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: usize = 300;
+
+    fn seeds() -> Vec<u64> {
+        match std::env::var("SIMULATION_SEED") {
+            Ok(seed) => vec![seed.parse().expect("SIMULATION_SEED is not a number")],
+            Err(_) => (0..20).collect(),
+        }
+    }
+
+    #[test]
+    fn seeds_keep_the_bank_consistent() {
+        for seed in seeds() {
+            if let Err(e) = run(seed, STEPS, None) {
+                panic!("{e}");
+            }
+        }
+    }
+
+    #[test]
+    fn seeds_keep_the_bank_consistent_across_crashes() {
+        for seed in seeds() {
+            let data_dir =
+                std::env::temp_dir().join(format!("bank-simulation-{}-{seed}", std::process::id()));
+            let result = run(seed, STEPS, Some(&data_dir));
+            std::fs::remove_dir_all(&data_dir).unwrap();
+            if let Err(e) = result {
+                panic!("{e}");
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let first = run(7, STEPS, None).unwrap();
+        assert_eq!(first, run(7, STEPS, None).unwrap());
+        assert!(first.refused > 0);
+        assert!(first.state.ledger_entries > 0);
+        assert_ne!(first, run(8, STEPS, None).unwrap());
+    }
+}
@@ -0,0 +1,217 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{chown, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use crate::{users, Config};
+
+/// Credentials of the process that sent a datagram, as reported by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub pid: libc::pid_t,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+pub fn create_socket(config: &Config) -> io::Result<UnixDatagram> {
+    create_socket_at(&config.socket_path, config)
+}
+
+/// Bind a socket at `socket_path` with the permissions configured for the
+/// server's socket.
+pub fn create_socket_at(socket_path: &Path, config: &Config) -> io::Result<UnixDatagram> {
+    if socket_path.exists() {
+        fs::remove_file(socket_path)?;
+    }
+    let socket = match config.socket_mode {
+        Some(_) => {
+            // Bind under a restrictive umask so the socket is never reachable
+            // with looser permissions than configured, even briefly.
+            // SAFETY: umask cannot fail.
+            let previous_umask = unsafe { libc::umask(0o177) };
+            let socket = UnixDatagram::bind(socket_path);
+            unsafe { libc::umask(previous_umask) };
+            socket?
+        }
+        None => UnixDatagram::bind(socket_path)?,
+    };
+    apply_socket_permissions(socket_path, config)?;
+    Ok(socket)
+}
+
+fn apply_socket_permissions(socket_path: &Path, config: &Config) -> io::Result<()> {
+    let owner = config
+        .socket_owner
+        .as_deref()
+        .map(users::resolve_user)
+        .transpose()?;
+    let group = config
+        .socket_group
+        .as_deref()
+        .map(users::resolve_group)
+        .transpose()?;
+    if owner.is_some() || group.is_some() {
+        chown(socket_path, owner, group)?;
+    }
+    if let Some(mode) = config.socket_mode {
+        fs::set_permissions(socket_path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Ask the kernel to attach the sender's credentials to every datagram
+/// received on `socket`.
+pub fn enable_credentials(socket: &UnixDatagram) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    // SAFETY: the option value points to a live c_int of the given size.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PASSCRED,
+            &enable as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Like `UnixDatagram::recv_from`, but also returns the sender's credentials
+/// when `enable_credentials` was called on the socket. The sender's path is
+/// `None` for unbound or abstract sockets, which cannot be replied to. The
+/// length is that of the whole datagram, more than `buffer` holds when it
+/// was cut off.
+pub fn recv_with_credentials(
+    socket: &UnixDatagram,
+    buffer: &mut [u8],
+) -> io::Result<(usize, Option<PathBuf>, Option<PeerCredentials>)> {
+    // SAFETY: all-zero is a valid bit pattern for these C structs.
+    let mut address: libc::sockaddr_un = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    // u64 elements keep the control buffer suitably aligned for cmsghdr.
+    let mut control = [0u64; 8];
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_name = &mut address as *mut libc::sockaddr_un as *mut libc::c_void;
+    message.msg_namelen = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = mem::size_of_val(&control);
+
+    // SAFETY: every pointer in `message` refers to a live local buffer.
+    let received = unsafe {
+        libc::recvmsg(
+            socket.as_raw_fd(),
+            &mut message,
+            libc::MSG_CMSG_CLOEXEC | libc::MSG_TRUNC,
+        )
+    };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut credentials = None;
+    // SAFETY: the CMSG_* helpers walk the control buffer the kernel filled in.
+    unsafe {
+        let mut header = libc::CMSG_FIRSTHDR(&message);
+        while !header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET
+                && (*header).cmsg_type == libc::SCM_CREDENTIALS
+            {
+                let ucred = ptr::read_unaligned(libc::CMSG_DATA(header) as *const libc::ucred);
+                credentials = Some(PeerCredentials {
+                    pid: ucred.pid,
+                    uid: ucred.uid,
+                    gid: ucred.gid,
+                });
+            }
+            header = libc::CMSG_NXTHDR(&message, header);
+        }
+    }
+
+    Ok((
+        received as usize,
+        sender_path(&address, message.msg_namelen),
+        credentials,
+    ))
+}
+
+fn sender_path(address: &libc::sockaddr_un, length: libc::socklen_t) -> Option<PathBuf> {
+    let path_offset = mem::size_of::<libc::sa_family_t>();
+    let path_length = (length as usize).checked_sub(path_offset)?;
+    let path: Vec<u8> = address.sun_path[..path_length.min(address.sun_path.len())]
+        .iter()
+        .map(|&c| c as u8)
+        .take_while(|&c| c != 0)
+        .collect();
+    // An empty path means an unnamed socket, a leading NUL an abstract one.
+    if path.is_empty() {
+        return None;
+    }
+    Some(PathBuf::from(OsStr::from_bytes(&path)))
+}
+
+/// Like `UnixDatagram::recv`, but returns the length of the whole datagram,
+/// more than `buffer` holds when it was cut off.
+pub fn recv_whole_length(socket: &UnixDatagram, buffer: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: `buffer` is live and writable for its length.
+    let received = unsafe {
+        libc::recv(
+            socket.as_raw_fd(),
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+            libc::MSG_TRUNC,
+        )
+    };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(received as usize)
+}
+
+/// Like `UnixDatagram::send_to`, but fails with `WouldBlock` instead of
+/// waiting when the receiver's queue is full.
+pub fn send_to_nowait(socket: &UnixDatagram, message: &[u8], path: &Path) -> io::Result<()> {
+    // SAFETY: all-zero is a valid bit pattern for sockaddr_un.
+    let mut address: libc::sockaddr_un = unsafe { mem::zeroed() };
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let bytes = path.as_os_str().as_bytes();
+    // Leave room for the terminating NUL.
+    if bytes.len() >= address.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "socket path is too long",
+        ));
+    }
+    for (slot, &byte) in address.sun_path.iter_mut().zip(bytes) {
+        *slot = byte as libc::c_char;
+    }
+    let length = mem::size_of::<libc::sa_family_t>() + bytes.len() + 1;
+    // SAFETY: `message` and `address` are live for the duration of the call.
+    let sent = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            message.as_ptr() as *const libc::c_void,
+            message.len(),
+            libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL,
+            &address as *const libc::sockaddr_un as *const libc::sockaddr,
+            length as libc::socklen_t,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,508 @@
+//! Account statements: every movement in and out of one account over a
+//! period of days, with the balance after each, between the balance the
+//! period opened and closed with. Days are UTC.
+//!
+//! Statements are rendered as text for people, as CSV for spreadsheets and
+//! accounting tools, or as OFX or QIF for personal-finance tools, see
+//! `Statement::render`.
+
+use std::fmt::{self, Display, Write};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+use crate::ledger::{LedgerAccount, LedgerEntry, TransactionId};
+use crate::money::{Balance, Currency, Money};
+use crate::{csv, Account, AccountId};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+/// Identifies the bank to OFX readers.
+const OFX_BANK_ID: &str = "bank";
+/// Longest payee name OFX allows.
+const OFX_NAME_LENGTH: usize = 32;
+
+#[derive(Error, Debug)]
+#[error("Invalid date '{0}', expected one like \"2024-03-31\"")]
+pub struct ParseDateError(String);
+
+/// A day of the proleptic Gregorian calendar, written `YYYY-MM-DD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    year: u32,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    /// The date in UTC of a time in seconds since the Unix epoch.
+    pub fn from_timestamp(timestamp: u64) -> Date {
+        // Days since 0000-03-01, which puts leap days at the end of the year.
+        let days = timestamp / SECS_PER_DAY + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+        Date {
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+        }
+    }
+
+    /// Seconds since the Unix epoch at the start of the day, UTC.
+    pub fn timestamp(self) -> u64 {
+        let year = u64::from(self.year) - u64::from(self.month <= 2);
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let shifted_month = u64::from((self.month + 9) % 12);
+        let day_of_year = (153 * shifted_month + 2) / 5 + u64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        (era * 146_097 + day_of_era).saturating_sub(719_468) * SECS_PER_DAY
+    }
+
+    fn days_in_month(year: u32, month: u32) -> u32 {
+        let leap =
+            year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+        match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+}
+
+impl FromStr for Date {
+    type Err = ParseDateError;
+
+    fn from_str(s: &str) -> Result<Date, ParseDateError> {
+        let invalid = || ParseDateError(s.to_string());
+        let mut parts = s.splitn(3, '-');
+        let mut next = |digits: usize| {
+            parts
+                .next()
+                .filter(|part| part.len() == digits && part.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|part| part.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let (year, month, day) = (next(4)?, next(2)?, next(2)?);
+        if year < 1970 || !(1..=12).contains(&month) {
+            return Err(invalid());
+        }
+        if !(1..=Date::days_in_month(year, month)).contains(&day) {
+            return Err(invalid());
+        }
+        Ok(Date { year, month, day })
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Date, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementFormat {
+    #[default]
+    Text,
+    Csv,
+    Ofx,
+    Qif,
+}
+
+/// One movement in or out of the account.
+#[derive(Debug, Clone)]
+pub struct StatementLine {
+    pub sequence: u64,
+    pub transaction_id: TransactionId,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Name of the other account, a customer's or one of the bank's own.
+    pub counterparty: Option<String>,
+    pub memo: Option<String>,
+    pub debit: Option<Money>,
+    pub credit: Option<Money>,
+    /// After the movement.
+    pub balance: Balance,
+}
+
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub account: AccountId,
+    pub name: String,
+    pub currency: Currency,
+    /// First day of the period, the account's opening if `None`.
+    pub from: Option<Date>,
+    /// Last day of the period, today if `None`.
+    pub to: Option<Date>,
+    pub opening_balance: Balance,
+    pub lines: Vec<StatementLine>,
+    pub closing_balance: Balance,
+    /// Seconds since the Unix epoch.
+    pub generated_at: u64,
+}
+
+impl Statement {
+    /// Replay the ledger entries of `account` into a statement over
+    /// `from..=to` as of `generated_at`. `name_of` names the other accounts.
+    /// Returns `None` if the balance overflows.
+    pub(crate) fn build<'a>(
+        account: &Account,
+        from: Option<Date>,
+        to: Option<Date>,
+        generated_at: u64,
+        entries: impl IntoIterator<Item = &'a LedgerEntry>,
+        name_of: impl Fn(AccountId) -> Option<String>,
+    ) -> Option<Statement> {
+        let start = from.map_or(0, Date::timestamp);
+        let end = to.map(|to| to.timestamp() + SECS_PER_DAY);
+        let mut opening_balance = Balance::ZERO;
+        let mut balance = Balance::ZERO;
+        let mut lines = Vec::new();
+        for entry in entries {
+            if end.is_some_and(|end| entry.timestamp >= end) {
+                continue;
+            }
+            let [debit_line, credit_line] = entry.postings();
+            let own = LedgerAccount::Customer(account.id);
+            let (counterparty, debit, credit) = if debit_line.account == own {
+                balance = balance.checked_sub(entry.amount)?;
+                (credit_line.account, Some(entry.amount), None)
+            } else if credit_line.account == own {
+                balance = balance.checked_add(entry.amount)?;
+                (debit_line.account, None, Some(entry.amount))
+            } else {
+                continue;
+            };
+            if entry.timestamp < start {
+                opening_balance = balance;
+                continue;
+            }
+            lines.push(StatementLine {
+                sequence: entry.sequence,
+                transaction_id: entry.transaction_id,
+                timestamp: entry.timestamp,
+                counterparty: match counterparty {
+                    LedgerAccount::Customer(id) => name_of(id),
+                    LedgerAccount::System(system) => Some(system.to_string()),
+                },
+                memo: entry.memo.clone(),
+                debit,
+                credit,
+                balance,
+            });
+        }
+        Some(Statement {
+            account: account.id,
+            name: account.name.to_string(),
+            currency: account.currency,
+            from,
+            to,
+            opening_balance,
+            lines,
+            closing_balance: balance,
+            generated_at,
+        })
+    }
+
+    pub fn render(&self, format: StatementFormat) -> String {
+        match format {
+            StatementFormat::Text => self.to_text(),
+            StatementFormat::Csv => self.to_csv(),
+            StatementFormat::Ofx => self.to_ofx(),
+            StatementFormat::Qif => self.to_qif(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let period = match (self.from, self.to) {
+            (Some(from), Some(to)) => format!("{from} to {to}"),
+            (Some(from), None) => format!("{from} to today"),
+            (None, Some(to)) => format!("opening to {to}"),
+            (None, None) => "opening to today".to_string(),
+        };
+        // Writing to a String does not fail.
+        let _ = writeln!(
+            text,
+            "Statement of account {} ({}), {}\nPeriod: {period}\n",
+            self.name, self.account, self.currency
+        );
+        let _ = writeln!(
+            text,
+            "{:<19}  {:<8}  {:<16}  {:>12}  {:>12}  {:>12}  Memo",
+            "Date", "Tx", "Counterparty", "Debit", "Credit", "Balance"
+        );
+        let _ = writeln!(
+            text,
+            "{:<19}  {:<8}  {:<16}  {:>12}  {:>12}  {:>12}",
+            "",
+            "",
+            "Opening balance",
+            "",
+            "",
+            self.opening_balance.to_string()
+        );
+        for line in &self.lines {
+            let row = format!(
+                "{:<19}  {:<8}  {:<16}  {:>12}  {:>12}  {:>12}  {}",
+                format_timestamp(line.timestamp),
+                line.transaction_id.to_string(),
+                line.counterparty.as_deref().unwrap_or("-"),
+                optional(line.debit),
+                optional(line.credit),
+                line.balance.to_string(),
+                line.memo.as_deref().unwrap_or("")
+            );
+            text.push_str(row.trim_end());
+            text.push('\n');
+        }
+        let _ = writeln!(
+            text,
+            "{:<19}  {:<8}  {:<16}  {:>12}  {:>12}  {:>12}",
+            "",
+            "",
+            "Closing balance",
+            "",
+            "",
+            self.closing_balance.to_string()
+        );
+        text
+    }
+
+    /// One row per movement, between rows for the opening and closing
+    /// balances that leave the movement columns empty.
+    pub fn to_csv(&self) -> String {
+        let mut rows = String::from(
+            "date,sequence,transaction_id,counterparty,memo,debit,credit,balance,currency\n",
+        );
+        let mut row = |fields: [String; 9]| {
+            let fields: Vec<_> = fields.iter().map(|field| csv::field(field)).collect();
+            rows.push_str(&fields.join(","));
+            rows.push('\n');
+        };
+        let balance_row = |label: &str, date: String, balance: Balance| {
+            [
+                date,
+                String::new(),
+                String::new(),
+                String::new(),
+                label.to_string(),
+                String::new(),
+                String::new(),
+                balance.to_string(),
+                self.currency.to_string(),
+            ]
+        };
+        row(balance_row(
+            "Opening balance",
+            self.from.map_or_else(String::new, |from| from.to_string()),
+            self.opening_balance,
+        ));
+        for line in &self.lines {
+            row([
+                format_timestamp(line.timestamp),
+                line.sequence.to_string(),
+                line.transaction_id.0.to_string(),
+                line.counterparty.clone().unwrap_or_default(),
+                line.memo.clone().unwrap_or_default(),
+                optional(line.debit),
+                optional(line.credit),
+                line.balance.to_string(),
+                self.currency.to_string(),
+            ]);
+        }
+        row(balance_row(
+            "Closing balance",
+            self.to.map_or_else(String::new, |to| to.to_string()),
+            self.closing_balance,
+        ));
+        rows
+    }
+
+    /// An OFX 2.2 bank statement response. Movements are identified by
+    /// their ledger sequence, so importing overlapping statements does not
+    /// duplicate them.
+    pub fn to_ofx(&self) -> String {
+        let start = self.from.map_or_else(
+            || {
+                self.lines
+                    .first()
+                    .map_or(self.generated_at, |line| line.timestamp)
+            },
+            Date::timestamp,
+        );
+        let end = self
+            .to
+            .map_or(self.generated_at, |to| to.timestamp() + SECS_PER_DAY - 1);
+        let mut ofx = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n",
+            "<?OFX OFXHEADER=\"200\" VERSION=\"220\" SECURITY=\"NONE\" ",
+            "OLDFILEUID=\"NONE\" NEWFILEUID=\"NONE\"?>\n",
+        ));
+        let status = "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>";
+        let _ = write!(
+            ofx,
+            "<OFX>\n<SIGNONMSGSRSV1><SONRS>{status}<DTSERVER>{}</DTSERVER>\
+             <LANGUAGE>ENG</LANGUAGE></SONRS></SIGNONMSGSRSV1>\n\
+             <BANKMSGSRSV1><STMTTRNRS><TRNUID>0</TRNUID>{status}\n\
+             <STMTRS><CURDEF>{}</CURDEF>\n\
+             <BANKACCTFROM><BANKID>{OFX_BANK_ID}</BANKID><ACCTID>{}</ACCTID>\
+             <ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>\n\
+             <BANKTRANLIST><DTSTART>{}</DTSTART><DTEND>{}</DTEND>\n",
+            ofx_timestamp(self.generated_at),
+            self.currency,
+            self.account.0,
+            ofx_timestamp(start),
+            ofx_timestamp(end)
+        );
+        for line in &self.lines {
+            let (kind, amount) = match (line.debit, line.credit) {
+                (Some(debit), _) => ("DEBIT", format!("-{debit}")),
+                (None, credit) => ("CREDIT", optional(credit)),
+            };
+            let _ = write!(
+                ofx,
+                "<STMTTRN><TRNTYPE>{kind}</TRNTYPE><DTPOSTED>{}</DTPOSTED>\
+                 <TRNAMT>{amount}</TRNAMT><FITID>{}</FITID><REFNUM>{}</REFNUM>",
+                ofx_timestamp(line.timestamp),
+                line.sequence,
+                line.transaction_id.0
+            );
+            if let Some(counterparty) = &line.counterparty {
+                let name: String = counterparty.chars().take(OFX_NAME_LENGTH).collect();
+                let _ = write!(ofx, "<NAME>{}</NAME>", xml_escape(&name));
+            }
+            if let Some(memo) = &line.memo {
+                let _ = write!(ofx, "<MEMO>{}</MEMO>", xml_escape(memo));
+            }
+            ofx.push_str("</STMTTRN>\n");
+        }
+        let _ = writeln!(
+            ofx,
+            "</BANKTRANLIST>\n\
+             <LEDGERBAL><BALAMT>{}</BALAMT><DTASOF>{}</DTASOF></LEDGERBAL>\n\
+             </STMTRS></STMTTRNRS></BANKMSGSRSV1>\n</OFX>",
+            self.closing_balance,
+            ofx_timestamp(end)
+        );
+        ofx
+    }
+
+    /// A QIF bank account, one record per movement. QIF has no balances, so
+    /// the opening and closing balances are left out.
+    pub fn to_qif(&self) -> String {
+        let mut qif = String::from("!Type:Bank\n");
+        for line in &self.lines {
+            let date = Date::from_timestamp(line.timestamp);
+            let amount = match (line.debit, line.credit) {
+                (Some(debit), _) => format!("-{debit}"),
+                (None, credit) => optional(credit),
+            };
+            let _ = writeln!(
+                qif,
+                "D{:02}/{:02}/{:04}\nT{amount}\nN{}",
+                date.month, date.day, date.year, line.transaction_id.0
+            );
+            if let Some(counterparty) = &line.counterparty {
+                let _ = writeln!(qif, "P{}", single_line(counterparty));
+            }
+            if let Some(memo) = &line.memo {
+                let _ = writeln!(qif, "M{}", single_line(memo));
+            }
+            qif.push_str("^\n");
+        }
+        qif
+    }
+}
+
+/// `YYYY-MM-DD HH:MM:SS`, UTC.
+fn format_timestamp(timestamp: u64) -> String {
+    let secs = timestamp % SECS_PER_DAY;
+    format!(
+        "{} {:02}:{:02}:{:02}",
+        Date::from_timestamp(timestamp),
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// `YYYYMMDDHHMMSS`, UTC.
+fn ofx_timestamp(timestamp: u64) -> String {
+    let date = Date::from_timestamp(timestamp);
+    let secs = timestamp % SECS_PER_DAY;
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        date.year,
+        date.month,
+        date.day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// QIF fields end at the end of the line.
+fn single_line(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+}
+
+fn optional(amount: Option<Money>) -> String {
+    amount.map_or_else(String::new, |amount| amount.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_convert_to_and_from_timestamps() {
+        for (date, timestamp) in [
+            ("1970-01-01", 0),
+            ("2000-02-29", 951_782_400),
+            ("2024-12-31", 1_735_603_200),
+        ] {
+            let parsed: Date = date.parse().unwrap();
+            assert_eq!(parsed.timestamp(), timestamp);
+            assert_eq!(Date::from_timestamp(timestamp + 86_399), parsed);
+            assert_eq!(parsed.to_string(), date);
+        }
+        for invalid in [
+            "2023-02-29",
+            "2024-13-01",
+            "2024-1-01",
+            "24-01-01",
+            "2024-01-01x",
+        ] {
+            assert!(invalid.parse::<Date>().is_err(), "{invalid}");
+        }
+        assert_eq!(format_timestamp(951_782_400 + 3661), "2000-02-29 01:01:01");
+        assert_eq!(ofx_timestamp(951_782_400 + 3661), "20000229010101");
+    }
+}
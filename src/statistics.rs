@@ -0,0 +1,265 @@
+//! Aggregate figures for the `g` instruction, so clients do not have to
+//! fetch every account and add them up, and for the `y` instruction, the
+//! same over the accounts matching a filter, e.g. `{"top": 5, "filter":
+//! {"tag": "retail"}}`, for dashboards and reports.
+//!
+//! Figures are per currency, as amounts in different currencies do not add
+//! up or compare. Transactions since startup are those the server committed
+//! since it started, fees not counted separately, and their volume is the
+//! sum of the amounts moved, both legs of a conversion included.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::events::Event;
+use crate::listing::AccountFilter;
+use crate::money::{Balance, Currency, Total};
+use crate::{AccountId, AccountStatus, Bank};
+
+/// Most accounts listed per currency by the `y` instruction.
+pub const MAX_TOP: usize = 100;
+
+/// Transactions committed since startup.
+#[derive(Debug, Default)]
+pub struct Activity {
+    transactions: u64,
+    volume: BTreeMap<Currency, Total>,
+}
+
+impl Activity {
+    pub fn record(&mut self, event: &Event) {
+        let movements = match event {
+            Event::FundsTransferred {
+                movements,
+                fee_for: None,
+                ..
+            }
+            | Event::AccountMerged {
+                transaction_id: Some(_),
+                movements,
+                ..
+            } => movements,
+            _ => return,
+        };
+        self.transactions += 1;
+        for movement in movements {
+            self.volume
+                .entry(movement.currency)
+                .or_default()
+                .add_amount(movement.amount);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Statistics {
+    pub accounts: usize,
+    pub transactions_since_startup: u64,
+    pub currencies: Vec<CurrencyStatistics>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CurrencyStatistics {
+    pub currency: Currency,
+    pub accounts: usize,
+    pub total_balance: Total,
+    pub volume_since_startup: Total,
+    /// Highest balance, the oldest account on a tie.
+    pub largest_account: Option<LargestAccount>,
+}
+
+impl CurrencyStatistics {
+    fn new(currency: Currency) -> CurrencyStatistics {
+        CurrencyStatistics {
+            currency,
+            accounts: 0,
+            total_balance: Total::default(),
+            volume_since_startup: Total::default(),
+            largest_account: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LargestAccount {
+    pub id: AccountId,
+    pub name: String,
+    pub balance: Balance,
+}
+
+/// Payload of the `y` instruction.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AggregateQuery {
+    /// Accounts with the highest balances to list per currency, capped at
+    /// `MAX_TOP`.
+    pub top: usize,
+    /// Accounts to aggregate, all of them by default.
+    pub filter: AccountFilter,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Aggregates {
+    pub accounts: usize,
+    pub by_status: StatusCounts,
+    pub currencies: Vec<CurrencyAggregates>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StatusCounts {
+    pub active: usize,
+    pub frozen: usize,
+    pub closed: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CurrencyAggregates {
+    pub currency: Currency,
+    pub accounts: usize,
+    pub total_balance: Total,
+    pub average_balance: Balance,
+    /// Highest balances first, the oldest account on a tie.
+    pub top: Vec<LargestAccount>,
+}
+
+pub fn aggregates(bank: &Bank, query: &AggregateQuery) -> Aggregates {
+    let mut aggregates = Aggregates::default();
+    let mut currencies: BTreeMap<Currency, Vec<_>> = BTreeMap::new();
+    for account in bank.accounts.values() {
+        if !query.filter.matches(account) {
+            continue;
+        }
+        aggregates.accounts += 1;
+        *match account.status {
+            AccountStatus::Active => &mut aggregates.by_status.active,
+            AccountStatus::Frozen => &mut aggregates.by_status.frozen,
+            AccountStatus::Closed => &mut aggregates.by_status.closed,
+        } += 1;
+        currencies
+            .entry(account.currency)
+            .or_default()
+            .push(account);
+    }
+    for (currency, mut accounts) in currencies {
+        let mut total_balance = Total::default();
+        for account in &accounts {
+            total_balance.add_balance(account.balance);
+        }
+        accounts.sort_unstable_by_key(|account| (Reverse(account.balance), account.id));
+        aggregates.currencies.push(CurrencyAggregates {
+            currency,
+            accounts: accounts.len(),
+            total_balance,
+            average_balance: total_balance.average(accounts.len()),
+            top: accounts
+                .iter()
+                .take(query.top.min(MAX_TOP))
+                .map(|account| LargestAccount {
+                    id: account.id,
+                    name: account.name.to_string(),
+                    balance: account.balance,
+                })
+                .collect(),
+        });
+    }
+    aggregates
+}
+
+pub fn statistics(bank: &Bank) -> Statistics {
+    let mut currencies: BTreeMap<Currency, CurrencyStatistics> = BTreeMap::new();
+    for account in bank.accounts.values() {
+        let statistics = currencies
+            .entry(account.currency)
+            .or_insert_with(|| CurrencyStatistics::new(account.currency));
+        statistics.accounts += 1;
+        statistics.total_balance.add_balance(account.balance);
+        let larger = statistics.largest_account.as_ref().is_none_or(|largest| {
+            (account.balance, Reverse(account.id)) > (largest.balance, Reverse(largest.id))
+        });
+        if larger {
+            statistics.largest_account = Some(LargestAccount {
+                id: account.id,
+                name: account.name.to_string(),
+                balance: account.balance,
+            });
+        }
+    }
+    for (&currency, &volume) in &bank.activity.volume {
+        currencies
+            .entry(currency)
+            .or_insert_with(|| CurrencyStatistics::new(currency))
+            .volume_since_startup = volume;
+    }
+    Statistics {
+        accounts: bank.accounts.len(),
+        transactions_since_startup: bank.activity.transactions,
+        currencies: currencies.into_values().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TxInfo;
+
+    #[test]
+    fn figures_are_per_currency() {
+        let mut bank = Bank::new();
+        for (name, balance, currency) in [
+            ("a", 500, Currency::EUR),
+            ("b", 700, Currency::EUR),
+            ("c", 300, "USD".parse().unwrap()),
+        ] {
+            bank.open_account(name.to_string(), Balance::from_minor(balance), currency)
+                .unwrap();
+        }
+        let opened = bank.activity.transactions;
+        let transfer: TxInfo =
+            serde_json::from_str(r#"{"from": "b", "to": "a", "amount": "2.50"}"#).unwrap();
+        bank.handle_transaction(transfer).unwrap();
+
+        let statistics = statistics(&bank);
+        assert_eq!(statistics.accounts, 3);
+        assert_eq!(statistics.transactions_since_startup, opened + 1);
+        let eur = &statistics.currencies[0];
+        assert_eq!(eur.currency, Currency::EUR);
+        assert_eq!(eur.accounts, 2);
+        assert_eq!(eur.total_balance.to_string(), "12.00");
+        let largest = eur.largest_account.as_ref().unwrap();
+        assert_eq!(
+            (largest.name.as_str(), largest.balance),
+            ("a", Balance::from_minor(750))
+        );
+        assert_eq!(statistics.currencies[1].total_balance.to_string(), "3.00");
+    }
+
+    #[test]
+    fn aggregates_cover_the_matching_accounts() {
+        let mut bank = Bank::new();
+        for (name, balance) in [("a", 500), ("b", 700), ("c", 200), ("other", 9_900)] {
+            bank.open_account(
+                name.to_string(),
+                Balance::from_minor(balance),
+                Currency::EUR,
+            )
+            .unwrap();
+        }
+        let query: AggregateQuery =
+            serde_json::from_str(r#"{"top": 2, "filter": {"max_balance": "10.00"}}"#).unwrap();
+
+        let aggregates = aggregates(&bank, &query);
+        assert_eq!(aggregates.accounts, 3);
+        assert_eq!(aggregates.by_status.active, 3);
+        let eur = &aggregates.currencies[0];
+        assert_eq!(eur.total_balance.to_string(), "14.00");
+        assert_eq!(eur.average_balance, Balance::from_minor(466));
+        let top: Vec<_> = eur
+            .top
+            .iter()
+            .map(|account| account.name.as_str())
+            .collect();
+        assert_eq!(top, ["b", "a"]);
+    }
+}
@@ -0,0 +1,256 @@
+//! Keeps the bank on disk, in a data directory, as a snapshot of its state
+//! and the events since. Events are appended to `events.jsonl` as they
+//! happen. Every `snapshot_every` events the state is written to
+//! `snapshot.json` and the event log starts over, so starting up replays at
+//! most that many events and the log does not grow without bound.
+//!
+//! The snapshot keeps the whole ledger, which is the bank's history. Every
+//! event also goes to the audit log, see `audit`. With a key set, all of it
+//! is encrypted, see `encryption`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::audit::{self, AuditEntry};
+use crate::crypto::{self, Digest};
+use crate::encryption::{self, EncryptionError};
+use crate::escrow::Escrow;
+use crate::events::{self, EventLogError, EventRecord};
+use crate::fraud::FlaggedTransaction;
+use crate::fx::Rate;
+use crate::goals::Goal;
+use crate::holds::Hold;
+use crate::interbank::UnconfirmedTransfer;
+use crate::ledger::LedgerEntry;
+use crate::loans::Loan;
+use crate::money::Currency;
+use crate::owners::ApprovalRequest;
+use crate::review::ParkedTransfer;
+use crate::scheduler::{ScheduledTransfer, StandingOrder};
+use crate::Account;
+
+pub const DEFAULT_SNAPSHOT_EVERY: u64 = 10_000;
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const EVENTS_FILE: &str = "events.jsonl";
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("Unable to access {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Invalid snapshot {}", path.display())]
+    Snapshot {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Unable to read snapshot {}", path.display())]
+    Sealed {
+        path: PathBuf,
+        #[source]
+        source: EncryptionError,
+    },
+    #[error("Snapshot {} does not match the audit log", path.display())]
+    AuditHash { path: PathBuf },
+    #[error("Unable to replay {}", path.display())]
+    Events {
+        path: PathBuf,
+        #[source]
+        source: EventLogError,
+    },
+}
+
+/// The bank's state after the event `sequence`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub sequence: u64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub accounts: Vec<Account>,
+    pub ledger: Vec<LedgerEntry>,
+    pub holds: Vec<Hold>,
+    #[serde(default)]
+    pub escrows: Vec<Escrow>,
+    #[serde(default)]
+    pub approvals: Vec<ApprovalRequest>,
+    #[serde(default)]
+    pub loans: Vec<Loan>,
+    #[serde(default)]
+    pub goals: Vec<Goal>,
+    #[serde(default)]
+    pub parked: Vec<ParkedTransfer>,
+    #[serde(default)]
+    pub scheduled: Vec<ScheduledTransfer>,
+    #[serde(default)]
+    pub standing_orders: Vec<StandingOrder>,
+    /// The last IDs given out to scheduled transfers and standing orders,
+    /// which are not given out again.
+    #[serde(default)]
+    pub last_schedule_ids: (u64, u64),
+    /// Exchange rates as from and to currency and rate.
+    #[serde(default)]
+    pub fx_rates: Vec<(Currency, Currency, Rate)>,
+    #[serde(default)]
+    pub flagged: Vec<FlaggedTransaction>,
+    /// Transfers sent to peer banks that have not answered.
+    #[serde(default)]
+    pub unconfirmed: Vec<UnconfirmedTransfer>,
+    /// Hex-encoded hash of the audit entry for event `sequence`.
+    pub audit_hash: String,
+}
+
+#[derive(Debug)]
+pub struct Store {
+    dir: PathBuf,
+    events: File,
+    audit: File,
+    /// Hash of the last audit entry.
+    audit_head: Digest,
+    snapshot_every: u64,
+    /// Events appended since the last snapshot.
+    pending: u64,
+}
+
+impl Store {
+    /// Open the data directory, creating it if needed, and read the latest
+    /// snapshot and the events after it.
+    pub fn open(
+        dir: &Path,
+        snapshot_every: u64,
+    ) -> Result<(Store, Option<Snapshot>, Vec<EventRecord>), StoreError> {
+        fs::create_dir_all(dir).map_err(io_error(dir))?;
+        let (snapshot, records) = read(dir)?;
+        let mut audit_head = audit::GENESIS;
+        if let Some(snapshot) = &snapshot {
+            let hash = crypto::from_hex(&snapshot.audit_hash)
+                .and_then(|hash| Digest::try_from(hash).ok())
+                .ok_or_else(|| StoreError::AuditHash {
+                    path: dir.join(SNAPSHOT_FILE),
+                })?;
+            audit_head = hash;
+        }
+        for record in &records {
+            audit_head = audit::chain(&audit_head, record);
+        }
+        let append = |name: &str| {
+            let path = dir.join(name);
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(io_error(&path))
+        };
+        let store = Store {
+            dir: dir.to_owned(),
+            events: append(EVENTS_FILE)?,
+            audit: append(audit::FILE)?,
+            audit_head,
+            snapshot_every,
+            pending: records.len() as u64,
+        };
+        Ok((store, snapshot, records))
+    }
+
+    /// Write an event and its audit entry to disk before returning.
+    pub fn append(&mut self, record: &EventRecord) -> io::Result<()> {
+        let entry = AuditEntry::new(&self.audit_head, record);
+        write_line(&mut self.audit, &entry)?;
+        self.audit_head = audit::chain(&self.audit_head, record);
+        write_line(&mut self.events, record)?;
+        self.pending += 1;
+        Ok(())
+    }
+
+    /// Hash of the audit entry for the last event.
+    pub fn audit_head(&self) -> &Digest {
+        &self.audit_head
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Events in the event log, which a restart replays.
+    pub fn events_since_snapshot(&self) -> u64 {
+        self.pending
+    }
+
+    pub fn snapshot_due(&self) -> bool {
+        self.pending >= self.snapshot_every.max(1)
+    }
+
+    pub fn set_snapshot_every(&mut self, snapshot_every: u64) {
+        self.snapshot_every = snapshot_every;
+    }
+
+    /// Replace the snapshot and clear the event log, which it covers.
+    pub fn save_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let path = self.dir.join(SNAPSHOT_FILE);
+        let temporary = path.with_extension("json.tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&encryption::encode(serde_json::to_vec(snapshot)?)?)?;
+        file.sync_all()?;
+        fs::rename(&temporary, &path)?;
+        File::open(&self.dir)?.sync_all()?;
+        self.events.set_len(0)?;
+        self.events.sync_all()?;
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+/// Read the latest snapshot in `dir` and the events after it.
+pub fn read(dir: &Path) -> Result<(Option<Snapshot>, Vec<EventRecord>), StoreError> {
+    let snapshot_path = dir.join(SNAPSHOT_FILE);
+    let snapshot: Option<Snapshot> = match fs::read_to_string(&snapshot_path) {
+        Ok(contents) => {
+            let contents = encryption::decode(&contents).map_err(|source| StoreError::Sealed {
+                path: snapshot_path.clone(),
+                source,
+            })?;
+            Some(
+                serde_json::from_str(&contents).map_err(|source| StoreError::Snapshot {
+                    path: snapshot_path.clone(),
+                    source,
+                })?,
+            )
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(io_error(&snapshot_path)(e)),
+    };
+    let events_path = dir.join(EVENTS_FILE);
+    let mut records = match File::open(&events_path) {
+        Ok(events) => {
+            events::read(BufReader::new(events)).map_err(|source| StoreError::Events {
+                path: events_path.clone(),
+                source,
+            })?
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(io_error(&events_path)(e)),
+    };
+    // Left over if the log was not cleared after the last snapshot.
+    let covered = snapshot.as_ref().map_or(0, |snapshot| snapshot.sequence);
+    records.retain(|record| record.sequence > covered);
+    Ok((snapshot, records))
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> StoreError {
+    let path = path.to_owned();
+    move |source| StoreError::Io { path, source }
+}
+
+fn write_line(file: &mut File, value: &impl Serialize) -> io::Result<()> {
+    let mut line = encryption::encode(serde_json::to_vec(value)?)?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    file.sync_data()
+}
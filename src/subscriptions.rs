@@ -0,0 +1,170 @@
+//! Notifications pushed to clients as transfers commit, so they can react
+//! without polling. A client subscribes the socket it sent the `u`
+//! instruction from, to transfers in and out of one account or, if allowed
+//! to see them, of all accounts. Every committed transfer it subscribed to
+//! then arrives on that socket as a datagram like
+//! `{"event":"transfer_committed","transaction_id":7,...}`.
+//!
+//! Replies to the client's own requests arrive on the same socket, so a
+//! client that keeps sending requests had better subscribe a separate one.
+//! Notifications are not queued: a subscriber that does not keep up misses
+//! them, and one whose socket is gone is dropped.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use crate::hooks::{CommittedTransfer, TransferEvent};
+use crate::transport::Transport;
+use crate::AccountId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionAction {
+    Subscribe,
+    /// Stop all notifications to the socket.
+    Unsubscribe,
+}
+
+/// Transfers a subscriber is notified of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    All,
+    /// Transfers from or to any of the accounts.
+    Accounts(BTreeSet<AccountId>),
+}
+
+impl Filter {
+    fn matches(&self, transfer: &CommittedTransfer) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::Accounts(accounts) => {
+                accounts.contains(&transfer.from) || accounts.contains(&transfer.to)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Subscriptions {
+    /// The server's, so notifications come from its socket.
+    transport: Arc<dyn Transport>,
+    subscribers: Mutex<BTreeMap<PathBuf, Filter>>,
+}
+
+impl Subscriptions {
+    pub fn new(transport: Arc<dyn Transport>) -> Subscriptions {
+        Subscriptions {
+            transport,
+            subscribers: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Notify `path` of transfers from or to `account`, or of all transfers
+    /// if it is `None`, on top of what it is already subscribed to.
+    pub fn subscribe(&self, path: PathBuf, account: Option<AccountId>) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let filter = subscribers
+            .entry(path)
+            .or_insert_with(|| Filter::Accounts(BTreeSet::new()));
+        match (filter, account) {
+            (filter, None) => *filter = Filter::All,
+            (Filter::Accounts(accounts), Some(account)) => {
+                accounts.insert(account);
+            }
+            (Filter::All, Some(_)) => {}
+        }
+    }
+
+    /// Returns whether `path` was subscribed.
+    pub fn unsubscribe(&self, path: &Path) -> bool {
+        self.subscribers.lock().unwrap().remove(path).is_some()
+    }
+
+    pub fn publish(&self, transfer: &CommittedTransfer) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let message = match serde_json::to_vec(&TransferEvent::committed(transfer)) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Unable to encode notification: {e}");
+                return;
+            }
+        };
+        subscribers.retain(|path, filter| {
+            if !filter.matches(transfer) {
+                return true;
+            }
+            match self.transport.send_to_nowait(&message, path) {
+                Ok(()) => true,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    debug!("Dropped a notification to {}, it is behind", path.display());
+                    true
+                }
+                Err(e) => {
+                    info!("Unsubscribed {}: {e}", path.display());
+                    false
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::*;
+    use crate::ledger::TransactionId;
+    use crate::money::{Currency, Money};
+
+    fn transfer(from: u64, to: u64) -> CommittedTransfer {
+        CommittedTransfer {
+            transaction_id: TransactionId(7),
+            timestamp: 0,
+            from: AccountId(from),
+            to: AccountId(to),
+            amount: Money::from_minor(250),
+            currency: Currency::EUR,
+            credited_amount: Money::from_minor(250),
+            credited_currency: Currency::EUR,
+            fee: None,
+        }
+    }
+
+    #[test]
+    fn subscribers_get_the_transfers_they_filter_for() {
+        let dir = std::env::temp_dir().join(format!("bank-subscriptions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server = UnixDatagram::unbound().unwrap();
+        let subscriptions = Subscriptions::new(Arc::new(server));
+        let path = dir.join("client");
+        let _ = std::fs::remove_file(&path);
+        let client = UnixDatagram::bind(&path).unwrap();
+        client.set_nonblocking(true).unwrap();
+        subscriptions.subscribe(path.clone(), Some(AccountId(1)));
+
+        subscriptions.publish(&transfer(2, 3));
+        subscriptions.publish(&transfer(2, 1));
+        let mut buffer = [0; 512];
+        let length = client.recv(&mut buffer).unwrap();
+        let notification: serde_json::Value = serde_json::from_slice(&buffer[..length]).unwrap();
+        assert_eq!(notification["event"], "transfer_committed");
+        assert_eq!(notification["transaction_id"], 7);
+        assert_eq!(notification["to"], 1);
+        assert!(client.recv(&mut buffer).is_err());
+
+        // Gone sockets are dropped.
+        drop(client);
+        std::fs::remove_file(&path).unwrap();
+        subscriptions.publish(&transfer(1, 2));
+        assert!(!subscriptions.unsubscribe(&path));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
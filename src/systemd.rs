@@ -0,0 +1,59 @@
+//! Minimal systemd integration: socket activation (`LISTEN_FDS`) and
+//! readiness notification (`NOTIFY_SOCKET`), without linking libsystemd.
+
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+/// First file descriptor passed by systemd, see sd_listen_fds(3).
+const SD_LISTEN_FDS_START: libc::c_int = 3;
+
+/// Take the socket passed by systemd, if this process was socket-activated.
+///
+/// Only the first passed descriptor is used. The `LISTEN_*` variables are
+/// removed so they are not inherited by child processes.
+pub fn listen_socket() -> io::Result<Option<UnixDatagram>> {
+    let pid_matches = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let fds = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<u32>().ok())
+        .unwrap_or(0);
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    if !pid_matches || fds == 0 {
+        return Ok(None);
+    }
+    let fd = SD_LISTEN_FDS_START;
+    // SAFETY: plain fcntl calls on a descriptor systemd handed to us.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    // SAFETY: systemd transfers ownership of the descriptor to this process.
+    Ok(Some(unsafe { UnixDatagram::from_raw_fd(fd) }))
+}
+
+/// Send a state update to the service manager, see sd_notify(3). Does
+/// nothing when not running under systemd.
+pub fn notify(state: &str) -> io::Result<()> {
+    let socket_path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return Ok(()),
+    };
+    let address = match socket_path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&socket_path)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
@@ -0,0 +1,185 @@
+//! Timing of the request path, to see where the time goes when the server
+//! is slow. Each request is traced as a `request` span with a child span for
+//! every phase it goes through:
+//!
+//! - `decode`: parsing the header.
+//! - `validate`: authentication, rate limiting, signatures, parsing and
+//!   authorizing the payload.
+//! - `receive`: waiting for the payload after acknowledging the
+//!   instruction, which is mostly the client's time.
+//! - `apply`: the bank's work.
+//! - `respond`: sending the reply.
+//!
+//! Phases follow one another and may repeat, a request with a payload is
+//! validated both before and after receiving it. Finished requests are
+//! logged at debug level and, with the `otlp` feature, exported to an
+//! OpenTelemetry collector, see `otlp`.
+
+#[cfg(feature = "otlp")]
+pub mod otlp;
+
+use std::fmt::{self, Display};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Decode,
+    Validate,
+    Receive,
+    Apply,
+    Respond,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Decode => "decode",
+            Phase::Validate => "validate",
+            Phase::Receive => "receive",
+            Phase::Apply => "apply",
+            Phase::Respond => "respond",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PhaseSpan {
+    pub phase: Phase,
+    /// From the start of the request.
+    #[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+    pub offset: Duration,
+    pub duration: Duration,
+}
+
+/// A request being handled.
+#[derive(Debug)]
+pub struct RequestTrace {
+    instruction: String,
+    started: Instant,
+    started_at: SystemTime,
+    phase: Phase,
+    phase_started: Instant,
+    phases: Vec<PhaseSpan>,
+}
+
+impl RequestTrace {
+    /// Start tracing a request, in the `decode` phase.
+    pub fn start(instruction: &str) -> RequestTrace {
+        let started = Instant::now();
+        RequestTrace {
+            instruction: instruction.to_string(),
+            started,
+            started_at: SystemTime::now(),
+            phase: Phase::Decode,
+            phase_started: started,
+            phases: Vec::new(),
+        }
+    }
+
+    /// End the current phase and start `phase`, unless it is the current one.
+    pub fn phase(&mut self, phase: Phase) {
+        if phase != self.phase {
+            self.end_phase(Instant::now());
+            self.phase = phase;
+        }
+    }
+
+    fn end_phase(&mut self, now: Instant) {
+        self.phases.push(PhaseSpan {
+            phase: self.phase,
+            offset: self.phase_started - self.started,
+            duration: now - self.phase_started,
+        });
+        self.phase_started = now;
+    }
+
+    pub fn finish(mut self) -> FinishedRequest {
+        let now = Instant::now();
+        self.end_phase(now);
+        FinishedRequest {
+            instruction: self.instruction,
+            started_at: self.started_at,
+            duration: now - self.started,
+            phases: self.phases,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FinishedRequest {
+    pub instruction: String,
+    #[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+    pub started_at: SystemTime,
+    pub duration: Duration,
+    pub phases: Vec<PhaseSpan>,
+}
+
+impl Display for FinishedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' in {:?} (", self.instruction, self.duration)?;
+        for (index, span) in self.phases.iter().enumerate() {
+            let separator = if index == 0 { "" } else { ", " };
+            write!(f, "{separator}{} {:?}", span.phase.name(), span.duration)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Where finished requests go.
+#[derive(Debug, Default)]
+pub struct Tracer {
+    #[cfg(feature = "otlp")]
+    exporter: Option<otlp::Exporter>,
+}
+
+impl Tracer {
+    pub fn new() -> Tracer {
+        Tracer::default()
+    }
+
+    #[cfg(feature = "otlp")]
+    pub fn set_exporter(&mut self, exporter: Option<otlp::Exporter>) {
+        self.exporter = exporter;
+    }
+
+    pub fn record(&self, request: FinishedRequest) {
+        debug!("Handled {request}");
+        #[cfg(feature = "otlp")]
+        if let Some(exporter) = &self.exporter {
+            exporter.export(request);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases_follow_one_another() {
+        let mut trace = RequestTrace::start("t");
+        trace.phase(Phase::Decode);
+        trace.phase(Phase::Validate);
+        trace.phase(Phase::Receive);
+        trace.phase(Phase::Validate);
+        trace.phase(Phase::Apply);
+        let request = trace.finish();
+        let phases: Vec<&str> = request
+            .phases
+            .iter()
+            .map(|span| span.phase.name())
+            .collect();
+        assert_eq!(
+            phases,
+            ["decode", "validate", "receive", "validate", "apply"]
+        );
+        let mut offset = Duration::ZERO;
+        for span in &request.phases {
+            assert_eq!(span.offset, offset);
+            offset += span.duration;
+        }
+        assert_eq!(offset, request.duration);
+    }
+}
@@ -0,0 +1,187 @@
+//! Export of request traces to an OpenTelemetry collector, over OTLP/HTTP
+//! with the JSON encoding. Only plain `http://` endpoints are supported, put
+//! a local collector in front of anything else.
+//!
+//! Traces go to a background thread that posts them in batches, so a slow or
+//! unreachable collector does not hold up requests. When it falls behind,
+//! traces are dropped.
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use super::FinishedRequest;
+use crate::crypto;
+use crate::http::{self, Endpoint};
+
+const TRACES_PATH: &str = "/v1/traces";
+/// Traces waiting to be sent before new ones are dropped.
+const QUEUE_LENGTH: usize = 1024;
+const MAX_BATCH: usize = 256;
+
+const SPAN_KIND_INTERNAL: u8 = 1;
+const SPAN_KIND_SERVER: u8 = 2;
+
+#[derive(Error, Debug)]
+pub enum OtlpError {
+    #[error("Invalid OTLP endpoint '{0}', expected one like \"http://localhost:4318\"")]
+    Endpoint(String),
+    #[error("Unable to start the OTLP exporter")]
+    Thread(#[from] io::Error),
+}
+
+fn target(endpoint: &str) -> Option<Endpoint> {
+    Endpoint::parse(&format!("{}{TRACES_PATH}", endpoint.trim_end_matches('/')))
+}
+
+#[derive(Debug)]
+pub struct Exporter {
+    sender: SyncSender<FinishedRequest>,
+}
+
+impl Exporter {
+    /// Start exporting to the collector at `endpoint`, e.g.
+    /// `http://localhost:4318`. The thread stops when the exporter is
+    /// dropped.
+    pub fn start(endpoint: &str) -> Result<Exporter, OtlpError> {
+        let target = target(endpoint).ok_or_else(|| OtlpError::Endpoint(endpoint.into()))?;
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LENGTH);
+        thread::Builder::new()
+            .name("otlp-exporter".to_string())
+            .spawn(move || run(&target, &receiver))?;
+        Ok(Exporter { sender })
+    }
+
+    pub fn export(&self, request: FinishedRequest) {
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(request) {
+            debug!("Dropped a trace, the OTLP exporter is behind");
+        }
+    }
+}
+
+fn run(target: &Endpoint, receiver: &Receiver<FinishedRequest>) {
+    while let Ok(first) = receiver.recv() {
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            match receiver.try_recv() {
+                Ok(request) => batch.push(request),
+                Err(_) => break,
+            }
+        }
+        let body = match encode(&batch) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Unable to encode traces: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = http::post_json(target, &body) {
+            warn!(
+                "Unable to export {} traces to {}: {e}",
+                batch.len(),
+                target.address
+            );
+        }
+    }
+}
+
+/// An `ExportTraceServiceRequest` in the OTLP JSON encoding.
+fn encode(batch: &[FinishedRequest]) -> io::Result<Vec<u8>> {
+    let mut spans = Vec::new();
+    for request in batch {
+        let trace_id = crypto::to_hex(&crypto::random_bytes(16)?);
+        let root_id = crypto::to_hex(&crypto::random_bytes(8)?);
+        let start = unix_nanos(request.started_at);
+        spans.push(json!({
+            "traceId": trace_id,
+            "spanId": root_id,
+            "name": "request",
+            "kind": SPAN_KIND_SERVER,
+            "startTimeUnixNano": start.to_string(),
+            "endTimeUnixNano": (start + request.duration.as_nanos()).to_string(),
+            "attributes": [string_attribute("bank.instruction", &request.instruction)],
+        }));
+        for phase in &request.phases {
+            let phase_start = start + phase.offset.as_nanos();
+            spans.push(json!({
+                "traceId": trace_id,
+                "spanId": crypto::to_hex(&crypto::random_bytes(8)?),
+                "parentSpanId": root_id,
+                "name": phase.phase.name(),
+                "kind": SPAN_KIND_INTERNAL,
+                "startTimeUnixNano": phase_start.to_string(),
+                "endTimeUnixNano": (phase_start + phase.duration.as_nanos()).to_string(),
+            }));
+        }
+    }
+    let request = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    string_attribute("service.name", "bank"),
+                    string_attribute("service.version", env!("CARGO_PKG_VERSION")),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": {"name": "bank"},
+                "spans": spans,
+            }],
+        }],
+    });
+    Ok(serde_json::to_vec(&request)?)
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::{Phase, RequestTrace};
+
+    #[test]
+    fn endpoints_are_parsed() {
+        assert_eq!(
+            target("http://localhost:4318"),
+            Some(Endpoint {
+                address: "localhost:4318".to_string(),
+                host: "localhost:4318".to_string(),
+                path: "/v1/traces".to_string(),
+            })
+        );
+        let target = target("http://collector/otel/").unwrap();
+        assert_eq!(target.address, "collector:80");
+        assert_eq!(target.path, "/otel/v1/traces");
+        assert_eq!(super::target("https://collector:4318"), None);
+        assert_eq!(super::target("http://collector:port"), None);
+    }
+
+    #[test]
+    fn phases_are_children_of_the_request_span() {
+        let mut trace = RequestTrace::start("h");
+        trace.phase(Phase::Apply);
+        let body = encode(&[trace.finish()]).unwrap();
+        let request: Value = serde_json::from_slice(&body).unwrap();
+        let spans = request["resourceSpans"][0]["scopeSpans"][0]["spans"]
+            .as_array()
+            .unwrap();
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[0]["name"], "request");
+        for span in &spans[1..] {
+            assert_eq!(span["traceId"], spans[0]["traceId"]);
+            assert_eq!(span["parentSpanId"], spans[0]["spanId"]);
+        }
+        assert_eq!(spans[2]["name"], "apply");
+    }
+}
@@ -0,0 +1,164 @@
+//! How requests reach the server and replies leave it. `run_app` serves a
+//! Unix datagram socket, or vsock or UDP, see `vsock` and `udp`; tests
+//! serve a `MockTransport` instead, which takes queued datagrams and keeps
+//! what is sent back, without touching the filesystem.
+//!
+//! The server only ever sees a `Transport`, so another listener is an
+//! implementation of it and a case in `listen`, which picks one from the
+//! configuration. Decorators such as `NoiseTransport` wrap whichever it
+//! picked.
+
+use std::fmt::Debug;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+use crate::socket::{self, PeerCredentials};
+
+pub trait Transport: Debug + Send + Sync {
+    /// The next request datagram, its sender's path and credentials. Fails
+    /// with `WouldBlock` or `TimedOut` when none came in for a while.
+    ///
+    /// Both `recv_from` and `recv` return the length of the whole datagram,
+    /// which is more than `buffer` holds when it was cut off.
+    fn recv_from(
+        &self,
+        buffer: &mut [u8],
+    ) -> io::Result<(usize, Option<PathBuf>, Option<PeerCredentials>)>;
+
+    /// The next datagram, e.g. the payload following a request.
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize>;
+
+    fn send_to(&self, message: &[u8], path: &Path) -> io::Result<()>;
+
+    /// Like `send_to`, but fails with `WouldBlock` rather than wait for a
+    /// receiver that is behind.
+    fn send_to_nowait(&self, message: &[u8], path: &Path) -> io::Result<()>;
+}
+
+impl Transport for UnixDatagram {
+    fn recv_from(
+        &self,
+        buffer: &mut [u8],
+    ) -> io::Result<(usize, Option<PathBuf>, Option<PeerCredentials>)> {
+        socket::recv_with_credentials(self, buffer)
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        socket::recv_whole_length(self, buffer)
+    }
+
+    fn send_to(&self, message: &[u8], path: &Path) -> io::Result<()> {
+        UnixDatagram::send_to(self, message, path).map(drop)
+    }
+
+    fn send_to_nowait(&self, message: &[u8], path: &Path) -> io::Result<()> {
+        socket::send_to_nowait(self, message, path)
+    }
+}
+
+#[cfg(test)]
+pub use mock::MockTransport;
+
+#[cfg(test)]
+mod mock {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// A datagram queued for the server.
+    type Datagram = (Vec<u8>, PathBuf, Option<PeerCredentials>);
+
+    /// Serves the datagrams queued with `send`, in order, and keeps the
+    /// replies. Running out of datagrams is a bug in the test, so it
+    /// panics rather than keep the server waiting forever.
+    #[derive(Debug, Default)]
+    pub struct MockTransport {
+        /// `None` where nothing comes in for a while.
+        incoming: Mutex<VecDeque<Option<Datagram>>>,
+        sent: Mutex<Vec<(PathBuf, Vec<u8>)>>,
+    }
+
+    impl MockTransport {
+        /// Queue a datagram from `client`, a local user like the one
+        /// running the test.
+        pub fn send(&self, client: &str, datagram: impl Into<Vec<u8>>) {
+            let credentials = unsafe {
+                PeerCredentials {
+                    pid: libc::getpid(),
+                    uid: libc::geteuid(),
+                    gid: libc::getegid(),
+                }
+            };
+            self.incoming.lock().unwrap().push_back(Some((
+                datagram.into(),
+                PathBuf::from(client),
+                Some(credentials),
+            )));
+        }
+
+        /// Let receiving time out once, after what is queued so far.
+        pub fn pause(&self) {
+            self.incoming.lock().unwrap().push_back(None);
+        }
+
+        /// What was sent to `client`, oldest first.
+        pub fn replies(&self, client: &str) -> Vec<String> {
+            self.sent_to(client)
+                .iter()
+                .map(|message| String::from_utf8_lossy(message).into_owned())
+                .collect()
+        }
+
+        /// Like `replies`, byte for byte.
+        pub fn sent_to(&self, client: &str) -> Vec<Vec<u8>> {
+            self.sent
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(path, _)| path == Path::new(client))
+                .map(|(_, message)| message.clone())
+                .collect()
+        }
+
+        fn next(&self, buffer: &mut [u8]) -> io::Result<Datagram> {
+            let (datagram, client, credentials) = self
+                .incoming
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("the server read more datagrams than were queued")
+                .ok_or(io::ErrorKind::TimedOut)?;
+            let length = datagram.len().min(buffer.len());
+            buffer[..length].copy_from_slice(&datagram[..length]);
+            Ok((datagram, client, credentials))
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn recv_from(
+            &self,
+            buffer: &mut [u8],
+        ) -> io::Result<(usize, Option<PathBuf>, Option<PeerCredentials>)> {
+            let (datagram, client, credentials) = self.next(buffer)?;
+            Ok((datagram.len(), Some(client), credentials))
+        }
+
+        fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+            Ok(self.next(buffer)?.0.len())
+        }
+
+        fn send_to(&self, message: &[u8], path: &Path) -> io::Result<()> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((path.to_owned(), message.to_vec()));
+            Ok(())
+        }
+
+        fn send_to_nowait(&self, message: &[u8], path: &Path) -> io::Result<()> {
+            self.send_to(message, path)
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Serving over UDP, for clients on other hosts that want no connection
+//! set up. With `udp_address` in the configuration the server listens
+//! there instead of at `socket_path`, speaking the same datagram protocol.
+//! A client is known by its address, `udp:<ip>:<port>`. UDP carries no
+//! credentials, so `peer_auth` lets no client through; use tokens, and
+//! `noise` so that requests do not cross the network in the clear.
+//!
+//! Datagrams get lost, so clients send again what got no answer in time,
+//! the same datagram byte for byte, and the server sees to it that nothing
+//! is done twice:
+//!
+//! - A request sent again while the server waits for its payload, because
+//!   the "200" was lost, gets the "200" again.
+//! - A payload sent again once it was served, because the reply was lost,
+//!   gets the same reply again rather than being served again. To repeat a
+//!   transfer on purpose, a client sends its request again first.
+//! - A payload that comes after the server gave up waiting for it gets
+//!   "408", and the client starts over with the request.
+//!
+//! Requests without a payload are served again when they come again.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::socket::PeerCredentials;
+use crate::transport::Transport;
+
+/// Clients whose last exchange is remembered. The one heard from least
+/// recently is forgotten to make room for another.
+const MAX_CLIENTS: usize = 256;
+
+/// Datagrams of other clients kept while waiting for a payload.
+const MAX_QUEUED: usize = 1024;
+
+/// Largest datagram UDP carries.
+const MAX_DATAGRAM: usize = 65_535;
+
+/// The last request of a client, its payload and what was replied since the
+/// last of the two came in.
+#[derive(Debug)]
+struct Exchange {
+    request: Vec<u8>,
+    payload: Option<Vec<u8>>,
+    replies: Vec<Vec<u8>>,
+    /// The server gave up waiting for the payload.
+    abandoned: bool,
+    heard: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    exchanges: HashMap<SocketAddr, Exchange>,
+    queued: VecDeque<(Vec<u8>, SocketAddr)>,
+    /// The client whose request is being served.
+    current: Option<SocketAddr>,
+}
+
+/// What to do with a datagram that came in.
+enum Handling {
+    Serve,
+    Resend(Vec<Vec<u8>>),
+    Refuse,
+}
+
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+    timeout: Duration,
+    state: Mutex<State>,
+}
+
+fn path(address: SocketAddr) -> PathBuf {
+    PathBuf::from(format!("udp:{address}"))
+}
+
+fn address(path: &Path) -> io::Result<SocketAddr> {
+    path.to_str()
+        .and_then(|path| path.strip_prefix("udp:"))
+        .and_then(|address| address.parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a UDP client", path.display()),
+            )
+        })
+}
+
+impl UdpTransport {
+    /// Listen at `address`. Receiving fails with `WouldBlock` or `TimedOut`
+    /// when nothing came in for `timeout`.
+    pub fn bind(address: SocketAddr, timeout: Duration) -> io::Result<UdpTransport> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_read_timeout(Some(timeout))?;
+        Ok(UdpTransport {
+            socket,
+            timeout,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// The next datagram, of `client` if given. Those of other clients are
+    /// kept for later.
+    fn next(&self, client: Option<SocketAddr>) -> io::Result<(Vec<u8>, SocketAddr)> {
+        {
+            let mut state = self.state.lock().unwrap();
+            let queued = state
+                .queued
+                .iter()
+                .position(|(_, from)| client.is_none_or(|client| *from == client));
+            if let Some(index) = queued {
+                return Ok(state.queued.remove(index).unwrap());
+            }
+        }
+        let deadline = Instant::now() + self.timeout;
+        let mut buffer = vec![0; MAX_DATAGRAM];
+        loop {
+            let (length, from) = self.socket.recv_from(&mut buffer)?;
+            let datagram = buffer[..length].to_vec();
+            if client.is_none_or(|client| from == client) {
+                return Ok((datagram, from));
+            }
+            let mut state = self.state.lock().unwrap();
+            if state.queued.len() >= MAX_QUEUED {
+                warn!("Dropped a datagram from {from}, too many are waiting");
+            } else {
+                state.queued.push_back((datagram, from));
+            }
+            drop(state);
+            // Other clients must not keep the payload from timing out.
+            if Instant::now() >= deadline {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        }
+    }
+
+    /// How to handle `datagram`, a request unless it repeats the last
+    /// exchange of its sender.
+    fn handle(&self, datagram: &[u8], from: SocketAddr) -> Handling {
+        let mut state = self.state.lock().unwrap();
+        if let Some(exchange) = state.exchanges.get_mut(&from) {
+            exchange.heard = Instant::now();
+            if exchange.payload.as_deref() == Some(datagram) {
+                return Handling::Resend(exchange.replies.clone());
+            }
+            if exchange.abandoned && exchange.request != datagram {
+                state.exchanges.remove(&from);
+                return Handling::Refuse;
+            }
+        }
+        if !state.exchanges.contains_key(&from) && state.exchanges.len() >= MAX_CLIENTS {
+            let oldest = state
+                .exchanges
+                .iter()
+                .min_by_key(|(_, exchange)| exchange.heard)
+                .map(|(client, _)| *client);
+            if let Some(oldest) = oldest {
+                state.exchanges.remove(&oldest);
+            }
+        }
+        state.exchanges.insert(
+            from,
+            Exchange {
+                request: datagram.to_vec(),
+                payload: None,
+                replies: Vec::new(),
+                abandoned: false,
+                heard: Instant::now(),
+            },
+        );
+        state.current = Some(from);
+        Handling::Serve
+    }
+
+    fn resend(&self, replies: &[Vec<u8>], to: SocketAddr) {
+        for reply in replies {
+            if let Err(e) = self.socket.send_to(reply, to) {
+                warn!("Unable to reply again to {to}: {e}");
+            }
+        }
+    }
+
+    fn abandon(&self, client: SocketAddr) {
+        if let Some(exchange) = self.state.lock().unwrap().exchanges.get_mut(&client) {
+            exchange.abandoned = true;
+        }
+    }
+}
+
+fn copy(datagram: &[u8], buffer: &mut [u8]) -> usize {
+    let length = datagram.len().min(buffer.len());
+    buffer[..length].copy_from_slice(&datagram[..length]);
+    datagram.len()
+}
+
+impl Transport for UdpTransport {
+    fn recv_from(
+        &self,
+        buffer: &mut [u8],
+    ) -> io::Result<(usize, Option<PathBuf>, Option<PeerCredentials>)> {
+        loop {
+            let (datagram, from) = self.next(None)?;
+            match self.handle(&datagram, from) {
+                Handling::Serve => return Ok((copy(&datagram, buffer), Some(path(from)), None)),
+                Handling::Resend(replies) => self.resend(&replies, from),
+                Handling::Refuse => self.resend(&[b"408".to_vec()], from),
+            }
+        }
+    }
+
+    /// The payload of the client whose request came in last.
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let client = self.state.lock().unwrap().current;
+        let client = client.ok_or(io::ErrorKind::NotConnected)?;
+        loop {
+            let datagram = match self.next(Some(client)) {
+                Ok((datagram, _)) => datagram,
+                Err(e) => {
+                    self.abandon(client);
+                    return Err(e);
+                }
+            };
+            let mut state = self.state.lock().unwrap();
+            let Some(exchange) = state.exchanges.get_mut(&client) else {
+                return Err(io::ErrorKind::NotConnected.into());
+            };
+            exchange.heard = Instant::now();
+            if exchange.request == datagram {
+                let replies = exchange.replies.clone();
+                drop(state);
+                self.resend(&replies, client);
+                continue;
+            }
+            exchange.replies.clear();
+            let length = copy(&datagram, buffer);
+            exchange.payload = Some(datagram);
+            return Ok(length);
+        }
+    }
+
+    fn send_to(&self, message: &[u8], path: &Path) -> io::Result<()> {
+        let to = address(path)?;
+        self.socket.send_to(message, to)?;
+        if let Some(exchange) = self.state.lock().unwrap().exchanges.get_mut(&to) {
+            exchange.replies.push(message.to_vec());
+        }
+        Ok(())
+    }
+
+    /// Like `send_to`, but not replied again: what is sent this way is not
+    /// a reply to the exchange.
+    fn send_to_nowait(&self, message: &[u8], path: &Path) -> io::Result<()> {
+        self.socket.send_to(message, address(path)?).map(drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> UdpSocket {
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        client
+    }
+
+    fn received(client: &UdpSocket) -> String {
+        let mut buffer = [0; 64];
+        let length = client.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..length]).into_owned()
+    }
+
+    #[test]
+    fn lost_datagrams_are_made_up_for_without_serving_twice() {
+        let server =
+            UdpTransport::bind("127.0.0.1:0".parse().unwrap(), Duration::from_millis(200)).unwrap();
+        let to = server.socket.local_addr().unwrap();
+        let (a, b) = (client(), client());
+        let mut buffer = [0; 64];
+
+        a.send_to(b"t{}", to).unwrap();
+        let (_, sender, _) = server.recv_from(&mut buffer).unwrap();
+        let sender = sender.unwrap();
+        server.send_to(b"200", &sender).unwrap();
+        assert_eq!(received(&a), "200");
+        // The "200" got lost, so the request comes again, and another
+        // client's request comes meanwhile.
+        a.send_to(b"t{}", to).unwrap();
+        b.send_to(b"i", to).unwrap();
+        a.send_to(b"payload", to).unwrap();
+        assert_eq!(server.recv(&mut buffer).unwrap(), 7);
+        assert_eq!(received(&a), "200");
+        server.send_to(b"receipt", &sender).unwrap();
+        assert_eq!(received(&a), "receipt");
+
+        let (length, other, _) = server.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"i");
+        assert_eq!(other, Some(path(b.local_addr().unwrap())));
+
+        // The receipt got lost, so the payload comes again.
+        a.send_to(b"payload", to).unwrap();
+        assert!(server.recv_from(&mut buffer).is_err());
+        assert_eq!(received(&a), "receipt");
+
+        // A payload coming after the server gave up on it is refused.
+        a.send_to(b"t{}", to).unwrap();
+        server.recv_from(&mut buffer).unwrap();
+        assert!(server.recv(&mut buffer).is_err());
+        a.send_to(b"late", to).unwrap();
+        assert!(server.recv_from(&mut buffer).is_err());
+        assert_eq!(received(&a), "408");
+    }
+}
@@ -0,0 +1,104 @@
+//! Lookup of local users and groups. Both accept either a name or a numeric id.
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem::MaybeUninit;
+use std::ptr;
+
+fn not_found(kind: &str, name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("Unknown {kind} '{name}'"))
+}
+
+/// Call a reentrant getXXnam_r style function, growing the scratch buffer
+/// until the entry fits, and take what is needed from the entry while the
+/// strings it points to are still there.
+fn lookup<K: Copy, T, R>(
+    key: K,
+    name: &str,
+    kind: &str,
+    getter: unsafe extern "C" fn(
+        K,
+        *mut T,
+        *mut libc::c_char,
+        libc::size_t,
+        *mut *mut T,
+    ) -> libc::c_int,
+    extract: impl Fn(&T) -> R,
+) -> io::Result<R> {
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut entry = MaybeUninit::<T>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: all pointers are valid for the duration of the call and
+        // the buffer length matches the allocation.
+        let status = unsafe {
+            getter(
+                key,
+                entry.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match status {
+            0 if result.is_null() => return Err(not_found(kind, name)),
+            // SAFETY: a non-null result means the entry was filled in.
+            0 => return Ok(extract(unsafe { entry.assume_init_ref() })),
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            errno => return Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+fn c_name(kind: &str, name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|_| not_found(kind, name))
+}
+
+pub fn resolve_user(user: &str) -> io::Result<libc::uid_t> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let c_user = c_name("user", user)?;
+    lookup(c_user.as_ptr(), user, "user", libc::getpwnam_r, |entry| {
+        entry.pw_uid
+    })
+}
+
+pub fn resolve_group(group: &str) -> io::Result<libc::gid_t> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let c_group = c_name("group", group)?;
+    lookup(
+        c_group.as_ptr(),
+        group,
+        "group",
+        libc::getgrnam_r,
+        |entry| entry.gr_gid,
+    )
+}
+
+/// A local user's ids and name.
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub uid: libc::uid_t,
+    /// Primary group.
+    pub gid: libc::gid_t,
+    pub name: CString,
+}
+
+pub fn resolve_account(user: &str) -> io::Result<Account> {
+    let extract = |entry: &libc::passwd| Account {
+        uid: entry.pw_uid,
+        gid: entry.pw_gid,
+        // SAFETY: pw_name points to a NUL-terminated string in the buffer.
+        name: unsafe { CStr::from_ptr(entry.pw_name) }.to_owned(),
+    };
+    match user.parse() {
+        Ok(uid) => lookup(uid, user, "user", libc::getpwuid_r, extract),
+        Err(_) => {
+            let c_user = c_name("user", user)?;
+            lookup(c_user.as_ptr(), user, "user", libc::getpwnam_r, extract)
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Serving over vsock, so that virtual machines reach a bank on their host,
+//! or the host one in a guest, with no network set up between them. With
+//! `vsock_port` in the configuration the server listens on that port of
+//! every context instead of at `socket_path`.
+//!
+//! Clients connect a `SOCK_SEQPACKET` socket to the server's context, 2
+//! for the host, and speak the protocol of the Unix socket over it: each
+//! packet is a datagram, replies come back on the same connection. A
+//! client is known by its address, `vsock:<cid>:<port>`. vsock carries no
+//! credentials, so `peer_auth` lets no client through; use tokens.
+
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::socket::PeerCredentials;
+use crate::transport::Transport;
+
+/// Connections served at once. Clients connecting beyond it are turned
+/// away until others hang up.
+const MAX_CONNECTIONS: usize = 1024;
+
+#[derive(Debug, Default)]
+struct Connections {
+    by_address: HashMap<PathBuf, Arc<OwnedFd>>,
+    /// The client whose request came in last, whose payload comes next.
+    last: Option<PathBuf>,
+    /// Where the search for a client with a request starts, so that a busy
+    /// one does not keep the others waiting.
+    next: usize,
+    /// Names connections of sockets without a vsock address.
+    accepted: u64,
+}
+
+/// Serves the clients connected to a listening `SOCK_SEQPACKET` socket.
+#[derive(Debug)]
+pub struct VsockTransport {
+    listener: OwnedFd,
+    timeout: Duration,
+    connections: Mutex<Connections>,
+}
+
+fn check<T: Default + PartialOrd>(result: T) -> io::Result<T> {
+    if result < T::default() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(result)
+}
+
+impl VsockTransport {
+    /// Listen on `port` of every context. Receiving fails with `TimedOut`
+    /// when nothing came in for `timeout`.
+    pub fn bind(port: u32, timeout: Duration) -> io::Result<VsockTransport> {
+        // SAFETY: plain system call.
+        let fd = check(unsafe {
+            libc::socket(libc::AF_VSOCK, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0)
+        })?;
+        // SAFETY: the kernel returned a new file descriptor, ours to close.
+        let listener = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: all-zero is a valid bit pattern for sockaddr_vm.
+        let mut address: libc::sockaddr_vm = unsafe { mem::zeroed() };
+        address.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        address.svm_cid = libc::VMADDR_CID_ANY;
+        address.svm_port = port;
+        // SAFETY: `address` is live for the call and of the length given.
+        check(unsafe {
+            libc::bind(
+                listener.as_raw_fd(),
+                &address as *const libc::sockaddr_vm as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+            )
+        })?;
+        VsockTransport::listen(listener, timeout)
+    }
+
+    /// Serve the bound `SOCK_SEQPACKET` socket `listener`.
+    fn listen(listener: OwnedFd, timeout: Duration) -> io::Result<VsockTransport> {
+        // SAFETY: plain system call on a socket we own.
+        check(unsafe { libc::listen(listener.as_raw_fd(), libc::SOMAXCONN) })?;
+        Ok(VsockTransport {
+            listener,
+            timeout,
+            connections: Mutex::new(Connections::default()),
+        })
+    }
+
+    /// Take in a client that connected.
+    fn accept(&self) -> io::Result<()> {
+        // SAFETY: all-zero is a valid bit pattern for sockaddr_vm.
+        let mut address: libc::sockaddr_vm = unsafe { mem::zeroed() };
+        let mut length = mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+        // SAFETY: `address` is live and `length` says how much of it there is.
+        let fd = check(unsafe {
+            libc::accept4(
+                self.listener.as_raw_fd(),
+                &mut address as *mut libc::sockaddr_vm as *mut libc::sockaddr,
+                &mut length,
+                libc::SOCK_CLOEXEC,
+            )
+        })?;
+        // SAFETY: the kernel returned a new file descriptor, ours to close.
+        let connection = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut connections = self.connections.lock().unwrap();
+        if connections.by_address.len() >= MAX_CONNECTIONS {
+            warn!("Turned away a vsock client, {MAX_CONNECTIONS} are connected");
+            return Ok(());
+        }
+        connections.accepted += 1;
+        let name = if address.svm_family == libc::AF_VSOCK as libc::sa_family_t {
+            format!("vsock:{}:{}", address.svm_cid, address.svm_port)
+        } else {
+            format!("vsock:{}", connections.accepted)
+        };
+        debug!("Accepted vsock client {name}");
+        connections
+            .by_address
+            .insert(PathBuf::from(name), Arc::new(connection));
+        Ok(())
+    }
+
+    /// Forget a client that hung up.
+    fn disconnect(&self, address: &Path) {
+        debug!("Vsock client {} hung up", address.display());
+        let mut connections = self.connections.lock().unwrap();
+        connections.by_address.remove(address);
+        if connections.last.as_deref() == Some(address) {
+            connections.last = None;
+        }
+    }
+
+    fn connection(&self, address: &Path) -> io::Result<Arc<OwnedFd>> {
+        self.connections
+            .lock()
+            .unwrap()
+            .by_address
+            .get(address)
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    format!("{} is not connected", address.display()),
+                )
+            })
+    }
+
+    fn send(&self, message: &[u8], address: &Path, flags: libc::c_int) -> io::Result<()> {
+        let connection = self.connection(address)?;
+        // SAFETY: `message` is live for the call.
+        check(unsafe {
+            libc::send(
+                connection.as_raw_fd(),
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                flags | libc::MSG_NOSIGNAL,
+            )
+        })?;
+        Ok(())
+    }
+}
+
+/// Wait up to `timeout` for one of `fds` to be readable, and return which
+/// are, or have hung up.
+fn poll(fds: &[RawFd], timeout: Duration) -> io::Result<Vec<bool>> {
+    let mut polled: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let timeout = libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: timeout.subsec_nanos().into(),
+    };
+    // SAFETY: `polled` is live for the call and holds as many entries as
+    // given.
+    let ready = check(unsafe {
+        libc::ppoll(
+            polled.as_mut_ptr(),
+            polled.len() as libc::nfds_t,
+            &timeout,
+            std::ptr::null(),
+        )
+    })?;
+    if ready == 0 {
+        return Err(io::ErrorKind::TimedOut.into());
+    }
+    Ok(polled.iter().map(|fd| fd.revents != 0).collect())
+}
+
+/// The next packet on `fd` into `buffer`, and its whole length. A length
+/// of zero means the client hung up.
+fn recv_packet(fd: RawFd, buffer: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: `buffer` is live and writable for its length.
+    let received = check(unsafe {
+        libc::recv(
+            fd,
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+            libc::MSG_TRUNC | libc::MSG_DONTWAIT,
+        )
+    })?;
+    Ok(received as usize)
+}
+
+impl Transport for VsockTransport {
+    fn recv_from(
+        &self,
+        buffer: &mut [u8],
+    ) -> io::Result<(usize, Option<PathBuf>, Option<PeerCredentials>)> {
+        loop {
+            let (clients, start) = {
+                let mut connections = self.connections.lock().unwrap();
+                let mut clients: Vec<_> = connections
+                    .by_address
+                    .iter()
+                    .map(|(address, fd)| (address.clone(), Arc::clone(fd)))
+                    .collect();
+                clients.sort_by(|a, b| a.0.cmp(&b.0));
+                connections.next = connections.next.wrapping_add(1);
+                (clients, connections.next)
+            };
+            let mut fds = vec![self.listener.as_raw_fd()];
+            fds.extend(clients.iter().map(|(_, fd)| fd.as_raw_fd()));
+            let ready = poll(&fds, self.timeout)?;
+            if ready[0] {
+                self.accept()?;
+            }
+            let count = clients.len();
+            for index in (0..count).map(|offset| (start + offset) % count) {
+                if !ready[index + 1] {
+                    continue;
+                }
+                let (address, fd) = &clients[index];
+                match recv_packet(fd.as_raw_fd(), buffer) {
+                    Ok(0) => self.disconnect(address),
+                    Ok(length) => {
+                        self.connections.lock().unwrap().last = Some(address.clone());
+                        return Ok((length, Some(address.clone()), None));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => {
+                        warn!("Dropped vsock client {}: {e}", address.display());
+                        self.disconnect(address);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The next packet of the client whose request came in last.
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let address = self.connections.lock().unwrap().last.clone();
+        let address = address.ok_or(io::ErrorKind::NotConnected)?;
+        let fd = self.connection(&address)?;
+        poll(&[fd.as_raw_fd()], self.timeout)?;
+        match recv_packet(fd.as_raw_fd(), buffer)? {
+            0 => {
+                self.disconnect(&address);
+                Err(io::ErrorKind::ConnectionAborted.into())
+            }
+            length => Ok(length),
+        }
+    }
+
+    fn send_to(&self, message: &[u8], path: &Path) -> io::Result<()> {
+        self.send(message, path, 0)
+    }
+
+    fn send_to_nowait(&self, message: &[u8], path: &Path) -> io::Result<()> {
+        self.send(message, path, libc::MSG_DONTWAIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    /// A Unix `SOCK_SEQPACKET` socket, which carries packets as vsock does,
+    /// bound to `path` or connected to it.
+    fn seqpacket(path: &Path, bind: bool) -> OwnedFd {
+        let socket =
+            unsafe { OwnedFd::from_raw_fd(libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0)) };
+        let mut address: libc::sockaddr_un = unsafe { mem::zeroed() };
+        address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (slot, &byte) in address
+            .sun_path
+            .iter_mut()
+            .zip(path.to_str().unwrap().as_bytes())
+        {
+            *slot = byte as libc::c_char;
+        }
+        let address = &address as *const libc::sockaddr_un as *const libc::sockaddr;
+        let length = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+        let result = unsafe {
+            match bind {
+                true => libc::bind(socket.as_raw_fd(), address, length),
+                false => libc::connect(socket.as_raw_fd(), address, length),
+            }
+        };
+        assert_eq!(result, 0);
+        socket
+    }
+
+    #[test]
+    fn each_client_gets_its_own_replies() {
+        let path = std::env::temp_dir().join(format!("bank-vsock-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = seqpacket(&path, true);
+        let transport = VsockTransport::listen(listener, Duration::from_millis(100)).unwrap();
+        let mut first = UnixStream::from(seqpacket(&path, false));
+        let mut second = UnixStream::from(seqpacket(&path, false));
+
+        first.write_all(b"i").unwrap();
+        let mut buffer = [0; 16];
+        let (length, a, credentials) = transport.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"i");
+        assert!(credentials.is_none());
+        second.write_all(b"t{}").unwrap();
+        second.write_all(b"payload").unwrap();
+        let (length, b, _) = transport.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"t{}");
+        assert_ne!(a, b);
+        // The payload is the next packet of the last client, whole or not.
+        assert_eq!(transport.recv(&mut buffer[..4]).unwrap(), 7);
+
+        let (a, b) = (a.unwrap(), b.unwrap());
+        transport.send_to(b"200", &b).unwrap();
+        transport.send_to(b"404", &a).unwrap();
+        let mut reply = [0; 8];
+        let length = second.read(&mut reply).unwrap();
+        assert_eq!(&reply[..length], b"200");
+        let length = first.read(&mut reply).unwrap();
+        assert_eq!(&reply[..length], b"404");
+
+        // Clients that hang up are forgotten.
+        drop(first);
+        assert_eq!(
+            transport.recv_from(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert!(transport.send_to(b"200", &a).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
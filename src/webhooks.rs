@@ -0,0 +1,199 @@
+//! Delivery of committed transfers to external systems, e.g. accounting or
+//! alerting, as HTTP POST requests to the configured endpoints. The body is
+//! the same JSON as subscribers get, see `hooks::TransferEvent`.
+//!
+//! Each endpoint has a background thread delivering transfers in the order
+//! they were committed. A failed delivery is retried with exponential
+//! backoff, holding up the ones behind it, and given up after
+//! `max_attempts`. When an endpoint falls too far behind, new transfers are
+//! not delivered to it.
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{debug, warn};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::hooks::{CommittedTransfer, TransferEvent};
+use crate::http::{self, Endpoint};
+
+/// Deliveries waiting for an endpoint before new ones are dropped.
+const QUEUE_LENGTH: usize = 1024;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum WebhookError {
+    #[error("Invalid webhook URL '{0}', expected one like \"http://localhost:8080/hooks\"")]
+    Url(String),
+    #[error("Unable to start delivering webhooks")]
+    Thread(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Deliveries of a transfer before giving up on it.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+#[derive(Debug)]
+struct Dispatcher {
+    url: String,
+    sender: SyncSender<Vec<u8>>,
+    thread: JoinHandle<()>,
+}
+
+/// Dispatchers for the configured endpoints. Dropping it stops them once
+/// they have delivered what they were given.
+#[derive(Debug, Default)]
+pub struct Webhooks {
+    dispatchers: Vec<Dispatcher>,
+}
+
+impl Webhooks {
+    pub fn start(configs: &[WebhookConfig]) -> Result<Webhooks, WebhookError> {
+        let mut dispatchers = Vec::new();
+        for config in configs {
+            let endpoint = Endpoint::parse(&config.url)
+                .ok_or_else(|| WebhookError::Url(config.url.clone()))?;
+            let (sender, receiver) = mpsc::sync_channel(QUEUE_LENGTH);
+            let url = config.url.clone();
+            let max_attempts = config.max_attempts.max(1);
+            let thread = thread::Builder::new()
+                .name("webhook".to_string())
+                .spawn(move || run(&url, &endpoint, max_attempts, FIRST_BACKOFF, &receiver))?;
+            dispatchers.push(Dispatcher {
+                url: config.url.clone(),
+                sender,
+                thread,
+            });
+        }
+        Ok(Webhooks { dispatchers })
+    }
+
+    pub fn deliver(&self, transfer: &CommittedTransfer) {
+        if self.dispatchers.is_empty() {
+            return;
+        }
+        let body = match serde_json::to_vec(&TransferEvent::committed(transfer)) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Unable to encode webhook payload: {e}");
+                return;
+            }
+        };
+        for dispatcher in &self.dispatchers {
+            if let Err(TrySendError::Full(_)) = dispatcher.sender.try_send(body.clone()) {
+                warn!(
+                    "Dropped webhook of transaction {} to {}, it is behind",
+                    transfer.transaction_id, dispatcher.url
+                );
+            }
+        }
+    }
+
+    /// Wait until what the dispatchers were given is delivered or given
+    /// up on.
+    pub fn finish(self) {
+        for Dispatcher {
+            url,
+            sender,
+            thread,
+        } in self.dispatchers
+        {
+            drop(sender);
+            if thread.join().is_err() {
+                warn!("Webhook delivery to {url} panicked");
+            }
+        }
+    }
+}
+
+/// Wait before retrying after `failures` failed attempts.
+fn backoff(first: Duration, failures: u32) -> Duration {
+    first
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+fn run(
+    url: &str,
+    endpoint: &Endpoint,
+    max_attempts: u32,
+    first_backoff: Duration,
+    receiver: &Receiver<Vec<u8>>,
+) {
+    while let Ok(body) = receiver.recv() {
+        for attempt in 1..=max_attempts {
+            match http::post_json(endpoint, &body) {
+                Ok(()) => {
+                    debug!("Delivered webhook to {url}");
+                    break;
+                }
+                Err(e) if attempt == max_attempts => {
+                    warn!("Gave up delivering webhook to {url} after {attempt} attempts: {e}");
+                }
+                Err(e) => {
+                    let wait = backoff(first_backoff, attempt);
+                    debug!("Retrying webhook to {url} in {wait:?}: {e}");
+                    thread::sleep(wait);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_a_limit() {
+        let waits: Vec<u64> = (1..=8)
+            .map(|failures| backoff(FIRST_BACKOFF, failures).as_secs())
+            .collect();
+        assert_eq!(waits, [1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+
+    #[test]
+    fn failed_deliveries_are_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint =
+            Endpoint::parse(&format!("http://{}/hooks", listener.local_addr().unwrap())).unwrap();
+        let (sender, receiver) = mpsc::sync_channel(1);
+        sender.send(b"{}".to_vec()).unwrap();
+        drop(sender);
+        let delivery =
+            thread::spawn(move || run("test", &endpoint, 3, Duration::from_millis(1), &receiver));
+
+        let mut requests = Vec::new();
+        for status in ["503 Service Unavailable", "200 OK"] {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"{}") {
+                let length = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..length]);
+            }
+            requests.push(String::from_utf8(request).unwrap());
+            write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").unwrap();
+        }
+        delivery.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("POST /hooks HTTP/1.1\r\n"));
+        assert!(requests[1].ends_with("\r\n\r\n{}"));
+    }
+}
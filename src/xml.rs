@@ -0,0 +1,262 @@
+//! Reading the subset of XML that data files use: elements, attributes,
+//! text, CDATA and the predefined and numeric entities. Comments,
+//! processing instructions and the document type are skipped, and namespace
+//! prefixes are dropped from names.
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("Invalid XML at byte {offset}: {reason}")]
+pub struct XmlError {
+    pub offset: usize,
+    pub reason: &'static str,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Element {
+    /// Without the namespace prefix.
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    /// Text directly inside the element, entities resolved.
+    pub text: String,
+}
+
+impl Element {
+    /// The first child named `name`.
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// The element reached by following the first child of each name in
+    /// `path`.
+    pub fn find(&self, path: &[&str]) -> Option<&Element> {
+        path.iter()
+            .try_fold(self, |element, name| element.child(name))
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The text without surrounding whitespace.
+    pub fn text(&self) -> &str {
+        self.text.trim()
+    }
+}
+
+/// The root element of the document `text`.
+pub fn parse(text: &str) -> Result<Element, XmlError> {
+    let mut parser = Parser { text, position: 0 };
+    parser.skip_misc()?;
+    let root = parser.element()?;
+    parser.skip_misc()?;
+    if parser.position < text.len() {
+        return Err(parser.error("content after the root element"));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, reason: &'static str) -> XmlError {
+        XmlError {
+            offset: self.position,
+            reason,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Move past `end`, returning what came before it.
+    fn until(&mut self, end: &str, reason: &'static str) -> Result<&'a str, XmlError> {
+        let length = self.rest().find(end).ok_or_else(|| self.error(reason))?;
+        let skipped = &self.text[self.position..self.position + length];
+        self.position += length + end.len();
+        Ok(skipped)
+    }
+
+    fn expect(&mut self, token: &str, reason: &'static str) -> Result<(), XmlError> {
+        if !self.rest().starts_with(token) {
+            return Err(self.error(reason));
+        }
+        self.position += token.len();
+        Ok(())
+    }
+
+    /// Skip whitespace, comments, processing instructions and the document
+    /// type outside the root element.
+    fn skip_misc(&mut self) -> Result<(), XmlError> {
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("<?") {
+                self.until("?>", "unterminated processing instruction")?;
+            } else if self.rest().starts_with("<!--") {
+                self.until("-->", "unterminated comment")?;
+            } else if self.rest().starts_with("<!DOCTYPE") {
+                self.until(">", "unterminated document type")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, XmlError> {
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '='))
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.position += length;
+        Ok(&rest[..length])
+    }
+
+    fn element(&mut self) -> Result<Element, XmlError> {
+        self.expect("<", "expected an element")?;
+        let qualified_name = self.name()?;
+        let mut element = Element {
+            name: local(qualified_name).to_string(),
+            ..Element::default()
+        };
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.position += 2;
+                return Ok(element);
+            }
+            if self.rest().starts_with('>') {
+                self.position += 1;
+                break;
+            }
+            let name = self.name()?;
+            self.skip_whitespace();
+            self.expect("=", "expected '=' after the attribute name")?;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote @ ('"' | '\'')) => quote,
+                _ => return Err(self.error("expected a quoted attribute value")),
+            };
+            self.position += 1;
+            let start = self.position;
+            let value = self.until(&quote.to_string(), "unterminated attribute value")?;
+            let value = unescape(value).map_err(|reason| XmlError {
+                offset: start,
+                reason,
+            })?;
+            if name != "xmlns" && !name.starts_with("xmlns:") {
+                element.attributes.push((local(name).to_string(), value));
+            }
+        }
+        loop {
+            if self.rest().starts_with("</") {
+                self.position += 2;
+                if self.name()? != qualified_name {
+                    return Err(self.error("closing tag does not match"));
+                }
+                self.skip_whitespace();
+                self.expect(">", "expected '>'")?;
+                return Ok(element);
+            } else if self.rest().starts_with("<!--") {
+                self.until("-->", "unterminated comment")?;
+            } else if self.rest().starts_with("<![CDATA[") {
+                self.position += "<![CDATA[".len();
+                let data = self.until("]]>", "unterminated CDATA section")?;
+                element.text.push_str(data);
+            } else if self.rest().starts_with("<?") {
+                self.until("?>", "unterminated processing instruction")?;
+            } else if self.rest().starts_with('<') {
+                element.children.push(self.element()?);
+            } else if self.rest().is_empty() {
+                return Err(self.error("unclosed element"));
+            } else {
+                let start = self.position;
+                let length = self.rest().find('<').unwrap_or(self.rest().len());
+                self.position += length;
+                let text =
+                    unescape(&self.text[start..self.position]).map_err(|reason| XmlError {
+                        offset: start,
+                        reason,
+                    })?;
+                element.text.push_str(&text);
+            }
+        }
+    }
+}
+
+fn local(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn unescape(text: &str) -> Result<String, &'static str> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let end = rest[start..].find(';').ok_or("unterminated entity")? + start;
+        let entity = &rest[start + 1..end];
+        let c = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32)
+                .ok_or("unknown entity")?,
+        };
+        unescaped.push(c);
+        rest = &rest[end + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements_are_read_without_prefixes() {
+        let root = parse(
+            "<?xml version=\"1.0\"?>\n<!-- payments -->\n\
+             <p:Doc xmlns:p=\"urn:x\"><p:Amt Ccy='EUR'>1.50</p:Amt>\
+             <Nm>A &amp; B &#x41;<![CDATA[<c>]]></Nm><Empty/></p:Doc>",
+        )
+        .unwrap();
+        assert_eq!(root.name, "Doc");
+        assert!(root.attributes.is_empty());
+        let amount = root.child("Amt").unwrap();
+        assert_eq!(
+            (amount.text(), amount.attribute("Ccy")),
+            ("1.50", Some("EUR"))
+        );
+        assert_eq!(root.find(&["Nm"]).unwrap().text(), "A & B A<c>");
+        assert_eq!(root.children("Empty").count(), 1);
+        assert!(parse("<a><b></a>").is_err());
+        assert!(parse("<a>&bogus;</a>").is_err());
+    }
+}